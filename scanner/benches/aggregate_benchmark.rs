@@ -0,0 +1,72 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::path::PathBuf;
+use storage_scanner::{
+    aggregate::{aggregate_chunks_with, find_chunk_files, AggregateOptions},
+    models::FileEntry,
+    rotating_writer::{RotatingParquetWriter, RotatingWriterConfig},
+};
+use tempfile::TempDir;
+
+const CHUNKS: usize = 32;
+const ROWS_PER_CHUNK: usize = 20_000;
+
+/// Write a scan of `CHUNKS` chunk files and return the chunk paths
+fn create_chunks() -> (TempDir, Vec<PathBuf>) {
+    let temp_dir = TempDir::new().unwrap();
+    let config = RotatingWriterConfig {
+        base_output_path: temp_dir.path().join("scan.parquet"),
+        rows_per_chunk: ROWS_PER_CHUNK,
+        time_interval: std::time::Duration::from_secs(3600),
+    };
+
+    let mut writer = RotatingParquetWriter::new(config, "/bench".to_string()).unwrap();
+    for chunk in 0..CHUNKS {
+        let entries: Vec<FileEntry> = (0..ROWS_PER_CHUNK)
+            .map(|i| FileEntry {
+                size: (i * 4096) as u64,
+                modified_time: 1_700_000_000 + i as i64,
+                accessed_time: 1_700_000_000 + i as i64,
+                created_time: Some(1_700_000_000),
+                inode: (chunk * ROWS_PER_CHUNK + i) as u64,
+                owner: Some("user".to_string()),
+                group: Some("group".to_string()),
+                ..FileEntry::fixture(&format!("/bench/dir_{}/chunk_{}/file_{:06}.dat", i % 16, chunk, i))
+            })
+            .collect();
+        writer.write_batch(&entries).unwrap();
+    }
+    let manifest = writer.finalize().unwrap();
+    assert_eq!(manifest.chunk_count, CHUNKS);
+
+    let chunks = find_chunk_files(temp_dir.path()).unwrap();
+    (temp_dir, chunks)
+}
+
+fn benchmark_aggregate_threads(c: &mut Criterion) {
+    let mut group = c.benchmark_group("aggregate_threads");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements((CHUNKS * ROWS_PER_CHUNK) as u64));
+
+    let (temp_dir, chunks) = create_chunks();
+    let output = temp_dir.path().join("out/aggregated.parquet");
+
+    for threads in [1, 2, 4, 8].iter() {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(threads),
+            threads,
+            |b, &threads| {
+                let options = AggregateOptions { threads, ..Default::default() };
+
+                b.iter(|| {
+                    let summary = aggregate_chunks_with(black_box(&chunks), &output, &options).unwrap();
+                    black_box(summary)
+                });
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, benchmark_aggregate_threads);
+criterion_main!(benches);
@@ -1,61 +1,48 @@
 use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
-use std::fs;
+use storage_scanner::generate::{generate, GenerateOptions, SizeDist};
 use storage_scanner::{models::ScanOptions, scanner::scan_directory};
 use tempfile::TempDir;
 
-/// Create a test directory structure with many small files
-fn create_small_files_structure(num_files: usize) -> TempDir {
+/// Generate a synthetic tree in a temporary directory
+fn create_tree(options: GenerateOptions) -> TempDir {
     let temp_dir = TempDir::new().unwrap();
-    let base = temp_dir.path();
-
-    for i in 0..num_files {
-        let file_path = base.join(format!("file_{:06}.txt", i));
-        fs::write(file_path, format!("Content {}", i)).unwrap();
-    }
-
+    generate(temp_dir.path(), &options).unwrap();
     temp_dir
 }
 
-/// Create a test directory structure with nested directories
-fn create_nested_structure(depth: usize, files_per_level: usize) -> TempDir {
-    let temp_dir = TempDir::new().unwrap();
-    let base = temp_dir.path();
-
-    fn create_level(path: &std::path::Path, current_depth: usize, max_depth: usize, files: usize) {
-        if current_depth >= max_depth {
-            return;
-        }
-
-        // Create files at this level
-        for i in 0..files {
-            fs::write(path.join(format!("file_{}.txt", i)), "content").unwrap();
-        }
-
-        // Create subdirectories
-        for i in 0..3 {
-            let subdir = path.join(format!("dir_{}", i));
-            fs::create_dir(&subdir).unwrap();
-            create_level(&subdir, current_depth + 1, max_depth, files);
-        }
-    }
+/// Create a test directory structure with many small files
+fn create_small_files_structure(num_files: usize) -> TempDir {
+    create_tree(GenerateOptions {
+        files: num_files as u64,
+        dirs: 0,
+        size_dist: SizeDist::Fixed { bytes: 16 },
+        ..Default::default()
+    })
+}
 
-    create_level(base, 0, depth, files_per_level);
-    temp_dir
+/// Create a test directory structure with nested directories: three
+/// subdirectories per level on average, and `files_per_dir` files in each
+fn create_nested_structure(depth: usize, files_per_dir: usize) -> TempDir {
+    let dirs: u64 = (1..=depth as u32).map(|level| 3u64.pow(level)).sum();
+    create_tree(GenerateOptions {
+        files: (dirs + 1) * files_per_dir as u64,
+        dirs,
+        depth,
+        size_dist: SizeDist::Fixed { bytes: 16 },
+        ..Default::default()
+    })
 }
 
 /// Create a structure with large files
+#[allow(dead_code)]
 fn create_large_files_structure(num_files: usize, file_size_mb: usize) -> TempDir {
-    let temp_dir = TempDir::new().unwrap();
-    let base = temp_dir.path();
-
-    let content = vec![0u8; file_size_mb * 1024 * 1024];
-
-    for i in 0..num_files {
-        let file_path = base.join(format!("large_file_{:02}.bin", i));
-        fs::write(file_path, &content).unwrap();
-    }
-
-    temp_dir
+    create_tree(GenerateOptions {
+        files: num_files as u64,
+        dirs: 0,
+        size_dist: SizeDist::Fixed { bytes: (file_size_mb * 1024 * 1024) as u64 },
+        sparse: false,
+        ..Default::default()
+    })
 }
 
 fn benchmark_scan_small_files(c: &mut Criterion) {
@@ -69,11 +56,7 @@ fn benchmark_scan_small_files(c: &mut Criterion) {
             num_files,
             |b, &num_files| {
                 let temp_dir = create_small_files_structure(num_files);
-                let options = ScanOptions {
-                    num_threads: 4,
-                    batch_size: 1000,
-                    ..Default::default()
-                };
+                let options = ScanOptions::builder().threads(4).batch_size(1000).build().unwrap();
 
                 b.iter(|| {
                     let entries = scan_directory(black_box(temp_dir.path()), options.clone()).unwrap();
@@ -95,11 +78,7 @@ fn benchmark_scan_nested_directories(c: &mut Criterion) {
             depth,
             |b, &depth| {
                 let temp_dir = create_nested_structure(depth, 5);
-                let options = ScanOptions {
-                    num_threads: 4,
-                    batch_size: 1000,
-                    ..Default::default()
-                };
+                let options = ScanOptions::builder().threads(4).batch_size(1000).build().unwrap();
 
                 b.iter(|| {
                     let entries = scan_directory(black_box(temp_dir.path()), options.clone()).unwrap();
@@ -121,11 +100,7 @@ fn benchmark_parallel_vs_sequential(c: &mut Criterion) {
             BenchmarkId::from_parameter(num_threads),
             num_threads,
             |b, &threads| {
-                let options = ScanOptions {
-                    num_threads: threads,
-                    batch_size: 1000,
-                    ..Default::default()
-                };
+                let options = ScanOptions::builder().threads(threads).batch_size(1000).build().unwrap();
 
                 b.iter(|| {
                     let entries = scan_directory(black_box(temp_dir.path()), options.clone()).unwrap();
@@ -147,11 +122,7 @@ fn benchmark_batch_sizes(c: &mut Criterion) {
             BenchmarkId::from_parameter(batch_size),
             batch_size,
             |b, &batch_size| {
-                let options = ScanOptions {
-                    num_threads: 4,
-                    batch_size,
-                    ..Default::default()
-                };
+                let options = ScanOptions::builder().threads(4).batch_size(batch_size).build().unwrap();
 
                 b.iter(|| {
                     let entries = scan_directory(black_box(temp_dir.path()), options.clone()).unwrap();
@@ -175,12 +146,12 @@ fn benchmark_max_depth(c: &mut Criterion) {
             BenchmarkId::from_parameter(&label),
             max_depth,
             |b, &max_depth| {
-                let options = ScanOptions {
-                    num_threads: 4,
-                    batch_size: 1000,
-                    max_depth,
-                    ..Default::default()
-                };
+                let options = ScanOptions::builder()
+                    .threads(4)
+                    .batch_size(1000)
+                    .max_depth(max_depth)
+                    .build()
+                    .unwrap();
 
                 b.iter(|| {
                     let entries = scan_directory(black_box(temp_dir.path()), options.clone()).unwrap();
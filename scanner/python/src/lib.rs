@@ -0,0 +1,193 @@
+//! Python bindings: `storage_scanner.scan` and `storage_scanner.iter_scan`.
+//!
+//! Scans run with the GIL released. Entries are converted to Arrow record
+//! batches in the scan output schema and handed to pyarrow through the C data
+//! interface, without copying the column buffers.
+
+use arrow::pyarrow::PyArrowConvert;
+use arrow::record_batch::RecordBatch;
+use pyo3::exceptions::{PyFileNotFoundError, PyOSError, PyPermissionError, PyRuntimeError, PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use scanner::scanner::{ScanError, ScanIter, Scanner};
+use scanner::writer::entries_to_batch;
+use scanner::{Error, FileEntry, ScanOptions, ScanStats};
+use std::path::PathBuf;
+
+/// Scan options from the keyword arguments of `scan` and `iter_scan`
+fn scan_options(function: &str, kwargs: Option<&PyDict>) -> PyResult<ScanOptions> {
+    let mut builder = ScanOptions::builder();
+    for (key, value) in kwargs.into_iter().flatten() {
+        let key: &str = key.extract()?;
+        let invalid = |e: PyErr| PyValueError::new_err(format!("Invalid {}: {}", key, e));
+        builder = match key {
+            "threads" => builder.threads(value.extract().map_err(invalid)?),
+            "batch_size" => builder.batch_size(value.extract().map_err(invalid)?),
+            "follow_symlinks" => builder.follow_symlinks(value.extract().map_err(invalid)?),
+            "max_depth" => builder.max_depth(value.extract::<Option<usize>>().map_err(invalid)?),
+            "min_size" => builder.min_size(value.extract::<Option<u64>>().map_err(invalid)?),
+            "exclude" => {
+                // One pattern, or a list of them
+                let patterns: Vec<String> = match value.extract::<String>() {
+                    Ok(pattern) => vec![pattern],
+                    Err(_) => value.extract().map_err(invalid)?,
+                };
+                patterns.into_iter().fold(builder, |builder, pattern| builder.exclude_glob(pattern))
+            }
+            other => {
+                return Err(PyTypeError::new_err(format!("{}() got an unexpected keyword argument '{}'", function, other)));
+            }
+        };
+    }
+    builder.build().map_err(|e| PyValueError::new_err(e.to_string()))
+}
+
+fn scan_error(e: Error) -> PyErr {
+    match e {
+        e @ Error::InvalidOptions { .. } => PyValueError::new_err(e.to_string()),
+        e => match e.io_error().map(|io| io.kind()) {
+            Some(std::io::ErrorKind::NotFound) => PyFileNotFoundError::new_err(e.to_string()),
+            Some(std::io::ErrorKind::PermissionDenied) => PyPermissionError::new_err(e.to_string()),
+            Some(_) => PyOSError::new_err(e.to_string()),
+            None => PyRuntimeError::new_err(e.to_string()),
+        },
+    }
+}
+
+/// The failure that ended a scan run by [`ScanIter`]
+fn failed_scan(e: ScanError) -> PyErr {
+    let message = e.message;
+    match e.kind.as_str() {
+        "ENOENT" => PyFileNotFoundError::new_err(message),
+        "EACCES" => PyPermissionError::new_err(message),
+        "other" => PyRuntimeError::new_err(message),
+        _ => PyOSError::new_err(message),
+    }
+}
+
+fn arrow_error(e: arrow::error::ArrowError) -> PyErr {
+    PyRuntimeError::new_err(e.to_string())
+}
+
+fn stats_dict<'py>(py: Python<'py>, stats: &ScanStats) -> PyResult<&'py PyDict> {
+    let dict = PyDict::new(py);
+    dict.set_item("files_scanned", stats.files_scanned)?;
+    dict.set_item("directories_scanned", stats.directories_scanned)?;
+    dict.set_item("symlinks_scanned", stats.symlinks_scanned)?;
+    dict.set_item("total_size", stats.total_size)?;
+    dict.set_item("unique_total_size", stats.unique_total_size)?;
+    dict.set_item("errors_encountered", stats.errors_encountered)?;
+    dict.set_item("duration_secs", stats.duration_secs)?;
+    Ok(dict)
+}
+
+/// Every entry below `path` as record batches, scanned on this thread and
+/// the scanner's pool
+fn scan_batches(path: PathBuf, options: ScanOptions) -> Result<Vec<RecordBatch>, Error> {
+    let (tx, rx) = crossbeam_channel::bounded::<Vec<FileEntry>>(2);
+    let scanner = Scanner::new(options);
+    std::thread::scope(|scope| {
+        let scan = scope.spawn(|| scanner.scan(path, tx));
+        let batches: Vec<_> = rx.iter().map(|entries| entries_to_batch(&entries)).collect();
+        scan.join().map_err(|_| anyhow::anyhow!("Scanner thread panicked"))??;
+        batches.into_iter()
+            .collect::<Result<_, _>>()
+            .map_err(|e| Error::Other(e.into()))
+    })
+}
+
+/// scan(path, **options) -> pyarrow.Table
+///
+/// Scan the tree at `path` and return every entry in a table with the
+/// columns of the scanner's Parquet output. Options: threads, batch_size,
+/// follow_symlinks, max_depth, exclude (a glob or list of globs), min_size.
+#[pyfunction]
+#[pyo3(signature = (path, **options))]
+fn scan(py: Python<'_>, path: PathBuf, options: Option<&PyDict>) -> PyResult<PyObject> {
+    let options = scan_options("scan", options)?;
+    let batches = py.allow_threads(|| scan_batches(path, options)).map_err(scan_error)?;
+
+    let schema = entries_to_batch(&[]).map_err(arrow_error)?.schema();
+    let batches = batches.iter()
+        .map(|batch| batch.to_pyarrow(py))
+        .collect::<PyResult<Vec<_>>>()?;
+    let table = py.import("pyarrow")?
+        .getattr("Table")?
+        .call_method1("from_batches", (batches, schema.as_ref().to_pyarrow(py)?))?;
+    Ok(table.into())
+}
+
+/// iter_scan(path, **options) -> iterator of dict
+///
+/// Scan the tree at `path` in the background, yielding its entries in
+/// batches of `batch_size`, each a dict of column name to list of values.
+/// Takes the options `scan` does. Leaving the loop early stops the scan.
+#[pyfunction]
+#[pyo3(signature = (path, **options))]
+fn iter_scan(path: PathBuf, options: Option<&PyDict>) -> PyResult<ScanBatches> {
+    let options = scan_options("iter_scan", options)?;
+    let batch_size = options.batch_size;
+    Ok(ScanBatches {
+        entries: Scanner::new(options).iter(path),
+        batch_size,
+        last_error: None,
+    })
+}
+
+/// The batches of a scan running in the background, from `iter_scan`
+#[pyclass(module = "storage_scanner")]
+struct ScanBatches {
+    entries: ScanIter,
+    batch_size: usize,
+
+    /// The most recent error the scan yielded. Paths that cannot be read are
+    /// only counted in `stats`; an error right before the end failed the scan.
+    last_error: Option<ScanError>,
+}
+
+#[pymethods]
+impl ScanBatches {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(mut slf: PyRefMut<'_, Self>, py: Python<'_>) -> PyResult<Option<PyObject>> {
+        let this = &mut *slf;
+        let (entries, last_error, batch_size) = (&mut this.entries, &mut this.last_error, this.batch_size);
+        let batch = py.allow_threads(|| {
+            let mut batch = Vec::with_capacity(batch_size.min(4096));
+            while batch.len() < batch_size {
+                match entries.next() {
+                    Some(Ok(entry)) => batch.push(entry),
+                    Some(Err(e)) => *last_error = Some(e),
+                    None => break,
+                }
+            }
+            batch
+        });
+
+        if batch.is_empty() {
+            return match (this.entries.stats(), this.last_error.take()) {
+                (None, Some(e)) => Err(failed_scan(e)),
+                _ => Ok(None),
+            };
+        }
+        let batch = entries_to_batch(&batch).map_err(arrow_error)?;
+        Ok(Some(batch.to_pyarrow(py)?.call_method0(py, "to_pydict")?))
+    }
+
+    /// Counts of the finished scan, once every batch has been yielded
+    #[getter]
+    fn stats<'py>(&self, py: Python<'py>) -> PyResult<Option<&'py PyDict>> {
+        self.entries.stats().map(|stats| stats_dict(py, stats)).transpose()
+    }
+}
+
+#[pymodule]
+fn storage_scanner(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
+    m.add("__version__", env!("CARGO_PKG_VERSION"))?;
+    m.add_function(wrap_pyfunction!(scan, m)?)?;
+    m.add_function(wrap_pyfunction!(iter_scan, m)?)?;
+    m.add_class::<ScanBatches>()?;
+    Ok(())
+}
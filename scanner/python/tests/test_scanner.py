@@ -0,0 +1,115 @@
+import pyarrow as pa
+import pytest
+
+import storage_scanner
+
+
+@pytest.fixture
+def tree(tmp_path):
+    """Two top-level directories, a nested one, and a file at the root"""
+    (tmp_path / "labA" / "results").mkdir(parents=True)
+    (tmp_path / "labB").mkdir()
+    (tmp_path / "labA" / "notes.txt").write_text("hello")
+    (tmp_path / "labA" / "results" / "run1.csv").write_text("a,b\n1,2\n")
+    (tmp_path / "labA" / "results" / "run2.csv").write_text("a,b\n3,4\n")
+    (tmp_path / "labB" / "data.bin").write_bytes(b"\0" * 1000)
+    (tmp_path / "README.md").write_text("# tree\n")
+    return tmp_path
+
+
+def paths(table, root):
+    return sorted(p[len(str(root)) + 1:] for p in table.column("path").to_pylist() if p != str(root))
+
+
+def test_scan_returns_a_table_of_every_entry(tree):
+    table = storage_scanner.scan(tree, threads=2)
+
+    assert isinstance(table, pa.Table)
+    assert table.column_names[:3] == ["path", "size", "modified_time"]
+    assert paths(table, tree) == [
+        "README.md",
+        "labA",
+        "labA/notes.txt",
+        "labA/results",
+        "labA/results/run1.csv",
+        "labA/results/run2.csv",
+        "labB",
+        "labB/data.bin",
+    ]
+    rows = {row["path"]: row for row in table.to_pylist()}
+    data = rows[str(tree / "labB" / "data.bin")]
+    assert data["size"] == 1000
+    assert data["top_level_dir"] == "labB"
+    assert data["file_type"] == "bin"
+    assert table.schema.field("size").type == pa.uint64()
+
+
+def test_scan_options(tree):
+    shallow = storage_scanner.scan(str(tree), max_depth=1)
+    assert paths(shallow, tree) == ["README.md", "labA", "labB"]
+
+    excluded = storage_scanner.scan(tree, exclude=["results", "*.md"])
+    assert paths(excluded, tree) == ["labA", "labA/notes.txt", "labB", "labB/data.bin"]
+
+    large = storage_scanner.scan(tree, exclude="labA", min_size=100)
+    assert paths(large, tree) == ["labB", "labB/data.bin"]
+
+
+def test_scan_of_an_empty_tree_keeps_the_schema(tmp_path):
+    table = storage_scanner.scan(tmp_path, max_depth=0)
+    assert table.num_rows <= 1
+    assert table.schema == storage_scanner.scan(tmp_path).schema
+
+
+def test_iter_scan_yields_dict_batches(tree):
+    batches = storage_scanner.iter_scan(tree, batch_size=3)
+    seen = []
+    for batch in batches:
+        assert isinstance(batch, dict)
+        assert 0 < len(batch["path"]) <= 3
+        assert len(batch["size"]) == len(batch["path"])
+        seen.extend(batch["path"])
+
+    assert sorted(seen) == sorted(storage_scanner.scan(tree).column("path").to_pylist())
+    assert batches.stats["files_scanned"] == 5
+    assert batches.stats["directories_scanned"] == 4
+    assert batches.stats["errors_encountered"] == 0
+
+
+def test_leaving_iter_scan_early(tree):
+    batches = storage_scanner.iter_scan(tree, batch_size=1)
+    first = next(batches)
+    assert len(first["path"]) == 1
+    assert batches.stats is None
+    del batches
+
+
+@pytest.mark.parametrize(
+    "options",
+    [
+        {"threads": 0},
+        {"threads": -1},
+        {"batch_size": "many"},
+        {"exclude": "**"},
+        {"exclude": "["},
+        {"max_depth": 0, "min_size": 10},
+    ],
+)
+def test_invalid_options_raise_value_error(tree, options):
+    with pytest.raises(ValueError):
+        storage_scanner.scan(tree, **options)
+    with pytest.raises(ValueError):
+        storage_scanner.iter_scan(tree, **options)
+
+
+def test_unknown_option_raises_type_error(tree):
+    with pytest.raises(TypeError, match="unexpected keyword argument 'depth'"):
+        storage_scanner.scan(tree, depth=2)
+
+
+def test_missing_root(tmp_path):
+    missing = tmp_path / "missing"
+    with pytest.raises(FileNotFoundError, match="missing"):
+        storage_scanner.scan(missing)
+    with pytest.raises(FileNotFoundError):
+        next(storage_scanner.iter_scan(missing))
@@ -0,0 +1,2267 @@
+use crate::dedupe::{self, PathDeduper};
+use crate::filter::RowFilter;
+use crate::manifest::{ChunkRetention, ScanManifest};
+use crate::memory::{self, ByteBudget, MemoryPlan};
+use crate::models::{ScanTag, SCAN_DATE_KEY, SCAN_ID_KEY};
+use crate::partitioned_writer::{DatasetManifest, PartitionedParquetWriter};
+use crate::sort::{self, ExternalSorter};
+use crate::stats::UsageStats;
+use crate::verify;
+use crate::writer::{schema_version, schema_version_entry, split_modes};
+use anyhow::{Context, Result};
+use arrow::array::{new_null_array, ArrayRef, StringArray};
+use arrow::compute::cast;
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use arrow::record_batch::RecordBatch;
+use crossbeam_channel::{bounded, SendError, Sender};
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ArrowWriter;
+use parquet::file::properties::WriterProperties;
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Decoded batches buffered per chunk ahead of the writer
+const READ_AHEAD_BATCHES: usize = 2;
+
+/// Result of aggregating chunk files into a single Parquet file
+#[derive(Debug, Clone, Default)]
+pub struct AggregateSummary {
+    /// Number of chunk files read
+    pub chunk_files: usize,
+
+    /// Total rows written to the output
+    pub total_rows: u64,
+
+    /// Wall-clock duration of the aggregation in seconds
+    pub duration_secs: f64,
+
+    /// Rows dropped as duplicates (0 unless deduplicating)
+    pub duplicates_dropped: u64,
+
+    /// Rows dropped by the row filter
+    pub rows_filtered: u64,
+
+    /// Rows read from each chunk file, in input order
+    pub chunk_rows: Vec<u64>,
+
+    /// Combined size of the chunk files read
+    pub input_bytes: u64,
+
+    /// Size of the aggregated output file (or of every file in the dataset)
+    pub output_bytes: u64,
+
+    /// Layout of the output when it is a partitioned dataset
+    pub dataset: Option<DatasetManifest>,
+
+    /// Usage statistics of the rows written, if collected
+    pub stats: Option<UsageStats>,
+
+    /// Sorted runs spilled to disk (0 if unsorted or sorted in memory)
+    pub sort_runs: usize,
+
+    /// Seconds until every chunk was decoded
+    pub read_secs: f64,
+
+    /// Seconds the writer spent deduplicating, sorting and writing, excluding
+    /// time spent waiting for decoded batches
+    pub write_secs: f64,
+}
+
+/// What to do with chunk files once they have been aggregated
+#[derive(Debug, Clone, PartialEq)]
+pub enum RetentionAction {
+    /// Delete the chunk files
+    Delete,
+
+    /// Move the chunk files into this directory
+    Archive(PathBuf),
+}
+
+/// Which aggregated chunks to remove and how
+#[derive(Debug, Clone)]
+pub struct RetentionPolicy {
+    pub action: RetentionAction,
+
+    /// Number of newest chunks to leave in place
+    pub retain_last: usize,
+
+    /// Also remove chunks no manifest lists (every chunk, without a manifest)
+    pub delete_unlisted: bool,
+
+    /// Only report what would be removed
+    pub dry_run: bool,
+}
+
+/// Result of applying a retention policy
+#[derive(Debug, Clone, Default)]
+pub struct RetentionSummary {
+    /// Chunks deleted or archived (original paths); with `dry_run`, the
+    /// chunks that would be
+    pub removed: Vec<PathBuf>,
+
+    /// Chunks left in place
+    pub retained: Vec<PathBuf>,
+
+    /// Chunks left in place because no manifest lists them (see `delete_unlisted`)
+    pub unlisted: Vec<PathBuf>,
+
+    /// Chunks that could not be deleted or archived, with the reason
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+/// Whether the input path points at a manifest rather than chunk data
+pub fn is_manifest_path(path: &Path) -> bool {
+    path.is_file() && path.extension().map(|e| e == "json").unwrap_or(false)
+}
+
+/// Column that identifies duplicate rows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DedupeKey {
+    /// Keep one row per path, the one with the newest `modified_time`
+    Path,
+}
+
+/// Options controlling how chunks are combined
+#[derive(Debug, Clone, Default)]
+pub struct AggregateOptions {
+    /// Require every chunk to match the first chunk's schema exactly
+    pub strict: bool,
+
+    /// Drop duplicate rows; ties go to the later chunk in input order
+    pub dedupe_by: Option<DedupeKey>,
+
+    /// Columns to sort the output by, most significant first (empty for input order)
+    pub sort_by: Vec<String>,
+
+    /// Memory budget for sorting in bytes (None for [`sort::DEFAULT_SORT_MEMORY`])
+    pub sort_memory: Option<u64>,
+
+    /// Threads decoding chunks in parallel (0 for one per CPU core)
+    pub threads: usize,
+
+    /// One tag per chunk file; when given, the output gains `scan_id` and
+    /// `scan_date` columns (see [`resolve_scan_tags`])
+    pub scan_tags: Vec<ScanTag>,
+
+    /// One host per chunk; when given, the output gains a `hostname` column
+    /// (see [`crate::host_merge`])
+    pub hostnames: Vec<String>,
+
+    /// Bound on memory in bytes; batch and row group sizes are derived from it
+    /// (see [`MemoryPlan`])
+    pub memory_limit: Option<u64>,
+
+    /// Only rows passing this filter are written
+    pub filter: RowFilter,
+
+    /// Write a hive-partitioned dataset split on this column, with the output
+    /// path as its directory (see [`PartitionedParquetWriter`])
+    pub partition_by: Option<String>,
+
+    /// Accumulate [`UsageStats`] over the rows written
+    pub collect_stats: bool,
+}
+
+/// Destination of aggregated rows
+enum OutputWriter {
+    File(ArrowWriter<fs::File>),
+    Dataset(PartitionedParquetWriter),
+}
+
+impl OutputWriter {
+    fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        match self {
+            OutputWriter::File(writer) => Ok(writer.write(batch)?),
+            OutputWriter::Dataset(writer) => writer.write(batch),
+        }
+    }
+
+    /// Close the output; returns the dataset layout when partitioned
+    fn close(self) -> Result<Option<DatasetManifest>> {
+        match self {
+            OutputWriter::File(writer) => {
+                writer.close()?;
+                Ok(None)
+            }
+            OutputWriter::Dataset(writer) => Ok(Some(writer.finish()?)),
+        }
+    }
+}
+
+/// Aggregate chunk files into a single Parquet file
+pub fn aggregate_chunks(chunk_files: &[PathBuf], output: &Path) -> Result<AggregateSummary> {
+    aggregate_chunks_with(chunk_files, output, &AggregateOptions::default())
+}
+
+/// Aggregate chunk files into a single Parquet file, or a partitioned dataset
+/// if `partition_by` is set.
+///
+/// Unless `strict` is set, chunks written by different scanner versions are
+/// reconciled: the output has the union of their columns, columns a chunk
+/// lacks are null, and narrower types are upcast (see [`reconcile_schemas`]).
+pub fn aggregate_chunks_with(
+    chunk_files: &[PathBuf],
+    output: &Path,
+    options: &AggregateOptions,
+) -> Result<AggregateSummary> {
+    if chunk_files.is_empty() {
+        anyhow::bail!("No chunk files to aggregate");
+    }
+
+    // Ensure output directory exists
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent)
+            .context("Failed to create output directory")?;
+    }
+
+    if !options.scan_tags.is_empty() && options.scan_tags.len() != chunk_files.len() {
+        anyhow::bail!(
+            "Got {} scan tags for {} chunk files",
+            options.scan_tags.len(),
+            chunk_files.len()
+        );
+    }
+    if !options.hostnames.is_empty() && options.hostnames.len() != chunk_files.len() {
+        anyhow::bail!(
+            "Got {} hostnames for {} chunk files",
+            options.hostnames.len(),
+            chunk_files.len()
+        );
+    }
+
+    let input_schema: SchemaRef = if options.strict {
+        // Read schema from first file
+        let first_file = fs::File::open(&chunk_files[0])?;
+        let first_reader = SerializedFileReader::new(first_file)?;
+        let schema = first_reader.metadata().file_metadata().schema_descr();
+        Arc::new(parquet::arrow::parquet_to_arrow_schema(schema, None)?)
+    } else {
+        reconcile_schemas(chunk_files)?
+    };
+    let input_schema = with_raw_mode(input_schema);
+
+    let mut tag_fields = Vec::new();
+    if !options.scan_tags.is_empty() {
+        tag_fields.extend(scan_tag_fields());
+    }
+    if !options.hostnames.is_empty() {
+        tag_fields.push(Field::new(HOSTNAME_COLUMN, DataType::Utf8, false));
+    }
+    let arrow_schema = if tag_fields.is_empty() {
+        input_schema.clone()
+    } else {
+        let mut fields: Vec<Field> = input_schema.fields().iter().map(|f| f.as_ref().clone()).collect();
+        for field in tag_fields {
+            if input_schema.field_with_name(field.name()).is_ok() {
+                anyhow::bail!("Chunks already have a '{}' column; they cannot be tagged again", field.name());
+            }
+            fields.push(field);
+        }
+        Arc::new(Schema::new(fields))
+    };
+    options.filter.validate(&input_schema)?;
+    if options.collect_stats {
+        UsageStats::validate(&input_schema)?;
+    }
+
+    let threads = match options.threads {
+        0 => num_cpus::get(),
+        n => n,
+    }.min(chunk_files.len());
+
+    // Each reader holds the batch it is decoding plus those queued for the writer
+    let plan = match options.memory_limit {
+        Some(limit) => {
+            let bytes_per_row = memory::estimate_bytes_per_row(&chunk_files[0])?;
+            let plan = MemoryPlan::new(limit, bytes_per_row, threads, READ_AHEAD_BATCHES + 1);
+            info!(
+                "Memory limit {}: ~{} bytes/row, {} rows per batch, {} rows per row group",
+                crate::utils::format_bytes(limit),
+                plan.bytes_per_row,
+                plan.batch_rows,
+                plan.row_group_rows
+            );
+            Some(plan)
+        }
+        None => None,
+    };
+
+    let props = plan.as_ref().map(|p| {
+        WriterProperties::builder().set_max_row_group_size(p.row_group_rows).build()
+    });
+    let mut writer = match options.partition_by {
+        Some(ref column) => {
+            info!("Creating dataset partitioned by {}...", column);
+            OutputWriter::Dataset(PartitionedParquetWriter::new(output, arrow_schema.clone(), column, props)?)
+        }
+        None => {
+            info!("Creating aggregated file...");
+            let output_file = fs::File::create(output)
+                .context("Failed to create output file")?;
+            let mut writer = ArrowWriter::try_new(output_file, arrow_schema.clone(), props)?;
+            writer.append_key_value_metadata(schema_version_entry());
+            OutputWriter::File(writer)
+        }
+    };
+    let partial = PartialOutput { path: output, dataset: options.partition_by.is_some() };
+
+    let mut total_rows = 0u64;
+    let mut rows_filtered = 0u64;
+    let mut stats = options.collect_stats.then(UsageStats::new);
+    let mut chunk_rows = vec![0u64; chunk_files.len()];
+    let start_time = Instant::now();
+
+    let mut input_bytes = 0u64;
+    for path in chunk_files {
+        input_bytes += fs::metadata(path)?.len();
+    }
+    let name = output.file_name().unwrap_or_default().to_string_lossy();
+
+    // Rows are spilled to partitions next to the output and written at the end
+    let mut deduper = match options.dedupe_by {
+        Some(DedupeKey::Path) => {
+            let spill_dir = output.with_file_name(format!(".{}.dedupe", name));
+            Some(PathDeduper::new(&spill_dir, arrow_schema.clone(), dedupe::partitions_for(input_bytes))?)
+        }
+        None => None,
+    };
+
+    // Sorted runs are spilled next to the output and merged at the end
+    let mut sorter = if options.sort_by.is_empty() {
+        None
+    } else {
+        let spill_dir = output.with_file_name(format!(".{}.sort", name));
+        let budget = options.sort_memory
+            .or(plan.as_ref().map(|p| p.sort_bytes))
+            .unwrap_or(sort::DEFAULT_SORT_MEMORY);
+        Some(ExternalSorter::new(&spill_dir, arrow_schema.clone(), &options.sort_by, budget)?)
+    };
+
+    // Rows surviving deduplication go to the sorter, or straight to the output.
+    // Filtering after deduplication keeps a filtered-out newer row from letting
+    // an older copy of the path through.
+    let mut sink = |batch: RecordBatch| -> Result<()> {
+        let batch = if options.filter.is_empty() {
+            batch
+        } else {
+            let filtered = options.filter.apply(&batch)?;
+            rows_filtered += (batch.num_rows() - filtered.num_rows()) as u64;
+            filtered
+        };
+        if let Some(ref mut stats) = stats {
+            stats.add_batch(&batch)?;
+        }
+        match sorter {
+            Some(ref mut sorter) => sorter.push(batch),
+            None => {
+                total_rows += batch.num_rows() as u64;
+                writer.write(&batch)
+            }
+        }
+    };
+
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build()?;
+    let budget = plan.as_ref().map(|p| ByteBudget::new(p.reader_bytes));
+
+    // One bounded channel per chunk: the writer drains them in chunk order, so
+    // the output order is deterministic and readers that get ahead block once
+    // their channel is full
+    let (senders, receivers): (Vec<_>, Vec<_>) = chunk_files.iter()
+        .map(|_| bounded::<Result<RecordBatch>>(READ_AHEAD_BATCHES))
+        .map(|(tx, rx)| (Mutex::new(Some(tx)), rx))
+        .unzip();
+    let next_chunk = AtomicUsize::new(0);
+    let decoder = ChunkDecoder {
+        input_schema,
+        output_schema: arrow_schema.clone(),
+        strict: options.strict,
+        scan_tags: &options.scan_tags,
+        hostnames: &options.hostnames,
+        batch_rows: plan.as_ref().map(|p| p.batch_rows).unwrap_or(memory::DEFAULT_BATCH_ROWS),
+        budget: budget.as_ref(),
+    };
+    let mut read_secs = 0.0;
+    let mut write_time = Duration::ZERO;
+
+    std::thread::scope(|scope| -> Result<()> {
+        let readers = scope.spawn(|| {
+            pool.scope(|pool_scope| {
+                for _ in 0..threads {
+                    pool_scope.spawn(|_| read_chunks(chunk_files, &senders, &next_chunk, &decoder));
+                }
+            });
+            start_time.elapsed().as_secs_f64()
+        });
+
+        let written = (|| -> Result<()> {
+            for (i, (chunk_path, receiver)) in chunk_files.iter().zip(receivers).enumerate() {
+                info!("  [{}/{}] Processing: {}", i + 1, chunk_files.len(), chunk_path.display());
+                if let Some(ref budget) = budget {
+                    budget.start_chunk(i);
+                }
+
+                for batch in receiver {
+                    let batch = batch?;
+                    let bytes = batch.get_array_memory_size() as u64;
+                    let write_start = Instant::now();
+                    chunk_rows[i] += batch.num_rows() as u64;
+                    match deduper {
+                        Some(ref mut deduper) => deduper.push(&batch, i as u32)?,
+                        None => sink(batch)?,
+                    }
+                    write_time += write_start.elapsed();
+                    if let Some(ref budget) = budget {
+                        budget.release(bytes);
+                    }
+                }
+            }
+            Ok(())
+        })();
+
+        // Readers waiting for room must not outlive a failed writer
+        if let Some(ref budget) = budget {
+            budget.close();
+        }
+        read_secs = readers.join().expect("chunk reader panicked");
+        written
+    })?;
+
+    let write_start = Instant::now();
+    let mut duplicates_dropped = 0;
+    if let Some(deduper) = deduper {
+        info!("Writing deduplicated rows...");
+        duplicates_dropped = deduper.finish(&mut sink)?;
+    }
+
+    let mut sort_runs = 0;
+    if let Some(sorter) = sorter {
+        info!("Writing sorted rows...");
+        sort_runs = sorter.finish(|batch| {
+            total_rows += batch.num_rows() as u64;
+            writer.write(&batch)
+        })?;
+    }
+
+    // Finalize writer
+    let dataset = writer.close()?;
+    write_time += write_start.elapsed();
+    let output_bytes = match dataset {
+        Some(ref dataset) => dataset.size_bytes(),
+        None => fs::metadata(output)?.len(),
+    };
+    std::mem::forget(partial);
+
+    Ok(AggregateSummary {
+        chunk_files: chunk_files.len(),
+        total_rows,
+        duration_secs: start_time.elapsed().as_secs_f64(),
+        duplicates_dropped,
+        rows_filtered,
+        chunk_rows,
+        input_bytes,
+        output_bytes,
+        dataset,
+        stats,
+        sort_runs,
+        read_secs,
+        write_secs: write_time.as_secs_f64(),
+    })
+}
+
+/// An aggregate output being written, removed if it is dropped before the
+/// writer is closed so a failed run leaves no partial file or dataset
+struct PartialOutput<'a> {
+    path: &'a Path,
+    dataset: bool,
+}
+
+impl Drop for PartialOutput<'_> {
+    fn drop(&mut self) {
+        let removed = if self.dataset { fs::remove_dir_all(self.path) } else { fs::remove_file(self.path) };
+        match removed {
+            Ok(()) => warn!("Removed partial output {}", self.path.display()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => warn!("Failed to remove partial output {}: {}", self.path.display(), e),
+        }
+    }
+}
+
+/// How readers decode chunk batches into output batches
+struct ChunkDecoder<'a> {
+    /// Schema every chunk is conformed to (unless strict)
+    input_schema: SchemaRef,
+
+    /// `input_schema` plus the scan tag and hostname columns, if tagging
+    output_schema: SchemaRef,
+
+    strict: bool,
+
+    /// One tag per chunk file, or empty
+    scan_tags: &'a [ScanTag],
+
+    /// One host per chunk file, or empty
+    hostnames: &'a [String],
+
+    /// Rows per decoded batch
+    batch_rows: usize,
+
+    /// Cap on decoded bytes waiting for the writer, under a memory limit
+    budget: Option<&'a ByteBudget>,
+}
+
+impl ChunkDecoder<'_> {
+    /// Decode a batch of chunk `chunk`, which is `legacy` if it has no
+    /// schema version (see [`conform_chunk_batch`])
+    fn decode(&self, chunk: usize, legacy: bool, batch: RecordBatch) -> Result<RecordBatch> {
+        let batch = match (self.strict, legacy) {
+            (false, _) => conform_chunk_batch(&batch, &self.input_schema, legacy)?,
+            (true, false) => batch,
+            // Strict chunks must match but for the `raw_mode` their modes go to
+            (true, true) => {
+                let mut columns = batch.columns().to_vec();
+                if batch.column_by_name("raw_mode").is_none() && self.input_schema.field_with_name("raw_mode").is_ok() {
+                    columns.push(new_null_array(&DataType::UInt32, batch.num_rows()));
+                }
+                let batch = RecordBatch::try_new(self.input_schema.clone(), columns)?;
+                conform_chunk_batch(&batch, &self.input_schema, true)?
+            }
+        };
+        let mut columns = batch.columns().to_vec();
+        if let Some(tag) = self.scan_tags.get(chunk) {
+            columns.extend(tag_columns(batch.num_rows(), tag)?);
+        }
+        if let Some(host) = self.hostnames.get(chunk) {
+            columns.push(Arc::new(StringArray::from(vec![host.as_str(); batch.num_rows()])));
+        }
+        if columns.len() == batch.num_columns() {
+            return Ok(batch);
+        }
+        Ok(RecordBatch::try_new(self.output_schema.clone(), columns)?)
+    }
+}
+
+/// Reader loop: claim chunks in index order and send their batches to the
+/// chunk's channel. Returns early once the writer has hung up.
+fn read_chunks(
+    chunk_files: &[PathBuf],
+    senders: &[Mutex<Option<Sender<Result<RecordBatch>>>>],
+    next_chunk: &AtomicUsize,
+    decoder: &ChunkDecoder,
+) {
+    loop {
+        let i = next_chunk.fetch_add(1, Ordering::SeqCst);
+        if i >= chunk_files.len() {
+            return;
+        }
+
+        // Dropping the sender afterwards tells the writer the chunk is done
+        let sender = senders[i].lock().unwrap().take().expect("chunk claimed twice");
+        if send_chunk(i, &chunk_files[i], decoder, &sender).is_err() {
+            return;
+        }
+    }
+}
+
+/// Decode one chunk and send its batches, stopping after the first error
+fn send_chunk(
+    chunk: usize,
+    chunk_path: &Path,
+    decoder: &ChunkDecoder,
+    sender: &Sender<Result<RecordBatch>>,
+) -> std::result::Result<(), SendError<Result<RecordBatch>>> {
+    let reader = fs::File::open(chunk_path)
+        .with_context(|| format!("Failed to open chunk {}", chunk_path.display()))
+        .and_then(|file| {
+            let builder = ParquetRecordBatchReaderBuilder::try_new(file)?;
+            let legacy = schema_version(builder.metadata().file_metadata()).is_none();
+            Ok((legacy, builder.with_batch_size(decoder.batch_rows).build()?))
+        });
+    let (legacy, reader) = match reader {
+        Ok(reader) => reader,
+        Err(e) => return sender.send(Err(e)),
+    };
+
+    for batch in reader {
+        let batch = match batch {
+            Ok(batch) => decoder.decode(chunk, legacy, batch)
+                .with_context(|| format!("Failed to convert {}", chunk_path.display())),
+            Err(e) => Err(anyhow::Error::from(e))
+                .with_context(|| format!("Failed to read chunk {}", chunk_path.display())),
+        };
+        let failed = batch.is_err();
+        if let (Some(budget), Ok(batch)) = (decoder.budget, &batch) {
+            budget.acquire(batch.get_array_memory_size() as u64, chunk);
+        }
+        sender.send(batch)?;
+        if failed {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Columns added to the output when tagging rows with their scan
+pub(crate) fn scan_tag_fields() -> [Field; 2] {
+    [
+        Field::new(SCAN_ID_KEY, DataType::Utf8, false),
+        Field::new(SCAN_DATE_KEY, DataType::Date32, true),
+    ]
+}
+
+/// Column added to the output when tagging rows with the host that scanned them
+pub const HOSTNAME_COLUMN: &str = "hostname";
+
+/// `scan_id` and `scan_date` columns of a chunk's `rows` rows
+fn tag_columns(rows: usize, tag: &ScanTag) -> Result<[ArrayRef; 2]> {
+    let dates = StringArray::from(vec![tag.scan_date.as_deref(); rows]);
+    Ok([
+        Arc::new(StringArray::from(vec![tag.scan_id.as_str(); rows])),
+        cast(&dates, &DataType::Date32)?,
+    ])
+}
+
+/// Scan tag stored in a chunk's Parquet footer, if any
+pub fn read_scan_tag(chunk: &Path) -> Result<Option<ScanTag>> {
+    let file = fs::File::open(chunk)
+        .with_context(|| format!("Failed to open chunk {}", chunk.display()))?;
+    let reader = SerializedFileReader::new(file)
+        .with_context(|| format!("Failed to read footer of {}", chunk.display()))?;
+    let pairs = reader.metadata().file_metadata().key_value_metadata()
+        .map(|kv| kv.iter().map(|p| (p.key.as_str(), p.value.as_deref())).collect::<Vec<_>>())
+        .unwrap_or_default();
+    Ok(ScanTag::from_metadata(pairs))
+}
+
+/// One `--input` of an aggregation and the chunk files it resolved to
+#[derive(Debug, Clone)]
+pub struct AggregateInput {
+    pub input: PathBuf,
+    pub chunk_files: Vec<PathBuf>,
+
+    /// Scan identifier given for this input, overriding chunk metadata
+    pub scan_id: Option<String>,
+}
+
+/// Work out which scan each chunk belongs to, in input then chunk order.
+///
+/// An explicit scan id wins; otherwise the tag stamped in the chunk's footer
+/// is used, falling back to the input's manifest for chunks written before
+/// chunks were tagged. A chunk with none of these has no tag. Dates missing
+/// from an explicit id are filled in the same way.
+pub fn resolve_scan_tags(inputs: &[AggregateInput]) -> Result<Vec<Option<ScanTag>>> {
+    let mut tags = Vec::new();
+    for input in inputs {
+        let manifest_path = get_manifest_path(&input.input);
+        let manifest_tag = if manifest_path.is_file() {
+            Some(ScanTag::from_scan_start(ScanManifest::load_from_file(&manifest_path)?.scan_start))
+        } else {
+            None
+        };
+
+        for chunk in &input.chunk_files {
+            let found = read_scan_tag(chunk)?.or_else(|| manifest_tag.clone());
+            let tag = match input.scan_id {
+                Some(ref id) => {
+                    let mut tag = ScanTag::from_id(id);
+                    if tag.scan_date.is_none() {
+                        tag.scan_date = found.and_then(|t| t.scan_date);
+                    }
+                    Some(tag)
+                }
+                None => found,
+            };
+            tags.push(tag);
+        }
+    }
+    Ok(tags)
+}
+
+/// Union of the columns of every chunk, in first-seen order.
+///
+/// A column missing from some chunk, or nullable in any, is nullable in the
+/// result. Differing types are widened when that is lossless (integers of
+/// the same signedness to the wider width, unsigned to a wider signed type,
+/// Float32 to Float64, dictionaries to their value type); any other
+/// difference fails with a report naming each offending file.
+pub fn reconcile_schemas(chunk_files: &[PathBuf]) -> Result<SchemaRef> {
+    // (field, file the current type came from, number of files with the column)
+    let mut fields: Vec<(Field, usize, usize)> = Vec::new();
+    let mut conflicts = Vec::new();
+
+    for (file_index, path) in chunk_files.iter().enumerate() {
+        let file = fs::File::open(path)
+            .with_context(|| format!("Failed to open chunk {}", path.display()))?;
+        let schema = ParquetRecordBatchReaderBuilder::try_new(file)
+            .with_context(|| format!("Failed to read schema of {}", path.display()))?
+            .schema()
+            .clone();
+
+        for field in schema.fields() {
+            let data_type = value_type(field.data_type());
+            match fields.iter_mut().find(|(f, _, _)| f.name() == field.name()) {
+                Some((existing, source, seen)) => {
+                    *seen += 1;
+                    match widen(existing.data_type(), &data_type) {
+                        Some(widened) => {
+                            let nullable = existing.is_nullable() || field.is_nullable();
+                            *existing = Field::new(field.name(), widened, nullable);
+                        }
+                        None => conflicts.push(format!(
+                            "  {}: column '{}' is {:?} but {:?} in {}",
+                            path.display(),
+                            field.name(),
+                            field.data_type(),
+                            existing.data_type(),
+                            chunk_files[*source].display()
+                        )),
+                    }
+                }
+                None => {
+                    fields.push((Field::new(field.name(), data_type, field.is_nullable()), file_index, 1));
+                }
+            }
+        }
+    }
+
+    if !conflicts.is_empty() {
+        anyhow::bail!("Chunk schemas are incompatible:\n{}", conflicts.join("\n"));
+    }
+
+    let fields: Vec<Field> = fields.into_iter()
+        .map(|(field, _, seen)| {
+            // A column some files lack is null for their rows
+            let nullable = field.is_nullable() || seen < chunk_files.len();
+            field.with_nullable(nullable)
+        })
+        .collect();
+
+    Ok(Arc::new(Schema::new(fields)))
+}
+
+/// Cast a batch to a reconciled schema, filling absent columns with nulls
+pub(crate) fn conform_batch(batch: &RecordBatch, schema: &SchemaRef) -> Result<RecordBatch> {
+    let columns = schema.fields().iter()
+        .map(|field| match batch.column_by_name(field.name()) {
+            Some(column) if column.data_type() == field.data_type() => Ok(column.clone()),
+            Some(column) => Ok(cast(column, field.data_type())?),
+            None => Ok(new_null_array(field.data_type(), batch.num_rows())),
+        })
+        .collect::<Result<Vec<ArrayRef>>>()?;
+
+    Ok(RecordBatch::try_new(schema.clone(), columns)?)
+}
+
+/// `schema` plus a nullable `raw_mode` if it has `permissions` but no
+/// `raw_mode`, for the modes of chunks from before that column to go to
+pub(crate) fn with_raw_mode(schema: SchemaRef) -> SchemaRef {
+    if schema.field_with_name("permissions").is_err() || schema.field_with_name("raw_mode").is_ok() {
+        return schema;
+    }
+    let mut fields: Vec<Field> = schema.fields().iter().map(|f| f.as_ref().clone()).collect();
+    fields.push(Field::new("raw_mode", DataType::UInt32, true));
+    Arc::new(Schema::new(fields))
+}
+
+/// [`conform_batch`] for a batch read from a chunk. Chunks without a schema
+/// version are `legacy`: their `permissions` hold full modes, which are split
+/// into `raw_mode` the way [`crate::writer::ParquetFileWriter`] does, so the
+/// rows fit the version stamped on the output.
+pub(crate) fn conform_chunk_batch(batch: &RecordBatch, schema: &SchemaRef, legacy: bool) -> Result<RecordBatch> {
+    let batch = conform_batch(batch, schema)?;
+    if legacy && schema.field_with_name("permissions").is_ok() && schema.field_with_name("raw_mode").is_ok() {
+        split_modes(&batch)
+    } else {
+        Ok(batch)
+    }
+}
+
+/// Dictionary-encoded columns are reconciled by their value type
+pub(crate) fn value_type(data_type: &DataType) -> DataType {
+    match data_type {
+        DataType::Dictionary(_, value) => value.as_ref().clone(),
+        other => other.clone(),
+    }
+}
+
+/// Lossless common type of two column types, if there is one
+fn widen(a: &DataType, b: &DataType) -> Option<DataType> {
+    use DataType::*;
+
+    if a == b {
+        return Some(a.clone());
+    }
+
+    let signed_width = |t: &DataType| match t {
+        Int8 => Some(8),
+        Int16 => Some(16),
+        Int32 => Some(32),
+        Int64 => Some(64),
+        _ => None,
+    };
+    let unsigned_width = |t: &DataType| match t {
+        UInt8 => Some(8),
+        UInt16 => Some(16),
+        UInt32 => Some(32),
+        UInt64 => Some(64),
+        _ => None,
+    };
+    let signed = |width: u32| match width {
+        8 => Int8,
+        16 => Int16,
+        32 => Int32,
+        _ => Int64,
+    };
+    let unsigned = |width: u32| match width {
+        8 => UInt8,
+        16 => UInt16,
+        32 => UInt32,
+        _ => UInt64,
+    };
+
+    match (signed_width(a), signed_width(b), unsigned_width(a), unsigned_width(b)) {
+        (Some(x), Some(y), _, _) => return Some(signed(x.max(y))),
+        (_, _, Some(x), Some(y)) => return Some(unsigned(x.max(y))),
+        // An unsigned type fits a strictly wider signed one
+        (Some(s), None, None, Some(u)) | (None, Some(s), Some(u), None) if u < 64 => {
+            return Some(signed(s.max(u * 2)));
+        }
+        _ => {}
+    }
+
+    match (a, b) {
+        (Float32, Float64) | (Float64, Float32) => Some(Float64),
+        (Utf8, LargeUtf8) | (LargeUtf8, Utf8) => Some(LargeUtf8),
+        _ => None,
+    }
+}
+
+/// Split a chunk file name of the form `{stem}_chunk_{NNNN}.parquet` into
+/// its stem and chunk number
+pub fn parse_chunk_file_name(name: &str) -> Option<(&str, usize)> {
+    let (stem, number) = name.strip_suffix(".parquet")?.rsplit_once("_chunk_")?;
+    if stem.is_empty() || number.len() < 4 || !number.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some((stem, number.parse().ok()?))
+}
+
+fn is_chunk_file(path: &Path) -> bool {
+    path.is_file() && path.file_name()
+        .and_then(|n| n.to_str())
+        .and_then(parse_chunk_file_name)
+        .is_some()
+}
+
+/// Whether the input contains glob metacharacters
+fn is_glob_pattern(input: &Path) -> bool {
+    input.to_string_lossy().contains(['*', '?', '['])
+}
+
+/// Find the chunk files referenced by an aggregate input.
+///
+/// The input may be a manifest (including a merged one), a glob pattern, a
+/// directory of chunk files, a single Parquet file, or a base output path
+/// whose chunks live next to it. Directories and base paths only match
+/// `{stem}_chunk_{NNNN}.parquet` files; a base path with a manifest next to
+/// it, or chunks in a directory holding their `{stem}_manifest.json`, use
+/// the manifest's chunk list.
+pub fn find_chunk_files(input: &Path) -> Result<Vec<PathBuf>> {
+    if is_manifest_path(input) {
+        let manifest = ScanManifest::load_from_file(input)?;
+        return Ok(manifest.chunks.iter()
+            .filter(|c| !c.is_deleted())
+            .map(|c| PathBuf::from(&c.file_path))
+            .collect());
+    }
+
+    let mut chunk_files = Vec::new();
+
+    if input.is_dir() {
+        // Input is a directory: take each scan's chunks from its manifest
+        // when it is there, and from the files matching the pattern if not
+        let mut by_stem: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+        for entry in fs::read_dir(input)? {
+            let path = entry?.path();
+            if !is_chunk_file(&path) {
+                continue;
+            }
+            let stem = path.file_name()
+                .and_then(|n| n.to_str())
+                .and_then(parse_chunk_file_name)
+                .map(|(stem, _)| stem.to_string())
+                .unwrap_or_default();
+            by_stem.entry(stem).or_default().push(path);
+        }
+        for (stem, paths) in by_stem {
+            let manifest_path = input.join(format!("{}_manifest.json", stem));
+            if manifest_path.is_file() {
+                chunk_files.extend(find_chunk_files(&manifest_path)?);
+            } else {
+                chunk_files.extend(paths);
+            }
+        }
+    } else if input.is_file() {
+        // Input is a single file
+        chunk_files.push(input.to_path_buf());
+    } else if is_glob_pattern(input) {
+        let pattern = input.to_string_lossy();
+        for path in glob::glob(&pattern)
+            .with_context(|| format!("Invalid glob pattern '{}'", pattern))?
+        {
+            let path = path?;
+            // The manifest's Parquet sidecar is not chunk data
+            let is_sidecar = path.file_name()
+                .map(|n| n.to_string_lossy().ends_with("_manifest.parquet"))
+                .unwrap_or(false);
+            if path.is_file() && !is_sidecar {
+                chunk_files.push(path);
+            }
+        }
+    } else {
+        // Input is a base output path - use its manifest, or its chunk files
+        let manifest_path = get_manifest_path(input);
+        if manifest_path.is_file() {
+            return find_chunk_files(&manifest_path);
+        }
+
+        let parent = input.parent()
+            .filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+
+        let base_name = input.file_stem()
+            .and_then(|s| s.to_str())
+            .ok_or_else(|| anyhow::anyhow!("Invalid base filename"))?;
+
+        for entry in fs::read_dir(parent)? {
+            let path = entry?.path();
+            let matches = path.file_name()
+                .and_then(|n| n.to_str())
+                .and_then(parse_chunk_file_name)
+                .map(|(stem, _)| stem == base_name)
+                .unwrap_or(false);
+            if matches && path.is_file() {
+                chunk_files.push(path);
+            }
+        }
+    }
+
+    // Sort files for consistent ordering
+    chunk_files.sort();
+
+    Ok(chunk_files)
+}
+
+/// Disagreements between an aggregation and what its chunks should hold
+#[derive(Debug, Clone, Default)]
+pub struct AggregationCheck {
+    /// Rows the manifest (or the chunk footers, without one) says exist
+    pub expected_rows: u64,
+
+    /// Rows the aggregation read from the chunks
+    pub rows_read: u64,
+
+    /// Chunks that disagree, with the reason
+    pub chunk_problems: Vec<(PathBuf, String)>,
+
+    /// Disagreements not tied to a single chunk
+    pub problems: Vec<String>,
+}
+
+impl AggregationCheck {
+    pub fn is_ok(&self) -> bool {
+        self.chunk_problems.is_empty() && self.problems.is_empty()
+    }
+}
+
+/// Check that an aggregation read every row of its chunk files.
+///
+/// With a manifest, each chunk it lists must match its entry (size, footer
+/// row count and the rows actually read), every chunk the manifest lists must
+/// have been aggregated, and the totals must agree. Chunks the manifest does
+/// not list, and every chunk without a manifest, must have had as many rows
+/// read as their footer lists.
+///
+/// Returns an error only if the chunks cannot be inspected; disagreements are
+/// collected in the returned [`AggregationCheck`].
+pub fn verify_aggregation(
+    manifest: Option<&ScanManifest>,
+    chunk_files: &[PathBuf],
+    summary: &AggregateSummary,
+) -> Result<AggregationCheck> {
+    let mut check = AggregationCheck {
+        rows_read: summary.chunk_rows.iter().sum(),
+        ..Default::default()
+    };
+
+    match manifest {
+        Some(manifest) => {
+            let report = verify::verify_manifest(manifest, false);
+            check.problems.extend(report.problems);
+
+            for (path, &rows) in chunk_files.iter().zip(&summary.chunk_rows) {
+                let chunk = match report.chunks.iter().find(|c| same_path(Path::new(&c.file_path), path)) {
+                    Some(chunk) => chunk,
+                    None => {
+                        // Not the manifest's to vouch for; apply_retention leaves it alone
+                        check_footer_rows(&mut check, path, rows)?;
+                        continue;
+                    }
+                };
+                for problem in &chunk.problems {
+                    check.chunk_problems.push((path.clone(), problem.clone()));
+                }
+                if rows != chunk.expected_rows {
+                    check.chunk_problems.push((
+                        path.clone(),
+                        format!("manifest lists {} rows but {} were read", chunk.expected_rows, rows),
+                    ));
+                }
+            }
+
+            for chunk in manifest.chunks.iter().filter(|c| !c.is_deleted()) {
+                check.expected_rows += chunk.row_count;
+                if !chunk_files.iter().any(|p| same_path(Path::new(&chunk.file_path), p)) {
+                    check.chunk_problems.push((
+                        PathBuf::from(&chunk.file_path),
+                        "listed in the manifest but not aggregated".to_string(),
+                    ));
+                }
+            }
+        }
+        None => {
+            for (path, &rows) in chunk_files.iter().zip(&summary.chunk_rows) {
+                check_footer_rows(&mut check, path, rows)?;
+            }
+        }
+    }
+
+    if check.rows_read != check.expected_rows {
+        check.problems.push(format!(
+            "aggregation read {} rows but {} are expected",
+            check.rows_read, check.expected_rows
+        ));
+    }
+
+    // Dropped duplicates and filtered rows were still read from the chunks
+    let rows_accounted = summary.total_rows + summary.duplicates_dropped + summary.rows_filtered;
+    if rows_accounted != check.rows_read {
+        check.problems.push(format!(
+            "output accounts for {} rows but {} were read",
+            rows_accounted, check.rows_read
+        ));
+    }
+
+    Ok(check)
+}
+
+/// Expect the rows a chunk's footer lists, and that they were all read
+fn check_footer_rows(check: &mut AggregationCheck, path: &Path, rows: u64) -> Result<()> {
+    let footer_rows = verify::count_rows_footer(path)
+        .with_context(|| format!("Failed to read row count of {}", path.display()))?;
+    check.expected_rows += footer_rows;
+    if rows != footer_rows {
+        check.chunk_problems.push((
+            path.to_path_buf(),
+            format!("footer lists {} rows but {} were read", footer_rows, rows),
+        ));
+    }
+    Ok(())
+}
+
+/// Delete or archive aggregated chunk files, keeping the newest
+/// `retain_last`, and record the outcome in the manifest.
+///
+/// Call only after [`verify_aggregation`] has passed. The manifest is kept
+/// and saved with each removed chunk marked as deleted or archived. Only
+/// chunks the manifest lists are removed, unless `delete_unlisted` is set;
+/// without a manifest, that means nothing is. With `dry_run`, the summary
+/// lists what would be removed and nothing is touched.
+pub fn apply_retention(
+    chunk_files: &[PathBuf],
+    manifest: Option<(&Path, &mut ScanManifest)>,
+    policy: &RetentionPolicy,
+) -> Result<RetentionSummary> {
+    // Order oldest first: by chunk number when the manifest knows it
+    let mut ordered: Vec<PathBuf> = Vec::new();
+    let mut unlisted: Vec<PathBuf> = chunk_files.to_vec();
+    if let Some((_, ref manifest)) = manifest {
+        let chunk_number = |path: &Path| manifest.chunks.iter()
+            .find(|c| same_path(Path::new(&c.file_path), path))
+            .map(|c| c.chunk_number);
+
+        let (listed, rest): (Vec<PathBuf>, Vec<PathBuf>) = unlisted.into_iter()
+            .partition(|path| chunk_number(path).is_some());
+        ordered = listed;
+        ordered.sort_by_key(|path| chunk_number(path));
+        unlisted = rest;
+    }
+
+    // Unlisted chunks come after listed ones, ordered by the number in their name
+    if policy.delete_unlisted {
+        unlisted.sort_by_cached_key(|path| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+            (parse_chunk_file_name(&name).map(|(_, n)| n), path.clone())
+        });
+        ordered.append(&mut unlisted);
+    }
+
+    let split = ordered.len().saturating_sub(policy.retain_last);
+    let retained = ordered.split_off(split);
+
+    if policy.dry_run {
+        return Ok(RetentionSummary {
+            removed: ordered,
+            retained,
+            unlisted,
+            failed: Vec::new(),
+        });
+    }
+
+    if let RetentionAction::Archive(ref dir) = policy.action {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create archive directory {}", dir.display()))?;
+    }
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+
+    let mut summary = RetentionSummary {
+        retained,
+        unlisted,
+        ..Default::default()
+    };
+    let mut outcomes = Vec::new();
+
+    for path in ordered {
+        let result = match policy.action {
+            RetentionAction::Delete => fs::remove_file(&path)
+                .map(|_| (ChunkRetention::Deleted { at: now }, path.clone())),
+            RetentionAction::Archive(ref dir) => archive_file(&path, dir).map(|dest| {
+                let original_path = path.to_string_lossy().to_string();
+                (ChunkRetention::Archived { at: now, original_path }, dest)
+            }),
+        };
+
+        match result {
+            Ok(outcome) => {
+                info!("  Removed: {}", path.display());
+                outcomes.push((path.clone(), outcome));
+                summary.removed.push(path);
+            }
+            Err(e) => {
+                tracing::error!("  Failed to remove {}: {}", path.display(), e);
+                summary.failed.push((path, e.to_string()));
+            }
+        }
+    }
+
+    if let Some((manifest_path, manifest)) = manifest {
+        for (path, (retention, new_path)) in outcomes {
+            if let Some(chunk) = manifest.chunks.iter_mut()
+                .find(|c| same_path(Path::new(&c.file_path), &path))
+            {
+                chunk.file_path = new_path.to_string_lossy().to_string();
+                chunk.retention = Some(retention);
+            }
+        }
+        manifest.save_to_file(manifest_path)?;
+    }
+
+    Ok(summary)
+}
+
+/// Move a file into a directory, copying when it is on another filesystem
+fn archive_file(path: &Path, dir: &Path) -> std::io::Result<PathBuf> {
+    let name = path.file_name()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "chunk path has no file name"))?;
+    let dest = dir.join(name);
+    if dest.exists() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("{} already exists", dest.display()),
+        ));
+    }
+
+    if fs::rename(path, &dest).is_err() {
+        fs::copy(path, &dest)?;
+        fs::remove_file(path)?;
+    }
+    Ok(dest)
+}
+
+fn same_path(a: &Path, b: &Path) -> bool {
+    match (std::path::absolute(a), std::path::absolute(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// Locate the manifest belonging to an aggregate input
+pub fn get_manifest_path(input: &Path) -> PathBuf {
+    if is_manifest_path(input) {
+        return input.to_path_buf();
+    }
+
+    if input.is_dir() {
+        // Look for any manifest file in the directory
+        if let Ok(entries) = fs::read_dir(input) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if let Some(name) = path.file_name() {
+                    // Skip the Parquet sidecar written next to the JSON manifest
+                    if name.to_string_lossy().contains("manifest") && is_manifest_path(&path) {
+                        return path;
+                    }
+                }
+            }
+        }
+    }
+
+    // Default: assume manifest is next to the input
+    let stem = input.file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("scan");
+    input.with_file_name(format!("{}_manifest.json", stem))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::FileEntry;
+    use crate::rotating_writer::{RotatingParquetWriter, RotatingWriterConfig};
+    use crate::writer::{ParquetFileWriter, LATER_COLUMNS, SCHEMA_VERSION};
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    fn create_test_entry(path: &str) -> FileEntry {
+        FileEntry::fixture(path)
+    }
+
+    fn write_scan(dir: &Path, name: &str, rows: usize) -> PathBuf {
+        let config = RotatingWriterConfig {
+            base_output_path: dir.join(format!("{}.parquet", name)),
+            rows_per_chunk: 3,
+            time_interval: Duration::from_secs(3600),
+        };
+
+        let mut writer = RotatingParquetWriter::new(config, "/test".to_string()).unwrap();
+        for i in 0..rows {
+            writer.write_batch(&[create_test_entry(&format!("/test/{}_{}.txt", name, i))]).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        dir.join(format!("{}_manifest.json", name))
+    }
+
+    #[test]
+    fn test_aggregate_directory() {
+        let temp_dir = TempDir::new().unwrap();
+        write_scan(temp_dir.path(), "scan", 7);
+
+        let chunks = find_chunk_files(temp_dir.path()).unwrap();
+        let output = temp_dir.path().join("out/aggregated.parquet");
+        let summary = aggregate_chunks(&chunks, &output).unwrap();
+
+        assert_eq!(summary.total_rows, 7);
+        assert_eq!(summary.chunk_files, chunks.len());
+        assert!(output.exists());
+    }
+
+    #[test]
+    fn test_parse_chunk_file_name() {
+        assert_eq!(parse_chunk_file_name("scan_chunk_0001.parquet"), Some(("scan", 1)));
+        assert_eq!(parse_chunk_file_name("my_scan_chunk_12345.parquet"), Some(("my_scan", 12345)));
+        assert_eq!(parse_chunk_file_name("other_data.parquet"), None);
+        assert_eq!(parse_chunk_file_name("scan_chunk_01.parquet"), None);
+        assert_eq!(parse_chunk_file_name("scan_chunk_0001.parquet.torn"), None);
+        assert_eq!(parse_chunk_file_name("scan_manifest.parquet"), None);
+        assert_eq!(parse_chunk_file_name("_chunk_0001.parquet"), None);
+    }
+
+    /// A scan directory with two unrelated Parquet files next to the chunks
+    fn scan_with_strays(dir: &Path) -> (PathBuf, Vec<PathBuf>) {
+        let manifest_path = write_scan(dir, "scan", 7);
+        fs::copy(dir.join("scan_chunk_0001.parquet"), dir.join("other_data.parquet")).unwrap();
+        fs::copy(dir.join("scan_chunk_0001.parquet"), dir.join("chunk_stats.parquet")).unwrap();
+
+        let expected = (1..=3).map(|n| dir.join(format!("scan_chunk_{:04}.parquet", n))).collect();
+        (manifest_path, expected)
+    }
+
+    #[test]
+    fn test_directory_input_ignores_unrelated_parquet() {
+        let temp_dir = TempDir::new().unwrap();
+        let (_, expected) = scan_with_strays(temp_dir.path());
+
+        assert_eq!(find_chunk_files(temp_dir.path()).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_directory_input_takes_a_manifests_chunks() {
+        let temp_dir = TempDir::new().unwrap();
+        let (manifest_path, expected) = scan_with_strays(temp_dir.path());
+        fs::copy(&expected[0], temp_dir.path().join("export_2024_data.parquet")).unwrap();
+        let stray = temp_dir.path().join("scan_chunk_0099.parquet");
+        fs::copy(&expected[0], &stray).unwrap();
+
+        // Another scan in the same directory, without a manifest
+        let other = write_scan(temp_dir.path(), "other", 2);
+        fs::remove_file(other).unwrap();
+        let mut with_other = expected.clone();
+        with_other.insert(0, temp_dir.path().join("other_chunk_0001.parquet"));
+
+        assert_eq!(find_chunk_files(temp_dir.path()).unwrap(), with_other);
+        let output = temp_dir.path().join("out/aggregated.parquet");
+        let summary = aggregate_chunks(&find_chunk_files(temp_dir.path()).unwrap(), &output).unwrap();
+        assert_eq!(summary.total_rows, 7 + 2);
+
+        // Without the manifest every file matching the pattern is a chunk
+        fs::remove_file(&manifest_path).unwrap();
+        let mut with_stray = with_other;
+        with_stray.push(stray);
+        assert_eq!(find_chunk_files(temp_dir.path()).unwrap(), with_stray);
+    }
+
+    #[test]
+    fn test_glob_input() {
+        let temp_dir = TempDir::new().unwrap();
+        let (_, expected) = scan_with_strays(temp_dir.path());
+        write_scan(temp_dir.path(), "other", 2);
+
+        let pattern = temp_dir.path().join("scan_chunk_*.parquet");
+        assert_eq!(find_chunk_files(&pattern).unwrap(), expected);
+
+        // A broad pattern picks up every Parquet file except the manifest sidecars
+        let pattern = temp_dir.path().join("*.parquet");
+        let found = find_chunk_files(&pattern).unwrap();
+        assert_eq!(found.len(), 3 + 1 + 2);
+        assert!(found.iter().all(|p| !p.to_string_lossy().contains("manifest")));
+
+        let pattern = temp_dir.path().join("nothing_*.parquet");
+        assert!(find_chunk_files(&pattern).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_base_path_input_uses_manifest() {
+        let temp_dir = TempDir::new().unwrap();
+        let (manifest_path, expected) = scan_with_strays(temp_dir.path());
+
+        // A chunk-named file the manifest doesn't list
+        let stray = temp_dir.path().join("scan_chunk_0099.parquet");
+        fs::copy(&expected[0], &stray).unwrap();
+
+        let base = temp_dir.path().join("scan.parquet");
+        assert_eq!(find_chunk_files(&base).unwrap(), expected);
+        assert_eq!(find_chunk_files(&manifest_path).unwrap(), expected);
+
+        // Without the manifest only the strict chunk pattern is used
+        fs::remove_file(&manifest_path).unwrap();
+        let mut with_stray = expected.clone();
+        with_stray.push(stray);
+        assert_eq!(find_chunk_files(&base).unwrap(), with_stray);
+    }
+
+    #[test]
+    fn test_aggregate_merged_manifest() {
+        let temp_dir = TempDir::new().unwrap();
+        let first = write_scan(temp_dir.path(), "first", 4);
+        let second = write_scan(temp_dir.path(), "second", 5);
+
+        let merged = ScanManifest::merge_files(&[&first, &second], false).unwrap();
+        let merged_path = temp_dir.path().join("merged_manifest.json");
+        merged.save_to_file(&merged_path).unwrap();
+
+        let chunks = find_chunk_files(&merged_path).unwrap();
+        assert_eq!(chunks.len(), merged.chunk_count);
+        assert_eq!(get_manifest_path(&merged_path), merged_path);
+
+        let output = temp_dir.path().join("aggregated.parquet");
+        let summary = aggregate_chunks(&chunks, &output).unwrap();
+        assert_eq!(summary.total_rows, 9);
+    }
+
+    #[test]
+    fn test_aggregate_after_moving_directory() {
+        let temp_dir = TempDir::new().unwrap();
+        let original = temp_dir.path().join("midway3");
+        fs::create_dir(&original).unwrap();
+        write_scan(&original, "scan", 7);
+
+        let moved = temp_dir.path().join("midway2");
+        fs::rename(&original, &moved).unwrap();
+
+        let manifest_path = moved.join("scan_manifest.json");
+        let chunks = find_chunk_files(&manifest_path).unwrap();
+        assert!(chunks.iter().all(|c| c.starts_with(&moved)));
+
+        let report = crate::verify::verify_manifest_file(&manifest_path, false).unwrap();
+        assert!(report.is_ok());
+
+        let output = temp_dir.path().join("aggregated.parquet");
+        let summary = aggregate_chunks(&chunks, &output).unwrap();
+        assert_eq!(summary.total_rows, 7);
+    }
+
+    /// Write a chunk with the given columns; `uid` and `inode` are optional extras
+    fn write_versioned_chunk(path: &Path, rows: usize, with_uid: bool, inode_type: DataType) {
+        use arrow::array::{Int32Array, Int64Array, StringArray, UInt32Array, UInt64Array};
+
+        let mut fields = vec![
+            Field::new("path", DataType::Utf8, false),
+            Field::new("size", DataType::UInt64, false),
+            Field::new("modified_time", DataType::Int64, false),
+            Field::new("accessed_time", DataType::Int64, false),
+            Field::new("created_time", DataType::Int64, true),
+            Field::new("file_type", DataType::Utf8, false),
+            Field::new("inode", inode_type.clone(), false),
+            Field::new("permissions", DataType::UInt32, false),
+            Field::new("parent_path", DataType::Utf8, false),
+            Field::new("depth", DataType::UInt32, false),
+            Field::new("top_level_dir", DataType::Utf8, false),
+        ];
+        let inode: ArrayRef = match inode_type {
+            DataType::Int32 => Arc::new(Int32Array::from(vec![7; rows])),
+            DataType::Utf8 => Arc::new(StringArray::from(vec!["7"; rows])),
+            _ => Arc::new(Int64Array::from(vec![7; rows])),
+        };
+        let mut columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from((0..rows).map(|i| format!("/test/{}", i)).collect::<Vec<_>>())),
+            Arc::new(UInt64Array::from(vec![1u64; rows])),
+            Arc::new(Int64Array::from(vec![1700000000; rows])),
+            Arc::new(Int64Array::from(vec![1700000000; rows])),
+            Arc::new(Int64Array::from(vec![Some(1700000000); rows])),
+            Arc::new(StringArray::from(vec!["txt"; rows])),
+            inode,
+            Arc::new(UInt32Array::from(vec![0o644; rows])),
+            Arc::new(StringArray::from(vec!["/test"; rows])),
+            Arc::new(UInt32Array::from(vec![1; rows])),
+            Arc::new(StringArray::from(vec!["test"; rows])),
+        ];
+        if with_uid {
+            fields.push(Field::new("uid", DataType::UInt32, false));
+            columns.push(Arc::new(UInt32Array::from(vec![1000; rows])));
+        }
+
+        let batch = RecordBatch::try_new(Arc::new(Schema::new(fields)), columns).unwrap();
+        let mut writer = ArrowWriter::try_new(fs::File::create(path).unwrap(), batch.schema(), None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+    }
+
+    fn read_all(path: &Path) -> RecordBatch {
+        let reader = ParquetRecordBatchReaderBuilder::try_new(fs::File::open(path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap();
+        let batches: Vec<RecordBatch> = reader.map(|b| b.unwrap()).collect();
+        arrow::compute::concat_batches(&batches[0].schema(), &batches).unwrap()
+    }
+
+    #[test]
+    fn test_aggregate_union_schema() {
+        use arrow::array::{Array, UInt32Array};
+
+        let temp_dir = TempDir::new().unwrap();
+        let v1 = temp_dir.path().join("v1_chunk_0001.parquet");
+        let v2 = temp_dir.path().join("v2_chunk_0001.parquet");
+        write_versioned_chunk(&v1, 3, false, DataType::Int64);
+        write_versioned_chunk(&v2, 2, true, DataType::Int64);
+
+        let output = temp_dir.path().join("aggregated.parquet");
+        let summary = aggregate_chunks(&[v1.clone(), v2.clone()], &output).unwrap();
+        assert_eq!(summary.total_rows, 5);
+
+        // The chunks' columns, plus the `raw_mode` their modes are split into
+        let batch = read_all(&output);
+        assert_eq!(batch.num_columns(), 13);
+        let uid_field = batch.schema().field_with_name("uid").unwrap().clone();
+        assert!(uid_field.is_nullable());
+
+        let uid = batch.column_by_name("uid").unwrap();
+        let uid = uid.as_any().downcast_ref::<UInt32Array>().unwrap();
+        assert_eq!(uid.null_count(), 3);
+        assert!((0..3).all(|i| uid.is_null(i)));
+        assert_eq!(uid.value(3), 1000);
+
+        // Strict mode keeps the first file's schema and rejects the mismatch
+        let strict = AggregateOptions { strict: true, ..Default::default() };
+        assert!(aggregate_chunks_with(&[v1, v2], &output, &strict).is_err());
+    }
+
+    /// A chunk as scans wrote them before `raw_mode`: full modes in
+    /// `permissions`, none of the later columns and no schema version
+    fn write_legacy_chunk(path: &Path, entries: &[FileEntry]) {
+        use arrow::array::UInt32Array;
+
+        let batch = crate::writer::entries_to_batch(entries).unwrap();
+        let schema = batch.schema();
+        let mut columns = batch.columns().to_vec();
+        columns[schema.index_of("permissions").unwrap()] =
+            Arc::new(UInt32Array::from_iter_values(entries.iter().map(|e| e.raw_mode)));
+        let kept: Vec<usize> = (0..schema.fields().len())
+            .filter(|&i| !LATER_COLUMNS.contains(&schema.field(i).name().as_str()))
+            .collect();
+        let batch = RecordBatch::try_new(schema, columns).unwrap().project(&kept).unwrap();
+        let mut writer = ArrowWriter::try_new(fs::File::create(path).unwrap(), batch.schema(), None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+    }
+
+    /// (`permissions`, `raw_mode`) of every row of a Parquet file
+    fn modes(path: &Path) -> Vec<(Option<u32>, Option<u32>)> {
+        use arrow::array::UInt32Array;
+
+        let batch = read_all(path);
+        let column = |name: &str| -> Vec<Option<u32>> {
+            batch.column_by_name(name).unwrap().as_any().downcast_ref::<UInt32Array>().unwrap().iter().collect()
+        };
+        column("permissions").into_iter().zip(column("raw_mode")).collect()
+    }
+
+    #[test]
+    fn test_aggregate_splits_the_modes_of_legacy_chunks() {
+        let temp_dir = TempDir::new().unwrap();
+        let legacy = temp_dir.path().join("old_chunk_0001.parquet");
+        let current = temp_dir.path().join("new_chunk_0001.parquet");
+        let dir = FileEntry::dir_fixture("/test/old/dir");
+        write_legacy_chunk(&legacy, &[create_test_entry("/test/old/a.txt"), dir]);
+        let mut writer = ParquetFileWriter::new(&current).unwrap();
+        writer.write_batch(&[create_test_entry("/test/new/b.txt")]).unwrap();
+        writer.close().unwrap();
+        let legacy_modes = [(Some(0o644), Some(0o100644)), (Some(0o755), Some(0o40755))];
+
+        let output = temp_dir.path().join("aggregated.parquet");
+        aggregate_chunks(&[legacy.clone(), current], &output).unwrap();
+        assert_eq!(modes(&output), [&legacy_modes[..], &[(Some(0o644), Some(0o100644))]].concat());
+
+        // The output is stamped, so aggregating it again leaves its modes alone
+        let footer = SerializedFileReader::new(fs::File::open(&output).unwrap()).unwrap();
+        assert_eq!(schema_version(footer.metadata().file_metadata()), Some(SCHEMA_VERSION));
+        let again = temp_dir.path().join("again.parquet");
+        aggregate_chunks(std::slice::from_ref(&output), &again).unwrap();
+        assert_eq!(modes(&again), modes(&output));
+
+        // Strict mode still gives legacy chunks a `raw_mode`
+        let strict = AggregateOptions { strict: true, ..Default::default() };
+        let strict_output = temp_dir.path().join("strict.parquet");
+        aggregate_chunks_with(&[legacy], &strict_output, &strict).unwrap();
+        assert_eq!(modes(&strict_output), legacy_modes);
+    }
+
+    #[test]
+    fn test_failed_aggregation_leaves_no_partial_output() {
+        let temp_dir = TempDir::new().unwrap();
+        let chunk = temp_dir.path().join("scan_chunk_0001.parquet");
+        let export = temp_dir.path().join("export_2024_data.parquet");
+        write_versioned_chunk(&chunk, 3, false, DataType::Int64);
+        write_versioned_chunk(&export, 2, true, DataType::Int64);
+
+        // The second file fails once the output is being written
+        let output = temp_dir.path().join("aggregated.parquet");
+        let strict = AggregateOptions { strict: true, ..Default::default() };
+        assert!(aggregate_chunks_with(&[chunk.clone(), export], &output, &strict).is_err());
+        assert!(!output.exists());
+
+        let dataset = temp_dir.path().join("dataset");
+        let partitioned = AggregateOptions { strict: true, partition_by: Some("top_level_dir".to_string()), ..Default::default() };
+        let err = aggregate_chunks_with(&[chunk.clone(), temp_dir.path().join("missing.parquet")], &dataset, &partitioned);
+        assert!(err.is_err());
+        assert!(!dataset.exists());
+
+        // Failing before the output is created leaves an earlier one alone
+        aggregate_chunks(std::slice::from_ref(&chunk), &output).unwrap();
+        let before = fs::read(&output).unwrap();
+        assert!(aggregate_chunks(&[chunk, temp_dir.path().join("missing.parquet")], &output).is_err());
+        assert_eq!(fs::read(&output).unwrap(), before);
+    }
+
+    #[test]
+    fn test_aggregate_upcasts_integers() {
+        use arrow::array::Int64Array;
+
+        let temp_dir = TempDir::new().unwrap();
+        let old = temp_dir.path().join("old_chunk_0001.parquet");
+        let new = temp_dir.path().join("new_chunk_0001.parquet");
+        write_versioned_chunk(&old, 2, false, DataType::Int32);
+        write_versioned_chunk(&new, 2, false, DataType::Int64);
+
+        let output = temp_dir.path().join("aggregated.parquet");
+        aggregate_chunks(&[old, new], &output).unwrap();
+
+        let batch = read_all(&output);
+        let inode = batch.column_by_name("inode").unwrap();
+        let inode = inode.as_any().downcast_ref::<Int64Array>().unwrap();
+        assert_eq!(inode.values(), &[7, 7, 7, 7]);
+    }
+
+    #[test]
+    fn test_incompatible_schemas_reported_per_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let good = temp_dir.path().join("good_chunk_0001.parquet");
+        let bad = temp_dir.path().join("bad_chunk_0001.parquet");
+        write_versioned_chunk(&good, 1, false, DataType::Int64);
+        write_versioned_chunk(&bad, 1, false, DataType::Utf8);
+
+        let err = reconcile_schemas(&[good, bad.clone()]).unwrap_err().to_string();
+        assert!(err.contains(&bad.display().to_string()), "{}", err);
+        assert!(err.contains("'inode'"), "{}", err);
+    }
+
+    #[test]
+    fn test_aggregate_dedupe_by_path() {
+        use crate::writer::ParquetFileWriter;
+        use arrow::array::{Int64Array, StringArray};
+        use std::collections::HashMap;
+
+        let temp_dir = TempDir::new().unwrap();
+        let write_chunk = |name: &str, range: std::ops::Range<usize>, mtime: i64| {
+            let path = temp_dir.path().join(name);
+            let entries: Vec<FileEntry> = range.map(|i| FileEntry {
+                modified_time: mtime,
+                ..create_test_entry(&format!("/test/file{}.txt", i))
+            }).collect();
+            let mut writer = ParquetFileWriter::new(&path).unwrap();
+            writer.write_batch(&entries).unwrap();
+            writer.close().unwrap();
+            path
+        };
+
+        // Paths 50..100 appear in both chunks; the second night has them newer
+        let first = write_chunk("night1_chunk_0001.parquet", 0..100, 1_000);
+        let second = write_chunk("night2_chunk_0001.parquet", 50..150, 2_000);
+
+        let output = temp_dir.path().join("aggregated.parquet");
+        let options = AggregateOptions { dedupe_by: Some(DedupeKey::Path), ..Default::default() };
+        let summary = aggregate_chunks_with(&[first.clone(), second.clone()], &output, &options).unwrap();
+
+        assert_eq!(summary.total_rows, 150);
+        assert_eq!(summary.duplicates_dropped, 50);
+        assert!(!temp_dir.path().join(".aggregated.parquet.dedupe").exists());
+
+        let batch = read_all(&output);
+        let paths = batch.column_by_name("path").unwrap();
+        let paths = paths.as_any().downcast_ref::<StringArray>().unwrap();
+        let mtimes = batch.column_by_name("modified_time").unwrap();
+        let mtimes = mtimes.as_any().downcast_ref::<Int64Array>().unwrap();
+
+        let rows: HashMap<&str, i64> = (0..batch.num_rows()).map(|i| (paths.value(i), mtimes.value(i))).collect();
+        assert_eq!(rows.len(), 150);
+        for i in 0..150 {
+            let expected = if i < 50 { 1_000 } else { 2_000 };
+            assert_eq!(rows[format!("/test/file{}.txt", i).as_str()], expected);
+        }
+
+        // Input order no longer matters once mtimes differ
+        let summary = aggregate_chunks_with(&[second, first], &output, &options).unwrap();
+        assert_eq!(summary.duplicates_dropped, 50);
+        let batch = read_all(&output);
+        let mtimes = batch.column_by_name("modified_time").unwrap();
+        let mtimes = mtimes.as_any().downcast_ref::<Int64Array>().unwrap();
+        assert_eq!(mtimes.values().iter().filter(|m| **m == 2_000).count(), 100);
+    }
+
+    #[test]
+    fn test_aggregate_sorted_by_path() {
+        use arrow::array::StringArray;
+
+        // Chunks of the "b" scan come first, so input order is not path order
+        let temp_dir = TempDir::new().unwrap();
+        let mut chunks = find_chunk_files(&write_scan(temp_dir.path(), "b", 5)).unwrap();
+        chunks.extend(find_chunk_files(&write_scan(temp_dir.path(), "a", 4)).unwrap());
+
+        // A 1-byte budget spills every batch as its own run
+        let output = temp_dir.path().join("aggregated.parquet");
+        let options = AggregateOptions {
+            sort_by: vec!["path".to_string()],
+            sort_memory: Some(1),
+            ..Default::default()
+        };
+        let summary = aggregate_chunks_with(&chunks, &output, &options).unwrap();
+        assert_eq!(summary.total_rows, 9);
+        assert_eq!(summary.sort_runs, 4);
+        assert!(summary.input_bytes > 0);
+        assert_eq!(summary.output_bytes, fs::metadata(&output).unwrap().len());
+        assert!(!temp_dir.path().join(".aggregated.parquet.sort").exists());
+
+        let batch = read_all(&output);
+        let paths = batch.column_by_name("path").unwrap();
+        let paths = paths.as_any().downcast_ref::<StringArray>().unwrap();
+        let actual: Vec<&str> = paths.iter().map(|p| p.unwrap()).collect();
+        let mut expected = actual.clone();
+        expected.sort();
+        assert_eq!(actual, expected);
+        assert_eq!(actual[0], "/test/a_0.txt");
+    }
+
+    #[test]
+    fn test_aggregate_partitioned_by_top_level_dir() {
+        use crate::partitioned_writer::DATASET_MANIFEST;
+        use arrow::array::StringArray;
+
+        let temp_dir = TempDir::new().unwrap();
+        let config = RotatingWriterConfig {
+            base_output_path: temp_dir.path().join("scan.parquet"),
+            rows_per_chunk: 4,
+            time_interval: Duration::from_secs(3600),
+        };
+        let dirs = ["alpha", "beta", "lab 2/x"];
+        let mut writer = RotatingParquetWriter::new(config, "/test".to_string()).unwrap();
+        for i in 0..10 {
+            let mut entry = create_test_entry(&format!("/test/file{}.txt", i));
+            entry.top_level_dir = dirs[i % 3].to_string();
+            writer.write_batch(&[entry]).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let chunks = find_chunk_files(&temp_dir.path().join("scan_manifest.json")).unwrap();
+        let output = temp_dir.path().join("dataset");
+        let options = AggregateOptions {
+            partition_by: Some("top_level_dir".to_string()),
+            ..Default::default()
+        };
+        let summary = aggregate_chunks_with(&chunks, &output, &options).unwrap();
+        assert_eq!(summary.total_rows, 10);
+
+        let dataset = summary.dataset.unwrap();
+        let layout: Vec<(&str, u64)> = dataset.partitions.iter().map(|p| (p.dir.as_str(), p.rows)).collect();
+        assert_eq!(layout, [
+            ("top_level_dir=alpha", 4),
+            ("top_level_dir=beta", 3),
+            ("top_level_dir=lab%202%2Fx", 3),
+        ]);
+        assert_eq!(summary.output_bytes, dataset.size_bytes());
+        assert!(output.join(DATASET_MANIFEST).exists());
+
+        // Reading the files on disk, as a dataset reader would
+        let pattern = output.join("top_level_dir=*").join("*.parquet");
+        let files: Vec<PathBuf> = glob::glob(pattern.to_str().unwrap()).unwrap().map(|p| p.unwrap()).collect();
+        assert_eq!(files.len(), 3);
+        let mut total = 0;
+        for file in &files {
+            let batch = read_all(file);
+            let values = batch.column_by_name("top_level_dir").unwrap();
+            let values = values.as_any().downcast_ref::<StringArray>().unwrap();
+            let partition = dataset.partitions.iter().find(|p| file.starts_with(output.join(&p.dir))).unwrap();
+            assert!(values.iter().all(|v| v == partition.value.as_deref()));
+            assert_eq!(batch.num_rows() as u64, partition.rows);
+            total += batch.num_rows();
+        }
+        assert_eq!(total, 10);
+    }
+
+    #[test]
+    fn test_aggregate_collects_usage_stats() {
+        use crate::stats::GroupStats;
+
+        let temp_dir = TempDir::new().unwrap();
+        let config = RotatingWriterConfig {
+            base_output_path: temp_dir.path().join("scan.parquet"),
+            rows_per_chunk: 3,
+            time_interval: Duration::from_secs(3600),
+        };
+        let rows = [
+            ("home", "directory", 4096),
+            ("home", "txt", 500),
+            ("home", "txt", 1_500),
+            ("projects", "directory", 4096),
+            ("projects", "h5", 2_000_000),
+            ("projects", "h5", 3_000_000),
+            ("projects", "txt", 10),
+        ];
+        let mut writer = RotatingParquetWriter::new(config, "/test".to_string()).unwrap();
+        for (i, (dir, file_type, size)) in rows.into_iter().enumerate() {
+            let mut entry = create_test_entry(&format!("/test/{}/{}", dir, i));
+            entry.top_level_dir = dir.to_string();
+            entry.file_type = file_type.to_string();
+            entry.size = size;
+            writer.write_batch(&[entry]).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let chunks = find_chunk_files(&temp_dir.path().join("scan_manifest.json")).unwrap();
+        let options = AggregateOptions { collect_stats: true, ..Default::default() };
+        let summary = aggregate_chunks_with(&chunks, &temp_dir.path().join("out.parquet"), &options).unwrap();
+        assert_eq!(chunks.len(), 3);
+
+        let stats = summary.stats.unwrap();
+        assert_eq!((stats.rows, stats.files, stats.directories), (7, 5, 2));
+        assert_eq!(stats.total_bytes, 5_002_010);
+        assert_eq!(stats.by_file_type["txt"], GroupStats { rows: 3, bytes: 2_010 });
+        assert_eq!(stats.by_file_type["h5"], GroupStats { rows: 2, bytes: 5_000_000 });
+        assert_eq!(stats.by_file_type["directory"], GroupStats { rows: 2, bytes: 0 });
+        assert_eq!(stats.by_top_level_dir["home"], GroupStats { rows: 3, bytes: 2_000 });
+        assert_eq!(stats.by_top_level_dir["projects"], GroupStats { rows: 4, bytes: 5_000_010 });
+        let files: Vec<u64> = stats.size_histogram.iter().map(|b| b.files).collect();
+        assert_eq!(files, [2, 1, 0, 0, 2, 0, 0, 0, 0, 0]);
+
+        // Without the option nothing is collected
+        let summary = aggregate_chunks(&chunks, &temp_dir.path().join("plain.parquet")).unwrap();
+        assert!(summary.stats.is_none());
+    }
+
+    #[test]
+    fn test_aggregate_filters_by_size() {
+        use arrow::array::{StringArray, UInt64Array};
+
+        let temp_dir = TempDir::new().unwrap();
+        let config = RotatingWriterConfig {
+            base_output_path: temp_dir.path().join("scan.parquet"),
+            rows_per_chunk: 3,
+            time_interval: Duration::from_secs(3600),
+        };
+        let mut writer = RotatingParquetWriter::new(config, "/test".to_string()).unwrap();
+        for i in 0..10u64 {
+            let mut entry = create_test_entry(&format!("/test/file{}.txt", i));
+            entry.size = i * 1_000_000;
+            writer.write_batch(&[entry]).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let manifest_path = temp_dir.path().join("scan_manifest.json");
+        let chunks = find_chunk_files(&manifest_path).unwrap();
+        let output = temp_dir.path().join("aggregated.parquet");
+        let options = AggregateOptions {
+            filter: RowFilter { min_size: Some(4_000_000), ..Default::default() },
+            ..Default::default()
+        };
+        let summary = aggregate_chunks_with(&chunks, &output, &options).unwrap();
+        assert_eq!(summary.total_rows, 6);
+        assert_eq!(summary.rows_filtered, 4);
+
+        let batch = read_all(&output);
+        let sizes = batch.column_by_name("size").unwrap();
+        let sizes = sizes.as_any().downcast_ref::<UInt64Array>().unwrap();
+        assert!(sizes.values().iter().all(|s| *s >= 4_000_000));
+        let paths = batch.column_by_name("path").unwrap();
+        let paths = paths.as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(paths.value(0), "/test/file4.txt");
+
+        // Filtered rows still count as read
+        let manifest = ScanManifest::load_from_file(&manifest_path).unwrap();
+        assert!(verify_aggregation(Some(&manifest), &chunks, &summary).unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_parallel_read_matches_sequential() {
+        use arrow::array::StringArray;
+
+        let temp_dir = TempDir::new().unwrap();
+        let chunks = find_chunk_files(&write_scan(temp_dir.path(), "scan", 40)).unwrap();
+        assert_eq!(chunks.len(), 14);
+
+        let read_paths = |threads: usize| {
+            let output = temp_dir.path().join(format!("aggregated_{}.parquet", threads));
+            let options = AggregateOptions { threads, ..Default::default() };
+            let summary = aggregate_chunks_with(&chunks, &output, &options).unwrap();
+            assert_eq!(summary.total_rows, 40);
+            assert_eq!(summary.chunk_rows.iter().sum::<u64>(), 40);
+
+            let batch = read_all(&output);
+            let paths = batch.column_by_name("path").unwrap();
+            let paths = paths.as_any().downcast_ref::<StringArray>().unwrap();
+            paths.iter().map(|p| p.unwrap().to_string()).collect::<Vec<_>>()
+        };
+
+        // Same rows in the same (chunk) order regardless of reader count
+        let sequential = read_paths(1);
+        for threads in [2, 8, 32] {
+            assert_eq!(read_paths(threads), sequential);
+        }
+    }
+
+    #[test]
+    fn test_parallel_read_error_stops_aggregation() {
+        let temp_dir = TempDir::new().unwrap();
+        let chunks = find_chunk_files(&write_scan(temp_dir.path(), "scan", 40)).unwrap();
+        fs::write(&chunks[5], b"not parquet").unwrap();
+
+        let options = AggregateOptions { threads: 4, ..Default::default() };
+        let err = aggregate_chunks_with(&chunks, &temp_dir.path().join("out.parquet"), &options).unwrap_err();
+        assert!(format!("{:#}", err).contains(&chunks[5].display().to_string()), "{:#}", err);
+    }
+
+    /// Write a scan as if it had started at `scan_start`
+    fn write_scan_started_at(dir: &Path, name: &str, rows: usize, scan_start: i64) -> PathBuf {
+        fs::create_dir_all(dir).unwrap();
+        let config = RotatingWriterConfig {
+            base_output_path: dir.join(format!("{}.parquet", name)),
+            rows_per_chunk: 3,
+            time_interval: Duration::from_secs(3600),
+        };
+
+        let mut writer = RotatingParquetWriter::new(config, "/test".to_string()).unwrap();
+        writer.manifest.scan_start = scan_start;
+        for i in 0..rows {
+            writer.write_batch(&[create_test_entry(&format!("/test/{}_{}.txt", name, i))]).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        dir.join(format!("{}_manifest.json", name))
+    }
+
+    fn input(path: &Path, scan_id: Option<&str>) -> AggregateInput {
+        AggregateInput {
+            input: path.to_path_buf(),
+            chunk_files: find_chunk_files(path).unwrap(),
+            scan_id: scan_id.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_aggregate_tags_rows_by_scan() {
+        use arrow::array::{Date32Array, StringArray};
+
+        let temp_dir = TempDir::new().unwrap();
+        let first = temp_dir.path().join("night1");
+        let second = temp_dir.path().join("night2");
+        write_scan_started_at(&first, "night1", 5, 1_716_170_400);
+        write_scan_started_at(&second, "night2", 4, 1_716_256_800);
+
+        // Chunks carry their scan in the footer
+        let inputs = [input(&first, None), input(&second, None)];
+        let tags = resolve_scan_tags(&inputs).unwrap();
+        assert_eq!(tags.len(), 2 + 2);
+        assert_eq!(tags[0].as_ref().unwrap().scan_id, "2024-05-20T02:00:00Z");
+        assert_eq!(tags[3].as_ref().unwrap().scan_id, "2024-05-21T02:00:00Z");
+
+        let chunks: Vec<PathBuf> = inputs.iter().flat_map(|i| i.chunk_files.clone()).collect();
+        let output = temp_dir.path().join("history.parquet");
+        let options = AggregateOptions {
+            scan_tags: tags.into_iter().map(Option::unwrap).collect(),
+            ..Default::default()
+        };
+        let summary = aggregate_chunks_with(&chunks, &output, &options).unwrap();
+        assert_eq!(summary.total_rows, 9);
+
+        let batch = read_all(&output);
+        let paths = batch.column_by_name("path").unwrap();
+        let paths = paths.as_any().downcast_ref::<StringArray>().unwrap();
+        let ids = batch.column_by_name("scan_id").unwrap();
+        let ids = ids.as_any().downcast_ref::<StringArray>().unwrap();
+        let dates = batch.column_by_name("scan_date").unwrap();
+        let dates = dates.as_any().downcast_ref::<Date32Array>().unwrap();
+
+        for i in 0..batch.num_rows() {
+            let (id, day) = if paths.value(i).starts_with("/test/night1_") {
+                ("2024-05-20T02:00:00Z", 19_863)
+            } else {
+                ("2024-05-21T02:00:00Z", 19_864)
+            };
+            assert_eq!(ids.value(i), id, "{}", paths.value(i));
+            assert_eq!(dates.value(i), day, "{}", paths.value(i));
+        }
+
+        // Tagging an already tagged table again is refused
+        let again = AggregateOptions {
+            scan_tags: vec![ScanTag::from_id("2024-06-01")],
+            ..Default::default()
+        };
+        let err = aggregate_chunks_with(&[output], &temp_dir.path().join("again.parquet"), &again).unwrap_err();
+        assert!(err.to_string().contains("scan_id"), "{}", err);
+    }
+
+    #[test]
+    fn test_aggregate_tags_rows_by_host() {
+        use arrow::array::StringArray;
+
+        let temp_dir = TempDir::new().unwrap();
+        let first = temp_dir.path().join("midway3");
+        let second = temp_dir.path().join("beagle");
+        write_scan_started_at(&first, "midway3", 3, 1_716_170_400);
+        write_scan_started_at(&second, "beagle", 2, 1_716_170_400);
+
+        let chunks: Vec<PathBuf> = [&first, &second].iter().flat_map(|i| find_chunk_files(i).unwrap()).collect();
+        let hostnames: Vec<String> = chunks.iter()
+            .map(|c| if c.starts_with(&first) { "midway3" } else { "beagle" }.to_string())
+            .collect();
+        let output = temp_dir.path().join("merged.parquet");
+        let options = AggregateOptions { hostnames, ..Default::default() };
+        aggregate_chunks_with(&chunks, &output, &options).unwrap();
+
+        let batch = read_all(&output);
+        assert_eq!(batch.schema().fields().last().unwrap().name(), HOSTNAME_COLUMN);
+        let paths = batch.column_by_name("path").unwrap();
+        let paths = paths.as_any().downcast_ref::<StringArray>().unwrap();
+        let hosts = batch.column_by_name(HOSTNAME_COLUMN).unwrap();
+        let hosts = hosts.as_any().downcast_ref::<StringArray>().unwrap();
+        for i in 0..batch.num_rows() {
+            let host = if paths.value(i).starts_with("/test/midway3_") { "midway3" } else { "beagle" };
+            assert_eq!(hosts.value(i), host, "{}", paths.value(i));
+        }
+
+        let short = AggregateOptions { hostnames: vec!["midway3".to_string()], ..Default::default() };
+        let err = aggregate_chunks_with(&chunks, &temp_dir.path().join("short.parquet"), &short).unwrap_err();
+        assert!(err.to_string().contains("hostnames"), "{}", err);
+    }
+
+    #[test]
+    fn test_resolve_explicit_and_untagged_scans() {
+        let temp_dir = TempDir::new().unwrap();
+        let tagged = temp_dir.path().join("tagged");
+        write_scan_started_at(&tagged, "scan", 2, 1_716_170_400);
+
+        // Explicit ids win; a non-date id takes its date from the chunks
+        let tags = resolve_scan_tags(&[input(&tagged, Some("nightly"))]).unwrap();
+        assert_eq!(tags[0], Some(ScanTag { scan_id: "nightly".into(), scan_date: Some("2024-05-20".into()) }));
+        let tags = resolve_scan_tags(&[input(&tagged, Some("2024-06-01"))]).unwrap();
+        assert_eq!(tags[0].as_ref().unwrap().scan_date.as_deref(), Some("2024-06-01"));
+
+        // Chunks from before tagging fall back to their manifest, then to nothing
+        let untagged = temp_dir.path().join("untagged");
+        fs::create_dir(&untagged).unwrap();
+        let chunk = untagged.join("old_chunk_0001.parquet");
+        let mut writer = crate::writer::ParquetFileWriter::new(&chunk).unwrap();
+        writer.write_batch(&[create_test_entry("/test/old.txt")]).unwrap();
+        writer.close().unwrap();
+        let old = input(&untagged, None);
+        assert_eq!(resolve_scan_tags(std::slice::from_ref(&old)).unwrap(), vec![None]);
+
+        let mut manifest = ScanManifest::new("/test".to_string());
+        manifest.scan_start = 1_716_256_800;
+        manifest.save_to_file(untagged.join("old_manifest.json")).unwrap();
+        let tags = resolve_scan_tags(&[old]).unwrap();
+        assert_eq!(tags[0].as_ref().unwrap().scan_id, "2024-05-21T02:00:00Z");
+    }
+
+    #[test]
+    fn test_tiny_memory_limit_still_completes() {
+        use arrow::array::StringArray;
+
+        let temp_dir = TempDir::new().unwrap();
+        let chunks = find_chunk_files(&write_scan(temp_dir.path(), "scan", 40)).unwrap();
+        assert!(memory::estimate_bytes_per_row(&chunks[0]).unwrap() > 0);
+
+        let read_paths = |memory_limit: Option<u64>, threads: usize| {
+            let output = temp_dir.path().join("aggregated.parquet");
+            let options = AggregateOptions { memory_limit, threads, ..Default::default() };
+            let summary = aggregate_chunks_with(&chunks, &output, &options).unwrap();
+            assert_eq!(summary.total_rows, 40);
+
+            let batch = read_all(&output);
+            let paths = batch.column_by_name("path").unwrap();
+            let paths = paths.as_any().downcast_ref::<StringArray>().unwrap();
+            paths.iter().map(|p| p.unwrap().to_string()).collect::<Vec<_>>()
+        };
+
+        let unlimited = read_paths(None, 4);
+        for threads in [1, 4] {
+            assert_eq!(read_paths(Some(1), threads), unlimited);
+            assert_eq!(read_paths(Some(64 * 1024), threads), unlimited);
+        }
+    }
+
+    #[test]
+    fn test_widen() {
+        assert_eq!(widen(&DataType::Int32, &DataType::Int64), Some(DataType::Int64));
+        assert_eq!(widen(&DataType::UInt64, &DataType::UInt32), Some(DataType::UInt64));
+        assert_eq!(widen(&DataType::UInt32, &DataType::Int32), Some(DataType::Int64));
+        assert_eq!(widen(&DataType::UInt64, &DataType::Int64), None);
+        assert_eq!(widen(&DataType::Utf8, &DataType::Int64), None);
+
+        let dictionary = DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8));
+        assert_eq!(widen(&value_type(&dictionary), &DataType::Utf8), Some(DataType::Utf8));
+    }
+
+    /// Aggregate a 7-row scan (3 chunks) and apply a retention policy
+    fn aggregate_and_retain(dir: &Path, policy: &RetentionPolicy) -> (PathBuf, RetentionSummary) {
+        let manifest_path = write_scan(dir, "scan", 7);
+        let chunks = find_chunk_files(&manifest_path).unwrap();
+        let summary = aggregate_chunks(&chunks, &dir.join("aggregated.parquet")).unwrap();
+
+        let mut manifest = ScanManifest::load_from_file(&manifest_path).unwrap();
+        assert!(verify_aggregation(Some(&manifest), &chunks, &summary).unwrap().is_ok());
+        let retention = apply_retention(&chunks, Some((&manifest_path, &mut manifest)), policy).unwrap();
+
+        (manifest_path, retention)
+    }
+
+    /// Assert that every chunk the manifest lists is where it says it is
+    fn assert_manifest_matches_disk(manifest_path: &Path) -> ScanManifest {
+        let manifest = ScanManifest::load_from_file(manifest_path).unwrap();
+        for chunk in &manifest.chunks {
+            assert_eq!(Path::new(&chunk.file_path).exists(), !chunk.is_deleted(), "{:?}", chunk);
+        }
+        assert!(crate::verify::verify_manifest(&manifest, false).is_ok());
+        manifest
+    }
+
+    #[test]
+    fn test_retention_delete_all() {
+        let temp_dir = TempDir::new().unwrap();
+        let policy = RetentionPolicy {
+            action: RetentionAction::Delete,
+            retain_last: 0,
+            delete_unlisted: false,
+            dry_run: false,
+        };
+        let (manifest_path, retention) = aggregate_and_retain(temp_dir.path(), &policy);
+
+        assert_eq!(retention.removed.len(), 3);
+        assert!(retention.failed.is_empty());
+
+        let manifest = assert_manifest_matches_disk(&manifest_path);
+        assert!(manifest.chunks.iter().all(|c| c.is_deleted()));
+        assert!(find_chunk_files(&manifest_path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_retention_retain_last() {
+        let temp_dir = TempDir::new().unwrap();
+        let policy = RetentionPolicy {
+            action: RetentionAction::Delete,
+            retain_last: 2,
+            delete_unlisted: false,
+            dry_run: false,
+        };
+        let (manifest_path, retention) = aggregate_and_retain(temp_dir.path(), &policy);
+
+        assert_eq!(retention.removed.len(), 1);
+        assert_eq!(retention.retained.len(), 2);
+
+        let manifest = assert_manifest_matches_disk(&manifest_path);
+        let deleted: Vec<usize> = manifest.chunks.iter()
+            .filter(|c| c.is_deleted())
+            .map(|c| c.chunk_number)
+            .collect();
+        assert_eq!(deleted, vec![manifest.chunks[0].chunk_number]);
+    }
+
+    #[test]
+    fn test_retention_archive() {
+        let temp_dir = TempDir::new().unwrap();
+        let archive = temp_dir.path().join("archive");
+        let policy = RetentionPolicy {
+            action: RetentionAction::Archive(archive.clone()),
+            retain_last: 1,
+            delete_unlisted: false,
+            dry_run: false,
+        };
+        let (manifest_path, retention) = aggregate_and_retain(temp_dir.path(), &policy);
+
+        assert_eq!(retention.removed.len(), 2);
+
+        let manifest = assert_manifest_matches_disk(&manifest_path);
+        let archived: Vec<_> = manifest.chunks.iter()
+            .filter(|c| matches!(c.retention, Some(ChunkRetention::Archived { .. })))
+            .collect();
+        assert_eq!(archived.len(), 2);
+        for chunk in archived {
+            assert!(Path::new(&chunk.file_path).starts_with(&archive));
+            let Some(ChunkRetention::Archived { ref original_path, .. }) = chunk.retention else { unreachable!() };
+            assert!(!Path::new(original_path).exists());
+        }
+
+        // Archived chunks can still be aggregated from the manifest
+        let chunks = find_chunk_files(&manifest_path).unwrap();
+        let summary = aggregate_chunks(&chunks, &temp_dir.path().join("again.parquet")).unwrap();
+        assert_eq!(summary.total_rows, 7);
+    }
+
+    #[test]
+    fn test_verification_failure_blocks_retention() {
+        let temp_dir = TempDir::new().unwrap();
+        let manifest_path = write_scan(temp_dir.path(), "scan", 7);
+        let chunks = find_chunk_files(&manifest_path).unwrap();
+        let summary = aggregate_chunks(&chunks, &temp_dir.path().join("aggregated.parquet")).unwrap();
+        assert_eq!(summary.chunk_rows, vec![3, 3, 1]);
+
+        let mut manifest = ScanManifest::load_from_file(&manifest_path).unwrap();
+        assert!(verify_aggregation(Some(&manifest), &chunks, &summary).unwrap().is_ok());
+
+        // A chunk that was skipped shows up as missing rows
+        let mut short = summary.clone();
+        short.chunk_rows[2] = 0;
+        short.total_rows -= 1;
+        let check = verify_aggregation(Some(&manifest), &chunks, &short).unwrap();
+        assert_eq!(check.chunk_problems.len(), 1);
+        assert_eq!(check.chunk_problems[0].0, chunks[2]);
+
+        // The manifest claims more rows than the chunks hold
+        manifest.chunks[1].row_count += 1;
+        manifest.total_rows += 1;
+        let check = verify_aggregation(Some(&manifest), &chunks, &summary).unwrap();
+        assert!(!check.is_ok());
+        assert_eq!(check.expected_rows, 8);
+        assert!(check.chunk_problems.iter().all(|(path, _)| *path == chunks[1]));
+        assert!(check.chunk_problems.iter().any(|(_, p)| p.contains("row count mismatch")));
+
+        // Without a manifest the chunk footers are the reference
+        assert!(verify_aggregation(None, &chunks, &summary).unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_decoy_not_in_manifest_survives_deletion() {
+        let temp_dir = TempDir::new().unwrap();
+        let manifest_path = write_scan(temp_dir.path(), "scan", 7);
+
+        // A hand-placed file that matches the chunk pattern but isn't in the manifest
+        let decoy = temp_dir.path().join("scan_chunk_0099.parquet");
+        let mut chunks = find_chunk_files(&manifest_path).unwrap();
+        fs::copy(&chunks[0], &decoy).unwrap();
+        chunks.push(decoy.clone());
+        assert_eq!(chunks.len(), 4);
+
+        // Its rows are accounted for by its footer, so it doesn't block deletion
+        let summary = aggregate_chunks(&chunks, &temp_dir.path().join("aggregated.parquet")).unwrap();
+        let mut manifest = ScanManifest::load_from_file(&manifest_path).unwrap();
+        let check = verify_aggregation(Some(&manifest), &chunks, &summary).unwrap();
+        assert!(check.is_ok(), "{:?}", check);
+
+        let mut policy = RetentionPolicy {
+            action: RetentionAction::Delete,
+            retain_last: 0,
+            delete_unlisted: false,
+            dry_run: true,
+        };
+        let planned = apply_retention(&chunks, Some((&manifest_path, &mut manifest)), &policy).unwrap();
+        assert_eq!(planned.removed, chunks[..3]);
+        assert!(chunks.iter().all(|c| c.exists()));
+        assert!(manifest.chunks.iter().all(|c| !c.is_deleted()));
+
+        policy.dry_run = false;
+        let retention = apply_retention(&chunks, Some((&manifest_path, &mut manifest)), &policy).unwrap();
+        assert_eq!(retention.removed, planned.removed);
+        assert_eq!(retention.unlisted, vec![decoy.clone()]);
+        assert!(decoy.exists());
+
+        // Only an explicit request removes it
+        policy.delete_unlisted = true;
+        let retention = apply_retention(std::slice::from_ref(&decoy), Some((&manifest_path, &mut manifest)), &policy).unwrap();
+        assert_eq!(retention.removed, vec![decoy.clone()]);
+        assert!(!decoy.exists());
+        assert_manifest_matches_disk(&manifest_path);
+    }
+
+    #[test]
+    fn test_nothing_deleted_without_manifest_unless_asked() {
+        let temp_dir = TempDir::new().unwrap();
+        let manifest_path = write_scan(temp_dir.path(), "scan", 7);
+        let chunks = find_chunk_files(&manifest_path).unwrap();
+        let summary = aggregate_chunks(&chunks, &temp_dir.path().join("aggregated.parquet")).unwrap();
+        assert!(verify_aggregation(None, &chunks, &summary).unwrap().is_ok());
+
+        let mut policy = RetentionPolicy {
+            action: RetentionAction::Delete,
+            retain_last: 1,
+            delete_unlisted: false,
+            dry_run: false,
+        };
+        let retention = apply_retention(&chunks, None, &policy).unwrap();
+        assert!(retention.removed.is_empty());
+        assert_eq!(retention.unlisted, chunks);
+
+        policy.delete_unlisted = true;
+        let retention = apply_retention(&chunks, None, &policy).unwrap();
+        assert_eq!(retention.removed, chunks[..2]);
+        assert_eq!(retention.retained, chunks[2..]);
+        assert!(chunks[2].exists() && !chunks[0].exists());
+    }
+}
@@ -0,0 +1,315 @@
+use crate::aggregate::{self, AggregateOptions, AggregateSummary};
+use crate::utils::file_checksum;
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// A chunk file incorporated into an aggregate
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AggregatedChunk {
+    /// CRC-32 of the file contents, as hex
+    pub checksum: String,
+
+    pub size_bytes: u64,
+
+    /// Rows read from the chunk
+    pub rows: u64,
+}
+
+/// Sidecar recording which chunks an aggregate already holds
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct AggregateState {
+    /// Size of the aggregate when the state was saved; a mismatch means the
+    /// aggregate changed without the state (e.g. a run died between the two)
+    pub output_bytes: u64,
+
+    pub total_rows: u64,
+
+    /// Chunks by file name
+    pub chunks: BTreeMap<String, AggregatedChunk>,
+}
+
+impl AggregateState {
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let contents = fs::read_to_string(path.as_ref())
+            .context("Failed to read aggregate state file")?;
+        serde_json::from_str(&contents).context("Failed to parse aggregate state JSON")
+    }
+
+    /// Write the state next to its final location and rename it into place
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let json = serde_json::to_string_pretty(self)
+            .context("Failed to serialize aggregate state")?;
+        let temp = path.with_extension("json.tmp");
+        fs::write(&temp, json).context("Failed to write aggregate state file")?;
+        fs::rename(&temp, path).context("Failed to replace aggregate state file")
+    }
+}
+
+/// Location of an aggregate's state file: `<stem>_aggregate_state.json` next to it
+pub fn state_path(output: &Path) -> PathBuf {
+    let stem = output.file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("aggregate");
+    output.with_file_name(format!("{}_aggregate_state.json", stem))
+}
+
+/// Result of appending chunks to an aggregate
+#[derive(Debug, Clone, Default)]
+pub struct AppendSummary {
+    /// Chunks not in the aggregate yet
+    pub new_chunks: Vec<PathBuf>,
+
+    /// Chunks whose contents changed since they were aggregated; their rows
+    /// were included again
+    pub changed_chunks: Vec<PathBuf>,
+
+    /// Chunks already in the aggregate and left out
+    pub skipped_chunks: usize,
+
+    /// Rows copied forward from the previous aggregate
+    pub carried_rows: u64,
+
+    /// Rows in the aggregate after appending
+    pub total_rows: u64,
+
+    /// The aggregation run (None if there was nothing to append)
+    pub aggregate: Option<AggregateSummary>,
+}
+
+/// Append the chunks an aggregate does not hold yet.
+///
+/// The existing aggregate is copied forward, followed by the new chunks, into
+/// a new file that then replaces it. Chunks recorded in the state file (see
+/// [`state_path`]) are skipped unless their checksum changed, in which case
+/// their rows are included again; pass `dedupe_by` to drop the stale copies.
+/// Without an aggregate or state file, this is a plain aggregation that
+/// starts the state.
+pub fn append_chunks(
+    chunk_files: &[PathBuf],
+    output: &Path,
+    options: &AggregateOptions,
+) -> Result<AppendSummary> {
+    if chunk_files.is_empty() {
+        anyhow::bail!("No chunk files to aggregate");
+    }
+    if options.partition_by.is_some() {
+        anyhow::bail!("Appending to a partitioned dataset is not supported");
+    }
+
+    let state_file = state_path(output);
+    let state = match (output.exists(), state_file.exists()) {
+        (true, true) => {
+            let state = AggregateState::load_from_file(&state_file)?;
+            let size = fs::metadata(output)?.len();
+            if size != state.output_bytes {
+                anyhow::bail!(
+                    "{} is {} bytes but its state file records {}; aggregate again without --append",
+                    output.display(),
+                    size,
+                    state.output_bytes
+                );
+            }
+            Some(state)
+        }
+        (true, false) => anyhow::bail!(
+            "{} has no state file ({}); aggregate again without --append",
+            output.display(),
+            state_file.display()
+        ),
+        (false, true) => {
+            warn!("{} is missing; starting a new aggregate", output.display());
+            None
+        }
+        (false, false) => None,
+    };
+    if state.is_some() && !options.scan_tags.is_empty() {
+        anyhow::bail!("Scan tags cannot be added when appending to an existing aggregate");
+    }
+
+    let checksums = chunk_files.par_iter()
+        .map(|path| file_checksum(path))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut summary = AppendSummary::default();
+    let mut pending = Vec::new();
+    for (path, checksum) in chunk_files.iter().zip(&checksums) {
+        let seen = state.as_ref().and_then(|s| s.chunks.get(&chunk_name(path)));
+        match seen {
+            Some(chunk) if chunk.checksum == *checksum => summary.skipped_chunks += 1,
+            Some(_) => {
+                warn!(
+                    "{} changed since it was aggregated; its rows are included again{}",
+                    path.display(),
+                    if options.dedupe_by.is_some() { "" } else { " (use --dedupe-by path to drop the old copies)" }
+                );
+                summary.changed_chunks.push(path.clone());
+                pending.push((path.clone(), checksum.clone()));
+            }
+            None => {
+                summary.new_chunks.push(path.clone());
+                pending.push((path.clone(), checksum.clone()));
+            }
+        }
+    }
+
+    if pending.is_empty() {
+        info!("No new chunks; {} is up to date", output.display());
+        summary.total_rows = state.map(|s| s.total_rows).unwrap_or(0);
+        return Ok(summary);
+    }
+
+    // The previous aggregate goes first so later chunks win deduplication ties
+    let mut inputs: Vec<PathBuf> = Vec::with_capacity(pending.len() + 1);
+    let carried = state.is_some();
+    if carried {
+        inputs.push(output.to_path_buf());
+    }
+    inputs.extend(pending.iter().map(|(path, _)| path.clone()));
+
+    let name = output.file_name().unwrap_or_default().to_string_lossy();
+    let next = output.with_file_name(format!(".{}.append", name));
+    info!("Appending {} chunk file(s) to {}", pending.len(), output.display());
+    let result = aggregate::aggregate_chunks_with(&inputs, &next, options);
+    let run = match result {
+        Ok(run) => run,
+        Err(e) => {
+            let _ = fs::remove_file(&next);
+            return Err(e);
+        }
+    };
+    fs::rename(&next, output)
+        .with_context(|| format!("Failed to replace {}", output.display()))?;
+
+    let mut state = state.unwrap_or_default();
+    let new_rows = if carried { &run.chunk_rows[1..] } else { &run.chunk_rows[..] };
+    for ((path, checksum), &rows) in pending.iter().zip(new_rows) {
+        state.chunks.insert(chunk_name(path), AggregatedChunk {
+            checksum: checksum.clone(),
+            size_bytes: fs::metadata(path)?.len(),
+            rows,
+        });
+    }
+    state.output_bytes = run.output_bytes;
+    state.total_rows = run.total_rows;
+    state.save_to_file(&state_file)?;
+
+    summary.carried_rows = if carried { run.chunk_rows[0] } else { 0 };
+    summary.total_rows = run.total_rows;
+    summary.aggregate = Some(run);
+    Ok(summary)
+}
+
+fn chunk_name(path: &Path) -> String {
+    path.file_name().unwrap_or_default().to_string_lossy().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::FileEntry;
+    use crate::rotating_writer::{RotatingParquetWriter, RotatingWriterConfig};
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    fn entry(path: &str) -> FileEntry {
+        FileEntry::fixture(path)
+    }
+
+    /// Write `rows` rows as chunks of 3 and return the chunk files
+    fn write_chunks(dir: &Path, name: &str, rows: usize) -> Vec<PathBuf> {
+        let config = RotatingWriterConfig {
+            base_output_path: dir.join(format!("{}.parquet", name)),
+            rows_per_chunk: 3,
+            time_interval: Duration::from_secs(3600),
+        };
+        let mut writer = RotatingParquetWriter::new(config, "/test".to_string()).unwrap();
+        for i in 0..rows {
+            writer.write_batch(&[entry(&format!("/test/{}_{}.txt", name, i))]).unwrap();
+        }
+        writer.finalize().unwrap();
+        aggregate::find_chunk_files(&dir.join(format!("{}_manifest.json", name))).unwrap()
+    }
+
+    #[test]
+    fn test_second_run_reads_only_new_chunks() {
+        let temp_dir = TempDir::new().unwrap();
+        let output = temp_dir.path().join("aggregate.parquet");
+        let options = AggregateOptions::default();
+
+        let mut chunks = write_chunks(temp_dir.path(), "night1", 6);
+        let first = append_chunks(&chunks, &output, &options).unwrap();
+        assert_eq!(first.new_chunks.len(), 2);
+        assert_eq!(first.total_rows, 6);
+
+        chunks.extend(write_chunks(temp_dir.path(), "night2", 4));
+        let second = append_chunks(&chunks, &output, &options).unwrap();
+        assert_eq!(second.new_chunks.len(), 2);
+        assert_eq!(second.skipped_chunks, 2);
+        assert_eq!(second.carried_rows, 6);
+        assert_eq!(second.total_rows, 10);
+
+        // Only the previous aggregate and the new chunks were read
+        let run = second.aggregate.unwrap();
+        assert_eq!(run.chunk_rows, [6, 3, 1]);
+        let copy = aggregate::aggregate_chunks(std::slice::from_ref(&output), &temp_dir.path().join("copy.parquet")).unwrap();
+        assert_eq!(copy.total_rows, 10);
+
+        let state = AggregateState::load_from_file(state_path(&output)).unwrap();
+        assert_eq!(state.chunks.len(), 4);
+        assert_eq!(state.total_rows, 10);
+        assert_eq!(state.chunks["night2_chunk_0002.parquet"].rows, 1);
+        assert_eq!(state.output_bytes, fs::metadata(&output).unwrap().len());
+
+        // Nothing new: the aggregate is left alone
+        let third = append_chunks(&chunks, &output, &options).unwrap();
+        assert!(third.aggregate.is_none());
+        assert_eq!(third.skipped_chunks, 4);
+        assert_eq!(third.total_rows, 10);
+    }
+
+    #[test]
+    fn test_changed_chunk_is_included_again() {
+        let temp_dir = TempDir::new().unwrap();
+        let output = temp_dir.path().join("aggregate.parquet");
+        let chunks = write_chunks(temp_dir.path(), "scan", 6);
+        append_chunks(&chunks, &output, &AggregateOptions::default()).unwrap();
+
+        // Rewrite the first chunk with different contents under the same name
+        let other = temp_dir.path().join("other");
+        fs::create_dir(&other).unwrap();
+        let replacement = write_chunks(&other, "scan", 2);
+        fs::copy(&replacement[0], &chunks[0]).unwrap();
+
+        let options = AggregateOptions { dedupe_by: Some(aggregate::DedupeKey::Path), ..Default::default() };
+        let summary = append_chunks(&chunks, &output, &options).unwrap();
+        assert_eq!(summary.changed_chunks, [chunks[0].clone()]);
+        assert_eq!(summary.skipped_chunks, 1);
+        // The replacement's 2 paths overlap the aggregate's, so they replace them
+        assert_eq!(summary.total_rows, 6);
+        assert_eq!(summary.aggregate.unwrap().duplicates_dropped, 2);
+
+        let state = AggregateState::load_from_file(state_path(&output)).unwrap();
+        assert_eq!(state.chunks["scan_chunk_0001.parquet"].rows, 2);
+    }
+
+    #[test]
+    fn test_aggregate_without_state_is_rejected() {
+        let temp_dir = TempDir::new().unwrap();
+        let output = temp_dir.path().join("aggregate.parquet");
+        let chunks = write_chunks(temp_dir.path(), "scan", 3);
+        aggregate::aggregate_chunks(&chunks, &output).unwrap();
+        assert!(append_chunks(&chunks, &output, &AggregateOptions::default()).is_err());
+
+        // Nor is an aggregate that changed behind the state's back
+        fs::remove_file(&output).unwrap();
+        append_chunks(&chunks, &output, &AggregateOptions::default()).unwrap();
+        fs::write(&output, b"truncated").unwrap();
+        assert!(append_chunks(&chunks, &output, &AggregateOptions::default()).is_err());
+    }
+}
@@ -0,0 +1,423 @@
+use crate::manifest::{ChunkMetadata, ScanManifest};
+use crate::rotating_writer::ChunkHook;
+use anyhow::{Context, Result};
+use crossbeam_channel::{unbounded, Sender};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use tracing::info;
+use xxhash_rust::xxh3::Xxh3;
+
+/// Extension of the checksum file written next to an output
+pub const CHECKSUMS_EXTENSION: &str = "CHECKSUMS";
+
+/// Algorithms for transfer checksums
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChecksumAlgo {
+    /// SHA-256, as `sha256sum -c` checks
+    Sha256,
+
+    /// 64-bit XXH3, much faster where only transfer errors matter
+    Xxh3,
+}
+
+impl ChecksumAlgo {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChecksumAlgo::Sha256 => "sha256",
+            ChecksumAlgo::Xxh3 => "xxh3",
+        }
+    }
+
+    /// The algorithm whose hex digests are `len` characters long
+    fn from_hex_len(len: usize) -> Option<Self> {
+        match len {
+            64 => Some(ChecksumAlgo::Sha256),
+            16 => Some(ChecksumAlgo::Xxh3),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ChecksumAlgo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ChecksumAlgo {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "sha256" => Ok(ChecksumAlgo::Sha256),
+            "xxh3" => Ok(ChecksumAlgo::Xxh3),
+            _ => Err(anyhow::anyhow!("Unknown checksum algorithm '{}' (expected sha256 or xxh3)", s)),
+        }
+    }
+}
+
+/// Hash a file's contents with `algo`, as lowercase hex
+pub fn checksum_file(path: &Path, algo: ChecksumAlgo) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut buffer = vec![0u8; 1 << 20];
+    let mut read = |update: &mut dyn FnMut(&[u8])| -> io::Result<()> {
+        loop {
+            let n = file.read(&mut buffer)?;
+            if n == 0 {
+                return Ok(());
+            }
+            update(&buffer[..n]);
+        }
+    };
+
+    match algo {
+        ChecksumAlgo::Sha256 => {
+            let mut hasher = Sha256::new();
+            read(&mut |bytes| hasher.update(bytes))?;
+            Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+        }
+        ChecksumAlgo::Xxh3 => {
+            let mut hasher = Xxh3::new();
+            read(&mut |bytes| hasher.update(bytes))?;
+            Ok(format!("{:016x}", hasher.digest()))
+        }
+    }
+}
+
+/// `<stem>.CHECKSUMS` next to `output`
+pub fn checksums_path(output: &Path) -> PathBuf {
+    output.with_extension(CHECKSUMS_EXTENSION)
+}
+
+/// Checksums of output files in the order they were finished, written out in
+/// the `HASH  relative/filename` format of `sha256sum`
+#[derive(Debug, Clone)]
+pub struct ChecksumList {
+    algo: ChecksumAlgo,
+
+    /// Directory the listed paths are relative to
+    base: PathBuf,
+
+    /// (path relative to `base`, hash)
+    entries: Vec<(String, String)>,
+}
+
+impl ChecksumList {
+    /// An empty list for the checksum file at `path`
+    pub fn new(path: &Path, algo: ChecksumAlgo) -> Result<Self> {
+        let dir = path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        let base = std::path::absolute(dir).context("Failed to resolve checksum file directory")?;
+        Ok(Self { algo, base, entries: Vec::new() })
+    }
+
+    pub fn algo(&self) -> ChecksumAlgo {
+        self.algo
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// `file` as listed: relative to the checksum file's directory when
+    /// inside it, else absolute
+    fn relative(&self, file: &Path) -> String {
+        let absolute = std::path::absolute(file).unwrap_or_else(|_| file.to_path_buf());
+        absolute.strip_prefix(&self.base).unwrap_or(&absolute).to_string_lossy().to_string()
+    }
+
+    pub fn contains(&self, file: &Path) -> bool {
+        let name = self.relative(file);
+        self.entries.iter().any(|(listed, _)| *listed == name)
+    }
+
+    /// Record `file`'s hash, replacing any earlier one
+    pub fn insert(&mut self, file: &Path, hash: String) {
+        let name = self.relative(file);
+        self.entries.retain(|(listed, _)| *listed != name);
+        self.entries.push((name, hash));
+    }
+
+    /// Hash `file` and record it
+    pub fn add(&mut self, file: &Path) -> Result<()> {
+        let hash = checksum_file(file, self.algo)
+            .with_context(|| format!("Failed to checksum {}", file.display()))?;
+        self.insert(file, hash);
+        Ok(())
+    }
+
+    /// The file's contents, one line per file
+    pub fn to_lines(&self) -> String {
+        self.entries.iter().map(|(name, hash)| format!("{}  {}\n", hash, name)).collect()
+    }
+
+    /// Write the list to `path`, replacing it whole so a reader never sees
+    /// part of it
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let tmp = path.with_extension(format!("{}.tmp", CHECKSUMS_EXTENSION));
+        fs::write(&tmp, self.to_lines())
+            .with_context(|| format!("Failed to write checksums to {}", tmp.display()))?;
+        fs::rename(&tmp, path)
+            .with_context(|| format!("Failed to move checksums into place at {}", path.display()))?;
+        Ok(())
+    }
+}
+
+/// Hashes each chunk the rotating writer closes, on a background thread in
+/// chunk order, into a list shared with the caller.
+///
+/// The list is only complete once the scan has finished; see [`finish_scan`].
+pub struct ChecksumHook {
+    chunks: Option<Sender<PathBuf>>,
+    worker: Option<JoinHandle<()>>,
+    failure: Arc<Mutex<Option<String>>>,
+}
+
+impl ChecksumHook {
+    pub fn new(list: Arc<Mutex<ChecksumList>>) -> Result<Self> {
+        let (chunks, queue) = unbounded::<PathBuf>();
+        let failure = Arc::new(Mutex::new(None));
+        let failed = failure.clone();
+        let algo = list.lock().unwrap().algo();
+        let worker = std::thread::Builder::new()
+            .name("chunk-checksums".to_string())
+            .spawn(move || {
+                for chunk in queue {
+                    match checksum_file(&chunk, algo) {
+                        Ok(hash) => list.lock().unwrap().insert(&chunk, hash),
+                        Err(e) => {
+                            *failed.lock().unwrap() = Some(format!("Failed to checksum {}: {}", chunk.display(), e));
+                            break;
+                        }
+                    }
+                }
+            })
+            .context("Failed to start checksum thread")?;
+
+        Ok(Self { chunks: Some(chunks), worker: Some(worker), failure })
+    }
+
+    /// The error that stopped hashing, if any
+    fn check(&self) -> Result<()> {
+        match *self.failure.lock().unwrap() {
+            Some(ref message) => Err(anyhow::anyhow!("{}", message)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl ChunkHook for ChecksumHook {
+    fn chunk_closed(&mut self, chunk: &ChunkMetadata) -> Result<()> {
+        self.check()?;
+        if let Some(ref chunks) = self.chunks {
+            let _ = chunks.send(PathBuf::from(&chunk.file_path));
+        }
+        Ok(())
+    }
+
+    fn record(&mut self, _manifest: &mut ScanManifest) {}
+
+    fn finish(&mut self, _manifest: &mut ScanManifest) -> Result<()> {
+        self.chunks = None;
+        if let Some(worker) = self.worker.take() {
+            worker.join().map_err(|_| anyhow::anyhow!("Checksum thread panicked"))?;
+        }
+        self.check()
+    }
+}
+
+impl Drop for ChecksumHook {
+    fn drop(&mut self) {
+        self.chunks = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Complete `list` for a finalized scan and write it to `path`.
+///
+/// Chunks the hook did not see, such as those written before a resume, are
+/// hashed now; the manifest's Parquet sidecar and then the manifest itself
+/// come last.
+pub fn finish_scan(list: &mut ChecksumList, manifest: &ScanManifest, manifest_path: &Path, path: &Path) -> Result<()> {
+    for chunk in manifest.chunks.iter().filter(|c| !c.is_deleted()) {
+        let chunk = Path::new(&chunk.file_path);
+        if !list.contains(chunk) {
+            list.add(chunk)?;
+        }
+    }
+    let sidecar = ScanManifest::sidecar_path(manifest_path);
+    if sidecar.is_file() {
+        list.add(&sidecar)?;
+    }
+    list.add(manifest_path)?;
+    list.write(path)?;
+    info!("Checksums ({}) written to {}", list.algo(), path.display());
+    Ok(())
+}
+
+/// Outcome of checking one file listed in a checksum file
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ChecksumCheck {
+    /// Path as listed
+    pub file: String,
+
+    pub algo: ChecksumAlgo,
+
+    pub expected: String,
+
+    /// Hash of the file on disk (None if it could not be read)
+    pub actual: Option<String>,
+
+    /// Why the file failed, if it did
+    pub problem: Option<String>,
+}
+
+impl ChecksumCheck {
+    pub fn is_ok(&self) -> bool {
+        self.problem.is_none()
+    }
+}
+
+/// Result of checking every file a checksum file lists
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ChecksumReport {
+    /// Checksum file that was checked
+    pub checksums_path: String,
+
+    /// Per-file results in listed order
+    pub files: Vec<ChecksumCheck>,
+}
+
+impl ChecksumReport {
+    pub fn is_ok(&self) -> bool {
+        self.files.iter().all(|f| f.is_ok())
+    }
+
+    pub fn problem_count(&self) -> usize {
+        self.files.iter().filter(|f| !f.is_ok()).count()
+    }
+}
+
+/// Split a `HASH  file` (or `HASH *file`) line into its algorithm, hash and file
+fn parse_line(line: &str) -> Option<(ChecksumAlgo, String, String)> {
+    let (hash, rest) = line.split_once(' ')?;
+    let file = rest.strip_prefix(' ').or_else(|| rest.strip_prefix('*'))?;
+    let algo = ChecksumAlgo::from_hex_len(hash.len())?;
+    if file.is_empty() || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    Some((algo, hash.to_ascii_lowercase(), file.to_string()))
+}
+
+/// Check every file a checksum file lists, resolving relative paths against
+/// the checksum file's directory
+pub fn verify_checksums_file(path: &Path) -> Result<ChecksumReport> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read checksum file {}", path.display()))?;
+    let dir = path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+
+    let mut files = Vec::new();
+    for (number, line) in contents.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+        let (algo, expected, file) = parse_line(line).ok_or_else(|| anyhow::anyhow!(
+            "{} line {} is not a sha256 or xxh3 checksum line: {}",
+            path.display(),
+            number + 1,
+            line
+        ))?;
+        let (actual, problem) = match checksum_file(&dir.join(&file), algo) {
+            Ok(actual) if actual == expected => (Some(actual), None),
+            Ok(actual) => (Some(actual), Some("checksum does not match".to_string())),
+            Err(e) => (None, Some(format!("cannot be read: {}", e))),
+        };
+        files.push(ChecksumCheck { file, algo, expected, actual, problem });
+    }
+
+    Ok(ChecksumReport { checksums_path: path.to_string_lossy().to_string(), files })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_known_digests() {
+        let temp_dir = TempDir::new().unwrap();
+        let empty = temp_dir.path().join("empty");
+        let abc = temp_dir.path().join("abc");
+        fs::write(&empty, b"").unwrap();
+        fs::write(&abc, b"abc").unwrap();
+
+        assert_eq!(
+            checksum_file(&abc, ChecksumAlgo::Sha256).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(checksum_file(&empty, ChecksumAlgo::Xxh3).unwrap(), "2d06800538d394c2");
+        assert_eq!(checksum_file(&abc, ChecksumAlgo::Xxh3).unwrap(), "78af5f94892f3950");
+    }
+
+    #[test]
+    fn test_xxh3_streams_across_buffers() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("big");
+        let data: Vec<u8> = (0..(3 << 20) + 12_345u32).map(|i| (i * 31 % 251) as u8).collect();
+        fs::write(&path, &data).unwrap();
+
+        let expected = format!("{:016x}", xxhash_rust::xxh3::xxh3_64(&data));
+        assert_eq!(checksum_file(&path, ChecksumAlgo::Xxh3).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_list_round_trips_through_verify() {
+        let temp_dir = TempDir::new().unwrap();
+        let chunk = temp_dir.path().join("scan_chunk_0001.parquet");
+        let manifest = temp_dir.path().join("scan_manifest.json");
+        fs::write(&chunk, b"rows").unwrap();
+        fs::write(&manifest, b"{}").unwrap();
+
+        let path = checksums_path(&temp_dir.path().join("scan.parquet"));
+        assert_eq!(path.file_name().unwrap(), "scan.CHECKSUMS");
+        let mut list = ChecksumList::new(&path, ChecksumAlgo::Sha256).unwrap();
+        list.add(&chunk).unwrap();
+        list.add(&manifest).unwrap();
+        list.add(&chunk).unwrap();
+        list.write(&path).unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        let names: Vec<&str> = contents.lines().map(|l| l.split_once("  ").unwrap().1).collect();
+        assert_eq!(names, ["scan_manifest.json", "scan_chunk_0001.parquet"]);
+        assert!(verify_checksums_file(&path).unwrap().is_ok());
+
+        fs::write(&chunk, b"rowz").unwrap();
+        let report = verify_checksums_file(&path).unwrap();
+        assert_eq!(report.problem_count(), 1);
+        assert_eq!(report.files[1].problem.as_deref(), Some("checksum does not match"));
+    }
+
+    #[test]
+    fn test_parse_line() {
+        let sha = "a".repeat(64);
+        assert_eq!(
+            parse_line(&format!("{}  dir/scan_chunk_0001.parquet", sha)),
+            Some((ChecksumAlgo::Sha256, sha.clone(), "dir/scan_chunk_0001.parquet".to_string()))
+        );
+        assert_eq!(parse_line(&format!("{} *binary.parquet", sha)).unwrap().2, "binary.parquet");
+        assert_eq!(parse_line("2D06800538D394C2  empty").unwrap().0, ChecksumAlgo::Xxh3);
+        assert_eq!(parse_line("abc  too-short"), None);
+        assert_eq!(parse_line(&format!("{}  ", sha)), None);
+        assert_eq!(parse_line(&"z".repeat(16)), None);
+    }
+}
@@ -0,0 +1,501 @@
+use crate::aggregate::{conform_batch, reconcile_schemas};
+use crate::query::{QueryFormat, ResultWriter};
+use crate::utils;
+use anyhow::{Context, Result};
+use arrow::array::{Array, BooleanArray, Int64Array, StringArray, UInt64Array};
+use arrow::compute::{cast, filter_record_batch};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Column of the candidate list naming the rule that selected each file
+pub const RULE_COLUMN: &str = "rule";
+
+/// A clean-up policy file: `[[rule]]` tables, evaluated in order
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CleanPolicy {
+    #[serde(rename = "rule", default)]
+    pub rules: Vec<CleanRule>,
+}
+
+/// Files one rule selects. Every condition it sets must hold.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CleanRule {
+    /// Written to the `rule` column of the files it selects
+    pub name: String,
+
+    /// Directory the rule covers, at any depth ("/" for everything)
+    pub prefix: String,
+
+    /// Last accessed longer ago than this (e.g., "180d")
+    pub atime_older_than: Option<String>,
+
+    /// Last modified longer ago than this
+    pub mtime_older_than: Option<String>,
+
+    /// At least this large (e.g., "1GB")
+    pub min_size: Option<String>,
+
+    /// Only files with one of these extensions (default: any)
+    #[serde(default)]
+    pub extensions: Vec<String>,
+
+    /// Never files with one of these extensions
+    #[serde(default)]
+    pub exclude_extensions: Vec<String>,
+
+    /// Never files owned by one of these user names or uids
+    #[serde(default)]
+    pub exclude_owners: Vec<String>,
+}
+
+/// A rule with its durations resolved against the reference time
+#[derive(Debug, Clone)]
+struct Matcher {
+    prefix: String,
+    accessed_before: Option<i64>,
+    modified_before: Option<i64>,
+    min_size: u64,
+    extensions: Vec<String>,
+    exclude_extensions: Vec<String>,
+    exclude_owners: Vec<String>,
+}
+
+/// Extensions as the `file_type` column holds them: no leading dot
+fn normalize_extensions(extensions: &[String]) -> Vec<String> {
+    extensions.iter().map(|e| e.trim_start_matches('.').to_string()).collect()
+}
+
+impl CleanRule {
+    fn matcher(&self, as_of: i64) -> Result<Matcher> {
+        let before = |age: &Option<String>| -> Result<Option<i64>> {
+            Ok(match age {
+                Some(age) => Some(as_of - utils::parse_duration(age)?.as_secs() as i64),
+                None => None,
+            })
+        };
+        Ok(Matcher {
+            prefix: self.prefix.trim_end_matches('/').to_string(),
+            accessed_before: before(&self.atime_older_than)?,
+            modified_before: before(&self.mtime_older_than)?,
+            min_size: self.min_size.as_deref().map(utils::parse_bytes).transpose()?.unwrap_or(0),
+            extensions: normalize_extensions(&self.extensions),
+            exclude_extensions: normalize_extensions(&self.exclude_extensions),
+            exclude_owners: self.exclude_owners.clone(),
+        })
+    }
+}
+
+impl CleanPolicy {
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let contents = fs::read_to_string(path.as_ref())
+            .with_context(|| format!("Failed to read {}", path.as_ref().display()))?;
+        let policy: Self = toml::from_str(&contents)
+            .with_context(|| format!("Failed to parse {}", path.as_ref().display()))?;
+        policy.validate()?;
+        Ok(policy)
+    }
+
+    /// Check every rule, so a typo fails before any chunk is read
+    pub fn validate(&self) -> Result<()> {
+        if self.rules.is_empty() {
+            anyhow::bail!("No [[rule]] entries in the policy");
+        }
+        for (i, rule) in self.rules.iter().enumerate() {
+            if rule.name.is_empty() {
+                anyhow::bail!("Rule {} has an empty name", i + 1);
+            }
+            if self.rules[..i].iter().any(|other| other.name == rule.name) {
+                anyhow::bail!("Duplicate rule name '{}'", rule.name);
+            }
+            if !rule.prefix.starts_with('/') {
+                anyhow::bail!("Rule '{}': prefix '{}' must be an absolute path", rule.name, rule.prefix);
+            }
+            rule.matcher(0).with_context(|| format!("Invalid rule '{}'", rule.name))?;
+        }
+        Ok(())
+    }
+
+    fn matchers(&self, as_of: i64) -> Result<Vec<Matcher>> {
+        self.rules.iter().map(|rule| rule.matcher(as_of)).collect()
+    }
+
+    /// Columns the rules read; `owner` is used when present but not required
+    fn required_columns(&self) -> Vec<&'static str> {
+        let mut columns = vec!["path", "size", "file_type"];
+        if self.rules.iter().any(|r| r.atime_older_than.is_some()) {
+            columns.push("accessed_time");
+        }
+        if self.rules.iter().any(|r| r.mtime_older_than.is_some()) {
+            columns.push("modified_time");
+        }
+        if self.rules.iter().any(|r| !r.exclude_owners.is_empty()) {
+            columns.push("uid");
+        }
+        columns
+    }
+
+    fn validate_schema(&self, schema: &Schema) -> Result<()> {
+        for column in self.required_columns() {
+            schema.field_with_name(column)
+                .with_context(|| format!("Finding clean-up candidates requires a '{}' column", column))?;
+        }
+        if schema.field_with_name(RULE_COLUMN).is_ok() {
+            anyhow::bail!("Input already has a '{}' column; is it a candidate list?", RULE_COLUMN);
+        }
+        Ok(())
+    }
+}
+
+/// The columns of one record batch the rules read
+struct Columns {
+    path: StringArray,
+    size: UInt64Array,
+    file_type: StringArray,
+    accessed: Option<Int64Array>,
+    modified: Option<Int64Array>,
+    owner: Option<StringArray>,
+    uid: Option<StringArray>,
+}
+
+impl Columns {
+    fn read(batch: &RecordBatch) -> Result<Self> {
+        let column = |name: &str, data_type: &DataType| -> Result<Option<Arc<dyn Array>>> {
+            Ok(batch.column_by_name(name).map(|c| cast(c, data_type)).transpose()?)
+        };
+        let strings = |name: &str| -> Result<Option<StringArray>> {
+            Ok(column(name, &DataType::Utf8)?.map(|c| c.as_any().downcast_ref::<StringArray>().unwrap().clone()))
+        };
+        let times = |name: &str| -> Result<Option<Int64Array>> {
+            Ok(column(name, &DataType::Int64)?.map(|c| c.as_any().downcast_ref::<Int64Array>().unwrap().clone()))
+        };
+        let size = column("size", &DataType::UInt64)?
+            .context("Finding clean-up candidates requires a 'size' column")?;
+        Ok(Self {
+            path: strings("path")?.context("Finding clean-up candidates requires a 'path' column")?,
+            size: size.as_any().downcast_ref::<UInt64Array>().unwrap().clone(),
+            file_type: strings("file_type")?.context("Finding clean-up candidates requires a 'file_type' column")?,
+            accessed: times("accessed_time")?,
+            modified: times("modified_time")?,
+            owner: strings("owner")?,
+            uid: strings("uid")?,
+        })
+    }
+
+    fn string(array: &Option<StringArray>, i: usize) -> Option<&str> {
+        array.as_ref().filter(|a| a.is_valid(i)).map(|a| a.value(i))
+    }
+
+    fn time(array: &Option<Int64Array>, i: usize) -> Option<i64> {
+        array.as_ref().filter(|a| a.is_valid(i)).map(|a| a.value(i))
+    }
+
+    /// Whether row `i` satisfies every condition of `matcher`. Null values
+    /// never satisfy a condition.
+    fn matches(&self, i: usize, matcher: &Matcher) -> bool {
+        if !self.path.is_valid(i) || !utils::is_within(self.path.value(i), &matcher.prefix) {
+            return false;
+        }
+        if self.size.is_null(i) || self.size.value(i) < matcher.min_size {
+            return false;
+        }
+
+        let extension = if self.file_type.is_valid(i) { self.file_type.value(i) } else { "" };
+        let listed = |list: &[String]| list.iter().any(|e| e.eq_ignore_ascii_case(extension));
+        if !matcher.extensions.is_empty() && !listed(&matcher.extensions) {
+            return false;
+        }
+        if listed(&matcher.exclude_extensions) {
+            return false;
+        }
+
+        if let Some(before) = matcher.accessed_before {
+            if Self::time(&self.accessed, i).is_none_or(|t| t >= before) {
+                return false;
+            }
+        }
+        if let Some(before) = matcher.modified_before {
+            if Self::time(&self.modified, i).is_none_or(|t| t >= before) {
+                return false;
+            }
+        }
+
+        if !matcher.exclude_owners.is_empty() {
+            let owner = Self::string(&self.owner, i);
+            let uid = Self::string(&self.uid, i);
+            if matcher.exclude_owners.iter().any(|o| Some(o.as_str()) == owner || Some(o.as_str()) == uid) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Index of the first rule claiming row `i`. Directories are never candidates.
+    fn first_match(&self, i: usize, matchers: &[Matcher]) -> Option<usize> {
+        if self.file_type.is_valid(i) && self.file_type.value(i) == "directory" {
+            return None;
+        }
+        matchers.iter().position(|matcher| self.matches(i, matcher))
+    }
+}
+
+/// Candidates selected by one rule
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RuleSummary {
+    pub rule: String,
+
+    pub files: u64,
+
+    /// Bytes deleting the rule's candidates would reclaim
+    pub bytes: u64,
+}
+
+/// Result of [`find_candidates`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CandidateReport {
+    /// Ages were measured from this Unix timestamp
+    pub as_of: i64,
+
+    pub rows_scanned: u64,
+
+    /// In policy order, including rules that selected nothing
+    pub rules: Vec<RuleSummary>,
+
+    pub files: u64,
+
+    pub bytes: u64,
+}
+
+/// Evaluate `policy` against every row of `chunk_files` and write the files
+/// it selects, with all their columns plus [`RULE_COLUMN`], to `output`.
+/// Rules are tried in order and the first one a file satisfies claims it,
+/// so each file is listed and counted once. Nothing is deleted.
+pub fn find_candidates(
+    chunk_files: &[PathBuf],
+    policy: &CleanPolicy,
+    as_of: i64,
+    output: &Path,
+    format: QueryFormat,
+) -> Result<CandidateReport> {
+    let matchers = policy.matchers(as_of)?;
+    let input_schema = reconcile_schemas(chunk_files)?;
+    policy.validate_schema(&input_schema)?;
+
+    let mut fields: Vec<Field> = input_schema.fields().iter().map(|f| f.as_ref().clone()).collect();
+    fields.push(Field::new(RULE_COLUMN, DataType::Utf8, false));
+    let schema = Arc::new(Schema::new(fields));
+    let mut writer = ResultWriter::create(output, format, schema.clone())?;
+
+    let mut rules: Vec<RuleSummary> = policy.rules.iter()
+        .map(|rule| RuleSummary { rule: rule.name.clone(), files: 0, bytes: 0 })
+        .collect();
+    let mut rows_scanned = 0;
+
+    for path in chunk_files {
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)
+            .with_context(|| format!("Failed to read {}", path.display()))?
+            .build()?;
+        for batch in reader {
+            let batch = conform_batch(&batch?, &input_schema)?;
+            rows_scanned += batch.num_rows() as u64;
+
+            let columns = Columns::read(&batch)?;
+            let mut keep = Vec::with_capacity(batch.num_rows());
+            let mut names = Vec::new();
+            for i in 0..batch.num_rows() {
+                let matched = columns.first_match(i, &matchers);
+                if let Some(rule) = matched {
+                    rules[rule].files += 1;
+                    rules[rule].bytes += columns.size.value(i);
+                    names.push(policy.rules[rule].name.as_str());
+                }
+                keep.push(matched.is_some());
+            }
+            if names.is_empty() {
+                continue;
+            }
+
+            let matched = filter_record_batch(&batch, &BooleanArray::from(keep))?;
+            let mut columns = matched.columns().to_vec();
+            columns.push(Arc::new(StringArray::from(names)));
+            writer.write(&RecordBatch::try_new(schema.clone(), columns)?)?;
+        }
+    }
+    writer.finish()?;
+
+    Ok(CandidateReport {
+        as_of,
+        rows_scanned,
+        files: rules.iter().map(|r| r.files).sum(),
+        bytes: rules.iter().map(|r| r.bytes).sum(),
+        rules,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::FileEntry;
+    use crate::writer::ParquetFileWriter;
+    use tempfile::TempDir;
+
+    const AS_OF: i64 = 1_700_000_000;
+    const DAY: i64 = 86_400;
+    const MB: u64 = 1_000_000;
+
+    fn entry(path: &str, size: u64, accessed_days_ago: i64, modified_days_ago: i64, uid: u32) -> FileEntry {
+        let entry = if size == 0 { FileEntry::dir_fixture(path) } else { FileEntry::fixture(path) };
+        FileEntry {
+            size,
+            modified_time: AS_OF - modified_days_ago * DAY,
+            accessed_time: AS_OF - accessed_days_ago * DAY,
+            uid,
+            gid: 100,
+            owner: (uid == 1000).then(|| "alice".to_string()),
+            ..entry
+        }
+    }
+
+    const POLICY: &str = r#"
+[[rule]]
+name = "scratch-tmp"
+prefix = "/project/scratch/"
+extensions = [".tmp", "log"]
+
+[[rule]]
+name = "scratch-old"
+prefix = "/project/scratch"
+atime_older_than = "90d"
+exclude_owners = ["alice"]
+
+[[rule]]
+name = "archive-big"
+prefix = "/"
+mtime_older_than = "365d"
+min_size = "100MB"
+exclude_extensions = ["h5"]
+"#;
+
+    /// Two chunks of files under /project
+    fn fixture(dir: &Path) -> Vec<PathBuf> {
+        let chunks = [
+            vec![
+                // Young, but every tmp and log file under scratch goes
+                entry("/project/scratch/run.tmp", 5 * MB, 1, 1, 2000),
+                entry("/project/scratch/RUN.LOG", 7 * MB, 1, 1, 1000),
+                // Claimed by scratch-old before archive-big could
+                entry("/project/scratch/old.dat", 300 * MB, 100, 400, 2000),
+                // alice is excluded from scratch-old, so archive-big takes it
+                entry("/project/scratch/alice.dat", 200 * MB, 100, 400, 1000),
+                // Excluded by owner and too young for archive-big
+                entry("/project/scratch/alice_new.dat", 200 * MB, 100, 30, 1000),
+                // A sibling directory sharing the prefix string
+                entry("/project/scratch2/old.dat", 50 * MB, 100, 30, 2000),
+                entry("/project/scratch/old_dir", 0, 500, 500, 2000),
+            ],
+            vec![
+                entry("/project/labA/big.dat", 100 * MB, 10, 400, 3000),
+                entry("/project/labA/small.dat", 100 * MB - 1, 10, 400, 3000),
+                entry("/project/labA/data.h5", 900 * MB, 10, 400, 3000),
+                // Modified exactly at the cut-off is not old enough
+                entry("/project/labA/boundary.dat", 500 * MB, 10, 365, 3000),
+            ],
+        ];
+        chunks.iter().enumerate().map(|(i, entries)| {
+            let path = dir.join(format!("scan_chunk_{:04}.parquet", i + 1));
+            let mut writer = ParquetFileWriter::new(&path).unwrap();
+            writer.write_batch(entries).unwrap();
+            writer.close().unwrap();
+            path
+        }).collect()
+    }
+
+    fn policy(toml: &str) -> CleanPolicy {
+        let policy: CleanPolicy = toml::from_str(toml).unwrap();
+        policy.validate().unwrap();
+        policy
+    }
+
+    #[test]
+    fn test_rule_precedence_and_summary() {
+        let temp_dir = TempDir::new().unwrap();
+        let chunks = fixture(temp_dir.path());
+        let output = temp_dir.path().join("candidates.csv");
+
+        let report = find_candidates(&chunks, &policy(POLICY), AS_OF, &output, QueryFormat::Csv).unwrap();
+        assert_eq!(report.rows_scanned, 11);
+        let totals: Vec<(&str, u64, u64)> = report.rules.iter()
+            .map(|r| (r.rule.as_str(), r.files, r.bytes))
+            .collect();
+        assert_eq!(totals, [
+            ("scratch-tmp", 2, 12 * MB),
+            ("scratch-old", 1, 300 * MB),
+            ("archive-big", 2, 300 * MB),
+        ]);
+        assert_eq!((report.files, report.bytes), (5, 612 * MB));
+
+        let csv = fs::read_to_string(&output).unwrap();
+        let mut lines = csv.lines();
+        assert!(lines.next().unwrap().ends_with(",rule"));
+        let tagged: Vec<(&str, &str)> = lines
+            .map(|l| (l.split(',').next().unwrap(), l.rsplit(',').next().unwrap()))
+            .collect();
+        assert_eq!(tagged, [
+            ("/project/scratch/run.tmp", "scratch-tmp"),
+            ("/project/scratch/RUN.LOG", "scratch-tmp"),
+            ("/project/scratch/old.dat", "scratch-old"),
+            ("/project/scratch/alice.dat", "archive-big"),
+            ("/project/labA/big.dat", "archive-big"),
+        ]);
+    }
+
+    #[test]
+    fn test_rule_order_decides_the_claim() {
+        let temp_dir = TempDir::new().unwrap();
+        let chunks = fixture(temp_dir.path());
+        let output = temp_dir.path().join("candidates.parquet");
+
+        // The catch-all rule now comes first and takes old.dat from scratch-old
+        let reordered = policy(r#"
+[[rule]]
+name = "everything-old"
+prefix = "/project"
+mtime_older_than = "365d"
+
+[[rule]]
+name = "scratch-old"
+prefix = "/project/scratch"
+atime_older_than = "90d"
+"#);
+        let report = find_candidates(&chunks, &reordered, AS_OF, &output, QueryFormat::Parquet).unwrap();
+        assert_eq!(report.rules[0].files, 5);
+        assert_eq!(report.rules[0].bytes, 300 * MB + 200 * MB + 100 * MB + (100 * MB - 1) + 900 * MB);
+        assert_eq!((report.rules[1].files, report.rules[1].bytes), (1, 200 * MB));
+
+        let rows: usize = ParquetRecordBatchReaderBuilder::try_new(File::open(&output).unwrap()).unwrap()
+            .build().unwrap()
+            .map(|batch| batch.unwrap().num_rows())
+            .sum();
+        assert_eq!(rows, 6);
+    }
+
+    #[test]
+    fn test_policy_validation() {
+        let invalid = |toml: &str| {
+            let policy: CleanPolicy = toml::from_str(toml).unwrap();
+            policy.validate().unwrap_err().to_string()
+        };
+        assert!(invalid("").contains("No [[rule]]"));
+        assert!(invalid("[[rule]]\nname = \"a\"\nprefix = \"/\"\n[[rule]]\nname = \"a\"\nprefix = \"/x\"\n")
+            .contains("Duplicate"));
+        assert!(invalid("[[rule]]\nname = \"a\"\nprefix = \"scratch\"\n").contains("absolute"));
+        assert!(invalid("[[rule]]\nname = \"a\"\nprefix = \"/\"\nmin_size = \"10XB\"\n").contains("'a'"));
+        assert!(toml::from_str::<CleanPolicy>("[[rule]]\nname = \"a\"\nprefix = \"/\"\nmax_age = \"1d\"\n").is_err());
+    }
+}
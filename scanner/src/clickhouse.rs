@@ -0,0 +1,371 @@
+use crate::aggregate;
+use crate::writer::schema_version_entry;
+use anyhow::{Context, Result};
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// ClickHouse error codes worth retrying: the server was busy or the
+/// connection dropped, not the data or query at fault
+const TRANSIENT_CODES: &[u32] = &[
+    159, // TIMEOUT_EXCEEDED
+    202, // TOO_MANY_SIMULTANEOUS_QUERIES
+    209, // SOCKET_TIMEOUT
+    210, // NETWORK_ERROR
+    241, // MEMORY_LIMIT_EXCEEDED
+    252, // TOO_MANY_PARTS
+    285, // TOO_FEW_LIVE_REPLICAS
+    319, // UNKNOWN_STATUS_OF_INSERT
+];
+
+/// Where and how rows are loaded
+#[derive(Debug, Clone)]
+pub struct ClickHouseOptions {
+    /// HTTP interface, e.g. http://ch:8123
+    pub url: String,
+
+    /// Target table, optionally with its database (e.g., storage.file_entries)
+    pub table: String,
+
+    pub user: Option<String>,
+
+    pub password: Option<String>,
+
+    /// Most rows in one INSERT
+    pub batch_size: usize,
+
+    /// Further attempts after a transient failure
+    pub retries: u32,
+
+    /// Wait before the first retry, doubled before each later one
+    pub backoff: Duration,
+}
+
+/// What [`load`] did
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LoadSummary {
+    pub files: usize,
+
+    /// INSERT requests sent
+    pub batches: usize,
+
+    pub rows: u64,
+
+    /// Rows in the table before and after the load
+    pub rows_before: u64,
+    pub rows_after: u64,
+}
+
+/// `table` checked to be a plain `[database.]table` name, so it can go into
+/// a query unquoted
+pub fn validate_table(table: &str) -> Result<()> {
+    let valid_part = |part: &str| {
+        part.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    };
+    let parts: Vec<&str> = table.split('.').collect();
+    if parts.len() > 2 || !parts.iter().all(|part| valid_part(part)) {
+        anyhow::bail!("Invalid table name '{}'; expected [database.]table", table);
+    }
+    Ok(())
+}
+
+/// Whether a failed request is worth retrying
+fn is_transient(status: u16, exception_code: Option<u32>) -> bool {
+    matches!(status, 429 | 502 | 503 | 504)
+        || exception_code.is_some_and(|code| TRANSIENT_CODES.contains(&code))
+}
+
+/// A ClickHouse server reached over its HTTP interface
+pub struct ClickHouse {
+    client: Client,
+    options: ClickHouseOptions,
+}
+
+impl ClickHouse {
+    pub fn new(options: ClickHouseOptions) -> Result<Self> {
+        validate_table(&options.table)?;
+        let client = Client::builder()
+            .user_agent(concat!("storage-scanner/", env!("CARGO_PKG_VERSION")))
+            .timeout(None)
+            .build()
+            .context("Failed to build HTTP client")?;
+        Ok(Self { client, options })
+    }
+
+    /// A POST of `query`, with credentials in the ClickHouse headers
+    fn request(&self, query: &str) -> RequestBuilder {
+        let mut request = self.client.post(&self.options.url).query(&[("query", query)]);
+        if let Some(ref user) = self.options.user {
+            request = request.header("X-ClickHouse-User", user);
+        }
+        if let Some(ref password) = self.options.password {
+            request = request.header("X-ClickHouse-Key", password);
+        }
+        request
+    }
+
+    /// An INSERT of `body`, a Parquet file
+    pub fn insert_request(&self, body: Vec<u8>) -> RequestBuilder {
+        self.request(&format!("INSERT INTO {} FORMAT Parquet", self.options.table))
+            .header("Content-Type", "application/octet-stream")
+            .body(body)
+    }
+
+    /// Send the request `build` makes, retrying transient failures
+    fn send(&self, what: &str, build: impl Fn() -> RequestBuilder) -> Result<Response> {
+        let mut backoff = self.options.backoff;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let (error, transient) = match build().send() {
+                Ok(response) if response.status().is_success() => return Ok(response),
+                Ok(response) => {
+                    let status = response.status().as_u16();
+                    let code = response.headers().get("X-ClickHouse-Exception-Code")
+                        .and_then(|v| v.to_str().ok())
+                        .and_then(|v| v.parse().ok());
+                    let body = response.text().unwrap_or_default();
+                    (anyhow::anyhow!("{} failed with HTTP {}: {}", what, status, body.trim()), is_transient(status, code))
+                }
+                Err(e) => {
+                    let transient = e.is_connect() || e.is_timeout();
+                    (anyhow::Error::new(e).context(format!("{} failed", what)), transient)
+                }
+            };
+            if !transient || attempt > self.options.retries {
+                return Err(error);
+            }
+            warn!("{:#}; retrying in {:?}", error, backoff);
+            std::thread::sleep(backoff);
+            backoff *= 2;
+        }
+    }
+
+    /// Rows in the table
+    pub fn count(&self) -> Result<u64> {
+        let query = format!("SELECT count() FROM {}", self.options.table);
+        let text = self.send("Row count", || self.request(&query))?.text()?;
+        text.trim().parse().with_context(|| format!("Unexpected row count '{}'", text.trim()))
+    }
+
+    /// Insert `batch`; `what` names it in errors.
+    ///
+    /// A retried insert whose first attempt did land is dropped by tables
+    /// with insert deduplication (replicated ones, by default), as the block
+    /// is the same.
+    pub fn insert(&self, batch: &RecordBatch, what: &str) -> Result<()> {
+        let body = encode_parquet(batch)?;
+        self.send(&format!("Insert of {}", what), || self.insert_request(body.clone()))?;
+        Ok(())
+    }
+}
+
+/// `batch` as an in-memory Parquet file
+fn encode_parquet(batch: &RecordBatch) -> Result<Vec<u8>> {
+    let props = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+    let mut writer = ArrowWriter::try_new(Vec::new(), batch.schema(), Some(props))?;
+    writer.append_key_value_metadata(schema_version_entry());
+    writer.write(batch)?;
+    Ok(writer.into_inner()?)
+}
+
+/// Load the scan at `input` into the table, `batch_size` rows per INSERT,
+/// then check the table grew by the rows sent.
+///
+/// The check assumes nothing else writes to the table meanwhile.
+pub fn load(input: &Path, options: &ClickHouseOptions) -> Result<LoadSummary> {
+    let files: Vec<PathBuf> = aggregate::find_chunk_files(input)?;
+    if files.is_empty() {
+        anyhow::bail!("No Parquet files found in: {}", input.display());
+    }
+    let server = ClickHouse::new(options.clone())?;
+
+    let mut summary = LoadSummary { files: files.len(), ..Default::default() };
+    summary.rows_before = server.count()?;
+    for file in &files {
+        let name = file.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(file)
+                .with_context(|| format!("Failed to open {}", file.display()))?)?
+            .with_batch_size(options.batch_size)
+            .build()?;
+        for (i, batch) in reader.enumerate() {
+            let batch = batch.with_context(|| format!("Failed to read {}", file.display()))?;
+            server.insert(&batch, &format!("{} batch {}", name, i + 1))?;
+            summary.batches += 1;
+            summary.rows += batch.num_rows() as u64;
+        }
+        info!("Loaded {} into {}", file.display(), options.table);
+    }
+    summary.rows_after = server.count()?;
+
+    if summary.rows_after != summary.rows_before + summary.rows {
+        anyhow::bail!(
+            "Row count check failed: sent {} rows but {} went from {} to {} rows",
+            summary.rows, options.table, summary.rows_before, summary.rows_after
+        );
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::FileEntry;
+    use crate::writer::write_to_parquet;
+    use crossbeam_channel::bounded;
+    use std::sync::{Arc, Mutex};
+    use tempfile::TempDir;
+
+    fn options(url: &str) -> ClickHouseOptions {
+        ClickHouseOptions {
+            url: url.to_string(),
+            table: "storage.file_entries".to_string(),
+            user: Some("loader".to_string()),
+            password: Some("secret".to_string()),
+            batch_size: 2,
+            retries: 2,
+            backoff: Duration::from_millis(1),
+        }
+    }
+
+    fn write_scan(dir: &Path, rows: usize) -> PathBuf {
+        let path = dir.join("scan.parquet");
+        let entries = (0..rows).map(|i| FileEntry { inode: i as u64, ..FileEntry::fixture(&format!("/test/file_{}.txt", i)) }).collect();
+        let (tx, rx) = bounded(1);
+        tx.send(entries).unwrap();
+        drop(tx);
+        write_to_parquet(&path, rx).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_validate_table() {
+        assert!(validate_table("file_entries").is_ok());
+        assert!(validate_table("storage.file_entries").is_ok());
+        assert!(validate_table("a.b.c").is_err());
+        assert!(validate_table("t; DROP TABLE x").is_err());
+        assert!(validate_table("1table").is_err());
+        assert!(validate_table("").is_err());
+    }
+
+    #[test]
+    fn test_is_transient() {
+        assert!(is_transient(503, None));
+        assert!(is_transient(500, Some(252)));
+        assert!(!is_transient(500, Some(62)));
+        assert!(!is_transient(400, None));
+    }
+
+    #[test]
+    fn test_insert_request() {
+        let server = ClickHouse::new(options("http://ch:8123/")).unwrap();
+        let request = server.insert_request(b"PAR1".to_vec()).build().unwrap();
+
+        assert_eq!(request.method(), "POST");
+        let query: Vec<(String, String)> = request.url().query_pairs().into_owned().collect();
+        assert_eq!(query, [("query".to_string(), "INSERT INTO storage.file_entries FORMAT Parquet".to_string())]);
+        assert_eq!(request.headers()["X-ClickHouse-User"], "loader");
+        assert_eq!(request.headers()["X-ClickHouse-Key"], "secret");
+        assert_eq!(request.body().unwrap().as_bytes(), Some(&b"PAR1"[..]));
+    }
+
+    /// Answers like ClickHouse: counts from `table_rows`, inserts add their
+    /// rows, and the first insert fails with `first_failure` (status and
+    /// exception code)
+    fn mock_server(table_rows: Arc<Mutex<u64>>, first_failure: (u16, u32)) -> (String, Arc<Mutex<Vec<String>>>) {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", server.server_addr().to_ip().unwrap());
+        let queries = Arc::new(Mutex::new(Vec::new()));
+        let seen = queries.clone();
+        std::thread::spawn(move || {
+            let mut failed = false;
+            for mut request in server.incoming_requests() {
+                let url = reqwest::Url::parse(&format!("http://localhost{}", request.url())).unwrap();
+                let query = url.query_pairs().find(|(k, _)| k == "query").unwrap().1.to_string();
+                seen.lock().unwrap().push(query.clone());
+                let response = if query.starts_with("SELECT count()") {
+                    tiny_http::Response::from_string(format!("{}\n", table_rows.lock().unwrap()))
+                } else if !failed {
+                    failed = true;
+                    let (status, code) = first_failure;
+                    tiny_http::Response::from_string(format!("Code: {}. DB::Exception: injected", code))
+                        .with_status_code(status)
+                        .with_header(tiny_http::Header::from_bytes("X-ClickHouse-Exception-Code", code.to_string()).unwrap())
+                } else {
+                    let mut body = tempfile::tempfile().unwrap();
+                    std::io::copy(request.as_reader(), &mut body).unwrap();
+                    let reader = ParquetRecordBatchReaderBuilder::try_new(body).unwrap();
+                    *table_rows.lock().unwrap() += reader.metadata().file_metadata().num_rows() as u64;
+                    tiny_http::Response::from_string("")
+                };
+                request.respond(response).unwrap();
+            }
+        });
+        (url, queries)
+    }
+
+    #[test]
+    fn test_load_batches_retries_and_verifies() {
+        let dir = TempDir::new().unwrap();
+        let scan = write_scan(dir.path(), 5);
+        let rows = Arc::new(Mutex::new(10));
+        // TOO_MANY_PARTS
+        let (url, queries) = mock_server(rows.clone(), (500, 252));
+
+        let summary = load(&scan, &options(&url)).unwrap();
+        assert_eq!(summary, LoadSummary { files: 1, batches: 3, rows: 5, rows_before: 10, rows_after: 15 });
+
+        let queries = queries.lock().unwrap();
+        assert_eq!(queries.first().unwrap(), "SELECT count() FROM storage.file_entries");
+        assert_eq!(queries.last().unwrap(), "SELECT count() FROM storage.file_entries");
+        // Three batches, the first sent twice
+        assert_eq!(queries.iter().filter(|q| q.starts_with("INSERT")).count(), 4);
+    }
+
+    #[test]
+    fn test_load_gives_up_on_permanent_errors() {
+        let dir = TempDir::new().unwrap();
+        let scan = write_scan(dir.path(), 5);
+        // SYNTAX_ERROR
+        let (url, queries) = mock_server(Arc::new(Mutex::new(0)), (400, 62));
+
+        let err = load(&scan, &options(&url)).unwrap_err();
+        assert!(format!("{:#}", err).contains("HTTP 400"), "{:#}", err);
+        assert_eq!(queries.lock().unwrap().iter().filter(|q| q.starts_with("INSERT")).count(), 1);
+    }
+
+    /// Round trip against a real server:
+    /// CLICKHOUSE_TEST_URL=http://127.0.0.1:8123
+    #[test]
+    fn test_load_against_server() {
+        let Ok(url) = std::env::var("CLICKHOUSE_TEST_URL") else {
+            eprintln!("CLICKHOUSE_TEST_URL not set; skipping");
+            return;
+        };
+        let mut options = options(&url);
+        options.table = format!("default.storage_scanner_test_{}", std::process::id());
+        options.user = std::env::var("CLICKHOUSE_USER").ok();
+        options.password = std::env::var("CLICKHOUSE_PASSWORD").ok();
+        let server = ClickHouse::new(options.clone()).unwrap();
+        let execute = |query: String| server.send("Query", || server.request(&query)).unwrap();
+        execute(format!(
+            "CREATE TABLE {} (path String, size UInt64, owner Nullable(String)) ENGINE = MergeTree ORDER BY path",
+            options.table
+        ));
+
+        let dir = TempDir::new().unwrap();
+        let result = load(&write_scan(dir.path(), 5), &options);
+        execute(format!("DROP TABLE {}", options.table));
+        assert_eq!(result.unwrap().rows_after, 5);
+    }
+}
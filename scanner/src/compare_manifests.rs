@@ -0,0 +1,200 @@
+use crate::manifest::ScanManifest;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::path::Path;
+
+/// Scope of the metrics that cover the whole scan rather than one directory
+pub const SCAN_SCOPE: &str = "(scan)";
+
+/// One metric of two scans
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MetricDelta {
+    /// [`SCAN_SCOPE`], or the top-level directory the metric belongs to
+    pub scope: String,
+
+    /// e.g. `rows`, `bytes`, `chunks`, or `duration_secs`
+    pub metric: String,
+
+    /// None when the old scan did not record the metric
+    pub old: Option<u64>,
+
+    pub new: Option<u64>,
+
+    /// Change relative to the old value; None when the old value is zero or
+    /// either value is unknown
+    pub change_percent: Option<f64>,
+
+    /// Whether the change is larger than the threshold in either direction.
+    /// Growth from zero always counts; unknown values never do.
+    pub exceeded: bool,
+}
+
+impl MetricDelta {
+    fn new(scope: &str, metric: &str, old: Option<u64>, new: Option<u64>, threshold: f64) -> Self {
+        let (change_percent, exceeded) = match (old, new) {
+            (Some(0), Some(0)) => (Some(0.0), false),
+            (Some(0), Some(_)) => (None, true),
+            (Some(old), Some(new)) => {
+                let change = (new as f64 - old as f64) * 100.0 / old as f64;
+                (Some(change), change.abs() > threshold)
+            }
+            _ => (None, false),
+        };
+        Self {
+            scope: scope.to_string(),
+            metric: metric.to_string(),
+            old,
+            new,
+            change_percent,
+            exceeded,
+        }
+    }
+}
+
+/// Result of [`compare_manifests`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestComparison {
+    pub old_manifest: String,
+
+    pub new_manifest: String,
+
+    /// Largest allowed change, in percent
+    pub threshold_percent: f64,
+
+    /// Scan-wide metrics first, then each top-level directory in name order
+    pub metrics: Vec<MetricDelta>,
+
+    /// Number of metrics beyond the threshold
+    pub deviations: usize,
+}
+
+impl ManifestComparison {
+    pub fn is_ok(&self) -> bool {
+        self.deviations == 0
+    }
+
+    pub fn exceeded(&self) -> impl Iterator<Item = &MetricDelta> {
+        self.metrics.iter().filter(|m| m.exceeded)
+    }
+}
+
+fn duration_secs(manifest: &ScanManifest) -> Option<u64> {
+    manifest.scan_end.map(|end| (end - manifest.scan_start).max(0) as u64)
+}
+
+/// Compare the totals, chunk counts, and durations of two scans, and the rows
+/// and bytes of every top-level directory either one recorded. A directory
+/// missing from one scan counts as empty there, so a directory that vanished
+/// shows as a 100% drop.
+pub fn compare(old: &ScanManifest, new: &ScanManifest, threshold: f64) -> Vec<MetricDelta> {
+    let total_bytes = |m: &ScanManifest| (!m.dir_stats.is_empty()).then(|| m.dir_stats.values().map(|s| s.bytes).sum());
+
+    let mut metrics = vec![
+        MetricDelta::new(SCAN_SCOPE, "rows", Some(old.total_rows), Some(new.total_rows), threshold),
+        MetricDelta::new(SCAN_SCOPE, "bytes", total_bytes(old), total_bytes(new), threshold),
+        MetricDelta::new(SCAN_SCOPE, "chunks", Some(old.chunk_count as u64), Some(new.chunk_count as u64), threshold),
+        MetricDelta::new(SCAN_SCOPE, "duration_secs", duration_secs(old), duration_secs(new), threshold),
+    ];
+
+    // Manifests written before per-directory stats existed have none to compare
+    if old.dir_stats.is_empty() || new.dir_stats.is_empty() {
+        return metrics;
+    }
+    let dirs: BTreeSet<&String> = old.dir_stats.keys().chain(new.dir_stats.keys()).collect();
+    for dir in dirs {
+        let old_stats = old.dir_stats.get(dir).copied().unwrap_or_default();
+        let new_stats = new.dir_stats.get(dir).copied().unwrap_or_default();
+        metrics.push(MetricDelta::new(dir, "rows", Some(old_stats.rows), Some(new_stats.rows), threshold));
+        metrics.push(MetricDelta::new(dir, "bytes", Some(old_stats.bytes), Some(new_stats.bytes), threshold));
+    }
+    metrics
+}
+
+/// Load two manifests and [`compare`] them
+pub fn compare_manifests(old: &Path, new: &Path, threshold: f64) -> Result<ManifestComparison> {
+    let old_manifest = ScanManifest::load_from_file(old)
+        .with_context(|| format!("Failed to load {}", old.display()))?;
+    let new_manifest = ScanManifest::load_from_file(new)
+        .with_context(|| format!("Failed to load {}", new.display()))?;
+
+    let metrics = compare(&old_manifest, &new_manifest, threshold);
+    Ok(ManifestComparison {
+        old_manifest: old.display().to_string(),
+        new_manifest: new.display().to_string(),
+        threshold_percent: threshold,
+        deviations: metrics.iter().filter(|m| m.exceeded).count(),
+        metrics,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::DirStats;
+    use tempfile::TempDir;
+
+    fn manifest(rows: u64, chunks: usize, duration: i64, dirs: &[(&str, u64, u64)]) -> ScanManifest {
+        let mut manifest = ScanManifest::new("/project".to_string());
+        manifest.total_rows = rows;
+        manifest.chunk_count = chunks;
+        manifest.scan_start = 1_700_000_000;
+        manifest.scan_end = Some(1_700_000_000 + duration);
+        manifest.completed = true;
+        for &(dir, rows, bytes) in dirs {
+            manifest.dir_stats.insert(dir.to_string(), DirStats { rows, bytes });
+        }
+        manifest
+    }
+
+    fn delta<'a>(metrics: &'a [MetricDelta], scope: &str, metric: &str) -> &'a MetricDelta {
+        metrics.iter().find(|m| m.scope == scope && m.metric == metric).unwrap()
+    }
+
+    #[test]
+    fn test_missing_directory_exceeds_threshold() {
+        let temp_dir = TempDir::new().unwrap();
+        let old = temp_dir.path().join("old_manifest.json");
+        let new = temp_dir.path().join("new_manifest.json");
+        manifest(1000, 10, 3600, &[("labA", 600, 6_000), ("labB", 400, 4_000)]).save_to_file(&old).unwrap();
+        // labB's mount was missing; labC is new
+        manifest(650, 7, 3400, &[("labA", 610, 6_100), ("labC", 40, 400)]).save_to_file(&new).unwrap();
+
+        let report = compare_manifests(&old, &new, 10.0).unwrap();
+        let m = &report.metrics;
+        assert_eq!(delta(m, SCAN_SCOPE, "rows").change_percent, Some(-35.0));
+        assert!(delta(m, SCAN_SCOPE, "rows").exceeded);
+        assert_eq!(delta(m, SCAN_SCOPE, "bytes").old, Some(10_000));
+        assert_eq!(delta(m, SCAN_SCOPE, "bytes").new, Some(6_500));
+        assert!(delta(m, SCAN_SCOPE, "chunks").exceeded);
+        assert!(!delta(m, SCAN_SCOPE, "duration_secs").exceeded);
+
+        assert!(!delta(m, "labA", "rows").exceeded);
+        assert_eq!(delta(m, "labB", "rows").change_percent, Some(-100.0));
+        assert_eq!(delta(m, "labB", "bytes").new, Some(0));
+        assert_eq!(delta(m, "labC", "rows").change_percent, None);
+        assert!(delta(m, "labC", "rows").exceeded);
+
+        let scopes: Vec<&str> = report.exceeded().map(|m| m.scope.as_str()).collect();
+        assert_eq!(scopes, [SCAN_SCOPE, SCAN_SCOPE, SCAN_SCOPE, "labB", "labB", "labC", "labC"]);
+        assert_eq!(report.deviations, 7);
+        assert!(!report.is_ok());
+    }
+
+    #[test]
+    fn test_threshold_and_unknown_values() {
+        let old = manifest(1000, 10, 3600, &[]);
+        let mut new = manifest(1100, 10, 3600, &[("labA", 1100, 0)]);
+        new.scan_end = None;
+
+        // Exactly at the threshold passes
+        let metrics = compare(&old, &new, 10.0);
+        assert!(metrics.iter().all(|m| !m.exceeded));
+        assert_eq!(delta(&metrics, SCAN_SCOPE, "duration_secs").change_percent, None);
+        // Without stats in both manifests, no directory is compared
+        assert_eq!(metrics.len(), 4);
+
+        let metrics = compare(&old, &new, 9.5);
+        assert!(delta(&metrics, SCAN_SCOPE, "rows").exceeded);
+    }
+}
@@ -0,0 +1,450 @@
+use anyhow::{Context, Result};
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use std::ffi::OsString;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+/// Arguments a config file can't set, besides the global `--config` itself
+const RESERVED: &[&str] = &["help", "version"];
+
+/// One option from a config file, as the command-line arguments that set it
+#[derive(Debug, Clone, PartialEq)]
+struct Setting {
+    /// Subcommand path the option belongs to; empty for global options
+    commands: Vec<String>,
+
+    /// Argument id (the field name clap parses into)
+    id: String,
+
+    args: Vec<OsString>,
+}
+
+/// The config file `matches` names, unless the subcommand being run takes
+/// its own file with `--config` (as `schedule` and `pipeline` do)
+pub fn config_path(command: &Command, matches: &ArgMatches) -> Option<PathBuf> {
+    let mut command = command;
+    let mut deepest = matches;
+    while let Some((name, sub_matches)) = deepest.subcommand() {
+        command = command.find_subcommand(name)?;
+        if owns_config(command) {
+            return None;
+        }
+        deepest = sub_matches;
+    }
+    matches.get_raw("config")?.next().map(PathBuf::from)
+}
+
+/// Whether `command` has a `--config` of its own, shadowing the global one
+fn owns_config(command: &Command) -> bool {
+    command.get_arguments().any(|arg| arg.get_id() == "config" && !arg.is_global_set())
+}
+
+/// Whether a config file can set `arg` of `command`
+fn configurable(command: &Command, arg: &Arg) -> bool {
+    !arg.is_positional()
+        && !RESERVED.contains(&arg.get_id().as_str())
+        && (arg.get_id() != "config" || owns_config(command))
+}
+
+/// `args` (the whole command line) with the options set in the TOML file at
+/// `path` added, except those the command line (or the environment) already
+/// sets. Parsing the result again fills the same structs the command line
+/// does, with the same validation. `matches` is `args` parsed by `command`
+/// with errors ignored, since the file may supply required options.
+///
+/// Sections are named after subcommands (`[scan]`, `[manifest.merge]`) and
+/// keys after their flags, with `-` or `_` (`rows_per_chunk`); top-level keys
+/// set global options. A table within a section joins its name to its keys,
+/// so `[scan.upload]` `url = ...` sets `upload_url`. Unknown keys are errors.
+pub fn args_with_config(
+    command: &Command,
+    matches: &ArgMatches,
+    mut args: Vec<OsString>,
+    path: &Path,
+) -> Result<Vec<OsString>> {
+    let settings = load(command, path)?;
+
+    // The subcommand being run, and its matches
+    let mut invoked = Vec::new();
+    let mut deepest = matches;
+    while let Some((name, sub_matches)) = deepest.subcommand() {
+        invoked.push(name.to_string());
+        deepest = sub_matches;
+    }
+
+    for setting in settings {
+        if !setting.commands.is_empty() && setting.commands != invoked {
+            continue;
+        }
+        // Global options reach the subcommand's matches too
+        let given = matches!(
+            deepest.value_source(&setting.id),
+            Some(ValueSource::CommandLine) | Some(ValueSource::EnvVariable)
+        );
+        if !given {
+            args.extend(setting.args);
+        }
+    }
+    Ok(args)
+}
+
+/// Every option the file at `path` sets, checked against `command`
+fn load(command: &Command, path: &Path) -> Result<Vec<Setting>> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let table: Table = toml::from_str(&contents)
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    let mut settings = Vec::new();
+    collect(command, &mut Vec::new(), &mut Vec::new(), "", &table, &mut settings)
+        .with_context(|| format!("Invalid config file {}", path.display()))?;
+    Ok(settings)
+}
+
+/// Add the options `table` (at `keys` in the file) sets for `command`
+/// (reached by `commands`) to `settings`; `prefix` is joined to the keys of
+/// tables within a section
+fn collect(
+    command: &Command,
+    commands: &mut Vec<String>,
+    keys: &mut Vec<String>,
+    prefix: &str,
+    table: &Table,
+    settings: &mut Vec<Setting>,
+) -> Result<()> {
+    for (key, value) in table {
+        let name = format!("{}{}", prefix, key.replace('-', "_"));
+        let dotted = || keys.iter().chain(std::iter::once(key)).cloned().collect::<Vec<_>>().join(".");
+
+        if let Value::Table(inner) = value {
+            keys.push(key.clone());
+            match command.find_subcommand(key) {
+                Some(sub) if prefix.is_empty() && owns_config(sub) => {
+                    anyhow::bail!("{} reads its own --config file, not this one", keys.join(" "))
+                }
+                Some(sub) if prefix.is_empty() => {
+                    commands.push(sub.get_name().to_string());
+                    collect(sub, commands, keys, "", inner, settings)?;
+                    commands.pop();
+                }
+                _ if !commands.is_empty() => collect(command, commands, keys, &format!("{}_", name), inner, settings)?,
+                _ => anyhow::bail!("Unknown key {}", keys.join(".")),
+            }
+            keys.pop();
+            continue;
+        }
+
+        let arg = command.get_arguments()
+            .filter(|arg| configurable(command, arg))
+            .find(|arg| arg.get_id() == name.as_str() || arg.get_long().is_some_and(|l| l.replace('-', "_") == name))
+            .ok_or_else(|| anyhow::anyhow!("Unknown key {}", dotted()))?;
+        let args = setting_args(arg, value).with_context(|| format!("Invalid value for {}", dotted()))?;
+        settings.push(Setting { commands: commands.clone(), id: arg.get_id().to_string(), args });
+    }
+    Ok(())
+}
+
+/// The command-line arguments that set `arg` to `value`
+fn setting_args(arg: &Arg, value: &Value) -> Result<Vec<OsString>> {
+    let long = arg.get_long().context("Option has no long flag")?;
+    let flag = |value: &str| OsString::from(format!("--{}={}", long, value));
+    match (arg.get_action(), value) {
+        (ArgAction::SetTrue, Value::Boolean(true)) => Ok(vec![OsString::from(format!("--{}", long))]),
+        (ArgAction::SetTrue, Value::Boolean(false)) => Ok(Vec::new()),
+        (ArgAction::SetTrue, _) => anyhow::bail!("Expected true or false"),
+        (ArgAction::Append, Value::Array(values)) => values.iter().map(|v| Ok(flag(&scalar(v)?))).collect(),
+        (_, Value::Array(_)) => anyhow::bail!("Expected a single value"),
+        (_, value) => Ok(vec![flag(&scalar(value)?)]),
+    }
+}
+
+/// `value` as it would be written on the command line
+fn scalar(value: &Value) -> Result<String> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Integer(i) => Ok(i.to_string()),
+        Value::Float(f) => Ok(f.to_string()),
+        Value::Boolean(b) => Ok(b.to_string()),
+        Value::Datetime(d) => Ok(d.to_string()),
+        Value::Array(_) | Value::Table(_) => anyhow::bail!("Expected a single value"),
+    }
+}
+
+/// A config file with every option `command` takes, commented out at its
+/// default, for `config print-default`
+pub fn template(command: &Command) -> String {
+    let mut out = format!(
+        "# {} configuration, read with --config FILE\n\
+         #\n\
+         # Each [section] holds a subcommand's options, named as its flags (with - or _).\n\
+         # Environment variables override the file, and the command line overrides both.\n\
+         # Uncomment to set.\n\n",
+        command.get_name()
+    );
+    write_section(&mut out, command, &mut Vec::new());
+    out
+}
+
+fn write_section(out: &mut String, command: &Command, commands: &mut Vec<String>) {
+    let args: Vec<&Arg> = command.get_arguments()
+        .filter(|arg| configurable(command, arg) && (commands.is_empty() || !arg.is_global_set()))
+        .collect();
+    if !args.is_empty() {
+        if !commands.is_empty() {
+            let _ = write!(out, "\n[{}]\n", commands.join("."));
+        }
+        for arg in args {
+            write_arg(out, arg);
+        }
+    }
+
+    for sub in command.get_subcommands() {
+        if matches!(sub.get_name(), "help" | "config") || owns_config(sub) {
+            continue;
+        }
+        commands.push(sub.get_name().to_string());
+        write_section(out, sub, commands);
+        commands.pop();
+    }
+}
+
+fn write_arg(out: &mut String, arg: &Arg) {
+    let Some(long) = arg.get_long() else { return };
+    if let Some(help) = arg.get_help() {
+        let _ = writeln!(out, "# {}", help.to_string().lines().next().unwrap_or_default());
+    }
+    let choices: Vec<String> = arg.get_possible_values().iter()
+        .filter(|v| !v.is_hide_set())
+        .map(|v| v.get_name().to_string())
+        .collect();
+    if !choices.is_empty() && !matches!(arg.get_action(), ArgAction::SetTrue) {
+        let _ = writeln!(out, "# One of: {}", choices.join(", "));
+    }
+    if let Some(env) = arg.get_env() {
+        let _ = writeln!(out, "# Environment: {}", env.to_string_lossy());
+    }
+
+    let defaults: Vec<String> = arg.get_default_values().iter()
+        .map(|v| toml_value(&v.to_string_lossy()))
+        .collect();
+    let value = match arg.get_action() {
+        ArgAction::SetTrue => "false".to_string(),
+        ArgAction::Append => format!("[{}]", defaults.join(", ")),
+        _ if !defaults.is_empty() => defaults.join(", "),
+        _ => {
+            let name = arg.get_value_names().and_then(|names| names.first()).map(|n| n.to_string());
+            format!("\"<{}>\"", name.unwrap_or_else(|| arg.get_id().to_string().to_uppercase()))
+        }
+    };
+    let _ = writeln!(out, "# {} = {}", long.replace('-', "_"), value);
+}
+
+/// A Markdown table of the environment variables that set options of
+/// `command` and its subcommands, with the flag each one stands in for
+pub fn env_table(command: &Command) -> String {
+    let mut out = String::from("| Variable | Option | Description |\n|---|---|---|\n");
+    write_env_rows(&mut out, command, &mut Vec::new());
+    out
+}
+
+fn write_env_rows(out: &mut String, command: &Command, commands: &mut Vec<String>) {
+    for arg in command.get_arguments() {
+        let (Some(env), Some(long)) = (arg.get_env(), arg.get_long()) else { continue };
+        let help = arg.get_help().map(|help| help.to_string()).unwrap_or_default();
+        let mut option = commands.clone();
+        option.push(format!("--{}", long));
+        let _ = writeln!(
+            out, "| `{}` | `{}` | {} |",
+            env.to_string_lossy(), option.join(" "), help.lines().next().unwrap_or_default().replace('|', "\\|")
+        );
+    }
+    for sub in command.get_subcommands() {
+        commands.push(sub.get_name().to_string());
+        write_env_rows(out, sub, commands);
+        commands.pop();
+    }
+}
+
+/// A default value written as TOML: numbers and booleans bare, the rest quoted
+fn toml_value(value: &str) -> String {
+    let bare = value.parse::<i64>().is_ok() || value.parse::<f64>().is_ok_and(f64::is_finite)
+        || value == "true" || value == "false";
+    match bare {
+        true => value.to_string(),
+        false => Value::String(value.to_string()).to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::value_parser;
+    use tempfile::TempDir;
+
+    fn command() -> Command {
+        Command::new("tool")
+            .arg(Arg::new("verbose").short('v').long("verbose").global(true).action(ArgAction::SetTrue))
+            .arg(Arg::new("config").long("config").global(true))
+            .subcommand(
+                Command::new("scan")
+                    .arg(Arg::new("path").short('p').long("path").required(true).help("Path to scan"))
+                    .arg(Arg::new("rows_per_chunk").long("rows-per-chunk").default_value("100")
+                        .value_parser(value_parser!(u64)))
+                    .arg(Arg::new("incremental").long("incremental").action(ArgAction::SetTrue))
+                    .arg(Arg::new("upload_url").long("upload-url"))
+                    .arg(Arg::new("inputs").long("input").action(ArgAction::Append))
+                    .arg(Arg::new("mode").long("mode").value_parser(["fast", "slow"]).default_value("fast"))
+                    .arg(Arg::new("threads").long("threads").env("CONFIG_FILE_TEST_THREADS").help("Threads | workers")),
+            )
+            .subcommand(Command::new("manifest").subcommand(
+                Command::new("merge").arg(Arg::new("output").short('o').long("output")),
+            ))
+            .subcommand(Command::new("pipeline").arg(Arg::new("config").short('c').long("config")))
+    }
+
+    /// `args` with the config `toml` applied, parsed
+    fn parse(toml: &str, args: &[&str]) -> Result<ArgMatches> {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, toml).unwrap();
+        let args: Vec<OsString> = args.iter().map(OsString::from).collect();
+        let matches = command().ignore_errors(true).try_get_matches_from(&args)?;
+        assert_eq!(config_path(&command(), &matches), None);
+        let args = args_with_config(&command(), &matches, args, &path)?;
+        Ok(command().try_get_matches_from(args)?)
+    }
+
+    #[test]
+    fn test_precedence() {
+        let toml = "verbose = true\n\
+                    [scan]\npath = \"/data\"\nrows_per_chunk = 5\nincremental = true\n\
+                    input = [\"a\", \"b\"]\n\
+                    [scan.upload]\nurl = \"http://host/up\"\n\
+                    [manifest.merge]\noutput = \"merged.json\"\n";
+
+        // The file supplies the required path and overrides defaults
+        let matches = parse(toml, &["tool", "scan"]).unwrap();
+        let (_, scan) = matches.subcommand().unwrap();
+        assert_eq!(scan.get_one::<String>("path").unwrap(), "/data");
+        assert_eq!(scan.get_one::<u64>("rows_per_chunk"), Some(&5));
+        assert!(scan.get_flag("incremental"));
+        assert!(scan.get_flag("verbose"));
+        assert_eq!(scan.get_one::<String>("upload_url").unwrap(), "http://host/up");
+        assert_eq!(scan.get_many::<String>("inputs").unwrap().collect::<Vec<_>>(), ["a", "b"]);
+        // Unset by both: the default stands
+        assert_eq!(scan.get_one::<String>("mode").unwrap(), "fast");
+
+        // The command line overrides the file
+        let matches = parse(toml, &["tool", "scan", "-p", "/other", "--rows-per-chunk=7", "--input", "c"]).unwrap();
+        let (_, scan) = matches.subcommand().unwrap();
+        assert_eq!(scan.get_one::<String>("path").unwrap(), "/other");
+        assert_eq!(scan.get_one::<u64>("rows_per_chunk"), Some(&7));
+        assert_eq!(scan.get_many::<String>("inputs").unwrap().collect::<Vec<_>>(), ["c"]);
+
+        // Only the invoked subcommand's section applies
+        let matches = parse(toml, &["tool", "manifest", "merge"]).unwrap();
+        let (_, manifest) = matches.subcommand().unwrap();
+        let (_, merge) = manifest.subcommand().unwrap();
+        assert_eq!(merge.get_one::<String>("output").unwrap(), "merged.json");
+    }
+
+    #[test]
+    fn test_config_path() {
+        let path = |args: &[&str]| {
+            let matches = command().ignore_errors(true).try_get_matches_from(args).unwrap();
+            config_path(&command(), &matches)
+        };
+        assert_eq!(path(&["tool", "--config", "a.toml", "scan"]), Some(PathBuf::from("a.toml")));
+        assert_eq!(path(&["tool", "scan", "--config", "a.toml"]), Some(PathBuf::from("a.toml")));
+        assert_eq!(path(&["tool", "scan"]), None);
+        // The pipeline's own file
+        assert_eq!(path(&["tool", "pipeline", "--config", "p.toml"]), None);
+    }
+
+    #[test]
+    fn test_values_are_validated_like_flags() {
+        let err = parse("[scan]\npath = \"/data\"\nrows_per_chunk = \"many\"\n", &["tool", "scan"]).unwrap_err();
+        assert!(err.to_string().contains("many"), "{}", err);
+        let err = parse("[scan]\npath = \"/data\"\nmode = \"medium\"\n", &["tool", "scan"]).unwrap_err();
+        assert!(err.to_string().contains("medium"), "{}", err);
+    }
+
+    #[test]
+    fn test_unknown_keys() {
+        for (toml, key) in [
+            ("[scan]\npaht = \"/data\"\n", "scan.paht"),
+            ("[scna]\npath = \"/data\"\n", "scna"),
+            ("[scan.upload]\nurll = \"x\"\n", "scan.upload.urll"),
+            ("[manifest.merge]\ninput = \"x\"\n", "manifest.merge.input"),
+            ("config = \"other.toml\"\n", "config"),
+        ] {
+            let err = parse(toml, &["tool", "scan", "-p", "/data"]).unwrap_err();
+            assert!(format!("{:#}", err).contains(&format!("Unknown key {}", key)), "{:#}", err);
+        }
+
+        let err = parse("[pipeline]\nconfig = \"p.toml\"\n", &["tool", "scan", "-p", "/data"]).unwrap_err();
+        assert!(format!("{:#}", err).contains("pipeline reads its own --config file"), "{:#}", err);
+        let err = parse("[scan]\nincremental = \"yes\"\n", &["tool", "scan", "-p", "/data"]).unwrap_err();
+        assert!(format!("{:#}", err).contains("Invalid value for scan.incremental"), "{:#}", err);
+        let err = parse("[scan]\nupload_url = [\"a\"]\n", &["tool", "scan", "-p", "/data"]).unwrap_err();
+        assert!(format!("{:#}", err).contains("Expected a single value"), "{:#}", err);
+    }
+
+    #[test]
+    fn test_template_round_trip() {
+        let template = template(&command());
+        assert!(template.contains("\n[scan]\n# Path to scan\n# path = \"<PATH>\"\n"), "{}", template);
+        assert!(template.contains("# rows_per_chunk = 100\n"), "{}", template);
+        assert!(template.contains("# One of: fast, slow\n# mode = \"fast\"\n"), "{}", template);
+        assert!(template.contains("# incremental = false\n"), "{}", template);
+        assert!(template.contains("# input = []\n"), "{}", template);
+        assert!(template.contains("\n[manifest.merge]\n# output = \"<OUTPUT>\"\n"), "{}", template);
+        assert!(!template.contains("[manifest]\n"), "{}", template);
+        assert!(!template.contains("config ="), "{}", template);
+        assert!(!template.contains("[pipeline]"), "{}", template);
+
+        // Uncommented, the template is a config file that sets every option
+        let uncommented: String = template.lines()
+            .filter(|line| !line.starts_with("# ") || line.contains(" = "))
+            .map(|line| line.strip_prefix("# ").unwrap_or(line))
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| format!("{}\n", line))
+            .collect();
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, &uncommented).unwrap();
+        let settings = load(&command(), &path).unwrap();
+        assert_eq!(settings.len(), 9, "{}", uncommented);
+    }
+
+    #[test]
+    fn test_environment_between_flags_and_file() {
+        let toml = "[scan]\npath = \"/data\"\nthreads = \"2\"\n";
+        let threads = |args: &[&str]| parse(toml, args).unwrap().subcommand_matches("scan").unwrap()
+            .get_one::<String>("threads").cloned();
+
+        // Only this test sets the variable
+        std::env::set_var("CONFIG_FILE_TEST_THREADS", "4");
+        let from_env = threads(&["tool", "scan"]);
+        let from_flag = threads(&["tool", "scan", "--threads", "8"]);
+        std::env::remove_var("CONFIG_FILE_TEST_THREADS");
+        let from_file = threads(&["tool", "scan"]);
+
+        assert_eq!(from_env.as_deref(), Some("4"));
+        assert_eq!(from_flag.as_deref(), Some("8"));
+        assert_eq!(from_file.as_deref(), Some("2"));
+    }
+
+    #[test]
+    fn test_env_table() {
+        let table = env_table(&command());
+        assert_eq!(
+            table,
+            "| Variable | Option | Description |\n|---|---|---|\n\
+             | `CONFIG_FILE_TEST_THREADS` | `scan --threads` | Threads \\| workers |\n"
+        );
+        assert!(template(&command()).contains("# Threads | workers\n# Environment: CONFIG_FILE_TEST_THREADS\n# threads = \"<THREADS>\"\n"));
+    }
+}
@@ -0,0 +1,400 @@
+use crate::aggregate::find_chunk_files;
+use crate::models::FileEntry;
+use crate::query::{is_gzip, Expr, QueryFormat};
+use crate::reader::ParquetEntryReader;
+use crate::sink::{entry_schema, EntrySink, FormatSink};
+use crate::writer::{entries_from_batch, entry_columns, ParquetFileWriter};
+use anyhow::{Context, Result};
+use arrow::array::Array;
+use arrow::datatypes::SchemaRef;
+use arrow::record_batch::RecordBatch;
+use flate2::read::GzDecoder;
+use parquet::basic::{BrotliLevel, Compression, GzipLevel, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// Entries per batch read from CSV, JSONL, and Arrow inputs
+const BATCH_SIZE: usize = 65_536;
+
+/// What a path becomes when its name was not valid UTF-8 at scan time
+const REPLACEMENT_CHAR: char = '\u{FFFD}';
+
+/// How [`convert`] writes its output
+#[derive(Debug, Clone, Default)]
+pub struct ConvertOptions {
+    /// Columns to write, in this order; None writes every column
+    pub columns: Option<Vec<String>>,
+
+    /// Only convert entries this expression holds for
+    pub filter: Option<Expr>,
+
+    /// Codec of Parquet output (default: Snappy, as the scanner writes)
+    pub compression: Option<Compression>,
+
+    /// Maximum rows per Parquet row group
+    pub row_group_size: Option<usize>,
+}
+
+/// Entries read and written by [`convert`]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ConvertSummary {
+    pub rows_read: u64,
+
+    pub rows_written: u64,
+
+    /// Paths holding U+FFFD, most likely names that were not valid UTF-8
+    /// and were replaced when scanned
+    pub lossy_paths: u64,
+}
+
+/// Parse a Parquet codec name: none, snappy, gzip, zstd, lz4, or brotli
+pub fn parse_compression(input: &str) -> Result<Compression> {
+    match input.to_ascii_lowercase().as_str() {
+        "none" | "uncompressed" => Ok(Compression::UNCOMPRESSED),
+        "snappy" => Ok(Compression::SNAPPY),
+        "gzip" => Ok(Compression::GZIP(GzipLevel::default())),
+        "zstd" => Ok(Compression::ZSTD(ZstdLevel::default())),
+        "lz4" => Ok(Compression::LZ4_RAW),
+        "brotli" => Ok(Compression::BROTLI(BrotliLevel::default())),
+        _ => anyhow::bail!("Unknown compression '{}' (expected none, snappy, gzip, zstd, lz4, or brotli)", input),
+    }
+}
+
+/// Kinds of files [`convert`] reads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InputFormat {
+    /// Any input `find_chunk_files` accepts
+    Parquet,
+    Csv,
+    Jsonl,
+    Arrow,
+}
+
+impl InputFormat {
+    fn from_path(path: &Path) -> Self {
+        let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("").to_ascii_lowercase();
+        let name = name.strip_suffix(".gz").unwrap_or(&name);
+        match Path::new(name).extension().and_then(|e| e.to_str()) {
+            Some("csv") => InputFormat::Csv,
+            Some("jsonl") | Some("ndjson") => InputFormat::Jsonl,
+            Some("arrow") | Some("feather") => InputFormat::Arrow,
+            _ => InputFormat::Parquet,
+        }
+    }
+}
+
+/// Batches of entries read from one input
+type EntryBatches = Box<dyn Iterator<Item = Result<Vec<FileEntry>>>>;
+
+fn open_text(path: &Path) -> Result<Box<dyn Read>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    Ok(if is_gzip(path) { Box::new(GzDecoder::new(file)) } else { Box::new(file) })
+}
+
+/// Read a CSV file with a header row naming FileEntry columns, in any order
+/// and any subset that includes `path`
+fn read_csv(path: &Path) -> Result<EntryBatches> {
+    let mut header = String::new();
+    BufReader::new(open_text(path)?).read_line(&mut header)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let columns: Vec<String> = header.trim_end().split(',').map(|c| c.trim_matches('"').to_string()).collect();
+    let schema = entry_schema(Some(&columns))
+        .with_context(|| format!("Unexpected header in {}", path.display()))?;
+
+    let reader = arrow::csv::ReaderBuilder::new(schema)
+        .has_header(true)
+        .with_batch_size(BATCH_SIZE)
+        .build(open_text(path)?)?;
+    Ok(Box::new(reader.map(|batch| {
+        let mut entries = entries_from_batch(&batch?)?;
+        // CSV writes a missing owner, group, or link target as an empty field
+        for entry in &mut entries {
+            entry.owner = entry.owner.take().filter(|o| !o.is_empty());
+            entry.group = entry.group.take().filter(|g| !g.is_empty());
+            entry.link_target = entry.link_target.take().filter(|t| !t.is_empty());
+        }
+        Ok(entries)
+    })))
+}
+
+/// Read one JSON object per line, as `export` and `query` write them: keys
+/// for missing values are omitted
+fn read_jsonl(path: &Path) -> Result<EntryBatches> {
+    let template = match serde_json::to_value(empty_entry())? {
+        serde_json::Value::Object(map) => map,
+        _ => unreachable!("entries serialize to objects"),
+    };
+    let display = path.display().to_string();
+    let mut lines = BufReader::new(open_text(path)?).lines().enumerate();
+
+    Ok(Box::new(std::iter::from_fn(move || {
+        let mut entries = Vec::new();
+        for (number, line) in lines.by_ref() {
+            let parsed = line.map_err(anyhow::Error::from).and_then(|line| {
+                if line.trim().is_empty() {
+                    return Ok(None);
+                }
+                let serde_json::Value::Object(fields) = serde_json::from_str(&line)? else {
+                    anyhow::bail!("expected a JSON object");
+                };
+                let mut object = template.clone();
+                object.extend(fields);
+                Ok(Some(serde_json::from_value::<FileEntry>(serde_json::Value::Object(object))?))
+            });
+            match parsed {
+                Ok(Some(entry)) => entries.push(entry),
+                Ok(None) => {}
+                Err(e) => return Some(Err(e.context(format!("{}, line {}", display, number + 1)))),
+            }
+            if entries.len() == BATCH_SIZE {
+                break;
+            }
+        }
+        (!entries.is_empty()).then_some(Ok(entries))
+    })))
+}
+
+fn read_arrow(path: &Path) -> Result<EntryBatches> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let reader = arrow::ipc::reader::FileReader::try_new(file, None)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(Box::new(reader.map(|batch| entries_from_batch(&batch?))))
+}
+
+fn read_parquet(path: &Path) -> Result<EntryBatches> {
+    Ok(Box::new(ParquetEntryReader::open(path)?.map(|batch| Ok(batch?))))
+}
+
+/// An entry with every value empty, for filling in missing fields
+fn empty_entry() -> FileEntry {
+    FileEntry {
+        path: String::new(),
+        size: 0,
+        modified_time: 0,
+        accessed_time: 0,
+        created_time: None,
+        file_type: String::new(),
+        inode: 0,
+        permissions: 0,
+        uid: 0,
+        gid: 0,
+        owner: None,
+        group: None,
+        parent_path: String::new(),
+        depth: 0,
+        top_level_dir: String::new(),
+        link_target: None,
+        device: 0,
+        nlink: 0,
+        raw_mode: 0,
+    }
+}
+
+/// Entries of `entries` that `expr` holds for
+fn filter_entries(entries: Vec<FileEntry>, expr: &Expr, schema: &SchemaRef) -> Result<Vec<FileEntry>> {
+    let batch = RecordBatch::try_new(schema.clone(), entry_columns(&entries))?;
+    let mask = expr.mask(&batch)?;
+    Ok(entries.into_iter()
+        .enumerate()
+        .filter(|(i, _)| mask.is_valid(*i) && mask.value(*i))
+        .map(|(_, entry)| entry)
+        .collect())
+}
+
+/// Open the sink for `output`, chosen by its extension
+fn create_sink(output: &Path, options: &ConvertOptions) -> Result<Box<dyn EntrySink>> {
+    let columns = options.columns.as_deref();
+    let extension = output.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+    if matches!(extension.as_str(), "sqlite" | "sqlite3" | "db") {
+        #[cfg(feature = "sqlite")]
+        return Ok(Box::new(crate::sink::SqliteSink::create(output, columns)?));
+        #[cfg(not(feature = "sqlite"))]
+        anyhow::bail!("SQLite output requires building with --features sqlite");
+    }
+
+    let format = QueryFormat::from_path(output)?;
+    let properties = (format == QueryFormat::Parquet).then(|| {
+        WriterProperties::builder()
+            .set_compression(options.compression.unwrap_or(Compression::SNAPPY))
+            .set_max_row_group_size(options.row_group_size.unwrap_or(100_000))
+            .build()
+    });
+    Ok(Box::new(FormatSink::create(output, format, columns, properties)?))
+}
+
+/// Stream the entries of `input` into `output`, converting between Parquet
+/// (any input `find_chunk_files` accepts), CSV, JSONL, Arrow IPC, and, with
+/// the `sqlite` feature, SQLite. Formats follow the file extensions; CSV and
+/// JSONL may be gzip-compressed. Entries are read and written one batch at a
+/// time.
+pub fn convert(input: &Path, output: &Path, options: &ConvertOptions) -> Result<ConvertSummary> {
+    let full_schema = ParquetFileWriter::create_schema();
+    if let Some(ref expr) = options.filter {
+        expr.validate(&full_schema)?;
+    }
+
+    let batches: Vec<EntryBatches> = match InputFormat::from_path(input) {
+        InputFormat::Csv => vec![read_csv(input)?],
+        InputFormat::Jsonl => vec![read_jsonl(input)?],
+        InputFormat::Arrow => vec![read_arrow(input)?],
+        InputFormat::Parquet => {
+            let chunk_files: Vec<PathBuf> = find_chunk_files(input)?;
+            if chunk_files.is_empty() {
+                anyhow::bail!("No Parquet files found in: {}", input.display());
+            }
+            chunk_files.iter().map(|path| read_parquet(path)).collect::<Result<_>>()?
+        }
+    };
+
+    let mut sink = create_sink(output, options)?;
+    let mut summary = ConvertSummary::default();
+    for batch in batches.into_iter().flatten() {
+        let entries = batch?;
+        summary.rows_read += entries.len() as u64;
+        summary.lossy_paths += entries.iter().filter(|e| e.path.contains(REPLACEMENT_CHAR)).count() as u64;
+        let entries = match options.filter {
+            Some(ref expr) => filter_entries(entries, expr, &full_schema)?,
+            None => entries,
+        };
+        sink.write_batch(&entries)?;
+        summary.rows_written += entries.len() as u64;
+    }
+    sink.finalize()?;
+
+    if summary.lossy_paths > 0 {
+        warn!(
+            "{} paths contain U+FFFD: their names were not valid UTF-8 when scanned, and no output format keeps the original bytes",
+            summary.lossy_paths
+        );
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use tempfile::TempDir;
+
+    fn entries() -> Vec<FileEntry> {
+        (0..5)
+            .map(|i| FileEntry {
+                size: 1000 * i,
+                modified_time: 1_700_000_000 + i as i64,
+                accessed_time: 1_700_000_100,
+                created_time: (i % 2 == 0).then_some(1_600_000_000),
+                inode: 100 + i,
+                gid: 100,
+                owner: (i != 3).then(|| "alice".to_string()),
+                group: (i != 4).then(|| "lab".to_string()),
+                depth: 2,
+                top_level_dir: "lab".to_string(),
+                ..FileEntry::fixture(&format!("/project/lab/file, \"{}\".dat", i))
+            })
+            .collect()
+    }
+
+    fn fixture(dir: &Path, entries: &[FileEntry]) -> PathBuf {
+        let path = dir.join("scan.parquet");
+        let mut writer = ParquetFileWriter::new(&path).unwrap();
+        writer.write_batch(entries).unwrap();
+        writer.close().unwrap();
+        path
+    }
+
+    /// Entries of any supported file, via a conversion to Parquet
+    fn read_back(dir: &Path, path: &Path) -> Vec<FileEntry> {
+        let parquet = dir.join(format!("{}.back.parquet", path.file_name().unwrap().to_string_lossy()));
+        convert(path, &parquet, &ConvertOptions::default()).unwrap();
+        read_parquet(&parquet).unwrap().flat_map(|b| b.unwrap()).collect()
+    }
+
+    #[test]
+    fn test_round_trip_every_pair() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        let source = fixture(dir, &entries());
+        let formats = ["parquet", "csv", "csv.gz", "jsonl", "jsonl.gz", "arrow"];
+
+        for from in formats {
+            let first = dir.join(format!("first.{}", from));
+            convert(&source, &first, &ConvertOptions::default()).unwrap();
+            for to in formats {
+                let second = dir.join(format!("{}_to.{}", from.replace('.', "_"), to));
+                let summary = convert(&first, &second, &ConvertOptions::default())
+                    .unwrap_or_else(|e| panic!("{} -> {}: {:#}", from, to, e));
+                assert_eq!(summary.rows_written, 5);
+                assert_eq!(read_back(dir, &second), entries(), "{} -> {}", from, to);
+            }
+        }
+    }
+
+    #[test]
+    fn test_columns_filter_and_codec() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        let source = fixture(dir, &entries());
+
+        let output = dir.join("subset.csv");
+        let options = ConvertOptions {
+            columns: Some(vec!["size".to_string(), "path".to_string()]),
+            filter: Some(Expr::parse("size >= 2000 && owner == 'alice'").unwrap()),
+            ..Default::default()
+        };
+        let summary = convert(&source, &output, &options).unwrap();
+        assert_eq!((summary.rows_read, summary.rows_written), (5, 2));
+        let text = std::fs::read_to_string(&output).unwrap();
+        assert_eq!(text.lines().next(), Some("size,path"));
+
+        // Columns left out come back empty
+        let back = read_back(dir, &output);
+        assert_eq!(back.iter().map(|e| e.size).collect::<Vec<_>>(), [2000, 4000]);
+        assert_eq!(back[0].owner, None);
+
+        let output = dir.join("zstd.parquet");
+        let options = ConvertOptions {
+            compression: Some(parse_compression("zstd").unwrap()),
+            row_group_size: Some(2),
+            ..Default::default()
+        };
+        convert(&source, &output, &options).unwrap();
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(&output).unwrap()).unwrap();
+        let metadata = reader.metadata();
+        assert_eq!(metadata.num_row_groups(), 3);
+        assert!(matches!(metadata.row_group(0).column(0).compression(), Compression::ZSTD(_)));
+        assert!(parse_compression("lzma").is_err());
+    }
+
+    #[test]
+    fn test_lossy_paths_are_counted() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        let mut lossy = entries();
+        lossy[1].path = format!("/project/lab/caf{}.dat", REPLACEMENT_CHAR);
+        let source = fixture(dir, &lossy);
+
+        let summary = convert(&source, &dir.join("out.csv"), &ConvertOptions::default()).unwrap();
+        assert_eq!(summary.lossy_paths, 1);
+        assert_eq!(read_back(dir, &dir.join("out.csv"))[1].path, lossy[1].path);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_sqlite_output() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        let source = fixture(dir, &entries());
+        let output = dir.join("scan.sqlite");
+
+        convert(&source, &output, &ConvertOptions::default()).unwrap();
+        let connection = rusqlite::Connection::open(&output).unwrap();
+        let (rows, owners): (i64, i64) = connection
+            .query_row("SELECT COUNT(*), COUNT(owner) FROM entries", [], |r| Ok((r.get(0)?, r.get(1)?)))
+            .unwrap();
+        assert_eq!((rows, owners), (5, 4));
+    }
+}
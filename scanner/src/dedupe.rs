@@ -0,0 +1,390 @@
+use crate::writer::schema_version_entry;
+use anyhow::{Context, Result};
+use arrow::array::{Array, ArrayRef, Int64Array, StringArray, UInt32Array};
+use arrow::compute::{cast, concat_batches, take};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ArrowWriter;
+use std::collections::hash_map::{DefaultHasher, Entry};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::warn;
+
+/// Compressed input bytes per spill partition; each partition is loaded whole
+pub const PARTITION_TARGET_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Upper bound on spill partitions (open files during the spill pass)
+pub const MAX_PARTITIONS: usize = 256;
+
+/// Times a partition larger than its target is split again before it is
+/// loaded whole anyway; only rows sharing one path can't be split apart
+const MAX_SPLIT_LEVELS: u32 = 3;
+
+/// Column added to spilled rows recording which input they came from
+const ORDER_COLUMN: &str = "__dedupe_input_order";
+
+/// Number of spill partitions for a given amount of compressed input
+pub fn partitions_for(input_bytes: u64) -> usize {
+    partitions_for_target(input_bytes, PARTITION_TARGET_BYTES)
+}
+
+fn partitions_for_target(input_bytes: u64, partition_bytes: u64) -> usize {
+    ((input_bytes / partition_bytes) as usize + 1).min(MAX_PARTITIONS)
+}
+
+/// Drops rows with a duplicate `path`, keeping the one with the greatest
+/// `modified_time` (ties go to the later input, then the later row).
+///
+/// Rows are hash-partitioned by path into Parquet spill files so that only
+/// one partition has to be held in memory at a time. A partition whose spill
+/// file outgrows the target (say, from more input than [`MAX_PARTITIONS`]
+/// partitions cover) is split again by a differently seeded hash.
+pub struct PathDeduper {
+    spill_dir: PathBuf,
+    schema: SchemaRef,
+    spill_schema: SchemaRef,
+    writers: Vec<Option<ArrowWriter<File>>>,
+    partition_bytes: u64,
+}
+
+impl PathDeduper {
+    /// Create a deduper spilling into `spill_dir`, which must not exist yet
+    pub fn new(spill_dir: &Path, schema: SchemaRef, partitions: usize) -> Result<Self> {
+        for column in ["path", "modified_time"] {
+            schema.field_with_name(column)
+                .with_context(|| format!("Deduplicating by path requires a '{}' column", column))?;
+        }
+
+        fs::create_dir(spill_dir)
+            .with_context(|| format!("Failed to create spill directory {}", spill_dir.display()))?;
+
+        let mut fields: Vec<Field> = schema.fields().iter().map(|f| f.as_ref().clone()).collect();
+        fields.push(Field::new(ORDER_COLUMN, DataType::UInt32, false));
+
+        Ok(Self {
+            spill_dir: spill_dir.to_path_buf(),
+            schema,
+            spill_schema: Arc::new(Schema::new(fields)),
+            writers: (0..partitions.max(1)).map(|_| None).collect(),
+            partition_bytes: PARTITION_TARGET_BYTES,
+        })
+    }
+
+    /// Split spill files larger than `bytes` instead of [`PARTITION_TARGET_BYTES`]
+    pub fn with_partition_bytes(mut self, bytes: u64) -> Self {
+        self.partition_bytes = bytes.max(1);
+        self
+    }
+
+    /// Spill a batch from the input with the given order (later inputs win ties)
+    pub fn push(&mut self, batch: &RecordBatch, input_order: u32) -> Result<()> {
+        let mut columns = batch.columns().to_vec();
+        columns.push(Arc::new(UInt32Array::from(vec![input_order; batch.num_rows()])));
+        let spilled = RecordBatch::try_new(self.spill_schema.clone(), columns)?;
+
+        let (spill_dir, schema) = (&self.spill_dir, &self.spill_schema);
+        scatter(&spilled, 0, &mut self.writers, |partition| {
+            spill_writer(&spill_dir.join(format!("partition_{:04}.parquet", partition)), schema)
+        })
+    }
+
+    fn partition_path(&self, partition: usize) -> PathBuf {
+        self.spill_dir.join(format!("partition_{:04}.parquet", partition))
+    }
+
+    /// Emit the surviving rows partition by partition.
+    ///
+    /// Returns the number of duplicate rows dropped.
+    pub fn finish(mut self, mut emit: impl FnMut(RecordBatch) -> Result<()>) -> Result<u64> {
+        let mut dropped = 0u64;
+
+        for partition in 0..self.writers.len() {
+            let writer = match self.writers[partition].take() {
+                Some(writer) => writer,
+                None => continue,
+            };
+            writer.close()?;
+            dropped += self.drain(&self.partition_path(partition), 0, &mut emit)?;
+        }
+
+        Ok(dropped)
+    }
+
+    /// Emit the survivors of the spill file at `path`, a partition split
+    /// `level` times, splitting it again first if it is over the target.
+    /// Returns the number of duplicate rows dropped.
+    fn drain(&self, path: &Path, level: u32, emit: &mut impl FnMut(RecordBatch) -> Result<()>) -> Result<u64> {
+        let size = fs::metadata(path)?.len();
+        if size > self.partition_bytes {
+            if level < MAX_SPLIT_LEVELS {
+                let mut dropped = 0;
+                for part in self.split(path, level + 1, partitions_for_target(size, self.partition_bytes))? {
+                    dropped += self.drain(&part, level + 1, emit)?;
+                }
+                return Ok(dropped);
+            }
+            warn!(
+                "Spill partition {} is still {} after {} splits; loading it whole",
+                path.display(),
+                crate::utils::format_bytes(size),
+                level
+            );
+        }
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?.build()?;
+        let batches = reader.collect::<std::result::Result<Vec<_>, _>>()?;
+        let batch = concat_batches(&self.spill_schema, &batches)?;
+        fs::remove_file(path)?;
+
+        let survivors = survivors(&batch)?;
+        let dropped = (batch.num_rows() - survivors.len()) as u64;
+
+        let indices = UInt32Array::from(survivors);
+        let columns = batch.columns()[..self.schema.fields().len()].iter()
+            .map(|c| Ok(take(c.as_ref(), &indices, None)?))
+            .collect::<Result<Vec<ArrayRef>>>()?;
+        emit(RecordBatch::try_new(self.schema.clone(), columns)?)?;
+
+        Ok(dropped)
+    }
+
+    /// Split the spill file at `path` into `partitions` files by the hash for
+    /// `level`, replacing it. Rows keep their order within each part, which
+    /// [`survivors`] relies on to break ties.
+    fn split(&self, path: &Path, level: u32, partitions: usize) -> Result<Vec<PathBuf>> {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+        let part_path = |partition: usize| self.spill_dir.join(format!("{}_{:04}.parquet", stem, partition));
+
+        let mut writers: Vec<Option<ArrowWriter<File>>> = (0..partitions).map(|_| None).collect();
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?.build()?;
+        for batch in reader {
+            scatter(&batch?, level, &mut writers, |partition| spill_writer(&part_path(partition), &self.spill_schema))?;
+        }
+        fs::remove_file(path)?;
+
+        let mut parts = Vec::new();
+        for (partition, writer) in writers.into_iter().enumerate() {
+            if let Some(writer) = writer {
+                writer.close()?;
+                parts.push(part_path(partition));
+            }
+        }
+        Ok(parts)
+    }
+}
+
+/// Create a spill file at `path`
+fn spill_writer(path: &Path, schema: &SchemaRef) -> Result<ArrowWriter<File>> {
+    let file = File::create(path)
+        .with_context(|| format!("Failed to create spill file {}", path.display()))?;
+    let mut writer = ArrowWriter::try_new(file, schema.clone(), None)?;
+    writer.append_key_value_metadata(schema_version_entry());
+    Ok(writer)
+}
+
+/// Write each row of a spilled batch to the writer of its partition by the
+/// hash for `level`, creating writers with `create` as partitions fill
+fn scatter(
+    batch: &RecordBatch,
+    level: u32,
+    writers: &mut [Option<ArrowWriter<File>>],
+    create: impl Fn(usize) -> Result<ArrowWriter<File>>,
+) -> Result<()> {
+    let paths = string_column(batch, "path")?;
+    let partitions = writers.len();
+
+    let mut rows: Vec<Vec<u32>> = vec![Vec::new(); partitions];
+    for i in 0..batch.num_rows() {
+        rows[partition_of(paths.value(i), partitions, level)].push(i as u32);
+    }
+
+    for (partition, indices) in rows.into_iter().enumerate() {
+        if indices.is_empty() {
+            continue;
+        }
+
+        let indices = UInt32Array::from(indices);
+        let columns = batch.columns().iter()
+            .map(|c| Ok(take(c.as_ref(), &indices, None)?))
+            .collect::<Result<Vec<ArrayRef>>>()?;
+        let part = RecordBatch::try_new(batch.schema(), columns)?;
+
+        if writers[partition].is_none() {
+            writers[partition] = Some(create(partition)?);
+        }
+        writers[partition].as_mut().unwrap().write(&part)?;
+    }
+
+    Ok(())
+}
+
+impl Drop for PathDeduper {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.spill_dir) {
+            warn!("Failed to remove spill directory {}: {}", self.spill_dir.display(), e);
+        }
+    }
+}
+
+/// Row indices of the newest row per path, in original row order
+fn survivors(batch: &RecordBatch) -> Result<Vec<u32>> {
+    let paths = string_column(batch, "path")?;
+    let mtimes = cast(batch.column_by_name("modified_time").unwrap(), &DataType::Int64)?;
+    let mtimes = mtimes.as_any().downcast_ref::<Int64Array>().unwrap();
+    let orders = batch.column_by_name(ORDER_COLUMN).unwrap();
+    let orders = orders.as_any().downcast_ref::<UInt32Array>().unwrap();
+
+    // Rows are in spill order, so among equal keys the later row is newer
+    let key = |i: usize| (mtimes.is_valid(i).then(|| mtimes.value(i)), orders.value(i));
+
+    let mut best: HashMap<&str, usize> = HashMap::new();
+    for i in 0..batch.num_rows() {
+        match best.entry(paths.value(i)) {
+            Entry::Vacant(e) => {
+                e.insert(i);
+            }
+            Entry::Occupied(mut e) => {
+                if key(i) >= key(*e.get()) {
+                    e.insert(i);
+                }
+            }
+        }
+    }
+
+    let mut rows: Vec<u32> = best.into_values().map(|i| i as u32).collect();
+    rows.sort_unstable();
+    Ok(rows)
+}
+
+fn string_column<'a>(batch: &'a RecordBatch, name: &str) -> Result<&'a StringArray> {
+    batch.column_by_name(name)
+        .and_then(|c| c.as_any().downcast_ref::<StringArray>())
+        .ok_or_else(|| anyhow::anyhow!("Column '{}' must be a string column", name))
+}
+
+/// Partition of a path among `partitions`; each split `level` hashes
+/// differently, so the rows of one partition spread over the next level's
+fn partition_of(path: &str, partitions: usize, level: u32) -> usize {
+    let mut hasher = DefaultHasher::new();
+    level.hash(&mut hasher);
+    path.hash(&mut hasher);
+    (hasher.finish() % partitions as u64) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// Rows of (path, modified_time, tag); the tag identifies the surviving row
+    fn batch(rows: &[(&str, i64, &str)]) -> RecordBatch {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("path", DataType::Utf8, false),
+            Field::new("modified_time", DataType::Int64, false),
+            Field::new("tag", DataType::Utf8, false),
+        ]));
+        RecordBatch::try_new(schema, vec![
+            Arc::new(StringArray::from(rows.iter().map(|r| r.0).collect::<Vec<_>>())),
+            Arc::new(Int64Array::from(rows.iter().map(|r| r.1).collect::<Vec<_>>())),
+            Arc::new(StringArray::from(rows.iter().map(|r| r.2).collect::<Vec<_>>())),
+        ]).unwrap()
+    }
+
+    /// Dedupe the inputs; returns path -> surviving tag and the dropped count
+    fn dedupe(inputs: &[RecordBatch], partitions: usize) -> (HashMap<String, String>, u64) {
+        let (rows, dropped, _) = dedupe_with(inputs, partitions, PARTITION_TARGET_BYTES);
+        (rows, dropped)
+    }
+
+    /// [`dedupe`] splitting spill files over `partition_bytes`; also returns
+    /// the rows of the largest batch emitted
+    fn dedupe_with(inputs: &[RecordBatch], partitions: usize, partition_bytes: u64) -> (HashMap<String, String>, u64, usize) {
+        let temp_dir = TempDir::new().unwrap();
+        let spill_dir = temp_dir.path().join("spill");
+        let mut deduper = PathDeduper::new(&spill_dir, inputs[0].schema(), partitions).unwrap()
+            .with_partition_bytes(partition_bytes);
+        for (order, input) in inputs.iter().enumerate() {
+            deduper.push(input, order as u32).unwrap();
+        }
+
+        let mut rows = HashMap::new();
+        let mut largest = 0;
+        let dropped = deduper.finish(|b| {
+            largest = largest.max(b.num_rows());
+            assert_eq!(b.schema(), inputs[0].schema());
+            let paths = string_column(&b, "path").unwrap();
+            let tags = string_column(&b, "tag").unwrap();
+            for i in 0..b.num_rows() {
+                let previous = rows.insert(paths.value(i).to_string(), tags.value(i).to_string());
+                assert!(previous.is_none(), "{} emitted twice", paths.value(i));
+            }
+            Ok(())
+        }).unwrap();
+
+        assert!(!spill_dir.exists());
+        (rows, dropped, largest)
+    }
+
+    #[test]
+    fn test_newest_mtime_wins() {
+        let first = batch(&[("/a", 10, "old"), ("/b", 30, "new"), ("/c", 5, "only")]);
+        let second = batch(&[("/a", 20, "new"), ("/b", 25, "old"), ("/d", 1, "only")]);
+
+        for partitions in [1, 4] {
+            let (rows, dropped) = dedupe(&[first.clone(), second.clone()], partitions);
+            assert_eq!(dropped, 2);
+            assert_eq!(rows.len(), 4);
+            assert_eq!(rows["/a"], "new");
+            assert_eq!(rows["/b"], "new");
+        }
+    }
+
+    #[test]
+    fn test_tie_goes_to_later_input() {
+        let first = batch(&[("/a", 10, "first")]);
+        let second = batch(&[("/a", 10, "second")]);
+
+        let (rows, dropped) = dedupe(&[first, second], 2);
+        assert_eq!(dropped, 1);
+        assert_eq!(rows["/a"], "second");
+    }
+
+    #[test]
+    fn test_oversize_partition_is_split_again() {
+        let paths: Vec<String> = (0..20_000).map(|i| format!("/data/dir{}/file{}", i % 50, i)).collect();
+        let rows = |mtime: i64, tag: &'static str| -> RecordBatch {
+            batch(&paths.iter().map(|p| (p.as_str(), mtime, tag)).collect::<Vec<_>>())
+        };
+
+        // One partition for all 40,000 rows, with a target a fraction of its size
+        let (rows, dropped, largest) = dedupe_with(&[rows(10, "old"), rows(20, "new")], 1, 32 * 1024);
+        assert_eq!(dropped, 20_000);
+        assert_eq!(rows.len(), 20_000);
+        assert!(rows.values().all(|tag| tag == "new"));
+        assert!(largest < 20_000, "partition was loaded whole: {} rows", largest);
+
+        // Copies of one path can't be split apart, and are loaded whole in the end
+        let same: Vec<(&str, i64, &str)> = (0..5_000).map(|i| ("/same", i, "old")).chain([("/same", 5_000, "new")]).collect();
+        let (rows, dropped, _) = dedupe_with(&[batch(&same)], 1, 1024);
+        assert_eq!(dropped, 5_000);
+        assert_eq!(rows["/same"], "new");
+    }
+
+    #[test]
+    fn test_requires_path_column() {
+        let temp_dir = TempDir::new().unwrap();
+        let schema = Arc::new(Schema::new(vec![Field::new("size", DataType::UInt64, false)]));
+        assert!(PathDeduper::new(&temp_dir.path().join("spill"), schema, 1).is_err());
+    }
+
+    #[test]
+    fn test_partitions_for() {
+        assert_eq!(partitions_for(0), 1);
+        assert_eq!(partitions_for(PARTITION_TARGET_BYTES * 3), 4);
+        assert_eq!(partitions_for(u64::MAX), MAX_PARTITIONS);
+    }
+}
@@ -0,0 +1,639 @@
+use crate::filter::RowFilter;
+use crate::report::read_columns;
+use crate::sort::ExternalSorter;
+use crate::writer::schema_version_entry;
+use anyhow::{Context, Result};
+use arrow::array::{
+    Array, ArrayRef, Int64Array, Int64Builder, StringArray, StringBuilder, UInt64Array, UInt64Builder,
+};
+use arrow::compute::{cast, filter_record_batch, is_not_null};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ArrowWriter;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::{info, warn};
+
+/// Columns compared by [`diff_scans`]
+const COLUMNS: [&str; 4] = ["path", "size", "modified_time", "file_type"];
+
+/// `file_type` of directory entries
+const DIRECTORY_TYPE: &str = "directory";
+
+/// Rows per batch of the detail output
+const OUTPUT_BATCH_ROWS: usize = 65_536;
+
+/// How an entry differs between the old and the new scan
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeType {
+    /// Only in the new scan
+    Added,
+
+    /// Only in the old scan
+    Removed,
+
+    /// In both, with a different size or modified_time
+    Modified,
+}
+
+impl ChangeType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChangeType::Added => "added",
+            ChangeType::Removed => "removed",
+            ChangeType::Modified => "modified",
+        }
+    }
+}
+
+/// What [`diff_scans`] compares and where it writes
+#[derive(Debug, Clone)]
+pub struct DiffOptions {
+    /// Only compare this path and the entries below it
+    pub under: Option<String>,
+
+    /// Write one row per changed entry here, in the [`diff_schema`] layout
+    pub output: Option<PathBuf>,
+
+    /// Memory budget for sorting an input that is not sorted by path
+    pub sort_memory: u64,
+
+    /// Scratch directory for sorting; must not exist, and is removed afterwards
+    pub spill_dir: PathBuf,
+}
+
+/// Counts and bytes of each kind of change.
+///
+/// Directories count as entries but add nothing to bytes, as in the scan summary.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct DiffSummary {
+    pub old_rows: u64,
+
+    pub new_rows: u64,
+
+    pub unchanged: u64,
+
+    pub added: u64,
+
+    /// Size of the added files
+    pub added_bytes: u64,
+
+    pub removed: u64,
+
+    /// Size of the removed files, as of the old scan
+    pub removed_bytes: u64,
+
+    pub modified: u64,
+
+    /// Modified entries that got larger
+    pub grew: u64,
+
+    /// Combined growth of the entries that got larger
+    pub grown_bytes: u64,
+
+    /// Modified entries that got smaller
+    pub shrank: u64,
+
+    /// Combined shrinkage of the entries that got smaller
+    pub shrunk_bytes: u64,
+
+    /// Change in total file bytes from the old scan to the new one
+    pub net_bytes: i64,
+
+    /// Whether the old input had to be sorted by path first
+    pub old_sorted: bool,
+
+    /// Whether the new input had to be sorted by path first
+    pub new_sorted: bool,
+}
+
+/// Layout of the `--output` file: one row per changed entry
+pub fn diff_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("change_type", DataType::Utf8, false),
+        Field::new("path", DataType::Utf8, false),
+        Field::new("file_type", DataType::Utf8, true),
+        Field::new("old_size", DataType::UInt64, true),
+        Field::new("new_size", DataType::UInt64, true),
+        Field::new("size_delta", DataType::Int64, false),
+        Field::new("old_modified_time", DataType::Int64, true),
+        Field::new("new_modified_time", DataType::Int64, true),
+    ]))
+}
+
+/// Normalized batches of one side of the comparison
+type Batches<'a> = Box<dyn Iterator<Item = Result<RecordBatch>> + 'a>;
+
+/// Schema batches are normalized to before sorting and comparing
+fn input_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("path", DataType::Utf8, false),
+        Field::new("size", DataType::UInt64, true),
+        Field::new("modified_time", DataType::Int64, true),
+        Field::new("file_type", DataType::Utf8, true),
+    ]))
+}
+
+fn validate(schema: &Schema) -> Result<()> {
+    for column in COLUMNS {
+        schema.field_with_name(column)
+            .with_context(|| format!("Comparing scans requires a '{}' column", column))?;
+    }
+    Ok(())
+}
+
+/// Cast the compared columns to [`input_schema`], dropping rows without a path
+fn normalize(batch: &RecordBatch) -> Result<RecordBatch> {
+    let schema = input_schema();
+    let columns = schema.fields().iter()
+        .map(|field| {
+            let column = batch.column_by_name(field.name())
+                .with_context(|| format!("Comparing scans requires a '{}' column", field.name()))?;
+            Ok(cast(column, field.data_type())?)
+        })
+        .collect::<Result<Vec<ArrayRef>>>()?;
+    let keep = is_not_null(&columns[0])?;
+    let batch = RecordBatch::try_new(schema, columns)?;
+    Ok(filter_record_batch(&batch, &keep)?)
+}
+
+/// Normalized batches of `files`, in order, restricted by `filter`
+fn read_side<'a>(files: &'a [PathBuf], filter: &'a RowFilter) -> impl Iterator<Item = Result<RecordBatch>> + 'a {
+    files.iter()
+        .map(move |path| {
+            let reader = read_columns(path, &COLUMNS, validate)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            Ok(reader.map(move |batch| {
+                let batch = batch.with_context(|| format!("Failed to read {}", path.display()))?;
+                normalize(&filter.apply(&batch)?)
+            }))
+        })
+        .flat_map(|reader: Result<_>| -> Batches<'a> {
+            match reader {
+                Ok(reader) => Box::new(reader),
+                Err(e) => Box::new(std::iter::once(Err(e))),
+            }
+        })
+}
+
+/// Whether the rows of `files`, read in order, are sorted by path
+fn is_sorted_by_path(files: &[PathBuf]) -> Result<bool> {
+    let mut last: Option<String> = None;
+    for path in files {
+        let reader = read_columns(path, &["path"], validate)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        for batch in reader {
+            let paths = cast(batch?.column(0), &DataType::Utf8)?;
+            let paths = paths.as_any().downcast_ref::<StringArray>().unwrap();
+            for p in paths.iter().flatten() {
+                if last.as_deref().is_some_and(|l| l > p) {
+                    return Ok(false);
+                }
+                last = Some(p.to_string());
+            }
+        }
+    }
+    Ok(true)
+}
+
+/// A stream of normalized, path-sorted batches read one row at a time
+struct Cursor<'a> {
+    batches: Batches<'a>,
+    paths: StringArray,
+    sizes: UInt64Array,
+    mtimes: Int64Array,
+    types: StringArray,
+    row: usize,
+    rows: u64,
+}
+
+/// One entry of a scan
+struct Entry<'a> {
+    path: &'a str,
+    size: Option<u64>,
+    mtime: Option<i64>,
+    file_type: Option<&'a str>,
+}
+
+impl Entry<'_> {
+    /// Size counted toward bytes: directories count as 0
+    fn bytes(&self) -> u64 {
+        if self.file_type == Some(DIRECTORY_TYPE) { 0 } else { self.size.unwrap_or(0) }
+    }
+}
+
+impl<'a> Cursor<'a> {
+    fn new(batches: Batches<'a>) -> Result<Self> {
+        let mut cursor = Self {
+            batches,
+            paths: StringArray::from(Vec::<&str>::new()),
+            sizes: UInt64Array::from(Vec::<u64>::new()),
+            mtimes: Int64Array::from(Vec::<i64>::new()),
+            types: StringArray::from(Vec::<&str>::new()),
+            row: 0,
+            rows: 0,
+        };
+        cursor.load()?;
+        Ok(cursor)
+    }
+
+    /// Move to the next non-empty batch, if any
+    fn load(&mut self) -> Result<()> {
+        self.row = 0;
+        self.paths = StringArray::from(Vec::<&str>::new());
+        while self.paths.is_empty() {
+            let Some(batch) = self.batches.next().transpose()? else {
+                return Ok(());
+            };
+            let column = |i: usize| batch.column(i).clone();
+            self.paths = column(0).as_any().downcast_ref::<StringArray>().unwrap().clone();
+            self.sizes = column(1).as_any().downcast_ref::<UInt64Array>().unwrap().clone();
+            self.mtimes = column(2).as_any().downcast_ref::<Int64Array>().unwrap().clone();
+            self.types = column(3).as_any().downcast_ref::<StringArray>().unwrap().clone();
+        }
+        Ok(())
+    }
+
+    fn current(&self) -> Option<Entry<'_>> {
+        let i = self.row;
+        (i < self.paths.len()).then(|| Entry {
+            path: self.paths.value(i),
+            size: self.sizes.is_valid(i).then(|| self.sizes.value(i)),
+            mtime: self.mtimes.is_valid(i).then(|| self.mtimes.value(i)),
+            file_type: self.types.is_valid(i).then(|| self.types.value(i)),
+        })
+    }
+
+    fn advance(&mut self) -> Result<()> {
+        self.rows += 1;
+        self.row += 1;
+        if self.row >= self.paths.len() {
+            self.load()?;
+        }
+        Ok(())
+    }
+}
+
+/// Buffers changed entries and writes them to the `--output` file
+struct DetailWriter {
+    writer: ArrowWriter<File>,
+    rows: usize,
+    change_type: StringBuilder,
+    path: StringBuilder,
+    file_type: StringBuilder,
+    old_size: UInt64Builder,
+    new_size: UInt64Builder,
+    size_delta: Int64Builder,
+    old_mtime: Int64Builder,
+    new_mtime: Int64Builder,
+}
+
+impl DetailWriter {
+    fn new(path: &Path) -> Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        let mut writer = ArrowWriter::try_new(file, diff_schema(), None)?;
+        writer.append_key_value_metadata(schema_version_entry());
+        Ok(Self {
+            writer,
+            rows: 0,
+            change_type: StringBuilder::new(),
+            path: StringBuilder::new(),
+            file_type: StringBuilder::new(),
+            old_size: UInt64Builder::new(),
+            new_size: UInt64Builder::new(),
+            size_delta: Int64Builder::new(),
+            old_mtime: Int64Builder::new(),
+            new_mtime: Int64Builder::new(),
+        })
+    }
+
+    fn push(&mut self, change: ChangeType, old: Option<&Entry>, new: Option<&Entry>) -> Result<()> {
+        let entry = new.or(old).expect("a change has at least one side");
+        let old_size = old.and_then(|e| e.size);
+        let new_size = new.and_then(|e| e.size);
+
+        self.change_type.append_value(change.as_str());
+        self.path.append_value(entry.path);
+        self.file_type.append_option(entry.file_type);
+        self.old_size.append_option(old_size);
+        self.new_size.append_option(new_size);
+        self.size_delta.append_value(new_size.unwrap_or(0) as i64 - old_size.unwrap_or(0) as i64);
+        self.old_mtime.append_option(old.and_then(|e| e.mtime));
+        self.new_mtime.append_option(new.and_then(|e| e.mtime));
+
+        self.rows += 1;
+        if self.rows >= OUTPUT_BATCH_ROWS {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if self.rows == 0 {
+            return Ok(());
+        }
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(self.change_type.finish()),
+            Arc::new(self.path.finish()),
+            Arc::new(self.file_type.finish()),
+            Arc::new(self.old_size.finish()),
+            Arc::new(self.new_size.finish()),
+            Arc::new(self.size_delta.finish()),
+            Arc::new(self.old_mtime.finish()),
+            Arc::new(self.new_mtime.finish()),
+        ];
+        self.writer.write(&RecordBatch::try_new(diff_schema(), columns)?)?;
+        self.rows = 0;
+        Ok(())
+    }
+
+    fn close(mut self) -> Result<()> {
+        self.flush()?;
+        self.writer.close()?;
+        Ok(())
+    }
+}
+
+/// Removes the scratch directory when dropped
+struct SpillDir(PathBuf);
+
+impl Drop for SpillDir {
+    fn drop(&mut self) {
+        if self.0.exists() {
+            if let Err(e) = fs::remove_dir_all(&self.0) {
+                warn!("Failed to remove spill directory {}: {}", self.0.display(), e);
+            }
+        }
+    }
+}
+
+/// Batches of one side in path order: read as is if already sorted, otherwise
+/// externally sorted into a file under `spill_dir` first
+fn sorted_side<'a>(
+    name: &str,
+    files: &'a [PathBuf],
+    filter: &'a RowFilter,
+    options: &DiffOptions,
+    spill: &SpillDir,
+) -> Result<(Batches<'a>, bool)> {
+    if is_sorted_by_path(files)? {
+        return Ok((Box::new(read_side(files, filter)), false));
+    }
+
+    info!("Sorting the {} scan by path", name);
+    if !spill.0.exists() {
+        fs::create_dir(&spill.0)
+            .with_context(|| format!("Failed to create spill directory {}", spill.0.display()))?;
+    }
+    let mut sorter = ExternalSorter::new(
+        &spill.0.join(format!("{}_runs", name)),
+        input_schema(),
+        &["path".to_string()],
+        options.sort_memory,
+    )?;
+    for batch in read_side(files, filter) {
+        sorter.push(batch?)?;
+    }
+
+    let sorted_path = spill.0.join(format!("{}_sorted.parquet", name));
+    let mut writer = ArrowWriter::try_new(File::create(&sorted_path)?, input_schema(), None)?;
+    writer.append_key_value_metadata(schema_version_entry());
+    sorter.finish(|batch| Ok(writer.write(&batch)?))?;
+    writer.close()?;
+
+    let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(&sorted_path)?)?.build()?;
+    Ok((Box::new(reader.map(|batch| Ok(batch?))), true))
+}
+
+/// Compare two scans entry by entry, matching rows on `path`.
+///
+/// Both sides are streamed in path order and merge-joined, so memory stays
+/// bounded by the batch size and the sort budget, whatever the number of rows.
+/// An input not already sorted by path is externally sorted first. Each path
+/// should appear once per scan; aggregate with `--dedupe-by path` otherwise.
+pub fn diff_scans(old: &[PathBuf], new: &[PathBuf], options: &DiffOptions) -> Result<DiffSummary> {
+    if options.spill_dir.exists() {
+        anyhow::bail!("Spill directory {} already exists", options.spill_dir.display());
+    }
+    let spill = SpillDir(options.spill_dir.clone());
+    let filter = RowFilter { path_prefix: options.under.clone(), ..Default::default() };
+
+    let mut summary = DiffSummary::default();
+    let (old_batches, old_sorted) = sorted_side("old", old, &filter, options, &spill)?;
+    let (new_batches, new_sorted) = sorted_side("new", new, &filter, options, &spill)?;
+    summary.old_sorted = old_sorted;
+    summary.new_sorted = new_sorted;
+
+    let mut old = Cursor::new(old_batches)?;
+    let mut new = Cursor::new(new_batches)?;
+    let mut detail = options.output.as_deref().map(DetailWriter::new).transpose()?;
+
+    loop {
+        let (advance_old, advance_new) = match (old.current(), new.current()) {
+            (None, None) => break,
+            (o, n) => {
+                let order = match (&o, &n) {
+                    (Some(o), Some(n)) => o.path.cmp(n.path),
+                    (Some(_), None) => Ordering::Less,
+                    _ => Ordering::Greater,
+                };
+                match order {
+                    Ordering::Less => {
+                        let o = o.unwrap();
+                        summary.removed += 1;
+                        summary.removed_bytes += o.bytes();
+                        summary.net_bytes -= o.bytes() as i64;
+                        if let Some(ref mut detail) = detail {
+                            detail.push(ChangeType::Removed, Some(&o), None)?;
+                        }
+                        (true, false)
+                    }
+                    Ordering::Greater => {
+                        let n = n.unwrap();
+                        summary.added += 1;
+                        summary.added_bytes += n.bytes();
+                        summary.net_bytes += n.bytes() as i64;
+                        if let Some(ref mut detail) = detail {
+                            detail.push(ChangeType::Added, None, Some(&n))?;
+                        }
+                        (false, true)
+                    }
+                    Ordering::Equal => {
+                        let (o, n) = (o.unwrap(), n.unwrap());
+                        if o.size == n.size && o.mtime == n.mtime {
+                            summary.unchanged += 1;
+                        } else {
+                            summary.modified += 1;
+                            match n.bytes().cmp(&o.bytes()) {
+                                Ordering::Greater => {
+                                    summary.grew += 1;
+                                    summary.grown_bytes += n.bytes() - o.bytes();
+                                }
+                                Ordering::Less => {
+                                    summary.shrank += 1;
+                                    summary.shrunk_bytes += o.bytes() - n.bytes();
+                                }
+                                Ordering::Equal => {}
+                            }
+                            summary.net_bytes += n.bytes() as i64 - o.bytes() as i64;
+                            if let Some(ref mut detail) = detail {
+                                detail.push(ChangeType::Modified, Some(&o), Some(&n))?;
+                            }
+                        }
+                        (true, true)
+                    }
+                }
+            }
+        };
+        if advance_old {
+            old.advance()?;
+        }
+        if advance_new {
+            new.advance()?;
+        }
+    }
+
+    if let Some(detail) = detail {
+        detail.close()?;
+    }
+    summary.old_rows = old.rows;
+    summary.new_rows = new.rows;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::FileEntry;
+    use crate::writer::ParquetFileWriter;
+    use tempfile::TempDir;
+
+    fn entry(path: &str, size: u64, modified_time: i64) -> FileEntry {
+        let entry = if size == 0 { FileEntry::dir_fixture(path) } else { FileEntry::fixture(path) };
+        FileEntry { size, modified_time, accessed_time: modified_time, ..entry }
+    }
+
+    fn write_scan(path: &Path, entries: &[FileEntry]) -> Vec<PathBuf> {
+        let mut writer = ParquetFileWriter::new(path).unwrap();
+        writer.write_batch(entries).unwrap();
+        writer.close().unwrap();
+        vec![path.to_path_buf()]
+    }
+
+    fn options(dir: &Path, output: Option<PathBuf>) -> DiffOptions {
+        DiffOptions {
+            under: None,
+            output,
+            sort_memory: 1,
+            spill_dir: dir.join("spill"),
+        }
+    }
+
+    /// Old and new scans, neither sorted by path
+    fn fixture(dir: &Path) -> (Vec<PathBuf>, Vec<PathBuf>) {
+        let old = write_scan(&dir.join("old.parquet"), &[
+            entry("/p/same.dat", 100, 10),
+            entry("/p/gone.dat", 400, 10),
+            entry("/p", 0, 10),
+            entry("/p/grows.dat", 100, 10),
+            entry("/p/shrinks.dat", 1000, 10),
+            entry("/p/touched.dat", 50, 10),
+            entry("/q/gone.dat", 7, 10),
+        ]);
+        let new = write_scan(&dir.join("new.parquet"), &[
+            entry("/p/touched.dat", 50, 20),
+            entry("/p/shrinks.dat", 300, 20),
+            entry("/p/new.dat", 250, 20),
+            entry("/p", 0, 10),
+            entry("/p/same.dat", 100, 10),
+            entry("/p/grows.dat", 160, 20),
+        ]);
+        (old, new)
+    }
+
+    #[test]
+    fn test_every_change_category() {
+        let temp_dir = TempDir::new().unwrap();
+        let (old, new) = fixture(temp_dir.path());
+        let output = temp_dir.path().join("diff.parquet");
+
+        let summary = diff_scans(&old, &new, &options(temp_dir.path(), Some(output.clone()))).unwrap();
+        assert_eq!(summary, DiffSummary {
+            old_rows: 7,
+            new_rows: 6,
+            unchanged: 2,
+            added: 1,
+            added_bytes: 250,
+            removed: 2,
+            removed_bytes: 407,
+            modified: 3,
+            grew: 1,
+            grown_bytes: 60,
+            shrank: 1,
+            shrunk_bytes: 700,
+            net_bytes: 250 - 407 + 60 - 700,
+            old_sorted: true,
+            new_sorted: true,
+        });
+        assert!(!temp_dir.path().join("spill").exists());
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(&output).unwrap()).unwrap().build().unwrap();
+        let mut rows = Vec::new();
+        for batch in reader {
+            let batch = batch.unwrap();
+            let column = |name: &str| batch.column_by_name(name).unwrap().clone();
+            let changes = column("change_type");
+            let changes = changes.as_any().downcast_ref::<StringArray>().unwrap();
+            let paths = column("path");
+            let paths = paths.as_any().downcast_ref::<StringArray>().unwrap();
+            let deltas = column("size_delta");
+            let deltas = deltas.as_any().downcast_ref::<Int64Array>().unwrap();
+            for i in 0..batch.num_rows() {
+                rows.push((changes.value(i).to_string(), paths.value(i).to_string(), deltas.value(i)));
+            }
+        }
+        let rows: Vec<(&str, &str, i64)> = rows.iter().map(|r| (r.0.as_str(), r.1.as_str(), r.2)).collect();
+        assert_eq!(rows, [
+            ("removed", "/p/gone.dat", -400),
+            ("modified", "/p/grows.dat", 60),
+            ("added", "/p/new.dat", 250),
+            ("modified", "/p/shrinks.dat", -700),
+            ("modified", "/p/touched.dat", 0),
+            ("removed", "/q/gone.dat", -7),
+        ]);
+    }
+
+    #[test]
+    fn test_under_and_presorted_input() {
+        let temp_dir = TempDir::new().unwrap();
+        let (old, _) = fixture(temp_dir.path());
+        let new = write_scan(&temp_dir.path().join("sorted.parquet"), &[
+            entry("/p/grows.dat", 100, 10),
+            entry("/p/same.dat", 100, 10),
+            entry("/q/gone.dat", 7, 10),
+        ]);
+
+        let options = DiffOptions { under: Some("/p".to_string()), ..options(temp_dir.path(), None) };
+        let summary = diff_scans(&old, &new, &options).unwrap();
+        assert!(summary.old_sorted);
+        assert!(!summary.new_sorted);
+        assert_eq!((summary.old_rows, summary.new_rows), (6, 2));
+        assert_eq!((summary.added, summary.removed, summary.modified, summary.unchanged), (0, 4, 0, 2));
+    }
+
+    #[test]
+    fn test_identical_scans() {
+        let temp_dir = TempDir::new().unwrap();
+        let (old, _) = fixture(temp_dir.path());
+
+        let summary = diff_scans(&old, &old, &options(temp_dir.path(), None)).unwrap();
+        assert_eq!(summary.unchanged, 7);
+        assert_eq!(summary.added + summary.removed + summary.modified, 0);
+        assert_eq!(summary.net_bytes, 0);
+    }
+}
@@ -0,0 +1,297 @@
+//! Per-top-level-directory progress display.
+//!
+//! The scan reports each counted entry as a [`ProgressEvent`]. A
+//! [`DirProgress`] turns those into one line per top-level directory, and
+//! `ScanDisplay`, with the `progress` feature, draws the lines as bars under the
+//! scan's spinner. The walk yields entries depth first, so once an entry
+//! outside a directory arrives, that directory is done.
+
+use std::collections::HashMap;
+#[cfg(feature = "progress")]
+use {
+    crate::progress::{Baseline, Progress},
+    crate::utils,
+    indicatif::{MultiProgress, ProgressBar, ProgressStyle},
+    std::io::IsTerminal,
+    std::time::Duration,
+};
+
+/// Most top-level directories shown a line each; above this the scan shows
+/// its single spinner only
+pub const MAX_DIR_LINES: usize = 30;
+
+/// What a scan reports to its progress display
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressEvent<'a> {
+    /// An entry below the root was counted
+    Entry { top_level_dir: &'a str, bytes: u64 },
+
+    /// The walk is over
+    Finished,
+}
+
+/// Where a top-level directory's walk is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DirState {
+    Waiting,
+    Scanning,
+    Done,
+}
+
+/// The progress line of one top-level directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirLine {
+    pub name: String,
+    pub state: DirState,
+    pub entries: u64,
+    pub bytes: u64,
+}
+
+/// Lines an event changed
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Changed {
+    /// Line the entry was counted on
+    pub advanced: Option<usize>,
+
+    /// Lines whose directory is now done
+    pub completed: Vec<usize>,
+}
+
+/// Progress of each top-level directory, driven by [`ProgressEvent`]s
+#[derive(Debug, Clone)]
+pub struct DirProgress {
+    lines: Vec<DirLine>,
+    index: HashMap<String, usize>,
+    current: Option<usize>,
+}
+
+impl DirProgress {
+    /// Track `dirs`, in the order their lines are shown
+    pub fn new(dirs: Vec<String>) -> Self {
+        let index = dirs.iter().enumerate().map(|(i, dir)| (dir.clone(), i)).collect();
+        let lines = dirs.into_iter()
+            .map(|name| DirLine { name, state: DirState::Waiting, entries: 0, bytes: 0 })
+            .collect();
+        Self { lines, index, current: None }
+    }
+
+    pub fn lines(&self) -> &[DirLine] {
+        &self.lines
+    }
+
+    pub fn apply(&mut self, event: ProgressEvent) -> Changed {
+        let mut changed = Changed::default();
+        match event {
+            ProgressEvent::Entry { top_level_dir, bytes } => {
+                let line = self.index.get(top_level_dir).copied();
+                if line != self.current {
+                    changed.completed.extend(self.complete_current());
+                }
+                // Files at the root, or directories that appeared since the
+                // root was listed, have no line
+                let Some(line) = line else { return changed };
+                let dir = &mut self.lines[line];
+                dir.state = DirState::Scanning;
+                dir.entries += 1;
+                dir.bytes += bytes;
+                self.current = Some(line);
+                changed.advanced = Some(line);
+            }
+            ProgressEvent::Finished => {
+                self.current = None;
+                for (i, dir) in self.lines.iter_mut().enumerate() {
+                    if dir.state != DirState::Done {
+                        dir.state = DirState::Done;
+                        changed.completed.push(i);
+                    }
+                }
+            }
+        }
+        changed
+    }
+
+    fn complete_current(&mut self) -> Option<usize> {
+        let line = self.current.take()?;
+        self.lines[line].state = DirState::Done;
+        Some(line)
+    }
+}
+
+/// The terminal display of a running scan: a spinner with the totals and,
+/// when stderr is a terminal and there are few enough top-level directories,
+/// a line per directory under it
+#[cfg(feature = "progress")]
+pub(crate) struct ScanDisplay {
+    spinner: ProgressBar,
+    dirs: Option<DirBars>,
+}
+
+#[cfg(feature = "progress")]
+struct DirBars {
+    // Kept so the bars stay drawn together
+    _multi: MultiProgress,
+    bars: Vec<ProgressBar>,
+    progress: DirProgress,
+}
+
+#[cfg(feature = "progress")]
+impl ScanDisplay {
+    /// Display a scan of the top-level directories that `list_dirs` returns,
+    /// called only when stderr is a terminal. Bars run to each directory's
+    /// entries in `baseline`, where it has them.
+    pub(crate) fn new(list_dirs: impl FnOnce() -> Vec<String>, baseline: Option<&Baseline>) -> Self {
+        // Keep the elapsed time moving while the walk waits on a slow directory
+        let spinner = spinner();
+        spinner.enable_steady_tick(Duration::from_millis(100));
+        let dirs = if std::io::stderr().is_terminal() { list_dirs() } else { Vec::new() };
+        if dirs.is_empty() || dirs.len() > MAX_DIR_LINES {
+            return Self { spinner, dirs: None };
+        }
+
+        let multi = MultiProgress::new();
+        let spinner = multi.add(spinner);
+        let width = dirs.iter().map(|d| d.chars().count()).max().unwrap_or(0);
+        let bars = dirs.iter()
+            .map(|dir| {
+                let total = baseline.and_then(|b| b.dir_rows.get(dir)).copied();
+                let bar = multi.add(ProgressBar::new(total.unwrap_or(0)));
+                bar.set_style(dir_style(total.is_some()));
+                bar.set_prefix(format!("{:<width$}", dir, width = width));
+                bar.set_message("(waiting)");
+                bar
+            })
+            .collect();
+        Self { spinner, dirs: Some(DirBars { _multi: multi, bars, progress: DirProgress::new(dirs) }) }
+    }
+
+}
+
+#[cfg(feature = "progress")]
+impl Progress for ScanDisplay {
+    /// Show `message` on the spinner with the scan's totals
+    fn set_message(&self, message: String) {
+        self.spinner.set_message(message);
+    }
+
+    fn elapsed(&self) -> Duration {
+        self.spinner.elapsed()
+    }
+
+    fn event(&mut self, event: ProgressEvent) {
+        let Some(dirs) = self.dirs.as_mut() else { return };
+        let changed = dirs.progress.apply(event);
+        for line in changed.completed {
+            let dir = &dirs.progress.lines()[line];
+            dirs.bars[line].set_style(done_style());
+            dirs.bars[line].finish_with_message(format!(
+                "{} entries, {}",
+                utils::format_number(dir.entries),
+                utils::format_bytes(dir.bytes),
+            ));
+        }
+        if let Some(line) = changed.advanced {
+            let bar = &dirs.bars[line];
+            if bar.position() == 0 {
+                bar.set_message("");
+            }
+            bar.inc(1);
+        }
+    }
+
+    /// Mark every directory done and leave the totals shown
+    fn finish(&mut self, message: &'static str) {
+        self.event(ProgressEvent::Finished);
+        self.spinner.finish_with_message(message);
+    }
+}
+
+/// The spinner of a scan or other long operation, on stderr
+#[cfg(feature = "progress")]
+pub(crate) fn spinner() -> ProgressBar {
+    let spinner = ProgressBar::new_spinner();
+    spinner.set_style(
+        ProgressStyle::default_spinner()
+            .template("{spinner:.green} [{elapsed_precise}] {msg}")
+            .unwrap()
+    );
+    spinner
+}
+
+#[cfg(feature = "progress")]
+fn dir_style(has_total: bool) -> ProgressStyle {
+    let template = if has_total {
+        "  {spinner:.cyan} {prefix} [{bar:30.cyan/blue}] {human_pos}/{human_len} {msg}"
+    } else {
+        "  {spinner:.cyan} {prefix} {human_pos} entries {msg}"
+    };
+    ProgressStyle::default_spinner().template(template).unwrap().progress_chars("=> ")
+}
+
+#[cfg(feature = "progress")]
+fn done_style() -> ProgressStyle {
+    ProgressStyle::default_spinner().template("  {prefix:.green} ✓ {msg}").unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(top_level_dir: &str) -> ProgressEvent<'_> {
+        ProgressEvent::Entry { top_level_dir, bytes: 10 }
+    }
+
+    fn states(progress: &DirProgress) -> Vec<DirState> {
+        progress.lines().iter().map(|line| line.state).collect()
+    }
+
+    #[test]
+    fn test_entries_advance_their_directory() {
+        let mut progress = DirProgress::new(vec!["home".to_string(), "projects".to_string()]);
+        assert_eq!(states(&progress), vec![DirState::Waiting, DirState::Waiting]);
+
+        assert_eq!(progress.apply(entry("home")), Changed { advanced: Some(0), completed: vec![] });
+        assert_eq!(progress.apply(entry("home")), Changed { advanced: Some(0), completed: vec![] });
+        assert_eq!(states(&progress), vec![DirState::Scanning, DirState::Waiting]);
+        assert_eq!(progress.lines()[0].entries, 2);
+        assert_eq!(progress.lines()[0].bytes, 20);
+    }
+
+    #[test]
+    fn test_leaving_a_directory_completes_it() {
+        let mut progress = DirProgress::new(vec!["a".to_string(), "b".to_string(), "c".to_string()]);
+        progress.apply(entry("a"));
+        assert_eq!(progress.apply(entry("b")), Changed { advanced: Some(1), completed: vec![0] });
+        assert_eq!(states(&progress), vec![DirState::Done, DirState::Scanning, DirState::Waiting]);
+
+        // A file at the root ends the directory before it but has no line
+        assert_eq!(progress.apply(entry("notes.txt")), Changed { advanced: None, completed: vec![1] });
+        assert_eq!(progress.apply(entry("other.txt")), Changed::default());
+        assert_eq!(progress.lines()[1].entries, 1);
+    }
+
+    #[test]
+    fn test_finished_completes_the_rest() {
+        let mut progress = DirProgress::new(vec!["a".to_string(), "b".to_string(), "c".to_string()]);
+        progress.apply(entry("a"));
+        progress.apply(entry("b"));
+        let changed = progress.apply(ProgressEvent::Finished);
+        assert_eq!(changed, Changed { advanced: None, completed: vec![1, 2] });
+        assert_eq!(states(&progress), vec![DirState::Done; 3]);
+        assert_eq!(progress.apply(ProgressEvent::Finished), Changed::default());
+
+        let counts: Vec<u64> = progress.lines().iter().map(|line| line.entries).collect();
+        assert_eq!(counts, vec![1, 1, 0]);
+    }
+
+    #[cfg(feature = "progress")]
+    #[test]
+    fn test_display_falls_back_to_the_spinner() {
+        let dirs = || (0..=MAX_DIR_LINES).map(|i| format!("dir{}", i)).collect();
+        let mut display = ScanDisplay::new(dirs, None);
+        assert!(display.dirs.is_none());
+        display.event(entry("dir0"));
+        display.finish("Scan complete");
+        assert!(display.spinner.is_finished());
+        assert!(ScanDisplay::new(Vec::new, None).dirs.is_none());
+    }
+}
@@ -0,0 +1,149 @@
+use crate::rollup::Rollup;
+use crate::utils;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+pub use crate::rollup::DirOrder as DuOrder;
+
+/// What [`disk_usage`] rolls up and shows
+#[derive(Debug, Clone, Default)]
+pub struct DuOptions {
+    /// Deepest level shown, counted from the scan root (or `under`) at 0
+    pub depth: usize,
+
+    /// Only roll up this directory and the entries below it
+    pub under: Option<String>,
+
+    /// Hide directories with fewer cumulative bytes than this
+    pub min_size: u64,
+
+    pub order: DuOrder,
+}
+
+/// Cumulative usage of one directory
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DuEntry {
+    pub path: String,
+
+    /// Level below the scan root (or `under`)
+    pub depth: usize,
+
+    /// Combined size of the files anywhere below the directory
+    pub bytes: u64,
+
+    /// Files anywhere below the directory
+    pub files: u64,
+
+    /// Share of the parent directory's bytes, in percent (None at the top)
+    pub percent_of_parent: Option<f64>,
+}
+
+/// Cumulative file bytes and counts per directory, down to `options.depth`,
+/// in tree order: each directory is followed by its subdirectories
+pub fn disk_usage(chunk_files: &[PathBuf], options: &DuOptions) -> Result<Vec<DuEntry>> {
+    let rollup = Rollup::collect(chunk_files, options.under.as_deref(), options.depth)?;
+    Ok(rollup.tree(options.order, options.min_size).into_iter()
+        .map(|node| DuEntry {
+            percent_of_parent: node.parent_bytes.map(|p| utils::percentage(node.bytes, p)),
+            path: node.path,
+            depth: node.depth,
+            bytes: node.bytes,
+            files: node.files,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aggregate;
+    use crate::models::FileEntry;
+    use crate::rotating_writer::{RotatingParquetWriter, RotatingWriterConfig};
+    use std::path::Path;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    fn entry(path: &str, size: u64) -> FileEntry {
+        let entry = if size == 0 { FileEntry::dir_fixture(path) } else { FileEntry::fixture(path) };
+        FileEntry { size, ..entry }
+    }
+
+    /// Scan of /project in chunks of 3 rows; size 0 marks a directory
+    fn fixture(dir: &Path) -> Vec<PathBuf> {
+        let config = RotatingWriterConfig {
+            base_output_path: dir.join("scan.parquet"),
+            rows_per_chunk: 3,
+            time_interval: Duration::from_secs(3600),
+        };
+        let mut writer = RotatingParquetWriter::new(config, "/project".to_string()).unwrap();
+        let entries = [
+            entry("/project", 0),
+            entry("/project/top.txt", 100),
+            entry("/project/labA", 0),
+            entry("/project/labA/a1.dat", 500),
+            entry("/project/labA/raw", 0),
+            entry("/project/labA/raw/r1.dat", 300),
+            entry("/project/labA/raw/deep", 0),
+            entry("/project/labA/raw/deep/d1.dat", 50),
+            entry("/project/labB", 0),
+            entry("/project/labB/b1.dat", 2000),
+            entry("/project/empty", 0),
+        ];
+        for entry in entries {
+            writer.write_batch(&[entry]).unwrap();
+        }
+        writer.finalize().unwrap();
+        aggregate::find_chunk_files(&dir.join("scan_manifest.json")).unwrap()
+    }
+
+    fn rows(entries: &[DuEntry]) -> Vec<(&str, usize, u64, u64)> {
+        entries.iter().map(|e| (e.path.as_str(), e.depth, e.bytes, e.files)).collect()
+    }
+
+    #[test]
+    fn test_cumulative_sizes_match_tree() {
+        let temp_dir = TempDir::new().unwrap();
+        let chunks = fixture(temp_dir.path());
+
+        let entries = disk_usage(&chunks, &DuOptions { depth: 2, ..Default::default() }).unwrap();
+        assert_eq!(rows(&entries), [
+            ("/project", 0, 2950, 5),
+            ("/project/empty", 1, 0, 0),
+            ("/project/labA", 1, 850, 3),
+            ("/project/labA/raw", 2, 350, 2),
+            ("/project/labB", 1, 2000, 1),
+        ]);
+        assert_eq!(entries[0].percent_of_parent, None);
+        assert_eq!(entries[3].percent_of_parent, Some(350.0 / 850.0 * 100.0));
+
+        let entries = disk_usage(&chunks, &DuOptions { depth: 0, ..Default::default() }).unwrap();
+        assert_eq!(rows(&entries), [("/project", 0, 2950, 5)]);
+    }
+
+    #[test]
+    fn test_under_sort_and_min_size() {
+        let temp_dir = TempDir::new().unwrap();
+        let chunks = fixture(temp_dir.path());
+
+        let options = DuOptions { depth: 5, under: Some("/project/labA/".to_string()), ..Default::default() };
+        let entries = disk_usage(&chunks, &options).unwrap();
+        assert_eq!(rows(&entries), [
+            ("/project/labA", 0, 850, 3),
+            ("/project/labA/raw", 1, 350, 2),
+            ("/project/labA/raw/deep", 2, 50, 1),
+        ]);
+
+        let options = DuOptions { depth: 1, order: DuOrder::Size, min_size: 100, ..Default::default() };
+        let entries = disk_usage(&chunks, &options).unwrap();
+        assert_eq!(rows(&entries), [
+            ("/project", 0, 2950, 5),
+            ("/project/labB", 1, 2000, 1),
+            ("/project/labA", 1, 850, 3),
+        ]);
+
+        let options = DuOptions { depth: 1, order: DuOrder::Count, ..Default::default() };
+        let paths: Vec<String> = disk_usage(&chunks, &options).unwrap().into_iter().map(|e| e.path).collect();
+        assert_eq!(paths, ["/project", "/project/labA", "/project/labB", "/project/empty"]);
+    }
+}
@@ -0,0 +1,583 @@
+use crate::filter::{RowFilter, TypeFilter};
+use crate::report::read_columns;
+use crate::sort::ExternalSorter;
+use crate::utils::file_checksum;
+use crate::writer::schema_version_entry;
+use anyhow::{Context, Result};
+use arrow::array::{Array, ArrayRef, StringArray, StringBuilder, UInt32Builder, UInt64Array, UInt64Builder};
+use arrow::compute::cast;
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ArrowWriter;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+/// Columns every scan must have to look for duplicates
+const COLUMNS: [&str; 4] = ["path", "size", "inode", "file_type"];
+
+/// Optional column holding a hash of each file's contents
+pub const CONTENT_HASH_COLUMN: &str = "content_hash";
+
+/// Live hashing reads this many candidate files per parallel round
+const HASH_ROUND_FILES: usize = 4096;
+
+/// How the files in a group were found to be the same
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchMethod {
+    /// Same size only; contents were not compared
+    Size,
+
+    /// Same size and the same `content_hash` recorded by the scan
+    ContentHash,
+
+    /// Same size and the same checksum, read from the live filesystem
+    LiveHash,
+}
+
+/// What [`find_duplicates`] looks for
+#[derive(Debug, Clone)]
+pub struct DuplicateOptions {
+    /// Ignore files smaller than this (empty files are always ignored)
+    pub min_size: u64,
+
+    /// Compare contents: by the scan's `content_hash` column if it has one,
+    /// otherwise by reading the candidates from the live filesystem
+    pub hash: bool,
+
+    /// Threads used for live hashing
+    pub threads: usize,
+
+    /// Memory budget for sorting files by size
+    pub sort_memory: u64,
+
+    /// Scratch directory for sorting; must not exist, and is removed afterwards
+    pub spill_dir: PathBuf,
+}
+
+/// Files with the same contents.
+///
+/// Paths hardlinked to the same inode are one copy on disk, so they share an
+/// entry in `copies` and only additional copies count as wasted.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DuplicateGroup {
+    /// Size of each file
+    pub size: u64,
+
+    /// Content hash the group shares, if contents were compared
+    pub hash: Option<String>,
+
+    /// Paths of each distinct copy; more than one path means hardlinks
+    pub copies: Vec<Vec<String>>,
+
+    /// Bytes freed by keeping a single copy
+    pub wasted_bytes: u64,
+}
+
+/// Duplicate groups found by [`find_duplicates`], largest waste first
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DuplicateReport {
+    pub matched_by: MatchMethod,
+
+    /// Files sharing their size with at least one other file
+    pub candidates: u64,
+
+    pub groups: Vec<DuplicateGroup>,
+
+    /// Files that are redundant copies, not counting hardlinks
+    pub duplicate_files: u64,
+
+    /// Total of the groups' wasted bytes
+    pub reclaimable_bytes: u64,
+
+    /// Paths that are extra hardlinks to a copy, and free nothing if removed
+    pub hardlinks: u64,
+
+    /// Candidates left out: unreadable or changed since the scan (live
+    /// hashing), or without a recorded hash (`content_hash`)
+    pub skipped: u64,
+}
+
+/// Layout of the Parquet output: one row per path in a duplicate group
+pub fn duplicates_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("group_id", DataType::UInt64, false),
+        Field::new("path", DataType::Utf8, false),
+        Field::new("size", DataType::UInt64, false),
+        Field::new("hash", DataType::Utf8, true),
+        Field::new("copy", DataType::UInt32, false),
+        Field::new("group_wasted_bytes", DataType::UInt64, false),
+    ]))
+}
+
+/// Layout of the rows sorted by size in the first stage
+fn candidate_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("size", DataType::UInt64, false),
+        Field::new("path", DataType::Utf8, false),
+        Field::new("inode", DataType::UInt64, true),
+        Field::new(CONTENT_HASH_COLUMN, DataType::Utf8, true),
+    ]))
+}
+
+fn validate(schema: &Schema) -> Result<()> {
+    for column in COLUMNS {
+        schema.field_with_name(column)
+            .with_context(|| format!("Finding duplicates requires a '{}' column", column))?;
+    }
+    Ok(())
+}
+
+/// Whether every chunk records a `content_hash`
+fn has_content_hash(chunk_files: &[PathBuf]) -> Result<bool> {
+    for path in chunk_files {
+        let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        if builder.schema().field_with_name(CONTENT_HASH_COLUMN).is_err() {
+            return Ok(false);
+        }
+    }
+    Ok(!chunk_files.is_empty())
+}
+
+/// Cast the columns of a filtered scan batch to [`candidate_schema`]
+fn candidates(batch: &RecordBatch) -> Result<RecordBatch> {
+    let schema = candidate_schema();
+    let columns = schema.fields().iter()
+        .map(|field| match batch.column_by_name(field.name()) {
+            Some(column) => Ok(cast(column, field.data_type())?),
+            None => Ok(arrow::array::new_null_array(field.data_type(), batch.num_rows())),
+        })
+        .collect::<Result<Vec<ArrayRef>>>()?;
+    Ok(RecordBatch::try_new(schema, columns)?)
+}
+
+/// One file of a size group
+struct Candidate {
+    path: String,
+
+    /// (device, inode) identifying the copy on disk
+    copy: (u64, u64),
+
+    hash: Option<String>,
+}
+
+/// Removes the scratch directory when dropped
+struct SpillDir(PathBuf);
+
+impl Drop for SpillDir {
+    fn drop(&mut self) {
+        if self.0.exists() {
+            if let Err(e) = fs::remove_dir_all(&self.0) {
+                warn!("Failed to remove spill directory {}: {}", self.0.display(), e);
+            }
+        }
+    }
+}
+
+/// Stat and checksum a candidate on the live filesystem. None if it is
+/// unreadable or no longer the size the scan recorded.
+#[cfg(unix)]
+fn hash_live(candidate: &Candidate, size: u64) -> Option<Candidate> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = fs::metadata(&candidate.path).ok()?;
+    if !metadata.is_file() || metadata.len() != size {
+        return None;
+    }
+    let hash = file_checksum(Path::new(&candidate.path)).ok()?;
+    Some(Candidate { path: candidate.path.clone(), copy: (metadata.dev(), metadata.ino()), hash: Some(hash) })
+}
+
+#[cfg(not(unix))]
+fn hash_live(candidate: &Candidate, size: u64) -> Option<Candidate> {
+    let metadata = fs::metadata(&candidate.path).ok()?;
+    if !metadata.is_file() || metadata.len() != size {
+        return None;
+    }
+    let hash = file_checksum(Path::new(&candidate.path)).ok()?;
+    Some(Candidate { path: candidate.path.clone(), copy: candidate.copy, hash: Some(hash) })
+}
+
+/// Accumulates duplicate groups from size groups in ascending size order
+struct Grouper {
+    method: MatchMethod,
+    report: DuplicateReport,
+
+    /// Size groups waiting for live hashing
+    pending: Vec<(u64, Vec<Candidate>)>,
+    pending_files: usize,
+}
+
+impl Grouper {
+    fn new(method: MatchMethod) -> Self {
+        Self {
+            method,
+            report: DuplicateReport {
+                matched_by: method,
+                candidates: 0,
+                groups: Vec::new(),
+                duplicate_files: 0,
+                reclaimable_bytes: 0,
+                hardlinks: 0,
+                skipped: 0,
+            },
+            pending: Vec::new(),
+            pending_files: 0,
+        }
+    }
+
+    /// Take a complete group of files with the same size
+    fn add_size_group(&mut self, size: u64, members: Vec<Candidate>, pool: &rayon::ThreadPool) {
+        if members.len() < 2 {
+            return;
+        }
+        self.report.candidates += members.len() as u64;
+
+        match self.method {
+            MatchMethod::Size => self.add_group(size, None, members),
+            MatchMethod::ContentHash => {
+                let (hashed, unhashed): (Vec<_>, Vec<_>) = members.into_iter().partition(|m| m.hash.is_some());
+                self.report.skipped += unhashed.len() as u64;
+                self.split_by_hash(size, hashed);
+            }
+            MatchMethod::LiveHash => {
+                self.pending_files += members.len();
+                self.pending.push((size, members));
+                if self.pending_files >= HASH_ROUND_FILES {
+                    self.hash_pending(pool);
+                }
+            }
+        }
+    }
+
+    /// Checksum every pending candidate in parallel, then group them
+    fn hash_pending(&mut self, pool: &rayon::ThreadPool) {
+        let pending = std::mem::take(&mut self.pending);
+        self.pending_files = 0;
+        let hashed: Vec<(u64, Vec<Option<Candidate>>)> = pool.install(|| {
+            pending.par_iter()
+                .map(|(size, members)| (*size, members.par_iter().map(|m| hash_live(m, *size)).collect()))
+                .collect()
+        });
+        for (size, members) in hashed {
+            let total = members.len();
+            let readable: Vec<Candidate> = members.into_iter().flatten().collect();
+            self.report.skipped += (total - readable.len()) as u64;
+            self.split_by_hash(size, readable);
+        }
+    }
+
+    fn split_by_hash(&mut self, size: u64, members: Vec<Candidate>) {
+        let mut by_hash: HashMap<String, Vec<Candidate>> = HashMap::new();
+        for member in members {
+            by_hash.entry(member.hash.clone().unwrap_or_default()).or_default().push(member);
+        }
+        for (hash, members) in by_hash {
+            self.add_group(size, Some(hash), members);
+        }
+    }
+
+    /// Record a set of identical files, merging hardlinks into one copy
+    fn add_group(&mut self, size: u64, hash: Option<String>, members: Vec<Candidate>) {
+        let mut copies: Vec<((u64, u64), Vec<String>)> = Vec::new();
+        for member in members {
+            match copies.iter_mut().find(|(copy, _)| *copy == member.copy) {
+                Some((_, paths)) => paths.push(member.path),
+                None => copies.push((member.copy, vec![member.path])),
+            }
+        }
+        let paths: usize = copies.iter().map(|(_, paths)| paths.len()).sum();
+        if copies.len() < 2 {
+            return;
+        }
+
+        let redundant = copies.len() as u64 - 1;
+        let wasted_bytes = size * redundant;
+        self.report.duplicate_files += redundant;
+        self.report.reclaimable_bytes += wasted_bytes;
+        self.report.hardlinks += (paths - copies.len()) as u64;
+
+        let mut copies: Vec<Vec<String>> = copies.into_iter().map(|(_, mut paths)| {
+            paths.sort();
+            paths
+        }).collect();
+        copies.sort();
+        self.report.groups.push(DuplicateGroup { size, hash, copies, wasted_bytes });
+    }
+
+    fn finish(mut self, pool: &rayon::ThreadPool) -> DuplicateReport {
+        self.hash_pending(pool);
+        self.report.groups.sort_by(|a, b| {
+            b.wasted_bytes.cmp(&a.wasted_bytes).then_with(|| a.copies.cmp(&b.copies))
+        });
+        self.report
+    }
+}
+
+/// Find groups of identical files in a scan.
+///
+/// Files are first sorted by size with an external sort, so only one size
+/// group at a time (plus a round of live-hashing candidates) is held in
+/// memory. Files of the same size are then compared by content when
+/// `options.hash` is set. Without a live filesystem to consult, hardlinks are
+/// recognized by inode alone, which assumes the scan did not cross devices.
+pub fn find_duplicates(chunk_files: &[PathBuf], options: &DuplicateOptions) -> Result<DuplicateReport> {
+    if options.spill_dir.exists() {
+        anyhow::bail!("Spill directory {} already exists", options.spill_dir.display());
+    }
+    let spill = SpillDir(options.spill_dir.clone());
+
+    let method = match options.hash {
+        false => MatchMethod::Size,
+        true if has_content_hash(chunk_files)? => MatchMethod::ContentHash,
+        true => MatchMethod::LiveHash,
+    };
+    let mut columns = COLUMNS.to_vec();
+    if method == MatchMethod::ContentHash {
+        columns.push(CONTENT_HASH_COLUMN);
+    }
+    let filter = RowFilter {
+        file_type: Some(TypeFilter::File),
+        min_size: Some(options.min_size.max(1)),
+        ..Default::default()
+    };
+
+    let mut sorter = ExternalSorter::new(
+        &spill.0,
+        candidate_schema(),
+        &["size".to_string(), "path".to_string()],
+        options.sort_memory,
+    )?;
+    for path in chunk_files {
+        let reader = read_columns(path, &columns, validate)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        for batch in reader {
+            let batch = batch.with_context(|| format!("Failed to read {}", path.display()))?;
+            sorter.push(candidates(&filter.apply(&batch)?)?)?;
+        }
+    }
+
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(options.threads.max(1)).build()?;
+    let mut grouper = Grouper::new(method);
+    let mut size = 0;
+    let mut members: Vec<Candidate> = Vec::new();
+    sorter.finish(|batch| {
+        let sizes = batch.column(0).as_any().downcast_ref::<UInt64Array>().unwrap();
+        let paths = batch.column(1).as_any().downcast_ref::<StringArray>().unwrap();
+        let inodes = batch.column(2).as_any().downcast_ref::<UInt64Array>().unwrap();
+        let hashes = batch.column(3).as_any().downcast_ref::<StringArray>().unwrap();
+        for i in 0..batch.num_rows() {
+            if sizes.value(i) != size {
+                grouper.add_size_group(size, std::mem::take(&mut members), &pool);
+                size = sizes.value(i);
+            }
+            // Without an inode, a file can only be its own copy
+            let inode = if inodes.is_valid(i) { inodes.value(i) } else { u64::MAX - members.len() as u64 };
+            members.push(Candidate {
+                path: paths.value(i).to_string(),
+                copy: (0, inode),
+                hash: hashes.is_valid(i).then(|| hashes.value(i).to_string()),
+            });
+        }
+        Ok(())
+    })?;
+    grouper.add_size_group(size, members, &pool);
+
+    let report = grouper.finish(&pool);
+    debug!(
+        "Found {} duplicate groups among {} candidates",
+        report.groups.len(), report.candidates
+    );
+    Ok(report)
+}
+
+/// Write one row per path of each group, in report order, to Parquet
+pub fn write_parquet(report: &DuplicateReport, output: &Path) -> Result<()> {
+    let file = File::create(output)
+        .with_context(|| format!("Failed to create {}", output.display()))?;
+    let mut writer = ArrowWriter::try_new(file, duplicates_schema(), None)?;
+    writer.append_key_value_metadata(schema_version_entry());
+
+    let mut group_id = UInt64Builder::new();
+    let mut path = StringBuilder::new();
+    let mut size = UInt64Builder::new();
+    let mut hash = StringBuilder::new();
+    let mut copy = UInt32Builder::new();
+    let mut wasted = UInt64Builder::new();
+    for (id, group) in report.groups.iter().enumerate() {
+        for (index, paths) in group.copies.iter().enumerate() {
+            for p in paths {
+                group_id.append_value(id as u64);
+                path.append_value(p);
+                size.append_value(group.size);
+                hash.append_option(group.hash.as_deref());
+                copy.append_value(index as u32);
+                wasted.append_value(group.wasted_bytes);
+            }
+        }
+    }
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(group_id.finish()),
+        Arc::new(path.finish()),
+        Arc::new(size.finish()),
+        Arc::new(hash.finish()),
+        Arc::new(copy.finish()),
+        Arc::new(wasted.finish()),
+    ];
+    writer.write(&RecordBatch::try_new(duplicates_schema(), columns)?)?;
+    writer.close()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ScanOptions;
+    use crate::scanner::scan_directory;
+    use crate::writer::ParquetFileWriter;
+    use tempfile::TempDir;
+
+    /// Files of 1000 bytes: a, b, c and the hardlink a_link share contents,
+    /// d differs; g and its hardlink g_link are the only 500-byte files
+    fn create_files(root: &Path) {
+        let same = vec![b'x'; 1000];
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("a.dat"), &same).unwrap();
+        fs::write(root.join("b.dat"), &same).unwrap();
+        fs::write(root.join("sub/c.dat"), &same).unwrap();
+        fs::hard_link(root.join("a.dat"), root.join("sub/a_link.dat")).unwrap();
+        fs::write(root.join("d.dat"), vec![b'y'; 1000]).unwrap();
+        fs::write(root.join("g.dat"), vec![b'z'; 500]).unwrap();
+        fs::hard_link(root.join("g.dat"), root.join("g_link.dat")).unwrap();
+        fs::write(root.join("unique.dat"), vec![b'u'; 42]).unwrap();
+        fs::write(root.join("empty1.dat"), b"").unwrap();
+        fs::write(root.join("empty2.dat"), b"").unwrap();
+    }
+
+    fn scan(root: &Path, output: &Path) -> Vec<PathBuf> {
+        let entries = scan_directory(root, ScanOptions::default()).unwrap();
+        let mut writer = ParquetFileWriter::new(output).unwrap();
+        writer.write_batch(&entries).unwrap();
+        writer.close().unwrap();
+        vec![output.to_path_buf()]
+    }
+
+    fn options(dir: &Path, hash: bool) -> DuplicateOptions {
+        DuplicateOptions {
+            min_size: 0,
+            hash,
+            threads: 2,
+            // Spill every batch
+            sort_memory: 1,
+            spill_dir: dir.join("spill"),
+        }
+    }
+
+    #[test]
+    fn test_live_hash_groups_and_hardlinks() {
+        let files = TempDir::new().unwrap();
+        create_files(files.path());
+        let out = TempDir::new().unwrap();
+        let chunks = scan(files.path(), &out.path().join("scan.parquet"));
+        let root = files.path().to_string_lossy();
+
+        let report = find_duplicates(&chunks, &options(out.path(), true)).unwrap();
+        assert_eq!(report.matched_by, MatchMethod::LiveHash);
+        assert_eq!(report.candidates, 7);
+        assert_eq!(report.groups.len(), 1);
+        let group = &report.groups[0];
+        assert_eq!(group.size, 1000);
+        assert_eq!(group.copies, [
+            vec![format!("{}/a.dat", root), format!("{}/sub/a_link.dat", root)],
+            vec![format!("{}/b.dat", root)],
+            vec![format!("{}/sub/c.dat", root)],
+        ]);
+        assert_eq!(group.wasted_bytes, 2000);
+        assert_eq!((report.duplicate_files, report.reclaimable_bytes, report.hardlinks), (2, 2000, 1));
+        assert_eq!(report.skipped, 0);
+        assert!(!out.path().join("spill").exists());
+
+        // A file changed since the scan is skipped rather than misreported
+        fs::write(files.path().join("b.dat"), b"now shorter").unwrap();
+        let report = find_duplicates(&chunks, &options(out.path(), true)).unwrap();
+        assert_eq!(report.skipped, 1);
+        assert_eq!(report.reclaimable_bytes, 1000);
+    }
+
+    #[test]
+    fn test_size_only_and_min_size() {
+        let files = TempDir::new().unwrap();
+        create_files(files.path());
+        let out = TempDir::new().unwrap();
+        let chunks = scan(files.path(), &out.path().join("scan.parquet"));
+
+        // d.dat has the same size, so it counts without --hash; g/g_link are one copy
+        let report = find_duplicates(&chunks, &options(out.path(), false)).unwrap();
+        assert_eq!(report.matched_by, MatchMethod::Size);
+        assert_eq!(report.groups.len(), 1);
+        assert_eq!(report.groups[0].copies.len(), 4);
+        assert_eq!(report.groups[0].hash, None);
+        assert_eq!(report.reclaimable_bytes, 3000);
+
+        let options = DuplicateOptions { min_size: 1001, ..options(out.path(), false) };
+        assert!(find_duplicates(&chunks, &options).unwrap().groups.is_empty());
+    }
+
+    #[test]
+    fn test_content_hash_column_and_parquet_output() {
+        let out = TempDir::new().unwrap();
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("path", DataType::Utf8, false),
+            Field::new("size", DataType::UInt64, false),
+            Field::new("inode", DataType::UInt64, false),
+            Field::new("file_type", DataType::Utf8, false),
+            Field::new(CONTENT_HASH_COLUMN, DataType::Utf8, true),
+        ]));
+        let rows = [
+            ("/p/one", 100, 1, Some("aa")),
+            ("/p/two", 100, 2, Some("aa")),
+            ("/p/three", 100, 3, Some("bb")),
+            ("/p/four", 100, 4, None),
+            ("/p/five", 300, 5, Some("cc")),
+            ("/p/six", 300, 6, Some("cc")),
+            ("/p/seven", 300, 7, Some("cc")),
+        ];
+        let batch = RecordBatch::try_new(schema.clone(), vec![
+            Arc::new(StringArray::from(rows.iter().map(|r| r.0).collect::<Vec<_>>())),
+            Arc::new(UInt64Array::from(rows.iter().map(|r| r.1).collect::<Vec<_>>())),
+            Arc::new(UInt64Array::from(rows.iter().map(|r| r.2).collect::<Vec<_>>())),
+            Arc::new(StringArray::from(vec!["dat"; rows.len()])),
+            Arc::new(StringArray::from(rows.iter().map(|r| r.3).collect::<Vec<_>>())),
+        ]).unwrap();
+        let chunk = out.path().join("hashed.parquet");
+        let mut writer = ArrowWriter::try_new(File::create(&chunk).unwrap(), schema, None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+
+        let report = find_duplicates(&[chunk], &options(out.path(), true)).unwrap();
+        assert_eq!(report.matched_by, MatchMethod::ContentHash);
+        assert_eq!(report.skipped, 1);
+        let groups: Vec<(Option<&str>, usize, u64)> = report.groups.iter()
+            .map(|g| (g.hash.as_deref(), g.copies.len(), g.wasted_bytes))
+            .collect();
+        assert_eq!(groups, [(Some("cc"), 3, 600), (Some("aa"), 2, 100)]);
+        assert_eq!(report.reclaimable_bytes, 700);
+
+        let output = out.path().join("duplicates.parquet");
+        write_parquet(&report, &output).unwrap();
+        let batches: Vec<RecordBatch> = ParquetRecordBatchReaderBuilder::try_new(File::open(&output).unwrap())
+            .unwrap().build().unwrap().map(|b| b.unwrap()).collect();
+        let rows: usize = batches.iter().map(|b| b.num_rows()).sum();
+        assert_eq!(rows, 5);
+        let paths = batches[0].column(1).as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(paths.value(0), "/p/five");
+    }
+}
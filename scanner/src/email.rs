@@ -0,0 +1,327 @@
+use crate::manifest::DirStats;
+use crate::notify::{ScanNotification, ScanOutcome};
+use crate::utils::{format_bytes, format_duration, format_number};
+use anyhow::Result;
+use std::fmt::Write;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Top-level directories listed in a report, largest first
+const MAX_DIRS: usize = 50;
+
+/// Output files listed in a report; a long scan writes thousands of chunks
+const MAX_OUTPUTS: usize = 20;
+
+/// How the connection to the SMTP server is secured
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmtpTls {
+    /// Plain text, for a relay on a trusted network
+    None,
+    /// STARTTLS when the server offers it, plain text otherwise
+    Opportunistic,
+    /// STARTTLS, failing if the server doesn't offer it
+    StartTls,
+    /// TLS from the start (SMTPS, usually port 465)
+    Tls,
+}
+
+/// Where reports are sent and how
+#[derive(Debug, Clone)]
+pub struct EmailOptions {
+    /// Recipients
+    pub to: Vec<String>,
+
+    /// Sender address
+    pub from: String,
+
+    /// SMTP server as "host:port" or "host" (port 25, or 465 with [`SmtpTls::Tls`])
+    pub server: String,
+
+    pub tls: SmtpTls,
+
+    /// Username for SMTP AUTH; none sends unauthenticated
+    pub user: Option<String>,
+
+    pub password: Option<String>,
+
+    /// Limit on each SMTP command, connecting included
+    pub timeout: Duration,
+}
+
+/// A report ready to send: the subject and the same body as plain text and HTML
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmailReport {
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+}
+
+impl EmailReport {
+    /// The report of how `notification`'s scan ended
+    pub fn render(notification: &ScanNotification) -> Self {
+        let n = notification;
+        let outcome = match n.status {
+            ScanOutcome::Completed => "completed",
+            ScanOutcome::Failed => "failed",
+            ScanOutcome::Interrupted => "was interrupted",
+        };
+        let subject = match n.status {
+            ScanOutcome::Completed => format!(
+                "Scan of {} completed: {} files, {}",
+                n.root, format_number(n.files), format_bytes(n.bytes)
+            ),
+            _ => format!("Scan of {} {}", n.root, outcome),
+        };
+
+        // Label and value rows shared by both bodies
+        let mut summary = vec![
+            ("Scan ID", n.scan_id.clone()),
+            ("Duration", format_duration(n.duration_secs)),
+        ];
+        if let Some(ref error) = n.error {
+            summary.push(("Error", error.clone()));
+        }
+        if n.status == ScanOutcome::Completed {
+            summary.extend([
+                ("Files", format_number(n.files)),
+                ("Directories", format_number(n.directories)),
+                ("Total size", format_bytes(n.bytes)),
+                ("Rows written", format_number(n.rows)),
+                ("Errors", format_number(n.errors.total)),
+            ]);
+        }
+
+        let mut dirs: Vec<(&String, &DirStats)> = n.dir_stats.iter().collect();
+        dirs.sort_by(|a, b| b.1.bytes.cmp(&a.1.bytes).then_with(|| a.0.cmp(b.0)));
+        let more_dirs = dirs.len().saturating_sub(MAX_DIRS);
+        dirs.truncate(MAX_DIRS);
+        let outputs = &n.outputs[..n.outputs.len().min(MAX_OUTPUTS)];
+        let more_outputs = n.outputs.len() - outputs.len();
+
+        let mut text = format!("Scan of {} {}\n\n", n.root, outcome);
+        for (label, value) in &summary {
+            let _ = writeln!(text, "{:<14}{}", format!("{}:", label), value);
+        }
+        for (kind, count) in &n.errors.by_kind {
+            let _ = writeln!(text, "  {:<18} {}", format!("{}:", kind), format_number(*count));
+        }
+        if !dirs.is_empty() {
+            let width = dirs.iter().map(|(name, _)| name.len()).max().unwrap_or(0).max("Directory".len());
+            let _ = write!(text, "\nTop-level directories:\n  {:<width$}  {:>12}  {:>12}\n", "Directory", "Rows", "Size");
+            for (name, stats) in &dirs {
+                let _ = writeln!(text, "  {:<width$}  {:>12}  {:>12}", name, format_number(stats.rows), format_bytes(stats.bytes));
+            }
+            if more_dirs > 0 {
+                let _ = writeln!(text, "  ... and {} more", more_dirs);
+            }
+        }
+        if !outputs.is_empty() {
+            text.push_str("\nOutputs:\n");
+            for output in outputs {
+                let _ = writeln!(text, "  {}", output);
+            }
+            if more_outputs > 0 {
+                let _ = writeln!(text, "  ... and {} more", more_outputs);
+            }
+        }
+        if let Some(ref manifest) = n.manifest {
+            let _ = writeln!(text, "Manifest: {}", manifest);
+        }
+
+        let mut html = format!("<html><body>\n<h2>Scan of {} {}</h2>\n<table>\n", escape(&n.root), outcome);
+        for (label, value) in &summary {
+            let _ = writeln!(html, "<tr><th align=\"left\">{}</th><td>{}</td></tr>", label, escape(value));
+        }
+        for (kind, count) in &n.errors.by_kind {
+            let _ = writeln!(html, "<tr><td>&nbsp;&nbsp;{}</td><td>{}</td></tr>", escape(kind), format_number(*count));
+        }
+        html.push_str("</table>\n");
+        if !dirs.is_empty() {
+            html.push_str("<h3>Top-level directories</h3>\n<table>\n\
+                           <tr><th align=\"left\">Directory</th><th align=\"right\">Rows</th><th align=\"right\">Size</th></tr>\n");
+            for (name, stats) in &dirs {
+                let _ = writeln!(html, "<tr><td>{}</td><td align=\"right\">{}</td><td align=\"right\">{}</td></tr>",
+                                 escape(name), format_number(stats.rows), format_bytes(stats.bytes));
+            }
+            html.push_str("</table>\n");
+            if more_dirs > 0 {
+                let _ = writeln!(html, "<p>... and {} more</p>", more_dirs);
+            }
+        }
+        if !outputs.is_empty() {
+            html.push_str("<h3>Outputs</h3>\n<ul>\n");
+            for output in outputs {
+                let _ = writeln!(html, "<li>{}</li>", escape(output));
+            }
+            if more_outputs > 0 {
+                let _ = writeln!(html, "<li>... and {} more</li>", more_outputs);
+            }
+            html.push_str("</ul>\n");
+        }
+        if let Some(ref manifest) = n.manifest {
+            let _ = writeln!(html, "<p>Manifest: {}</p>", escape(manifest));
+        }
+        html.push_str("</body></html>\n");
+
+        Self { subject, text, html }
+    }
+}
+
+/// `value` safe to put in HTML text
+fn escape(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Split "host:port" into its parts, defaulting the port by `tls`
+#[cfg_attr(not(feature = "email"), allow(dead_code))]
+fn server_address(server: &str, tls: SmtpTls) -> Result<(String, u16)> {
+    match server.rsplit_once(':') {
+        Some((host, port)) => {
+            let port = port.parse()
+                .map_err(|_| anyhow::anyhow!("Invalid port in SMTP server {}", server))?;
+            Ok((host.to_string(), port))
+        }
+        None if tls == SmtpTls::Tls => Ok((server.to_string(), 465)),
+        None => Ok((server.to_string(), 25)),
+    }
+}
+
+/// Send `report` as a plain text and HTML alternative message
+#[cfg(feature = "email")]
+pub fn send(options: &EmailOptions, report: &EmailReport) -> Result<()> {
+    use anyhow::Context;
+    use lettre::message::{Mailbox, MultiPart};
+    use lettre::transport::smtp::authentication::Credentials;
+    use lettre::transport::smtp::client::{Tls, TlsParameters};
+    use lettre::{Message, SmtpTransport, Transport};
+
+    let mailbox = |address: &str| address.parse::<Mailbox>()
+        .with_context(|| format!("Invalid email address {}", address));
+    let mut message = Message::builder().from(mailbox(&options.from)?).subject(&report.subject);
+    for to in &options.to {
+        message = message.to(mailbox(to)?);
+    }
+    let message = message
+        .multipart(MultiPart::alternative_plain_html(report.text.clone(), report.html.clone()))
+        .context("Failed to build email")?;
+
+    let (host, port) = server_address(&options.server, options.tls)?;
+    let parameters = || TlsParameters::new(host.clone()).context("Failed to set up TLS");
+    let tls = match options.tls {
+        SmtpTls::None => Tls::None,
+        SmtpTls::Opportunistic => Tls::Opportunistic(parameters()?),
+        SmtpTls::StartTls => Tls::Required(parameters()?),
+        SmtpTls::Tls => Tls::Wrapper(parameters()?),
+    };
+    let mut transport = SmtpTransport::builder_dangerous(&host)
+        .port(port)
+        .tls(tls)
+        .timeout(Some(options.timeout));
+    if let Some(ref user) = options.user {
+        transport = transport.credentials(Credentials::new(user.clone(), options.password.clone().unwrap_or_default()));
+    }
+    transport.build().send(&message)
+        .with_context(|| format!("Failed to send email through {}", options.server))?;
+    Ok(())
+}
+
+#[cfg(not(feature = "email"))]
+pub fn send(_options: &EmailOptions, _report: &EmailReport) -> Result<()> {
+    anyhow::bail!("Email reports require building with --features email")
+}
+
+/// Email the report of `notification`; a report that can't be sent is
+/// logged, never allowed to change how the scan ends
+pub fn email_report(options: &EmailOptions, notification: &ScanNotification) {
+    let report = EmailReport::render(notification);
+    match send(options, &report) {
+        Ok(()) => info!("Emailed scan report to {}", options.to.join(", ")),
+        Err(e) => warn!("Failed to email scan report: {:#}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ScanStats;
+
+    fn completed() -> ScanNotification {
+        let mut stats = ScanStats::new();
+        stats.files_scanned = 12345;
+        stats.directories_scanned = 67;
+        stats.total_size = 5_000_000_000;
+        stats.duration_secs = 3725.0;
+        stats.error_summary.record("permission_denied", "/data/locked", "denied".to_string());
+
+        let mut notification = ScanNotification::completed("/data".to_string(), 1716170400, &stats, 12412);
+        notification.outputs = (1..=25).map(|i| format!("/out/scan_chunk_{:04}.parquet", i)).collect();
+        notification.manifest = Some("/out/scan_manifest.json".to_string());
+        notification.dir_stats.insert("alpha".to_string(), DirStats { rows: 100, bytes: 1_000_000 });
+        notification.dir_stats.insert("<beta>".to_string(), DirStats { rows: 12300, bytes: 4_990_000_000 });
+        notification
+    }
+
+    #[test]
+    fn test_render_completed() {
+        let report = EmailReport::render(&completed());
+        assert_eq!(report.subject, "Scan of /data completed: 12,345 files, 5.00 GB");
+
+        for body in [&report.text, &report.html] {
+            for figure in ["2024-05-20T02:00:00Z", "1h 2m", "12,345", "67", "5.00 GB", "12,412",
+                           "permission_denied", "1.00 MB", "12,300", "4.99 GB", "/out/scan_chunk_0001.parquet",
+                           "/out/scan_manifest.json", "... and 5 more"] {
+                assert!(body.contains(figure), "{} missing from\n{}", figure, body);
+            }
+            assert!(!body.contains("scan_chunk_0021"), "{}", body);
+        }
+        assert!(report.text.contains("Errors:       1\n"), "{}", report.text);
+
+        // Largest directory first
+        let beta = report.text.find("<beta>").unwrap();
+        let alpha = report.text.find("alpha").unwrap();
+        assert!(beta < alpha, "{}", report.text);
+        assert!(report.html.contains("&lt;beta&gt;"), "{}", report.html);
+        assert!(!report.html.contains("<beta>"), "{}", report.html);
+    }
+
+    #[test]
+    fn test_render_failed() {
+        let notification = ScanNotification::failed(
+            "/data".to_string(), 1716170400, ScanOutcome::Failed, "Scan failed: disk full".to_string(),
+        );
+        let report = EmailReport::render(&notification);
+        assert_eq!(report.subject, "Scan of /data failed");
+        assert!(report.text.contains("Error:        Scan failed: disk full\n"), "{}", report.text);
+        assert!(report.html.contains("Scan failed: disk full"), "{}", report.html);
+        assert!(!report.text.contains("Files:"), "{}", report.text);
+    }
+
+    #[test]
+    fn test_server_address() {
+        assert_eq!(server_address("mail:2525", SmtpTls::None).unwrap(), ("mail".to_string(), 2525));
+        assert_eq!(server_address("mail", SmtpTls::StartTls).unwrap(), ("mail".to_string(), 25));
+        assert_eq!(server_address("mail", SmtpTls::Tls).unwrap(), ("mail".to_string(), 465));
+        assert!(server_address("mail:smtp", SmtpTls::None).is_err());
+    }
+
+    /// Delivery to a local SMTP sink such as MailHog or smtp4dev:
+    /// SMTP_TEST_SERVER=127.0.0.1:1025
+    #[cfg(feature = "email")]
+    #[test]
+    fn test_send_to_sink() {
+        let Ok(server) = std::env::var("SMTP_TEST_SERVER") else {
+            eprintln!("SMTP_TEST_SERVER not set; skipping");
+            return;
+        };
+        let options = EmailOptions {
+            to: vec!["storage-admins@example.edu".to_string()],
+            from: "storage-scanner@example.edu".to_string(),
+            server,
+            tls: SmtpTls::None,
+            user: None,
+            password: None,
+            timeout: Duration::from_secs(10),
+        };
+        send(&options, &EmailReport::render(&completed())).unwrap();
+    }
+}
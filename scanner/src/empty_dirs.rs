@@ -0,0 +1,299 @@
+use crate::report::read_columns;
+use crate::utils::parent_dir;
+use crate::writer::schema_version_entry;
+use anyhow::{Context, Result};
+use arrow::array::{Array, ArrayRef, StringArray, StringBuilder, UInt64Builder};
+use arrow::compute::cast;
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Columns read by [`find_empty_dirs`]
+const COLUMNS: [&str; 4] = ["path", "file_type", "parent_path", "top_level_dir"];
+
+/// `file_type` of directory entries
+const DIRECTORY_TYPE: &str = "directory";
+
+/// A directory with nothing worth keeping below it
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct EmptyDir {
+    pub path: String,
+
+    pub top_level_dir: String,
+
+    /// Directories in the empty subtree, this one included (always 1 without
+    /// `recursive`)
+    pub dirs: u64,
+}
+
+/// Empty directories per top-level directory
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TopLevelCount {
+    pub top_level_dir: String,
+
+    /// Empty directories, nested ones included
+    pub dirs: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct EmptyDirsReport {
+    /// Whether `dirs` lists whole empty subtrees rather than leaf directories
+    pub recursive: bool,
+
+    /// Directories scanned
+    pub directories: u64,
+
+    /// In path order. With `recursive`, only the top of each empty subtree.
+    pub dirs: Vec<EmptyDir>,
+
+    /// Empty directories in all, nested ones included
+    pub total: u64,
+
+    /// In name order
+    pub by_top_level_dir: Vec<TopLevelCount>,
+}
+
+fn validate(schema: &Schema) -> Result<()> {
+    for column in COLUMNS {
+        schema.field_with_name(column)
+            .with_context(|| format!("Finding empty directories requires a '{}' column", column))?;
+    }
+    Ok(())
+}
+
+/// What the scan says about its directories
+#[derive(Debug, Default)]
+struct Hierarchy {
+    /// Top-level directory of each directory
+    dirs: HashMap<String, String>,
+
+    /// Directories with at least one entry directly inside
+    with_children: HashSet<String>,
+
+    /// Directories with at least one non-directory entry anywhere below
+    with_files: HashSet<String>,
+}
+
+impl Hierarchy {
+    fn add_batch(&mut self, batch: &RecordBatch) -> Result<()> {
+        let column = |name: &str| -> Result<ArrayRef> {
+            let column = batch.column_by_name(name)
+                .with_context(|| format!("Finding empty directories requires a '{}' column", name))?;
+            Ok(cast(column, &DataType::Utf8)?)
+        };
+        let (paths, types, parents, top_level) =
+            (column("path")?, column("file_type")?, column("parent_path")?, column("top_level_dir")?);
+        let paths = paths.as_any().downcast_ref::<StringArray>().unwrap();
+        let types = types.as_any().downcast_ref::<StringArray>().unwrap();
+        let parents = parents.as_any().downcast_ref::<StringArray>().unwrap();
+        let top_level = top_level.as_any().downcast_ref::<StringArray>().unwrap();
+
+        for i in 0..batch.num_rows() {
+            if paths.is_null(i) {
+                continue;
+            }
+            let is_dir = types.is_valid(i) && types.value(i) == DIRECTORY_TYPE;
+            if is_dir {
+                let top = if top_level.is_valid(i) { top_level.value(i) } else { "" };
+                self.dirs.insert(paths.value(i).to_string(), top.to_string());
+            }
+            if parents.is_null(i) {
+                continue;
+            }
+            let parent = parents.value(i);
+            if !self.with_children.contains(parent) {
+                self.with_children.insert(parent.to_string());
+            }
+            if !is_dir {
+                // Ancestors of a marked directory are marked already
+                let mut dir = Some(parent);
+                while let Some(current) = dir {
+                    if !self.with_files.insert(current.to_string()) {
+                        break;
+                    }
+                    dir = parent_dir(current);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn report(self, recursive: bool) -> EmptyDirsReport {
+        let empty: HashSet<&str> = self.dirs.keys()
+            .map(String::as_str)
+            .filter(|dir| if recursive {
+                !self.with_files.contains(*dir)
+            } else {
+                !self.with_children.contains(*dir)
+            })
+            .collect();
+
+        let mut by_top_level: BTreeMap<&str, u64> = BTreeMap::new();
+        // Subtree size by the top of each empty subtree
+        let mut subtrees: HashMap<&str, u64> = HashMap::new();
+        for &dir in &empty {
+            *by_top_level.entry(self.dirs[dir].as_str()).or_default() += 1;
+            let mut top = dir;
+            while let Some(parent) = parent_dir(top).filter(|p| recursive && empty.contains(p)) {
+                top = parent;
+            }
+            *subtrees.entry(top).or_default() += 1;
+        }
+
+        let mut dirs: Vec<EmptyDir> = subtrees.into_iter()
+            .map(|(path, dirs)| EmptyDir {
+                path: path.to_string(),
+                top_level_dir: self.dirs[path].clone(),
+                dirs,
+            })
+            .collect();
+        dirs.sort_by(|a, b| a.path.cmp(&b.path));
+
+        EmptyDirsReport {
+            recursive,
+            directories: self.dirs.len() as u64,
+            dirs,
+            total: empty.len() as u64,
+            by_top_level_dir: by_top_level.into_iter()
+                .map(|(top_level_dir, dirs)| TopLevelCount { top_level_dir: top_level_dir.to_string(), dirs })
+                .collect(),
+        }
+    }
+}
+
+/// Find the empty directories of `chunk_files`: those with no entries
+/// directly inside, or with `recursive`, those with no files anywhere below,
+/// grouped into the empty subtrees they form. Memory grows with the number of
+/// directories, not files.
+pub fn find_empty_dirs(chunk_files: &[PathBuf], recursive: bool) -> Result<EmptyDirsReport> {
+    let mut hierarchy = Hierarchy::default();
+    for path in chunk_files {
+        let reader = read_columns(path, &COLUMNS, validate)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        for batch in reader {
+            hierarchy.add_batch(&batch?)?;
+        }
+    }
+    Ok(hierarchy.report(recursive))
+}
+
+/// Layout of the Parquet output: one row per reported directory
+pub fn empty_dirs_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("path", DataType::Utf8, false),
+        Field::new("top_level_dir", DataType::Utf8, false),
+        Field::new("dirs", DataType::UInt64, false),
+    ]))
+}
+
+/// Write the report's directories, in path order, to Parquet
+pub fn write_parquet(report: &EmptyDirsReport, output: &Path) -> Result<()> {
+    let file = File::create(output)
+        .with_context(|| format!("Failed to create {}", output.display()))?;
+    let mut writer = ArrowWriter::try_new(file, empty_dirs_schema(), None)?;
+    writer.append_key_value_metadata(schema_version_entry());
+
+    let mut path = StringBuilder::new();
+    let mut top_level_dir = StringBuilder::new();
+    let mut dirs = UInt64Builder::new();
+    for dir in &report.dirs {
+        path.append_value(&dir.path);
+        top_level_dir.append_value(&dir.top_level_dir);
+        dirs.append_value(dir.dirs);
+    }
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(path.finish()),
+        Arc::new(top_level_dir.finish()),
+        Arc::new(dirs.finish()),
+    ];
+    writer.write(&RecordBatch::try_new(empty_dirs_schema(), columns)?)?;
+    writer.close()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::FileEntry;
+    use crate::writer::ParquetFileWriter;
+    use tempfile::TempDir;
+
+    fn entry(path: &str, file_type: &str) -> FileEntry {
+        match file_type {
+            "directory" => FileEntry { size: 4096, gid: 100, ..FileEntry::dir_fixture(path) },
+            _ => FileEntry { size: 10, file_type: file_type.to_string(), gid: 100, ..FileEntry::fixture(path) },
+        }
+    }
+
+    fn fixture(dir: &Path) -> PathBuf {
+        let chunk = dir.join("scan.parquet");
+        let mut writer = ParquetFileWriter::new(&chunk).unwrap();
+        writer.write_batch(&[
+            entry("/p/a", "directory"),
+            entry("/p/a/data.csv", "csv"),
+            entry("/p/a/empty", "directory"),
+            // A skeleton of directories with no files in it
+            entry("/p/a/skeleton", "directory"),
+            entry("/p/a/skeleton/x", "directory"),
+            entry("/p/a/skeleton/x/y", "directory"),
+            entry("/p/a/skeleton/z", "directory"),
+            entry("/p/b", "directory"),
+            entry("/p/b/keep", "directory"),
+            entry("/p/b/keep/deep", "directory"),
+            entry("/p/b/keep/deep/file.txt", "txt"),
+            entry("/p/b/keep/hollow", "directory"),
+        ]).unwrap();
+        writer.close().unwrap();
+        chunk
+    }
+
+    fn paths(report: &EmptyDirsReport) -> Vec<(&str, u64)> {
+        report.dirs.iter().map(|d| (d.path.as_str(), d.dirs)).collect()
+    }
+
+    #[test]
+    fn test_direct_children() {
+        let temp_dir = TempDir::new().unwrap();
+        let report = find_empty_dirs(&[fixture(temp_dir.path())], false).unwrap();
+
+        assert_eq!(report.directories, 10);
+        assert_eq!(paths(&report), [
+            ("/p/a/empty", 1),
+            ("/p/a/skeleton/x/y", 1),
+            ("/p/a/skeleton/z", 1),
+            ("/p/b/keep/hollow", 1),
+        ]);
+        assert_eq!(report.by_top_level_dir, [
+            TopLevelCount { top_level_dir: "a".to_string(), dirs: 3 },
+            TopLevelCount { top_level_dir: "b".to_string(), dirs: 1 },
+        ]);
+    }
+
+    #[test]
+    fn test_recursive_subtrees() {
+        let temp_dir = TempDir::new().unwrap();
+        let chunk = fixture(temp_dir.path());
+        let report = find_empty_dirs(&[chunk], true).unwrap();
+
+        assert_eq!(paths(&report), [
+            ("/p/a/empty", 1),
+            ("/p/a/skeleton", 4),
+            ("/p/b/keep/hollow", 1),
+        ]);
+        assert_eq!(report.total, 6);
+        assert_eq!(report.by_top_level_dir[0].dirs, 5);
+
+        let output = temp_dir.path().join("empty.parquet");
+        write_parquet(&report, &output).unwrap();
+        let reader = parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder::try_new(File::open(&output).unwrap())
+            .unwrap().build().unwrap();
+        let rows: usize = reader.map(|b| b.unwrap().num_rows()).sum();
+        assert_eq!(rows, 3);
+    }
+}
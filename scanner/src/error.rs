@@ -0,0 +1,211 @@
+//! Errors returned by the scanning and writing API.
+//!
+//! The rest of the crate, and the CLI, work with `anyhow`; an [`Error`]
+//! converts into `anyhow::Error` with `?`, and back into [`Error::Other`].
+
+use crate::models::ScanStats;
+use std::io;
+use std::path::PathBuf;
+
+/// What went wrong in a scan or while writing its output
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// The scan root does not exist or cannot be read
+    #[error("Cannot scan {}: {source}", path.display())]
+    InvalidRoot {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    /// Scan options that cannot be used, such as an exclude glob that does
+    /// not parse
+    #[error("Invalid scan option {field}: {reason}")]
+    InvalidOptions {
+        /// The option, as named by its [`ScanOptionsBuilder`](crate::models::ScanOptionsBuilder) method
+        field: &'static str,
+        reason: String,
+    },
+
+    /// Creating, writing, renaming, or removing an output file failed
+    #[error("Failed to write {}: {source}", path.display())]
+    OutputIo {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    /// Entries could not be encoded or written as Parquet
+    #[cfg(feature = "parquet-writer")]
+    #[error("Parquet writer failed for {}{}: {source}", path.display(), chunk_suffix(*chunk))]
+    WriterSchema {
+        path: PathBuf,
+
+        /// Chunk being written, for rotating writers
+        chunk: Option<usize>,
+
+        #[source]
+        source: parquet::errors::ParquetError,
+    },
+
+    /// A scan output could not be opened or read
+    #[error("Failed to read {}: {source}", path.display())]
+    InputIo {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    /// A scan output that is not Parquet, or whose columns cannot be read as
+    /// entries
+    #[error("Cannot read entries from {}: {source}", path.display())]
+    InputCorrupt {
+        path: PathBuf,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    /// A manifest could not be read
+    #[error("Failed to read manifest {}: {source}", path.display())]
+    ManifestIo {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    /// A manifest that is not valid JSON or is missing fields
+    #[cfg(feature = "serde")]
+    #[error("Manifest {} is corrupt: {source}", path.display())]
+    ManifestCorrupt {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    /// The [`EntrySink`](crate::sink::EntrySink) a scan was feeding failed;
+    /// the scan was stopped
+    #[error("Entry sink failed: {source:#}")]
+    Sink {
+        #[source]
+        source: anyhow::Error,
+    },
+
+    /// The scan was stopped before it finished: it was cancelled, or the
+    /// receiver of its entries went away
+    #[error("Scan of {} was cancelled", root.display())]
+    Cancelled {
+        root: PathBuf,
+
+        /// Counts up to the point the scan stopped
+        stats: Box<ScanStats>,
+    },
+
+    /// Writing a scan's output failed. The scan was stopped when the writer
+    /// stopped taking entries, so `stats` may count only part of the tree.
+    #[error(
+        "{source} (the scan stopped with {} files and {} directories scanned)",
+        stats.files_scanned, stats.directories_scanned
+    )]
+    WriterFailed {
+        #[source]
+        source: Box<Error>,
+
+        /// Counts of the scan up to the point it stopped
+        stats: Box<ScanStats>,
+    },
+
+    /// Any other failure, such as a panicked worker thread or a chunk hook
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+/// Result with this crate's [`Error`]
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[cfg(feature = "parquet-writer")]
+fn chunk_suffix(chunk: Option<usize>) -> String {
+    chunk.map(|n| format!(" (chunk {})", n)).unwrap_or_default()
+}
+
+impl Error {
+    /// An [`Error::InvalidOptions`] for `field`
+    pub(crate) fn invalid_option(field: &'static str, reason: impl Into<String>) -> Self {
+        Error::InvalidOptions { field, reason: reason.into() }
+    }
+
+    /// The I/O error underneath, if there is one
+    pub fn io_error(&self) -> Option<&io::Error> {
+        match self {
+            Error::InvalidRoot { source, .. }
+            | Error::OutputIo { source, .. }
+            | Error::InputIo { source, .. }
+            | Error::ManifestIo { source, .. } => Some(source),
+            Error::WriterFailed { source, .. } => source.io_error(),
+            Error::Other(e) => e.downcast_ref(),
+            _ => None,
+        }
+    }
+
+    /// Attribute a Parquet writer failure to a chunk
+    #[cfg(feature = "parquet-writer")]
+    pub(crate) fn in_chunk(self, number: usize) -> Self {
+        match self {
+            Error::WriterSchema { path, source, .. } => Error::WriterSchema { path, chunk: Some(number), source },
+            other => other,
+        }
+    }
+
+    #[cfg(feature = "parquet-writer")]
+    pub(crate) fn output_io(path: impl Into<PathBuf>) -> impl FnOnce(io::Error) -> Self {
+        let path = path.into();
+        move |source| Error::OutputIo { path, source }
+    }
+
+    #[cfg(feature = "parquet-writer")]
+    pub(crate) fn input_corrupt<E>(path: impl Into<PathBuf>) -> impl FnOnce(E) -> Self
+    where
+        E: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        let path = path.into();
+        move |source| Error::InputCorrupt { path, source: source.into() }
+    }
+
+    #[cfg(feature = "parquet-writer")]
+    pub(crate) fn writer(path: impl Into<PathBuf>) -> impl FnOnce(parquet::errors::ParquetError) -> Self {
+        let path = path.into();
+        move |source| Error::WriterSchema { path, chunk: None, source }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_errors_convert_both_ways() {
+        let error = Error::OutputIo {
+            path: PathBuf::from("/scratch/scan.parquet"),
+            source: io::Error::from(io::ErrorKind::PermissionDenied),
+        };
+        assert_eq!(error.io_error().unwrap().kind(), io::ErrorKind::PermissionDenied);
+
+        let wrapped = anyhow::Error::from(error);
+        assert!(wrapped.to_string().starts_with("Failed to write /scratch/scan.parquet"));
+        assert!(matches!(wrapped.downcast_ref::<Error>(), Some(Error::OutputIo { .. })));
+
+        let error = Error::from(anyhow::anyhow!("hook failed"));
+        assert!(matches!(error, Error::Other(_)));
+        assert_eq!(error.to_string(), "hook failed");
+    }
+
+    #[cfg(feature = "parquet-writer")]
+    #[test]
+    fn test_writer_errors_name_their_chunk() {
+        let error = Error::writer("/scratch/scan_chunk_0003.parquet")(
+            parquet::errors::ParquetError::General("bad batch".to_string()),
+        ).in_chunk(3);
+        assert!(matches!(error, Error::WriterSchema { chunk: Some(3), .. }));
+        assert!(error.to_string().contains("(chunk 3)"), "{}", error);
+    }
+}
@@ -0,0 +1,240 @@
+use crate::aggregate::{conform_batch, reconcile_schemas};
+use crate::query::{Expr, QueryFormat, ResultWriter};
+use anyhow::{Context, Result};
+use arrow::compute::filter_record_batch;
+use arrow::datatypes::{Schema, SchemaRef};
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ProjectionMask;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Which columns and rows to export
+#[derive(Debug, Clone, Default)]
+pub struct ExportOptions {
+    /// Columns to write, in this order; None writes every column
+    pub columns: Option<Vec<String>>,
+
+    /// Only export rows this expression holds for
+    pub filter: Option<Expr>,
+}
+
+/// Rows read and written by [`export`]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ExportSummary {
+    pub rows_scanned: u64,
+
+    pub rows_exported: u64,
+}
+
+/// `schema` restricted to `names`, in that order
+fn project(schema: &Schema, names: &[&str]) -> Result<SchemaRef> {
+    let fields = names.iter()
+        .map(|name| schema.field_with_name(name).cloned().map_err(|_| {
+            let known: Vec<&str> = schema.fields().iter().map(|f| f.name().as_str()).collect();
+            anyhow::anyhow!("Unknown column '{}' (columns in the scan: {})", name, known.join(", "))
+        }))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Arc::new(Schema::new(fields)))
+}
+
+/// Stream the rows of `chunk_files` selected by `options` to `output`.
+///
+/// Chunks are read in order, one record batch at a time, reading only the
+/// exported columns and those the filter needs, so memory does not grow with
+/// the size of the scan. Chunks missing a column get nulls for it.
+pub fn export(
+    chunk_files: &[PathBuf],
+    output: &Path,
+    format: QueryFormat,
+    options: &ExportOptions,
+) -> Result<ExportSummary> {
+    let schema = reconcile_schemas(chunk_files)?;
+    if let Some(ref expr) = options.filter {
+        expr.validate(&schema)?;
+    }
+
+    let output_columns: Vec<&str> = match options.columns {
+        Some(ref columns) => columns.iter().map(String::as_str).collect(),
+        None => schema.fields().iter().map(|f| f.name().as_str()).collect(),
+    };
+    let output_schema = project(&schema, &output_columns)?;
+
+    // The filter may read columns that are not exported
+    let mut read_columns = output_columns.clone();
+    for column in options.filter.iter().flat_map(Expr::columns) {
+        if !read_columns.contains(&column) {
+            read_columns.push(column);
+        }
+    }
+    let read_schema = project(&schema, &read_columns)?;
+    let output_indices: Vec<usize> = (0..output_columns.len()).collect();
+
+    let mut writer = ResultWriter::create(output, format, output_schema)?;
+    let mut summary = ExportSummary::default();
+    for path in chunk_files {
+        let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let roots: Vec<usize> = read_columns.iter()
+            .filter_map(|name| builder.schema().index_of(name).ok())
+            .collect();
+        let mask = ProjectionMask::roots(builder.parquet_schema(), roots);
+
+        for batch in builder.with_projection(mask).build()? {
+            let batch = conform_batch(&batch?, &read_schema)?;
+            summary.rows_scanned += batch.num_rows() as u64;
+
+            let batch = match options.filter {
+                Some(ref expr) => filter_record_batch(&batch, &expr.mask(&batch)?)?,
+                None => batch,
+            };
+            if batch.num_rows() == 0 {
+                continue;
+            }
+            summary.rows_exported += batch.num_rows() as u64;
+            writer.write(&batch.project(&output_indices)?)?;
+        }
+    }
+
+    writer.finish()?;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::FileEntry;
+    use crate::writer::ParquetFileWriter;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+    use tempfile::TempDir;
+
+    fn entry(path: &str, size: u64, owner: Option<&str>) -> FileEntry {
+        FileEntry {
+            size,
+            modified_time: 1_700_000_000 + size as i64,
+            owner: owner.map(str::to_string),
+            ..FileEntry::fixture(path)
+        }
+    }
+
+    fn fixture(dir: &Path) -> Vec<PathBuf> {
+        let chunks = [
+            vec![
+                entry("/project/lab/plain.dat", 10, Some("alice")),
+                entry("/project/lab/a,b \"quoted\".dat", 2000, None),
+            ],
+            vec![
+                entry("/project/lab/line\nbreak.dat", 3000, Some("bob")),
+                entry("/project/lab/small.dat", 5, None),
+            ],
+        ];
+        chunks.iter().enumerate().map(|(i, entries)| {
+            let path = dir.join(format!("scan_chunk_{:04}.parquet", i + 1));
+            let mut writer = ParquetFileWriter::new(&path).unwrap();
+            writer.write_batch(entries).unwrap();
+            writer.close().unwrap();
+            path
+        }).collect()
+    }
+
+    /// Minimal RFC 4180 reader: quoted fields may hold commas, quotes, and newlines
+    fn parse_csv(text: &str) -> Vec<Vec<String>> {
+        let mut rows = Vec::new();
+        let mut row = Vec::new();
+        let mut field = String::new();
+        let mut quoted = false;
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match (c, quoted) {
+                ('"', true) if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                ('"', _) => quoted = !quoted,
+                (',', false) => row.push(std::mem::take(&mut field)),
+                ('\n', false) => {
+                    row.push(std::mem::take(&mut field));
+                    rows.push(std::mem::take(&mut row));
+                }
+                (c, _) => field.push(c),
+            }
+        }
+        rows
+    }
+
+    fn columns(names: &[&str]) -> Option<Vec<String>> {
+        Some(names.iter().map(|c| c.to_string()).collect())
+    }
+
+    #[test]
+    fn test_export_gzip_csv_round_trip() {
+        let temp_dir = TempDir::new().unwrap();
+        let chunks = fixture(temp_dir.path());
+        let output = temp_dir.path().join("out.csv.gz");
+
+        let options = ExportOptions {
+            columns: columns(&["path", "size", "modified_time"]),
+            filter: Some(Expr::parse("size >= 10").unwrap()),
+        };
+        let format = QueryFormat::from_path(&output).unwrap();
+        let summary = export(&chunks, &output, format, &options).unwrap();
+        assert_eq!(summary, ExportSummary { rows_scanned: 4, rows_exported: 3 });
+
+        let mut text = String::new();
+        GzDecoder::new(File::open(&output).unwrap()).read_to_string(&mut text).unwrap();
+        assert_eq!(parse_csv(&text), [
+            vec!["path", "size", "modified_time"],
+            vec!["/project/lab/plain.dat", "10", "1700000010"],
+            vec!["/project/lab/a,b \"quoted\".dat", "2000", "1700002000"],
+            vec!["/project/lab/line\nbreak.dat", "3000", "1700003000"],
+        ]);
+    }
+
+    #[test]
+    fn test_export_jsonl_filter_on_unexported_column() {
+        let temp_dir = TempDir::new().unwrap();
+        let chunks = fixture(temp_dir.path());
+        let output = temp_dir.path().join("out.jsonl");
+
+        let options = ExportOptions {
+            columns: columns(&["size", "path"]),
+            filter: Some(Expr::parse("owner == 'bob' || size < 10").unwrap()),
+        };
+        export(&chunks, &output, QueryFormat::Jsonl, &options).unwrap();
+
+        let rows: Vec<serde_json::Value> = std::fs::read_to_string(&output).unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(rows, [
+            serde_json::json!({"size": 3000, "path": "/project/lab/line\nbreak.dat"}),
+            serde_json::json!({"size": 5, "path": "/project/lab/small.dat"}),
+        ]);
+    }
+
+    #[test]
+    fn test_export_all_columns_and_unknown_column() {
+        let temp_dir = TempDir::new().unwrap();
+        let chunks = fixture(temp_dir.path());
+        let output = temp_dir.path().join("out.csv");
+
+        let summary = export(&chunks, &output, QueryFormat::Csv, &ExportOptions::default()).unwrap();
+        assert_eq!(summary.rows_exported, 4);
+        let rows = parse_csv(&std::fs::read_to_string(&output).unwrap());
+        assert_eq!(rows[0].len(), 19);
+        assert_eq!(rows[0][0], "path");
+        // Null owners are empty fields
+        assert_eq!(rows[2][10], "");
+
+        let options = ExportOptions { columns: columns(&["path", "sise"]), filter: None };
+        let err = export(&chunks, &output, QueryFormat::Csv, &options).unwrap_err();
+        assert!(err.to_string().contains("Unknown column 'sise'"), "{}", err);
+
+        // Nothing matched still writes a header
+        let options = ExportOptions { columns: columns(&["path"]), filter: Some(Expr::parse("size > 1TB").unwrap()) };
+        export(&chunks, &output, QueryFormat::Csv, &options).unwrap();
+        assert_eq!(std::fs::read_to_string(&output).unwrap(), "path\n");
+    }
+}
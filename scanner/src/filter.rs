@@ -0,0 +1,219 @@
+use anyhow::{Context, Result};
+use arrow::array::{Array, BooleanArray, StringArray};
+use arrow::compute::kernels::comparison::{
+    eq_dyn_utf8_scalar, gt_eq_dyn_scalar, lt_dyn_scalar, neq_dyn_utf8_scalar, starts_with_utf8_scalar,
+};
+use arrow::compute::{and, filter_record_batch, or};
+use arrow::datatypes::Schema;
+use arrow::record_batch::RecordBatch;
+use std::str::FromStr;
+
+/// Which entries a `file_type` filter keeps
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TypeFilter {
+    /// Everything but directories
+    File,
+
+    /// Directories only
+    Directory,
+
+    /// Files with this `file_type` (extension, or "no_extension")
+    Extension(String),
+}
+
+impl FromStr for TypeFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "" => anyhow::bail!("File type filter must not be empty"),
+            "file" => Ok(TypeFilter::File),
+            "dir" | "directory" => Ok(TypeFilter::Directory),
+            ext => Ok(TypeFilter::Extension(ext.trim_start_matches('.').to_string())),
+        }
+    }
+}
+
+/// Row predicates; a row is kept only if it passes all of them
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RowFilter {
+    pub file_type: Option<TypeFilter>,
+
+    /// Keep rows with `size` at least this many bytes
+    pub min_size: Option<u64>,
+
+    /// Keep rows with `modified_time` before this Unix timestamp
+    pub modified_before: Option<i64>,
+
+    /// Keep rows with `accessed_time` before this Unix timestamp
+    pub accessed_before: Option<i64>,
+
+    /// Keep rows with this `path` or a path below it
+    pub path_prefix: Option<String>,
+}
+
+impl RowFilter {
+    /// Whether the filter keeps every row
+    pub fn is_empty(&self) -> bool {
+        *self == RowFilter::default()
+    }
+
+    /// Check that the schema has the columns the filter reads
+    pub fn validate(&self, schema: &Schema) -> Result<()> {
+        let columns = [
+            ("file_type", self.file_type.is_some()),
+            ("size", self.min_size.is_some()),
+            ("modified_time", self.modified_before.is_some()),
+            ("accessed_time", self.accessed_before.is_some()),
+            ("path", self.path_prefix.is_some()),
+        ];
+        for (column, used) in columns {
+            if used {
+                schema.field_with_name(column)
+                    .with_context(|| format!("Filtering requires a '{}' column", column))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Rows of `batch` that pass the filter (null values never pass)
+    pub fn mask(&self, batch: &RecordBatch) -> Result<BooleanArray> {
+        let mut mask = BooleanArray::from(vec![true; batch.num_rows()]);
+
+        if let Some(ref file_type) = self.file_type {
+            let column = column(batch, "file_type")?;
+            let keep = match file_type {
+                TypeFilter::File => neq_dyn_utf8_scalar(column, "directory")?,
+                TypeFilter::Directory => eq_dyn_utf8_scalar(column, "directory")?,
+                TypeFilter::Extension(ext) => eq_dyn_utf8_scalar(column, ext)?,
+            };
+            mask = and(&mask, &keep)?;
+        }
+
+        if let Some(min_size) = self.min_size {
+            mask = and(&mask, &gt_eq_dyn_scalar(column(batch, "size")?, min_size)?)?;
+        }
+
+        if let Some(before) = self.modified_before {
+            mask = and(&mask, &lt_dyn_scalar(column(batch, "modified_time")?, before)?)?;
+        }
+
+        if let Some(before) = self.accessed_before {
+            mask = and(&mask, &lt_dyn_scalar(column(batch, "accessed_time")?, before)?)?;
+        }
+
+        if let Some(ref prefix) = self.path_prefix {
+            let paths = column(batch, "path")?.as_any().downcast_ref::<StringArray>()
+                .ok_or_else(|| anyhow::anyhow!("Column 'path' must be a string column"))?;
+
+            // "/project/lab" matches itself and "/project/lab/...", not "/project/labs"
+            let dir = prefix.trim_end_matches('/');
+            let below = starts_with_utf8_scalar(paths, &format!("{}/", dir))?;
+            let keep = if dir.is_empty() { below } else { or(&eq_dyn_utf8_scalar(paths, dir)?, &below)? };
+            mask = and(&mask, &keep)?;
+        }
+
+        Ok(mask)
+    }
+
+    /// Drop the rows of `batch` that fail the filter
+    pub fn apply(&self, batch: &RecordBatch) -> Result<RecordBatch> {
+        if self.is_empty() {
+            return Ok(batch.clone());
+        }
+        Ok(filter_record_batch(batch, &self.mask(batch)?)?)
+    }
+}
+
+fn column<'a>(batch: &'a RecordBatch, name: &str) -> Result<&'a dyn Array> {
+    batch.column_by_name(name)
+        .map(|c| c.as_ref())
+        .ok_or_else(|| anyhow::anyhow!("Filtering requires a '{}' column", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::{Int64Array, UInt64Array};
+    use arrow::datatypes::{DataType, Field};
+    use std::sync::Arc;
+
+    /// Rows of (path, size, modified_time, file_type)
+    fn batch(rows: &[(&str, u64, i64, &str)]) -> RecordBatch {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("path", DataType::Utf8, false),
+            Field::new("size", DataType::UInt64, false),
+            Field::new("modified_time", DataType::Int64, false),
+            Field::new("file_type", DataType::Utf8, false),
+        ]));
+        RecordBatch::try_new(schema, vec![
+            Arc::new(StringArray::from(rows.iter().map(|r| r.0).collect::<Vec<_>>())),
+            Arc::new(UInt64Array::from(rows.iter().map(|r| r.1).collect::<Vec<_>>())),
+            Arc::new(Int64Array::from(rows.iter().map(|r| r.2).collect::<Vec<_>>())),
+            Arc::new(StringArray::from(rows.iter().map(|r| r.3).collect::<Vec<_>>())),
+        ]).unwrap()
+    }
+
+    fn kept(filter: &RowFilter, batch: &RecordBatch) -> Vec<String> {
+        let filtered = filter.apply(batch).unwrap();
+        let paths = filtered.column_by_name("path").unwrap().as_any().downcast_ref::<StringArray>().unwrap();
+        paths.iter().map(|p| p.unwrap().to_string()).collect()
+    }
+
+    fn fixture() -> RecordBatch {
+        batch(&[
+            ("/project/lab", 4096, 100, "directory"),
+            ("/project/lab/big.dat", 5_000_000, 100, "dat"),
+            ("/project/lab/new.dat", 5_000_000, 900, "dat"),
+            ("/project/lab/small.txt", 10, 100, "txt"),
+            ("/project/labs/other.dat", 5_000_000, 100, "dat"),
+        ])
+    }
+
+    #[test]
+    fn test_predicates_combine() {
+        let batch = fixture();
+
+        let filter = RowFilter {
+            file_type: Some(TypeFilter::File),
+            min_size: Some(1_000_000),
+            ..Default::default()
+        };
+        assert_eq!(kept(&filter, &batch), [
+            "/project/lab/big.dat", "/project/lab/new.dat", "/project/labs/other.dat",
+        ]);
+
+        let filter = RowFilter { modified_before: Some(500), ..filter };
+        assert_eq!(kept(&filter, &batch), ["/project/lab/big.dat", "/project/labs/other.dat"]);
+
+        let filter = RowFilter { path_prefix: Some("/project/lab/".to_string()), ..filter };
+        assert_eq!(kept(&filter, &batch), ["/project/lab/big.dat"]);
+
+        assert_eq!(kept(&RowFilter::default(), &batch).len(), 5);
+    }
+
+    #[test]
+    fn test_path_prefix_matches_whole_components() {
+        let filter = RowFilter { path_prefix: Some("/project/lab".to_string()), ..Default::default() };
+        assert_eq!(kept(&filter, &fixture()), [
+            "/project/lab", "/project/lab/big.dat", "/project/lab/new.dat", "/project/lab/small.txt",
+        ]);
+
+        let root = RowFilter { path_prefix: Some("/".to_string()), ..Default::default() };
+        assert_eq!(kept(&root, &fixture()).len(), 5);
+    }
+
+    #[test]
+    fn test_type_filter() {
+        assert_eq!("file".parse::<TypeFilter>().unwrap(), TypeFilter::File);
+        assert_eq!("directory".parse::<TypeFilter>().unwrap(), TypeFilter::Directory);
+        assert_eq!(".txt".parse::<TypeFilter>().unwrap(), TypeFilter::Extension("txt".to_string()));
+        assert!("".parse::<TypeFilter>().is_err());
+
+        let filter = RowFilter { file_type: Some(TypeFilter::Directory), ..Default::default() };
+        assert_eq!(kept(&filter, &fixture()), ["/project/lab"]);
+
+        let schema = Schema::new(vec![Field::new("path", DataType::Utf8, false)]);
+        assert!(filter.validate(&schema).is_err());
+    }
+}
@@ -0,0 +1,374 @@
+use crate::manifest::DirStats;
+use crate::utils;
+use anyhow::{Context, Result};
+use rand::rngs::StdRng;
+use rand::{RngExt, SeedableRng};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Extensions given to generated files, so file-type breakdowns have something to show
+const EXTENSIONS: [&str; 8] = ["dat", "txt", "csv", "bam", "h5", "nc", "py", "log"];
+
+/// Largest generated file; keeps a heavy-tailed distribution from asking for
+/// more than a filesystem allows
+const MAX_FILE_SIZE: u64 = 1 << 40;
+
+/// How generated file sizes are drawn
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum SizeDist {
+    /// Every file has this size
+    Fixed { bytes: u64 },
+
+    /// Uniform between `min` and `max` bytes, inclusive
+    Uniform { min: u64, max: u64 },
+
+    /// `exp(N(mu, sigma))` bytes; `lognormal:12,2` has a median of about 160KB
+    LogNormal { mu: f64, sigma: f64 },
+}
+
+impl Default for SizeDist {
+    fn default() -> Self {
+        SizeDist::LogNormal { mu: 12.0, sigma: 2.0 }
+    }
+}
+
+impl FromStr for SizeDist {
+    type Err = anyhow::Error;
+
+    /// `fixed:SIZE`, `uniform:MIN,MAX` (sizes like "4KB"), or `lognormal:MU,SIGMA`
+    fn from_str(s: &str) -> Result<Self> {
+        let (kind, params) = s.split_once(':')
+            .ok_or_else(|| anyhow::anyhow!("Invalid size distribution '{}': expected KIND:PARAMS", s))?;
+        let params: Vec<&str> = params.split(',').map(str::trim).collect();
+        let dist = match (kind.to_ascii_lowercase().as_str(), params.as_slice()) {
+            ("fixed", [bytes]) => SizeDist::Fixed { bytes: utils::parse_bytes(bytes)? },
+            ("uniform", [min, max]) => {
+                let (min, max) = (utils::parse_bytes(min)?, utils::parse_bytes(max)?);
+                if min > max {
+                    anyhow::bail!("Invalid size distribution '{}': minimum is above maximum", s);
+                }
+                SizeDist::Uniform { min, max }
+            }
+            ("lognormal", [mu, sigma]) => {
+                let parse = |v: &str| v.parse::<f64>()
+                    .map_err(|_| anyhow::anyhow!("Invalid size distribution '{}': '{}' is not a number", s, v));
+                let (mu, sigma) = (parse(mu)?, parse(sigma)?);
+                if !mu.is_finite() || !sigma.is_finite() || sigma < 0.0 {
+                    anyhow::bail!("Invalid size distribution '{}': sigma must be at least 0", s);
+                }
+                SizeDist::LogNormal { mu, sigma }
+            }
+            _ => anyhow::bail!(
+                "Invalid size distribution '{}': expected fixed:SIZE, uniform:MIN,MAX, or lognormal:MU,SIGMA", s
+            ),
+        };
+        Ok(dist)
+    }
+}
+
+impl SizeDist {
+    fn sample(&self, rng: &mut StdRng) -> u64 {
+        match *self {
+            SizeDist::Fixed { bytes } => bytes,
+            SizeDist::Uniform { min, max } => rng.random_range(min..=max),
+            SizeDist::LogNormal { mu, sigma } => {
+                // Box-Muller; 1 - u keeps the logarithm finite
+                let u1: f64 = 1.0 - rng.random::<f64>();
+                let u2: f64 = rng.random();
+                let z = (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos();
+                (mu + sigma * z).exp().round().min(MAX_FILE_SIZE as f64) as u64
+            }
+        }
+    }
+}
+
+/// Shape of the tree [`generate`] creates
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GenerateOptions {
+    pub files: u64,
+
+    /// Directories below the root
+    pub dirs: u64,
+
+    /// Deepest directory level below the root
+    pub depth: usize,
+
+    pub size_dist: SizeDist,
+
+    /// The same seed and options always give the same tree
+    pub seed: u64,
+
+    /// Give files their size with `set_len` instead of writing zeros
+    pub sparse: bool,
+}
+
+impl Default for GenerateOptions {
+    fn default() -> Self {
+        Self {
+            files: 1000,
+            dirs: 50,
+            depth: 4,
+            size_dist: SizeDist::default(),
+            seed: 0,
+            sparse: true,
+        }
+    }
+}
+
+/// What [`generate`] created, as written by `generate --manifest`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GeneratedTree {
+    pub root: String,
+
+    pub options: GenerateOptions,
+
+    pub files: u64,
+
+    /// Directories below the root
+    pub directories: u64,
+
+    /// Combined apparent size of the files
+    pub bytes: u64,
+
+    /// Entries and file bytes per top-level name below the root, comparable
+    /// with a scan manifest's `dir_stats` (which also counts the root itself)
+    pub top_level_dirs: BTreeMap<String, DirStats>,
+}
+
+impl GeneratedTree {
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path.as_ref(), json)
+            .with_context(|| format!("Failed to write {}", path.as_ref().display()))
+    }
+}
+
+/// A directory to create: its parent (None for the root's children), level, and name
+struct PlannedDir {
+    parent: Option<usize>,
+    depth: usize,
+    name: String,
+}
+
+/// A file to create: its directory (None for the root), name, and size
+struct PlannedFile {
+    dir: Option<usize>,
+    name: String,
+    size: u64,
+}
+
+/// Lay out the whole tree from the seed before touching the filesystem, so
+/// the layout doesn't depend on creation order
+fn plan(options: &GenerateOptions) -> (Vec<PlannedDir>, Vec<PlannedFile>) {
+    let mut rng = StdRng::seed_from_u64(options.seed);
+
+    let mut dirs: Vec<PlannedDir> = Vec::with_capacity(options.dirs as usize);
+    // Directories that can still take subdirectories
+    let mut open: Vec<usize> = Vec::new();
+    for i in 0..options.dirs {
+        // Pick the root or any directory above the depth limit, equally likely
+        let choice = rng.random_range(0..=open.len());
+        let parent = choice.checked_sub(1).map(|c| open[c]);
+        let depth = parent.map_or(1, |p| dirs[p].depth + 1);
+        dirs.push(PlannedDir { parent, depth, name: format!("dir_{:06}", i) });
+        if depth < options.depth {
+            open.push(dirs.len() - 1);
+        }
+    }
+
+    let files = (0..options.files)
+        .map(|i| {
+            let dir = match dirs.len() {
+                0 => None,
+                n => Some(rng.random_range(0..n)),
+            };
+            let extension = EXTENSIONS[rng.random_range(0..EXTENSIONS.len())];
+            PlannedFile { dir, name: format!("file_{:08}.{}", i, extension), size: options.size_dist.sample(&mut rng) }
+        })
+        .collect();
+
+    (dirs, files)
+}
+
+fn create_file(path: &Path, size: u64, sparse: bool) -> Result<()> {
+    let file = File::create(path)
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+    if sparse {
+        file.set_len(size)?;
+        return Ok(());
+    }
+
+    let zeros = [0u8; 64 * 1024];
+    let mut writer = BufWriter::new(file);
+    let mut remaining = size;
+    while remaining > 0 {
+        let n = remaining.min(zeros.len() as u64) as usize;
+        writer.write_all(&zeros[..n])?;
+        remaining -= n as u64;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Create a reproducible synthetic tree under `root`, which must not exist
+/// or be empty. Directories hang off the root or a random shallower
+/// directory; files go to random directories (the root, when there are none).
+pub fn generate(root: &Path, options: &GenerateOptions) -> Result<GeneratedTree> {
+    if options.dirs > 0 && options.depth == 0 {
+        anyhow::bail!("--depth must be at least 1 to create directories");
+    }
+    if root.exists() && fs::read_dir(root)?.next().is_some() {
+        anyhow::bail!("{} is not empty", root.display());
+    }
+    fs::create_dir_all(root)
+        .with_context(|| format!("Failed to create {}", root.display()))?;
+
+    let (dirs, files) = plan(options);
+
+    // Parents are planned before their children, so one pass creates them all
+    let mut dir_paths: Vec<PathBuf> = Vec::with_capacity(dirs.len());
+    // Top-level ancestor of each directory
+    let mut top_level: Vec<usize> = Vec::with_capacity(dirs.len());
+    let mut stats: BTreeMap<String, DirStats> = BTreeMap::new();
+    for (i, dir) in dirs.iter().enumerate() {
+        let (path, top) = match dir.parent {
+            Some(p) => (dir_paths[p].join(&dir.name), top_level[p]),
+            None => (root.join(&dir.name), i),
+        };
+        fs::create_dir(&path)
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        stats.entry(dirs[top].name.clone()).or_default().rows += 1;
+        dir_paths.push(path);
+        top_level.push(top);
+    }
+
+    files.par_iter()
+        .try_for_each(|file| {
+            let dir = file.dir.map_or(root, |d| dir_paths[d].as_path());
+            create_file(&dir.join(&file.name), file.size, options.sparse)
+        })?;
+
+    let mut bytes = 0;
+    for file in &files {
+        let top = match file.dir {
+            Some(d) => dirs[top_level[d]].name.clone(),
+            None => file.name.clone(),
+        };
+        let entry = stats.entry(top).or_default();
+        entry.rows += 1;
+        entry.bytes += file.size;
+        bytes += file.size;
+    }
+
+    Ok(GeneratedTree {
+        root: root.to_string_lossy().to_string(),
+        options: options.clone(),
+        files: files.len() as u64,
+        directories: dirs.len() as u64,
+        bytes,
+        top_level_dirs: stats,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ScanOptions;
+    use crate::scanner::scan_directory;
+    use std::os::unix::fs::MetadataExt;
+    use tempfile::TempDir;
+
+    /// (relative path, is directory, size) of everything below `root`, sorted
+    fn listing(root: &Path) -> Vec<(String, bool, u64)> {
+        let mut entries: Vec<(String, bool, u64)> = jwalk::WalkDir::new(root)
+            .into_iter()
+            .map(|e| e.unwrap().path())
+            .filter(|p| p != root)
+            .map(|p| {
+                let metadata = fs::metadata(&p).unwrap();
+                let relative = p.strip_prefix(root).unwrap().to_string_lossy().to_string();
+                (relative, metadata.is_dir(), if metadata.is_dir() { 0 } else { metadata.len() })
+            })
+            .collect();
+        entries.sort();
+        entries
+    }
+
+    #[test]
+    fn test_parse_size_dist() {
+        assert_eq!("fixed:4KB".parse::<SizeDist>().unwrap(), SizeDist::Fixed { bytes: 4000 });
+        assert_eq!("uniform:0,1MB".parse::<SizeDist>().unwrap(), SizeDist::Uniform { min: 0, max: 1_000_000 });
+        assert_eq!("lognormal:12, 2".parse::<SizeDist>().unwrap(), SizeDist::LogNormal { mu: 12.0, sigma: 2.0 });
+        assert!("uniform:2MB,1MB".parse::<SizeDist>().is_err());
+        assert!("lognormal:12".parse::<SizeDist>().is_err());
+        assert!("pareto:1,2".parse::<SizeDist>().is_err());
+    }
+
+    #[test]
+    fn test_generate_exact_counts_and_reproducible() {
+        let temp_dir = TempDir::new().unwrap();
+        let options = GenerateOptions {
+            files: 200,
+            dirs: 30,
+            depth: 3,
+            size_dist: "lognormal:10,1".parse().unwrap(),
+            seed: 7,
+            sparse: true,
+        };
+
+        let first = generate(&temp_dir.path().join("a"), &options).unwrap();
+        assert_eq!((first.files, first.directories), (200, 30));
+        let rows: u64 = first.top_level_dirs.values().map(|s| s.rows).sum();
+        assert_eq!(rows, 230);
+
+        let listing_a = listing(&temp_dir.path().join("a"));
+        assert_eq!(listing_a.iter().filter(|e| e.1).count(), 30);
+        assert_eq!(listing_a.iter().filter(|e| !e.1).map(|e| e.2).sum::<u64>(), first.bytes);
+        let deepest = listing_a.iter().filter(|e| e.1).map(|e| e.0.matches('/').count() + 1).max();
+        assert!(deepest.unwrap() <= 3);
+
+        // Same seed, same tree; another seed, another tree
+        let second = generate(&temp_dir.path().join("b"), &options).unwrap();
+        assert_eq!(listing(&temp_dir.path().join("b")), listing_a);
+        assert_eq!(second.top_level_dirs, first.top_level_dirs);
+        generate(&temp_dir.path().join("c"), &GenerateOptions { seed: 8, ..options.clone() }).unwrap();
+        assert_ne!(listing(&temp_dir.path().join("c")), listing_a);
+
+        // Sparse files take (almost) no space
+        let blocks: u64 = listing_a.iter()
+            .map(|e| fs::metadata(temp_dir.path().join("a").join(&e.0)).unwrap().blocks())
+            .sum();
+        assert!(blocks * 512 < first.bytes / 10);
+
+        // Refuses to write into a non-empty directory
+        assert!(generate(&temp_dir.path().join("a"), &options).is_err());
+    }
+
+    #[test]
+    fn test_scan_matches_generated_tree() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("synth");
+        let options = GenerateOptions {
+            files: 50,
+            dirs: 8,
+            depth: 2,
+            size_dist: SizeDist::Uniform { min: 0, max: 2000 },
+            seed: 1,
+            sparse: false,
+        };
+        let tree = generate(&root, &options).unwrap();
+
+        let entries = scan_directory(&root, ScanOptions::default()).unwrap();
+        let files: Vec<_> = entries.iter().filter(|e| e.file_type != "directory").collect();
+        assert_eq!(files.len() as u64, tree.files);
+        assert_eq!(files.iter().map(|e| e.size).sum::<u64>(), tree.bytes);
+        // The root itself is counted too
+        assert_eq!(entries.len() as u64, tree.files + tree.directories + 1);
+    }
+}
@@ -0,0 +1,524 @@
+use crate::aggregate::{find_chunk_files, is_manifest_path, parse_chunk_file_name, read_scan_tag};
+use crate::manifest::ScanManifest;
+use crate::report::{csv_field, read_columns};
+use crate::utils;
+use anyhow::{Context, Result};
+use arrow::array::{Array, StringArray, UInt64Array};
+use arrow::compute::cast;
+use arrow::datatypes::{DataType, Schema};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Group label of rows whose group column is null
+const UNKNOWN_GROUP: &str = "(unknown)";
+
+const DAY_SECS: f64 = 86_400.0;
+
+/// Column scans are totalled by
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GrowthGroupBy {
+    #[default]
+    TopLevelDir,
+
+    /// Owning user id
+    Uid,
+}
+
+impl GrowthGroupBy {
+    pub fn column(&self) -> &'static str {
+        match self {
+            GrowthGroupBy::TopLevelDir => "top_level_dir",
+            GrowthGroupBy::Uid => "uid",
+        }
+    }
+}
+
+/// How [`growth`] compares scans
+#[derive(Debug, Clone, Default)]
+pub struct GrowthOptions {
+    pub group_by: GrowthGroupBy,
+
+    /// Only use scans taken at most this many days before the newest one
+    pub window_days: Option<u64>,
+
+    /// Byte quota per group, for estimating when each will be reached
+    pub quotas: HashMap<String, u64>,
+}
+
+/// One scan found by [`find_scans`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct DatedScan {
+    /// Manifest, Parquet file, or base output path the scan was read from
+    pub source: PathBuf,
+
+    /// When the scan was taken, in Unix seconds
+    pub time: i64,
+
+    pub chunk_files: Vec<PathBuf>,
+}
+
+/// First YYYY-MM-DD date in a file name, as Unix seconds at midnight UTC
+fn date_in_name(path: &Path) -> Option<i64> {
+    let name = path.file_name()?.to_str()?;
+    let bytes = name.as_bytes();
+    (0..bytes.len().saturating_sub(9)).find_map(|start| {
+        let candidate = &bytes[start..start + 10];
+        let shaped = candidate.iter().enumerate()
+            .all(|(i, b)| if i == 4 || i == 7 { *b == b'-' } else { b.is_ascii_digit() });
+        shaped.then(|| utils::parse_timestamp(&name[start..start + 10]).ok()).flatten()
+    })
+}
+
+/// When a scan was taken: the manifest's start time, else the scan date in
+/// the first chunk's footer, else a date in the source's file name
+fn scan_time(source: &Path, chunk_files: &[PathBuf]) -> Result<Option<i64>> {
+    if is_manifest_path(source) {
+        return Ok(Some(ScanManifest::load_from_file(source)?.scan_start));
+    }
+    if let Some(first) = chunk_files.first() {
+        if let Some(date) = read_scan_tag(first)?.and_then(|tag| tag.scan_date) {
+            return Ok(Some(utils::parse_timestamp(&date)?));
+        }
+    }
+    Ok(date_in_name(source))
+}
+
+/// Scan outputs directly inside `dir`: each manifest, each set of chunk files
+/// without a manifest, and each other Parquet file
+fn scans_in_dir(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .with_context(|| format!("Failed to list {}", dir.display()))?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_file())
+        .filter_map(|entry| entry.file_name().to_str().map(str::to_string))
+        .collect();
+    names.sort();
+
+    let mut sources = Vec::new();
+    let mut chunk_stems = BTreeSet::new();
+    for name in &names {
+        if name.ends_with("_manifest.json") {
+            sources.push(dir.join(name));
+        } else if let Some((stem, _)) = parse_chunk_file_name(name) {
+            if !names.iter().any(|n| *n == format!("{}_manifest.json", stem)) {
+                chunk_stems.insert(stem);
+            }
+        } else if name.ends_with(".parquet") && !name.ends_with("_manifest.parquet") {
+            sources.push(dir.join(name));
+        }
+    }
+    // find_chunk_files resolves a base output path to its chunks
+    sources.extend(chunk_stems.into_iter().map(|stem| dir.join(format!("{}.parquet", stem))));
+    Ok(sources)
+}
+
+/// The scans named by `inputs`, oldest first. A directory stands for every
+/// scan output directly inside it; anything else is one scan, resolved as
+/// by `aggregate`. Every scan must be datable.
+pub fn find_scans(inputs: &[PathBuf]) -> Result<Vec<DatedScan>> {
+    let mut sources = Vec::new();
+    for input in inputs {
+        if input.is_dir() {
+            sources.extend(scans_in_dir(input)?);
+        } else {
+            sources.push(input.clone());
+        }
+    }
+
+    let mut scans = Vec::new();
+    for source in sources {
+        let chunk_files = find_chunk_files(&source)?;
+        if chunk_files.is_empty() {
+            anyhow::bail!("No Parquet files found in: {}", source.display());
+        }
+        let time = scan_time(&source, &chunk_files)?.with_context(|| format!(
+            "Cannot tell when {} was taken: it has no manifest, no scan_date in its metadata, and no YYYY-MM-DD in its name",
+            source.display()
+        ))?;
+        scans.push(DatedScan { source, time, chunk_files });
+    }
+    scans.sort_by(|a, b| a.time.cmp(&b.time).then_with(|| a.source.cmp(&b.source)));
+    Ok(scans)
+}
+
+/// (bytes, files) per group
+type Totals = HashMap<String, (u64, u64)>;
+
+fn validate(schema: &Schema, group_by: GrowthGroupBy) -> Result<()> {
+    for column in ["size", "file_type", group_by.column()] {
+        schema.field_with_name(column)
+            .with_context(|| format!("Growth requires a '{}' column", column))?;
+    }
+    Ok(())
+}
+
+fn chunk_totals(path: &Path, group_by: GrowthGroupBy) -> Result<Totals> {
+    let mut totals = Totals::new();
+    let columns = ["size", "file_type", group_by.column()];
+    for batch in read_columns(path, &columns, |schema| validate(schema, group_by))? {
+        let batch = batch?;
+        let sizes = cast(batch.column_by_name("size").unwrap(), &DataType::UInt64)?;
+        let sizes = sizes.as_any().downcast_ref::<UInt64Array>().unwrap();
+        let types = cast(batch.column_by_name("file_type").unwrap(), &DataType::Utf8)?;
+        let types = types.as_any().downcast_ref::<StringArray>().unwrap();
+        let keys = cast(batch.column_by_name(group_by.column()).unwrap(), &DataType::Utf8)?;
+        let keys = keys.as_any().downcast_ref::<StringArray>().unwrap();
+
+        for i in 0..batch.num_rows() {
+            // Directories have no size of their own and are not files
+            if types.is_valid(i) && types.value(i) == "directory" {
+                continue;
+            }
+            let key = if keys.is_valid(i) { keys.value(i) } else { UNKNOWN_GROUP };
+            let size = if sizes.is_valid(i) { sizes.value(i) } else { 0 };
+            let group = match totals.get_mut(key) {
+                Some(group) => group,
+                None => totals.entry(key.to_string()).or_default(),
+            };
+            group.0 += size;
+            group.1 += 1;
+        }
+    }
+    Ok(totals)
+}
+
+fn scan_totals(scan: &DatedScan, group_by: GrowthGroupBy) -> Result<Totals> {
+    scan.chunk_files.par_iter()
+        .map(|path| chunk_totals(path, group_by)
+            .with_context(|| format!("Failed to read {}", path.display())))
+        .try_reduce(Totals::new, |mut a, b| {
+            for (key, (bytes, files)) in b {
+                let group = a.entry(key).or_default();
+                group.0 += bytes;
+                group.1 += files;
+            }
+            Ok(a)
+        })
+}
+
+/// A scan used by a [`GrowthReport`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScanInfo {
+    pub source: String,
+
+    /// Unix seconds
+    pub time: i64,
+
+    /// UTC date of `time`
+    pub date: String,
+}
+
+/// One group's usage at one scan
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GroupPoint {
+    pub date: String,
+    pub bytes: u64,
+    pub files: u64,
+}
+
+/// How one group changed across the scans
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GroupGrowth {
+    pub group: String,
+
+    /// Usage at each scan, oldest first; a group missing from a scan had nothing in it
+    pub points: Vec<GroupPoint>,
+
+    /// Newest bytes minus oldest bytes
+    pub bytes_change: i64,
+
+    /// `bytes_change` relative to the oldest scan; None if the group was empty then
+    pub percent_change: Option<f64>,
+
+    pub files_change: i64,
+
+    /// Least-squares slope of bytes over time
+    pub bytes_per_day: f64,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quota: Option<u64>,
+
+    /// Days after the newest scan until the quota is reached at `bytes_per_day`:
+    /// 0 when already reached, None without a quota or when not growing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub days_until_quota: Option<f64>,
+}
+
+impl GroupGrowth {
+    pub fn last(&self) -> &GroupPoint {
+        self.points.last().unwrap()
+    }
+}
+
+/// Result of [`growth`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GrowthReport {
+    pub group_by: GrowthGroupBy,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub window_days: Option<u64>,
+
+    /// Scans compared, oldest first
+    pub scans: Vec<ScanInfo>,
+
+    /// Fastest growing first, then by group
+    pub groups: Vec<GroupGrowth>,
+}
+
+fn date_of(time: i64) -> String {
+    let time = UNIX_EPOCH + Duration::from_secs(time.max(0) as u64);
+    humantime::format_rfc3339_seconds(time).to_string()[..10].to_string()
+}
+
+/// Least-squares slope of `(days, bytes)`
+fn slope(points: &[(f64, f64)]) -> f64 {
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|p| p.0).sum::<f64>() / n;
+    let mean_y = points.iter().map(|p| p.1).sum::<f64>() / n;
+    let covariance: f64 = points.iter().map(|p| (p.0 - mean_x) * (p.1 - mean_y)).sum();
+    let variance: f64 = points.iter().map(|p| (p.0 - mean_x).powi(2)).sum();
+    if variance == 0.0 { 0.0 } else { covariance / variance }
+}
+
+fn days_until(quota: u64, bytes: u64, bytes_per_day: f64) -> Option<f64> {
+    if bytes >= quota {
+        Some(0.0)
+    } else if bytes_per_day > 0.0 {
+        Some((quota - bytes) as f64 / bytes_per_day)
+    } else {
+        None
+    }
+}
+
+/// Total each scan by group and work out how every group grew between the
+/// oldest and newest scan in the window, with a linear trend per day
+pub fn growth(scans: &[DatedScan], options: &GrowthOptions) -> Result<GrowthReport> {
+    let newest = scans.last().map(|s| s.time).unwrap_or(0);
+    let scans: Vec<&DatedScan> = scans.iter()
+        .filter(|s| options.window_days.is_none_or(|days| s.time >= newest - days as i64 * 86_400))
+        .collect();
+    if scans.len() < 2 {
+        anyhow::bail!("Growth needs at least two scans{}, found {}",
+                      if options.window_days.is_some() { " in the window" } else { "" }, scans.len());
+    }
+
+    let totals = scans.iter()
+        .map(|scan| scan_totals(scan, options.group_by))
+        .collect::<Result<Vec<_>>>()?;
+    let groups: BTreeSet<&String> = totals.iter().flat_map(|t| t.keys()).collect();
+    let start = scans[0].time;
+
+    let mut report_groups: Vec<GroupGrowth> = groups.into_iter().map(|group| {
+        let points: Vec<GroupPoint> = scans.iter().zip(&totals).map(|(scan, totals)| {
+            let (bytes, files) = totals.get(group).copied().unwrap_or_default();
+            GroupPoint { date: date_of(scan.time), bytes, files }
+        }).collect();
+        let trend: Vec<(f64, f64)> = scans.iter().zip(&points)
+            .map(|(scan, point)| ((scan.time - start) as f64 / DAY_SECS, point.bytes as f64))
+            .collect();
+        let (first, last) = (&points[0], &points[points.len() - 1]);
+        let bytes_per_day = slope(&trend);
+        let quota = options.quotas.get(group.as_str()).copied();
+        GroupGrowth {
+            group: group.clone(),
+            bytes_change: last.bytes as i64 - first.bytes as i64,
+            percent_change: (first.bytes > 0)
+                .then(|| (last.bytes as f64 - first.bytes as f64) * 100.0 / first.bytes as f64),
+            files_change: last.files as i64 - first.files as i64,
+            bytes_per_day,
+            quota,
+            days_until_quota: quota.and_then(|q| days_until(q, last.bytes, bytes_per_day)),
+            points,
+        }
+    }).collect();
+    report_groups.sort_by(|a, b| b.bytes_change.cmp(&a.bytes_change).then_with(|| a.group.cmp(&b.group)));
+
+    Ok(GrowthReport {
+        group_by: options.group_by,
+        window_days: options.window_days,
+        scans: scans.iter()
+            .map(|s| ScanInfo { source: s.source.display().to_string(), time: s.time, date: date_of(s.time) })
+            .collect(),
+        groups: report_groups,
+    })
+}
+
+fn signed_bytes(bytes: i64) -> String {
+    let sign = if bytes < 0 { "-" } else { "+" };
+    format!("{}{}", sign, utils::format_bytes(bytes.unsigned_abs()))
+}
+
+impl GrowthReport {
+    pub fn write_table(&self, out: &mut impl Write) -> io::Result<()> {
+        let (first, last) = (&self.scans[0], &self.scans[self.scans.len() - 1]);
+        writeln!(out, "Growth from {} to {} ({} scans)", first.date, last.date, self.scans.len())?;
+        let label = match self.group_by {
+            GrowthGroupBy::TopLevelDir => "Directory",
+            GrowthGroupBy::Uid => "UID",
+        };
+        writeln!(out, "{:<24}  {:>12}  {:>13}  {:>8}  {:>13}  {:>12}  {:>12}",
+                 label, "Size", "Change", "Percent", "Files change", "Per day", "Quota in")?;
+        for group in &self.groups {
+            let percent = group.percent_change.map(|p| format!("{:+.1}%", p)).unwrap_or_else(|| "new".to_string());
+            let per_day = signed_bytes(group.bytes_per_day.round() as i64);
+            let quota = match (group.quota, group.days_until_quota) {
+                (Some(_), Some(days)) => format!("{:.0} days", days),
+                (Some(_), None) => "never".to_string(),
+                (None, _) => "-".to_string(),
+            };
+            writeln!(out, "{:<24}  {:>12}  {:>13}  {:>8}  {:>13}  {:>12}  {:>12}",
+                     group.group,
+                     utils::format_bytes(group.last().bytes),
+                     signed_bytes(group.bytes_change),
+                     percent,
+                     format!("{:+}", group.files_change),
+                     per_day,
+                     quota)?;
+        }
+        Ok(())
+    }
+
+    /// One `group,date,bytes,files` line per group and scan
+    pub fn write_csv(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "group,date,bytes,files,bytes_change,percent_change,bytes_per_day,quota,days_until_quota")?;
+        for group in &self.groups {
+            let optional = |value: Option<String>| value.unwrap_or_default();
+            for point in &group.points {
+                writeln!(out, "{},{},{},{},{},{},{:.0},{},{}",
+                         csv_field(&group.group),
+                         point.date,
+                         point.bytes,
+                         point.files,
+                         group.bytes_change,
+                         optional(group.percent_change.map(|p| format!("{:.2}", p))),
+                         group.bytes_per_day,
+                         optional(group.quota.map(|q| q.to_string())),
+                         optional(group.days_until_quota.map(|d| format!("{:.1}", d))))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parse a `GROUP=SIZE` quota (e.g., "labA=10TB")
+pub fn parse_quota(input: &str) -> Result<(String, u64)> {
+    let (group, size) = input.split_once('=')
+        .with_context(|| format!("Invalid quota '{}' (expected GROUP=SIZE, e.g., labA=10TB)", input))?;
+    Ok((group.trim().to_string(), utils::parse_bytes(size)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::FileEntry;
+    use crate::writer::ParquetFileWriter;
+    use tempfile::TempDir;
+
+    const GB: u64 = 1_000_000_000;
+
+    fn entry(path: &str, size: u64) -> FileEntry {
+        let entry = if size == 0 { FileEntry::dir_fixture(path) } else { FileEntry::fixture(path) };
+        FileEntry { size, gid: 100, ..entry }
+    }
+
+    /// Three scans ten days apart: labA grows by 10GB a scan, labB shrinks,
+    /// labC appears in the second scan
+    fn fixture(dir: &Path) {
+        let scans = [
+            ("nightly_2024-05-01.parquet", vec![
+                entry("/project/labA", 0),
+                entry("/project/labA/a.dat", 100 * GB),
+                entry("/project/labB/b.dat", 50 * GB),
+            ]),
+            ("nightly_2024-05-11.parquet", vec![
+                entry("/project/labA/a.dat", 100 * GB),
+                entry("/project/labA/a2.dat", 10 * GB),
+                entry("/project/labB/b.dat", 40 * GB),
+                entry("/project/labC/c.dat", 5 * GB),
+            ]),
+            ("nightly_2024-05-21.parquet", vec![
+                entry("/project/labA/a.dat", 100 * GB),
+                entry("/project/labA/a2.dat", 10 * GB),
+                entry("/project/labA/a3.dat", 10 * GB),
+                entry("/project/labB/b.dat", 40 * GB),
+                entry("/project/labC/c.dat", 5 * GB),
+            ]),
+        ];
+        for (name, entries) in scans {
+            let mut writer = ParquetFileWriter::new(dir.join(name)).unwrap();
+            writer.write_batch(&entries).unwrap();
+            writer.close().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_growth_rates_and_quota_estimate() {
+        let temp_dir = TempDir::new().unwrap();
+        fixture(temp_dir.path());
+        let scans = find_scans(&[temp_dir.path().to_path_buf()]).unwrap();
+        assert_eq!(scans.len(), 3);
+        assert_eq!(scans[0].time, utils::parse_timestamp("2024-05-01").unwrap());
+
+        let options = GrowthOptions {
+            quotas: HashMap::from([("labA".to_string(), 150 * GB), ("labB".to_string(), 30 * GB)]),
+            ..Default::default()
+        };
+        let report = growth(&scans, &options).unwrap();
+        let dates: Vec<&str> = report.scans.iter().map(|s| s.date.as_str()).collect();
+        assert_eq!(dates, ["2024-05-01", "2024-05-11", "2024-05-21"]);
+
+        let groups: Vec<(&str, i64, i64)> = report.groups.iter()
+            .map(|g| (g.group.as_str(), g.bytes_change, g.files_change))
+            .collect();
+        assert_eq!(groups, [("labA", 20 * GB as i64, 2), ("labC", 5 * GB as i64, 1), ("labB", -10 * GB as i64, 0)]);
+
+        let lab_a = &report.groups[0];
+        assert_eq!(lab_a.percent_change, Some(20.0));
+        assert!((lab_a.bytes_per_day - GB as f64).abs() < 1.0);
+        // 30GB left at 1GB a day
+        assert!((lab_a.days_until_quota.unwrap() - 30.0).abs() < 1e-6);
+
+        assert_eq!(report.groups[1].percent_change, None);
+        assert_eq!(report.groups[1].points[0].bytes, 0);
+        // labB is shrinking but already over its quota
+        assert_eq!(report.groups[2].days_until_quota, Some(0.0));
+    }
+
+    #[test]
+    fn test_window_keeps_recent_scans() {
+        let temp_dir = TempDir::new().unwrap();
+        fixture(temp_dir.path());
+        let scans = find_scans(&[temp_dir.path().to_path_buf()]).unwrap();
+
+        let options = GrowthOptions { window_days: Some(10), ..Default::default() };
+        let report = growth(&scans, &options).unwrap();
+        assert_eq!(report.scans.len(), 2);
+        let lab_a = report.groups.iter().find(|g| g.group == "labA").unwrap();
+        assert_eq!(lab_a.bytes_change, 10 * GB as i64);
+        assert!((lab_a.percent_change.unwrap() - 100.0 / 11.0).abs() < 1e-9);
+        let lab_b = report.groups.iter().find(|g| g.group == "labB").unwrap();
+        assert_eq!((lab_b.bytes_change, lab_b.bytes_per_day), (0, 0.0));
+
+        let options = GrowthOptions { window_days: Some(5), ..Default::default() };
+        assert!(growth(&scans, &options).unwrap_err().to_string().contains("at least two scans"));
+    }
+
+    #[test]
+    fn test_scans_need_a_date() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut writer = ParquetFileWriter::new(temp_dir.path().join("latest.parquet")).unwrap();
+        writer.write_batch(&[entry("/project/labA/a.dat", GB)]).unwrap();
+        writer.close().unwrap();
+
+        let err = find_scans(&[temp_dir.path().to_path_buf()]).unwrap_err();
+        assert!(err.to_string().contains("latest.parquet"), "{}", err);
+        assert_eq!(parse_quota("labA=10TB").unwrap(), ("labA".to_string(), 10_000_000_000_000));
+        assert!(parse_quota("labA").is_err());
+    }
+}
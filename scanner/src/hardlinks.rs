@@ -0,0 +1,331 @@
+use crate::filter::{RowFilter, TypeFilter};
+use crate::report::read_columns;
+use crate::sort::ExternalSorter;
+use crate::top::TopN;
+use anyhow::{Context, Result};
+use arrow::array::{Array, ArrayRef, BooleanArray, StringArray, UInt64Array};
+use arrow::compute::{cast, filter_record_batch};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use arrow::record_batch::RecordBatch;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Columns read by [`find_hardlinks`]
+const COLUMNS: [&str; 6] = ["path", "size", "file_type", "device", "inode", "nlink"];
+
+/// What [`find_hardlinks`] keeps and how it sorts
+#[derive(Debug, Clone)]
+pub struct HardlinkOptions {
+    /// Number of largest groups to list
+    pub top: usize,
+
+    /// Memory budget for sorting linked files by inode
+    pub sort_memory: u64,
+
+    /// Scratch directory for sorting; must not exist, and is removed afterwards
+    pub spill_dir: PathBuf,
+}
+
+/// Paths of the scan that are hard links to one inode
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HardlinkGroup {
+    pub device: u64,
+
+    pub inode: u64,
+
+    /// Size of the inode, stored once on disk
+    pub size: u64,
+
+    /// Link count of the inode; more than `paths` when some links lie
+    /// outside the scan
+    pub nlink: u64,
+
+    /// In path order
+    pub paths: Vec<String>,
+}
+
+impl HardlinkGroup {
+    /// Size counted once per path, as tools unaware of hard links do
+    pub fn apparent_bytes(&self) -> u64 {
+        self.size * self.paths.len() as u64
+    }
+}
+
+// Larger apparent size first, then by first path so ties rank the same on
+// every run
+impl Ord for HardlinkGroup {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.apparent_bytes().cmp(&other.apparent_bytes())
+            .then_with(|| other.paths.cmp(&self.paths))
+    }
+}
+
+impl PartialOrd for HardlinkGroup {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HardlinkReport {
+    /// Files in the scan, linked or not
+    pub files: u64,
+
+    /// Sizes of every file, each path counted
+    pub apparent_bytes: u64,
+
+    /// `apparent_bytes` with each linked inode counted once: the true usage
+    pub corrected_bytes: u64,
+
+    /// Inodes with more than one link
+    pub groups: u64,
+
+    /// Paths to those inodes found in the scan
+    pub linked_paths: u64,
+
+    /// Sizes of the linked paths, each path counted
+    pub linked_apparent_bytes: u64,
+
+    /// Sizes of the linked inodes, each counted once
+    pub linked_deduplicated_bytes: u64,
+
+    /// Groups with fewer paths in the scan than links to the inode
+    pub partial_groups: u64,
+
+    /// Largest apparent size first
+    pub largest: Vec<HardlinkGroup>,
+}
+
+fn validate(schema: &Schema) -> Result<()> {
+    if schema.field_with_name("device").is_err() || schema.field_with_name("nlink").is_err() {
+        anyhow::bail!("This scan did not record devices and link counts; rescan with this version to report hard links");
+    }
+    for column in COLUMNS {
+        schema.field_with_name(column)
+            .with_context(|| format!("Reporting hard links requires a '{}' column", column))?;
+    }
+    Ok(())
+}
+
+/// Layout of the linked files sorted by inode
+fn linked_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("device", DataType::UInt64, true),
+        Field::new("inode", DataType::UInt64, true),
+        Field::new("path", DataType::Utf8, false),
+        Field::new("size", DataType::UInt64, true),
+        Field::new("nlink", DataType::UInt64, true),
+    ]))
+}
+
+/// Rows of a batch of files whose inode has more than one link, cast to
+/// [`linked_schema`]
+fn linked_rows(batch: &RecordBatch) -> Result<RecordBatch> {
+    let schema = linked_schema();
+    let columns = schema.fields().iter()
+        .map(|field| {
+            let column = batch.column_by_name(field.name()).context("missing column")?;
+            Ok(cast(column, field.data_type())?)
+        })
+        .collect::<Result<Vec<ArrayRef>>>()?;
+    let batch = RecordBatch::try_new(schema, columns)?;
+
+    let devices = batch.column(0).as_any().downcast_ref::<UInt64Array>().unwrap();
+    let inodes = batch.column(1).as_any().downcast_ref::<UInt64Array>().unwrap();
+    let nlinks = batch.column(4).as_any().downcast_ref::<UInt64Array>().unwrap();
+    let mask: BooleanArray = (0..batch.num_rows())
+        .map(|i| Some(devices.is_valid(i) && inodes.is_valid(i) && nlinks.is_valid(i) && nlinks.value(i) > 1))
+        .collect();
+    Ok(filter_record_batch(&batch, &mask)?)
+}
+
+/// Totals over the groups, fed one complete group at a time
+struct Tally {
+    report: HardlinkReport,
+    largest: TopN<HardlinkGroup>,
+}
+
+impl Tally {
+    fn add(&mut self, group: HardlinkGroup) {
+        let report = &mut self.report;
+        report.groups += 1;
+        report.linked_paths += group.paths.len() as u64;
+        report.linked_apparent_bytes += group.apparent_bytes();
+        report.linked_deduplicated_bytes += group.size;
+        if (group.paths.len() as u64) < group.nlink {
+            report.partial_groups += 1;
+        }
+        self.largest.push(group);
+    }
+}
+
+/// Group the files of a scan that are hard links to the same inode, by
+/// (device, inode), and correct the scan's total size by counting each
+/// inode once.
+///
+/// Linked files are sorted by inode with an external sort, so only one group
+/// at a time, plus the `top` largest, is held in memory.
+pub fn find_hardlinks(chunk_files: &[PathBuf], options: &HardlinkOptions) -> Result<HardlinkReport> {
+    if options.spill_dir.exists() {
+        anyhow::bail!("Spill directory {} already exists", options.spill_dir.display());
+    }
+    let filter = RowFilter { file_type: Some(TypeFilter::File), ..Default::default() };
+
+    let mut files = 0u64;
+    let mut apparent_bytes = 0u64;
+    let mut sorter = ExternalSorter::new(
+        &options.spill_dir,
+        linked_schema(),
+        &["device".to_string(), "inode".to_string(), "path".to_string()],
+        options.sort_memory,
+    )?;
+    for path in chunk_files {
+        let reader = read_columns(path, &COLUMNS, validate)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        for batch in reader {
+            let batch = filter.apply(&batch.with_context(|| format!("Failed to read {}", path.display()))?)?;
+            let sizes = cast(batch.column_by_name("size").unwrap(), &DataType::UInt64)?;
+            let sizes = sizes.as_any().downcast_ref::<UInt64Array>().unwrap();
+            files += batch.num_rows() as u64;
+            apparent_bytes += sizes.iter().flatten().sum::<u64>();
+            sorter.push(linked_rows(&batch)?)?;
+        }
+    }
+
+    let mut tally = Tally {
+        report: HardlinkReport {
+            files,
+            apparent_bytes,
+            corrected_bytes: 0,
+            groups: 0,
+            linked_paths: 0,
+            linked_apparent_bytes: 0,
+            linked_deduplicated_bytes: 0,
+            partial_groups: 0,
+            largest: Vec::new(),
+        },
+        largest: TopN::new(options.top),
+    };
+    let mut current: Option<HardlinkGroup> = None;
+    sorter.finish(|batch| {
+        let devices = batch.column(0).as_any().downcast_ref::<UInt64Array>().unwrap();
+        let inodes = batch.column(1).as_any().downcast_ref::<UInt64Array>().unwrap();
+        let paths = batch.column(2).as_any().downcast_ref::<StringArray>().unwrap();
+        let sizes = batch.column(3).as_any().downcast_ref::<UInt64Array>().unwrap();
+        let nlinks = batch.column(4).as_any().downcast_ref::<UInt64Array>().unwrap();
+        for i in 0..batch.num_rows() {
+            let (device, inode) = (devices.value(i), inodes.value(i));
+            match current {
+                Some(ref mut group) if group.device == device && group.inode == inode => {
+                    group.paths.push(paths.value(i).to_string());
+                }
+                _ => {
+                    if let Some(group) = current.take() {
+                        tally.add(group);
+                    }
+                    current = Some(HardlinkGroup {
+                        device,
+                        inode,
+                        size: if sizes.is_valid(i) { sizes.value(i) } else { 0 },
+                        nlink: nlinks.value(i),
+                        paths: vec![paths.value(i).to_string()],
+                    });
+                }
+            }
+        }
+        Ok(())
+    })?;
+    if let Some(group) = current {
+        tally.add(group);
+    }
+
+    let mut report = tally.report;
+    report.corrected_bytes = report.apparent_bytes
+        - (report.linked_apparent_bytes - report.linked_deduplicated_bytes);
+    report.largest = tally.largest.into_sorted_vec();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ScanOptions;
+    use crate::scanner::scan_directory;
+    use crate::writer::ParquetFileWriter;
+    use std::fs;
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    fn options(dir: &Path, top: usize) -> HardlinkOptions {
+        HardlinkOptions {
+            top,
+            // Spill every batch
+            sort_memory: 1,
+            spill_dir: dir.join("spill"),
+        }
+    }
+
+    #[test]
+    fn test_groups_and_corrected_total() {
+        let files = TempDir::new().unwrap();
+        let root = files.path();
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::write(root.join("big.dat"), vec![b'x'; 1000]).unwrap();
+        fs::hard_link(root.join("big.dat"), root.join("a/big_link.dat")).unwrap();
+        fs::hard_link(root.join("big.dat"), root.join("a/b/big_link2.dat")).unwrap();
+        fs::write(root.join("small.dat"), vec![b'y'; 100]).unwrap();
+        fs::hard_link(root.join("small.dat"), root.join("a/small_link.dat")).unwrap();
+        fs::write(root.join("plain.dat"), vec![b'z'; 10]).unwrap();
+        // Linked from outside the scanned tree
+        let outside = TempDir::new().unwrap();
+        fs::write(root.join("shared.dat"), vec![b's'; 50]).unwrap();
+        fs::hard_link(root.join("shared.dat"), outside.path().join("shared.dat")).unwrap();
+
+        let out = TempDir::new().unwrap();
+        let chunk = out.path().join("scan.parquet");
+        let mut writer = ParquetFileWriter::new(&chunk).unwrap();
+        writer.write_batch(&scan_directory(root, ScanOptions::default()).unwrap()).unwrap();
+        writer.close().unwrap();
+
+        let report = find_hardlinks(&[chunk], &options(out.path(), 1)).unwrap();
+        assert_eq!(report.files, 7);
+        assert_eq!(report.apparent_bytes, 3000 + 200 + 10 + 50);
+        assert_eq!(report.groups, 3);
+        assert_eq!(report.linked_paths, 6);
+        assert_eq!(report.linked_apparent_bytes, 3000 + 200 + 50);
+        assert_eq!(report.linked_deduplicated_bytes, 1000 + 100 + 50);
+        assert_eq!(report.corrected_bytes, 1000 + 100 + 10 + 50);
+        assert_eq!(report.partial_groups, 1);
+
+        let root = root.to_string_lossy();
+        assert_eq!(report.largest.len(), 1);
+        let largest = &report.largest[0];
+        assert_eq!((largest.size, largest.nlink), (1000, 3));
+        assert_eq!(largest.paths, [
+            format!("{}/a/b/big_link2.dat", root),
+            format!("{}/a/big_link.dat", root),
+            format!("{}/big.dat", root),
+        ]);
+        assert!(!out.path().join("spill").exists());
+    }
+
+    #[test]
+    fn test_scan_without_link_counts() {
+        let temp_dir = TempDir::new().unwrap();
+        let chunk = temp_dir.path().join("old.parquet");
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("path", DataType::Utf8, false),
+            Field::new("size", DataType::UInt64, false),
+            Field::new("file_type", DataType::Utf8, false),
+            Field::new("inode", DataType::UInt64, false),
+        ]));
+        let file = fs::File::create(&chunk).unwrap();
+        let writer = parquet::arrow::ArrowWriter::try_new(file, schema, None).unwrap();
+        writer.close().unwrap();
+
+        let err = find_hardlinks(&[chunk], &options(temp_dir.path(), 5)).unwrap_err();
+        assert!(format!("{:#}", err).contains("rescan"), "{:#}", err);
+    }
+}
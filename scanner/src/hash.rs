@@ -0,0 +1,381 @@
+use crate::aggregate::{conform_chunk_batch, reconcile_schemas, with_raw_mode};
+use crate::duplicates::CONTENT_HASH_COLUMN;
+use crate::models::classify_io_error;
+use crate::progress;
+use crate::query::Expr;
+use crate::writer::{schema_version, schema_version_entry};
+use anyhow::{Context, Result};
+use arrow::array::{Array, ArrayRef, BooleanArray, StringArray};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::metadata::KeyValue;
+use parquet::file::properties::WriterProperties;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Column holding why a file could not be hashed
+pub const ERROR_COLUMN: &str = "error";
+
+/// Parquet key-value metadata key naming the algorithm of `content_hash`
+pub const HASH_ALGO_KEY: &str = "content_hash_algo";
+
+/// Content hash algorithms
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgo {
+    /// CRC-32, as `duplicates --hash` uses for live hashing
+    Crc32,
+
+    Blake3,
+}
+
+impl HashAlgo {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HashAlgo::Crc32 => "crc32",
+            HashAlgo::Blake3 => "blake3",
+        }
+    }
+}
+
+impl fmt::Display for HashAlgo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for HashAlgo {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "crc32" => Ok(HashAlgo::Crc32),
+            "blake3" => Ok(HashAlgo::Blake3),
+            _ => Err(anyhow::anyhow!("Unknown hash algorithm '{}' (expected crc32 or blake3)", s)),
+        }
+    }
+}
+
+/// Hash a file's contents with `algo`, as lowercase hex
+pub fn hash_file(path: &Path, algo: HashAlgo) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut buffer = vec![0u8; 1 << 20];
+    let mut read = |update: &mut dyn FnMut(&[u8])| -> io::Result<()> {
+        loop {
+            let n = file.read(&mut buffer)?;
+            if n == 0 {
+                return Ok(());
+            }
+            update(&buffer[..n]);
+        }
+    };
+
+    match algo {
+        HashAlgo::Crc32 => {
+            let mut hasher = crc32fast::Hasher::new();
+            read(&mut |bytes| hasher.update(bytes))?;
+            Ok(format!("{:08x}", hasher.finalize()))
+        }
+        HashAlgo::Blake3 => {
+            let mut hasher = blake3::Hasher::new();
+            read(&mut |bytes| {
+                hasher.update(bytes);
+            })?;
+            Ok(hasher.finalize().to_hex().to_string())
+        }
+    }
+}
+
+/// Which rows [`backfill_hashes`] hashes, and how
+#[derive(Debug, Clone)]
+pub struct HashOptions {
+    /// Only hash files this expression holds for; None hashes every file
+    pub filter: Option<Expr>,
+
+    pub algo: HashAlgo,
+
+    /// Threads reading files
+    pub threads: usize,
+
+    /// Show a progress spinner on stderr
+    pub progress: bool,
+}
+
+/// What [`backfill_hashes`] did
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct HashSummary {
+    pub rows_scanned: u64,
+
+    /// Files selected for hashing
+    pub files_matched: u64,
+
+    pub files_hashed: u64,
+
+    pub bytes_hashed: u64,
+
+    /// Files that could not be read; their `error` says why
+    pub errors: u64,
+
+    pub elapsed_secs: f64,
+}
+
+/// `schema` with Utf8 `content_hash` and `error` columns, added at the end
+/// when missing
+fn output_schema(schema: &Schema) -> SchemaRef {
+    let mut fields: Vec<Field> = schema.fields().iter()
+        .map(|f| match f.name().as_str() {
+            CONTENT_HASH_COLUMN | ERROR_COLUMN => Field::new(f.name(), DataType::Utf8, true),
+            _ => f.as_ref().clone(),
+        })
+        .collect();
+    for name in [CONTENT_HASH_COLUMN, ERROR_COLUMN] {
+        if schema.field_with_name(name).is_err() {
+            fields.push(Field::new(name, DataType::Utf8, true));
+        }
+    }
+    Arc::new(Schema::new(fields))
+}
+
+/// Result of hashing one row's file: the digest and bytes read, or the error
+type RowHash = std::result::Result<(String, u64), String>;
+
+fn hash_row(path: &str, algo: HashAlgo) -> RowHash {
+    let path = Path::new(path);
+    hash_file(path, algo)
+        .and_then(|hash| Ok((hash, path.metadata()?.len())))
+        .map_err(|e| format!("{}: {}", classify_io_error(&e), e))
+}
+
+/// Rows of `batch` to hash: those `filter` selects that are not directories
+fn selected_rows(batch: &RecordBatch, filter: Option<&Expr>) -> Result<Vec<usize>> {
+    let mask = match filter {
+        Some(expr) => expr.mask(batch)?,
+        None => BooleanArray::from(vec![true; batch.num_rows()]),
+    };
+    let paths = batch.column_by_name("path")
+        .and_then(|c| c.as_any().downcast_ref::<StringArray>())
+        .context("Hashing requires a 'path' column")?;
+    let file_types = batch.column_by_name("file_type")
+        .and_then(|c| c.as_any().downcast_ref::<StringArray>());
+
+    Ok((0..batch.num_rows())
+        .filter(|&i| mask.is_valid(i) && mask.value(i) && paths.is_valid(i))
+        .filter(|&i| file_types.is_none_or(|t| t.is_null(i) || t.value(i) != "directory"))
+        .collect())
+}
+
+/// Copy of `batch` with the hashes of `rows` written into its `content_hash`
+/// and `error` columns; every other row keeps its values
+fn apply_hashes(batch: &RecordBatch, rows: &[usize], hashes: Vec<RowHash>) -> Result<RecordBatch> {
+    let column = |name: &str| -> Result<Vec<Option<String>>> {
+        let array = batch.column_by_name(name)
+            .and_then(|c| c.as_any().downcast_ref::<StringArray>())
+            .with_context(|| format!("Missing '{}' column", name))?;
+        Ok(array.iter().map(|v| v.map(str::to_string)).collect())
+    };
+    let mut content_hash = column(CONTENT_HASH_COLUMN)?;
+    let mut error = column(ERROR_COLUMN)?;
+    for (&row, result) in rows.iter().zip(hashes) {
+        match result {
+            Ok((hash, _)) => {
+                content_hash[row] = Some(hash);
+                error[row] = None;
+            }
+            Err(message) => {
+                content_hash[row] = None;
+                error[row] = Some(message);
+            }
+        }
+    }
+
+    let schema = batch.schema();
+    let columns: Vec<ArrayRef> = schema.fields().iter().zip(batch.columns())
+        .map(|(field, array)| match field.name().as_str() {
+            CONTENT_HASH_COLUMN => Arc::new(StringArray::from(std::mem::take(&mut content_hash))) as ArrayRef,
+            ERROR_COLUMN => Arc::new(StringArray::from(std::mem::take(&mut error))) as ArrayRef,
+            _ => array.clone(),
+        })
+        .collect();
+    Ok(RecordBatch::try_new(schema, columns)?)
+}
+
+/// Stream `chunk_files` to a single Parquet `output`, filling `content_hash`
+/// for the files `options.filter` selects by reading them from the live
+/// filesystem. Rows that are not selected pass through unchanged; files that
+/// cannot be read get a null hash and the reason in `error`. Both columns are
+/// added when the input lacks them, and the algorithm is recorded in the
+/// footer under [`HASH_ALGO_KEY`].
+pub fn backfill_hashes(chunk_files: &[PathBuf], output: &Path, options: &HashOptions) -> Result<HashSummary> {
+    let input_schema = with_raw_mode(reconcile_schemas(chunk_files)?);
+    if let Some(ref expr) = options.filter {
+        expr.validate(&input_schema)?;
+    }
+    let schema = output_schema(&input_schema);
+
+    let file = File::create(output)
+        .with_context(|| format!("Failed to create {}", output.display()))?;
+    let props = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .set_key_value_metadata(Some(vec![
+            schema_version_entry(),
+            KeyValue::new(HASH_ALGO_KEY.to_string(), options.algo.to_string()),
+        ]))
+        .build();
+    let mut writer = ArrowWriter::try_new(file, schema.clone(), Some(props))?;
+
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(options.threads.max(1)).build()?;
+    let mut progress = progress::spinner(options.progress);
+
+    let started = Instant::now();
+    let mut summary = HashSummary::default();
+    for path in chunk_files {
+        let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let legacy = schema_version(builder.metadata().file_metadata()).is_none();
+        for batch in builder.build()? {
+            let batch = conform_chunk_batch(&batch?, &schema, legacy)?;
+            summary.rows_scanned += batch.num_rows() as u64;
+
+            let rows = selected_rows(&batch, options.filter.as_ref())?;
+            if rows.is_empty() {
+                writer.write(&batch)?;
+                continue;
+            }
+
+            let paths = batch.column_by_name("path").unwrap().as_any().downcast_ref::<StringArray>().unwrap();
+            let hashes: Vec<RowHash> = pool.install(|| {
+                rows.par_iter().map(|&i| hash_row(paths.value(i), options.algo)).collect()
+            });
+
+            summary.files_matched += rows.len() as u64;
+            for result in &hashes {
+                match result {
+                    Ok((_, bytes)) => {
+                        summary.files_hashed += 1;
+                        summary.bytes_hashed += bytes;
+                    }
+                    Err(_) => summary.errors += 1,
+                }
+            }
+            writer.write(&apply_hashes(&batch, &rows, hashes)?)?;
+
+            let elapsed = started.elapsed().as_secs_f64();
+            progress.set_message(format!(
+                "Hashed: {} files, {:.2} GB ({:.1} MB/s), {} errors",
+                summary.files_hashed,
+                summary.bytes_hashed as f64 / 1_073_741_824.0,
+                summary.bytes_hashed as f64 / 1_048_576.0 / elapsed.max(0.001),
+                summary.errors,
+            ));
+        }
+    }
+
+    writer.close()?;
+    progress.finish("Hashing complete");
+    summary.elapsed_secs = started.elapsed().as_secs_f64();
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::FileEntry;
+    use crate::writer::ParquetFileWriter;
+    use tempfile::TempDir;
+
+    fn entry(path: &Path, size: u64, file_type: &str) -> FileEntry {
+        FileEntry { size, file_type: file_type.to_string(), gid: 100, ..FileEntry::fixture(&path.to_string_lossy()) }
+    }
+
+    fn read_column(path: &Path, name: &str) -> Vec<Option<String>> {
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap()).unwrap().build().unwrap();
+        let mut values = Vec::new();
+        for batch in reader {
+            let batch = batch.unwrap();
+            let column = batch.column_by_name(name).unwrap().as_any().downcast_ref::<StringArray>().unwrap();
+            values.extend(column.iter().map(|v| v.map(str::to_string)));
+        }
+        values
+    }
+
+    #[test]
+    fn test_hash_file_digests() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("abc.txt");
+        std::fs::write(&path, "abc").unwrap();
+
+        assert_eq!(hash_file(&path, HashAlgo::Crc32).unwrap(), "352441c2");
+        assert_eq!(
+            hash_file(&path, HashAlgo::Blake3).unwrap(),
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+        );
+        assert_eq!("BLAKE3".parse::<HashAlgo>().unwrap(), HashAlgo::Blake3);
+        assert!("md5".parse::<HashAlgo>().is_err());
+    }
+
+    #[test]
+    fn test_backfill_selected_rows() {
+        let temp_dir = TempDir::new().unwrap();
+        let data = temp_dir.path().join("data");
+        std::fs::create_dir(&data).unwrap();
+        let big = data.join("big.bam");
+        let small = data.join("small.bam");
+        let other = data.join("notes.txt");
+        std::fs::write(&big, "abc").unwrap();
+        std::fs::write(&small, "a").unwrap();
+        std::fs::write(&other, "abc").unwrap();
+        let gone = data.join("gone.bam");
+
+        let input = temp_dir.path().join("scan.parquet");
+        let mut writer = ParquetFileWriter::new(&input).unwrap();
+        writer.write_batch(&[
+            entry(&data, 0, "directory"),
+            entry(&big, 3, "bam"),
+            entry(&small, 1, "bam"),
+            entry(&other, 3, "txt"),
+            entry(&gone, 3, "bam"),
+        ]).unwrap();
+        writer.close().unwrap();
+
+        let output = temp_dir.path().join("hashed.parquet");
+        let options = HashOptions {
+            filter: Some(Expr::parse("size > 2 && file_type == 'bam'").unwrap()),
+            algo: HashAlgo::Blake3,
+            threads: 2,
+            progress: false,
+        };
+        let summary = backfill_hashes(&[input], &output, &options).unwrap();
+        assert_eq!(summary.rows_scanned, 5);
+        assert_eq!(summary.files_matched, 2);
+        assert_eq!(summary.files_hashed, 1);
+        assert_eq!(summary.bytes_hashed, 3);
+        assert_eq!(summary.errors, 1);
+
+        let hashes = read_column(&output, CONTENT_HASH_COLUMN);
+        assert_eq!(hashes[1].as_deref(), Some(hash_file(&big, HashAlgo::Blake3).unwrap().as_str()));
+        // The directory, the small file, the text file, and the missing file have no hash
+        assert_eq!(hashes.iter().filter(|h| h.is_some()).count(), 1);
+
+        let errors = read_column(&output, ERROR_COLUMN);
+        assert!(errors[4].as_deref().unwrap().starts_with("ENOENT"), "{:?}", errors[4]);
+        assert_eq!(errors.iter().filter(|e| e.is_some()).count(), 1);
+
+        // Everything else passes through
+        let paths = read_column(&output, "path");
+        assert_eq!(paths[3].as_deref(), Some(other.to_string_lossy().as_ref()));
+        assert_eq!(paths.len(), 5);
+
+        let footer = ParquetRecordBatchReaderBuilder::try_new(File::open(&output).unwrap()).unwrap();
+        assert_eq!(schema_version(footer.metadata().file_metadata()), Some(crate::writer::SCHEMA_VERSION));
+    }
+}
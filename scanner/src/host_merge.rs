@@ -0,0 +1,211 @@
+use crate::aggregate;
+use crate::manifest::ScanManifest;
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+/// How `aggregate --merge` combines scans from several hosts
+#[derive(Debug, Clone, Default)]
+pub struct HostMergeOptions {
+    /// One host per input, overriding the host its manifest records, or empty
+    pub hosts: Vec<String>,
+
+    /// Merge scans whose roots overlap, dropping duplicate paths
+    pub allow_overlap: bool,
+
+    /// Where to write the merged manifest
+    pub manifest: PathBuf,
+}
+
+/// One scan taking part in a multi-host merge
+#[derive(Debug, Clone)]
+pub struct HostSource {
+    /// Path of the scan's manifest
+    pub manifest_path: PathBuf,
+
+    pub manifest: ScanManifest,
+
+    /// Host the scan ran on: as given, else as recorded in the manifest
+    pub hostname: String,
+}
+
+/// Two sources whose scan roots cover some of the same files
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Overlap {
+    /// Index of the source whose root contains the other's
+    pub outer: usize,
+
+    /// Index of the source whose root is inside (or equal to) the other's
+    pub inner: usize,
+}
+
+/// Load the manifest each input names, with the host it was scanned on.
+///
+/// `hosts` is empty or holds one host per input, overriding the host
+/// recorded in the manifest; manifests written before hosts were recorded
+/// need one.
+pub fn load_sources(inputs: &[PathBuf], hosts: &[String]) -> Result<Vec<HostSource>> {
+    if !hosts.is_empty() && hosts.len() != inputs.len() {
+        anyhow::bail!(
+            "--host must be given once per --input ({} inputs, {} hosts)",
+            inputs.len(),
+            hosts.len()
+        );
+    }
+
+    let mut sources = Vec::with_capacity(inputs.len());
+    for (i, input) in inputs.iter().enumerate() {
+        if !aggregate::is_manifest_path(input) {
+            anyhow::bail!("--merge takes scan manifests, but {} is not one", input.display());
+        }
+        let manifest = ScanManifest::load_from_file(input)
+            .with_context(|| format!("Failed to load manifest {}", input.display()))?;
+        let hostname = hosts.get(i).cloned()
+            .or_else(|| manifest.hostname.clone())
+            .ok_or_else(|| anyhow::anyhow!(
+                "{} does not record the host it was scanned on; pass --host for each input",
+                input.display()
+            ))?;
+        sources.push(HostSource { manifest_path: input.clone(), manifest, hostname });
+    }
+    Ok(sources)
+}
+
+/// Pairs of sources with nested or equal scan roots.
+///
+/// Tasks of one sharded scan share a root but not files, so they never overlap
+/// each other.
+pub fn find_overlaps(sources: &[HostSource]) -> Vec<Overlap> {
+    let mut overlaps = Vec::new();
+    for (i, a) in sources.iter().enumerate() {
+        for (j, b) in sources.iter().enumerate().skip(i + 1) {
+            let (a_root, b_root) = (Path::new(&a.manifest.scan_path), Path::new(&b.manifest.scan_path));
+            let sharded = match (a.manifest.shard, b.manifest.shard) {
+                (Some(x), Some(y)) => x.count == y.count && x.depth == y.depth && x.index != y.index,
+                _ => false,
+            };
+            if a_root == b_root && sharded {
+                continue;
+            }
+            if b_root.starts_with(a_root) {
+                overlaps.push(Overlap { outer: i, inner: j });
+            } else if a_root.starts_with(b_root) {
+                overlaps.push(Overlap { outer: j, inner: i });
+            }
+        }
+    }
+    overlaps
+}
+
+/// One line per overlap, naming both scans and their hosts
+pub fn describe_overlaps(sources: &[HostSource], overlaps: &[Overlap]) -> Vec<String> {
+    overlaps.iter()
+        .map(|o| {
+            let (outer, inner) = (&sources[o.outer], &sources[o.inner]);
+            format!(
+                "{} ({} on {}) covers {} ({} on {})",
+                outer.manifest.scan_path,
+                outer.manifest_path.display(),
+                outer.hostname,
+                inner.manifest.scan_path,
+                inner.manifest_path.display(),
+                inner.hostname
+            )
+        })
+        .collect()
+}
+
+/// Manifest of the merged scans, listing each source manifest and its host
+pub fn merged_manifest(sources: &[HostSource]) -> Result<ScanManifest> {
+    let mut merged = ScanManifest::merge(
+        sources.iter()
+            .map(|s| (s.manifest_path.to_string_lossy().to_string(), s.manifest.clone()))
+            .collect(),
+        true,
+    )?;
+    for (merged_source, source) in merged.merged_from.iter_mut().zip(sources) {
+        merged_source.hostname = Some(source.hostname.clone());
+    }
+    let first = merged.merged_from[0].hostname.clone();
+    merged.hostname = if merged.merged_from.iter().all(|s| s.hostname == first) { first } else { None };
+    Ok(merged)
+}
+
+/// Rows read from each source, given how many chunk files each contributed
+/// and the rows read from every chunk in order
+pub fn rows_per_source(chunks_per_source: &[usize], chunk_rows: &[u64]) -> Vec<u64> {
+    let mut rows = chunk_rows.iter();
+    chunks_per_source.iter()
+        .map(|&chunks| rows.by_ref().take(chunks).sum())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shard::Shard;
+
+    fn source(scan_path: &str, hostname: &str) -> HostSource {
+        HostSource {
+            manifest_path: PathBuf::from(format!("{}_manifest.json", hostname)),
+            manifest: ScanManifest::new(scan_path.to_string()),
+            hostname: hostname.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_find_overlaps() {
+        let sources = vec![
+            source("/project/cil", "midway3"),
+            source("/scratch", "beagle"),
+            source("/project/cil/gcp", "gcp-login"),
+            source("/project/cilx", "midway2"),
+        ];
+        let overlaps = find_overlaps(&sources);
+        assert_eq!(overlaps, vec![Overlap { outer: 0, inner: 2 }]);
+        assert_eq!(
+            describe_overlaps(&sources, &overlaps),
+            vec!["/project/cil (midway3_manifest.json on midway3) covers /project/cil/gcp (gcp-login_manifest.json on gcp-login)"]
+        );
+
+        // The same root twice overlaps, unless the two are shards of one scan
+        let mut shards = vec![source("/scratch", "a"), source("/scratch", "b")];
+        assert_eq!(find_overlaps(&shards), vec![Overlap { outer: 0, inner: 1 }]);
+        shards[0].manifest.shard = Some(Shard::new(0, 2, 1).unwrap());
+        shards[1].manifest.shard = Some(Shard::new(1, 2, 1).unwrap());
+        assert!(find_overlaps(&shards).is_empty());
+    }
+
+    #[test]
+    fn test_load_sources_needs_a_host() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("old_manifest.json");
+        ScanManifest::new("/scratch".to_string()).save_to_file(&path).unwrap();
+
+        let err = load_sources(std::slice::from_ref(&path), &[]).unwrap_err();
+        assert!(err.to_string().contains("pass --host"), "{}", err);
+
+        let sources = load_sources(std::slice::from_ref(&path), &["beagle".to_string()]).unwrap();
+        assert_eq!(sources[0].hostname, "beagle");
+
+        let err = load_sources(&[temp_dir.path().to_path_buf()], &[]).unwrap_err();
+        assert!(err.to_string().contains("is not one"), "{}", err);
+    }
+
+    #[test]
+    fn test_merged_manifest_lists_hosts() {
+        let merged = merged_manifest(&[source("/project/cil", "midway3"), source("/scratch", "beagle")]).unwrap();
+        assert_eq!(merged.scan_path, "/");
+        assert_eq!(merged.hostname, None);
+        let hosts: Vec<_> = merged.merged_from.iter().map(|s| s.hostname.as_deref().unwrap()).collect();
+        assert_eq!(hosts, ["midway3", "beagle"]);
+        assert_eq!(merged.merged_from[0].manifest_path, "midway3_manifest.json");
+
+        let merged = merged_manifest(&[source("/a", "midway3"), source("/b", "midway3")]).unwrap();
+        assert_eq!(merged.hostname.as_deref(), Some("midway3"));
+    }
+
+    #[test]
+    fn test_rows_per_source() {
+        assert_eq!(rows_per_source(&[2, 0, 1], &[3, 4, 5]), vec![7, 0, 5]);
+    }
+}
@@ -0,0 +1,291 @@
+use crate::report::{csv_field, read_columns};
+use crate::sort::ExternalSorter;
+use crate::top::TopN;
+use anyhow::{Context, Result};
+use arrow::array::{Array, ArrayRef, StringArray, UInt64Array};
+use arrow::compute::cast;
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use arrow::record_batch::RecordBatch;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Columns read by [`find_hotspots`]
+const COLUMNS: [&str; 3] = ["path", "parent_path", "depth"];
+
+/// What [`find_hotspots`] ranks
+#[derive(Debug, Clone)]
+pub struct HotspotOptions {
+    /// Number of entries kept in each ranking
+    pub top: usize,
+
+    /// Only rank directories with at least this many direct children
+    pub min_entries: u64,
+
+    /// Memory budget for sorting entries by parent directory
+    pub sort_memory: u64,
+
+    /// Scratch directory for sorting; must not exist, and is removed afterwards
+    pub spill_dir: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DenseDir {
+    pub path: String,
+
+    /// Files and directories directly inside
+    pub entries: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeepPath {
+    pub path: String,
+
+    pub depth: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LongPath {
+    pub path: String,
+
+    /// In bytes, as limits such as PATH_MAX count it
+    pub length: u64,
+}
+
+// Each ranks greater first, then by path so ties rank the same on every run
+impl Ord for DenseDir {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.entries.cmp(&other.entries).then_with(|| other.path.cmp(&self.path))
+    }
+}
+
+impl PartialOrd for DenseDir {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for DeepPath {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.depth.cmp(&other.depth).then_with(|| other.path.cmp(&self.path))
+    }
+}
+
+impl PartialOrd for DeepPath {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for LongPath {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.length.cmp(&other.length).then_with(|| other.path.cmp(&self.path))
+    }
+}
+
+impl PartialOrd for LongPath {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HotspotReport {
+    pub min_entries: u64,
+
+    /// Entries scanned
+    pub entries: u64,
+
+    /// Directories with at least `min_entries` direct children
+    pub dense_directories: u64,
+
+    /// Most direct children first
+    pub dense: Vec<DenseDir>,
+
+    /// Deepest first
+    pub deepest: Vec<DeepPath>,
+
+    /// Longest first
+    pub longest: Vec<LongPath>,
+}
+
+impl HotspotReport {
+    /// One section,path,value line per ranked entry
+    pub fn write_csv(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "section,path,value")?;
+        for dir in &self.dense {
+            writeln!(out, "dense,{},{}", csv_field(&dir.path), dir.entries)?;
+        }
+        for path in &self.deepest {
+            writeln!(out, "deepest,{},{}", csv_field(&path.path), path.depth)?;
+        }
+        for path in &self.longest {
+            writeln!(out, "longest,{},{}", csv_field(&path.path), path.length)?;
+        }
+        Ok(())
+    }
+}
+
+fn validate(schema: &Schema) -> Result<()> {
+    for column in COLUMNS {
+        schema.field_with_name(column)
+            .with_context(|| format!("Finding hotspots requires a '{}' column", column))?;
+    }
+    Ok(())
+}
+
+/// Layout of the parent directories sorted to count children
+fn parent_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![Field::new("parent_path", DataType::Utf8, true)]))
+}
+
+/// Rank the directories of `chunk_files` by number of direct children, and
+/// their paths by depth and by length.
+///
+/// Parent directories are counted after an external sort, so memory holds
+/// the rankings and one directory's count at a time however many
+/// directories there are.
+pub fn find_hotspots(chunk_files: &[PathBuf], options: &HotspotOptions) -> Result<HotspotReport> {
+    if options.spill_dir.exists() {
+        anyhow::bail!("Spill directory {} already exists", options.spill_dir.display());
+    }
+
+    let mut entries = 0u64;
+    let mut deepest = TopN::new(options.top);
+    let mut longest = TopN::new(options.top);
+    let mut sorter = ExternalSorter::new(
+        &options.spill_dir,
+        parent_schema(),
+        &["parent_path".to_string()],
+        options.sort_memory,
+    )?;
+    for path in chunk_files {
+        let reader = read_columns(path, &COLUMNS, validate)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        for batch in reader {
+            let batch = batch.with_context(|| format!("Failed to read {}", path.display()))?;
+            let column = |name: &str, data_type: &DataType| -> Result<ArrayRef> {
+                Ok(cast(batch.column_by_name(name).unwrap(), data_type)?)
+            };
+            let paths = column("path", &DataType::Utf8)?;
+            let paths = paths.as_any().downcast_ref::<StringArray>().unwrap();
+            let depths = column("depth", &DataType::UInt64)?;
+            let depths = depths.as_any().downcast_ref::<UInt64Array>().unwrap();
+            for i in 0..batch.num_rows() {
+                if paths.is_null(i) {
+                    continue;
+                }
+                let path = paths.value(i);
+                if depths.is_valid(i) {
+                    deepest.push(DeepPath { path: path.to_string(), depth: depths.value(i) });
+                }
+                longest.push(LongPath { path: path.to_string(), length: path.len() as u64 });
+            }
+            entries += batch.num_rows() as u64;
+
+            let parents = column("parent_path", &DataType::Utf8)?;
+            sorter.push(RecordBatch::try_new(parent_schema(), vec![parents])?)?;
+        }
+    }
+
+    let mut dense = TopN::new(options.top);
+    let mut dense_directories = 0u64;
+    let mut count = |dir: Option<String>, children: u64| {
+        if let Some(path) = dir.filter(|_| children >= options.min_entries.max(1)) {
+            dense_directories += 1;
+            dense.push(DenseDir { path, entries: children });
+        }
+    };
+    let mut current: Option<String> = None;
+    let mut children = 0u64;
+    sorter.finish(|batch| {
+        let parents = batch.column(0).as_any().downcast_ref::<StringArray>().unwrap();
+        for i in 0..batch.num_rows() {
+            if parents.is_null(i) {
+                continue;
+            }
+            let parent = parents.value(i);
+            if current.as_deref() != Some(parent) {
+                count(current.replace(parent.to_string()), children);
+                children = 0;
+            }
+            children += 1;
+        }
+        Ok(())
+    })?;
+    count(current, children);
+
+    Ok(HotspotReport {
+        min_entries: options.min_entries,
+        entries,
+        dense_directories,
+        dense: dense.into_sorted_vec(),
+        deepest: deepest.into_sorted_vec(),
+        longest: longest.into_sorted_vec(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ScanOptions;
+    use crate::scanner::scan_directory;
+    use crate::writer::ParquetFileWriter;
+    use std::fs;
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    fn options(dir: &Path, top: usize, min_entries: u64) -> HotspotOptions {
+        HotspotOptions {
+            top,
+            min_entries,
+            // Spill every batch
+            sort_memory: 1,
+            spill_dir: dir.join("spill"),
+        }
+    }
+
+    #[test]
+    fn test_dense_directory_ranks_first() {
+        let files = TempDir::new().unwrap();
+        let root = files.path();
+        fs::create_dir_all(root.join("dense")).unwrap();
+        for i in 0..250 {
+            fs::write(root.join(format!("dense/f{:03}", i)), "").unwrap();
+        }
+        fs::create_dir_all(root.join("a/b/c/d/e")).unwrap();
+        fs::write(root.join("a/b/c/d/e/deep.txt"), "").unwrap();
+        let long_name = "n".repeat(200);
+        fs::write(root.join(&long_name), "").unwrap();
+        for i in 0..5 {
+            fs::write(root.join(format!("a/sparse{}", i)), "").unwrap();
+        }
+
+        let out = TempDir::new().unwrap();
+        let chunk = out.path().join("scan.parquet");
+        let mut writer = ParquetFileWriter::new(&chunk).unwrap();
+        writer.write_batch(&scan_directory(root, ScanOptions::default()).unwrap()).unwrap();
+        writer.close().unwrap();
+
+        let report = find_hotspots(std::slice::from_ref(&chunk), &options(out.path(), 2, 0)).unwrap();
+        let root = root.to_string_lossy();
+        assert_eq!(report.dense[0], DenseDir { path: format!("{}/dense", root), entries: 250 });
+        // The root holds dense, a, and the long-named file; a holds b and five files
+        assert_eq!(report.dense[1], DenseDir { path: format!("{}/a", root), entries: 6 });
+        assert_eq!(report.deepest[0], DeepPath { path: format!("{}/a/b/c/d/e/deep.txt", root), depth: 6 });
+        assert_eq!(report.longest[0].path, format!("{}/{}", root, long_name));
+        assert_eq!(report.longest[0].length, root.len() as u64 + 201);
+        assert!(!out.path().join("spill").exists());
+
+        let report = find_hotspots(&[chunk], &options(out.path(), 10, 100)).unwrap();
+        assert_eq!(report.dense_directories, 1);
+        assert_eq!(report.dense.len(), 1);
+
+        let mut csv = Vec::new();
+        report.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().nth(1).unwrap(), format!("dense,{}/dense,250", root));
+    }
+}
@@ -0,0 +1,139 @@
+use anyhow::Result;
+use crossbeam_channel::Sender;
+use std::net::SocketAddr;
+use std::thread::JoinHandle;
+use std::time::Instant;
+
+/// What an [`HttpServer`] handler answers a GET with
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reply {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Reply {
+    pub fn new(status: u16, content_type: &'static str, body: String) -> Self {
+        Self { status, content_type, body }
+    }
+
+    pub fn not_found() -> Self {
+        Self::new(404, "text/plain", "Not found\n".to_string())
+    }
+}
+
+/// Answers GET requests on a background thread for a running scan, until
+/// [`HttpServer::finish`]
+pub struct HttpServer {
+    addr: Option<SocketAddr>,
+    stop: Sender<Instant>,
+    thread: JoinHandle<()>,
+}
+
+impl HttpServer {
+    /// Listen on `listen` (e.g., "0.0.0.0:9200"; port 0 picks a free one),
+    /// answering GETs of a URL with `handler` on a thread called `name`
+    #[cfg(any(feature = "metrics", feature = "status-server"))]
+    pub fn start<F>(listen: &str, name: &str, handler: F) -> Result<Self>
+    where
+        F: Fn(&str) -> Reply + Send + 'static,
+    {
+        use anyhow::Context;
+        use std::time::Duration;
+        use tiny_http::{Header, Method, Response, Server};
+        use tracing::warn;
+
+        /// How often the server checks whether it should stop
+        const POLL: Duration = Duration::from_millis(100);
+
+        let server = Server::http(listen)
+            .map_err(|e| anyhow::anyhow!("Failed to listen on {}: {}", listen, e))?;
+        let addr = server.server_addr().to_ip();
+        let (stop, stop_at) = crossbeam_channel::bounded::<Instant>(1);
+
+        let thread = std::thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || {
+                let mut deadline = None;
+                while deadline.is_none_or(|d| Instant::now() < d) {
+                    if let Ok(at) = stop_at.try_recv() {
+                        deadline = Some(at);
+                    }
+                    let request = match server.recv_timeout(POLL) {
+                        Ok(Some(request)) => request,
+                        Ok(None) => continue,
+                        Err(e) => {
+                            warn!("Failed to accept a request: {}", e);
+                            continue;
+                        }
+                    };
+                    let reply = match request.method() {
+                        Method::Get => handler(request.url()),
+                        _ => Reply::not_found(),
+                    };
+                    let content_type = Header::from_bytes("Content-Type", reply.content_type).unwrap();
+                    let response = Response::from_string(reply.body)
+                        .with_status_code(reply.status)
+                        .with_header(content_type);
+                    if let Err(e) = request.respond(response) {
+                        warn!("Failed to send a response: {}", e);
+                    }
+                }
+            })
+            .with_context(|| format!("Failed to start {} thread", name))?;
+
+        Ok(Self { addr, stop, thread })
+    }
+
+    #[cfg(not(any(feature = "metrics", feature = "status-server")))]
+    pub fn start<F>(_listen: &str, _name: &str, _handler: F) -> Result<Self>
+    where
+        F: Fn(&str) -> Reply + Send + 'static,
+    {
+        anyhow::bail!("Serving HTTP requires building with --features metrics or --features status-server")
+    }
+
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.addr
+    }
+
+    /// Keep serving until `until`, so the final values get read, then stop
+    pub fn finish(self, until: Instant) -> Result<()> {
+        let _ = self.stop.send(until);
+        self.thread.join().map_err(|_| anyhow::anyhow!("HTTP server thread panicked"))
+    }
+}
+
+#[cfg(all(test, any(feature = "metrics", feature = "status-server")))]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::time::Duration;
+
+    #[test]
+    fn test_get_and_finish() {
+        let server = HttpServer::start("127.0.0.1:0", "test-http", |url| match url {
+            "/hello" => Reply::new(200, "text/plain", "hi\n".to_string()),
+            _ => Reply::not_found(),
+        }).unwrap();
+        let addr = server.local_addr().unwrap();
+        let get = |method: &str, url: &str| {
+            let mut stream = std::net::TcpStream::connect(addr).unwrap();
+            write!(stream, "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: 0\r\n\r\n", method, url).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+
+        let response = get("GET", "/hello");
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.contains("Content-Type: text/plain"), "{}", response);
+        assert!(response.ends_with("hi\n"), "{}", response);
+        assert!(get("GET", "/missing").starts_with("HTTP/1.1 404"));
+        assert!(get("POST", "/hello").starts_with("HTTP/1.1 404"));
+
+        let started = Instant::now();
+        server.finish(Instant::now()).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,281 @@
+use crate::manifest::{ChunkMetadata, ScanManifest};
+use crate::models::ErrorSummary;
+use anyhow::{Context, Result};
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Magic bytes every Parquet file starts with
+const PARQUET_MAGIC: &[u8; 4] = b"PAR1";
+
+/// One leaf column of a Parquet file, with its sizes summed over row groups
+#[derive(Debug, Clone, Serialize)]
+pub struct ColumnSummary {
+    /// Dotted path of the column
+    pub name: String,
+
+    pub physical_type: String,
+
+    /// Logical type (or legacy converted type) annotating the physical one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logical_type: Option<String>,
+
+    pub repetition: String,
+
+    pub compression: String,
+
+    /// Encodings used by any row group, in order of first use
+    pub encodings: Vec<String>,
+
+    pub compressed_bytes: u64,
+
+    pub uncompressed_bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RowGroupSummary {
+    pub index: usize,
+
+    pub rows: u64,
+
+    pub compressed_bytes: u64,
+
+    pub uncompressed_bytes: u64,
+}
+
+/// What the footer of a Parquet file says about it
+#[derive(Debug, Clone, Serialize)]
+pub struct ParquetSummary {
+    pub path: String,
+
+    /// Size of the file on disk
+    pub file_bytes: u64,
+
+    pub rows: u64,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_by: Option<String>,
+
+    pub columns: Vec<ColumnSummary>,
+
+    pub row_groups: Vec<RowGroupSummary>,
+
+    /// Key-value metadata, e.g. `scan_id` and `scan_date`
+    pub metadata: BTreeMap<String, String>,
+}
+
+/// What a scan manifest says about its scan
+#[derive(Debug, Clone, Serialize)]
+pub struct ManifestSummary {
+    pub path: String,
+
+    pub scan_path: String,
+
+    pub completed: bool,
+
+    /// Unix timestamps
+    pub scan_start: i64,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scan_end: Option<i64>,
+
+    pub total_rows: u64,
+
+    /// Combined size of the chunk files
+    pub total_bytes: u64,
+
+    pub chunk_count: usize,
+
+    pub errors: ErrorSummary,
+
+    pub chunks: Vec<ChunkMetadata>,
+
+    /// Top-level directories fully written, in name order
+    pub completed_dirs: Vec<String>,
+
+    /// Directory being scanned when the manifest was last saved
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current_dir: Option<String>,
+}
+
+/// Result of `inspect`, depending on what the input turned out to be
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Inspection {
+    Parquet(ParquetSummary),
+    Manifest(ManifestSummary),
+}
+
+/// Inspect a Parquet file or a scan manifest, telling them apart by the
+/// Parquet magic bytes
+pub fn inspect(path: &Path) -> Result<Inspection> {
+    let mut magic = [0u8; 4];
+    let mut file = File::open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let is_parquet = file.read_exact(&mut magic).is_ok() && &magic == PARQUET_MAGIC;
+
+    if is_parquet {
+        inspect_parquet(path).map(Inspection::Parquet)
+    } else {
+        inspect_manifest(path).map(Inspection::Manifest)
+    }
+}
+
+/// Strip the level from compression codecs such as `ZSTD(ZstdLevel(3))`
+fn codec_name(debug: String) -> String {
+    match debug.split_once('(') {
+        Some((name, _)) => name.to_string(),
+        None => debug,
+    }
+}
+
+pub fn inspect_parquet(path: &Path) -> Result<ParquetSummary> {
+    let file = File::open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let file_bytes = file.metadata()?.len();
+    let reader = SerializedFileReader::new(file)
+        .with_context(|| format!("Failed to read footer of {}", path.display()))?;
+    let metadata = reader.metadata();
+    let file_metadata = metadata.file_metadata();
+
+    let mut columns: Vec<ColumnSummary> = file_metadata.schema_descr().columns().iter()
+        .map(|column| {
+            let basic_info = column.self_type().get_basic_info();
+            let logical_type = column.logical_type()
+                .map(|t| format!("{:?}", t))
+                .or_else(|| match column.converted_type() {
+                    parquet::basic::ConvertedType::NONE => None,
+                    converted => Some(converted.to_string()),
+                });
+            ColumnSummary {
+                name: column.path().string(),
+                physical_type: column.physical_type().to_string(),
+                logical_type,
+                repetition: if basic_info.has_repetition() {
+                    basic_info.repetition().to_string()
+                } else {
+                    "REQUIRED".to_string()
+                },
+                compression: String::new(),
+                encodings: Vec::new(),
+                compressed_bytes: 0,
+                uncompressed_bytes: 0,
+            }
+        })
+        .collect();
+
+    let mut row_groups = Vec::with_capacity(metadata.num_row_groups());
+    for (index, row_group) in metadata.row_groups().iter().enumerate() {
+        row_groups.push(RowGroupSummary {
+            index,
+            rows: row_group.num_rows() as u64,
+            compressed_bytes: row_group.compressed_size() as u64,
+            uncompressed_bytes: row_group.total_byte_size() as u64,
+        });
+        for (summary, chunk) in columns.iter_mut().zip(row_group.columns()) {
+            summary.compression = codec_name(format!("{:?}", chunk.compression()));
+            summary.compressed_bytes += chunk.compressed_size() as u64;
+            summary.uncompressed_bytes += chunk.uncompressed_size() as u64;
+            for encoding in chunk.encodings() {
+                let encoding = format!("{:?}", encoding);
+                if !summary.encodings.contains(&encoding) {
+                    summary.encodings.push(encoding);
+                }
+            }
+        }
+    }
+
+    let metadata_pairs = file_metadata.key_value_metadata()
+        .map(|kv| kv.iter().map(|p| (p.key.clone(), p.value.clone().unwrap_or_default())).collect())
+        .unwrap_or_default();
+
+    Ok(ParquetSummary {
+        path: path.to_string_lossy().to_string(),
+        file_bytes,
+        rows: file_metadata.num_rows() as u64,
+        created_by: file_metadata.created_by().map(str::to_string),
+        columns,
+        row_groups,
+        metadata: metadata_pairs,
+    })
+}
+
+pub fn inspect_manifest(path: &Path) -> Result<ManifestSummary> {
+    let manifest = ScanManifest::load_from_file(path)
+        .with_context(|| format!("{} is neither a Parquet file nor a scan manifest", path.display()))?;
+
+    let mut completed_dirs: Vec<String> = manifest.completed_top_level_dirs.iter().cloned().collect();
+    completed_dirs.sort();
+
+    Ok(ManifestSummary {
+        path: path.to_string_lossy().to_string(),
+        total_bytes: manifest.chunks.iter().map(|c| c.file_size).sum(),
+        scan_path: manifest.scan_path,
+        completed: manifest.completed,
+        scan_start: manifest.scan_start,
+        scan_end: manifest.scan_end,
+        total_rows: manifest.total_rows,
+        chunk_count: manifest.chunk_count,
+        errors: manifest.errors,
+        chunks: manifest.chunks,
+        completed_dirs,
+        current_dir: manifest.current_top_level_dir,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::FileEntry;
+    use crate::rotating_writer::{RotatingParquetWriter, RotatingWriterConfig};
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    fn entry(path: &str, size: u64) -> FileEntry {
+        FileEntry { size, gid: 100, ..FileEntry::fixture(path) }
+    }
+
+    #[test]
+    fn test_inspect_chunk_and_manifest() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = RotatingWriterConfig {
+            base_output_path: temp_dir.path().join("scan.parquet"),
+            rows_per_chunk: 2,
+            time_interval: Duration::from_secs(3600),
+        };
+        let mut writer = RotatingParquetWriter::new(config, "/project".to_string()).unwrap();
+        for i in 0..3 {
+            writer.write_batch(&[entry(&format!("/project/lab/{}.dat", i), 100)]).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let manifest_path = temp_dir.path().join("scan_manifest.json");
+        let Inspection::Manifest(manifest) = inspect(&manifest_path).unwrap() else {
+            panic!("manifest inspected as Parquet");
+        };
+        assert_eq!(manifest.total_rows, 3);
+        assert_eq!(manifest.chunks.len(), 2);
+        assert!(manifest.completed);
+
+        let chunk = Path::new(&manifest.chunks[0].file_path);
+        let Inspection::Parquet(summary) = inspect(chunk).unwrap() else {
+            panic!("chunk inspected as a manifest");
+        };
+        assert_eq!(summary.rows, 2);
+        assert_eq!(summary.row_groups.iter().map(|g| g.rows).sum::<u64>(), 2);
+        assert!(summary.metadata.contains_key("scan_id"));
+        let path = summary.columns.iter().find(|c| c.name == "path").unwrap();
+        assert_eq!(path.physical_type, "BYTE_ARRAY");
+        assert!(path.logical_type.is_some());
+        assert!(path.compressed_bytes > 0);
+        assert!(!path.encodings.is_empty());
+
+        // Anything else is reported as an unreadable manifest
+        let other = temp_dir.path().join("notes.txt");
+        std::fs::write(&other, "hello").unwrap();
+        assert!(inspect(&other).unwrap_err().to_string().contains("neither a Parquet file"));
+    }
+}
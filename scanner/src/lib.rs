@@ -1,10 +1,145 @@
+#[cfg(feature = "parquet-writer")]
+pub mod aggregate;
+#[cfg(feature = "parquet-writer")]
+pub mod append;
+#[cfg(feature = "parquet-writer")]
+pub mod checksums;
+#[cfg(feature = "parquet-writer")]
+pub mod clean_candidates;
+#[cfg(feature = "clickhouse")]
+pub mod clickhouse;
+#[cfg(feature = "parquet-writer")]
+pub mod compare_manifests;
+#[cfg(feature = "cli")]
+pub mod config_file;
+#[cfg(feature = "parquet-writer")]
+pub mod convert;
+#[cfg(feature = "parquet-writer")]
+pub mod dedupe;
+#[cfg(feature = "parquet-writer")]
+pub mod diff;
+pub mod dir_progress;
+#[cfg(feature = "parquet-writer")]
+pub mod du;
+#[cfg(feature = "parquet-writer")]
+pub mod email;
+#[cfg(feature = "parquet-writer")]
+pub mod empty_dirs;
+pub mod error;
+#[cfg(feature = "parquet-writer")]
+pub mod duplicates;
+#[cfg(feature = "parquet-writer")]
+pub mod export;
+#[cfg(feature = "parquet-writer")]
+pub mod filter;
+#[cfg(feature = "parquet-writer")]
+pub mod generate;
+#[cfg(feature = "parquet-writer")]
+pub mod growth;
+#[cfg(feature = "parquet-writer")]
+pub mod hardlinks;
+#[cfg(feature = "parquet-writer")]
+pub mod hash;
+#[cfg(feature = "parquet-writer")]
+pub mod host_merge;
+#[cfg(feature = "parquet-writer")]
+pub mod hotspots;
+pub mod http_server;
+#[cfg(feature = "parquet-writer")]
+pub mod inspect;
+#[cfg(feature = "parquet-writer")]
+pub mod lock;
+#[cfg(feature = "parquet-writer")]
+pub mod manifest;
+#[cfg(feature = "parquet-writer")]
+pub mod memory;
+#[cfg(feature = "parquet-writer")]
+pub mod metrics;
+#[cfg(feature = "parquet-writer")]
+pub mod monitor;
 pub mod models;
+#[cfg(feature = "parquet-writer")]
+pub mod notify;
+#[cfg(feature = "parquet-writer")]
+pub mod partitioned_writer;
+#[cfg(feature = "parquet-writer")]
+pub mod permissions;
+#[cfg(feature = "parquet-writer")]
+pub mod pipeline;
+#[cfg(feature = "parquet-writer")]
+pub mod post_command;
+pub mod progress;
+#[cfg(feature = "parquet-writer")]
+pub mod query;
+#[cfg(feature = "parquet-writer")]
+pub mod quota;
+#[cfg(feature = "parquet-writer")]
+pub mod reader;
+#[cfg(feature = "remote")]
+pub mod remote;
+#[cfg(feature = "parquet-writer")]
+pub mod report;
+#[cfg(feature = "parquet-writer")]
+pub mod resume_status;
+#[cfg(feature = "parquet-writer")]
+pub mod rollup;
+#[cfg(feature = "parquet-writer")]
+pub mod run;
+#[cfg(feature = "s3")]
+pub mod s3;
 pub mod scanner;
+#[cfg(feature = "parquet-writer")]
+pub mod sort;
+#[cfg(feature = "parquet-writer")]
+pub mod spool;
+#[cfg(feature = "parquet-writer")]
+pub mod stale;
+#[cfg(feature = "parquet-writer")]
+pub mod stats;
+pub mod status;
+#[cfg(feature = "parquet-writer")]
+pub mod symlinks;
+#[cfg(feature = "parquet-writer")]
+pub mod top;
+#[cfg(feature = "parquet-writer")]
+pub mod tree;
+#[cfg(feature = "tui")]
+pub mod tui;
+#[cfg(feature = "parquet-writer")]
+pub mod user_report;
+#[cfg(feature = "parquet-writer")]
 pub mod writer;
+#[cfg(feature = "parquet-writer")]
 pub mod rotating_writer;
+#[cfg(feature = "parquet-writer")]
+pub mod sample;
+#[cfg(feature = "parquet-writer")]
+pub mod schedule;
+#[cfg(feature = "serve")]
+pub mod serve;
+pub mod shard;
+#[cfg(feature = "parquet-writer")]
+pub mod slack;
+pub mod sink;
+#[cfg(feature = "async")]
+pub mod stream;
 pub mod utils;
+#[cfg(feature = "parquet-writer")]
+pub mod validate;
+#[cfg(feature = "parquet-writer")]
+pub mod verify;
+#[cfg(feature = "watch")]
+pub mod watch;
 
-pub use models::{FileEntry, ScanOptions, ScanStats};
-pub use scanner::{Scanner, scan_directory};
-pub use writer::{ParquetFileWriter, write_to_parquet};
-pub use rotating_writer::{RotatingParquetWriter, RotatingWriterConfig, ScanManifest};
+pub use error::Error;
+pub use models::{FileEntry, ScanOptions, ScanOptionsBuilder, ScanStats};
+pub use scanner::{ScanError, ScanIter, Scanner, scan_directory};
+pub use sink::EntrySink;
+#[cfg(feature = "parquet-writer")]
+pub use {
+    manifest::{ChunkMetadata, ScanManifest},
+    reader::{ChunkSetReader, ParquetEntryReader, read_all},
+    rotating_writer::{RotatingParquetWriter, RotatingWriterConfig},
+    run::{ScanResult, SinkConfig, run_scan_to_parquet},
+    writer::{ParquetFileWriter, write_to_parquet, write_to_parquet_with},
+};
@@ -0,0 +1,255 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use tracing::{info, warn};
+
+/// Lock files held by this process, removed by the signal handler on shutdown
+static ACTIVE_LOCKS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Lock attempts made by this process, naming each one's staged file
+static ATTEMPTS: AtomicU64 = AtomicU64::new(0);
+
+/// Contents of a lock file
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct LockInfo {
+    /// Process ID of the scan holding the lock
+    pub pid: u32,
+
+    /// Host the scan is running on
+    pub hostname: String,
+
+    /// Lock acquisition time (Unix timestamp)
+    pub started_at: i64,
+}
+
+impl LockInfo {
+    fn current() -> Self {
+        use std::time::SystemTime;
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        Self {
+            pid: std::process::id(),
+            hostname: hostname(),
+            started_at: now,
+        }
+    }
+
+    /// Whether the holding process is known to be gone.
+    ///
+    /// Only processes on this host can be checked; locks from other hosts
+    /// are never considered stale.
+    pub fn is_stale(&self) -> bool {
+        self.hostname == hostname() && !pid_alive(self.pid)
+    }
+}
+
+/// Exclusive lock on a scan output path, released on drop
+#[derive(Debug)]
+pub struct ScanLock {
+    path: PathBuf,
+}
+
+impl ScanLock {
+    /// Lock file path for an output path (`<output_stem>.lock` next to it)
+    pub fn lock_path_for(output: &Path) -> PathBuf {
+        let parent = output.parent().unwrap_or_else(|| Path::new("."));
+        let stem = output.file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_else(|| "scan".to_string());
+        parent.join(format!("{}.lock", stem))
+    }
+
+    /// Acquire the lock for an output path.
+    ///
+    /// A lock left behind by a process that no longer exists on this host is
+    /// broken automatically; any other existing lock is refused unless
+    /// `break_lock` is set.
+    pub fn acquire(output: &Path, break_lock: bool) -> Result<Self> {
+        let path = Self::lock_path_for(output);
+
+        match Self::try_create(&path) {
+            Ok(lock) => return Ok(lock),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to create lock file {}", path.display()));
+            }
+        }
+
+        let holder = fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| serde_json::from_str::<LockInfo>(&contents).ok());
+
+        match holder {
+            Some(info) if break_lock => {
+                warn!("Breaking lock {} held by pid {} on {}", path.display(), info.pid, info.hostname);
+            }
+            Some(info) if info.is_stale() => {
+                warn!("Removing stale lock {} left by pid {} (no longer running)", path.display(), info.pid);
+            }
+            Some(info) => {
+                anyhow::bail!(
+                    "Output is locked by pid {} on {} since {} ({}). Another scan may be writing to it; use --break-lock if it is stale.",
+                    info.pid,
+                    info.hostname,
+                    info.started_at,
+                    path.display()
+                );
+            }
+            None if break_lock => {
+                warn!("Breaking unreadable lock {}", path.display());
+            }
+            None => {
+                anyhow::bail!(
+                    "Lock file {} exists but is unreadable; use --break-lock to remove it",
+                    path.display()
+                );
+            }
+        }
+
+        fs::remove_file(&path)
+            .with_context(|| format!("Failed to remove lock file {}", path.display()))?;
+
+        Self::try_create(&path)
+            .with_context(|| format!("Failed to create lock file {}", path.display()))
+    }
+
+    /// Create the lock file, holding this process's details from the start.
+    ///
+    /// The details are written to a file of this attempt's own, then linked
+    /// to the lock path, which fails if the lock exists; another scan never
+    /// sees a lock file still empty.
+    fn try_create(path: &Path) -> std::io::Result<Self> {
+        let attempt = ATTEMPTS.fetch_add(1, Ordering::Relaxed);
+        let staged = PathBuf::from(format!("{}.{}.{}", path.display(), std::process::id(), attempt));
+        let mut file = OpenOptions::new().write(true).create_new(true).open(&staged)?;
+
+        let info = serde_json::to_string(&LockInfo::current())?;
+        let linked = file.write_all(info.as_bytes()).and_then(|_| fs::hard_link(&staged, path));
+        let _ = fs::remove_file(&staged);
+        linked?;
+
+        if let Ok(mut locks) = ACTIVE_LOCKS.lock() {
+            locks.push(path.to_path_buf());
+        }
+
+        info!("Acquired lock: {}", path.display());
+        Ok(Self { path: path.to_path_buf() })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Release the lock explicitly
+    pub fn release(self) {
+        // Removal happens in Drop
+    }
+}
+
+impl Drop for ScanLock {
+    fn drop(&mut self) {
+        if let Ok(mut locks) = ACTIVE_LOCKS.lock() {
+            locks.retain(|p| p != &self.path);
+        }
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("Failed to remove lock file {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Remove every lock file held by this process.
+///
+/// Intended for signal handlers, where destructors will not run.
+pub fn release_all_locks() {
+    if let Ok(mut locks) = ACTIVE_LOCKS.lock() {
+        for path in locks.drain(..) {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// This machine's name, or "unknown" if it can't be read
+pub fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    let ret = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    if ret != 0 {
+        return "unknown".to_string();
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).to_string()
+}
+
+fn pid_alive(pid: u32) -> bool {
+    let ret = unsafe { libc::kill(pid as libc::pid_t, 0) };
+    ret == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Barrier};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_concurrent_acquire_only_one_wins() {
+        let temp_dir = TempDir::new().unwrap();
+        let output = temp_dir.path().join("scan.parquet");
+        let barrier = Arc::new(Barrier::new(2));
+
+        let handles: Vec<_> = (0..2).map(|_| {
+            let output = output.clone();
+            let barrier = barrier.clone();
+            std::thread::spawn(move || {
+                barrier.wait();
+                let lock = ScanLock::acquire(&output, false);
+                // Hold the lock until both threads have tried
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                lock.is_ok()
+            })
+        }).collect();
+
+        let acquired = handles.into_iter().map(|h| h.join().unwrap()).filter(|ok| *ok).count();
+        assert_eq!(acquired, 1);
+        assert!(!ScanLock::lock_path_for(&output).exists());
+    }
+
+    #[test]
+    fn test_stale_lock_is_broken() {
+        let temp_dir = TempDir::new().unwrap();
+        let output = temp_dir.path().join("scan.parquet");
+        let lock_path = ScanLock::lock_path_for(&output);
+
+        // A pid beyond pid_max can never be alive
+        let stale = LockInfo { pid: 99_999_999, hostname: hostname(), started_at: 0 };
+        fs::write(&lock_path, serde_json::to_string(&stale).unwrap()).unwrap();
+
+        let lock = ScanLock::acquire(&output, false).unwrap();
+        let info: LockInfo = serde_json::from_str(&fs::read_to_string(&lock_path).unwrap()).unwrap();
+        assert_eq!(info.pid, std::process::id());
+
+        lock.release();
+        assert!(!lock_path.exists());
+    }
+
+    #[test]
+    fn test_live_lock_refused_unless_broken() {
+        let temp_dir = TempDir::new().unwrap();
+        let output = temp_dir.path().join("scan.parquet");
+        let lock_path = ScanLock::lock_path_for(&output);
+
+        let foreign = LockInfo { pid: 1, hostname: "other-node".to_string(), started_at: 0 };
+        fs::write(&lock_path, serde_json::to_string(&foreign).unwrap()).unwrap();
+
+        let err = ScanLock::acquire(&output, false).unwrap_err();
+        assert!(err.to_string().contains("other-node"));
+
+        let _lock = ScanLock::acquire(&output, true).unwrap();
+    }
+}
@@ -1,16 +1,70 @@
 use anyhow::{Context, Result};
-use clap::{Parser, Subcommand};
-use crossbeam_channel::bounded;
-use std::path::PathBuf;
-use std::time::Duration;
+use clap::{ArgGroup, CommandFactory, Parser, Subcommand, ValueEnum};
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use storage_scanner::{
-    models::ScanOptions,
+    aggregate::{self, AggregateInput, AggregateOptions, DedupeKey, RetentionAction, RetentionPolicy},
+    append,
+    checksums::{self, ChecksumAlgo, ChecksumHook, ChecksumList},
+    clean_candidates::{self, CleanPolicy},
+    compare_manifests,
+    config_file,
+    convert::{self, ConvertOptions},
+    diff::{self, DiffOptions},
+    du::{self, DuOptions, DuOrder},
+    email::{self, EmailOptions, SmtpTls},
+    empty_dirs,
+    duplicates::{self, DuplicateOptions, MatchMethod},
+    export::{self, ExportOptions},
+    filter::{RowFilter, TypeFilter},
+    generate::{self, GenerateOptions, SizeDist},
+    growth::{self, GrowthGroupBy, GrowthOptions},
+    hardlinks::{self, HardlinkOptions},
+    hash::{self, HashAlgo, HashOptions},
+    host_merge::{self, HostMergeOptions},
+    hotspots::{self, HotspotOptions},
+    http_server::HttpServer,
+    inspect::{self, Inspection, ManifestSummary, ParquetSummary},
+    lock::{self, ScanLock},
+    manifest::{ChunkUpload, DirStats, ScanManifest},
+    metrics::{self, ScanMetrics},
+    models::{ScanOptions, ScanStats},
+    notify::{self, ScanNotification, ScanOutcome, WebhookOptions},
+    partitioned_writer,
+    permissions::{self, PermissionOptions},
+    pipeline::{self, PipelineConfig, StageStatus},
+    post_command::{self, ChunkCommand, OnError, PostCommands},
+    progress::Baseline,
+    query::{self, Expr, QueryFormat},
+    quota::{self, QuotaConfig, QuotaLevel},
+    report::{self, StorageReport},
+    resume_status::{self, DirState},
+    sample::{self, SampleMode, SampleOptions},
+    run::{ScanResult, SinkConfig},
     scanner::Scanner,
-    utils,
-    writer::write_to_parquet,
-    rotating_writer::{RotatingParquetWriter, RotatingWriterConfig},
+    schedule::{self, ScheduleConfig, Scheduler},
+    shard::{self, Shard},
+    slack::{self, ErrorThresholds},
+    sort,
+    spool::{self, ObjectStore, SpoolOptions, SpoolUploader},
+    stale::{self, StaleGroupBy, StaleOptions},
+    stats::UsageStats,
+    status::{self, ScanCounters, ScanState, StatusCounts, StatusTracker},
+    symlinks::{self, Symlink},
+    top::{self, TopOptions},
+    tree::{self, TreeOptions, TreeStyle},
+    user_report::{self, UserReportOptions},
+    utils::{self, OutputKind},
+    validate::{self, CheckStatus},
+    verify,
+    rotating_writer::{ChunkHook, RotatingParquetWriter, RotatingWriterConfig},
 };
-use tracing::{error, info};
+use tracing::{error, info, warn};
 use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 
 #[derive(Parser)]
@@ -23,38 +77,53 @@ struct Cli {
     /// Enable verbose logging
     #[arg(short, long, global = true)]
     verbose: bool,
+
+    /// TOML file of options, in a [section] per subcommand; flags given here override it
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
 }
 
+// Parsed once per run, so the Scan variant's size costs nothing
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand)]
 enum Commands {
     /// Scan a directory and output to Parquet file
+    #[command(group(ArgGroup::new("notify_target").multiple(true).args(["notify_webhook", "notify_slack_webhook"])))]
     Scan {
         /// Path to scan
         #[arg(short, long)]
         path: PathBuf,
 
-        /// Output Parquet file path
-        #[arg(short, long)]
+        /// Output Parquet file path, or s3://bucket/prefix/name.parquet (needs the s3 feature and --incremental)
+        #[arg(short, long, env = "STORAGE_SCANNER_OUTPUT")]
         output: PathBuf,
 
         /// Number of threads to use (default: number of CPU cores)
-        #[arg(short, long)]
+        #[arg(short, long, env = "STORAGE_SCANNER_THREADS")]
         threads: Option<usize>,
 
         /// Batch size for writing to Parquet
-        #[arg(short, long, default_value = "100000")]
+        #[arg(short, long, default_value = "100000", env = "STORAGE_SCANNER_BATCH_SIZE")]
         batch_size: usize,
 
+        /// Entries queued between the walk and the writer before the walk waits
+        #[arg(long, default_value = "65536", env = "STORAGE_SCANNER_CHANNEL_CAPACITY")]
+        channel_capacity: usize,
+
+        /// Parquet codec: none, snappy, gzip, zstd, lz4, or brotli (default: snappy)
+        #[arg(long, value_parser = convert::parse_compression, env = "STORAGE_SCANNER_COMPRESSION")]
+        compression: Option<parquet::basic::Compression>,
+
         /// Follow symbolic links
         #[arg(short, long)]
         follow_symlinks: bool,
 
-        /// Maximum depth to scan (unlimited if not specified)
+        /// Deepest level to scan: 0 is the root alone, 1 adds its immediate children (unlimited if not specified)
         #[arg(short, long)]
         max_depth: Option<usize>,
 
         /// Log file path (optional)
-        #[arg(short, long)]
+        #[arg(short, long, env = "STORAGE_SCANNER_LOG_FILE")]
         log_file: Option<PathBuf>,
 
         /// Enable incremental output mode (creates multiple readable files during scan)
@@ -62,471 +131,4123 @@ enum Commands {
         incremental: bool,
 
         /// Rows per chunk when using incremental mode
-        #[arg(long, default_value = "500000")]
+        #[arg(long, default_value = "500000", env = "STORAGE_SCANNER_ROWS_PER_CHUNK")]
         rows_per_chunk: usize,
 
         /// Time interval in seconds between chunks (used alongside rows_per_chunk)
-        #[arg(long, default_value = "300")]
+        #[arg(long, default_value = "300", env = "STORAGE_SCANNER_CHUNK_INTERVAL_SECS")]
         chunk_interval_secs: u64,
 
+        /// Write <output stem>.CHECKSUMS listing a sha256 or xxh3 checksum of each chunk and the manifest, for checking transfers
+        #[arg(long, requires = "incremental", env = "STORAGE_SCANNER_EMIT_CHECKSUMS")]
+        emit_checksums: Option<ChecksumAlgo>,
+
         /// Resume an interrupted scan (only works with --incremental mode)
         #[arg(long)]
         resume: bool,
-    },
 
-    /// Aggregate multiple Parquet chunk files into a single file
-    Aggregate {
-        /// Input pattern or directory containing chunk files (e.g., scan_chunk_*.parquet or /path/to/chunks/)
-        #[arg(short, long)]
-        input: PathBuf,
+        /// On resume, use the given chunking parameters instead of the original scan's
+        #[arg(long, requires = "resume")]
+        override_chunking: bool,
 
-        /// Output Parquet file path
-        #[arg(short, long)]
-        output: PathBuf,
+        /// Remove an existing lock on the output even if its holder may still be running
+        #[arg(long)]
+        break_lock: bool,
 
-        /// Delete chunk files after successful aggregation
-        #[arg(short, long)]
-        delete_chunks: bool,
-    },
+        /// Scan the output's own files too when it is written inside the scanned tree
+        #[arg(long)]
+        allow_output_in_scan_path: bool,
 
-    /// Display version information
-    Version,
-}
+        /// This task's shard of a sharded scan, from 0 (e.g., $SLURM_ARRAY_TASK_ID)
+        #[arg(long, requires = "shard_count", env = "STORAGE_SCANNER_SHARD_INDEX")]
+        shard_index: Option<u32>,
 
-fn main() -> Result<()> {
-    let cli = Cli::parse();
+        /// Split the scan into this many shards, each task scanning one and writing <output>_shard_<index> chunks and manifest
+        #[arg(long, requires_all = ["shard_index", "incremental"], env = "STORAGE_SCANNER_SHARD_COUNT")]
+        shard_count: Option<u32>,
 
-    // Setup logging
-    setup_logging(cli.verbose)?;
+        /// Level whose directories are assigned to shards: 1 for top-level directories, 2 for the ones below them
+        #[arg(long, default_value = "1", requires = "shard_count", env = "STORAGE_SCANNER_SHARD_DEPTH")]
+        shard_depth: usize,
 
-    match cli.command {
-        Commands::Scan {
-            path,
-            output,
-            threads,
-            batch_size,
-            follow_symlinks,
-            max_depth,
-            log_file: _,
-            incremental,
-            rows_per_chunk,
-            chunk_interval_secs,
-            resume,
-        } => {
-            run_scan(
-                path,
-                output,
-                threads,
-                batch_size,
-                follow_symlinks,
-                max_depth,
-                incremental,
-                rows_per_chunk,
-                chunk_interval_secs,
-                resume,
-            )?;
-        }
-        Commands::Aggregate {
-            input,
-            output,
-            delete_chunks,
-        } => {
-            run_aggregate(input, output, delete_chunks)?;
-        }
-        Commands::Version => {
-            println!("storage-scanner v{}", env!("CARGO_PKG_VERSION"));
-            println!("High-performance filesystem scanner for storage analytics");
-        }
-    }
+        /// Manifest from a previous scan of the same tree, used for percent complete and ETA
+        #[arg(long)]
+        baseline: Option<PathBuf>,
 
-    Ok(())
-}
+        /// Keep a JSON snapshot of the scan's progress in this file, for monitor
+        #[arg(long, env = "STORAGE_SCANNER_STATUS_FILE")]
+        status_file: Option<PathBuf>,
 
-fn setup_logging(verbose: bool) -> Result<()> {
-    let filter = if verbose {
-        EnvFilter::new("debug")
-    } else {
-        EnvFilter::new("info")
-    };
+        /// Write the finished scan's counts, errors by kind, and skipped entries as JSON to this file
+        #[arg(long, env = "STORAGE_SCANNER_STATS_OUTPUT")]
+        stats_output: Option<PathBuf>,
 
-    tracing_subscriber::registry()
-        .with(fmt::layer().with_target(false))
-        .with(filter)
-        .init();
+        /// Exit with status 3 if the scan counted more than this many errors (default: never)
+        #[arg(long, env = "STORAGE_SCANNER_FAIL_ON_ERRORS")]
+        fail_on_errors: Option<u64>,
 
-    Ok(())
-}
+        /// Upload each chunk as it completes by PUT under this URL (needs the remote feature)
+        #[arg(long, requires = "incremental", env = "STORAGE_SCANNER_UPLOAD_URL")]
+        upload_url: Option<String>,
 
-fn run_scan(
-    path: PathBuf,
-    output: PathBuf,
-    threads: Option<usize>,
-    batch_size: usize,
-    follow_symlinks: bool,
-    max_depth: Option<usize>,
-    incremental: bool,
-    rows_per_chunk: usize,
-    chunk_interval_secs: u64,
-    resume: bool,
-) -> Result<()> {
-    info!("Storage Scanner v{}", env!("CARGO_PKG_VERSION"));
-    info!("Starting scan operation");
+        /// Further attempts after a failed chunk upload, with doubling backoff
+        #[arg(long, default_value = "5", requires = "upload_url", env = "STORAGE_SCANNER_UPLOAD_RETRIES")]
+        upload_retries: u32,
 
-    // Validate input path
-    utils::validate_path(&path)
-        .context("Invalid input path")?;
+        /// Endpoint of an S3-compatible server such as MinIO, for an s3:// output
+        #[arg(long, env = "STORAGE_SCANNER_S3_ENDPOINT")]
+        s3_endpoint: Option<String>,
 
-    // Ensure output directory exists
-    utils::ensure_output_dir(&output)
-        .context("Failed to create output directory")?;
+        /// Local directory chunks of an s3:// output are written to before upload (default: current directory)
+        #[arg(long, env = "STORAGE_SCANNER_SPOOL_DIR")]
+        spool_dir: Option<PathBuf>,
 
-    // Configure scan options
-    let options = ScanOptions {
-        num_threads: threads.unwrap_or_else(num_cpus::get),
-        batch_size,
-        follow_symlinks,
-        max_depth,
-        enable_checkpointing: false,
-        checkpoint_path: None,
-    };
+        /// Shell command run per completed chunk; {chunk}, {manifest}, {chunk_number}, and {rows} are substituted
+        #[arg(long, requires = "incremental")]
+        post_chunk_cmd: Option<String>,
 
-    info!("Scan configuration:");
-    info!("  Input path: {}", path.display());
-    info!("  Output file: {}", output.display());
-    info!("  Threads: {}", options.num_threads);
-    info!("  Batch size: {}", utils::format_number(options.batch_size as u64));
-    info!("  Follow symlinks: {}", options.follow_symlinks);
-    if let Some(depth) = options.max_depth {
-        info!("  Max depth: {}", depth);
-    }
+        /// Shell command run once the manifest is finalized; {manifest} and {rows} are substituted
+        #[arg(long, requires = "incremental")]
+        post_scan_cmd: Option<String>,
 
-    // Validate resume mode
-    if resume && !incremental {
-        error!("Resume mode requires --incremental flag");
-        return Err(anyhow::anyhow!("--resume requires --incremental"));
-    }
+        /// What a failed post-chunk or post-scan command does to the scan
+        #[arg(long, value_enum, default_value = "warn")]
+        post_chunk_on_error: OnErrorArg,
 
-    if incremental {
-        info!("  Incremental mode: ENABLED");
-        info!("  Rows per chunk: {}", utils::format_number(rows_per_chunk as u64));
-        info!("  Chunk interval: {} seconds", chunk_interval_secs);
-        if resume {
-            info!("  Resume mode: ENABLED");
-        }
-        info!("");
-        info!("Note: Each chunk will be a complete, readable Parquet file.");
-        info!("      You can read chunks while the scan is still running.");
-    }
+        /// POST a JSON notification here when the scan completes, fails, or is interrupted (needs the remote feature)
+        #[arg(long, env = "STORAGE_SCANNER_NOTIFY_WEBHOOK")]
+        notify_webhook: Option<String>,
 
-    // Create channels for communication
-    let (tx, rx) = bounded(batch_size * 2);
+        /// File holding a shared secret; notifications are signed with HMAC-SHA256 in X-Storage-Scanner-Signature
+        #[arg(long, requires = "notify_webhook", env = "STORAGE_SCANNER_NOTIFY_SECRET_FILE")]
+        notify_secret_file: Option<PathBuf>,
 
-    // Create scanner
-    let scanner = Scanner::new(options);
+        /// POST a Slack message here when the scan ends; Mattermost incoming webhooks take it too (needs the remote feature)
+        #[arg(long, env = "STORAGE_SCANNER_NOTIFY_SLACK_WEBHOOK")]
+        notify_slack_webhook: Option<String>,
 
-    // Spawn writer thread based on mode
-    let output_clone = output.clone();
-    let path_str = path.to_string_lossy().to_string();
+        /// Errors at which a completed scan's Slack message turns yellow
+        #[arg(long, default_value = "1", requires = "notify_slack_webhook")]
+        slack_warn_errors: u64,
 
-    // Run scanner and writer based on mode
-    let (stats, rows_written) = if incremental {
-        // Use rotating writer for incremental mode
-        let config = RotatingWriterConfig {
-            base_output_path: output_clone.clone(),
-            rows_per_chunk,
-            time_interval: Duration::from_secs(chunk_interval_secs),
-        };
+        /// Errors at which a completed scan's Slack message turns red
+        #[arg(long, default_value = "1000", requires = "notify_slack_webhook")]
+        slack_danger_errors: u64,
 
-        // Create or resume writer
-        let (writer, skip_dirs) = if resume {
-            let writer = RotatingParquetWriter::resume(config, path_str.clone())?;
-            let skip_dirs = Some(writer.manifest.completed_top_level_dirs.clone());
-            (writer, skip_dirs)
-        } else {
-            let writer = RotatingParquetWriter::new(config, path_str.clone())?;
-            (writer, None)
-        };
+        /// Limit on each notification request (e.g., 10s)
+        #[arg(long, default_value = "10s", value_parser = utils::parse_duration, requires = "notify_target", env = "STORAGE_SCANNER_NOTIFY_TIMEOUT")]
+        notify_timeout: Duration,
 
-        let writer_handle = std::thread::spawn(move || {
-            let manifest = writer.consume_batches(rx)?;
-            Ok::<u64, anyhow::Error>(manifest.total_rows)
-        });
+        /// Further attempts after a failed notification, with doubling backoff
+        #[arg(long, default_value = "3", requires = "notify_target", env = "STORAGE_SCANNER_NOTIFY_RETRIES")]
+        notify_retries: u32,
 
-        // Run scanner with optional directory filter
-        let stats = if let Some(skip_dirs) = skip_dirs {
-            scanner.scan_with_filter(&path, tx, Some(skip_dirs))
-                .context("Scan failed")?
-        } else {
-            scanner.scan(&path, tx)
-                .context("Scan failed")?
-        };
+        /// Email a summary report here when the scan completes or fails; repeat, or separate with commas, for more recipients (needs the email feature)
+        #[arg(long, value_delimiter = ',', env = "STORAGE_SCANNER_EMAIL_REPORT")]
+        email_report: Vec<String>,
 
-        // Wait for writer to finish
-        let rows = writer_handle
-            .join()
-            .map_err(|_| anyhow::anyhow!("Writer thread panicked"))?
-            .context("Failed to write Parquet files")?;
+        /// SMTP server reports are sent through, as host:port
+        #[arg(long, default_value = "localhost:25", env = "STORAGE_SCANNER_SMTP_SERVER")]
+        smtp_server: String,
 
-        (stats, rows)
-    } else {
-        // Use regular single-file writer
-        let writer_handle = std::thread::spawn(move || {
-            write_to_parquet(&output_clone, rx)
-        });
+        /// How the connection to the SMTP server is secured
+        #[arg(long, value_enum, default_value = "none", env = "STORAGE_SCANNER_SMTP_TLS")]
+        smtp_tls: SmtpTlsArg,
 
-        // Run scanner
-        let stats = scanner.scan(&path, tx)
-            .context("Scan failed")?;
+        /// Username for SMTP authentication; the password is read from SMTP_PASSWORD
+        #[arg(long, env = "STORAGE_SCANNER_SMTP_USER")]
+        smtp_user: Option<String>,
 
-        // Wait for writer to finish
-        let rows = writer_handle
-            .join()
-            .map_err(|_| anyhow::anyhow!("Writer thread panicked"))?
-            .context("Failed to write Parquet file")?;
+        /// Sender of email reports (default: storage-scanner@<hostname>)
+        #[arg(long, env = "STORAGE_SCANNER_EMAIL_FROM")]
+        email_from: Option<String>,
 
-        (stats, rows)
-    };
+        /// Serve Prometheus metrics on /metrics at this address (e.g., 0.0.0.0:9200; needs the metrics feature)
+        #[arg(long, env = "STORAGE_SCANNER_METRICS_LISTEN")]
+        metrics_listen: Option<String>,
 
-    // Print final statistics
-    println!();
-    println!("Scan completed successfully");
-    println!("---");
-    println!("Files scanned:       {}", utils::format_number(stats.files_scanned));
-    println!("Directories scanned: {}", utils::format_number(stats.directories_scanned));
-    println!("Total size:          {}", utils::format_bytes(stats.total_size));
-    println!("Rows written:        {}", utils::format_number(rows_written));
-    println!("Duration:            {}", utils::format_duration(stats.duration_secs));
-    println!("Performance:         {:.0} files/second", stats.files_per_second());
+        /// How long /metrics keeps serving after the scan, so the final values get scraped
+        #[arg(long, default_value = "15s", value_parser = utils::parse_duration, requires = "metrics_listen", env = "STORAGE_SCANNER_METRICS_GRACE")]
+        metrics_grace: Duration,
 
-    if stats.errors_encountered > 0 {
-        println!("Errors encountered:  {}", utils::format_number(stats.errors_encountered));
-        println!("Note: Some files may have been skipped due to permission errors");
-    }
+        /// Serve the status document on /status and a health check on /healthz at this address (e.g., 127.0.0.1:8090; needs the status-server feature)
+        #[arg(long, env = "STORAGE_SCANNER_STATUS_LISTEN")]
+        status_listen: Option<String>,
 
-    println!();
-    if incremental {
-        println!("Output written to chunk files:");
-        println!("  Base name: {}", output.display());
-        println!("  Pattern: {}_chunk_*.parquet", output.file_stem().unwrap().to_string_lossy());
-        println!("  Manifest: {}_manifest.json", output.file_stem().unwrap().to_string_lossy());
-        println!();
-        println!("To read all chunks in Python:");
-        println!("  import polars as pl");
-        println!("  df = pl.read_parquet('{}_chunk_*.parquet')",
-                 output.file_stem().unwrap().to_string_lossy());
-    } else {
-        println!("Output written to: {}", output.display());
-    }
+        /// How long /status keeps serving after the scan, so the final state can be read
+        #[arg(long, default_value = "5s", value_parser = utils::parse_duration, requires = "status_listen", env = "STORAGE_SCANNER_STATUS_GRACE")]
+        status_grace: Duration,
+    },
 
-    Ok(())
-}
+    /// Show how a sharded scan would split a tree, with sizes from a previous scan
+    ShardPlan {
+        /// Path to scan
+        #[arg(short, long)]
+        path: PathBuf,
 
-fn run_aggregate(input: PathBuf, output: PathBuf, delete_chunks: bool) -> Result<()> {
-    use arrow::datatypes::SchemaRef;
-    use parquet::arrow::ArrowWriter;
-    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
-    use parquet::file::reader::{FileReader, SerializedFileReader};
-    use std::fs;
-    use std::sync::Arc;
+        /// Shards the scan would be split into
+        #[arg(long)]
+        shard_count: u32,
 
-    info!("Storage Scanner v{}", env!("CARGO_PKG_VERSION"));
-    info!("Starting aggregation operation");
+        /// Level whose directories are assigned to shards
+        #[arg(long, default_value = "1")]
+        shard_depth: usize,
 
-    // Find chunk files
-    let chunk_files = find_chunk_files(&input)?;
+        /// Output of a previous scan of the tree (Parquet file, chunks, or manifest), for size estimates
+        #[arg(long)]
+        baseline: Option<PathBuf>,
 
-    if chunk_files.is_empty() {
-        error!("No Parquet chunk files found");
-        return Err(anyhow::anyhow!("No chunk files found in: {}", input.display()));
-    }
+        /// Print the plan as JSON
+        #[arg(long)]
+        json: bool,
+    },
 
-    info!("Found {} chunk file(s) to aggregate", chunk_files.len());
-    info!("Output file: {}", output.display());
+    /// Create a reproducible synthetic directory tree for benchmarks and tests
+    Generate {
+        /// Directory to create the tree in; must not exist or be empty
+        #[arg(short, long)]
+        output: PathBuf,
 
-    // Ensure output directory exists
-    if let Some(parent) = output.parent() {
-        fs::create_dir_all(parent)
-            .context("Failed to create output directory")?;
-    }
+        /// Files to create
+        #[arg(long, default_value = "1000")]
+        files: u64,
 
-    // Read schema from first file
-    let first_file = fs::File::open(&chunk_files[0])?;
-    let first_reader = SerializedFileReader::new(first_file)?;
-    let schema = first_reader.metadata().file_metadata().schema_descr();
+        /// Directories to create below the output directory
+        #[arg(long, default_value = "50")]
+        dirs: u64,
 
-    // Convert to Arrow schema
-    let arrow_schema: SchemaRef = Arc::new(
-        parquet::arrow::parquet_to_arrow_schema(&schema, None)?
-    );
+        /// Deepest directory level below the output directory
+        #[arg(long, default_value = "4")]
+        depth: usize,
 
-    info!("Creating aggregated file...");
+        /// File sizes: fixed:SIZE, uniform:MIN,MAX, or lognormal:MU,SIGMA (of the natural log of the size)
+        #[arg(long, default_value = "lognormal:12,2")]
+        size_dist: SizeDist,
 
-    // Create writer
-    let output_file = fs::File::create(&output)
-        .context("Failed to create output file")?;
+        /// Random seed; the same seed and options give the same tree
+        #[arg(long, default_value = "0")]
+        seed: u64,
 
-    let mut writer = ArrowWriter::try_new(
-        output_file,
-        arrow_schema.clone(),
-        None,
-    )?;
+        /// Write file contents instead of creating sparse files
+        #[arg(long)]
+        dense: bool,
 
-    let mut total_rows = 0u64;
-    let start_time = std::time::Instant::now();
+        /// Write a JSON summary of what was created (counts and bytes per top-level directory)
+        #[arg(long)]
+        manifest: Option<PathBuf>,
+    },
 
-    // Process each chunk file
-    for (i, chunk_path) in chunk_files.iter().enumerate() {
-        info!("  [{}/{}] Processing: {}", i + 1, chunk_files.len(), chunk_path.display());
+    /// Show a live dashboard of a scan started with --status-file (needs the tui feature)
+    Monitor {
+        /// Status file the scan writes
+        #[arg(long)]
+        status_file: PathBuf,
 
-        // Read chunk as Arrow batches
-        let file = fs::File::open(chunk_path)?;
-        let builder = ParquetRecordBatchReaderBuilder::try_new(file)?;
-        let mut reader = builder.with_batch_size(100000).build()?;
+        /// How often to re-read the status file
+        #[arg(long, default_value = "1s", value_parser = utils::parse_duration)]
+        refresh: Duration,
+    },
 
-        while let Some(batch_result) = reader.next() {
-            let batch = batch_result?;
-            total_rows += batch.num_rows() as u64;
-            writer.write(&batch)?;
-        }
-    }
+    /// Aggregate multiple Parquet chunk files into a single file
+    #[command(group(ArgGroup::new("retention").args(["delete_chunks", "archive_chunks"])))]
+    Aggregate {
+        /// Glob pattern, directory containing chunk files, base output path, or manifest (e.g., 'scan_chunk_*.parquet', /path/to/chunks/, scan.parquet, scan_manifest.json, or its http(s):// URL); repeat to combine scans
+        #[arg(short, long, required = true)]
+        input: Vec<PathBuf>,
 
-    // Finalize writer
-    writer.close()?;
+        /// Also aggregate this Parquet file, which the inputs don't pick up; repeat for more
+        #[arg(long, conflicts_with_all = ["merge", "retention"])]
+        include_file: Vec<PathBuf>,
 
-    let duration = start_time.elapsed();
+        /// Add scan_id and scan_date columns identifying each row's scan
+        #[arg(long)]
+        tag_scan_id: bool,
 
-    info!("Aggregation completed successfully");
-    println!();
-    println!("Aggregation Summary");
-    println!("---");
-    println!("Chunk files processed: {}", chunk_files.len());
-    println!("Total rows:            {}", utils::format_number(total_rows));
-    println!("Duration:              {:.2}s", duration.as_secs_f64());
-    println!("Output file:           {}", output.display());
-    println!("Output size:           {}", utils::format_bytes(fs::metadata(&output)?.len()));
-
-    // Delete chunk files if requested
-    if delete_chunks {
-        info!("Deleting chunk files...");
-        let mut deleted = 0;
-        for chunk_path in &chunk_files {
-            match fs::remove_file(chunk_path) {
-                Ok(_) => {
-                    deleted += 1;
-                    info!("  Deleted: {}", chunk_path.display());
-                }
-                Err(e) => {
-                    error!("  Failed to delete {}: {}", chunk_path.display(), e);
-                }
-            }
-        }
+        /// Scan identifier for the corresponding --input, in order (implies --tag-scan-id)
+        #[arg(long)]
+        scan_id: Vec<String>,
 
-        // Also try to delete manifest file if it exists
-        let manifest_path = get_manifest_path(&input);
-        if manifest_path.exists() {
-            if let Err(e) = fs::remove_file(&manifest_path) {
-                error!("Failed to delete manifest file: {}", e);
-            } else {
-                info!("  Deleted manifest: {}", manifest_path.display());
-            }
-        }
+        /// Combine scans of different filesystems, given as manifests: rows gain scan_id, scan_date and hostname columns, and a merged manifest lists each source and its host
+        #[arg(long, conflicts_with_all = ["append", "retention"])]
+        merge: bool,
 
-        println!();
-        println!("Deleted {} chunk file(s)", deleted);
-    }
+        /// Host for the corresponding --input, in order (default: the host its manifest records)
+        #[arg(long, requires = "merge")]
+        host: Vec<String>,
 
-    Ok(())
-}
+        /// Merge scans whose roots overlap, dropping duplicate paths (the newest modified_time wins)
+        #[arg(long, requires = "merge")]
+        allow_overlap: bool,
 
-fn find_chunk_files(input: &PathBuf) -> Result<Vec<PathBuf>> {
-    use std::fs;
+        /// Where to write the merged manifest (default: <output>_manifest.json)
+        #[arg(long, requires = "merge")]
+        merged_manifest: Option<PathBuf>,
 
-    let mut chunk_files = Vec::new();
+        /// Write <output stem>.CHECKSUMS listing a sha256 or xxh3 checksum of each output file, for checking transfers
+        #[arg(long)]
+        emit_checksums: Option<ChecksumAlgo>,
 
-    if input.is_dir() {
-        // Input is a directory, find all chunk files
-        for entry in fs::read_dir(input)? {
-            let entry = entry?;
-            let path = entry.path();
+        /// Output Parquet file path (a directory with --partition-by)
+        #[arg(short, long)]
+        output: PathBuf,
 
-            if path.is_file() {
-                if let Some(name) = path.file_name() {
-                    let name_str = name.to_string_lossy();
-                    // Match chunk files but exclude manifest
-                    if name_str.ends_with(".parquet") &&
-                       (name_str.contains("chunk") || name_str.contains("_")) &&
-                       !name_str.contains("manifest") {
-                        chunk_files.push(path);
-                    }
-                }
-            }
-        }
-    } else if input.is_file() {
-        // Input is a single file
-        chunk_files.push(input.clone());
-    } else {
-        // Input path doesn't exist - try to find matching chunk files
-        let parent = input.parent()
-            .ok_or_else(|| anyhow::anyhow!("Invalid input path"))?;
-
-        let base_name = input.file_stem()
-            .and_then(|s| s.to_str())
-            .ok_or_else(|| anyhow::anyhow!("Invalid base filename"))?;
-
-        for entry in fs::read_dir(parent)? {
-            let entry = entry?;
-            let path = entry.path();
-
-            if path.is_file() {
-                if let Some(name) = path.file_name() {
-                    let name_str = name.to_string_lossy();
-                    // Match files that start with the base name and are chunks
-                    if name_str.starts_with(base_name) &&
-                       name_str.ends_with(".parquet") &&
-                       name_str.contains("chunk") &&
-                       !name_str.contains("manifest") {
-                        chunk_files.push(path);
-                    }
-                }
-            }
-        }
-    }
+        /// Write a hive-partitioned dataset split on this column (e.g., top_level_dir) into the --output directory
+        #[arg(long)]
+        partition_by: Option<String>,
 
-    // Sort files for consistent ordering
-    chunk_files.sort();
+        /// Add only chunks not yet in --output, tracked in a state file next to it
+        #[arg(long, conflicts_with_all = ["retention", "partition_by"])]
+        append: bool,
 
-    Ok(chunk_files)
-}
+        /// Delete chunk files after successful, verified aggregation
+        #[arg(short, long)]
+        delete_chunks: bool,
 
-fn get_manifest_path(input: &PathBuf) -> PathBuf {
-    if input.is_dir() {
-        // Look for any manifest file in the directory
-        if let Ok(entries) = std::fs::read_dir(input) {
-            for entry in entries.flatten() {
-                let path = entry.path();
-                if let Some(name) = path.file_name() {
-                    if name.to_string_lossy().contains("manifest") {
-                        return path;
-                    }
-                }
-            }
-        }
-    }
+        /// Move chunk files into this directory after successful, verified aggregation
+        #[arg(long)]
+        archive_chunks: Option<PathBuf>,
 
-    // Default: assume manifest is next to the input
-    let stem = input.file_stem()
-        .and_then(|s| s.to_str())
-        .unwrap_or("scan");
-    input.with_file_name(format!("{}_manifest.json", stem))
-}
+        /// Keep the newest N chunks in place when deleting or archiving
+        #[arg(long, default_value = "0", requires = "retention")]
+        retain_last: usize,
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        /// Delete or archive chunks even if they disagree with the manifest
+        #[arg(long, requires = "retention")]
+        force_delete: bool,
 
-    #[test]
-    fn test_cli_parsing() {
-        use clap::CommandFactory;
-        Cli::command().debug_assert();
+        /// Print which chunks --delete-chunks or --archive-chunks would remove, without removing them
+        #[arg(long, requires = "retention")]
+        dry_run_delete: bool,
+
+        /// Also remove aggregated chunks the manifest does not list (or all of them, without a manifest)
+        #[arg(long, requires = "retention")]
+        delete_unlisted: bool,
+
+        /// Require every chunk to have the first chunk's schema instead of merging schemas
+        #[arg(long)]
+        strict: bool,
+
+        /// Drop duplicate rows, keeping the one with the newest modified_time
+        #[arg(long, value_enum)]
+        dedupe_by: Option<DedupeBy>,
+
+        /// Sort the output by these columns (e.g., path or top_level_dir,path)
+        #[arg(long, value_delimiter = ',')]
+        sort_by: Vec<String>,
+
+        /// Memory budget for sorting before runs are spilled to disk (e.g., 512MiB, 4GB; default: 1GiB, or a quarter of --memory-limit)
+        #[arg(long, value_parser = utils::parse_bytes)]
+        sort_memory: Option<u64>,
+
+        /// Bound memory use (e.g., 2GB); batch and row group sizes are derived from it
+        #[arg(long, value_parser = utils::parse_bytes)]
+        memory_limit: Option<u64>,
+
+        /// Keep only this type: file (anything but directories), directory, or an extension such as txt
+        #[arg(long)]
+        filter_type: Option<TypeFilter>,
+
+        /// Keep only entries at least this large (e.g., 1MB, 4KiB)
+        #[arg(long, value_parser = utils::parse_bytes)]
+        min_size: Option<u64>,
+
+        /// Keep only entries last modified longer ago than this (e.g., 30d, 1year)
+        #[arg(long, value_parser = utils::parse_duration)]
+        mtime_older_than: Option<Duration>,
+
+        /// Keep only this path and the entries below it (e.g., /project/labX)
+        #[arg(long)]
+        path_prefix: Option<String>,
+
+        /// Number of threads decoding chunks (default: number of CPU cores)
+        #[arg(short, long)]
+        threads: Option<usize>,
+
+        /// Print a usage report (by file type, top-level directory and size) of the rows written
+        #[arg(long)]
+        summary: bool,
+
+        /// Write the usage report as JSON to this file (implies --summary)
+        #[arg(long)]
+        summary_output: Option<PathBuf>,
+    },
+
+    /// Print a storage report (by file type, top-level directory, size and age) of scan output
+    Report {
+        /// Parquet file, glob pattern, directory containing chunk files, base output path, or manifest
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// Output format
+        #[arg(long, value_enum, default_value = "table")]
+        format: ReportFormat,
+
+        /// Largest file types and top-level directories to list
+        #[arg(long, default_value = "10")]
+        top: usize,
+    },
+
+    /// List the largest files and the directories holding the most data
+    Top {
+        /// Parquet file, glob pattern, directory containing chunk files, base output path, or manifest
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// Number of largest files to list
+        #[arg(long, default_value = "20")]
+        files: usize,
+
+        /// Number of heaviest directories to list
+        #[arg(long, default_value = "20")]
+        dirs: usize,
+
+        /// Only rank this path and the entries below it (e.g., /project/labX)
+        #[arg(long)]
+        under: Option<String>,
+
+        /// Count files toward every ancestor directory (down to --under), not just their parent
+        #[arg(long)]
+        cumulative: bool,
+
+        /// Print the rankings as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Show cumulative directory sizes, like du, from scan output
+    Du {
+        /// Parquet file, glob pattern, directory containing chunk files, base output path, or manifest
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// Deepest directory level to show, counted from the scan root (or --under) at 0
+        #[arg(long, default_value = "1")]
+        depth: usize,
+
+        /// Only show this directory and the directories below it (e.g., /project/labX)
+        #[arg(long)]
+        under: Option<String>,
+
+        /// Order of sibling directories
+        #[arg(long, value_enum, default_value = "path")]
+        sort_by: DuSortBy,
+
+        /// Hide directories smaller than this (e.g., 1GB)
+        #[arg(long, value_parser = utils::parse_bytes, default_value = "0")]
+        min_size: u64,
+    },
+
+    /// Draw directories as a tree with cumulative sizes and file counts, largest first
+    Tree {
+        /// Parquet file, glob pattern, directory containing chunk files, base output path, or manifest
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// Only show this directory and the directories below it (e.g., /project/labX)
+        #[arg(long)]
+        under: Option<String>,
+
+        /// Deepest directory level to show, counted from the scan root (or --under) at 0
+        #[arg(long, default_value = "3")]
+        depth: usize,
+
+        /// Hide directories smaller than this (e.g., 1GB)
+        #[arg(long, value_parser = utils::parse_bytes, default_value = "0")]
+        min_size: u64,
+
+        /// Mark the largest subdirectory with '*' instead of highlighting it (the default when not writing to a terminal)
+        #[arg(long)]
+        no_color: bool,
+
+        /// Draw branches with plain ASCII instead of box-drawing characters
+        #[arg(long)]
+        ascii: bool,
+    },
+
+    /// Find groups of duplicate files and the space they waste
+    Duplicates {
+        /// Parquet file, glob pattern, directory containing chunk files, base output path, or manifest
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// Ignore files smaller than this (e.g., 1MB)
+        #[arg(long, value_parser = utils::parse_bytes, default_value = "1")]
+        min_size: u64,
+
+        /// Compare contents, using the scan's content_hash column or reading files from the live filesystem
+        #[arg(long)]
+        hash: bool,
+
+        /// Threads used to hash files (default: number of CPUs)
+        #[arg(short, long)]
+        threads: Option<usize>,
+
+        /// Memory budget for sorting files by size (e.g., 512MiB; default: 1GiB)
+        #[arg(long, value_parser = utils::parse_bytes)]
+        sort_memory: Option<u64>,
+
+        /// Number of groups to list in the table
+        #[arg(long, default_value = "20")]
+        top: usize,
+
+        /// Print every group as JSON instead of a table
+        #[arg(long)]
+        json: bool,
+
+        /// Also write every duplicate path, one row each, to this Parquet file
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Group hard links by inode and correct total usage by counting each inode once
+    Hardlinks {
+        /// Parquet file, glob pattern, directory containing chunk files, base output path, or manifest
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// Memory budget for sorting linked files by inode (e.g., 512MiB; default: 1GiB)
+        #[arg(long, value_parser = utils::parse_bytes)]
+        sort_memory: Option<u64>,
+
+        /// Number of largest groups to list
+        #[arg(long, default_value = "20")]
+        top: usize,
+
+        /// Print the report as JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// List directories with nothing in them, or with --recursive, whole subtrees without files
+    EmptyDirs {
+        /// Parquet file, glob pattern, directory containing chunk files, base output path, or manifest
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// Report directories with no files anywhere below, grouped into empty subtrees
+        #[arg(long)]
+        recursive: bool,
+
+        /// Print the report as JSON instead of a list
+        #[arg(long)]
+        json: bool,
+
+        /// Also write the listed directories to this Parquet file
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Classify symbolic links as relative or absolute and internal or external, and find broken ones
+    Symlinks {
+        /// Parquet file, glob pattern, directory containing chunk files, base output path, or manifest
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// Follow each link on the filesystem as it is now to find broken ones
+        #[arg(long)]
+        check_live: bool,
+
+        /// Print the report as JSON instead of counts and lists
+        #[arg(long)]
+        json: bool,
+
+        /// Also write every link to this file; .parquet, .csv, or .jsonl selects the format
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Find world-writable, setuid, and setgid files and world-readable home directories
+    Permissions {
+        /// Parquet file, glob pattern, directory containing chunk files, base output path, or manifest
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// Checks to run: world-writable, setuid, setgid, world-readable-homes (default: all)
+        #[arg(long, value_delimiter = ',')]
+        check: Vec<String>,
+
+        /// Only audit this path and the entries below it; its children are the home directories
+        #[arg(long)]
+        under: Option<String>,
+
+        /// Print the report as JSON instead of tables
+        #[arg(long)]
+        json: bool,
+
+        /// Also write every finding to this file; .parquet, .csv, or .jsonl selects the format
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Rank directories by number of direct children, and paths by depth and length
+    Hotspots {
+        /// Parquet file, glob pattern, directory containing chunk files, base output path, or manifest
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// Number of directories and paths to list in each ranking
+        #[arg(long, default_value = "20")]
+        top: usize,
+
+        /// Only rank directories with at least this many direct children
+        #[arg(long, default_value = "1")]
+        min_entries: u64,
+
+        /// Memory budget for sorting entries by parent directory (e.g., 512MiB; default: 1GiB)
+        #[arg(long, value_parser = utils::parse_bytes)]
+        sort_memory: Option<u64>,
+
+        /// Output format
+        #[arg(long, value_enum, default_value = "table")]
+        format: ReportFormat,
+    },
+
+    /// Total files not accessed for a while, by owner or directory, for cleanup policies
+    Stale {
+        /// Parquet file, glob pattern, directory containing chunk files, base output path, or manifest
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// Files last accessed longer ago than this are stale (e.g., 365d)
+        #[arg(long, value_parser = utils::parse_duration)]
+        atime_older_than: Duration,
+
+        /// Ignore files smaller than this (e.g., 100MB)
+        #[arg(long, value_parser = utils::parse_bytes, default_value = "0")]
+        min_size: u64,
+
+        /// Column to total stale files by
+        #[arg(long, value_enum, default_value = "uid")]
+        group_by: StaleGroupByArg,
+
+        /// Measure ages from this Unix timestamp or UTC date (e.g., 2024-06-30) instead of now, for reproducible reports
+        #[arg(long, value_parser = utils::parse_timestamp)]
+        as_of: Option<i64>,
+
+        /// Print the totals as JSON
+        #[arg(long)]
+        json: bool,
+
+        /// Also write every stale file to this file; .parquet, .csv, or .jsonl selects the format
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// List the files a clean-up policy selects, tagged with the rule that selected each; deletes nothing
+    CleanCandidates {
+        /// Parquet file, glob pattern, directory containing chunk files, base output path, or manifest
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// TOML file of [[rule]] tables, tried in order; the first rule a file satisfies claims it
+        #[arg(long)]
+        policy: PathBuf,
+
+        /// File to write the candidates to; .parquet, .csv, or .jsonl selects the format
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// Measure ages from this Unix timestamp or UTC date (e.g., 2024-06-30) instead of now, for reproducible lists
+        #[arg(long, value_parser = utils::parse_timestamp)]
+        as_of: Option<i64>,
+
+        /// Print the per-rule summary as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Compare usage with byte and inode quotas; exits 0, 1, or 2 for ok, warning, or critical
+    QuotaCheck {
+        /// Parquet file, glob pattern, directory containing chunk files, base output path, or manifest
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// TOML file of [[quota]] tables, each with a path or top_level_dir and bytes and/or inodes limits
+        #[arg(long)]
+        quotas: PathBuf,
+
+        /// Print the statuses as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Show how each directory or owner grew across several dated scans
+    Growth {
+        /// Directory of scan outputs, or one scan's Parquet file, base output path, or manifest; repeat to add scans
+        #[arg(short, long, required = true)]
+        input: Vec<PathBuf>,
+
+        /// Column to total each scan by
+        #[arg(long, value_enum, default_value = "top_level_dir")]
+        group_by: GrowthGroupByArg,
+
+        /// Only compare scans taken at most this many days before the newest one
+        #[arg(long)]
+        window: Option<u64>,
+
+        /// Quota of a group as GROUP=SIZE (e.g., labA=10TB), to estimate the days until it is reached; repeatable
+        #[arg(long, value_parser = growth::parse_quota)]
+        quota: Vec<(String, u64)>,
+
+        /// Print the report as JSON
+        #[arg(long, conflicts_with = "csv")]
+        json: bool,
+
+        /// Print one CSV line per group and scan
+        #[arg(long)]
+        csv: bool,
+    },
+
+    /// Total entries and bytes per owner (uid) and group (gid)
+    UserReport {
+        /// Parquet file, glob pattern, directory containing chunk files, base output path, or manifest
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// passwd-style file (name:password:uid:...) naming uids; takes precedence over the scan and the system
+        #[arg(long)]
+        map: Option<PathBuf>,
+
+        /// group-style file (name:password:gid:...) naming gids
+        #[arg(long)]
+        group_map: Option<PathBuf>,
+
+        /// Only count this path and the paths below it (e.g., /project/labX)
+        #[arg(long)]
+        under: Option<String>,
+
+        /// Also split each owner's bytes by time since last modification
+        #[arg(long)]
+        age_bands: bool,
+
+        /// Measure ages from this Unix timestamp or UTC date (e.g., 2024-06-30) instead of now
+        #[arg(long, value_parser = utils::parse_timestamp, requires = "age_bands")]
+        as_of: Option<i64>,
+
+        /// Print the report as JSON
+        #[arg(long, conflicts_with = "csv")]
+        json: bool,
+
+        /// Print one CSV line per user and group
+        #[arg(long)]
+        csv: bool,
+    },
+
+    /// Write the entries matching a filter expression to Parquet, CSV, or JSONL
+    Query {
+        /// Parquet file, glob pattern, directory containing chunk files, base output path, or manifest
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// Filter expression, e.g. "file_type == 'bam' && size > 10GB && accessed_time < now-365d"
+        #[arg(long = "where")]
+        filter: String,
+
+        /// Output file; .parquet, .csv, or .jsonl selects the format (add .gz to compress CSV or JSONL)
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+
+    /// Fill in content hashes for selected files of an existing scan by reading them from the live filesystem
+    Hash {
+        /// Parquet file, glob pattern, directory containing chunk files, base output path, or manifest
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// Only hash files matching this expression, e.g. "size > 100MB && file_type == 'bam'" (default: every file)
+        #[arg(long = "where")]
+        filter: Option<String>,
+
+        /// Hash algorithm: crc32 or blake3
+        #[arg(long, default_value = "blake3")]
+        algo: HashAlgo,
+
+        /// Threads used to hash files (default: number of CPUs)
+        #[arg(short, long)]
+        threads: Option<usize>,
+
+        /// Output Parquet file with content_hash and error columns
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+
+    /// Convert scan data to CSV or JSONL, optionally selecting columns and rows
+    Export {
+        /// Parquet file, glob pattern, directory containing chunk files, base output path, or manifest
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// Output file; .csv or .jsonl selects the format, and a further .gz compresses it (e.g., out.csv.gz)
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// Columns to write, in order (e.g., path,size,modified_time); all columns by default
+        #[arg(long, value_delimiter = ',')]
+        columns: Vec<String>,
+
+        /// Only export entries matching this filter expression, as in query
+        #[arg(long = "where")]
+        filter: Option<String>,
+    },
+
+    /// Convert scan data between Parquet, CSV, JSONL, Arrow, and SQLite
+    Convert {
+        /// Parquet file, glob pattern, directory containing chunk files, base output path, or manifest; or a .csv, .jsonl, or .arrow file (CSV and JSONL may add .gz)
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// Output file; .parquet, .csv, .jsonl, .arrow, or .sqlite selects the format (CSV and JSONL may add .gz)
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// Columns to write, in order (e.g., path,size,modified_time); all columns by default
+        #[arg(long, value_delimiter = ',')]
+        columns: Vec<String>,
+
+        /// Only convert entries matching this filter expression, as in query
+        #[arg(long = "where")]
+        filter: Option<String>,
+
+        /// Parquet codec: none, snappy, gzip, zstd, lz4, or brotli (default: snappy)
+        #[arg(long, value_parser = convert::parse_compression)]
+        compression: Option<parquet::basic::Compression>,
+
+        /// Maximum rows per Parquet row group (default: 100000)
+        #[arg(long)]
+        row_group_size: Option<usize>,
+    },
+
+    /// Print or write a random sample, or the first rows, of scan data
+    Sample {
+        /// Parquet file, glob pattern, directory containing chunk files, base output path, or manifest
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// Rows to sample
+        #[arg(short = 'n', long, default_value = "100")]
+        size: usize,
+
+        /// Random seed; the same seed gives the same sample of the same data
+        #[arg(long)]
+        seed: Option<u64>,
+
+        /// Take the first N rows instead of sampling
+        #[arg(long, value_name = "N", conflicts_with_all = ["size", "seed"])]
+        head: Option<usize>,
+
+        /// Only sample entries matching this filter expression, as in query
+        #[arg(long = "where")]
+        filter: Option<String>,
+
+        /// How to print the sample
+        #[arg(long, value_enum, default_value = "table")]
+        format: SampleFormat,
+
+        /// Write the sample to this file instead; .parquet, .csv, or .jsonl selects the format
+        #[arg(short, long, conflicts_with = "format")]
+        output: Option<PathBuf>,
+    },
+
+    /// Run the scans listed in a schedule file at their times, staying resident
+    Schedule {
+        /// Schedule file with one [[job]] table per scan
+        #[arg(short, long)]
+        config: PathBuf,
+
+        /// Run this job once now, apply its retention, and exit
+        #[arg(long, value_name = "JOB")]
+        run_once: Option<String>,
+    },
+
+    /// Scan, aggregate, verify, and publish in one run, as listed in a pipeline file
+    Pipeline {
+        /// Pipeline file describing the stages
+        #[arg(short, long)]
+        config: PathBuf,
+
+        /// Pick up at the stage that failed in the previous run
+        #[arg(long)]
+        resume: bool,
+    },
+
+    /// Answer read-only JSON requests about a scan over HTTP (needs the serve feature)
+    Serve {
+        /// Parquet file, glob pattern, directory containing chunk files, base output path, or manifest
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: String,
+
+        /// Most rows, files, or directories in one response
+        #[arg(long, default_value = "10000")]
+        max_rows: usize,
+
+        /// Load every row into memory at startup instead of reading the chunks for each request
+        #[arg(long)]
+        preload: bool,
+    },
+
+    /// Record changes under a directory as they happen (needs the watch feature)
+    Watch {
+        /// Directory to watch, recursively
+        #[arg(short, long)]
+        path: PathBuf,
+
+        /// Directory for the change files and their manifest
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// Change file format
+        #[arg(long, value_enum, default_value = "jsonl")]
+        format: WatchFormatArg,
+
+        /// Changes per file before starting the next one
+        #[arg(long, default_value = "100000")]
+        rows_per_file: usize,
+
+        /// Longest a change file stays open (e.g., 15m, 1h)
+        #[arg(long, default_value = "1h", value_parser = utils::parse_duration)]
+        file_interval: Duration,
+
+        /// How often to re-scan subtrees inotify has no watches left for
+        #[arg(long, default_value = "5m", value_parser = utils::parse_duration)]
+        rescan_interval: Duration,
+    },
+
+    /// Compare two scans and report added, removed, and modified entries
+    Diff {
+        /// Earlier scan: Parquet file, glob pattern, directory containing chunk files, base output path, or manifest
+        #[arg(long)]
+        old: PathBuf,
+
+        /// Later scan, in any form accepted by --old
+        #[arg(long)]
+        new: PathBuf,
+
+        /// Only compare this path and the entries below it (e.g., /project/labX)
+        #[arg(long)]
+        under: Option<String>,
+
+        /// Write every changed entry, with a change_type column, to this Parquet file
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Memory budget for sorting an input that is not sorted by path (e.g., 512MiB; default: 1GiB)
+        #[arg(long, value_parser = utils::parse_bytes)]
+        sort_memory: Option<u64>,
+    },
+
+    /// Load scan output into a ClickHouse table over its HTTP interface (needs the clickhouse feature)
+    LoadClickhouse {
+        /// Parquet file, glob pattern, directory containing chunk files, base output path, or manifest
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// ClickHouse HTTP interface
+        #[arg(long, default_value = "http://localhost:8123")]
+        url: String,
+
+        /// Table to insert into, e.g. storage.file_entries; its columns are matched to the scan's by name
+        #[arg(long)]
+        table: String,
+
+        /// ClickHouse user; the password is read from CLICKHOUSE_PASSWORD
+        #[arg(long)]
+        user: Option<String>,
+
+        /// Most rows in one INSERT
+        #[arg(long, default_value = "100000")]
+        batch_size: usize,
+
+        /// Further attempts after a transient failure, with doubling backoff
+        #[arg(long, default_value = "5")]
+        retries: u32,
+    },
+
+    /// Upload the chunks of a manifest that a scan's --upload-url did not get through (needs the remote feature)
+    RetryUploads {
+        /// Manifest of the scan
+        #[arg(short, long)]
+        manifest: PathBuf,
+
+        /// Endpoint to PUT chunks under, by file name
+        #[arg(long)]
+        upload_url: String,
+
+        /// Further attempts after a failed chunk upload, with doubling backoff
+        #[arg(long, default_value = "5")]
+        upload_retries: u32,
+    },
+
+    /// Verify that a manifest matches its chunk files, or that files match a checksum file
+    #[command(group(ArgGroup::new("verify_target").required(true).multiple(true).args(["manifest", "checksums"])))]
+    Verify {
+        /// Manifest file to verify
+        #[arg(short, long)]
+        manifest: Option<PathBuf>,
+
+        /// Checksum file (<stem>.CHECKSUMS or any sha256sum-style file) whose listed files to check
+        #[arg(long)]
+        checksums: Option<PathBuf>,
+
+        /// Decode every row group instead of trusting Parquet footers
+        #[arg(long)]
+        deep: bool,
+
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Check scan output integrity before publishing; exits non-zero if any check fails
+    Validate {
+        /// Parquet file, glob pattern, directory containing chunk files, base output path, or manifest
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// Treat timestamps more than a day after this Unix timestamp or UTC date as implausible (default: now)
+        #[arg(long, value_parser = utils::parse_timestamp)]
+        as_of: Option<i64>,
+
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Show the layout and metadata of a Parquet file, or the summary and chunks of a scan manifest
+    Inspect {
+        /// Parquet file or scan manifest
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// Print the details as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Compare two scans' manifests and fail when a total, chunk count, duration, or directory changed too much
+    CompareManifests {
+        /// Manifest of the earlier scan
+        #[arg(long)]
+        old: PathBuf,
+
+        /// Manifest of the scan to check
+        #[arg(long)]
+        new: PathBuf,
+
+        /// Largest allowed change of any metric, in either direction (e.g., 10%)
+        #[arg(long, value_parser = utils::parse_percent, default_value = "10%")]
+        threshold: f64,
+
+        /// Print the comparison as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Show which top-level directories an interrupted scan still has to do
+    ResumeStatus {
+        /// Manifest of the interrupted scan
+        #[arg(short, long)]
+        manifest: PathBuf,
+
+        /// Scan root the manifest belongs to
+        #[arg(short, long)]
+        path: PathBuf,
+
+        /// Manifest from a previous complete scan, used to estimate remaining work
+        #[arg(long)]
+        baseline: Option<PathBuf>,
+
+        /// Print the status as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Manifest maintenance operations
+    Manifest {
+        #[command(subcommand)]
+        command: ManifestCommands,
+    },
+
+    /// Configuration file operations
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
+    },
+
+    /// Display version information
+    Version,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum DedupeBy {
+    /// One row per file path
+    Path,
+}
+
+impl From<DedupeBy> for DedupeKey {
+    fn from(value: DedupeBy) -> Self {
+        match value {
+            DedupeBy::Path => DedupeKey::Path,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum OnErrorArg {
+    /// Log the failure and carry on
+    Warn,
+    /// Fail the scan, leaving its manifest incomplete
+    Fail,
+}
+
+impl From<OnErrorArg> for OnError {
+    fn from(value: OnErrorArg) -> Self {
+        match value {
+            OnErrorArg::Warn => OnError::Warn,
+            OnErrorArg::Fail => OnError::Fail,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum SmtpTlsArg {
+    /// Plain text, for a relay on a trusted network
+    None,
+    /// STARTTLS when the server offers it
+    Opportunistic,
+    /// STARTTLS, required
+    Starttls,
+    /// TLS from the start (SMTPS)
+    Tls,
+}
+
+impl From<SmtpTlsArg> for SmtpTls {
+    fn from(value: SmtpTlsArg) -> Self {
+        match value {
+            SmtpTlsArg::None => SmtpTls::None,
+            SmtpTlsArg::Opportunistic => SmtpTls::Opportunistic,
+            SmtpTlsArg::Starttls => SmtpTls::StartTls,
+            SmtpTlsArg::Tls => SmtpTls::Tls,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum StaleGroupByArg {
+    Uid,
+    #[value(name = "top_level_dir")]
+    TopLevelDir,
+}
+
+impl From<StaleGroupByArg> for StaleGroupBy {
+    fn from(value: StaleGroupByArg) -> Self {
+        match value {
+            StaleGroupByArg::Uid => StaleGroupBy::Uid,
+            StaleGroupByArg::TopLevelDir => StaleGroupBy::TopLevelDir,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum GrowthGroupByArg {
+    #[value(name = "top_level_dir")]
+    TopLevelDir,
+    Uid,
+}
+
+impl From<GrowthGroupByArg> for GrowthGroupBy {
+    fn from(value: GrowthGroupByArg) -> Self {
+        match value {
+            GrowthGroupByArg::TopLevelDir => GrowthGroupBy::TopLevelDir,
+            GrowthGroupByArg::Uid => GrowthGroupBy::Uid,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum DuSortBy {
+    /// By path
+    Path,
+    /// Largest first
+    Size,
+    /// Most files first
+    Count,
+}
+
+impl From<DuSortBy> for DuOrder {
+    fn from(value: DuSortBy) -> Self {
+        match value {
+            DuSortBy::Path => DuOrder::Path,
+            DuSortBy::Size => DuOrder::Size,
+            DuSortBy::Count => DuOrder::Count,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ReportFormat {
+    /// Aligned tables
+    Table,
+    /// The report as one JSON object
+    Json,
+    /// One line per table row, its section in the first column
+    Csv,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum SampleFormat {
+    /// Aligned columns, long values truncated
+    Table,
+    /// CSV with a header line
+    Csv,
+    /// One JSON object per line
+    Jsonl,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum WatchFormatArg {
+    /// One JSON object per line, flushed as changes arrive
+    Jsonl,
+    /// Parquet files, readable once rotated
+    ParquetChunks,
+}
+
+#[derive(Subcommand)]
+enum ManifestCommands {
+    /// Merge several scan manifests into one
+    Merge {
+        /// Input manifest files (repeat for each manifest)
+        #[arg(short, long = "input", required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// Output manifest path
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// Allow merging manifests whose scan roots differ
+        #[arg(long)]
+        allow_multiple_roots: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommands {
+    /// Print a config file with every option, commented out at its default
+    PrintDefault,
+
+    /// Print the environment variables that set options, as a Markdown table
+    Env,
+}
+
+/// Set while `watch` runs, so an interrupt stops it instead of exiting
+static WATCHING: AtomicBool = AtomicBool::new(false);
+static STOP_WATCH: AtomicBool = AtomicBool::new(false);
+
+/// Limit on each command of an email report's SMTP session
+const SMTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Exit status of a scan that finished with more errors than `--fail-on-errors`
+const EXIT_TOO_MANY_ERRORS: i32 = 3;
+
+fn main() -> Result<()> {
+    let cli = parse_cli()?;
+
+    // Setup logging
+    setup_logging(cli.verbose)?;
+
+    // Destructors don't run on SIGINT/SIGTERM, so release lock files here
+    ctrlc::set_handler(|| {
+        // A running watch closes its change files and exits by itself
+        if WATCHING.load(Ordering::Relaxed) {
+            info!("Interrupted, stopping watch");
+            STOP_WATCH.store(true, Ordering::Relaxed);
+            return;
+        }
+        error!("Interrupted, releasing locks");
+        lock::release_all_locks();
+        notify::notify_interrupted();
+        std::process::exit(130);
+    })
+    .context("Failed to install signal handler")?;
+
+    match cli.command {
+        Commands::Scan {
+            path,
+            output,
+            threads,
+            batch_size,
+            channel_capacity,
+            compression,
+            follow_symlinks,
+            max_depth,
+            log_file: _,
+            incremental,
+            rows_per_chunk,
+            chunk_interval_secs,
+            emit_checksums,
+            resume,
+            override_chunking,
+            break_lock,
+            allow_output_in_scan_path,
+            shard_index,
+            shard_count,
+            shard_depth,
+            baseline,
+            status_file,
+            stats_output,
+            fail_on_errors,
+            upload_url,
+            upload_retries,
+            s3_endpoint,
+            spool_dir,
+            post_chunk_cmd,
+            post_scan_cmd,
+            post_chunk_on_error,
+            notify_webhook,
+            notify_secret_file,
+            notify_slack_webhook,
+            slack_warn_errors,
+            slack_danger_errors,
+            notify_timeout,
+            notify_retries,
+            email_report,
+            smtp_server,
+            smtp_tls,
+            smtp_user,
+            email_from,
+            metrics_listen,
+            metrics_grace,
+            status_listen,
+            status_grace,
+        } => {
+            let shard = match (shard_index, shard_count) {
+                (Some(index), Some(count)) => Some(Shard::new(index, count, shard_depth)?),
+                _ => None,
+            };
+            let post = PostCommands {
+                chunk: post_chunk_cmd,
+                scan: post_scan_cmd,
+                on_error: post_chunk_on_error.into(),
+            };
+            let webhook = match notify_webhook {
+                Some(url) => {
+                    if !cfg!(feature = "remote") {
+                        return Err(anyhow::anyhow!("--notify-webhook requires building with --features remote"));
+                    }
+                    let secret = notify_secret_file
+                        .map(|file| std::fs::read_to_string(&file)
+                            .with_context(|| format!("Failed to read {}", file.display())))
+                        .transpose()?
+                        .map(|secret| secret.trim().to_string());
+                    Some(WebhookOptions { url, secret, timeout: notify_timeout, retries: notify_retries })
+                }
+                None => None,
+            };
+            let slack = match notify_slack_webhook {
+                Some(url) => {
+                    if !cfg!(feature = "remote") {
+                        return Err(anyhow::anyhow!("--notify-slack-webhook requires building with --features remote"));
+                    }
+                    let options = WebhookOptions { url, secret: None, timeout: notify_timeout, retries: notify_retries };
+                    Some((options, ErrorThresholds { warning: slack_warn_errors, danger: slack_danger_errors }))
+                }
+                None => None,
+            };
+
+            let email = if email_report.is_empty() {
+                None
+            } else {
+                if !cfg!(feature = "email") {
+                    return Err(anyhow::anyhow!("--email-report requires building with --features email"));
+                }
+                Some(EmailOptions {
+                    to: email_report,
+                    from: email_from.unwrap_or_else(|| format!("storage-scanner@{}", lock::hostname())),
+                    server: smtp_server,
+                    tls: smtp_tls.into(),
+                    user: smtp_user,
+                    password: std::env::var("SMTP_PASSWORD").ok(),
+                    timeout: SMTP_TIMEOUT,
+                })
+            };
+
+            if metrics_listen.is_some() && !cfg!(feature = "metrics") {
+                return Err(anyhow::anyhow!("--metrics-listen requires building with --features metrics"));
+            }
+            if status_listen.is_some() && !cfg!(feature = "status-server") {
+                return Err(anyhow::anyhow!("--status-listen requires building with --features status-server"));
+            }
+
+            // Whichever way the scan ends, the webhooks and email hear about it
+            let root = path.to_string_lossy().to_string();
+            let started_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+            let failed = |status: ScanOutcome, error: String| {
+                let mut notification = ScanNotification::failed(root.clone(), started_at, status, error);
+                notification.resumed = resume;
+                notification
+            };
+            let announce = {
+                let (webhook, slack) = (webhook.clone(), slack.clone());
+                move |notification: &ScanNotification| {
+                    if let Some(ref webhook) = webhook {
+                        notify::notify(webhook, notification);
+                    }
+                    if let Some((ref slack, thresholds)) = slack {
+                        slack::notify_slack(slack, thresholds, notification);
+                    }
+                }
+            };
+            if webhook.is_some() || slack.is_some() {
+                notify::on_interrupt(failed(ScanOutcome::Interrupted, "Interrupted by signal".to_string()), Box::new(announce.clone()));
+            }
+            let result = run_scan(
+                path,
+                output,
+                threads,
+                batch_size,
+                channel_capacity,
+                compression,
+                follow_symlinks,
+                max_depth,
+                incremental,
+                rows_per_chunk,
+                chunk_interval_secs,
+                emit_checksums,
+                resume,
+                override_chunking,
+                break_lock,
+                allow_output_in_scan_path,
+                shard,
+                baseline,
+                status_file,
+                stats_output,
+                upload_url.map(|url| (url, upload_retries)),
+                s3_endpoint,
+                spool_dir,
+                post,
+                metrics_listen.map(|listen| (listen, metrics_grace)),
+                status_listen.map(|listen| (listen, status_grace)),
+            );
+            notify::clear_interrupt();
+            if webhook.is_some() || slack.is_some() || email.is_some() {
+                let notification = match result {
+                    Ok(ref notification) => notification.clone(),
+                    Err(ref e) => failed(ScanOutcome::Failed, format!("{:#}", e)),
+                };
+                announce(&notification);
+                if let Some(ref email) = email {
+                    email::email_report(email, &notification);
+                }
+            }
+            let notification = result?;
+            if let Some(limit) = fail_on_errors {
+                if notification.errors.total > limit {
+                    error!(
+                        "Scan counted {} errors, more than --fail-on-errors {}",
+                        utils::format_number(notification.errors.total),
+                        utils::format_number(limit)
+                    );
+                    std::process::exit(EXIT_TOO_MANY_ERRORS);
+                }
+            }
+        }
+        Commands::ShardPlan { path, shard_count, shard_depth, baseline, json } => {
+            run_shard_plan(path, shard_count, shard_depth, baseline, json)?;
+        }
+        Commands::Generate { output, files, dirs, depth, size_dist, seed, dense, manifest } => {
+            let options = GenerateOptions { files, dirs, depth, size_dist, seed, sparse: !dense };
+            run_generate(output, options, manifest)?;
+        }
+        Commands::Monitor { status_file, refresh } => {
+            run_monitor(status_file, refresh)?;
+        }
+        Commands::Aggregate {
+            input,
+            include_file,
+            tag_scan_id,
+            scan_id,
+            merge,
+            host,
+            allow_overlap,
+            merged_manifest,
+            emit_checksums,
+            output,
+            partition_by,
+            append,
+            delete_chunks,
+            archive_chunks,
+            retain_last,
+            force_delete,
+            dry_run_delete,
+            delete_unlisted,
+            strict,
+            dedupe_by,
+            sort_by,
+            sort_memory,
+            memory_limit,
+            filter_type,
+            min_size,
+            mtime_older_than,
+            path_prefix,
+            threads,
+            summary,
+            summary_output,
+        } => {
+            let retention = match archive_chunks {
+                Some(dir) => Some(RetentionAction::Archive(dir)),
+                None if delete_chunks => Some(RetentionAction::Delete),
+                None => None,
+            };
+            let policy = retention.map(|action| RetentionPolicy {
+                action,
+                retain_last,
+                delete_unlisted,
+                dry_run: dry_run_delete,
+            });
+            let options = AggregateOptions {
+                strict,
+                dedupe_by: dedupe_by.map(DedupeKey::from),
+                sort_by,
+                sort_memory,
+                threads: threads.unwrap_or_else(num_cpus::get),
+                memory_limit,
+                filter: RowFilter {
+                    file_type: filter_type,
+                    min_size,
+                    modified_before: mtime_older_than.map(|age| {
+                        (SystemTime::now() - age).duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+                    }),
+                    accessed_before: None,
+                    path_prefix,
+                },
+                partition_by,
+                collect_stats: summary || summary_output.is_some(),
+                ..Default::default()
+            };
+            let merge = merge.then(|| HostMergeOptions {
+                hosts: host,
+                allow_overlap,
+                manifest: merged_manifest.unwrap_or_else(|| aggregate::get_manifest_path(&output)),
+            });
+            run_aggregate(
+                input,
+                include_file,
+                scan_id,
+                tag_scan_id,
+                merge,
+                output,
+                options,
+                policy,
+                force_delete,
+                append,
+                summary_output,
+                emit_checksums,
+            )?;
+        }
+        Commands::Report { input, format, top } => {
+            run_report(input, format, top)?;
+        }
+        Commands::Top { input, files, dirs, under, cumulative, json } => {
+            run_top(input, TopOptions { files, dirs, under, cumulative }, json)?;
+        }
+        Commands::Du { input, depth, under, sort_by, min_size } => {
+            run_du(input, DuOptions { depth, under, min_size, order: sort_by.into() })?;
+        }
+        Commands::Tree { input, under, depth, min_size, no_color, ascii } => {
+            let style = TreeStyle { ascii, color: !no_color && std::io::stdout().is_terminal() };
+            run_tree(input, TreeOptions { depth, under, min_size }, style)?;
+        }
+        Commands::Hardlinks { input, sort_memory, top, json } => {
+            let options = HardlinkOptions {
+                top,
+                sort_memory: sort_memory.unwrap_or(sort::DEFAULT_SORT_MEMORY),
+                spill_dir: std::env::temp_dir().join(format!("storage-scanner-hardlinks-{}", std::process::id())),
+            };
+            run_hardlinks(input, options, json)?;
+        }
+        Commands::EmptyDirs { input, recursive, json, output } => {
+            run_empty_dirs(input, recursive, json, output)?;
+        }
+        Commands::Symlinks { input, check_live, json, output } => {
+            run_symlinks(input, check_live, json, output)?;
+        }
+        Commands::Permissions { input, check, under, json, output } => {
+            let checks = if check.is_empty() {
+                permissions::builtin_checks()
+            } else {
+                check.iter().map(|name| permissions::find_check(name)).collect::<Result<Vec<_>>>()?
+            };
+            run_permissions(input, PermissionOptions { checks, under }, json, output)?;
+        }
+        Commands::Hotspots { input, top, min_entries, sort_memory, format } => {
+            let options = HotspotOptions {
+                top,
+                min_entries,
+                sort_memory: sort_memory.unwrap_or(sort::DEFAULT_SORT_MEMORY),
+                spill_dir: std::env::temp_dir().join(format!("storage-scanner-hotspots-{}", std::process::id())),
+            };
+            run_hotspots(input, options, format)?;
+        }
+        Commands::Duplicates { input, min_size, hash, threads, sort_memory, top, json, output } => {
+            let options = DuplicateOptions {
+                min_size,
+                hash,
+                threads: threads.unwrap_or_else(num_cpus::get),
+                sort_memory: sort_memory.unwrap_or(sort::DEFAULT_SORT_MEMORY),
+                spill_dir: std::env::temp_dir().join(format!("storage-scanner-duplicates-{}", std::process::id())),
+            };
+            run_duplicates(input, options, top, json, output)?;
+        }
+        Commands::Stale { input, atime_older_than, min_size, group_by, as_of, json, output } => {
+            let as_of = as_of.unwrap_or_else(|| {
+                SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+            });
+            let options = StaleOptions {
+                accessed_before: as_of - atime_older_than.as_secs() as i64,
+                min_size,
+                group_by: group_by.into(),
+            };
+            run_stale(input, options, json, output)?;
+        }
+        Commands::CleanCandidates { input, policy, output, as_of, json } => {
+            let as_of = as_of.unwrap_or_else(|| {
+                SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+            });
+            run_clean_candidates(input, policy, output, as_of, json)?;
+        }
+        Commands::QuotaCheck { input, quotas, json } => {
+            let status = run_quota_check(input, quotas, json)?;
+            if status != QuotaLevel::Ok {
+                std::process::exit(status.exit_code());
+            }
+        }
+        Commands::Growth { input, group_by, window, quota, json, csv } => {
+            let options = GrowthOptions {
+                group_by: group_by.into(),
+                window_days: window,
+                quotas: quota.into_iter().collect(),
+            };
+            run_growth(input, options, json, csv)?;
+        }
+        Commands::UserReport { input, map, group_map, under, age_bands, as_of, json, csv } => {
+            let as_of = as_of.unwrap_or_else(|| {
+                SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+            });
+            let options = UserReportOptions {
+                under,
+                age_bands_as_of: age_bands.then_some(as_of),
+                user_names: map.as_deref().map(user_report::load_id_map).transpose()?.unwrap_or_default(),
+                group_names: group_map.as_deref().map(user_report::load_id_map).transpose()?.unwrap_or_default(),
+            };
+            run_user_report(input, options, json, csv)?;
+        }
+        Commands::Hash { input, filter, algo, threads, output } => {
+            run_hash(input, filter, algo, threads.unwrap_or_else(num_cpus::get), output)?;
+        }
+        Commands::Query { input, filter, output } => {
+            run_query(input, filter, output)?;
+        }
+        Commands::Convert { input, output, columns, filter, compression, row_group_size } => {
+            let filter = filter
+                .map(|filter| Expr::parse(&filter).map_err(|e| anyhow::anyhow!("Invalid --where expression: {}", e)))
+                .transpose()?;
+            let columns = (!columns.is_empty()).then_some(columns);
+            let options = ConvertOptions { columns, filter, compression, row_group_size };
+            run_convert(input, output, options)?;
+        }
+        Commands::Export { input, output, columns, filter } => {
+            run_export(input, output, columns, filter)?;
+        }
+        Commands::Sample { input, size, seed, head, filter, format, output } => {
+            run_sample(input, size, seed, head, filter, format, output)?;
+        }
+        Commands::Schedule { config, run_once } => {
+            run_schedule(config, run_once)?;
+        }
+        Commands::Pipeline { config, resume } => {
+            run_pipeline(config, resume)?;
+        }
+        Commands::Serve { input, listen, max_rows, preload } => {
+            run_serve(input, listen, max_rows, preload)?;
+        }
+        Commands::Watch { path, output, format, rows_per_file, file_interval, rescan_interval } => {
+            run_watch(path, output, format, rows_per_file, file_interval, rescan_interval)?;
+        }
+        Commands::Diff { old, new, under, output, sort_memory } => {
+            run_diff(old, new, under, output, sort_memory)?;
+        }
+        Commands::LoadClickhouse { input, url, table, user, batch_size, retries } => {
+            run_load_clickhouse(input, url, table, user, batch_size, retries)?;
+        }
+        Commands::RetryUploads { manifest, upload_url, upload_retries } => {
+            run_retry_uploads(manifest, upload_url, upload_retries)?;
+        }
+        Commands::Verify { manifest, checksums, deep, json } => {
+            if let Some(manifest) = manifest {
+                run_verify(manifest, deep, json)?;
+            }
+            if let Some(checksums) = checksums {
+                run_verify_checksums(checksums, json)?;
+            }
+        }
+        Commands::Inspect { input, json } => {
+            run_inspect(input, json)?;
+        }
+        Commands::Validate { input, as_of, json } => {
+            let as_of = as_of.unwrap_or_else(|| {
+                SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+            });
+            run_validate(input, as_of, json)?;
+        }
+        Commands::CompareManifests { old, new, threshold, json } => {
+            run_compare_manifests(old, new, threshold, json)?;
+        }
+        Commands::ResumeStatus { manifest, path, baseline, json } => {
+            run_resume_status(manifest, path, baseline, json)?;
+        }
+        Commands::Manifest { command } => match command {
+            ManifestCommands::Merge {
+                inputs,
+                output,
+                allow_multiple_roots,
+            } => {
+                run_manifest_merge(inputs, output, allow_multiple_roots)?;
+            }
+        },
+        Commands::Config { command: ConfigCommands::PrintDefault } => {
+            print!("{}", config_file::template(&Cli::command()));
+        }
+        Commands::Config { command: ConfigCommands::Env } => {
+            print!("{}", config_file::env_table(&Cli::command()));
+        }
+        Commands::Version => {
+            println!("storage-scanner v{}", env!("CARGO_PKG_VERSION"));
+            println!("High-performance filesystem scanner for storage analytics");
+        }
+    }
+
+    Ok(())
+}
+
+/// The command line, with the options of a `--config` file it doesn't set;
+/// environment variables come between the two
+fn parse_cli() -> Result<Cli> {
+    Ok(Cli::parse_from(args_with_config(std::env::args_os().collect())?))
+}
+
+/// `args` with the options of the `--config` file they name added
+fn args_with_config(args: Vec<OsString>) -> Result<Vec<OsString>> {
+    // Required options may be in the file, so the first look ignores errors
+    let config = Cli::command()
+        .ignore_errors(true)
+        .try_get_matches_from(&args)
+        .ok()
+        .and_then(|matches| Some((config_file::config_path(&Cli::command(), &matches)?, matches)));
+    match config {
+        Some((path, matches)) => config_file::args_with_config(&Cli::command(), &matches, args, &path),
+        None => Ok(args),
+    }
+}
+
+fn setup_logging(verbose: bool) -> Result<()> {
+    let filter = if verbose {
+        EnvFilter::new("debug")
+    } else {
+        EnvFilter::new("info")
+    };
+
+    tracing_subscriber::registry()
+        .with(fmt::layer().with_target(false))
+        .with(filter)
+        .init();
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_scan(
+    path: PathBuf,
+    output: PathBuf,
+    threads: Option<usize>,
+    batch_size: usize,
+    channel_capacity: usize,
+    compression: Option<parquet::basic::Compression>,
+    follow_symlinks: bool,
+    max_depth: Option<usize>,
+    incremental: bool,
+    rows_per_chunk: usize,
+    chunk_interval_secs: u64,
+    emit_checksums: Option<ChecksumAlgo>,
+    resume: bool,
+    override_chunking: bool,
+    break_lock: bool,
+    allow_output_in_scan_path: bool,
+    shard: Option<Shard>,
+    baseline: Option<PathBuf>,
+    status_file: Option<PathBuf>,
+    stats_output: Option<PathBuf>,
+    upload: Option<(String, u32)>,
+    s3_endpoint: Option<String>,
+    spool_dir: Option<PathBuf>,
+    post: PostCommands,
+    metrics_listen: Option<(String, Duration)>,
+    status_listen: Option<(String, Duration)>,
+) -> Result<ScanNotification> {
+    info!("Storage Scanner v{}", env!("CARGO_PKG_VERSION"));
+    info!("Starting scan operation");
+
+    // Each shard writes chunks and a manifest of its own
+    let output = match shard {
+        Some(ref shard) => shard.output_path(&output),
+        None => output,
+    };
+
+    // An s3:// output is written to a local spool and uploaded chunk by chunk
+    let (output, store) = if output.to_string_lossy().starts_with("s3://") {
+        if !incremental {
+            return Err(anyhow::anyhow!("An s3:// --output requires --incremental"));
+        }
+        if emit_checksums.is_some() {
+            return Err(anyhow::anyhow!("--emit-checksums needs a local --output; chunks in the spool are removed once uploaded"));
+        }
+        let url = output.to_string_lossy().to_string();
+        let (store, file_name) = s3_store(&url, s3_endpoint.as_deref())?;
+        info!("  Uploading to: {}", url);
+        (spool_dir.unwrap_or_else(|| PathBuf::from(".")).join(file_name), Some(store))
+    } else {
+        if s3_endpoint.is_some() || spool_dir.is_some() {
+            return Err(anyhow::anyhow!("--s3-endpoint and --spool-dir require an s3:// --output"));
+        }
+        (output, None)
+    };
+    let stem = output.file_stem().context("output path has no file name")?.to_string_lossy().to_string();
+    let manifest_path = output.with_file_name(format!("{}_manifest.json", stem));
+    let checksums = match emit_checksums {
+        Some(algo) => Some(Arc::new(Mutex::new(ChecksumList::new(&checksums::checksums_path(&output), algo)?))),
+        None => None,
+    };
+
+    // Validate input path
+    utils::validate_path(&path)
+        .context("Invalid input path")?;
+
+    // Ensure output directory exists
+    utils::prepare_output_path(&output, OutputKind::File)?;
+
+    // Refuse to run if another scan is writing to the same output
+    let lock = ScanLock::acquire(&output, break_lock)?;
+
+    let baseline = match (baseline, shard) {
+        (Some(path), Some(ref shard)) => {
+            let manifest = ScanManifest::load_from_file(&path)
+                .with_context(|| format!("Failed to load baseline manifest {}", path.display()))?;
+            Some(Baseline::for_shard(&manifest, shard))
+        }
+        (path, _) => path.map(Baseline::load).transpose()?,
+    };
+
+    // Configure scan options
+    let mut options = ScanOptions::builder()
+        .threads(threads.unwrap_or_else(num_cpus::get))
+        .batch_size(batch_size)
+        .channel_capacity_entries(channel_capacity)
+        .follow_symlinks(follow_symlinks)
+        .max_depth(max_depth);
+
+    // An output inside the tree would be scanned as it grows: its chunks,
+    // manifest, and lock are left out unless asked for
+    if utils::output_in_tree(&output, &path) {
+        if allow_output_in_scan_path {
+            warn!("Output {} is inside the scanned tree; its files will be scanned too", output.display());
+        } else {
+            warn!("Output {} is inside the scanned tree; leaving its files out of the scan", output.display());
+            options = options.exclude_output(&output);
+        }
+    }
+    let options = options.build()?;
+
+    info!("Scan configuration:");
+    info!("  Input path: {}", path.display());
+    info!("  Output file: {}", output.display());
+    info!("  Threads: {}", options.num_threads);
+    info!("  Batch size: {}", utils::format_number(options.batch_size as u64));
+    if let Some(compression) = compression {
+        info!("  Compression: {}", compression);
+    }
+    info!("  Follow symlinks: {}", options.follow_symlinks);
+    if let Some(depth) = options.max_depth {
+        info!("  Max depth: {}", depth);
+    }
+    if let Some(ref shard) = shard {
+        info!("  Shard: {} of {} (directories at depth {})", shard.index, shard.count, shard.depth);
+    }
+    if let Some(ref baseline) = baseline {
+        info!("  Baseline: {} entries", utils::format_number(baseline.total_rows));
+    }
+
+    // Validate resume mode
+    if resume && !incremental {
+        error!("Resume mode requires --incremental flag");
+        return Err(anyhow::anyhow!("--resume requires --incremental"));
+    }
+
+    if incremental {
+        info!("  Incremental mode: ENABLED");
+        info!("  Rows per chunk: {}", utils::format_number(rows_per_chunk as u64));
+        info!("  Chunk interval: {} seconds", chunk_interval_secs);
+        if let Some(algo) = emit_checksums {
+            info!("  Checksums: {} ({})", checksums::checksums_path(&output).display(), algo);
+        }
+        if resume {
+            info!("  Resume mode: ENABLED");
+        }
+        if let Some((ref url, _)) = upload {
+            info!("  Upload URL: {}", url);
+        }
+        info!("");
+        info!("Note: Each chunk will be a complete, readable Parquet file.");
+        info!("      You can read chunks while the scan is still running.");
+    }
+
+    // Create scanner
+    let counters = ScanCounters::default();
+    let mut scanner = Scanner::new(options).with_counters(counters.clone());
+    if let Some(ref baseline) = baseline {
+        scanner = scanner.with_baseline(baseline.clone());
+    }
+    if let Some(shard) = shard {
+        scanner = scanner.with_shard(shard);
+    }
+    let status = StatusGuard((status_file.is_some() || status_listen.is_some()).then(|| {
+        Arc::new(StatusTracker::new(status_file, path.to_string_lossy().to_string(), baseline.clone()))
+    }));
+    let status_server = match (&status.0, &status_listen) {
+        (Some(tracker), Some((listen, _))) => {
+            let server = status::serve(listen, tracker.clone())?;
+            match server.local_addr() {
+                Some(addr) => info!("  Status: http://{}/status", addr),
+                None => info!("  Status: {}", listen),
+            }
+            Some(server)
+        }
+        _ => None,
+    };
+    if let Some(ref tracker) = status.0 {
+        if let Some(file) = tracker.path() {
+            info!("  Status file: {}", file.display());
+        }
+        scanner = scanner.with_status(tracker.clone());
+    }
+
+    let path_str = path.to_string_lossy().to_string();
+
+    // Choose the output based on mode
+    let (sink, metrics) = if incremental {
+        // Use rotating writer for incremental mode
+        let config = RotatingWriterConfig {
+            base_output_path: output.clone(),
+            rows_per_chunk,
+            time_interval: Duration::from_secs(chunk_interval_secs),
+        };
+
+        // Check the shard before resuming, which repairs the output on disk
+        if resume && manifest_path.exists() {
+            let previous = ScanManifest::load_from_file(&manifest_path)?;
+            if previous.shard.is_some_and(|previous| Some(previous) != shard) {
+                return Err(anyhow::anyhow!(
+                    "Cannot resume: {} was written with a different --shard-count or --shard-depth",
+                    manifest_path.display()
+                ));
+            }
+        }
+
+        // Create or resume writer; a resumed one skips the directories it completed
+        let mut writer = if resume {
+            RotatingParquetWriter::resume_with(config, path_str.clone(), override_chunking)?
+        } else {
+            RotatingParquetWriter::new(config, path_str.clone())?
+        };
+        writer.manifest.shard = shard;
+        let writer = match status.0 {
+            Some(ref tracker) => writer.with_status(tracker.clone()),
+            None => writer,
+        };
+        let writer = match compression {
+            Some(compression) => writer.with_compression(compression),
+            None => writer,
+        };
+        let writer = match checksums {
+            Some(ref list) => writer.with_chunk_hook(Box::new(ChecksumHook::new(list.clone())?)),
+            None => writer,
+        };
+        let writer = match upload {
+            Some((ref url, retries)) => writer.with_chunk_hook(chunk_uploader(url.clone(), retries)?),
+            None => writer,
+        };
+        let writer = match store {
+            Some(ref store) => writer.with_chunk_hook(Box::new(SpoolUploader::new(store.clone(), SpoolOptions::default())?)),
+            None => writer,
+        };
+        let writer = match post.chunk {
+            Some(ref command) => {
+                let hook = ChunkCommand::new(command.clone(), manifest_path.clone(), post.on_error)?;
+                writer.with_chunk_hook(Box::new(hook))
+            }
+            None => writer,
+        };
+        // A resumed scan keeps the id of the scan it continues
+        let metrics = start_metrics(metrics_listen.as_ref(), &counters, &path_str, writer.manifest.scan_start)?;
+        let writer = match metrics {
+            Some((_, ref metrics)) => writer.with_chunk_hook(metrics.chunk_counter()),
+            None => writer,
+        };
+
+        (SinkConfig::Writer(Box::new(writer)), metrics)
+    } else {
+        let scan_start = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+        let metrics = start_metrics(metrics_listen.as_ref(), &counters, &path_str, scan_start)?;
+
+        // Use regular single-file writer
+        let sink = SinkConfig::File {
+            path: output.clone(),
+            compression: compression.unwrap_or(parquet::basic::Compression::SNAPPY),
+        };
+        (sink, metrics)
+    };
+
+    // Run scanner and writer
+    let result = scanner.run_to_parquet(&path, sink);
+    if let Err(storage_scanner::Error::WriterFailed { ref stats, .. }) = result {
+        error!(
+            "Output failed; partial counts when the scan stopped: {} files, {} directories, {}",
+            utils::format_number(stats.files_scanned),
+            utils::format_number(stats.directories_scanned),
+            utils::format_bytes(stats.total_size)
+        );
+    }
+    let ScanResult { stats, rows_written, manifest, .. } = result.context("Scan failed")?;
+    if let (Some(list), Some(manifest)) = (&checksums, &manifest) {
+        checksums::finish_scan(&mut list.lock().unwrap(), manifest, &manifest_path, &checksums::checksums_path(&output))?;
+    }
+
+    status.complete(&stats)?;
+
+    // The manifest goes up last, once every chunk has had its turn
+    let manifest_url = match store {
+        Some(ref store) => Some(spool::upload_manifest(store.as_ref(), &manifest_path)?),
+        None => None,
+    };
+    if let Some(ref command) = post.scan {
+        post_command::run_post_scan(command, &manifest_path, rows_written, post.on_error)?;
+    }
+
+    // Print final statistics
+    println!();
+    println!("Scan completed successfully");
+    println!("---");
+    println!("Files scanned:       {}", utils::format_number(stats.files_scanned));
+    println!("Directories scanned: {}", utils::format_number(stats.directories_scanned));
+    if stats.symlinks_scanned > 0 {
+        println!("Symlinks:            {}", utils::format_number(stats.symlinks_scanned));
+    }
+    println!("Total size:          {} (apparent)", utils::format_bytes(stats.total_size));
+    println!("Unique size:         {}", utils::format_bytes(stats.unique_total_size));
+    println!("Rows written:        {}", utils::format_number(rows_written));
+    println!("Duration:            {}", utils::format_duration(stats.duration_secs));
+    println!("Performance:         {:.0} files/second", stats.files_per_second());
+
+    if let Some(ref baseline) = baseline {
+        match baseline.growth(rows_written) {
+            Some(growth) => println!(
+                "Baseline:            {} entries (grew by {}, {:.1}%)",
+                utils::format_number(baseline.total_rows),
+                utils::format_number(growth),
+                utils::percentage(growth, baseline.total_rows)
+            ),
+            None => println!(
+                "Baseline:            {} entries ({:.1}% of baseline)",
+                utils::format_number(baseline.total_rows),
+                utils::percentage(rows_written, baseline.total_rows)
+            ),
+        }
+    }
+
+    print_scan_counts(&mut std::io::stdout().lock(), &stats)?;
+
+    if let Some(ref stats_path) = stats_output {
+        let mut document = serde_json::to_value(&stats)?;
+        document["root"] = path_str.clone().into();
+        document["rows_written"] = rows_written.into();
+        std::fs::write(stats_path, serde_json::to_string_pretty(&document)?)
+            .with_context(|| format!("Failed to write scan stats to {}", stats_path.display()))?;
+        println!("Stats written to:    {}", stats_path.display());
+    }
+
+    if let (Some(manifest), true) = (&manifest, upload.is_some() || store.is_some()) {
+        let uploaded = manifest.chunks.iter().filter(|c| c.is_uploaded()).count();
+        println!("Chunks uploaded:     {} of {}", uploaded, manifest.chunks.len());
+        if uploaded < manifest.chunks.len() && store.is_some() {
+            println!("Note: Chunks not uploaded remain in the spool directory");
+        } else if uploaded < manifest.chunks.len() {
+            println!("Note: Run retry-uploads on the manifest to upload the rest");
+        }
+    }
+
+    println!();
+    if incremental {
+        println!("Output written to chunk files:");
+        println!("  Base name: {}", output.display());
+        println!("  Pattern: {}_chunk_*.parquet", stem);
+        println!("  Manifest: {}_manifest.json", stem);
+        if checksums.is_some() {
+            println!("  Checksums: {}", checksums::checksums_path(&output).display());
+        }
+        if let Some(ref url) = manifest_url {
+            println!("  Uploaded manifest: {}", url);
+        }
+        println!();
+        println!("To read all chunks in Python:");
+        println!("  import polars as pl");
+        println!("  df = pl.read_parquet('{}_chunk_*.parquet')", stem);
+    } else {
+        println!("Output written to: {}", output.display());
+    }
+
+    lock.release();
+
+    // Both servers keep serving their grace periods at once
+    let finished = Instant::now();
+    if let (Some((server, _)), Some((_, grace))) = (metrics, metrics_listen) {
+        info!("Serving final metrics for {}", utils::format_duration(grace.as_secs_f64()));
+        server.finish(finished + grace)?;
+    }
+    if let (Some(server), Some((_, grace))) = (status_server, status_listen) {
+        info!("Serving final status for {}", utils::format_duration(grace.as_secs_f64()));
+        server.finish(finished + grace)?;
+    }
+
+    // A resumed scan keeps the id of the scan it continues
+    let scan_start = manifest.as_ref().map_or(stats.start_time, |m| m.scan_start);
+    let mut notification = ScanNotification::completed(path_str, scan_start, &stats, rows_written);
+    notification.outputs = match manifest {
+        Some(ref manifest) => manifest.chunks.iter()
+            .map(|c| match c.upload {
+                Some(ChunkUpload::Uploaded { ref url, .. }) => url.clone(),
+                _ => c.file_path.clone(),
+            })
+            .collect(),
+        None => vec![output.to_string_lossy().to_string()],
+    };
+    if incremental {
+        notification.manifest = manifest_url.or_else(|| Some(manifest_path.to_string_lossy().to_string()));
+    }
+    notification.resumed = resume;
+    if let Some(manifest) = manifest {
+        notification.dir_stats = manifest.dir_stats;
+    }
+
+    Ok(notification)
+}
+
+/// Serve the scan's counters on `/metrics`, if asked to
+fn start_metrics(
+    listen: Option<&(String, Duration)>,
+    counters: &ScanCounters,
+    root: &str,
+    scan_start: i64,
+) -> Result<Option<(HttpServer, Arc<ScanMetrics>)>> {
+    let Some((listen, _)) = listen else { return Ok(None) };
+    let metrics = Arc::new(ScanMetrics::new(counters.clone(), root.to_string(), scan_start));
+    let server = metrics::serve(listen, metrics.clone())?;
+    match server.local_addr() {
+        Some(addr) => info!("  Metrics: http://{}/metrics", addr),
+        None => info!("  Metrics: {}", listen),
+    }
+    Ok(Some((server, metrics)))
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_aggregate(
+    mut inputs: Vec<PathBuf>,
+    include_files: Vec<PathBuf>,
+    scan_ids: Vec<String>,
+    tag_scan_id: bool,
+    merge: Option<HostMergeOptions>,
+    output: PathBuf,
+    mut options: AggregateOptions,
+    retention: Option<RetentionPolicy>,
+    force_delete: bool,
+    append: bool,
+    summary_output: Option<PathBuf>,
+    emit_checksums: Option<ChecksumAlgo>,
+) -> Result<()> {
+    info!("Storage Scanner v{}", env!("CARGO_PKG_VERSION"));
+    info!("Starting aggregation operation");
+
+    if !scan_ids.is_empty() && scan_ids.len() != inputs.len() {
+        return Err(anyhow::anyhow!(
+            "--scan-id must be given once per --input ({} inputs, {} scan ids)",
+            inputs.len(),
+            scan_ids.len()
+        ));
+    }
+    if retention.is_some() && inputs.len() > 1 {
+        return Err(anyhow::anyhow!("Deleting or archiving chunks requires a single --input"));
+    }
+
+    // A dataset is written into a directory, anything else to one file
+    let kind = if options.partition_by.is_some() { OutputKind::Directory } else { OutputKind::File };
+    utils::prepare_output_path(&output, kind)?;
+
+    // Download URL inputs; the copies are removed when this returns
+    let _remote = if inputs.iter().any(|i| utils::is_url(&i.to_string_lossy())) {
+        if retention.is_some() || append {
+            return Err(anyhow::anyhow!("--delete-chunks, --archive-chunks and --append require local inputs"));
+        }
+        let remote = RemoteInputs::next_to(&output)?;
+        fetch_remote_inputs(&mut inputs, &remote.0)?;
+        Some(remote)
+    } else {
+        None
+    };
+
+    // Scans merged from several hosts must not cover the same files twice
+    let sources = match merge {
+        Some(ref merge) => {
+            let sources = host_merge::load_sources(&inputs, &merge.hosts)?;
+            let overlaps = host_merge::describe_overlaps(&sources, &host_merge::find_overlaps(&sources));
+            if !overlaps.is_empty() && !merge.allow_overlap {
+                return Err(anyhow::anyhow!(
+                    "Scan roots overlap:\n  {}\nPass --allow-overlap to merge them anyway, dropping duplicate paths",
+                    overlaps.join("\n  ")
+                ));
+            }
+            for overlap in &overlaps {
+                let message = format!("Scan roots overlap: {}; duplicate paths will be dropped", overlap);
+                warn!("{}", message);
+                eprintln!("WARNING: {}", message);
+            }
+            if !overlaps.is_empty() {
+                options.dedupe_by.get_or_insert(DedupeKey::Path);
+            }
+            Some(sources)
+        }
+        None => None,
+    };
+
+    // Find chunk files
+    let mut aggregate_inputs = Vec::new();
+    for (i, input) in inputs.iter().enumerate() {
+        let chunk_files = aggregate::find_chunk_files(input)?;
+        if chunk_files.is_empty() {
+            error!("No Parquet chunk files found");
+            return Err(anyhow::anyhow!("No chunk files found in: {}", input.display()));
+        }
+        aggregate_inputs.push(AggregateInput {
+            input: input.clone(),
+            chunk_files,
+            scan_id: scan_ids.get(i).cloned(),
+        });
+    }
+    for file in include_files {
+        if !file.is_file() {
+            return Err(anyhow::anyhow!("--include-file {} is not a file", file.display()));
+        }
+        aggregate_inputs.push(AggregateInput { input: file.clone(), chunk_files: vec![file], scan_id: None });
+    }
+
+    // Name every file before reading any, so a stray one is caught up front
+    for input in &aggregate_inputs {
+        println!("Selected {} file(s) from {}:", input.chunk_files.len(), input.input.display());
+        for chunk in &input.chunk_files {
+            println!("  {}", chunk.display());
+        }
+    }
+    let chunk_files: Vec<PathBuf> = aggregate_inputs.iter()
+        .flat_map(|i| i.chunk_files.iter().cloned())
+        .collect();
+
+    // Tag rows when asked to, or automatically when the chunks span scans
+    let tags = aggregate::resolve_scan_tags(&aggregate_inputs)?;
+    let distinct = tags.iter().flatten().map(|t| t.scan_id.as_str()).collect::<HashSet<_>>().len();
+    if tag_scan_id || !scan_ids.is_empty() || distinct > 1 || sources.is_some() {
+        options.scan_tags = tags.into_iter().zip(&chunk_files)
+            .map(|(tag, chunk)| tag.ok_or_else(|| anyhow::anyhow!(
+                "Cannot tell which scan {} belongs to; pass --scan-id for its input",
+                chunk.display()
+            )))
+            .collect::<Result<Vec<_>>>()?;
+        info!("Tagging rows with {} scan id(s)", distinct.max(1));
+    } else if inputs.len() > 1 {
+        let message = format!(
+            "Combining {} inputs without scan identifiers; rows will not be attributable to a scan. \
+             Pass --tag-scan-id or --scan-id per input to add scan_id and scan_date columns",
+            inputs.len()
+        );
+        warn!("{}", message);
+        eprintln!("WARNING: {}", message);
+    }
+
+    if let Some(ref sources) = sources {
+        options.hostnames = aggregate_inputs.iter().zip(sources)
+            .flat_map(|(input, source)| vec![source.hostname.clone(); input.chunk_files.len()])
+            .collect();
+        let hosts: HashSet<&str> = sources.iter().map(|s| s.hostname.as_str()).collect();
+        info!("Merging {} scan(s) from {} host(s)", sources.len(), hosts.len());
+    }
+
+    info!("Found {} chunk file(s) to aggregate", chunk_files.len());
+    info!("Output file: {}", output.display());
+
+    let appended = if append {
+        Some(append::append_chunks(&chunk_files, &output, &options)?)
+    } else {
+        None
+    };
+    let summary = match appended {
+        Some(ref appended) => match appended.aggregate {
+            Some(ref run) => run.clone(),
+            None => {
+                println!();
+                println!("No new chunks ({} already aggregated); {} is up to date", appended.skipped_chunks, output.display());
+                println!("Total rows:            {}", utils::format_number(appended.total_rows));
+                return Ok(());
+            }
+        },
+        None => aggregate::aggregate_chunks_with(&chunk_files, &output, &options)?,
+    };
+
+    info!("Aggregation completed successfully");
+    println!();
+    println!("Aggregation Summary");
+    println!("---");
+    match appended {
+        Some(ref appended) => {
+            println!("New chunk files:       {}", appended.new_chunks.len());
+            if !appended.changed_chunks.is_empty() {
+                println!("Changed chunk files:   {} (rows included again)", appended.changed_chunks.len());
+            }
+            println!("Already aggregated:    {}", appended.skipped_chunks);
+            println!("Rows carried forward:  {}", utils::format_number(appended.carried_rows));
+        }
+        None => println!("Chunk files processed: {}", summary.chunk_files),
+    }
+    println!("Total rows:            {}", utils::format_number(summary.total_rows));
+    if !options.scan_tags.is_empty() {
+        let scans: HashSet<&str> = options.scan_tags.iter().map(|t| t.scan_id.as_str()).collect();
+        println!("Scans tagged:          {}", scans.len());
+    }
+    if let Some(ref sources) = sources {
+        let hosts: HashSet<&str> = sources.iter().map(|s| s.hostname.as_str()).collect();
+        println!("Hosts:                 {}", hosts.len());
+    }
+    if options.dedupe_by.is_some() {
+        println!("Duplicates dropped:    {}", utils::format_number(summary.duplicates_dropped));
+    }
+    if !options.filter.is_empty() {
+        println!("Rows filtered out:     {}", utils::format_number(summary.rows_filtered));
+    }
+    if !options.sort_by.is_empty() {
+        println!("Sorted by:             {} ({} spilled run(s))", options.sort_by.join(","), summary.sort_runs);
+    }
+    println!("Duration:              {:.2}s", summary.duration_secs);
+    println!("Read throughput:       {}", throughput(summary.input_bytes, summary.chunk_rows.iter().sum(), summary.read_secs));
+    println!("Write throughput:      {}", throughput(summary.output_bytes, summary.total_rows, summary.write_secs));
+    match summary.dataset {
+        Some(ref dataset) => {
+            let files: usize = dataset.partitions.iter().map(|p| p.files.len()).sum();
+            println!("Output dataset:        {}", output.display());
+            println!("Partitions:            {} ({} file(s))", dataset.partitions.len(), files);
+            println!("Dataset manifest:      {}", output.join(partitioned_writer::DATASET_MANIFEST).display());
+        }
+        None => println!("Output file:           {}", output.display()),
+    }
+    println!("Output size:           {}", utils::format_bytes(summary.output_bytes));
+    if !options.sort_by.is_empty() && summary.input_bytes > 0 {
+        println!(
+            "Unsorted chunks:       {} (sorted output is {:.1}%)",
+            utils::format_bytes(summary.input_bytes),
+            summary.output_bytes as f64 / summary.input_bytes as f64 * 100.0,
+        );
+    }
+
+    if let (Some(sources), Some(ref merge)) = (sources, &merge) {
+        let chunks: Vec<usize> = aggregate_inputs.iter().map(|i| i.chunk_files.len()).collect();
+        let rows = host_merge::rows_per_source(&chunks, &summary.chunk_rows);
+        println!();
+        println!("Rows per Source");
+        println!("---");
+        println!("{:<24} {:<40} {:>14}", "Host", "Scan root", "Rows read");
+        for (source, rows) in sources.iter().zip(rows) {
+            println!("{:<24} {:<40} {:>14}", source.hostname, source.manifest.scan_path, utils::format_number(rows));
+        }
+
+        let merged = host_merge::merged_manifest(&sources)?;
+        utils::prepare_output_path(&merge.manifest, OutputKind::File)?;
+        merged.save_to_file(&merge.manifest)?;
+        println!("Merged manifest:       {}", merge.manifest.display());
+    }
+
+    // Output files first, then whatever describes them
+    if let Some(algo) = emit_checksums {
+        let path = checksums::checksums_path(&output);
+        let mut list = ChecksumList::new(&path, algo)?;
+        match summary.dataset {
+            Some(ref dataset) => {
+                for file in dataset.partitions.iter().flat_map(|p| &p.files) {
+                    list.add(&output.join(&file.path))?;
+                }
+                list.add(&output.join(partitioned_writer::DATASET_MANIFEST))?;
+            }
+            None => list.add(&output)?,
+        }
+        if append {
+            list.add(&append::state_path(&output))?;
+        }
+        if let Some(ref merge) = merge {
+            list.add(&merge.manifest)?;
+        }
+        list.write(&path)?;
+        println!("Checksums:             {} ({})", path.display(), algo);
+    }
+
+    if let Some(ref stats) = summary.stats {
+        print_usage_report(stats)?;
+        if let Some(ref path) = summary_output {
+            std::fs::write(path, serde_json::to_string_pretty(stats)?)
+                .with_context(|| format!("Failed to write usage report to {}", path.display()))?;
+            println!("Usage report written:  {}", path.display());
+        }
+    }
+
+    // Delete or archive chunk files if requested
+    if let Some(policy) = retention {
+        let manifest_path = aggregate::get_manifest_path(&inputs[0]);
+        let mut manifest = if manifest_path.exists() {
+            Some(ScanManifest::load_from_file(&manifest_path)?)
+        } else {
+            None
+        };
+
+        let check = aggregate::verify_aggregation(manifest.as_ref(), &chunk_files, &summary)?;
+        if check.is_ok() {
+            info!("Verification passed, applying retention policy...");
+        } else {
+            for (path, problem) in &check.chunk_problems {
+                error!("  {}: {}", path.display(), problem);
+            }
+            for problem in &check.problems {
+                error!("  {}", problem);
+            }
+            if !force_delete {
+                return Err(anyhow::anyhow!(
+                    "Verification failed ({} rows read, {} expected); chunk files were left in place. Use --force-delete to override",
+                    check.rows_read,
+                    check.expected_rows
+                ));
+            }
+            warn!("Verification failed, applying retention policy anyway (--force-delete)");
+        }
+        let retention = aggregate::apply_retention(
+            &chunk_files,
+            manifest.as_mut().map(|m| (manifest_path.as_path(), m)),
+            &policy,
+        )?;
+
+        let verb = match (&policy.action, policy.dry_run) {
+            (RetentionAction::Delete, false) => "Deleted",
+            (RetentionAction::Archive(_), false) => "Archived",
+            (RetentionAction::Delete, true) => "Would delete",
+            (RetentionAction::Archive(_), true) => "Would archive",
+        };
+        println!();
+        if policy.dry_run {
+            println!("Dry run: no chunk files were removed");
+            for path in &retention.removed {
+                println!("{}: {}", verb, path.display());
+            }
+            for path in &retention.retained {
+                println!("Would keep: {}", path.display());
+            }
+            for path in &retention.unlisted {
+                println!("Would skip (unlisted): {}", path.display());
+            }
+        }
+        let removed_label = format!("{}:", verb);
+        println!("{:<23}{}", removed_label, retention.removed.len());
+        println!("Kept (--retain-last):  {}", retention.retained.len());
+        println!("Skipped (unlisted):    {}", retention.unlisted.len());
+        println!("Failed:                {}", retention.failed.len());
+        if !retention.unlisted.is_empty() && !policy.dry_run {
+            let message = format!(
+                "Left {} chunk file(s) not listed in {}; pass --delete-unlisted to remove them",
+                retention.unlisted.len(),
+                if manifest.is_some() { "the manifest" } else { "a manifest" }
+            );
+            warn!("{}", message);
+            eprintln!("WARNING: {}", message);
+        }
+        if manifest.is_some() && !policy.dry_run {
+            println!("Manifest updated: {}", manifest_path.display());
+        }
+        if !retention.failed.is_empty() {
+            for (path, reason) in &retention.failed {
+                error!("  {}: {}", path.display(), reason);
+            }
+            return Err(anyhow::anyhow!("Failed to remove {} chunk file(s)", retention.failed.len()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Groups listed per breakdown in the usage report
+const REPORT_TOP_GROUPS: usize = 10;
+
+fn print_usage_report(stats: &UsageStats) -> Result<()> {
+    println!();
+    println!("Usage Report");
+    println!("---");
+    StorageReport::new(stats, REPORT_TOP_GROUPS).write_table(&mut std::io::stdout().lock())?;
+    Ok(())
+}
+
+fn run_report(input: PathBuf, format: ReportFormat, top: usize) -> Result<()> {
+    let chunk_files = aggregate::find_chunk_files(&input)?;
+    if chunk_files.is_empty() {
+        return Err(anyhow::anyhow!("No Parquet files found in: {}", input.display()));
+    }
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+    let stats = report::collect_stats(&chunk_files, now)?;
+    let report = StorageReport::new(&stats, top);
+
+    let mut out = std::io::stdout().lock();
+    match format {
+        ReportFormat::Table => {
+            writeln!(out, "Storage Report: {} ({} file(s))", input.display(), chunk_files.len())?;
+            writeln!(out, "---")?;
+            report.write_table(&mut out)?;
+        }
+        ReportFormat::Json => writeln!(out, "{}", serde_json::to_string_pretty(&report)?)?,
+        ReportFormat::Csv => report.write_csv(&mut out)?,
+    }
+    Ok(())
+}
+
+fn run_top(input: PathBuf, options: TopOptions, json: bool) -> Result<()> {
+    let chunk_files = aggregate::find_chunk_files(&input)?;
+    if chunk_files.is_empty() {
+        return Err(anyhow::anyhow!("No Parquet files found in: {}", input.display()));
+    }
+
+    let report = top::top_entries(&chunk_files, &options)?;
+
+    let mut out = std::io::stdout().lock();
+    if json {
+        writeln!(out, "{}", serde_json::to_string_pretty(&report)?)?;
+        return Ok(());
+    }
+
+    if options.files > 0 {
+        writeln!(out, "Largest files{}", options.under.as_deref().map(|u| format!(" under {}", u)).unwrap_or_default())?;
+        writeln!(out, "{:>5}  {:>12}  Path", "Rank", "Size")?;
+        for (rank, file) in report.files.iter().enumerate() {
+            writeln!(out, "{:>5}  {:>12}  {}", rank + 1, utils::format_bytes(file.size), file.path)?;
+        }
+    }
+    if options.dirs > 0 {
+        if options.files > 0 {
+            writeln!(out)?;
+        }
+        let rollup = if options.cumulative { "including subdirectories" } else { "files directly inside" };
+        writeln!(out, "Heaviest directories ({})", rollup)?;
+        writeln!(out, "{:>5}  {:>12}  {:>12}  Path", "Rank", "Size", "Files")?;
+        for (rank, dir) in report.directories.iter().enumerate() {
+            writeln!(out, "{:>5}  {:>12}  {:>12}  {}",
+                     rank + 1,
+                     utils::format_bytes(dir.bytes),
+                     utils::format_number(dir.files),
+                     dir.path)?;
+        }
+    }
+    Ok(())
+}
+
+fn run_shard_plan(path: PathBuf, count: u32, depth: usize, baseline: Option<PathBuf>, json: bool) -> Result<()> {
+    let chunk_files = match baseline {
+        Some(ref input) => {
+            let chunk_files = aggregate::find_chunk_files(input)?;
+            if chunk_files.is_empty() {
+                return Err(anyhow::anyhow!("No Parquet files found in: {}", input.display()));
+            }
+            Some(chunk_files)
+        }
+        None => None,
+    };
+    let plan = shard::plan(&path, count, depth, chunk_files.as_deref())?;
+
+    let mut out = std::io::stdout().lock();
+    if json {
+        writeln!(out, "{}", serde_json::to_string_pretty(&plan)?)?;
+        return Ok(());
+    }
+
+    let estimate = |value: Option<u64>, format: fn(u64) -> String| value.map(format).unwrap_or_else(|| "-".to_string());
+    writeln!(out, "{:>5}  {:>12}  {:>12}  Directory", "Shard", "Files", "Size")?;
+    for unit in &plan.units {
+        writeln!(out, "{:>5}  {:>12}  {:>12}  {}",
+                 unit.shard,
+                 estimate(unit.files, utils::format_number),
+                 estimate(unit.bytes, utils::format_bytes),
+                 unit.path)?;
+    }
+
+    writeln!(out)?;
+    writeln!(out, "{:>5}  {:>12}  {:>12}  {:>12}", "Shard", "Directories", "Files", "Size")?;
+    for totals in &plan.shards {
+        writeln!(out, "{:>5}  {:>12}  {:>12}  {:>12}",
+                 totals.index,
+                 utils::format_number(totals.units),
+                 utils::format_number(totals.files),
+                 utils::format_bytes(totals.bytes))?;
+    }
+    let measure = if chunk_files.is_some() { "size" } else { "directories" };
+    writeln!(out)?;
+    writeln!(out, "Largest shard: {:.2}x the mean by {}", plan.imbalance(), measure)?;
+    let unestimated = plan.units.iter().filter(|u| u.bytes.is_none()).count();
+    if chunk_files.is_some() && unestimated > 0 {
+        writeln!(out, "Note: {} directories are not in the baseline and count as empty", unestimated)?;
+    }
+    Ok(())
+}
+
+fn run_du(input: PathBuf, options: DuOptions) -> Result<()> {
+    let chunk_files = aggregate::find_chunk_files(&input)?;
+    if chunk_files.is_empty() {
+        return Err(anyhow::anyhow!("No Parquet files found in: {}", input.display()));
+    }
+
+    let entries = du::disk_usage(&chunk_files, &options)?;
+    if entries.is_empty() {
+        return Err(anyhow::anyhow!("No directories found{}",
+                                   options.under.map(|u| format!(" under {}", u)).unwrap_or_default()));
+    }
+
+    let mut out = std::io::stdout().lock();
+    writeln!(out, "{:>12}  {:>12}  {:>7}  Directory", "Size", "Files", "Parent")?;
+    for entry in &entries {
+        let share = entry.percent_of_parent
+            .map(|p| format!("{:.1}%", p))
+            .unwrap_or_default();
+        writeln!(out, "{:>12}  {:>12}  {:>7}  {}{}",
+                 utils::format_bytes(entry.bytes),
+                 utils::format_number(entry.files),
+                 share,
+                 "  ".repeat(entry.depth),
+                 entry.path)?;
+    }
+    Ok(())
+}
+
+fn run_tree(input: PathBuf, options: TreeOptions, style: TreeStyle) -> Result<()> {
+    let chunk_files = aggregate::find_chunk_files(&input)?;
+    if chunk_files.is_empty() {
+        return Err(anyhow::anyhow!("No Parquet files found in: {}", input.display()));
+    }
+
+    let nodes = tree::directory_tree(&chunk_files, &options)?;
+    if nodes.is_empty() {
+        return Err(anyhow::anyhow!("No directories found{}",
+                                   options.under.map(|u| format!(" under {}", u)).unwrap_or_default()));
+    }
+
+    write!(std::io::stdout().lock(), "{}", tree::render(&nodes, style))?;
+    Ok(())
+}
+
+fn run_duplicates(input: PathBuf, options: DuplicateOptions, top: usize, json: bool, output: Option<PathBuf>) -> Result<()> {
+    let chunk_files = aggregate::find_chunk_files(&input)?;
+    if chunk_files.is_empty() {
+        return Err(anyhow::anyhow!("No Parquet files found in: {}", input.display()));
+    }
+
+    let report = duplicates::find_duplicates(&chunk_files, &options)?;
+    if let Some(ref output) = output {
+        duplicates::write_parquet(&report, output)?;
+    }
+
+    let mut out = std::io::stdout().lock();
+    if json {
+        writeln!(out, "{}", serde_json::to_string_pretty(&report)?)?;
+        return Ok(());
+    }
+
+    let matched_by = match report.matched_by {
+        MatchMethod::Size => "same size; contents not compared, use --hash",
+        MatchMethod::ContentHash => "same size and content_hash",
+        MatchMethod::LiveHash => "same size and checksum",
+    };
+    writeln!(out, "Duplicate groups ({})", matched_by)?;
+    writeln!(out, "{:>12}  {:>7}  {:>12}  Paths", "Size", "Copies", "Wasted")?;
+    for group in report.groups.iter().take(top) {
+        for (i, paths) in group.copies.iter().enumerate() {
+            let path = paths.join(" = ");
+            if i == 0 {
+                writeln!(out, "{:>12}  {:>7}  {:>12}  {}",
+                         utils::format_bytes(group.size),
+                         group.copies.len(),
+                         utils::format_bytes(group.wasted_bytes),
+                         path)?;
+            } else {
+                writeln!(out, "{:>12}  {:>7}  {:>12}  {}", "", "", "", path)?;
+            }
+        }
+    }
+    if report.groups.len() > top {
+        writeln!(out, "... {} more groups", utils::format_number((report.groups.len() - top) as u64))?;
+    }
+    writeln!(out)?;
+    writeln!(out, "Groups:                {}", utils::format_number(report.groups.len() as u64))?;
+    writeln!(out, "Duplicate files:       {}", utils::format_number(report.duplicate_files))?;
+    writeln!(out, "Reclaimable:           {}", utils::format_bytes(report.reclaimable_bytes))?;
+    if report.hardlinks > 0 {
+        writeln!(out, "Extra hardlinks:       {}", utils::format_number(report.hardlinks))?;
+    }
+    if report.skipped > 0 {
+        writeln!(out, "Skipped candidates:    {}", utils::format_number(report.skipped))?;
+    }
+    if let Some(ref output) = output {
+        writeln!(out, "Groups written to:     {}", output.display())?;
+    }
+    Ok(())
+}
+
+fn run_hardlinks(input: PathBuf, options: HardlinkOptions, json: bool) -> Result<()> {
+    let chunk_files = aggregate::find_chunk_files(&input)?;
+    if chunk_files.is_empty() {
+        return Err(anyhow::anyhow!("No Parquet files found in: {}", input.display()));
+    }
+
+    let report = hardlinks::find_hardlinks(&chunk_files, &options)?;
+
+    let mut out = std::io::stdout().lock();
+    if json {
+        writeln!(out, "{}", serde_json::to_string_pretty(&report)?)?;
+        return Ok(());
+    }
+
+    if !report.largest.is_empty() {
+        writeln!(out, "{:>12}  {:>12}  {:>7}  Paths", "Size", "Apparent", "Links")?;
+        for group in &report.largest {
+            // Paths found in the scan out of the inode's link count
+            let links = format!("{}/{}", group.paths.len(), group.nlink);
+            for (i, path) in group.paths.iter().enumerate() {
+                if i == 0 {
+                    writeln!(out, "{:>12}  {:>12}  {:>7}  {}",
+                             utils::format_bytes(group.size),
+                             utils::format_bytes(group.apparent_bytes()),
+                             links,
+                             path)?;
+                } else {
+                    writeln!(out, "{:>12}  {:>12}  {:>7}  {}", "", "", "", path)?;
+                }
+            }
+        }
+        if report.groups > report.largest.len() as u64 {
+            writeln!(out, "... {} more groups", utils::format_number(report.groups - report.largest.len() as u64))?;
+        }
+        writeln!(out)?;
+    }
+    writeln!(out, "Files:                 {}", utils::format_number(report.files))?;
+    writeln!(out, "Hardlink groups:       {}", utils::format_number(report.groups))?;
+    writeln!(out, "Linked paths:          {}", utils::format_number(report.linked_paths))?;
+    writeln!(out, "Linked, apparent:      {}", utils::format_bytes(report.linked_apparent_bytes))?;
+    writeln!(out, "Linked, on disk:       {}", utils::format_bytes(report.linked_deduplicated_bytes))?;
+    if report.partial_groups > 0 {
+        writeln!(out, "Linked outside scan:   {} groups", utils::format_number(report.partial_groups))?;
+    }
+    writeln!(out, "Apparent total:        {}", utils::format_bytes(report.apparent_bytes))?;
+    writeln!(out, "Corrected total:       {}", utils::format_bytes(report.corrected_bytes))?;
+    Ok(())
+}
+
+fn run_empty_dirs(input: PathBuf, recursive: bool, json: bool, output: Option<PathBuf>) -> Result<()> {
+    let chunk_files = aggregate::find_chunk_files(&input)?;
+    if chunk_files.is_empty() {
+        return Err(anyhow::anyhow!("No Parquet files found in: {}", input.display()));
+    }
+
+    let report = empty_dirs::find_empty_dirs(&chunk_files, recursive)?;
+    if let Some(ref output) = output {
+        empty_dirs::write_parquet(&report, output)?;
+    }
+
+    let mut out = std::io::stdout().lock();
+    if json {
+        writeln!(out, "{}", serde_json::to_string_pretty(&report)?)?;
+        return Ok(());
+    }
+
+    for dir in &report.dirs {
+        if recursive {
+            writeln!(out, "{:>7}  {}", dir.dirs, dir.path)?;
+        } else {
+            writeln!(out, "{}", dir.path)?;
+        }
+    }
+    if !report.by_top_level_dir.is_empty() {
+        writeln!(out)?;
+        writeln!(out, "{:<30}  {:>12}", "Top-level directory", "Empty dirs")?;
+        for count in &report.by_top_level_dir {
+            writeln!(out, "{:<30}  {:>12}", count.top_level_dir, utils::format_number(count.dirs))?;
+        }
+    }
+    writeln!(out)?;
+    writeln!(out, "Directories:           {}", utils::format_number(report.directories))?;
+    writeln!(out, "Empty directories:     {}", utils::format_number(report.total))?;
+    if recursive {
+        writeln!(out, "Empty subtrees:        {}", utils::format_number(report.dirs.len() as u64))?;
+    }
+    if let Some(ref output) = output {
+        writeln!(out, "Written to:            {}", output.display())?;
+    }
+    Ok(())
+}
+
+fn run_symlinks(input: PathBuf, check_live: bool, json: bool, output: Option<PathBuf>) -> Result<()> {
+    let format = output.as_deref().map(QueryFormat::from_path).transpose()?;
+    let chunk_files = aggregate::find_chunk_files(&input)?;
+    if chunk_files.is_empty() {
+        return Err(anyhow::anyhow!("No Parquet files found in: {}", input.display()));
+    }
+
+    let report = symlinks::audit_symlinks(&chunk_files, check_live)?;
+    if let (Some(ref output), Some(format)) = (&output, format) {
+        symlinks::write_symlinks(&report, output, format)?;
+    }
+
+    let mut out = std::io::stdout().lock();
+    if json {
+        writeln!(out, "{}", serde_json::to_string_pretty(&report)?)?;
+        return Ok(());
+    }
+
+    #[allow(clippy::type_complexity)]
+    let lists: [(&str, fn(&Symlink) -> bool); 3] = [
+        ("Broken links", |s| s.broken == Some(true)),
+        ("External links", |s| !s.internal),
+        // These break when the tree is moved or mounted elsewhere
+        ("Absolute internal links", |s| s.absolute && s.internal),
+    ];
+    for (title, matches) in &lists {
+        let mut listed = report.symlinks.iter().filter(|s| matches(s)).peekable();
+        if listed.peek().is_none() {
+            continue;
+        }
+        writeln!(out, "{}:", title)?;
+        for link in listed {
+            writeln!(out, "  {} -> {}", link.path, link.target)?;
+        }
+        writeln!(out)?;
+    }
+
+    writeln!(out, "Scan root:             {}", report.scan_root)?;
+    writeln!(out, "Symbolic links:        {}", utils::format_number(report.links))?;
+    writeln!(out, "Relative:              {}", utils::format_number(report.relative))?;
+    writeln!(out, "Absolute:              {}", utils::format_number(report.absolute))?;
+    writeln!(out, "Internal:              {}", utils::format_number(report.internal))?;
+    writeln!(out, "External:              {}", utils::format_number(report.external))?;
+    if let Some(broken) = report.broken {
+        writeln!(out, "Broken:                {}", utils::format_number(broken))?;
+    }
+    if let Some(ref output) = output {
+        writeln!(out, "Written to:            {}", output.display())?;
+    }
+    Ok(())
+}
+
+fn run_permissions(input: PathBuf, options: PermissionOptions, json: bool, output: Option<PathBuf>) -> Result<()> {
+    let format = output.as_deref().map(QueryFormat::from_path).transpose()?;
+    let chunk_files = aggregate::find_chunk_files(&input)?;
+    if chunk_files.is_empty() {
+        return Err(anyhow::anyhow!("No Parquet files found in: {}", input.display()));
+    }
+
+    let destination = output.as_deref().zip(format);
+    let report = permissions::audit_permissions(&chunk_files, &options, destination)?;
+
+    let mut out = std::io::stdout().lock();
+    if json {
+        writeln!(out, "{}", serde_json::to_string_pretty(&report)?)?;
+        return Ok(());
+    }
+
+    writeln!(out, "{:<24}  {:>12}  Description", "Check", "Findings")?;
+    for check in &report.checks {
+        writeln!(out, "{:<24}  {:>12}  {}", check.check, utils::format_number(check.findings), check.description)?;
+    }
+    if !report.by_top_level_dir.is_empty() {
+        writeln!(out)?;
+        write!(out, "{:<24}", "Top-level directory")?;
+        for check in &report.checks {
+            write!(out, "  {:>20}", check.check)?;
+        }
+        writeln!(out)?;
+        for dir in &report.by_top_level_dir {
+            write!(out, "{:<24}", dir.top_level_dir)?;
+            for check in &report.checks {
+                let findings = dir.findings.get(&check.check).copied().unwrap_or(0);
+                write!(out, "  {:>20}", utils::format_number(findings))?;
+            }
+            writeln!(out)?;
+        }
+    }
+    writeln!(out)?;
+    writeln!(out, "Entries audited:       {}", utils::format_number(report.entries))?;
+    if let Some(ref output) = output {
+        writeln!(out, "Findings written to:   {}", output.display())?;
+    }
+    Ok(())
+}
+
+fn run_hotspots(input: PathBuf, options: HotspotOptions, format: ReportFormat) -> Result<()> {
+    let chunk_files = aggregate::find_chunk_files(&input)?;
+    if chunk_files.is_empty() {
+        return Err(anyhow::anyhow!("No Parquet files found in: {}", input.display()));
+    }
+
+    let report = hotspots::find_hotspots(&chunk_files, &options)?;
+
+    let mut out = std::io::stdout().lock();
+    match format {
+        ReportFormat::Table => {}
+        ReportFormat::Json => {
+            writeln!(out, "{}", serde_json::to_string_pretty(&report)?)?;
+            return Ok(());
+        }
+        ReportFormat::Csv => {
+            report.write_csv(&mut out)?;
+            return Ok(());
+        }
+    }
+
+    writeln!(out, "{:>12}  Directory", "Entries")?;
+    for dir in &report.dense {
+        writeln!(out, "{:>12}  {}", utils::format_number(dir.entries), dir.path)?;
+    }
+    writeln!(out)?;
+    writeln!(out, "{:>12}  Deepest path", "Depth")?;
+    for path in &report.deepest {
+        writeln!(out, "{:>12}  {}", path.depth, path.path)?;
+    }
+    writeln!(out)?;
+    writeln!(out, "{:>12}  Longest path", "Bytes")?;
+    for path in &report.longest {
+        writeln!(out, "{:>12}  {}", utils::format_number(path.length), path.path)?;
+    }
+    writeln!(out)?;
+    writeln!(out, "Entries scanned:       {}", utils::format_number(report.entries))?;
+    writeln!(out, "Dense directories:     {} with at least {} entries",
+             utils::format_number(report.dense_directories), utils::format_number(report.min_entries))?;
+    Ok(())
+}
+
+fn run_stale(input: PathBuf, options: StaleOptions, json: bool, output: Option<PathBuf>) -> Result<()> {
+    let format = output.as_deref().map(QueryFormat::from_path).transpose()?;
+    let chunk_files = aggregate::find_chunk_files(&input)?;
+    if chunk_files.is_empty() {
+        return Err(anyhow::anyhow!("No Parquet files found in: {}", input.display()));
+    }
+
+    let report = match (&output, format) {
+        (Some(output), Some(format)) => stale::write_stale_files(&chunk_files, &options, output, format)?,
+        _ => stale::stale_files(&chunk_files, &options)?,
+    };
+
+    let mut out = std::io::stdout().lock();
+    if json {
+        writeln!(out, "{}", serde_json::to_string_pretty(&report)?)?;
+        return Ok(());
+    }
+
+    let cutoff = UNIX_EPOCH + Duration::from_secs(report.accessed_before.max(0) as u64);
+    writeln!(out, "Files of at least {} last accessed before {}",
+             utils::format_bytes(report.min_size), humantime::format_rfc3339_seconds(cutoff))?;
+    let label = match report.group_by {
+        StaleGroupBy::Uid => "UID",
+        StaleGroupBy::TopLevelDir => "Directory",
+    };
+    writeln!(out, "{:<24}  {:>12}  {:>12}  {:>8}  Oldest access", label, "Files", "Size", "Share")?;
+    for group in &report.groups {
+        let name = match group.owner {
+            Some(ref owner) => format!("{} ({})", group.group, owner),
+            None => group.group.clone(),
+        };
+        let oldest = UNIX_EPOCH + Duration::from_secs(group.oldest_access.max(0) as u64);
+        writeln!(out, "{:<24}  {:>12}  {:>12}  {:>7.1}%  {}",
+                 name,
+                 utils::format_number(group.files),
+                 utils::format_bytes(group.bytes),
+                 utils::percentage(group.bytes, report.bytes),
+                 humantime::format_rfc3339_seconds(oldest))?;
+    }
+    writeln!(out, "{:<24}  {:>12}  {:>12}", "Total",
+             utils::format_number(report.files), utils::format_bytes(report.bytes))?;
+    if let Some(ref output) = output {
+        writeln!(out, "Files written to:      {}", output.display())?;
+    }
+    Ok(())
+}
+
+fn run_clean_candidates(input: PathBuf, policy: PathBuf, output: PathBuf, as_of: i64, json: bool) -> Result<()> {
+    let policy = CleanPolicy::load_from_file(&policy)?;
+    let format = QueryFormat::from_path(&output)?;
+    let chunk_files = aggregate::find_chunk_files(&input)?;
+    if chunk_files.is_empty() {
+        return Err(anyhow::anyhow!("No Parquet files found in: {}", input.display()));
+    }
+
+    let report = clean_candidates::find_candidates(&chunk_files, &policy, as_of, &output, format)?;
+
+    let mut out = std::io::stdout().lock();
+    if json {
+        writeln!(out, "{}", serde_json::to_string_pretty(&report)?)?;
+        return Ok(());
+    }
+
+    let as_of = UNIX_EPOCH + Duration::from_secs(report.as_of.max(0) as u64);
+    writeln!(out, "Clean-up candidates as of {} ({} rows scanned)",
+             humantime::format_rfc3339_seconds(as_of), utils::format_number(report.rows_scanned))?;
+    writeln!(out, "{:<24}  {:>12}  {:>12}  {:>8}", "Rule", "Files", "Reclaimable", "Share")?;
+    for rule in &report.rules {
+        writeln!(out, "{:<24}  {:>12}  {:>12}  {:>7.1}%",
+                 rule.rule,
+                 utils::format_number(rule.files),
+                 utils::format_bytes(rule.bytes),
+                 utils::percentage(rule.bytes, report.bytes))?;
+    }
+    writeln!(out, "{:<24}  {:>12}  {:>12}", "Total",
+             utils::format_number(report.files), utils::format_bytes(report.bytes))?;
+    writeln!(out, "Candidates written to: {}", output.display())?;
+    Ok(())
+}
+
+fn run_quota_check(input: PathBuf, quotas: PathBuf, json: bool) -> Result<QuotaLevel> {
+    let config = QuotaConfig::load_from_file(&quotas)?;
+    let chunk_files = aggregate::find_chunk_files(&input)?;
+    if chunk_files.is_empty() {
+        return Err(anyhow::anyhow!("No Parquet files found in: {}", input.display()));
+    }
+
+    let report = quota::check_quotas(&chunk_files, &config)?;
+
+    let mut out = std::io::stdout().lock();
+    if json {
+        writeln!(out, "{}", serde_json::to_string_pretty(&report)?)?;
+    } else {
+        let limit = |used: String, limit: Option<String>, percent: Option<f64>| match (limit, percent) {
+            (Some(limit), Some(percent)) => format!("{} / {} ({:.1}%)", used, limit, percent),
+            _ => used,
+        };
+        writeln!(out, "{:<8}  {:<28}  {:>34}  {:>34}", "Status", "Quota", "Bytes", "Inodes")?;
+        for status in &report.quotas {
+            writeln!(out, "{:<8}  {:<28}  {:>34}  {:>34}",
+                     status.status.as_str(),
+                     status.name,
+                     limit(utils::format_bytes(status.bytes_used), status.bytes_limit.map(utils::format_bytes), status.bytes_percent),
+                     limit(utils::format_number(status.inodes_used), status.inodes_limit.map(utils::format_number), status.inodes_percent))?;
+        }
+        writeln!(out, "Overall: {}", report.status.as_str())?;
+    }
+    out.flush()?;
+    Ok(report.status)
+}
+
+fn run_growth(inputs: Vec<PathBuf>, options: GrowthOptions, json: bool, csv: bool) -> Result<()> {
+    let scans = growth::find_scans(&inputs)?;
+    let report = growth::growth(&scans, &options)?;
+
+    let mut out = std::io::stdout().lock();
+    if json {
+        writeln!(out, "{}", serde_json::to_string_pretty(&report)?)?;
+    } else if csv {
+        report.write_csv(&mut out)?;
+    } else {
+        report.write_table(&mut out)?;
+    }
+    Ok(())
+}
+
+fn run_user_report(input: PathBuf, options: UserReportOptions, json: bool, csv: bool) -> Result<()> {
+    let chunk_files = aggregate::find_chunk_files(&input)?;
+    if chunk_files.is_empty() {
+        return Err(anyhow::anyhow!("No Parquet files found in: {}", input.display()));
+    }
+
+    let report = user_report::user_report(&chunk_files, &options)?;
+
+    let mut out = std::io::stdout().lock();
+    if json {
+        writeln!(out, "{}", serde_json::to_string_pretty(&report)?)?;
+    } else if csv {
+        report.write_csv(&mut out)?;
+    } else {
+        writeln!(out, "User Report: {} ({} file(s))", input.display(), chunk_files.len())?;
+        if let Some(ref under) = report.under {
+            writeln!(out, "Under:                 {}", under)?;
+        }
+        writeln!(out, "---")?;
+        report.write_table(&mut out)?;
+    }
+    Ok(())
+}
+
+fn run_query(input: PathBuf, filter: String, output: PathBuf) -> Result<()> {
+    let expr = Expr::parse(&filter)
+        .map_err(|e| anyhow::anyhow!("Invalid --where expression: {}", e))?;
+    let format = QueryFormat::from_path(&output)?;
+
+    let chunk_files = aggregate::find_chunk_files(&input)?;
+    if chunk_files.is_empty() {
+        return Err(anyhow::anyhow!("No Parquet files found in: {}", input.display()));
+    }
+
+    let summary = query::run_query(&chunk_files, &expr, &output, format)?;
+
+    let mut out = std::io::stdout().lock();
+    writeln!(out, "Rows scanned:          {}", utils::format_number(summary.rows_scanned))?;
+    writeln!(out, "Rows matched:          {} ({})",
+             utils::format_number(summary.rows_matched), utils::format_bytes(summary.bytes_matched))?;
+    writeln!(out, "Results written to:    {}", output.display())?;
+    Ok(())
+}
+
+fn run_hash(input: PathBuf, filter: Option<String>, algo: HashAlgo, threads: usize, output: PathBuf) -> Result<()> {
+    let filter = filter
+        .map(|filter| Expr::parse(&filter).map_err(|e| anyhow::anyhow!("Invalid --where expression: {}", e)))
+        .transpose()?;
+
+    let chunk_files = aggregate::find_chunk_files(&input)?;
+    if chunk_files.is_empty() {
+        return Err(anyhow::anyhow!("No Parquet files found in: {}", input.display()));
+    }
+
+    let options = HashOptions { filter, algo, threads, progress: std::io::stderr().is_terminal() };
+    let summary = hash::backfill_hashes(&chunk_files, &output, &options)?;
+
+    let mut out = std::io::stdout().lock();
+    writeln!(out, "Rows scanned:          {}", utils::format_number(summary.rows_scanned))?;
+    writeln!(out, "Files matched:         {}", utils::format_number(summary.files_matched))?;
+    writeln!(out, "Files hashed:          {} ({}, {})",
+             utils::format_number(summary.files_hashed), utils::format_bytes(summary.bytes_hashed), algo)?;
+    writeln!(out, "Errors:                {}", utils::format_number(summary.errors))?;
+    writeln!(out, "Duration:              {}", utils::format_duration(summary.elapsed_secs))?;
+    writeln!(out, "Results written to:    {}", output.display())?;
+    Ok(())
+}
+
+fn run_export(input: PathBuf, output: PathBuf, columns: Vec<String>, filter: Option<String>) -> Result<()> {
+    let filter = filter
+        .map(|filter| Expr::parse(&filter).map_err(|e| anyhow::anyhow!("Invalid --where expression: {}", e)))
+        .transpose()?;
+    let format = QueryFormat::from_path(&output)?;
+
+    let chunk_files = aggregate::find_chunk_files(&input)?;
+    if chunk_files.is_empty() {
+        return Err(anyhow::anyhow!("No Parquet files found in: {}", input.display()));
+    }
+
+    let options = ExportOptions {
+        columns: (!columns.is_empty()).then_some(columns),
+        filter,
+    };
+    let summary = export::export(&chunk_files, &output, format, &options)?;
+
+    let mut out = std::io::stdout().lock();
+    writeln!(out, "Rows scanned:          {}", utils::format_number(summary.rows_scanned))?;
+    writeln!(out, "Rows exported:         {}", utils::format_number(summary.rows_exported))?;
+    writeln!(out, "Written to:            {}", output.display())?;
+    Ok(())
+}
+
+fn run_convert(input: PathBuf, output: PathBuf, options: ConvertOptions) -> Result<()> {
+    let summary = convert::convert(&input, &output, &options)?;
+
+    let mut out = std::io::stdout().lock();
+    writeln!(out, "Rows read:             {}", utils::format_number(summary.rows_read))?;
+    writeln!(out, "Rows written:          {}", utils::format_number(summary.rows_written))?;
+    if summary.lossy_paths > 0 {
+        writeln!(out, "Non-UTF-8 paths:       {} (already replaced when scanned)", utils::format_number(summary.lossy_paths))?;
+    }
+    writeln!(out, "Written to:            {}", output.display())?;
+    Ok(())
+}
+
+fn run_sample(
+    input: PathBuf,
+    size: usize,
+    seed: Option<u64>,
+    head: Option<usize>,
+    filter: Option<String>,
+    format: SampleFormat,
+    output: Option<PathBuf>,
+) -> Result<()> {
+    let filter = filter
+        .map(|filter| Expr::parse(&filter).map_err(|e| anyhow::anyhow!("Invalid --where expression: {}", e)))
+        .transpose()?;
+    if let Some(ref output) = output {
+        QueryFormat::from_path(output)?;
+    }
+
+    let chunk_files = aggregate::find_chunk_files(&input)?;
+    if chunk_files.is_empty() {
+        return Err(anyhow::anyhow!("No Parquet files found in: {}", input.display()));
+    }
+
+    // Report a generated seed so the sample can be drawn again
+    let (mode, seed) = match head {
+        Some(rows) => (SampleMode::Head(rows), None),
+        None => {
+            let seed = seed.unwrap_or_else(rand::random);
+            (SampleMode::Reservoir { size, seed }, Some(seed))
+        }
+    };
+    let result = sample::sample(&chunk_files, &SampleOptions { mode, filter })?;
+
+    let summary = match seed {
+        Some(seed) => format!(
+            "Sampled {} of {} matching rows ({} scanned, seed {})",
+            utils::format_number(result.batch.num_rows() as u64),
+            utils::format_number(result.rows_matched),
+            utils::format_number(result.rows_scanned),
+            seed,
+        ),
+        // Reading stops once enough rows match, so there is no total to report
+        None => format!(
+            "First {} matching rows ({} scanned)",
+            utils::format_number(result.batch.num_rows() as u64),
+            utils::format_number(result.rows_scanned),
+        ),
+    };
+
+    if let Some(output) = output {
+        sample::write_file(&result.batch, &output)?;
+        let mut out = std::io::stdout().lock();
+        writeln!(out, "{}", summary)?;
+        writeln!(out, "Written to:            {}", output.display())?;
+        return Ok(());
+    }
+
+    let mut out = std::io::stdout().lock();
+    match format {
+        SampleFormat::Table => {
+            sample::write_table(&result.batch, &mut out)?;
+            writeln!(out)?;
+            writeln!(out, "{}", summary)?;
+        }
+        // Keep stdout to the data alone
+        SampleFormat::Csv => {
+            sample::write_csv(&result.batch, &mut out)?;
+            eprintln!("{}", summary);
+        }
+        SampleFormat::Jsonl => {
+            sample::write_jsonl(&result.batch, &mut out)?;
+            eprintln!("{}", summary);
+        }
+    }
+    Ok(())
+}
+
+fn run_schedule(config: PathBuf, run_once: Option<String>) -> Result<()> {
+    let config = ScheduleConfig::load_from_file(&config)?;
+
+    if let Some(name) = run_once {
+        let summary = schedule::run_job(config.job(&name)?)?;
+        println!("Job '{}' completed", summary.job);
+        println!("Files scanned:       {}", utils::format_number(summary.stats.files_scanned));
+        println!("Directories scanned: {}", utils::format_number(summary.stats.directories_scanned));
+        println!("Total size:          {}", utils::format_bytes(summary.stats.total_size));
+        println!("Rows written:        {}", utils::format_number(summary.rows_written));
+        println!("Duration:            {}", utils::format_duration(summary.stats.duration_secs));
+        println!("Output:              {}", summary.output.display());
+        for path in &summary.pruned {
+            println!("Removed old output:  {}", path.display());
+        }
+        return Ok(());
+    }
+
+    info!("Running {} scheduled job(s); Ctrl-C to stop", config.jobs.len());
+    // Ctrl-C exits through the signal handler, which releases the job locks
+    let stop = AtomicBool::new(false);
+    Scheduler::new(config)?.run(&stop)?;
+    Ok(())
+}
+
+fn run_pipeline(config: PathBuf, resume: bool) -> Result<()> {
+    let config = PipelineConfig::load_from_file(&config)?;
+    let report = pipeline::run_pipeline(&config, resume)?;
+
+    println!("{:<10} {:<10} {:>10} {:>14}", "Stage", "Status", "Duration", "Rows");
+    for stage in &report.stages {
+        let status = match stage.status {
+            StageStatus::Succeeded if stage.resumed => "resumed",
+            StageStatus::Succeeded => "ok",
+            StageStatus::Failed => "FAILED",
+            StageStatus::NotRun => "not run",
+        };
+        println!(
+            "{:<10} {:<10} {:>10} {:>14}",
+            stage.stage.as_str(),
+            status,
+            utils::format_duration(stage.duration_secs),
+            stage.rows.map(utils::format_number).unwrap_or_else(|| "-".to_string()),
+        );
+        if let Some(ref error) = stage.error {
+            println!("  {}", error);
+        }
+    }
+    println!();
+    println!("Report:                {}", config.report_path().display());
+
+    if !report.succeeded {
+        return Err(anyhow::anyhow!("Pipeline failed; rerun with --resume to pick up at the failed stage"));
+    }
+    Ok(())
+}
+
+fn run_generate(output: PathBuf, options: GenerateOptions, manifest: Option<PathBuf>) -> Result<()> {
+    let started = std::time::Instant::now();
+    let tree = generate::generate(&output, &options)?;
+    if let Some(ref manifest) = manifest {
+        tree.save_to_file(manifest)?;
+    }
+
+    println!("Files created:         {}", utils::format_number(tree.files));
+    println!("Directories created:   {}", utils::format_number(tree.directories));
+    println!("Apparent size:         {}{}", utils::format_bytes(tree.bytes), if options.sparse { " (sparse)" } else { "" });
+    println!("Duration:              {}", utils::format_duration(started.elapsed().as_secs_f64()));
+    println!("Tree written to:       {}", output.display());
+    if let Some(manifest) = manifest {
+        println!("Manifest written to:   {}", manifest.display());
+    }
+    Ok(())
+}
+
+#[cfg(feature = "tui")]
+fn run_monitor(status_file: PathBuf, refresh: Duration) -> Result<()> {
+    storage_scanner::tui::run(&status_file, refresh)
+}
+
+#[cfg(not(feature = "tui"))]
+fn run_monitor(_status_file: PathBuf, _refresh: Duration) -> Result<()> {
+    Err(anyhow::anyhow!("monitor requires building with --features tui"))
+}
+
+#[cfg(feature = "serve")]
+fn run_serve(input: PathBuf, listen: String, max_rows: usize, preload: bool) -> Result<()> {
+    use storage_scanner::serve::{Api, ApiServer, ServeOptions};
+
+    if max_rows == 0 {
+        return Err(anyhow::anyhow!("--max-rows must be at least 1"));
+    }
+
+    let api = Api::open(&input, ServeOptions { max_rows, preload })?;
+    let server = ApiServer::bind(&listen, api)?;
+    match server.local_addr() {
+        Some(addr) => println!("Serving {} on http://{}", input.display(), addr),
+        None => println!("Serving {} on {}", input.display(), listen),
+    }
+    server.run();
+    Ok(())
+}
+
+#[cfg(not(feature = "serve"))]
+fn run_serve(_input: PathBuf, _listen: String, _max_rows: usize, _preload: bool) -> Result<()> {
+    Err(anyhow::anyhow!("serve requires building with --features serve"))
+}
+
+#[cfg(feature = "watch")]
+fn run_watch(
+    path: PathBuf,
+    output: PathBuf,
+    format: WatchFormatArg,
+    rows_per_file: usize,
+    file_interval: Duration,
+    rescan_interval: Duration,
+) -> Result<()> {
+    use storage_scanner::watch::{self, Watch, WatchConfig, WatchFormat};
+
+    utils::validate_path(&path)?;
+    if rows_per_file == 0 {
+        return Err(anyhow::anyhow!("--rows-per-file must be at least 1"));
+    }
+
+    let config = WatchConfig {
+        path,
+        output_dir: output,
+        format: match format {
+            WatchFormatArg::Jsonl => WatchFormat::Jsonl,
+            WatchFormatArg::ParquetChunks => WatchFormat::ParquetChunks,
+        },
+        rows_per_file,
+        file_interval,
+        rescan_interval,
+    };
+
+    let watch = Watch::start(&config)?;
+    WATCHING.store(true, Ordering::Relaxed);
+    println!("Watching {} (Ctrl-C to stop)", config.path.display());
+    let manifest = watch.run(&STOP_WATCH)?;
+
+    println!("Recorded {} change(s) in {} file(s)",
+             utils::format_number(manifest.total_records), manifest.files.len());
+    println!("Manifest:              {}", watch::manifest_path(&config.output_dir).display());
+    if !manifest.rescanned_paths.is_empty() {
+        println!("Re-scanned instead of watched (out of inotify watches):");
+        for path in &manifest.rescanned_paths {
+            println!("  {}", path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "watch"))]
+fn run_watch(
+    _path: PathBuf,
+    _output: PathBuf,
+    _format: WatchFormatArg,
+    _rows_per_file: usize,
+    _file_interval: Duration,
+    _rescan_interval: Duration,
+) -> Result<()> {
+    Err(anyhow::anyhow!("watch requires building with --features watch"))
+}
+
+fn run_diff(
+    old: PathBuf,
+    new: PathBuf,
+    under: Option<String>,
+    output: Option<PathBuf>,
+    sort_memory: Option<u64>,
+) -> Result<()> {
+    let mut sides = Vec::new();
+    for input in [&old, &new] {
+        let chunk_files = aggregate::find_chunk_files(input)?;
+        if chunk_files.is_empty() {
+            return Err(anyhow::anyhow!("No Parquet files found in: {}", input.display()));
+        }
+        sides.push(chunk_files);
+    }
+
+    // Unsorted inputs are sorted next to the output, or in the temp directory
+    let spill_dir = match output {
+        Some(ref output) => {
+            let name = output.file_name()
+                .ok_or_else(|| anyhow::anyhow!("Invalid output path: {}", output.display()))?;
+            let dir = output.with_file_name(format!(".{}.diff", name.to_string_lossy()));
+            if dir.exists() {
+                std::fs::remove_dir_all(&dir)
+                    .with_context(|| format!("Failed to remove {}", dir.display()))?;
+            }
+            dir
+        }
+        None => std::env::temp_dir().join(format!("storage-scanner-diff-{}", std::process::id())),
+    };
+
+    let options = DiffOptions {
+        under,
+        output,
+        sort_memory: sort_memory.unwrap_or(sort::DEFAULT_SORT_MEMORY),
+        spill_dir,
+    };
+    let summary = diff::diff_scans(&sides[0], &sides[1], &options)?;
+
+    let mut out = std::io::stdout().lock();
+    writeln!(out, "Old entries:           {}", utils::format_number(summary.old_rows))?;
+    writeln!(out, "New entries:           {}", utils::format_number(summary.new_rows))?;
+    writeln!(out, "Unchanged:             {}", utils::format_number(summary.unchanged))?;
+    writeln!(out, "Added:                 {} ({})",
+             utils::format_number(summary.added), utils::format_bytes(summary.added_bytes))?;
+    writeln!(out, "Removed:               {} ({})",
+             utils::format_number(summary.removed), utils::format_bytes(summary.removed_bytes))?;
+    writeln!(out, "Modified:              {} ({} grew by {}, {} shrank by {})",
+             utils::format_number(summary.modified),
+             utils::format_number(summary.grew), utils::format_bytes(summary.grown_bytes),
+             utils::format_number(summary.shrank), utils::format_bytes(summary.shrunk_bytes))?;
+    let sign = if summary.net_bytes < 0 { "-" } else { "+" };
+    writeln!(out, "Net change:            {}{}", sign, utils::format_bytes(summary.net_bytes.unsigned_abs()))?;
+    if let Some(ref output) = options.output {
+        writeln!(out, "Changes written to:    {}", output.display())?;
+    }
+    Ok(())
+}
+
+/// Lines of the scan summary for entries that were not recorded: errors by
+/// kind, entries that vanished, and entries a resumed scan had written
+fn print_scan_counts(out: &mut impl Write, stats: &ScanStats) -> Result<()> {
+    if stats.errors_encountered > 0 {
+        writeln!(out, "Errors encountered:  {}", utils::format_number(stats.errors_encountered))?;
+        for (kind, count) in &stats.error_summary.by_kind {
+            writeln!(out, "  {:<18} {}", format!("{}:", kind), utils::format_number(*count))?;
+        }
+        writeln!(out, "Note: Some files may have been skipped due to permission errors")?;
+    }
+    if stats.entries_vanished > 0 {
+        writeln!(out, "Vanished:            {} (removed while the scan ran; not errors)", utils::format_number(stats.entries_vanished))?;
+    }
+    if stats.entries_skipped > 0 {
+        writeln!(out, "Already written:     {} (skipped)", utils::format_number(stats.entries_skipped))?;
+    }
+    Ok(())
+}
+
+/// Bytes and rows per second, e.g. "85.20 MB/s, 1,204,332 rows/s"
+fn throughput(bytes: u64, rows: u64, secs: f64) -> String {
+    if secs <= 0.0 {
+        return "n/a".to_string();
+    }
+    format!(
+        "{}/s, {} rows/s",
+        utils::format_bytes((bytes as f64 / secs) as u64),
+        utils::format_number((rows as f64 / secs) as u64)
+    )
+}
+
+/// Status file of a scan, marked failed if dropped before [`StatusGuard::complete`]
+struct StatusGuard(Option<Arc<StatusTracker>>);
+
+impl StatusGuard {
+    fn complete(mut self, stats: &ScanStats) -> Result<()> {
+        let Some(tracker) = self.0.take() else { return Ok(()) };
+        let counts = StatusCounts {
+            files: stats.files_scanned,
+            directories: stats.directories_scanned,
+            symlinks: stats.symlinks_scanned,
+            bytes: stats.total_size,
+            unique_bytes: stats.unique_total_size,
+            errors: stats.errors_encountered,
+            skipped: tracker.last_counts().skipped,
+            vanished: stats.entries_vanished,
+        };
+        tracker.write(counts, ScanState::Completed)
+    }
+}
+
+impl Drop for StatusGuard {
+    fn drop(&mut self) {
+        if let Some(ref tracker) = self.0 {
+            if let Err(e) = tracker.write_failed() {
+                warn!("Failed to mark the scan failed in its status: {:#}", e);
+            }
+        }
+    }
+}
+
+/// Local copies of URL inputs, deleted when dropped
+struct RemoteInputs(PathBuf);
+
+impl RemoteInputs {
+    /// A hidden directory next to `output`, emptied of any interrupted run's downloads
+    fn next_to(output: &Path) -> Result<Self> {
+        let name = output.file_name()
+            .ok_or_else(|| anyhow::anyhow!("Invalid output path: {}", output.display()))?;
+        let dir = output.parent()
+            .filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or(Path::new("."))
+            .join(format!(".{}.remote", name.to_string_lossy()));
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir)
+                .with_context(|| format!("Failed to remove {}", dir.display()))?;
+        }
+        Ok(Self(dir))
+    }
+}
+
+impl Drop for RemoteInputs {
+    fn drop(&mut self) {
+        if self.0.exists() {
+            if let Err(e) = std::fs::remove_dir_all(&self.0) {
+                warn!("Failed to remove downloaded chunks in {}: {}", self.0.display(), e);
+            }
+        }
+    }
+}
+
+/// Replace URL inputs with local copies of their manifest and chunks, downloaded into `dir`
+#[cfg(feature = "remote")]
+fn fetch_remote_inputs(inputs: &mut [PathBuf], dir: &Path) -> Result<()> {
+    for (i, input) in inputs.iter_mut().enumerate() {
+        let url = input.to_string_lossy().to_string();
+        if !utils::is_url(&url) {
+            continue;
+        }
+        let fetched = storage_scanner::remote::fetch_scan(&url, &dir.join(i.to_string()))?;
+        println!(
+            "Downloaded {} chunk(s) ({}) from {}",
+            fetched.chunk_files.len(),
+            utils::format_bytes(fetched.bytes),
+            url
+        );
+        *input = fetched.manifest_path;
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "remote"))]
+fn fetch_remote_inputs(_inputs: &mut [PathBuf], _dir: &Path) -> Result<()> {
+    Err(anyhow::anyhow!("Reading --input from a URL requires building with --features remote"))
+}
+
+/// Store for an s3:// output, and the output file name chunks are named after
+#[cfg(feature = "s3")]
+fn s3_store(url: &str, endpoint: Option<&str>) -> Result<(Arc<dyn ObjectStore>, String)> {
+    use storage_scanner::s3::{S3Location, S3Store};
+    let location = S3Location::parse(url)?;
+    let file_name = location.file_name.clone();
+    Ok((Arc::new(S3Store::connect(location, endpoint)?), file_name))
+}
+
+#[cfg(not(feature = "s3"))]
+fn s3_store(_url: &str, _endpoint: Option<&str>) -> Result<(Arc<dyn ObjectStore>, String)> {
+    Err(anyhow::anyhow!("Writing to an s3:// output requires building with --features s3"))
+}
+
+/// Hook uploading each chunk of an incremental scan under `url`
+#[cfg(feature = "remote")]
+fn chunk_uploader(url: String, retries: u32) -> Result<Box<dyn ChunkHook>> {
+    use storage_scanner::remote::{ChunkUploader, UploadOptions};
+    let options = UploadOptions { url, retries, backoff: Duration::from_secs(1) };
+    Ok(Box::new(ChunkUploader::new(options)?))
+}
+
+#[cfg(not(feature = "remote"))]
+fn chunk_uploader(_url: String, _retries: u32) -> Result<Box<dyn ChunkHook>> {
+    Err(anyhow::anyhow!("Uploading chunks with --upload-url requires building with --features remote"))
+}
+
+#[cfg(feature = "remote")]
+fn run_retry_uploads(manifest: PathBuf, url: String, retries: u32) -> Result<()> {
+    let options = storage_scanner::remote::UploadOptions { url, retries, backoff: Duration::from_secs(1) };
+    let summary = storage_scanner::remote::retry_uploads(&manifest, &options)?;
+
+    println!("Chunks uploaded:     {}", utils::format_number(summary.uploaded));
+    println!("Already done:        {}", utils::format_number(summary.skipped));
+    if summary.failed > 0 {
+        return Err(anyhow::anyhow!("{} chunk(s) still failed to upload; see {}", summary.failed, manifest.display()));
+    }
+    Ok(())
+}
+
+#[cfg(feature = "clickhouse")]
+fn run_load_clickhouse(
+    input: PathBuf,
+    url: String,
+    table: String,
+    user: Option<String>,
+    batch_size: usize,
+    retries: u32,
+) -> Result<()> {
+    use storage_scanner::clickhouse::{self, ClickHouseOptions};
+
+    if batch_size == 0 {
+        return Err(anyhow::anyhow!("--batch-size must be at least 1"));
+    }
+    let options = ClickHouseOptions {
+        url,
+        table,
+        user,
+        password: std::env::var("CLICKHOUSE_PASSWORD").ok(),
+        batch_size,
+        retries,
+        backoff: Duration::from_secs(1),
+    };
+    let summary = clickhouse::load(&input, &options)?;
+
+    println!("Files loaded:          {}", utils::format_number(summary.files as u64));
+    println!("Rows inserted:         {} in {} batch(es)", utils::format_number(summary.rows), summary.batches);
+    println!("Rows in table:         {} (was {})",
+             utils::format_number(summary.rows_after), utils::format_number(summary.rows_before));
+    Ok(())
+}
+
+#[cfg(not(feature = "clickhouse"))]
+fn run_load_clickhouse(
+    _input: PathBuf,
+    _url: String,
+    _table: String,
+    _user: Option<String>,
+    _batch_size: usize,
+    _retries: u32,
+) -> Result<()> {
+    Err(anyhow::anyhow!("load-clickhouse requires building with --features clickhouse"))
+}
+
+#[cfg(not(feature = "remote"))]
+fn run_retry_uploads(_manifest: PathBuf, _url: String, _retries: u32) -> Result<()> {
+    Err(anyhow::anyhow!("retry-uploads requires building with --features remote"))
+}
+
+fn run_verify(manifest: PathBuf, deep: bool, json: bool) -> Result<()> {
+    info!("Verifying manifest: {}", manifest.display());
+
+    let report = verify::verify_manifest_file(&manifest, deep)
+        .context("Failed to verify manifest")?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        println!();
+        println!("{:>6}  {:<8}  {:>12}  {:>12}  File", "Chunk", "Status", "Rows", "Size");
+        for chunk in &report.chunks {
+            let rows = chunk.actual_rows
+                .map(utils::format_number)
+                .unwrap_or_else(|| "-".to_string());
+            let size = chunk.actual_size
+                .map(utils::format_bytes)
+                .unwrap_or_else(|| "-".to_string());
+            let status = if chunk.deleted {
+                "DELETED"
+            } else if chunk.is_ok() {
+                "OK"
+            } else {
+                "FAILED"
+            };
+            println!("{:>6}  {:<8}  {:>12}  {:>12}  {}", chunk.chunk_number, status, rows, size, chunk.file_path);
+            for problem in &chunk.problems {
+                println!("{:>6}  - {}", "", problem);
+            }
+        }
+        for problem in &report.problems {
+            println!("Manifest: {}", problem);
+        }
+
+        println!();
+        println!("Verification Summary");
+        println!("---");
+        println!("Mode:                  {}", if deep { "deep" } else { "shallow" });
+        println!("Chunks checked:        {}", report.chunks.len());
+        println!("Manifest total rows:   {}", utils::format_number(report.manifest_total_rows));
+        println!("Rows found:            {}", utils::format_number(report.found_rows));
+        println!("Problems:              {}", report.problem_count());
+    }
+
+    if !report.is_ok() {
+        return Err(anyhow::anyhow!("Verification failed with {} problem(s)", report.problem_count()));
+    }
+
+    Ok(())
+}
+
+fn run_verify_checksums(path: PathBuf, json: bool) -> Result<()> {
+    info!("Verifying checksums: {}", path.display());
+
+    let report = checksums::verify_checksums_file(&path)?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        println!();
+        println!("{:<8}  {:<6}  File", "Status", "Algo");
+        for file in &report.files {
+            println!("{:<8}  {:<6}  {}", if file.is_ok() { "OK" } else { "FAILED" }, file.algo, file.file);
+            if let Some(ref problem) = file.problem {
+                println!("{:<8}  - {}", "", problem);
+            }
+        }
+
+        println!();
+        println!("Checksum Summary");
+        println!("---");
+        println!("Files checked:         {}", report.files.len());
+        println!("Problems:              {}", report.problem_count());
+    }
+
+    if !report.is_ok() {
+        return Err(anyhow::anyhow!("Checksum verification failed for {} file(s)", report.problem_count()));
+    }
+
+    Ok(())
+}
+
+fn run_validate(input: PathBuf, as_of: i64, json: bool) -> Result<()> {
+    let report = validate::validate(&input, as_of)?;
+
+    let mut out = std::io::stdout().lock();
+    if json {
+        writeln!(out, "{}", serde_json::to_string_pretty(&report)?)?;
+    } else {
+        writeln!(out, "Validation: {} ({} file(s), {} rows)",
+                 input.display(), report.files, utils::format_number(report.rows))?;
+        if let Some(ref manifest) = report.manifest {
+            writeln!(out, "Manifest:              {}", manifest)?;
+        }
+        writeln!(out, "---")?;
+        writeln!(out, "{:<12}  {:<7}  {:>14}  {:>10}", "Check", "Status", "Checked", "Failures")?;
+        for check in &report.checks {
+            let status = match check.status {
+                CheckStatus::Passed => "PASS",
+                CheckStatus::Failed => "FAIL",
+                CheckStatus::Skipped => "SKIP",
+            };
+            writeln!(out, "{:<12}  {:<7}  {:>14}  {:>10}",
+                     check.name, status, utils::format_number(check.checked), utils::format_number(check.failures))?;
+            for example in &check.examples {
+                writeln!(out, "{:<12}  - {}", "", example)?;
+            }
+            if check.failures > check.examples.len() as u64 {
+                writeln!(out, "{:<12}  ... and {} more", "", check.failures - check.examples.len() as u64)?;
+            }
+            if let Some(ref note) = check.note {
+                writeln!(out, "{:<12}  ({})", "", note)?;
+            }
+        }
+    }
+
+    if !report.is_ok() {
+        let failed: Vec<&str> = report.failed_checks().map(|c| c.name.as_str()).collect();
+        return Err(anyhow::anyhow!("Validation failed: {}", failed.join(", ")));
+    }
+    Ok(())
+}
+
+fn run_inspect(input: PathBuf, json: bool) -> Result<()> {
+    let inspection = inspect::inspect(&input)?;
+
+    let mut out = std::io::stdout().lock();
+    if json {
+        writeln!(out, "{}", serde_json::to_string_pretty(&inspection)?)?;
+        return Ok(());
+    }
+    match inspection {
+        Inspection::Parquet(summary) => print_parquet_summary(&mut out, &summary)?,
+        Inspection::Manifest(summary) => print_manifest_summary(&mut out, &summary)?,
+    }
+    Ok(())
+}
+
+fn print_parquet_summary(out: &mut impl Write, summary: &ParquetSummary) -> Result<()> {
+    writeln!(out, "Parquet file: {}", summary.path)?;
+    writeln!(out, "---")?;
+    writeln!(out, "File size:             {}", utils::format_bytes(summary.file_bytes))?;
+    writeln!(out, "Rows:                  {}", utils::format_number(summary.rows))?;
+    writeln!(out, "Row groups:            {}", summary.row_groups.len())?;
+    if let Some(ref created_by) = summary.created_by {
+        writeln!(out, "Created by:            {}", created_by)?;
+    }
+
+    writeln!(out)?;
+    writeln!(out, "{:<20}  {:<10}  {:<24}  {:<8}  {:<8}  {:>12}  {:>12}  Encodings",
+             "Column", "Type", "Logical type", "Repeat", "Codec", "Compressed", "Raw")?;
+    for column in &summary.columns {
+        writeln!(out, "{:<20}  {:<10}  {:<24}  {:<8}  {:<8}  {:>12}  {:>12}  {}",
+                 column.name,
+                 column.physical_type,
+                 column.logical_type.as_deref().unwrap_or("-"),
+                 column.repetition,
+                 column.compression,
+                 utils::format_bytes(column.compressed_bytes),
+                 utils::format_bytes(column.uncompressed_bytes),
+                 column.encodings.join(","))?;
+    }
+
+    writeln!(out)?;
+    writeln!(out, "{:>9}  {:>12}  {:>12}  {:>12}", "Row group", "Rows", "Compressed", "Raw")?;
+    for group in &summary.row_groups {
+        writeln!(out, "{:>9}  {:>12}  {:>12}  {:>12}",
+                 group.index,
+                 utils::format_number(group.rows),
+                 utils::format_bytes(group.compressed_bytes),
+                 utils::format_bytes(group.uncompressed_bytes))?;
+    }
+
+    if !summary.metadata.is_empty() {
+        writeln!(out)?;
+        writeln!(out, "Metadata:")?;
+        for (key, value) in &summary.metadata {
+            // The embedded Arrow schema is a long base64 blob
+            if value.len() > 80 {
+                writeln!(out, "  {} = <{} bytes>", key, value.len())?;
+            } else {
+                writeln!(out, "  {} = {}", key, value)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn print_manifest_summary(out: &mut impl Write, summary: &ManifestSummary) -> Result<()> {
+    let timestamp = |secs: i64| {
+        humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(secs.max(0) as u64)).to_string()
+    };
+
+    writeln!(out, "Scan manifest: {}", summary.path)?;
+    writeln!(out, "---")?;
+    writeln!(out, "Scan path:             {}", summary.scan_path)?;
+    writeln!(out, "Completed:             {}", if summary.completed { "yes" } else { "no" })?;
+    writeln!(out, "Started:               {}", timestamp(summary.scan_start))?;
+    if let Some(end) = summary.scan_end {
+        writeln!(out, "Finished:              {}", timestamp(end))?;
+        writeln!(out, "Duration:              {}", utils::format_duration((end - summary.scan_start) as f64))?;
+    }
+    writeln!(out, "Total rows:            {}", utils::format_number(summary.total_rows))?;
+    writeln!(out, "Chunks:                {} ({})", summary.chunk_count, utils::format_bytes(summary.total_bytes))?;
+    writeln!(out, "Errors:                {}", utils::format_number(summary.errors.total))?;
+    if let Some(ref dir) = summary.current_dir {
+        writeln!(out, "In progress:           {}", dir)?;
+    }
+
+    writeln!(out)?;
+    writeln!(out, "{:>5}  {:>12}  {:>12}  {:<20}  Path", "Chunk", "Rows", "Size", "Created")?;
+    for chunk in &summary.chunks {
+        writeln!(out, "{:>5}  {:>12}  {:>12}  {:<20}  {}",
+                 chunk.chunk_number,
+                 utils::format_number(chunk.row_count),
+                 utils::format_bytes(chunk.file_size),
+                 timestamp(chunk.created_at),
+                 chunk.file_path)?;
+    }
+
+    writeln!(out)?;
+    writeln!(out, "Completed directories: {}", summary.completed_dirs.len())?;
+    for dir in &summary.completed_dirs {
+        writeln!(out, "  {}", dir)?;
+    }
+    Ok(())
+}
+
+fn run_compare_manifests(old: PathBuf, new: PathBuf, threshold: f64, json: bool) -> Result<()> {
+    let report = compare_manifests::compare_manifests(&old, &new, threshold)?;
+
+    let mut out = std::io::stdout().lock();
+    if json {
+        writeln!(out, "{}", serde_json::to_string_pretty(&report)?)?;
+    } else {
+        writeln!(out, "Old manifest:          {}", report.old_manifest)?;
+        writeln!(out, "New manifest:          {}", report.new_manifest)?;
+        writeln!(out, "Threshold:             {}%", report.threshold_percent)?;
+        writeln!(out, "---")?;
+        writeln!(out, "{:<24}  {:<14}  {:>16}  {:>16}  {:>9}", "Scope", "Metric", "Old", "New", "Change")?;
+        let value = |value: Option<u64>| value.map(utils::format_number).unwrap_or_else(|| "-".to_string());
+        for metric in &report.metrics {
+            let change = match (metric.change_percent, metric.new) {
+                (Some(change), _) => format!("{:+.1}%", change),
+                (None, Some(_)) if metric.exceeded => "new".to_string(),
+                _ => "-".to_string(),
+            };
+            writeln!(out, "{:<24}  {:<14}  {:>16}  {:>16}  {:>9}{}",
+                     metric.scope, metric.metric, value(metric.old), value(metric.new), change,
+                     if metric.exceeded { "  !" } else { "" })?;
+        }
+    }
+
+    if !report.is_ok() {
+        return Err(anyhow::anyhow!("{} metric(s) changed by more than {}%", report.deviations, report.threshold_percent));
+    }
+    Ok(())
+}
+
+fn run_resume_status(manifest: PathBuf, path: PathBuf, baseline: Option<PathBuf>, json: bool) -> Result<()> {
+    let manifest = ScanManifest::load_from_file(&manifest)
+        .context("Failed to load manifest")?;
+    let baseline = baseline
+        .map(|b| ScanManifest::load_from_file(&b).context("Failed to load baseline manifest"))
+        .transpose()?;
+
+    let status = resume_status::resume_status(&manifest, &path, baseline.as_ref())?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&status)?);
+        return Ok(());
+    }
+
+    let stats_cell = |stats: Option<DirStats>| match stats {
+        Some(stats) => (utils::format_number(stats.rows), utils::format_bytes(stats.bytes)),
+        None => ("-".to_string(), "-".to_string()),
+    };
+
+    println!();
+    println!("{:<30}  {:<12}  {:>12}  {:>12}  {:>12}  {:>12}",
+             "Directory", "State", "Rows", "Size", "Rows left", "Size left");
+    for dir in &status.dirs {
+        let (rows, size) = stats_cell(Some(dir.written));
+        let (rows_left, size_left) = stats_cell(dir.remaining);
+        println!("{:<30}  {:<12}  {:>12}  {:>12}  {:>12}  {:>12}",
+                 dir.name, dir.state.as_str(), rows, size, rows_left, size_left);
+    }
+
+    println!();
+    println!("Resume Status");
+    println!("---");
+    println!("Scan root:             {}", status.scan_path);
+    println!("Completed:             {}", status.count(DirState::Completed));
+    println!("In progress:           {}", status.count(DirState::InProgress));
+    println!("Pending:               {}", status.count(DirState::Pending));
+    println!("Rows written:          {}", utils::format_number(status.written.rows));
+    println!("Bytes written:         {}", utils::format_bytes(status.written.bytes));
+    if let Some(remaining) = status.remaining {
+        println!("Estimated rows left:   {}", utils::format_number(remaining.rows));
+        println!("Estimated bytes left:  {}", utils::format_bytes(remaining.bytes));
+        if status.unestimated_dirs > 0 {
+            println!("Note: {} directories are not in the baseline and are not included in the estimate",
+                     status.unestimated_dirs);
+        }
+    }
+
+    Ok(())
+}
+
+fn run_manifest_merge(inputs: Vec<PathBuf>, output: PathBuf, allow_multiple_roots: bool) -> Result<()> {
+    info!("Merging {} manifest(s)", inputs.len());
+
+    let merged = ScanManifest::merge_files(&inputs, allow_multiple_roots)
+        .context("Failed to merge manifests")?;
+
+    utils::prepare_output_path(&output, OutputKind::File)?;
+    merged.save_to_file(&output)?;
+
+    println!();
+    println!("Manifest Merge Summary");
+    println!("---");
+    println!("Source manifests:      {}", merged.merged_from.len());
+    if let Some(shard) = merged.merged_from.first().and_then(|source| source.shard) {
+        println!("Shards:                {} (directories at depth {})", shard.count, shard.depth);
+    }
+    println!("Scan root:             {}", merged.scan_path);
+    println!("Total chunks:          {}", utils::format_number(merged.chunk_count as u64));
+    println!("Total rows:            {}", utils::format_number(merged.total_rows));
+    println!("Completed dirs:        {}", merged.completed_top_level_dirs.len());
+    println!("Output manifest:       {}", output.display());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run `test` on a thread with a main thread's 8 MiB of stack: in debug
+    /// builds, clap's derived builder for this many subcommands needs more
+    /// than a test thread's 2 MiB
+    fn with_main_stack(test: impl FnOnce() + Send + 'static) {
+        let thread = std::thread::Builder::new().stack_size(8 << 20).spawn(test).unwrap();
+        if let Err(panic) = thread.join() {
+            std::panic::resume_unwind(panic);
+        }
+    }
+
+    #[test]
+    fn test_scan_counts_in_the_summary() {
+        let mut out = Vec::new();
+        print_scan_counts(&mut out, &ScanStats::default()).unwrap();
+        assert!(out.is_empty());
+
+        let mut stats = ScanStats::default();
+        stats.entries_vanished = 1234;
+        print_scan_counts(&mut out, &stats).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "Vanished:            1,234 (removed while the scan ran; not errors)\n");
+    }
+
+    #[test]
+    fn test_cli_parsing() {
+        with_main_stack(|| Cli::command().debug_assert());
+    }
+
+    #[test]
+    fn test_baseline_flag() {
+        with_main_stack(|| {
+            let temp_dir = tempfile::TempDir::new().unwrap();
+            let manifest_path = temp_dir.path().join("previous_manifest.json");
+            let mut manifest = ScanManifest::new("/scan".to_string());
+            manifest.total_rows = 1234;
+            manifest.save_to_file(&manifest_path).unwrap();
+
+            let cli = Cli::try_parse_from([
+                "storage-scanner", "scan",
+                "--path", "/scan",
+                "--output", "out.parquet",
+                "--baseline", manifest_path.to_str().unwrap(),
+            ]).unwrap();
+
+            let Commands::Scan { baseline: Some(path), .. } = cli.command else {
+                panic!("--baseline was not parsed");
+            };
+            assert_eq!(Baseline::load(path).unwrap().total_rows, 1234);
+        });
+    }
+
+    #[test]
+    fn test_environment_overrides() {
+        with_main_stack(|| {
+            let temp_dir = tempfile::TempDir::new().unwrap();
+            let config = temp_dir.path().join("scanner.toml");
+            std::fs::write(&config, "[scan]\nthreads = 2\nnotify_timeout = \"30s\"\nrows_per_chunk = 1000\n").unwrap();
+            let parse = |args: &[&str]| {
+                let args = args.iter().map(OsString::from).collect();
+                let Commands::Scan { threads, output, compression, notify_timeout, rows_per_chunk, email_report, .. } =
+                    Cli::try_parse_from(args_with_config(args).unwrap()).unwrap().command
+                else {
+                    panic!("not a scan");
+                };
+                (threads, output, compression, notify_timeout, rows_per_chunk, email_report)
+            };
+            let config = config.to_str().unwrap();
+            let scan = ["storage-scanner", "--config", config, "scan", "--path", "/data", "--notify-webhook", "http://hook"];
+
+            // Only this test sets these, and nothing the other tests check depends on them
+            let vars = [
+                ("STORAGE_SCANNER_THREADS", "16"),
+                ("STORAGE_SCANNER_OUTPUT", "/scratch/scan.parquet"),
+                ("STORAGE_SCANNER_COMPRESSION", "zstd"),
+                ("STORAGE_SCANNER_NOTIFY_TIMEOUT", "2m"),
+                ("STORAGE_SCANNER_EMAIL_REPORT", "a@example.org,b@example.org"),
+            ];
+            for (name, value) in vars {
+                std::env::set_var(name, value);
+            }
+            let from_env = parse(&scan);
+            let from_flags = parse(&[&scan[..], &["--threads", "4", "--output", "out.parquet", "--notify-timeout", "5s"]].concat());
+            let without_output = Cli::try_parse_from(args_with_config(
+                ["storage-scanner", "scan", "--path", "/data", "--notify-webhook", "http://hook"].iter().map(OsString::from).collect(),
+            ).unwrap());
+            std::env::set_var("STORAGE_SCANNER_COMPRESSION", "lzma");
+            let invalid = Cli::try_parse_from(["storage-scanner", "scan", "--path", "/data", "--notify-webhook", "http://hook"]);
+            for (name, _) in vars {
+                std::env::remove_var(name);
+            }
+
+            // The environment beats the file, and flags beat the environment
+            let (threads, output, compression, notify_timeout, rows_per_chunk, email_report) = from_env;
+            assert_eq!(threads, Some(16));
+            assert_eq!(output, PathBuf::from("/scratch/scan.parquet"));
+            assert!(matches!(compression, Some(parquet::basic::Compression::ZSTD(_))));
+            assert_eq!(notify_timeout, Duration::from_secs(120));
+            assert_eq!(rows_per_chunk, 1000);
+            assert_eq!(email_report, ["a@example.org", "b@example.org"]);
+            let (threads, output, _, notify_timeout, _, _) = from_flags;
+            assert_eq!(threads, Some(4));
+            assert_eq!(output, PathBuf::from("out.parquet"));
+            assert_eq!(notify_timeout, Duration::from_secs(5));
+
+            // A variable satisfies a required option, and is checked like the flag
+            assert!(without_output.is_ok());
+            let err = invalid.err().unwrap().to_string();
+            assert!(err.contains("lzma"), "{}", err);
+        });
+    }
+
+    #[test]
+    fn test_readme_lists_environment_variables() {
+        with_main_stack(|| {
+            let table = config_file::env_table(&Cli::command());
+            assert!(
+                include_str!("../README.md").contains(&table),
+                "README.md's environment variable table is out of date; replace it with `storage-scanner config env`:\n{}",
+                table
+            );
+        });
     }
 }
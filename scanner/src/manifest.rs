@@ -0,0 +1,872 @@
+use crate::error::{self, Error};
+use crate::models::{ErrorSummary, FileEntry, ROOT_FILES_UNIT};
+use crate::shard::{self, Shard};
+use crate::writer::schema_version_entry;
+use anyhow::{Context, Result};
+use arrow::array::{ArrayRef, BooleanArray, Int64Array, StringArray, UInt64Array};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::warn;
+
+/// Metadata about a chunk file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChunkMetadata {
+    /// Chunk number (0-indexed)
+    pub chunk_number: usize,
+
+    /// File path
+    pub file_path: String,
+
+    /// Number of rows in this chunk
+    pub row_count: u64,
+
+    /// Size of chunk file in bytes
+    pub file_size: u64,
+
+    /// Timestamp when chunk was created
+    pub created_at: i64,
+
+    /// CRC-32 of the chunk file as hex (see [`crate::utils::file_checksum`]);
+    /// None in manifests written before checksums were recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+
+    /// What happened to the chunk after aggregation (None while it is in place)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retention: Option<ChunkRetention>,
+
+    /// Outcome of uploading the chunk with `--upload-url` (None if not attempted)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upload: Option<ChunkUpload>,
+
+    /// Outcome of `--post-chunk-cmd` on the chunk (None if not run)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_chunk: Option<CommandOutcome>,
+}
+
+/// How a command run on scan output ended
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CommandOutcome {
+    /// Exit status; None if the command could not be started or was killed by a signal
+    pub exit_code: Option<i32>,
+
+    /// Wall-clock time the command took, in milliseconds
+    pub duration_ms: u64,
+
+    /// Why the command could not be started
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl CommandOutcome {
+    pub fn success(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+}
+
+/// Outcome of uploading a chunk to a remote endpoint
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum ChunkUpload {
+    /// The endpoint accepted the chunk
+    Uploaded {
+        /// Where the chunk was sent
+        url: String,
+
+        /// Timestamp of the accepted request
+        at: i64,
+
+        /// Requests made, the accepted one included
+        attempts: u32,
+    },
+
+    /// Every attempt failed; `retry-uploads` tries again
+    Failed {
+        /// Where the chunk was sent
+        url: String,
+
+        /// Timestamp of the last attempt
+        at: i64,
+
+        /// Requests made
+        attempts: u32,
+
+        /// Error of the last attempt
+        error: String,
+    },
+}
+
+/// Retention outcome of a chunk that was aggregated and then removed
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum ChunkRetention {
+    /// The chunk file was deleted; `file_path` no longer exists
+    Deleted {
+        /// Timestamp of the deletion
+        at: i64,
+    },
+
+    /// The chunk file was moved; `file_path` is its new location
+    Archived {
+        /// Timestamp of the move
+        at: i64,
+
+        /// Where the chunk was before it was archived
+        original_path: String,
+    },
+}
+
+impl ChunkRetention {
+    /// Action name as serialized ("deleted" or "archived")
+    pub fn action(&self) -> &'static str {
+        match self {
+            ChunkRetention::Deleted { .. } => "deleted",
+            ChunkRetention::Archived { .. } => "archived",
+        }
+    }
+
+    /// Timestamp of the action
+    pub fn at(&self) -> i64 {
+        match self {
+            ChunkRetention::Deleted { at } | ChunkRetention::Archived { at, .. } => *at,
+        }
+    }
+
+    /// Pre-archive location of an archived chunk
+    pub fn original_path(&self) -> Option<&str> {
+        match self {
+            ChunkRetention::Deleted { .. } => None,
+            ChunkRetention::Archived { original_path, .. } => Some(original_path),
+        }
+    }
+}
+
+impl ChunkMetadata {
+    /// Whether the chunk file was deleted by a retention policy
+    pub fn is_deleted(&self) -> bool {
+        matches!(self.retention, Some(ChunkRetention::Deleted { .. }))
+    }
+
+    /// Whether the chunk reached the upload endpoint
+    pub fn is_uploaded(&self) -> bool {
+        matches!(self.upload, Some(ChunkUpload::Uploaded { .. }))
+    }
+}
+
+/// How chunk paths are stored in a manifest file
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PathStyle {
+    /// Chunk paths are stored as written (manifests predating relative paths)
+    #[default]
+    Absolute,
+
+    /// Chunk paths are relative to the directory containing the manifest
+    Relative,
+}
+
+/// Manifest file tracking all chunks
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScanManifest {
+    /// Base scan path
+    pub scan_path: String,
+
+    /// Total rows across all chunks
+    pub total_rows: u64,
+
+    /// Number of chunks
+    pub chunk_count: usize,
+
+    /// How chunk paths are stored on disk; in memory they are always resolved
+    #[serde(default)]
+    pub path_style: PathStyle,
+
+    /// List of chunk metadata
+    pub chunks: Vec<ChunkMetadata>,
+
+    /// Scan start timestamp
+    pub scan_start: i64,
+
+    /// Scan end timestamp (if complete)
+    pub scan_end: Option<i64>,
+
+    /// Scan completed successfully
+    pub completed: bool,
+
+    /// Top-level directories that have been fully scanned and written
+    #[serde(default)]
+    pub completed_top_level_dirs: HashSet<String>,
+
+    /// Currently scanning top-level directory (may be incomplete)
+    #[serde(default)]
+    pub current_top_level_dir: Option<String>,
+
+    /// Chunk rotation parameters in effect for this scan
+    #[serde(default)]
+    pub rotation: Option<RotationParams>,
+
+    /// Rotation parameter changes forced on resume
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub parameter_changes: Vec<ParameterChange>,
+
+    /// Per-path errors encountered while scanning (accumulated across resumes)
+    #[serde(default)]
+    pub errors: ErrorSummary,
+
+    /// Entries the latest run skipped because an earlier run of the resumed
+    /// scan had written them
+    #[serde(default)]
+    pub entries_skipped: u64,
+
+    /// Rows and bytes written per top-level directory
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub dir_stats: BTreeMap<String, DirStats>,
+
+    /// Source manifests this manifest was merged from (empty for a single scan)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub merged_from: Vec<MergedSource>,
+
+    /// Part of the tree this scan covers, for one task of a sharded scan
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shard: Option<Shard>,
+
+    /// Host the scan ran on (absent in manifests written before hosts were
+    /// recorded, and in merges of scans from several hosts)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+}
+
+/// Chunk rotation parameters recorded in the manifest
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct RotationParams {
+    /// Maximum rows per chunk before rotation
+    pub rows_per_chunk: usize,
+
+    /// Seconds between time-based rotations
+    pub chunk_interval_secs: u64,
+}
+
+/// Rows and bytes written for one top-level directory
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DirStats {
+    /// Rows (files and directories) written
+    pub rows: u64,
+
+    /// Total size of the files written, in bytes
+    pub bytes: u64,
+}
+
+/// A rotation parameter change applied when resuming a scan
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ParameterChange {
+    /// Timestamp when the change took effect
+    pub changed_at: i64,
+
+    /// Chunk number of the first chunk written with the new parameters
+    pub from_chunk: usize,
+
+    /// Parameters before the change
+    pub previous: RotationParams,
+
+    /// Parameters after the change
+    pub current: RotationParams,
+}
+
+/// Provenance of one input to a merged manifest
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MergedSource {
+    /// Path of the source manifest file
+    pub manifest_path: String,
+
+    /// Scan root recorded in the source manifest
+    pub scan_path: String,
+
+    /// Host the source scan ran on, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+
+    /// Rows recorded in the source manifest
+    #[serde(default)]
+    pub total_rows: u64,
+
+    /// Chunk number the source's first chunk was renumbered to
+    pub chunk_offset: usize,
+
+    /// Number of chunks contributed by the source
+    pub chunk_count: usize,
+
+    /// Shard the source covers, if it was one task of a sharded scan
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shard: Option<Shard>,
+}
+
+impl ScanManifest {
+    pub fn new(scan_path: String) -> Self {
+        use std::time::SystemTime;
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        Self {
+            scan_path,
+            total_rows: 0,
+            chunk_count: 0,
+            path_style: PathStyle::Relative,
+            chunks: Vec::new(),
+            scan_start: now,
+            scan_end: None,
+            completed: false,
+            completed_top_level_dirs: HashSet::new(),
+            current_top_level_dir: None,
+            rotation: None,
+            parameter_changes: Vec::new(),
+            errors: ErrorSummary::default(),
+            entries_skipped: 0,
+            dir_stats: BTreeMap::new(),
+            merged_from: Vec::new(),
+            shard: None,
+            hostname: None,
+        }
+    }
+
+    /// Load an existing manifest from a file
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> error::Result<Self> {
+        let contents = std::fs::read_to_string(path.as_ref())
+            .map_err(|source| Error::ManifestIo { path: path.as_ref().to_path_buf(), source })?;
+
+        let mut manifest: Self = serde_json::from_str(&contents)
+            .map_err(|source| Error::ManifestCorrupt { path: path.as_ref().to_path_buf(), source })?;
+
+        // Resolve relative chunk paths against the manifest's own location
+        if manifest.path_style == PathStyle::Relative {
+            let base = manifest_dir(path.as_ref());
+            for chunk in &mut manifest.chunks {
+                if Path::new(&chunk.file_path).is_relative() {
+                    chunk.file_path = base.join(&chunk.file_path).to_string_lossy().to_string();
+                }
+            }
+        }
+
+        Ok(manifest)
+    }
+
+    /// Check if a top-level directory has been completed
+    pub fn is_dir_completed(&self, dir: &str) -> bool {
+        self.completed_top_level_dirs.contains(dir)
+    }
+
+    /// Mark a top-level directory as in progress
+    pub fn start_directory(&mut self, dir: String) {
+        self.current_top_level_dir = Some(dir);
+    }
+
+    /// Mark the current directory as completed.
+    ///
+    /// The root's files are written in between its directories, so leaving
+    /// them doesn't complete [`ROOT_FILES_UNIT`]; see [`Self::complete_root_files`].
+    pub fn complete_current_directory(&mut self) {
+        if let Some(dir) = self.current_top_level_dir.take() {
+            if dir != ROOT_FILES_UNIT {
+                self.completed_top_level_dirs.insert(dir);
+            }
+        }
+    }
+
+    /// Mark the files directly in the scan root as completed, once every
+    /// direct child of the root has been written
+    pub fn complete_root_files(&mut self) {
+        self.completed_top_level_dirs.insert(ROOT_FILES_UNIT.to_string());
+    }
+
+    /// Add written entries to the per-directory stats
+    pub fn record_entries(&mut self, entries: &[FileEntry]) {
+        for entry in entries {
+            let stats = self.dir_stats.entry(entry.top_level_dir.clone()).or_default();
+            stats.rows += 1;
+            if entry.file_type != "directory" {
+                stats.bytes += entry.size;
+            }
+        }
+    }
+
+    pub fn add_chunk(&mut self, metadata: ChunkMetadata) {
+        self.total_rows += metadata.row_count;
+        self.chunk_count += 1;
+        self.chunks.push(metadata);
+    }
+
+    pub fn complete(&mut self) {
+        use std::time::SystemTime;
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        self.scan_end = Some(now);
+        self.completed = true;
+    }
+
+    /// Save the manifest, storing chunk paths relative to the manifest file
+    /// so the directory can be moved as a whole.
+    ///
+    /// Chunks outside the manifest's directory keep absolute paths.
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> error::Result<()> {
+        let mut on_disk = self.clone();
+        on_disk.path_style = PathStyle::Relative;
+
+        let base = std::path::absolute(manifest_dir(path.as_ref()))
+            .map_err(Error::output_io(path.as_ref()))?;
+        for chunk in &mut on_disk.chunks {
+            let absolute = std::path::absolute(&chunk.file_path)
+                .map_err(Error::output_io(&chunk.file_path))?;
+            chunk.file_path = match absolute.strip_prefix(&base) {
+                Ok(relative) => relative.to_string_lossy().to_string(),
+                Err(_) => absolute.to_string_lossy().to_string(),
+            };
+        }
+
+        let json = serde_json::to_string_pretty(&on_disk)
+            .context("Failed to serialize manifest")?;
+
+        File::create(path.as_ref())
+            .and_then(|mut file| file.write_all(json.as_bytes()))
+            .map_err(Error::output_io(path.as_ref()))?;
+
+        // The JSON is authoritative; a failed sidecar must not fail the save
+        let sidecar = Self::sidecar_path(path.as_ref());
+        if let Err(e) = on_disk.write_sidecar(&sidecar) {
+            warn!("Failed to write manifest sidecar {}: {}", sidecar.display(), e);
+        }
+
+        Ok(())
+    }
+
+    /// Path of the Parquet sidecar written next to a JSON manifest
+    pub fn sidecar_path(manifest_path: &Path) -> PathBuf {
+        manifest_path.with_extension("parquet")
+    }
+
+    fn write_sidecar(&self, path: &Path) -> Result<()> {
+        let batch = self.to_record_batch()?;
+
+        // Write beside the target and rename so readers never see a partial file
+        let tmp = path.with_extension("parquet.tmp");
+        let mut writer = ArrowWriter::try_new(File::create(&tmp)?, batch.schema(), None)?;
+        writer.append_key_value_metadata(schema_version_entry());
+        writer.write(&batch)?;
+        writer.close()?;
+        std::fs::rename(&tmp, path)?;
+
+        Ok(())
+    }
+
+    /// Arrow schema of [`ScanManifest::to_record_batch`]
+    pub fn record_batch_schema() -> Arc<Schema> {
+        Arc::new(Schema::new(vec![
+            Field::new("chunk_number", DataType::UInt64, false),
+            Field::new("file_path", DataType::Utf8, false),
+            Field::new("row_count", DataType::UInt64, false),
+            Field::new("file_size", DataType::UInt64, false),
+            Field::new("created_at", DataType::Int64, false),
+            Field::new("retention_action", DataType::Utf8, true),
+            Field::new("retention_at", DataType::Int64, true),
+            Field::new("original_path", DataType::Utf8, true),
+            Field::new("scan_path", DataType::Utf8, false),
+            Field::new("scan_start", DataType::Int64, false),
+            Field::new("scan_end", DataType::Int64, true),
+            Field::new("completed", DataType::Boolean, false),
+            Field::new("total_rows", DataType::UInt64, false),
+            Field::new("chunk_count", DataType::UInt64, false),
+        ]))
+    }
+
+    /// One row per chunk with the scan-level fields repeated on every row
+    pub fn to_record_batch(&self) -> Result<RecordBatch> {
+        let chunks = &self.chunks;
+        let n = chunks.len();
+
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(chunks.iter().map(|c| c.chunk_number as u64).collect::<UInt64Array>()),
+            Arc::new(chunks.iter().map(|c| Some(c.file_path.as_str())).collect::<StringArray>()),
+            Arc::new(chunks.iter().map(|c| c.row_count).collect::<UInt64Array>()),
+            Arc::new(chunks.iter().map(|c| c.file_size).collect::<UInt64Array>()),
+            Arc::new(chunks.iter().map(|c| c.created_at).collect::<Int64Array>()),
+            Arc::new(chunks.iter().map(|c| c.retention.as_ref().map(ChunkRetention::action)).collect::<StringArray>()),
+            Arc::new(chunks.iter().map(|c| c.retention.as_ref().map(ChunkRetention::at)).collect::<Int64Array>()),
+            Arc::new(chunks.iter().map(|c| c.retention.as_ref().and_then(ChunkRetention::original_path)).collect::<StringArray>()),
+            Arc::new(StringArray::from(vec![self.scan_path.as_str(); n])),
+            Arc::new(Int64Array::from(vec![self.scan_start; n])),
+            Arc::new(Int64Array::from(vec![self.scan_end; n])),
+            Arc::new(BooleanArray::from(vec![self.completed; n])),
+            Arc::new(UInt64Array::from(vec![self.total_rows; n])),
+            Arc::new(UInt64Array::from(vec![self.chunk_count as u64; n])),
+        ];
+
+        RecordBatch::try_new(Self::record_batch_schema(), columns)
+            .context("Failed to build manifest record batch")
+    }
+
+    /// Merge several manifests into one.
+    ///
+    /// Chunk lists are concatenated in input order and renumbered so chunk
+    /// numbers stay unique; row totals are summed, completed directories are
+    /// unioned, and the merged scan spans the earliest start to the latest end.
+    /// Inputs must share the same scan root unless `allow_multiple_roots` is
+    /// set, in which case completed directories are namespaced by their root.
+    /// The shards of a sharded scan must all be there, each once.
+    pub fn merge(sources: Vec<(String, ScanManifest)>, allow_multiple_roots: bool) -> Result<Self> {
+        if sources.is_empty() {
+            anyhow::bail!("No manifests to merge");
+        }
+        let shards: Vec<Option<Shard>> = sources.iter().map(|(_, m)| m.shard).collect();
+        shard::check_complete(&shards)?;
+
+        let roots: HashSet<&str> = sources.iter().map(|(_, m)| m.scan_path.as_str()).collect();
+        let multiple_roots = roots.len() > 1;
+        if multiple_roots && !allow_multiple_roots {
+            let mut roots: Vec<&str> = roots.into_iter().collect();
+            roots.sort();
+            anyhow::bail!(
+                "Manifests have different scan roots ({}); pass --allow-multiple-roots to merge them anyway",
+                roots.join(", ")
+            );
+        }
+
+        let scan_path = if multiple_roots {
+            common_path_prefix(sources.iter().map(|(_, m)| m.scan_path.as_str()))
+        } else {
+            sources[0].1.scan_path.clone()
+        };
+
+        let mut merged = ScanManifest::new(scan_path);
+        merged.scan_start = sources.iter().map(|(_, m)| m.scan_start).min().unwrap_or(merged.scan_start);
+        merged.scan_end = sources.iter().filter_map(|(_, m)| m.scan_end).max();
+        merged.completed = sources.iter().all(|(_, m)| m.completed);
+        let hosts: HashSet<Option<&String>> = sources.iter().map(|(_, m)| m.hostname.as_ref()).collect();
+        if hosts.len() == 1 {
+            merged.hostname = sources[0].1.hostname.clone();
+        }
+
+        for (manifest_path, manifest) in sources {
+            let chunk_offset = merged.chunk_count;
+
+            for dir in &manifest.completed_top_level_dirs {
+                let dir = if multiple_roots {
+                    format!("{}/{}", manifest.scan_path.trim_end_matches('/'), dir)
+                } else {
+                    dir.clone()
+                };
+                merged.completed_top_level_dirs.insert(dir);
+            }
+
+            merged.errors.merge(&manifest.errors);
+            merged.entries_skipped += manifest.entries_skipped;
+
+            for (dir, stats) in &manifest.dir_stats {
+                let dir = if multiple_roots {
+                    format!("{}/{}", manifest.scan_path.trim_end_matches('/'), dir)
+                } else {
+                    dir.clone()
+                };
+                let merged_stats = merged.dir_stats.entry(dir).or_default();
+                merged_stats.rows += stats.rows;
+                merged_stats.bytes += stats.bytes;
+            }
+
+            merged.merged_from.push(MergedSource {
+                manifest_path,
+                scan_path: manifest.scan_path.clone(),
+                hostname: manifest.hostname.clone(),
+                total_rows: manifest.total_rows,
+                chunk_offset,
+                chunk_count: manifest.chunks.len(),
+                shard: manifest.shard,
+            });
+
+            for mut chunk in manifest.chunks {
+                chunk.chunk_number = merged.chunk_count;
+                merged.add_chunk(chunk);
+            }
+        }
+
+        Ok(merged)
+    }
+
+    /// Load and merge manifest files
+    pub fn merge_files<P: AsRef<Path>>(paths: &[P], allow_multiple_roots: bool) -> Result<Self> {
+        let mut sources = Vec::with_capacity(paths.len());
+        for path in paths {
+            let path = path.as_ref();
+            let manifest = Self::load_from_file(path)
+                .with_context(|| format!("Failed to load manifest {}", path.display()))?;
+            sources.push((path.to_string_lossy().to_string(), manifest));
+        }
+        Self::merge(sources, allow_multiple_roots)
+    }
+}
+
+/// Directory containing a manifest file ("." for a bare file name)
+fn manifest_dir(path: &Path) -> &Path {
+    path.parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."))
+}
+
+/// Longest common directory prefix of a set of absolute paths
+fn common_path_prefix<'a>(paths: impl Iterator<Item = &'a str>) -> String {
+    let mut prefix: Option<Vec<&str>> = None;
+
+    for path in paths {
+        let components: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+        prefix = Some(match prefix {
+            None => components,
+            Some(current) => current
+                .into_iter()
+                .zip(components)
+                .take_while(|(a, b)| a == b)
+                .map(|(a, _)| a)
+                .collect(),
+        });
+    }
+
+    format!("/{}", prefix.unwrap_or_default().join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::FileEntry;
+    use crate::rotating_writer::{RotatingParquetWriter, RotatingWriterConfig};
+    use std::path::PathBuf;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    fn create_test_entry(path: &str, top_level_dir: &str) -> FileEntry {
+        FileEntry { depth: 2, top_level_dir: top_level_dir.to_string(), ..FileEntry::fixture(path) }
+    }
+
+    /// Run the rotating writer over `rows` entries split across two top-level dirs
+    fn write_scan(dir: &Path, name: &str, scan_path: &str, rows: usize) -> PathBuf {
+        let config = RotatingWriterConfig {
+            base_output_path: dir.join(format!("{}.parquet", name)),
+            rows_per_chunk: 2,
+            time_interval: Duration::from_secs(3600),
+        };
+
+        let mut writer = RotatingParquetWriter::new(config, scan_path.to_string()).unwrap();
+        for i in 0..rows {
+            let top = if i < rows / 2 { "a" } else { "b" };
+            writer.write_batch(&[create_test_entry(&format!("{}/{}/f{}", scan_path, top, i), top)]).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        dir.join(format!("{}_manifest.json", name))
+    }
+
+    #[test]
+    fn test_merge_sums_and_renumbers() {
+        let temp_dir = TempDir::new().unwrap();
+        let first = write_scan(temp_dir.path(), "first", "/project/cil", 5);
+        let second = write_scan(temp_dir.path(), "second", "/project/cil", 4);
+
+        let m1 = ScanManifest::load_from_file(&first).unwrap();
+        let m2 = ScanManifest::load_from_file(&second).unwrap();
+
+        let merged = ScanManifest::merge_files(&[&first, &second], false).unwrap();
+
+        assert_eq!(merged.total_rows, 9);
+        assert_eq!(merged.chunk_count, m1.chunk_count + m2.chunk_count);
+        assert_eq!(merged.total_rows, merged.chunks.iter().map(|c| c.row_count).sum::<u64>());
+        assert_eq!(merged.scan_start, m1.scan_start.min(m2.scan_start));
+        assert_eq!(merged.scan_end, m1.scan_end.max(m2.scan_end));
+        assert!(merged.completed);
+
+        // Both inputs numbered their chunks from the same base; merged numbers must be unique
+        let numbers: HashSet<usize> = merged.chunks.iter().map(|c| c.chunk_number).collect();
+        assert_eq!(numbers.len(), merged.chunks.len());
+        assert_eq!(merged.merged_from[1].chunk_offset, m1.chunk_count);
+
+        // File paths are carried through untouched
+        assert_eq!(merged.chunks[0].file_path, m1.chunks[0].file_path);
+        assert_eq!(merged.chunks[m1.chunk_count].file_path, m2.chunks[0].file_path);
+    }
+
+    #[test]
+    fn test_merge_shards() {
+        let temp_dir = TempDir::new().unwrap();
+        let paths: Vec<PathBuf> = (0..3)
+            .map(|index| {
+                let path = write_scan(temp_dir.path(), &format!("scan_shard_{}", index), "/project/cil", 4);
+                let mut manifest = ScanManifest::load_from_file(&path).unwrap();
+                manifest.shard = Some(Shard::new(index, 3, 1).unwrap());
+                manifest.save_to_file(&path).unwrap();
+                path
+            })
+            .collect();
+
+        let merged = ScanManifest::merge_files(&paths, false).unwrap();
+        assert_eq!(merged.total_rows, 12);
+        assert_eq!(merged.shard, None);
+        assert_eq!(merged.merged_from[2].shard, Some(Shard::new(2, 3, 1).unwrap()));
+
+        let err = ScanManifest::merge_files(&paths[..2], false).unwrap_err();
+        assert_eq!(err.to_string(), "Missing shard 2 of 3");
+    }
+
+    #[test]
+    fn test_merge_rejects_conflicting_roots() {
+        let temp_dir = TempDir::new().unwrap();
+        let first = write_scan(temp_dir.path(), "gcp", "/project/cil/gcp", 4);
+        let second = write_scan(temp_dir.path(), "norgay", "/project/cil/norgay", 4);
+
+        let err = ScanManifest::merge_files(&[&first, &second], false).unwrap_err();
+        assert!(err.to_string().contains("--allow-multiple-roots"));
+
+        let merged = ScanManifest::merge_files(&[&first, &second], true).unwrap();
+        assert_eq!(merged.scan_path, "/project/cil");
+        assert_eq!(merged.total_rows, 8);
+        assert!(merged.completed_top_level_dirs.contains("/project/cil/gcp/a"));
+        assert!(merged.completed_top_level_dirs.contains("/project/cil/norgay/a"));
+
+        // Scans record the host they ran on; a merge keeps it per source
+        assert_eq!(merged.hostname, Some(crate::lock::hostname()));
+        assert_eq!(merged.merged_from[1].hostname, Some(crate::lock::hostname()));
+        assert_eq!(merged.merged_from[1].total_rows, 4);
+    }
+
+    #[test]
+    fn test_chunk_paths_relative_on_disk() {
+        let temp_dir = TempDir::new().unwrap();
+        let manifest_path = write_scan(temp_dir.path(), "scan", "/project/cil", 4);
+
+        let raw: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&manifest_path).unwrap()).unwrap();
+        assert_eq!(raw["path_style"], "relative");
+        assert_eq!(raw["chunks"][0]["file_path"], "scan_chunk_0001.parquet");
+
+        // Moving the whole directory keeps the manifest usable
+        let moved = temp_dir.path().join("moved");
+        std::fs::create_dir(&moved).unwrap();
+        for entry in std::fs::read_dir(temp_dir.path()).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_file() {
+                std::fs::rename(&path, moved.join(path.file_name().unwrap())).unwrap();
+            }
+        }
+
+        let manifest = ScanManifest::load_from_file(moved.join("scan_manifest.json")).unwrap();
+        for chunk in &manifest.chunks {
+            assert!(chunk.file_path.starts_with(moved.to_str().unwrap()));
+            assert!(Path::new(&chunk.file_path).exists());
+        }
+    }
+
+    #[test]
+    fn test_load_legacy_absolute_manifest() {
+        let temp_dir = TempDir::new().unwrap();
+        let manifest_path = temp_dir.path().join("legacy_manifest.json");
+        std::fs::write(&manifest_path, r#"{
+            "scan_path": "/project/cil",
+            "total_rows": 10,
+            "chunk_count": 1,
+            "chunks": [{
+                "chunk_number": 1,
+                "file_path": "/scratch/old/scan_chunk_0001.parquet",
+                "row_count": 10,
+                "file_size": 1000,
+                "created_at": 1700000000
+            }],
+            "scan_start": 1700000000,
+            "scan_end": null,
+            "completed": false
+        }"#).unwrap();
+
+        let manifest = ScanManifest::load_from_file(&manifest_path).unwrap();
+        assert_eq!(manifest.path_style, PathStyle::Absolute);
+        assert_eq!(manifest.chunks[0].file_path, "/scratch/old/scan_chunk_0001.parquet");
+    }
+
+    fn read_sidecar(path: &Path) -> RecordBatch {
+        let reader = parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap();
+        let batches: Vec<RecordBatch> = reader.map(|b| b.unwrap()).collect();
+        arrow::compute::concat_batches(&ScanManifest::record_batch_schema(), &batches).unwrap()
+    }
+
+    #[test]
+    fn test_sidecar_matches_json() {
+        use arrow::array::Array;
+
+        let temp_dir = TempDir::new().unwrap();
+        let manifest_path = write_scan(temp_dir.path(), "scan", "/project/cil", 5);
+        let sidecar_path = temp_dir.path().join("scan_manifest.parquet");
+        assert_eq!(ScanManifest::sidecar_path(&manifest_path), sidecar_path);
+
+        let raw: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&manifest_path).unwrap()).unwrap();
+        let chunks = raw["chunks"].as_array().unwrap();
+        let batch = read_sidecar(&sidecar_path);
+        assert_eq!(batch.num_rows(), chunks.len());
+
+        let column = |name: &str| batch.column_by_name(name).unwrap().clone();
+        let u64s = |name: &str| column(name).as_any().downcast_ref::<UInt64Array>().unwrap().clone();
+        let i64s = |name: &str| column(name).as_any().downcast_ref::<Int64Array>().unwrap().clone();
+        let strs = |name: &str| column(name).as_any().downcast_ref::<StringArray>().unwrap().clone();
+
+        for (i, chunk) in chunks.iter().enumerate() {
+            assert_eq!(u64s("chunk_number").value(i), chunk["chunk_number"].as_u64().unwrap());
+            assert_eq!(strs("file_path").value(i), chunk["file_path"].as_str().unwrap());
+            assert_eq!(u64s("row_count").value(i), chunk["row_count"].as_u64().unwrap());
+            assert_eq!(u64s("file_size").value(i), chunk["file_size"].as_u64().unwrap());
+            assert_eq!(i64s("created_at").value(i), chunk["created_at"].as_i64().unwrap());
+            assert!(strs("retention_action").is_null(i));
+
+            assert_eq!(strs("scan_path").value(i), raw["scan_path"].as_str().unwrap());
+            assert_eq!(i64s("scan_start").value(i), raw["scan_start"].as_i64().unwrap());
+            assert_eq!(i64s("scan_end").value(i), raw["scan_end"].as_i64().unwrap());
+            assert_eq!(u64s("total_rows").value(i), raw["total_rows"].as_u64().unwrap());
+            assert_eq!(u64s("chunk_count").value(i), raw["chunk_count"].as_u64().unwrap());
+        }
+
+        // Regenerated on every save
+        let mut manifest = ScanManifest::load_from_file(&manifest_path).unwrap();
+        manifest.chunks[0].retention = Some(ChunkRetention::Deleted { at: 1700000001 });
+        manifest.save_to_file(&manifest_path).unwrap();
+
+        let batch = read_sidecar(&sidecar_path);
+        let actions = batch.column_by_name("retention_action").unwrap();
+        let actions = actions.as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(actions.value(0), "deleted");
+        assert!(actions.is_null(1));
+    }
+
+    #[test]
+    fn test_common_path_prefix() {
+        assert_eq!(common_path_prefix(["/a/b/c", "/a/b/d"].into_iter()), "/a/b");
+        assert_eq!(common_path_prefix(["/a/b", "/x"].into_iter()), "/");
+        assert_eq!(common_path_prefix(["/a/b/"].into_iter()), "/a/b");
+    }
+
+    #[test]
+    fn test_load_errors_tell_missing_from_corrupt() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("scan_manifest.json");
+
+        let err = ScanManifest::load_from_file(&path).unwrap_err();
+        assert!(matches!(err, Error::ManifestIo { path: ref p, .. } if p == &path), "{:?}", err);
+
+        std::fs::write(&path, "{\"scan_path\": ").unwrap();
+        let err = ScanManifest::load_from_file(&path).unwrap_err();
+        assert!(matches!(err, Error::ManifestCorrupt { path: ref p, .. } if p == &path), "{:?}", err);
+    }
+}
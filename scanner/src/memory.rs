@@ -0,0 +1,219 @@
+use anyhow::{Context, Result};
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use std::fs::File;
+use std::path::Path;
+use std::sync::{Condvar, Mutex};
+
+/// Reader batch size used without a memory limit
+pub const DEFAULT_BATCH_ROWS: usize = 100_000;
+
+/// Smallest reader batch a memory limit can force
+pub const MIN_BATCH_ROWS: usize = 64;
+
+/// Rows per output row group without a memory limit (the Parquet default)
+pub const DEFAULT_ROW_GROUP_ROWS: usize = 1024 * 1024;
+
+/// Smallest output row group a memory limit can force
+pub const MIN_ROW_GROUP_ROWS: usize = 1024;
+
+/// Arrow arrays carry offsets, validity and padding the Parquet size omits
+const IN_MEMORY_OVERHEAD: u64 = 2;
+
+/// Assumed size of a row when the first chunk has no rows to measure
+const FALLBACK_BYTES_PER_ROW: u64 = 512;
+
+/// How a memory limit is split between reading, writing and sorting
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemoryPlan {
+    /// Estimated in-memory bytes per row
+    pub bytes_per_row: u64,
+
+    /// Rows per batch decoded by each reader
+    pub batch_rows: usize,
+
+    /// Bytes of decoded batches allowed between the readers and the writer
+    pub reader_bytes: u64,
+
+    /// Rows the writer buffers before flushing a row group
+    pub row_group_rows: usize,
+
+    /// Budget for sorting
+    pub sort_bytes: u64,
+}
+
+impl MemoryPlan {
+    /// Split `limit` bytes: half for decoded batches in flight, a quarter for
+    /// the writer's row group buffer and a quarter for sorting.
+    ///
+    /// Each of `threads` readers holds up to `batches_per_reader` batches (one
+    /// being decoded plus those queued for the writer), which sets the batch size.
+    pub fn new(limit: u64, bytes_per_row: u64, threads: usize, batches_per_reader: usize) -> Self {
+        let bytes_per_row = bytes_per_row.max(1);
+        let reader_bytes = limit / 2;
+        let writer_bytes = limit / 4;
+
+        let in_flight = (threads.max(1) * batches_per_reader.max(1)) as u64;
+        let batch_rows = (reader_bytes / in_flight / bytes_per_row) as usize;
+        let row_group_rows = (writer_bytes / bytes_per_row) as usize;
+
+        Self {
+            bytes_per_row,
+            batch_rows: batch_rows.clamp(MIN_BATCH_ROWS, DEFAULT_BATCH_ROWS),
+            reader_bytes,
+            row_group_rows: row_group_rows.clamp(MIN_ROW_GROUP_ROWS, DEFAULT_ROW_GROUP_ROWS),
+            sort_bytes: limit / 4,
+        }
+    }
+}
+
+/// Estimate the in-memory size of a row from a chunk's footer
+pub fn estimate_bytes_per_row(chunk: &Path) -> Result<u64> {
+    let file = File::open(chunk)
+        .with_context(|| format!("Failed to open chunk {}", chunk.display()))?;
+    let reader = SerializedFileReader::new(file)
+        .with_context(|| format!("Failed to read footer of {}", chunk.display()))?;
+
+    let (mut bytes, mut rows) = (0u64, 0u64);
+    for row_group in reader.metadata().row_groups() {
+        bytes += row_group.total_byte_size().max(0) as u64;
+        rows += row_group.num_rows().max(0) as u64;
+    }
+
+    if rows == 0 {
+        return Ok(FALLBACK_BYTES_PER_ROW);
+    }
+    Ok((bytes / rows).max(1) * IN_MEMORY_OVERHEAD)
+}
+
+/// Caps the bytes of decoded batches waiting for the writer.
+///
+/// Batches of the chunk the writer is draining are always admitted, so a
+/// reader that got ahead can never starve the chunk the writer waits on.
+pub(crate) struct ByteBudget {
+    limit: u64,
+    state: Mutex<BudgetState>,
+    changed: Condvar,
+}
+
+struct BudgetState {
+    used: u64,
+    writer_chunk: usize,
+    closed: bool,
+}
+
+impl ByteBudget {
+    pub(crate) fn new(limit: u64) -> Self {
+        Self {
+            limit,
+            state: Mutex::new(BudgetState { used: 0, writer_chunk: 0, closed: false }),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Wait until `bytes` for a batch of `chunk` fit in the budget
+    pub(crate) fn acquire(&self, bytes: u64, chunk: usize) {
+        let mut state = self.state.lock().unwrap();
+        while !state.closed
+            && chunk != state.writer_chunk
+            && state.used > 0
+            && state.used + bytes > self.limit
+        {
+            state = self.changed.wait(state).unwrap();
+        }
+        state.used += bytes;
+    }
+
+    /// Return bytes once the writer is done with a batch
+    pub(crate) fn release(&self, bytes: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.used = state.used.saturating_sub(bytes);
+        self.changed.notify_all();
+    }
+
+    /// The writer moved on to `chunk`
+    pub(crate) fn start_chunk(&self, chunk: usize) {
+        self.state.lock().unwrap().writer_chunk = chunk;
+        self.changed.notify_all();
+    }
+
+    /// The writer stopped; admit everything so readers can exit
+    pub(crate) fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.changed.notify_all();
+    }
+
+    #[cfg(test)]
+    fn used(&self) -> u64 {
+        self.state.lock().unwrap().used
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_plan_from_limit() {
+        // 2 GB, 1 KB rows, 4 readers holding 3 batches each
+        let plan = MemoryPlan::new(2_000_000_000, 1000, 4, 3);
+        assert_eq!(plan.reader_bytes, 1_000_000_000);
+        assert_eq!(plan.batch_rows, 83_333);
+        assert_eq!(plan.row_group_rows, 500_000);
+        assert_eq!(plan.sort_bytes, 500_000_000);
+
+        // Wide rows shrink the batches
+        let wide = MemoryPlan::new(2_000_000_000, 50_000, 4, 3);
+        assert_eq!(wide.batch_rows, 1_666);
+        assert_eq!(wide.row_group_rows, 10_000);
+    }
+
+    #[test]
+    fn test_plan_is_clamped() {
+        let huge = MemoryPlan::new(u64::MAX / 2, 10, 1, 1);
+        assert_eq!(huge.batch_rows, DEFAULT_BATCH_ROWS);
+        assert_eq!(huge.row_group_rows, DEFAULT_ROW_GROUP_ROWS);
+
+        let tiny = MemoryPlan::new(1, 1000, 8, 3);
+        assert_eq!(tiny.batch_rows, MIN_BATCH_ROWS);
+        assert_eq!(tiny.row_group_rows, MIN_ROW_GROUP_ROWS);
+
+        assert_eq!(MemoryPlan::new(1000, 0, 0, 0).bytes_per_row, 1);
+    }
+
+    #[test]
+    fn test_budget_blocks_readers_ahead_of_the_writer() {
+        let budget = Arc::new(ByteBudget::new(100));
+        budget.acquire(80, 1);
+
+        // A later chunk has to wait for room...
+        let waiting = {
+            let budget = budget.clone();
+            std::thread::spawn(move || budget.acquire(50, 2))
+        };
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!waiting.is_finished());
+
+        // ...but the writer's current chunk never does
+        budget.acquire(50, 0);
+        assert_eq!(budget.used(), 130);
+
+        budget.release(80);
+        budget.release(50);
+        waiting.join().unwrap();
+        assert_eq!(budget.used(), 50);
+    }
+
+    #[test]
+    fn test_budget_close_releases_waiters() {
+        let budget = Arc::new(ByteBudget::new(10));
+        budget.acquire(10, 1);
+        let waiting = {
+            let budget = budget.clone();
+            std::thread::spawn(move || budget.acquire(10, 2))
+        };
+        budget.close();
+        waiting.join().unwrap();
+    }
+}
@@ -0,0 +1,185 @@
+use crate::http_server::HttpServer;
+#[cfg(feature = "metrics")]
+use crate::http_server::Reply;
+use crate::manifest::{ChunkMetadata, ScanManifest};
+use crate::models::ScanTag;
+use crate::rotating_writer::ChunkHook;
+use crate::status::ScanCounters;
+use anyhow::Result;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Shortest window `current_rate` is measured over; scrapes closer together
+/// than this see the previous rate
+const MIN_RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Last `current_rate` measurement: when, the entry count then, and the rate
+struct RateSample {
+    at: Instant,
+    entries: u64,
+    rate: f64,
+}
+
+/// A running scan's counters in the Prometheus text format.
+///
+/// The file, directory, byte, and error counts are the scanner's own
+/// counters (see [`crate::scanner::Scanner::with_counters`]), read when
+/// scraped; chunks are counted by [`ScanMetrics::chunk_counter`].
+pub struct ScanMetrics {
+    counters: ScanCounters,
+    chunks: Arc<AtomicU64>,
+    scan_id: String,
+    root: String,
+    last: Mutex<RateSample>,
+}
+
+impl ScanMetrics {
+    /// Metrics of the scan of `root` started at `scan_start`, counting in `counters`
+    pub fn new(counters: ScanCounters, root: String, scan_start: i64) -> Self {
+        Self {
+            counters,
+            chunks: Arc::new(AtomicU64::new(0)),
+            scan_id: ScanTag::from_scan_start(scan_start).scan_id,
+            root,
+            last: Mutex::new(RateSample { at: Instant::now(), entries: 0, rate: 0.0 }),
+        }
+    }
+
+    /// A hook for the rotating writer that counts the chunks it closes
+    pub fn chunk_counter(&self) -> Box<dyn ChunkHook> {
+        Box::new(ChunkCounter(self.chunks.clone()))
+    }
+
+    /// Entries per second since the previous measurement
+    fn current_rate(&self, entries: u64) -> f64 {
+        let mut last = self.last.lock().unwrap_or_else(|e| e.into_inner());
+        let elapsed = last.at.elapsed();
+        if elapsed >= MIN_RATE_WINDOW {
+            last.rate = entries.saturating_sub(last.entries) as f64 / elapsed.as_secs_f64();
+            last.at = Instant::now();
+            last.entries = entries;
+        }
+        last.rate
+    }
+
+    /// The metrics as served on `/metrics`
+    pub fn render(&self) -> String {
+        let counts = self.counters.load();
+        let rate = self.current_rate(counts.files + counts.directories + counts.symlinks);
+
+        let counters = [
+            ("files_scanned_total", "Files scanned", counts.files),
+            ("dirs_scanned_total", "Directories scanned", counts.directories),
+            ("bytes_scanned_total", "Combined size of the files scanned", counts.bytes),
+            ("errors_total", "Paths that could not be read", counts.errors),
+            ("chunks_written_total", "Chunk files completed", self.chunks.load(Ordering::Relaxed)),
+        ];
+
+        let mut out = String::new();
+        for (name, help, value) in counters {
+            let _ = write!(out, "# HELP {0} {1}\n# TYPE {0} counter\n{0} {2}\n", name, help, value);
+        }
+        let _ = write!(out, "# HELP current_rate Files and directories scanned per second lately\n\
+                             # TYPE current_rate gauge\ncurrent_rate {}\n", rate);
+        let _ = write!(out, "# HELP scan_info The scan being run\n# TYPE scan_info gauge\n\
+                             scan_info{{scan_id=\"{}\",root=\"{}\"}} 1\n",
+                       escape_label(&self.scan_id), escape_label(&self.root));
+        out
+    }
+}
+
+/// `value` escaped for a label value in the Prometheus text format
+fn escape_label(value: &str) -> String {
+    value.replace('\\', r"\\").replace('"', "\\\"").replace('\n', r"\n")
+}
+
+struct ChunkCounter(Arc<AtomicU64>);
+
+impl ChunkHook for ChunkCounter {
+    fn chunk_closed(&mut self, _chunk: &ChunkMetadata) -> Result<()> {
+        self.0.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn record(&mut self, _manifest: &mut ScanManifest) {}
+
+    fn finish(&mut self, _manifest: &mut ScanManifest) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Serve `/metrics` on `listen` (e.g., "0.0.0.0:9200"; port 0 picks a free
+/// one) until [`HttpServer::finish`]
+#[cfg(feature = "metrics")]
+pub fn serve(listen: &str, metrics: Arc<ScanMetrics>) -> Result<HttpServer> {
+    HttpServer::start(listen, "metrics", move |url| match url {
+        "/metrics" => Reply::new(200, "text/plain; version=0.0.4", metrics.render()),
+        _ => Reply::not_found(),
+    })
+}
+
+#[cfg(not(feature = "metrics"))]
+pub fn serve(_listen: &str, _metrics: Arc<ScanMetrics>) -> Result<HttpServer> {
+    anyhow::bail!("--metrics-listen requires building with --features metrics")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The value of the sample line starting with `name`
+    fn value(text: &str, name: &str) -> f64 {
+        text.lines()
+            .find(|line| line.starts_with(name) && line[name.len()..].starts_with([' ', '{']))
+            .and_then(|line| line.rsplit(' ').next())
+            .unwrap_or_else(|| panic!("{} missing from\n{}", name, text))
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_render() {
+        let counters = ScanCounters::default();
+        counters.files.store(7, Ordering::Relaxed);
+        counters.directories.store(2, Ordering::Relaxed);
+        counters.bytes.store(4096, Ordering::Relaxed);
+        counters.errors.store(1, Ordering::Relaxed);
+        let metrics = ScanMetrics::new(counters, "/data/\"lab\"".to_string(), 1716170400);
+        let mut hook = metrics.chunk_counter();
+        for chunk_number in 1..=2 {
+            hook.chunk_closed(&ChunkMetadata {
+                chunk_number,
+                file_path: format!("/out/scan_chunk_{:04}.parquet", chunk_number),
+                row_count: 3,
+                file_size: 1024,
+                created_at: 1716170400,
+                checksum: None,
+                retention: None,
+                upload: None,
+                post_chunk: None,
+            }).unwrap();
+        }
+
+        let text = metrics.render();
+        assert_eq!(value(&text, "files_scanned_total"), 7.0);
+        assert_eq!(value(&text, "dirs_scanned_total"), 2.0);
+        assert_eq!(value(&text, "bytes_scanned_total"), 4096.0);
+        assert_eq!(value(&text, "errors_total"), 1.0);
+        assert_eq!(value(&text, "chunks_written_total"), 2.0);
+        assert!(text.contains("# TYPE files_scanned_total counter\n"));
+        assert!(text.contains("# TYPE current_rate gauge\n"));
+        assert!(text.contains(r#"scan_info{scan_id="2024-05-20T02:00:00Z",root="/data/\"lab\""} 1"#), "{}", text);
+    }
+
+    #[test]
+    fn test_current_rate_needs_a_window() {
+        let metrics = ScanMetrics::new(ScanCounters::default(), "/data".to_string(), 0);
+        metrics.last.lock().unwrap().at -= Duration::from_secs(2);
+        let rate = metrics.current_rate(100);
+        assert!(rate > 49.0 && rate <= 50.0, "{}", rate);
+        // Too soon after the last measurement: the rate stands
+        assert_eq!(metrics.current_rate(1000), rate);
+    }
+}
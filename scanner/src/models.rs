@@ -1,9 +1,11 @@
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 
 /// Get username from UID (Unix-specific)
 #[cfg(unix)]
-fn get_username(uid: u32) -> Option<String> {
+pub(crate) fn get_username(uid: u32) -> Option<String> {
     use std::ffi::CStr;
     unsafe {
         let passwd = libc::getpwuid(uid);
@@ -20,7 +22,7 @@ fn get_username(uid: u32) -> Option<String> {
 
 /// Get group name from GID (Unix-specific)
 #[cfg(unix)]
-fn get_groupname(gid: u32) -> Option<String> {
+pub(crate) fn get_groupname(gid: u32) -> Option<String> {
     use std::ffi::CStr;
     unsafe {
         let group = libc::getgrgid(gid);
@@ -37,18 +39,19 @@ fn get_groupname(gid: u32) -> Option<String> {
 
 /// Stub for non-Unix systems
 #[cfg(not(unix))]
-fn get_username(_uid: u32) -> Option<String> {
+pub(crate) fn get_username(_uid: u32) -> Option<String> {
     None
 }
 
 /// Stub for non-Unix systems
 #[cfg(not(unix))]
-fn get_groupname(_gid: u32) -> Option<String> {
+pub(crate) fn get_groupname(_gid: u32) -> Option<String> {
     None
 }
 
 /// Represents a single file entry in the filesystem scan
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct FileEntry {
     /// Full absolute path to the file
     pub path: String,
@@ -71,7 +74,8 @@ pub struct FileEntry {
     /// Inode number
     pub inode: u64,
 
-    /// Unix permissions (octal representation)
+    /// Permission bits of the mode (octal representation), without the file
+    /// type: 0o755 for a typical directory
     pub permissions: u32,
 
     /// User ID (owner)
@@ -89,11 +93,75 @@ pub struct FileEntry {
     /// Parent directory path
     pub parent_path: String,
 
-    /// Depth from scan root (0 = root)
+    /// Path components below the scan root: 0 for the root itself, 1 for
+    /// its immediate children
     pub depth: u32,
 
     /// Top-level directory name from scan root
     pub top_level_dir: String,
+
+    /// Target of a symbolic link, exactly as stored in the link; None for
+    /// other entries
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub link_target: Option<String>,
+
+    /// Device ID of the filesystem holding the entry
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub device: u64,
+
+    /// Number of hard links to the inode
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub nlink: u64,
+
+    /// The full `st_mode`, file type bits included
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub raw_mode: u32,
+}
+
+/// Bits of `st_mode` kept in [`FileEntry::permissions`]: setuid, setgid,
+/// sticky, and the read/write/execute bits
+pub const PERMISSION_BITS: u32 = 0o7777;
+
+/// Resume unit of the scan root and of everything directly in it that is not
+/// a directory. No entry can be named ".", so it can't be mistaken for a
+/// top-level directory.
+pub const ROOT_FILES_UNIT: &str = ".";
+
+/// Resume unit of an entry: its top-level directory, or [`ROOT_FILES_UNIT`]
+/// for the root and the root's files
+pub(crate) fn resume_unit(top_level_dir: &str, depth: u32, is_dir: bool) -> &str {
+    if depth == 0 || (depth == 1 && !is_dir) {
+        ROOT_FILES_UNIT
+    } else {
+        top_level_dir
+    }
+}
+
+/// Parent path, depth, and top-level directory of `path` within `scan_root`
+pub(crate) fn path_fields(path: &Path, scan_root: &Path) -> (String, u32, String) {
+    // Calculate parent path
+    let parent_path = path
+        .parent()
+        .map(|p| p.to_string_lossy().to_string())
+        .unwrap_or_else(|| "/".to_string());
+
+    // Calculate depth from scan root
+    let depth = path
+        .strip_prefix(scan_root)
+        .map(|p| p.components().count() as u32)
+        .unwrap_or(0);
+
+    // Get top-level directory
+    let top_level_dir = path
+        .strip_prefix(scan_root)
+        .ok()
+        .and_then(|p| p.components().next())
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .unwrap_or_else(|| scan_root.file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| "root".to_string()));
+
+    (parent_path, depth, top_level_dir)
 }
 
 impl FileEntry {
@@ -108,31 +176,13 @@ impl FileEntry {
 
         let path_str = path.to_string_lossy().to_string();
 
-        // Calculate parent path
-        let parent_path = path
-            .parent()
-            .map(|p| p.to_string_lossy().to_string())
-            .unwrap_or_else(|| "/".to_string());
-
-        // Calculate depth from scan root
-        let depth = path
-            .strip_prefix(scan_root)
-            .map(|p| p.components().count() as u32)
-            .unwrap_or(0);
-
-        // Get top-level directory
-        let top_level_dir = path
-            .strip_prefix(scan_root)
-            .ok()
-            .and_then(|p| p.components().next())
-            .map(|c| c.as_os_str().to_string_lossy().to_string())
-            .unwrap_or_else(|| scan_root.file_name()
-                .map(|n| n.to_string_lossy().to_string())
-                .unwrap_or_else(|| "root".to_string()));
+        let (parent_path, depth, top_level_dir) = path_fields(path, scan_root);
 
         // Determine file type
         let file_type = if metadata.is_dir() {
             "directory".to_string()
+        } else if metadata.file_type().is_symlink() {
+            "symlink".to_string()
         } else {
             path.extension()
                 .map(|e| e.to_string_lossy().to_string())
@@ -172,7 +222,7 @@ impl FileEntry {
             created_time,
             file_type,
             inode: metadata.ino(),
-            permissions: metadata.mode(),
+            permissions: metadata.mode() & PERMISSION_BITS,
             uid,
             gid,
             owner,
@@ -180,12 +230,81 @@ impl FileEntry {
             parent_path,
             depth,
             top_level_dir,
+            link_target: None,
+            device: metadata.dev(),
+            nlink: metadata.nlink(),
+            raw_mode: metadata.mode(),
         })
     }
+
+    /// The unit a resumed scan skips this entry with once it is completed.
+    ///
+    /// Each top-level directory is a unit holding everything below it. Files
+    /// directly in the root (and the root itself) share [`ROOT_FILES_UNIT`]
+    /// instead of making one unit each, so a root-level file can never stand
+    /// for a directory of the same name.
+    pub fn resume_unit(&self) -> &str {
+        resume_unit(&self.top_level_dir, self.depth, self.file_type == "directory")
+    }
+}
+
+/// Entries for tests. Integration tests and benches get these through the
+/// `test-fixtures` feature, which the crate's dev-dependency on itself turns on.
+#[cfg(any(test, feature = "test-fixtures"))]
+impl FileEntry {
+    /// A 1 KiB regular file at `path`, as a scan of the path's first
+    /// component records it ("/data" for "/data/a/b.txt"). It has mode 0644,
+    /// uid and gid 1000 without names, and every time at 1,700,000,000.
+    /// Change the rest with struct update syntax.
+    #[doc(hidden)]
+    pub fn fixture(path: &str) -> Self {
+        let path_buf = Path::new(path);
+        let scan_root: PathBuf = path_buf.components().take(2).collect();
+        let (parent_path, depth, top_level_dir) = path_fields(path_buf, &scan_root);
+        Self {
+            path: path.to_string(),
+            size: 1024,
+            modified_time: 1_700_000_000,
+            accessed_time: 1_700_000_000,
+            created_time: None,
+            file_type: path_buf.extension()
+                .map(|e| e.to_string_lossy().to_string())
+                .unwrap_or_else(|| "no_extension".to_string()),
+            inode: 1,
+            permissions: 0o644,
+            uid: 1000,
+            gid: 1000,
+            owner: None,
+            group: None,
+            parent_path,
+            depth,
+            top_level_dir,
+            link_target: None,
+            device: 1,
+            nlink: 1,
+            raw_mode: 0o100644,
+        }
+    }
+
+    /// [`FileEntry::fixture`] for a directory: size 0 and mode 0755
+    #[doc(hidden)]
+    pub fn dir_fixture(path: &str) -> Self {
+        Self {
+            size: 0,
+            file_type: "directory".to_string(),
+            permissions: 0o755,
+            raw_mode: 0o40755,
+            ..Self::fixture(path)
+        }
+    }
 }
 
-/// Configuration options for scanning
-#[derive(Debug, Clone)]
+/// Configuration options for scanning.
+///
+/// Build with [`ScanOptions::builder`] to have settings checked, or start
+/// from [`ScanOptions::default`] and set fields directly.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
 pub struct ScanOptions {
     /// Number of parallel threads to use
     pub num_threads: usize,
@@ -193,12 +312,31 @@ pub struct ScanOptions {
     /// Batch size for writing to Parquet
     pub batch_size: usize,
 
+    /// Entries the scan may queue for batching before the walk waits; the
+    /// channel of batches to the writer holds as many entries again. See
+    /// [`ScanOptions::max_queued_entries`].
+    pub channel_capacity_entries: usize,
+
     /// Whether to follow symbolic links
     pub follow_symlinks: bool,
 
-    /// Maximum depth to scan (None = unlimited)
+    /// Deepest [`FileEntry::depth`] to scan (None = unlimited): 0 records
+    /// only the root, 1 adds its immediate children
     pub max_depth: Option<usize>,
 
+    /// Glob patterns of entries to leave out, along with everything below
+    /// them. Patterns without a `/` match an entry's name; others match its
+    /// path relative to the scan root.
+    pub exclude_globs: Vec<String>,
+
+    /// Outputs written while scanning, whose files are left out when they
+    /// lie in the scanned tree: the output itself and the files next to it
+    /// named after its stem, such as chunks, the manifest and the lock
+    pub exclude_outputs: Vec<PathBuf>,
+
+    /// Leave out files smaller than this many bytes (directories are kept)
+    pub min_size: Option<u64>,
+
     /// Enable checkpointing for resume capability
     pub enable_checkpointing: bool,
 
@@ -211,16 +349,298 @@ impl Default for ScanOptions {
         Self {
             num_threads: num_cpus::get(),
             batch_size: 100_000,
+            channel_capacity_entries: DEFAULT_CHANNEL_CAPACITY_ENTRIES,
             follow_symlinks: false,
             max_depth: None,
+            exclude_globs: Vec::new(),
+            exclude_outputs: Vec::new(),
+            min_size: None,
             enable_checkpointing: false,
             checkpoint_path: None,
         }
     }
 }
 
+impl ScanOptions {
+    /// Start building options from the defaults
+    pub fn builder() -> ScanOptionsBuilder {
+        ScanOptionsBuilder::default()
+    }
+
+    /// Batches the channel to the writer holds: as many as fit in
+    /// `channel_capacity_entries`, but at least one
+    pub fn batch_channel_capacity(&self) -> usize {
+        (self.channel_capacity_entries / self.batch_size.max(1)).max(1)
+    }
+
+    /// Most entries a scan holds on their way to the writer: the entry
+    /// channel, the batches in the writer's channel, the batch being filled,
+    /// and the one the writer is taking
+    pub fn max_queued_entries(&self) -> usize {
+        self.channel_capacity_entries + (self.batch_channel_capacity() + 2) * self.batch_size
+    }
+
+    /// Rough memory [`max_queued_entries`](Self::max_queued_entries) take,
+    /// at [`ENTRY_BYTES_ESTIMATE`] each
+    pub fn queue_memory_estimate(&self) -> u64 {
+        self.max_queued_entries() as u64 * ENTRY_BYTES_ESTIMATE as u64
+    }
+}
+
+/// Default [`ScanOptions::channel_capacity_entries`]. At about 450 bytes per
+/// entry this is some 30 MB, enough to keep the writer busy while the walk
+/// stalls on a slow directory.
+pub const DEFAULT_CHANNEL_CAPACITY_ENTRIES: usize = 65_536;
+
+/// Memory a queued [`FileEntry`] takes: the struct, plus its path, parent
+/// path, and names on the heap at typical lengths
+pub const ENTRY_BYTES_ESTIMATE: usize = std::mem::size_of::<FileEntry>() + 200;
+
+/// Builds [`ScanOptions`], checking them in [`build`](Self::build)
+#[derive(Debug, Clone, Default)]
+pub struct ScanOptionsBuilder {
+    options: ScanOptions,
+}
+
+impl ScanOptionsBuilder {
+    /// Number of parallel threads to use
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.options.num_threads = threads;
+        self
+    }
+
+    /// Number of entries sent to the writer at a time
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.options.batch_size = batch_size;
+        self
+    }
+
+    /// Entries queued for batching before the walk waits for the writer
+    pub fn channel_capacity_entries(mut self, entries: usize) -> Self {
+        self.options.channel_capacity_entries = entries;
+        self
+    }
+
+    pub fn follow_symlinks(mut self, follow: bool) -> Self {
+        self.options.follow_symlinks = follow;
+        self
+    }
+
+    /// Deepest [`FileEntry::depth`] to scan, or None for unlimited
+    pub fn max_depth(mut self, depth: impl Into<Option<usize>>) -> Self {
+        self.options.max_depth = depth.into();
+        self
+    }
+
+    /// Leave out entries matching a glob pattern; may be given repeatedly
+    pub fn exclude_glob(mut self, pattern: impl Into<String>) -> Self {
+        self.options.exclude_globs.push(pattern.into());
+        self
+    }
+
+    /// Leave out the files of an output written while scanning; may be
+    /// given repeatedly
+    pub fn exclude_output(mut self, path: impl Into<PathBuf>) -> Self {
+        self.options.exclude_outputs.push(path.into());
+        self
+    }
+
+    /// Leave out files smaller than this many bytes, or None to keep all
+    pub fn min_size(mut self, bytes: impl Into<Option<u64>>) -> Self {
+        self.options.min_size = bytes.into();
+        self
+    }
+
+    /// Enable checkpointing to the given file
+    pub fn checkpoint(mut self, path: impl Into<String>) -> Self {
+        self.options.enable_checkpointing = true;
+        self.options.checkpoint_path = Some(path.into());
+        self
+    }
+
+    /// Check the settings and return the options
+    pub fn build(self) -> crate::error::Result<ScanOptions> {
+        use crate::error::Error;
+
+        let options = self.options;
+        if options.num_threads == 0 {
+            return Err(Error::invalid_option("threads", "must be greater than 0"));
+        }
+        if options.batch_size == 0 {
+            return Err(Error::invalid_option("batch_size", "must be greater than 0"));
+        }
+        if options.channel_capacity_entries == 0 {
+            return Err(Error::invalid_option("channel_capacity_entries", "must be greater than 0"));
+        }
+        for pattern in &options.exclude_globs {
+            glob::Pattern::new(pattern)
+                .map_err(|e| Error::invalid_option("exclude_glob", format!("{:?} does not parse: {}", pattern, e)))?;
+            if pattern.chars().all(|c| c == '*' || c == '/') {
+                return Err(Error::invalid_option("exclude_glob", format!("{:?} leaves nothing to scan", pattern)));
+            }
+        }
+        if options.min_size.is_some() && options.max_depth == Some(0) {
+            return Err(Error::invalid_option("min_size", "has no effect with max_depth 0, which scans no files"));
+        }
+        Ok(options)
+    }
+}
+
+/// Parquet key-value metadata key holding a chunk's scan identifier
+pub const SCAN_ID_KEY: &str = "scan_id";
+
+/// Parquet key-value metadata key holding a chunk's scan date (YYYY-MM-DD)
+pub const SCAN_DATE_KEY: &str = "scan_date";
+
+/// Identifies which scan a chunk (and its rows) came from
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ScanTag {
+    pub scan_id: String,
+
+    /// Scan date as YYYY-MM-DD (UTC), if known
+    pub scan_date: Option<String>,
+}
+
+impl ScanTag {
+    /// Tag for a scan started at `scan_start` (Unix seconds): the start time
+    /// in RFC 3339 UTC and its date
+    pub fn from_scan_start(scan_start: i64) -> Self {
+        let start = std::time::UNIX_EPOCH + std::time::Duration::from_secs(scan_start.max(0) as u64);
+        let scan_id = humantime::format_rfc3339_seconds(start).to_string();
+        let scan_date = Some(scan_id[..10].to_string());
+        Self { scan_id, scan_date }
+    }
+
+    /// Tag from a user-supplied identifier; a YYYY-MM-DD identifier is also the date
+    pub fn from_id(scan_id: &str) -> Self {
+        Self {
+            scan_id: scan_id.to_string(),
+            scan_date: is_iso_date(scan_id).then(|| scan_id.to_string()),
+        }
+    }
+
+    /// Key-value pairs stored in a chunk's Parquet footer
+    pub fn to_metadata(&self) -> Vec<(String, String)> {
+        let mut metadata = vec![(SCAN_ID_KEY.to_string(), self.scan_id.clone())];
+        if let Some(ref date) = self.scan_date {
+            metadata.push((SCAN_DATE_KEY.to_string(), date.clone()));
+        }
+        metadata
+    }
+
+    /// Read a tag back from Parquet footer key-value pairs
+    pub fn from_metadata<'a>(pairs: impl IntoIterator<Item = (&'a str, Option<&'a str>)>) -> Option<Self> {
+        let mut scan_id = None;
+        let mut scan_date = None;
+        for (key, value) in pairs {
+            match key {
+                SCAN_ID_KEY => scan_id = value,
+                SCAN_DATE_KEY => scan_date = value.filter(|v| is_iso_date(v)),
+                _ => {}
+            }
+        }
+        Some(Self {
+            scan_id: scan_id?.to_string(),
+            scan_date: scan_date.map(str::to_string),
+        })
+    }
+}
+
+/// Whether a string is a YYYY-MM-DD date
+fn is_iso_date(s: &str) -> bool {
+    let b = s.as_bytes();
+    b.len() == 10
+        && b[4] == b'-'
+        && b[7] == b'-'
+        && b.iter().enumerate().all(|(i, c)| i == 4 || i == 7 || c.is_ascii_digit())
+        && matches!(s[5..7].parse::<u8>(), Ok(1..=12))
+        && matches!(s[8..10].parse::<u8>(), Ok(1..=31))
+}
+
+/// Maximum number of failing paths kept as samples in an ErrorSummary
+pub const ERROR_SAMPLE_LIMIT: usize = 100;
+
+/// A single failing path kept as an example in an ErrorSummary
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ErrorSample {
+    /// Path that failed (empty if the error carried no path)
+    pub path: String,
+
+    /// Error kind (e.g. EACCES, ENOENT, ELOOP)
+    pub kind: String,
+
+    /// Full error message
+    pub message: String,
+}
+
+/// Summary of the per-path errors encountered during a scan
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ErrorSummary {
+    /// Total number of errors
+    pub total: u64,
+
+    /// Error counts by kind
+    pub by_kind: BTreeMap<String, u64>,
+
+    /// The first failing paths, bounded by ERROR_SAMPLE_LIMIT
+    pub samples: Vec<ErrorSample>,
+}
+
+impl ErrorSummary {
+    /// Record one error
+    pub fn record(&mut self, kind: &str, path: &str, message: String) {
+        self.total += 1;
+        *self.by_kind.entry(kind.to_string()).or_insert(0) += 1;
+
+        if self.samples.len() < ERROR_SAMPLE_LIMIT {
+            self.samples.push(ErrorSample {
+                path: path.to_string(),
+                kind: kind.to_string(),
+                message,
+            });
+        }
+    }
+
+    /// Add another summary's counts and samples to this one
+    pub fn merge(&mut self, other: &ErrorSummary) {
+        self.total += other.total;
+        for (kind, count) in &other.by_kind {
+            *self.by_kind.entry(kind.clone()).or_insert(0) += count;
+        }
+
+        let room = ERROR_SAMPLE_LIMIT.saturating_sub(self.samples.len());
+        self.samples.extend(other.samples.iter().take(room).cloned());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total == 0
+    }
+}
+
+/// Classify an I/O error into a short kind label (errno name where possible)
+pub fn classify_io_error(err: &std::io::Error) -> &'static str {
+    match err.raw_os_error() {
+        Some(libc::EACCES) | Some(libc::EPERM) => "EACCES",
+        Some(libc::ENOENT) => "ENOENT",
+        Some(libc::ELOOP) => "ELOOP",
+        Some(libc::ENOTDIR) => "ENOTDIR",
+        Some(libc::EIO) => "EIO",
+        Some(libc::ESTALE) => "ESTALE",
+        Some(libc::ETIMEDOUT) => "timeout",
+        _ => match err.kind() {
+            std::io::ErrorKind::PermissionDenied => "EACCES",
+            std::io::ErrorKind::NotFound => "ENOENT",
+            std::io::ErrorKind::TimedOut => "timeout",
+            _ => "other",
+        },
+    }
+}
+
 /// Statistics about a completed scan
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ScanStats {
     /// Total number of files scanned
     pub files_scanned: u64,
@@ -228,12 +648,36 @@ pub struct ScanStats {
     /// Total number of directories scanned
     pub directories_scanned: u64,
 
-    /// Total size of all files in bytes
+    /// Symbolic links recorded without being followed. They are not counted
+    /// as files, and their targets' sizes are not in `total_size`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub symlinks_scanned: u64,
+
+    /// Apparent size of all files in bytes: a file with several hard links
+    /// in the tree is counted once per link
     pub total_size: u64,
 
+    /// Size of all files in bytes, counting each hard-linked inode once
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub unique_total_size: u64,
+
     /// Number of errors encountered
     pub errors_encountered: u64,
 
+    /// Breakdown and samples of the errors encountered
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub error_summary: ErrorSummary,
+
+    /// Entries left out because a resumed scan had already written their
+    /// top-level directory
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub entries_skipped: u64,
+
+    /// Entries removed or renamed between their directory being listed and
+    /// their metadata being read. They are not counted as errors.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub entries_vanished: u64,
+
     /// Duration of scan in seconds
     pub duration_secs: f64,
 
@@ -242,6 +686,10 @@ pub struct ScanStats {
 
     /// Scan end time (Unix timestamp)
     pub end_time: i64,
+
+    /// Monotonic start instant used to measure the duration
+    #[cfg_attr(feature = "serde", serde(skip))]
+    started_at: Option<std::time::Instant>,
 }
 
 impl ScanStats {
@@ -253,6 +701,7 @@ impl ScanStats {
             .as_secs() as i64;
         Self {
             start_time: now,
+            started_at: Some(std::time::Instant::now()),
             ..Default::default()
         }
     }
@@ -264,7 +713,12 @@ impl ScanStats {
             .unwrap()
             .as_secs() as i64;
         self.end_time = now;
-        self.duration_secs = (self.end_time - self.start_time) as f64;
+        // Stats read back from JSON have no instant; their wall-clock stamps
+        // may have been stepped backwards in between
+        self.duration_secs = match self.started_at {
+            Some(started_at) => started_at.elapsed().as_secs_f64(),
+            None => (self.end_time - self.start_time).max(0) as f64,
+        };
     }
 
     pub fn files_per_second(&self) -> f64 {
@@ -297,20 +751,133 @@ mod tests {
         assert_eq!(entry.depth, 1);
     }
 
+    #[test]
+    fn test_fixture_fields_follow_the_path() {
+        let file = FileEntry::fixture("/project/lab/runs/a.dat");
+        assert_eq!(file.parent_path, "/project/lab/runs");
+        assert_eq!(file.top_level_dir, "lab");
+        assert_eq!(file.depth, 3);
+        assert_eq!(file.file_type, "dat");
+
+        let dir = FileEntry::dir_fixture("/project/lab");
+        assert_eq!((dir.size, dir.file_type.as_str(), dir.permissions, dir.depth), (0, "directory", 0o755, 1));
+        assert_eq!(FileEntry::fixture("/project/README").file_type, "no_extension");
+    }
+
+    #[test]
+    fn test_permissions_are_only_the_permission_bits() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("data.csv");
+        fs::write(&file_path, "a,b").unwrap();
+        fs::set_permissions(&file_path, fs::Permissions::from_mode(0o644)).unwrap();
+        let dir_path = temp_dir.path().join("results");
+        fs::create_dir(&dir_path).unwrap();
+        fs::set_permissions(&dir_path, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let file = FileEntry::from_path(&file_path, &fs::metadata(&file_path).unwrap(), temp_dir.path()).unwrap();
+        assert_eq!(file.permissions, 0o644);
+        assert_eq!(file.raw_mode, libc::S_IFREG | 0o644);
+
+        let dir = FileEntry::from_path(&dir_path, &fs::metadata(&dir_path).unwrap(), temp_dir.path()).unwrap();
+        assert_eq!(dir.permissions, 0o755);
+        assert_eq!(dir.permissions & libc::S_IFDIR, 0);
+        assert_eq!(dir.raw_mode, libc::S_IFDIR | 0o755);
+    }
+
+    #[test]
+    fn test_root_files_share_a_resume_unit() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("lab");
+        fs::create_dir_all(root.join("lab")).unwrap();
+        fs::create_dir(root.join("data")).unwrap();
+        fs::write(root.join("data.csv"), "a,b").unwrap();
+        fs::write(root.join("data/run.csv"), "a,b").unwrap();
+
+        let unit = |path: &Path| {
+            let entry = FileEntry::from_path(path, &fs::symlink_metadata(path).unwrap(), &root).unwrap();
+            entry.resume_unit().to_string()
+        };
+        assert_eq!(unit(&root), ROOT_FILES_UNIT);
+        assert_eq!(unit(&root.join("data.csv")), ROOT_FILES_UNIT);
+        assert_eq!(unit(&root.join("data")), "data");
+        assert_eq!(unit(&root.join("data/run.csv")), "data");
+
+        // A directory named after the root is still its own unit
+        assert_eq!(unit(&root.join("lab")), "lab");
+    }
+
     #[test]
     fn test_scan_stats() {
         let mut stats = ScanStats::new();
-        std::thread::sleep(std::time::Duration::from_millis(200));
+        std::thread::sleep(std::time::Duration::from_millis(50));
         stats.files_scanned = 1000;
         stats.finish();
 
-        // Duration should be at least some time (may be low resolution on some systems)
-        assert!(stats.duration_secs >= 0.0, "Duration was: {}", stats.duration_secs);
+        assert!(stats.duration_secs >= 0.05, "Duration was: {}", stats.duration_secs);
+        assert!(stats.duration_secs < 60.0, "Duration was: {}", stats.duration_secs);
+        assert!(stats.files_per_second() > 0.0);
+        assert!(stats.end_time >= stats.start_time);
+    }
+
+    #[test]
+    fn test_duration_without_an_instant_is_never_negative() {
+        let mut stats = ScanStats { start_time: i64::MAX / 2, files_scanned: 10, ..Default::default() };
+        stats.finish();
+        assert_eq!(stats.duration_secs, 0.0);
+        assert_eq!(stats.files_per_second(), 0.0);
+    }
 
-        // If duration is > 0, files_per_second should work
-        if stats.duration_secs > 0.0 {
-            assert!(stats.files_per_second() > 0.0);
+    #[test]
+    fn test_error_summary_record_and_merge() {
+        let mut summary = ErrorSummary::default();
+        for i in 0..ERROR_SAMPLE_LIMIT + 5 {
+            summary.record("EACCES", &format!("/locked/{}", i), "Permission denied".to_string());
         }
+        summary.record("ENOENT", "/gone", "No such file".to_string());
+
+        assert_eq!(summary.total, ERROR_SAMPLE_LIMIT as u64 + 6);
+        assert_eq!(summary.by_kind["EACCES"], ERROR_SAMPLE_LIMIT as u64 + 5);
+        assert_eq!(summary.samples.len(), ERROR_SAMPLE_LIMIT);
+
+        let mut previous = ErrorSummary::default();
+        previous.record("ELOOP", "/loop", "Too many levels of symbolic links".to_string());
+        previous.merge(&summary);
+
+        assert_eq!(previous.total, summary.total + 1);
+        assert_eq!(previous.by_kind["ELOOP"], 1);
+        assert_eq!(previous.by_kind["ENOENT"], 1);
+        assert_eq!(previous.samples[0].path, "/loop");
+        assert_eq!(previous.samples.len(), ERROR_SAMPLE_LIMIT);
+    }
+
+    #[test]
+    fn test_classify_io_error() {
+        let denied = std::io::Error::from_raw_os_error(libc::EACCES);
+        assert_eq!(classify_io_error(&denied), "EACCES");
+
+        let looped = std::io::Error::from_raw_os_error(libc::ELOOP);
+        assert_eq!(classify_io_error(&looped), "ELOOP");
+
+        let missing = std::io::Error::new(std::io::ErrorKind::NotFound, "gone");
+        assert_eq!(classify_io_error(&missing), "ENOENT");
+    }
+
+    #[test]
+    fn test_scan_tag() {
+        let tag = ScanTag::from_scan_start(1_716_170_400);
+        assert_eq!(tag.scan_id, "2024-05-20T02:00:00Z");
+        assert_eq!(tag.scan_date.as_deref(), Some("2024-05-20"));
+
+        let metadata = tag.to_metadata();
+        let read = ScanTag::from_metadata(metadata.iter().map(|(k, v)| (k.as_str(), Some(v.as_str()))));
+        assert_eq!(read, Some(tag));
+        assert_eq!(ScanTag::from_metadata([("other", Some("x"))]), None);
+
+        assert_eq!(ScanTag::from_id("2024-05-20").scan_date.as_deref(), Some("2024-05-20"));
+        assert_eq!(ScanTag::from_id("nightly-42").scan_date, None);
+        assert_eq!(ScanTag::from_id("2024-13-01").scan_date, None);
     }
 
     #[test]
@@ -319,5 +886,71 @@ mod tests {
         assert!(!options.follow_symlinks);
         assert_eq!(options.max_depth, None);
         assert!(options.batch_size > 0);
+        assert_eq!(ScanOptions::builder().build().unwrap(), options);
+    }
+
+    #[test]
+    fn test_scan_options_builder() {
+        let built = ScanOptions::builder()
+            .threads(4)
+            .batch_size(500)
+            .channel_capacity_entries(2000)
+            .follow_symlinks(true)
+            .max_depth(3)
+            .exclude_glob(".snapshot")
+            .exclude_glob("scratch/**/tmp")
+            .exclude_output("/scratch/scan.parquet")
+            .min_size(1024)
+            .checkpoint("scan.ckpt")
+            .build()
+            .unwrap();
+
+        let expected = ScanOptions {
+            num_threads: 4,
+            batch_size: 500,
+            channel_capacity_entries: 2000,
+            follow_symlinks: true,
+            max_depth: Some(3),
+            exclude_globs: vec![".snapshot".to_string(), "scratch/**/tmp".to_string()],
+            exclude_outputs: vec![PathBuf::from("/scratch/scan.parquet")],
+            min_size: Some(1024),
+            enable_checkpointing: true,
+            checkpoint_path: Some("scan.ckpt".to_string()),
+        };
+        assert_eq!(built, expected);
+
+        // Later calls override earlier ones
+        let built = ScanOptions::builder().max_depth(3).max_depth(None).build().unwrap();
+        assert_eq!(built.max_depth, None);
+    }
+
+    #[test]
+    fn test_queue_capacities_do_not_grow_with_batch_size() {
+        let options = ScanOptions::builder().batch_size(500).channel_capacity_entries(2000).build().unwrap();
+        assert_eq!(options.batch_channel_capacity(), 4);
+        assert_eq!(options.max_queued_entries(), 2000 + 6 * 500);
+
+        // Batches larger than the capacity still get one slot
+        let defaults = ScanOptions::default();
+        assert_eq!(defaults.batch_channel_capacity(), 1);
+        assert_eq!(defaults.max_queued_entries(), DEFAULT_CHANNEL_CAPACITY_ENTRIES + 3 * 100_000);
+        assert!(defaults.queue_memory_estimate() < 1 << 30);
+    }
+
+    #[test]
+    fn test_scan_options_builder_validates() {
+        let field = |builder: ScanOptionsBuilder| match builder.build() {
+            Err(crate::error::Error::InvalidOptions { field, reason }) => (field, reason),
+            other => panic!("expected InvalidOptions, got {:?}", other),
+        };
+
+        assert_eq!(field(ScanOptions::builder().threads(0)).0, "threads");
+        assert_eq!(field(ScanOptions::builder().batch_size(0)).0, "batch_size");
+        assert_eq!(field(ScanOptions::builder().channel_capacity_entries(0)).0, "channel_capacity_entries");
+        let (name, reason) = field(ScanOptions::builder().exclude_glob("[a-"));
+        assert_eq!(name, "exclude_glob");
+        assert!(reason.contains("does not parse"));
+        assert!(field(ScanOptions::builder().exclude_glob("**")).1.contains("leaves nothing"));
+        assert_eq!(field(ScanOptions::builder().max_depth(0).min_size(1)).0, "min_size");
     }
 }
@@ -0,0 +1,238 @@
+use crate::status::{DirProgress, RecentError, ScanState, ScanStatus};
+use std::collections::VecDeque;
+
+/// Throughput samples kept for the sparkline
+pub const HISTORY: usize = 120;
+
+/// A running scan whose status file is older than this is shown as stalled
+pub const STALE_SECS: i64 = 10;
+
+/// Smallest terminal that gets the full dashboard; anything smaller shows a
+/// one-line summary
+pub const MIN_WIDTH: u16 = 40;
+pub const MIN_HEIGHT: u16 = 10;
+
+/// Lines of the summary panel: counts, rate and ETA, chunk, plus borders
+const SUMMARY_HEIGHT: u16 = 5;
+
+/// What `monitor` shows, built from successive status snapshots
+#[derive(Debug, Default)]
+pub struct MonitorState {
+    status: Option<ScanStatus>,
+
+    /// Entries per second between consecutive snapshots, oldest first
+    throughput: VecDeque<u64>,
+
+    /// Why the status file could not be read last time, if it couldn't
+    load_error: Option<String>,
+}
+
+impl MonitorState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take in a freshly read snapshot. Snapshots the state already has are
+    /// ignored; a snapshot of a different scan starts the history over.
+    pub fn update(&mut self, status: ScanStatus) {
+        self.load_error = None;
+        if let Some(ref previous) = self.status {
+            let same_scan = previous.pid == status.pid && previous.started_at == status.started_at;
+            if !same_scan || status.elapsed_secs < previous.elapsed_secs {
+                self.throughput.clear();
+            } else if status.elapsed_secs > previous.elapsed_secs {
+                let seconds = status.elapsed_secs - previous.elapsed_secs;
+                let entries = status.entries().saturating_sub(previous.entries());
+                if self.throughput.len() == HISTORY {
+                    self.throughput.pop_front();
+                }
+                self.throughput.push_back((entries as f64 / seconds).round() as u64);
+            } else {
+                return;
+            }
+        }
+        self.status = Some(status);
+    }
+
+    /// Note that the status file could not be read; the last snapshot stays
+    pub fn load_failed(&mut self, message: String) {
+        self.load_error = Some(message);
+    }
+
+    pub fn status(&self) -> Option<&ScanStatus> {
+        self.status.as_ref()
+    }
+
+    pub fn load_error(&self) -> Option<&str> {
+        self.load_error.as_deref()
+    }
+
+    pub fn throughput(&self) -> Vec<u64> {
+        self.throughput.iter().copied().collect()
+    }
+
+    /// Whether a scan that claims to be running stopped updating its status
+    pub fn is_stale(&self, now: i64) -> bool {
+        self.status.as_ref()
+            .is_some_and(|s| s.state == ScanState::Running && now - s.updated_at > STALE_SECS)
+    }
+
+    /// "running", "completed", "failed", or "stalled"
+    pub fn state_label(&self, now: i64) -> &'static str {
+        match self.status.as_ref().map(|s| s.state) {
+            _ if self.is_stale(now) => "stalled",
+            Some(ScanState::Running) => "running",
+            Some(ScanState::Completed) => "completed",
+            Some(ScanState::Failed) => "failed",
+            None => "waiting",
+        }
+    }
+
+    /// Up to `limit` top-level directories, those with the most entries first
+    pub fn busiest_dirs(&self, limit: usize) -> Vec<&DirProgress> {
+        let Some(ref status) = self.status else { return Vec::new() };
+        let mut dirs: Vec<&DirProgress> = status.top_level_dirs.iter().collect();
+        dirs.sort_by(|a, b| b.entries.cmp(&a.entries).then_with(|| a.name.cmp(&b.name)));
+        dirs.truncate(limit);
+        dirs
+    }
+
+    /// Up to `limit` of the latest errors, newest first
+    pub fn latest_errors(&self, limit: usize) -> Vec<&RecentError> {
+        let Some(ref status) = self.status else { return Vec::new() };
+        status.recent_errors.iter().rev().take(limit).collect()
+    }
+}
+
+/// Heights of the dashboard's panels, top to bottom. Panels that don't fit
+/// get 0 and are left out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MonitorLayout {
+    /// The terminal is too small for panels; show a single summary line
+    pub compact: bool,
+
+    pub summary: u16,
+
+    pub throughput: u16,
+
+    pub dirs: u16,
+
+    pub errors: u16,
+}
+
+impl MonitorLayout {
+    /// Share a `width` x `height` terminal among the panels: the summary
+    /// first, then directories, then throughput and errors once there is room
+    pub fn for_size(width: u16, height: u16) -> Self {
+        if width < MIN_WIDTH || height < MIN_HEIGHT {
+            return Self { compact: true, summary: 0, throughput: 0, dirs: 0, errors: 0 };
+        }
+
+        // One line for the key help at the bottom
+        let mut rest = height - SUMMARY_HEIGHT - 1;
+        let throughput = if rest >= 15 { 6 } else { 0 };
+        rest -= throughput;
+        let errors = if rest >= 14 { (rest / 3).min(12) } else { 0 };
+        rest -= errors;
+
+        Self { compact: false, summary: SUMMARY_HEIGHT, throughput, dirs: rest, errors }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(pid: u32, elapsed_secs: f64, files: u64) -> ScanStatus {
+        ScanStatus {
+            scan_path: "/project".to_string(),
+            pid,
+            state: ScanState::Running,
+            started_at: 1_700_000_000,
+            updated_at: 1_700_000_000 + elapsed_secs as i64,
+            elapsed_secs,
+            files,
+            directories: 0,
+            symlinks: 0,
+            bytes: 0,
+            errors: 0,
+            skipped: 0,
+            entries_per_sec: None,
+            percent: None,
+            eta_secs: None,
+            current_chunk: None,
+            top_level_dirs: Vec::new(),
+            recent_errors: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_throughput_from_snapshots() {
+        let mut state = MonitorState::new();
+        state.update(status(1, 1.0, 100));
+        assert!(state.throughput().is_empty());
+
+        state.update(status(1, 2.0, 1100));
+        state.update(status(1, 4.0, 2100));
+        // The same snapshot read twice adds nothing
+        state.update(status(1, 4.0, 2100));
+        assert_eq!(state.throughput(), [1000, 500]);
+
+        // A restarted scan starts the history over
+        state.update(status(2, 1.0, 10));
+        assert!(state.throughput().is_empty());
+        assert_eq!(state.status().unwrap().pid, 2);
+
+        for i in 0..HISTORY as u64 + 5 {
+            state.update(status(2, 2.0 + i as f64, 10 + i * 10));
+        }
+        assert_eq!(state.throughput().len(), HISTORY);
+    }
+
+    #[test]
+    fn test_state_and_panels() {
+        let mut state = MonitorState::new();
+        assert_eq!(state.state_label(0), "waiting");
+
+        let mut snapshot = status(1, 5.0, 10);
+        snapshot.top_level_dirs = ["a", "b", "c"].iter().zip([5, 50, 20])
+            .map(|(name, entries)| DirProgress { name: name.to_string(), entries, baseline_entries: None, percent: None })
+            .collect();
+        snapshot.recent_errors = (0..3)
+            .map(|i| RecentError { time: 0, kind: "EACCES".to_string(), path: format!("/p{}", i), message: String::new() })
+            .collect();
+        let updated_at = snapshot.updated_at;
+        state.update(snapshot);
+
+        assert_eq!(state.state_label(updated_at + 1), "running");
+        assert_eq!(state.state_label(updated_at + STALE_SECS + 1), "stalled");
+        let dirs: Vec<&str> = state.busiest_dirs(2).iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(dirs, ["b", "c"]);
+        let errors: Vec<&str> = state.latest_errors(2).iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(errors, ["/p2", "/p1"]);
+
+        // A failed read keeps the last snapshot until the next good one
+        state.load_failed("missing".to_string());
+        assert_eq!(state.load_error(), Some("missing"));
+        assert!(state.status().is_some());
+
+        let mut done = status(1, 6.0, 20);
+        done.state = ScanState::Completed;
+        state.update(done);
+        assert_eq!(state.load_error(), None);
+        assert_eq!(state.state_label(updated_at + STALE_SECS + 1), "completed");
+    }
+
+    #[test]
+    fn test_layout_degrades_on_small_terminals() {
+        assert!(MonitorLayout::for_size(30, 40).compact);
+        assert!(MonitorLayout::for_size(120, 9).compact);
+
+        let small = MonitorLayout::for_size(80, 12);
+        assert_eq!((small.summary, small.throughput, small.dirs, small.errors), (5, 0, 6, 0));
+
+        let large = MonitorLayout::for_size(120, 50);
+        assert_eq!((large.throughput, large.errors), (6, 12));
+        assert_eq!(large.summary + large.throughput + large.dirs + large.errors + 1, 50);
+    }
+}
@@ -0,0 +1,315 @@
+use crate::manifest::DirStats;
+use crate::models::{ErrorSummary, ScanStats, ScanTag};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+/// Sends a notification wherever the scan's notifications go
+pub type Notifier = Box<dyn Fn(&ScanNotification) + Send>;
+
+/// Notification sent by the signal handler if the scan is interrupted
+static ON_INTERRUPT: Mutex<Option<(Notifier, ScanNotification)>> = Mutex::new(None);
+
+/// Where and how scan notifications are posted
+#[derive(Debug, Clone)]
+pub struct WebhookOptions {
+    pub url: String,
+
+    /// Shared secret the body is signed with (HMAC-SHA256)
+    pub secret: Option<String>,
+
+    /// Limit on each request, connecting included
+    pub timeout: Duration,
+
+    /// Further attempts after a failed request, one second apart and doubling
+    pub retries: u32,
+}
+
+/// How the scan ended
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ScanOutcome {
+    Completed,
+    Failed,
+    /// Stopped by SIGINT or SIGTERM
+    Interrupted,
+}
+
+/// JSON body posted when a scan ends
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ScanNotification {
+    pub status: ScanOutcome,
+
+    /// Scan start time in RFC 3339 UTC, as chunks are tagged
+    pub scan_id: String,
+
+    /// Scanned path
+    pub root: String,
+
+    /// Scan start time (Unix timestamp)
+    pub started_at: i64,
+
+    pub duration_secs: f64,
+
+    pub files: u64,
+
+    pub directories: u64,
+
+    pub bytes: u64,
+
+    /// Rows written to the output
+    pub rows: u64,
+
+    /// Whether the scan continued an interrupted one (`--resume`)
+    #[serde(default)]
+    pub resumed: bool,
+
+    /// Per-path errors the scan ran into
+    pub errors: ErrorSummary,
+
+    /// Output files: the chunks of an incremental scan, or the single Parquet file
+    pub outputs: Vec<String>,
+
+    /// Manifest path, or its URL when it was uploaded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manifest: Option<String>,
+
+    /// Why the scan did not complete
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    /// Rows and bytes per top-level directory, when the scan kept them
+    /// (incremental scans); shown in email reports, not posted
+    #[serde(skip)]
+    pub dir_stats: BTreeMap<String, DirStats>,
+}
+
+impl ScanNotification {
+    /// A scan of `root` that finished, started at `scan_start` (the
+    /// manifest's on resume, so the id matches the chunks')
+    pub fn completed(root: String, scan_start: i64, stats: &ScanStats, rows: u64) -> Self {
+        Self {
+            status: ScanOutcome::Completed,
+            scan_id: ScanTag::from_scan_start(scan_start).scan_id,
+            root,
+            started_at: scan_start,
+            duration_secs: stats.duration_secs,
+            files: stats.files_scanned,
+            directories: stats.directories_scanned,
+            bytes: stats.total_size,
+            rows,
+            resumed: false,
+            errors: stats.error_summary.clone(),
+            outputs: Vec::new(),
+            manifest: None,
+            error: None,
+            dir_stats: BTreeMap::new(),
+        }
+    }
+
+    /// A scan of `root` started at `started_at` that ended with `error`
+    pub fn failed(root: String, started_at: i64, status: ScanOutcome, error: String) -> Self {
+        Self {
+            status,
+            scan_id: ScanTag::from_scan_start(started_at).scan_id,
+            root,
+            started_at,
+            duration_secs: (now() - started_at).max(0) as f64,
+            files: 0,
+            directories: 0,
+            bytes: 0,
+            rows: 0,
+            resumed: false,
+            errors: ErrorSummary::default(),
+            outputs: Vec::new(),
+            manifest: None,
+            error: Some(error),
+            dir_stats: BTreeMap::new(),
+        }
+    }
+
+    /// Event name sent in the `X-Storage-Scanner-Event` header
+    pub fn event(&self) -> &'static str {
+        match self.status {
+            ScanOutcome::Completed => "scan.completed",
+            ScanOutcome::Failed => "scan.failed",
+            ScanOutcome::Interrupted => "scan.interrupted",
+        }
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+}
+
+/// `X-Storage-Scanner-Signature` value for `body`: `sha256=` and the hex
+/// HMAC-SHA256 of the body under `secret`
+#[cfg(feature = "remote")]
+pub fn signature(secret: &str, body: &[u8]) -> String {
+    use hmac::{Hmac, Mac};
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC takes keys of any length");
+    mac.update(body);
+    let digest = mac.finalize().into_bytes();
+    format!("sha256={}", digest.iter().map(|b| format!("{:02x}", b)).collect::<String>())
+}
+
+/// POST `body` as JSON with `event` in the `X-Storage-Scanner-Event` header,
+/// retrying failed requests
+#[cfg(feature = "remote")]
+pub fn post_json(options: &WebhookOptions, event: &str, body: &impl Serialize) -> Result<()> {
+    use anyhow::Context;
+
+    let body = serde_json::to_vec(body)?;
+    let client = reqwest::blocking::Client::builder()
+        .user_agent(concat!("storage-scanner/", env!("CARGO_PKG_VERSION")))
+        .timeout(options.timeout)
+        .build()
+        .context("Failed to build HTTP client")?;
+    let send = || -> Result<()> {
+        let mut request = client.post(&options.url)
+            .header("Content-Type", "application/json")
+            .header("X-Storage-Scanner-Event", event)
+            .body(body.clone());
+        if let Some(ref secret) = options.secret {
+            request = request.header("X-Storage-Scanner-Signature", signature(secret, &body));
+        }
+        request.send()
+            .and_then(|r| r.error_for_status())
+            .with_context(|| format!("Failed to notify {}", options.url))?;
+        Ok(())
+    };
+
+    let mut backoff = Duration::from_secs(1);
+    let mut result = send();
+    for _ in 0..options.retries {
+        let Err(ref e) = result else { break };
+        warn!("{:#}; retrying in {:?}", e, backoff);
+        std::thread::sleep(backoff);
+        backoff *= 2;
+        result = send();
+    }
+    result
+}
+
+#[cfg(not(feature = "remote"))]
+pub fn post_json(_options: &WebhookOptions, _event: &str, _body: &impl Serialize) -> Result<()> {
+    anyhow::bail!("Webhook notifications require building with --features remote")
+}
+
+/// Post `notification`; a notification that can't be delivered is logged,
+/// never allowed to change how the scan ends
+pub fn notify(options: &WebhookOptions, notification: &ScanNotification) {
+    match post_json(options, notification.event(), notification) {
+        Ok(()) => info!("Sent {} notification to {}", notification.event(), options.url),
+        Err(e) => warn!("Failed to send {} notification: {:#}", notification.event(), e),
+    }
+}
+
+/// Have [`notify_interrupted`] pass `notification` (an interrupted one, see
+/// [`ScanNotification::failed`]) to `send`, until [`clear_interrupt`] is called
+pub fn on_interrupt(notification: ScanNotification, send: Notifier) {
+    *ON_INTERRUPT.lock().unwrap() = Some((send, notification));
+}
+
+/// The scan ended by itself; an interrupt now has nothing to report
+pub fn clear_interrupt() {
+    ON_INTERRUPT.lock().unwrap().take();
+}
+
+/// Post the notification armed by [`on_interrupt`], if any; called from
+/// the signal handler, where destructors won't run
+pub fn notify_interrupted() {
+    let pending = ON_INTERRUPT.lock().map(|mut p| p.take()).unwrap_or(None);
+    if let Some((send, mut notification)) = pending {
+        notification.duration_secs = (now() - notification.started_at).max(0) as f64;
+        send(&notification);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_completed_payload() {
+        let mut stats = ScanStats::new();
+        stats.files_scanned = 10;
+        stats.directories_scanned = 2;
+        stats.total_size = 4096;
+        stats.duration_secs = 1.5;
+        stats.error_summary.record("permission_denied", "/data/locked", "denied".to_string());
+
+        let notification = ScanNotification::completed("/data".to_string(), 1716170400, &stats, 12);
+        let json = serde_json::to_value(&notification).unwrap();
+        assert_eq!(json["status"], "completed");
+        assert_eq!(json["scan_id"], "2024-05-20T02:00:00Z");
+        assert_eq!(json["files"], 10);
+        assert_eq!(json["rows"], 12);
+        assert_eq!(json["errors"]["by_kind"]["permission_denied"], 1);
+        assert!(json.get("error").is_none());
+        assert_eq!(notification.event(), "scan.completed");
+    }
+
+    #[test]
+    fn test_failed_payload() {
+        let notification = ScanNotification::failed(
+            "/data".to_string(), now() - 5, ScanOutcome::Failed, "Scan failed: disk full".to_string(),
+        );
+        let json = serde_json::to_value(&notification).unwrap();
+        assert_eq!(json["status"], "failed");
+        assert_eq!(json["error"], "Scan failed: disk full");
+        assert!(notification.duration_secs >= 5.0);
+        assert_eq!(notification.event(), "scan.failed");
+    }
+
+    #[cfg(feature = "remote")]
+    #[test]
+    fn test_signature() {
+        assert_eq!(
+            signature("key", b"The quick brown fox jumps over the lazy dog"),
+            "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+
+    #[cfg(feature = "remote")]
+    #[test]
+    fn test_post_json_retries_and_signs() {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", server.server_addr().to_ip().unwrap());
+        let handle = std::thread::spawn(move || {
+            // Fail the first request, then capture the retry
+            let request = server.recv().unwrap();
+            request.respond(tiny_http::Response::empty(502)).unwrap();
+            let mut request = server.recv().unwrap();
+            let mut body = String::new();
+            request.as_reader().read_to_string(&mut body).unwrap();
+            let header = |name: &'static str| request.headers().iter()
+                .find(|h| h.field.equiv(name))
+                .map(|h| h.value.to_string());
+            let captured = (header("X-Storage-Scanner-Event"), header("X-Storage-Scanner-Signature"), body);
+            request.respond(tiny_http::Response::empty(204)).unwrap();
+            captured
+        });
+
+        let options = WebhookOptions {
+            url,
+            secret: Some("s3cret".to_string()),
+            timeout: Duration::from_secs(5),
+            retries: 1,
+        };
+        let notification = ScanNotification::failed(
+            "/data".to_string(), now(), ScanOutcome::Interrupted, "Interrupted by signal".to_string(),
+        );
+        post_json(&options, notification.event(), &notification).unwrap();
+
+        let (event, signed, body) = handle.join().unwrap();
+        assert_eq!(event.as_deref(), Some("scan.interrupted"));
+        assert_eq!(signed, Some(signature("s3cret", body.as_bytes())));
+        let received: ScanNotification = serde_json::from_str(&body).unwrap();
+        assert_eq!(received, notification);
+    }
+}
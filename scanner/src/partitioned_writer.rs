@@ -0,0 +1,345 @@
+use crate::writer::schema_version_entry;
+use anyhow::{Context, Result};
+use arrow::array::{ArrayRef, StringArray, UInt32Array};
+use arrow::compute::{cast, take};
+use arrow::datatypes::{DataType, SchemaRef};
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use parquet::file::properties::WriterProperties;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+/// Name of the dataset manifest in the dataset root; the leading underscore
+/// keeps dataset readers from mistaking it for data
+pub const DATASET_MANIFEST: &str = "_manifest.json";
+
+/// Partition directories with Parquet files open at once; the least recently
+/// written one is closed beyond this and a later row starts a new part file
+pub const DEFAULT_MAX_OPEN_FILES: usize = 64;
+
+/// Directory value for rows whose partition column is null (Hive's convention)
+pub const NULL_PARTITION: &str = "__HIVE_DEFAULT_PARTITION__";
+
+/// One Parquet file of a partitioned dataset
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DatasetFile {
+    /// Path relative to the dataset root
+    pub path: String,
+
+    pub rows: u64,
+
+    pub size_bytes: u64,
+}
+
+/// One partition directory of a dataset
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DatasetPartition {
+    /// Partition column value (None for nulls)
+    pub value: Option<String>,
+
+    /// Directory relative to the dataset root, e.g. `top_level_dir=home`
+    pub dir: String,
+
+    pub rows: u64,
+
+    pub files: Vec<DatasetFile>,
+}
+
+/// Describes a hive-partitioned dataset written by [`PartitionedParquetWriter`]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DatasetManifest {
+    /// Column the dataset is partitioned by
+    pub partition_by: String,
+
+    pub total_rows: u64,
+
+    /// Partitions sorted by directory name
+    pub partitions: Vec<DatasetPartition>,
+}
+
+impl DatasetManifest {
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let contents = fs::read_to_string(path.as_ref())
+            .context("Failed to read dataset manifest")?;
+        serde_json::from_str(&contents).context("Failed to parse dataset manifest JSON")
+    }
+
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)
+            .context("Failed to serialize dataset manifest")?;
+        fs::write(path.as_ref(), json).context("Failed to write dataset manifest")
+    }
+
+    /// Combined size of the dataset's Parquet files
+    pub fn size_bytes(&self) -> u64 {
+        self.partitions.iter().flat_map(|p| &p.files).map(|f| f.size_bytes).sum()
+    }
+}
+
+/// Escape a partition value for use as a directory name.
+///
+/// Bytes outside `[A-Za-z0-9._-]` are percent-encoded, as are a leading `.`
+/// or `_`: readers skip such "hidden" directories, and `.`/`..` would escape
+/// the dataset.
+pub fn escape_partition_value(value: &str) -> String {
+    if value.is_empty() {
+        return NULL_PARTITION.to_string();
+    }
+
+    let mut escaped = String::with_capacity(value.len());
+    for (i, byte) in value.bytes().enumerate() {
+        let plain = byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_');
+        if plain && !(i == 0 && matches!(byte, b'.' | b'_')) {
+            escaped.push(byte as char);
+        } else {
+            escaped.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    escaped
+}
+
+/// An open part file and the partition it belongs to
+struct OpenFile {
+    writer: ArrowWriter<File>,
+    file: DatasetFile,
+    last_used: u64,
+}
+
+/// Writes record batches into a hive-partitioned dataset:
+/// `<root>/<column>=<value>/part-NNNN.parquet`, plus a [`DATASET_MANIFEST`].
+///
+/// Files keep the partition column, so they read the same with or without
+/// hive partitioning.
+pub struct PartitionedParquetWriter {
+    root: PathBuf,
+    schema: SchemaRef,
+    column: String,
+    props: Option<WriterProperties>,
+    max_open_files: usize,
+    open: HashMap<Option<String>, OpenFile>,
+    partitions: BTreeMap<String, DatasetPartition>,
+    writes: u64,
+}
+
+impl PartitionedParquetWriter {
+    /// Create a writer for a dataset at `root`, which must not exist or be empty
+    pub fn new(root: &Path, schema: SchemaRef, column: &str, props: Option<WriterProperties>) -> Result<Self> {
+        schema.field_with_name(column)
+            .with_context(|| format!("Cannot partition by '{}': no such column", column))?;
+
+        if root.exists() && fs::read_dir(root)?.next().is_some() {
+            anyhow::bail!("Dataset directory {} is not empty", root.display());
+        }
+        fs::create_dir_all(root)
+            .with_context(|| format!("Failed to create dataset directory {}", root.display()))?;
+
+        Ok(Self {
+            root: root.to_path_buf(),
+            schema,
+            column: column.to_string(),
+            props,
+            max_open_files: DEFAULT_MAX_OPEN_FILES,
+            open: HashMap::new(),
+            partitions: BTreeMap::new(),
+            writes: 0,
+        })
+    }
+
+    /// Limit the number of part files open at once (at least 1)
+    pub fn with_max_open_files(mut self, max_open_files: usize) -> Self {
+        self.max_open_files = max_open_files.max(1);
+        self
+    }
+
+    /// Split a batch by partition value and append each part to its partition
+    pub fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        let values = cast(batch.column_by_name(&self.column).unwrap(), &DataType::Utf8)?;
+        let values = values.as_any().downcast_ref::<StringArray>().unwrap();
+
+        // Rows per value, in order of first appearance
+        let mut groups: Vec<(Option<&str>, Vec<u32>)> = Vec::new();
+        let mut group_of: HashMap<Option<&str>, usize> = HashMap::new();
+        for (i, value) in values.iter().enumerate() {
+            // Empty strings share the null partition's directory
+            let value = value.filter(|v| !v.is_empty());
+            let group = *group_of.entry(value).or_insert_with(|| {
+                groups.push((value, Vec::new()));
+                groups.len() - 1
+            });
+            groups[group].1.push(i as u32);
+        }
+
+        for (value, rows) in groups {
+            let part = if rows.len() == batch.num_rows() {
+                batch.clone()
+            } else {
+                let indices = UInt32Array::from(rows);
+                let columns = batch.columns().iter()
+                    .map(|c| Ok(take(c.as_ref(), &indices, None)?))
+                    .collect::<Result<Vec<ArrayRef>>>()?;
+                RecordBatch::try_new(self.schema.clone(), columns)?
+            };
+
+            let open = self.open_file(value.map(str::to_string))?;
+            open.writer.write(&part)?;
+            open.file.rows += part.num_rows() as u64;
+        }
+
+        Ok(())
+    }
+
+    /// The open part file for a value, opening a new one if needed
+    fn open_file(&mut self, value: Option<String>) -> Result<&mut OpenFile> {
+        self.writes += 1;
+        if !self.open.contains_key(&value) {
+            if self.open.len() >= self.max_open_files {
+                let oldest = self.open.iter()
+                    .min_by_key(|(_, f)| f.last_used)
+                    .map(|(v, _)| v.clone())
+                    .unwrap();
+                let file = self.open.remove(&oldest).unwrap();
+                self.close_file(oldest, file)?;
+            }
+
+            let dir = format!(
+                "{}={}",
+                self.column,
+                value.as_deref().map(escape_partition_value).unwrap_or_else(|| NULL_PARTITION.to_string())
+            );
+            let part = self.partitions.get(&dir).map(|p| p.files.len()).unwrap_or(0);
+            let path = format!("{}/part-{:04}.parquet", dir, part);
+
+            fs::create_dir_all(self.root.join(&dir))
+                .with_context(|| format!("Failed to create partition directory {}", dir))?;
+            let file = File::create(self.root.join(&path))
+                .with_context(|| format!("Failed to create {}", path))?;
+            let mut writer = ArrowWriter::try_new(file, self.schema.clone(), self.props.clone())?;
+            writer.append_key_value_metadata(schema_version_entry());
+
+            self.open.insert(value.clone(), OpenFile {
+                writer,
+                file: DatasetFile { path, rows: 0, size_bytes: 0 },
+                last_used: 0,
+            });
+        }
+
+        let open = self.open.get_mut(&value).unwrap();
+        open.last_used = self.writes;
+        Ok(open)
+    }
+
+    fn close_file(&mut self, value: Option<String>, open: OpenFile) -> Result<()> {
+        open.writer.close()?;
+        let mut file = open.file;
+        file.size_bytes = fs::metadata(self.root.join(&file.path))?.len();
+
+        let dir = file.path.split('/').next().unwrap().to_string();
+        let partition = self.partitions.entry(dir.clone()).or_insert_with(|| DatasetPartition {
+            value,
+            dir,
+            rows: 0,
+            files: Vec::new(),
+        });
+        partition.rows += file.rows;
+        partition.files.push(file);
+        Ok(())
+    }
+
+    /// Close every part file and write the dataset manifest
+    pub fn finish(mut self) -> Result<DatasetManifest> {
+        let mut open: Vec<_> = self.open.drain().collect();
+        open.sort_by(|a, b| a.1.file.path.cmp(&b.1.file.path));
+        for (value, file) in open {
+            self.close_file(value, file)?;
+        }
+
+        let partitions: Vec<DatasetPartition> = self.partitions.into_values().collect();
+        let manifest = DatasetManifest {
+            partition_by: self.column,
+            total_rows: partitions.iter().map(|p| p.rows).sum(),
+            partitions,
+        };
+        manifest.save_to_file(self.root.join(DATASET_MANIFEST))?;
+        Ok(manifest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::Int64Array;
+    use arrow::datatypes::{Field, Schema};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    fn batch(rows: &[(Option<&str>, i64)]) -> RecordBatch {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("dir", DataType::Utf8, true),
+            Field::new("n", DataType::Int64, false),
+        ]));
+        RecordBatch::try_new(schema, vec![
+            Arc::new(StringArray::from(rows.iter().map(|r| r.0).collect::<Vec<_>>())),
+            Arc::new(Int64Array::from(rows.iter().map(|r| r.1).collect::<Vec<_>>())),
+        ]).unwrap()
+    }
+
+    fn count_rows(path: &Path) -> usize {
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap()).unwrap().build().unwrap();
+        reader.map(|b| b.unwrap().num_rows()).sum()
+    }
+
+    #[test]
+    fn test_escape_partition_value() {
+        assert_eq!(escape_partition_value("home"), "home");
+        assert_eq!(escape_partition_value("lab-2_x.y"), "lab-2_x.y");
+        assert_eq!(escape_partition_value("a/b"), "a%2Fb");
+        assert_eq!(escape_partition_value("a=b c%"), "a%3Db%20c%25");
+        assert_eq!(escape_partition_value(".."), "%2E.");
+        assert_eq!(escape_partition_value("_tmp"), "%5Ftmp");
+        assert_eq!(escape_partition_value("é"), "%C3%A9");
+        assert_eq!(escape_partition_value(""), NULL_PARTITION);
+    }
+
+    #[test]
+    fn test_rows_split_by_partition() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("dataset");
+        let first = batch(&[(Some("a"), 1), (Some("b/c"), 2), (Some("a"), 3)]);
+        let mut writer = PartitionedParquetWriter::new(&root, first.schema(), "dir", None).unwrap();
+        writer.write(&first).unwrap();
+        writer.write(&batch(&[(None, 4), (Some("a"), 5), (Some(""), 6)])).unwrap();
+        let manifest = writer.finish().unwrap();
+
+        assert_eq!(manifest.total_rows, 6);
+        let dirs: Vec<(&str, u64)> = manifest.partitions.iter().map(|p| (p.dir.as_str(), p.rows)).collect();
+        assert_eq!(dirs, [("dir=__HIVE_DEFAULT_PARTITION__", 2), ("dir=a", 3), ("dir=b%2Fc", 1)]);
+        assert_eq!(manifest.partitions[2].value.as_deref(), Some("b/c"));
+        assert_eq!(count_rows(&root.join("dir=a/part-0000.parquet")), 3);
+
+        assert_eq!(DatasetManifest::load_from_file(root.join(DATASET_MANIFEST)).unwrap(), manifest);
+        assert!(PartitionedParquetWriter::new(&root, first.schema(), "dir", None).is_err());
+    }
+
+    #[test]
+    fn test_evicted_partition_starts_new_part() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("dataset");
+        let first = batch(&[(Some("a"), 1)]);
+        let mut writer = PartitionedParquetWriter::new(&root, first.schema(), "dir", None)
+            .unwrap()
+            .with_max_open_files(1);
+        writer.write(&first).unwrap();
+        writer.write(&batch(&[(Some("b"), 2)])).unwrap();
+        writer.write(&batch(&[(Some("a"), 3), (Some("a"), 4)])).unwrap();
+        let manifest = writer.finish().unwrap();
+
+        let a = &manifest.partitions[0];
+        let files: Vec<(&str, u64)> = a.files.iter().map(|f| (f.path.as_str(), f.rows)).collect();
+        assert_eq!(files, [("dir=a/part-0000.parquet", 1), ("dir=a/part-0001.parquet", 2)]);
+        assert_eq!(a.rows, 3);
+        assert_eq!(manifest.size_bytes(), a.files.iter().chain(&manifest.partitions[1].files).map(|f| f.size_bytes).sum::<u64>());
+    }
+}
@@ -0,0 +1,461 @@
+use crate::filter::RowFilter;
+use crate::query::{QueryFormat, ResultWriter};
+use crate::report::read_columns;
+use anyhow::{Context, Result};
+use arrow::array::{Array, ArrayRef, StringArray, StringBuilder, UInt32Array, UInt32Builder};
+use arrow::compute::cast;
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Columns every scan must have to be audited
+const COLUMNS: [&str; 8] = ["path", "permissions", "file_type", "parent_path", "depth", "top_level_dir", "uid", "owner"];
+
+/// Read when the scan has it, so links aren't judged by their targets' modes
+const LINK_TARGET_COLUMN: &str = "link_target";
+
+/// What a check sees of an entry
+#[derive(Debug, Clone, Copy)]
+pub struct EntryMode<'a> {
+    pub path: &'a str,
+
+    /// Permission bits of the mode; scans made before `raw_mode` was added
+    /// also have the file type bits here
+    pub mode: u32,
+
+    pub is_dir: bool,
+
+    pub is_symlink: bool,
+
+    /// Directly below the root of the audit: a home directory when auditing
+    /// `--under /home`
+    pub top_level: bool,
+}
+
+/// A condition the audit looks for. Implement this and add it to
+/// [`builtin_checks`] to make a new check available by name.
+pub trait PermissionCheck: Send + Sync {
+    /// Name given to `--check` and shown in the output
+    fn name(&self) -> &'static str;
+
+    fn description(&self) -> &'static str;
+
+    /// Whether `entry` is a finding
+    fn flags(&self, entry: &EntryMode) -> bool;
+}
+
+/// Files and directories anyone may write to, except directories with the
+/// sticky bit, such as /tmp, where users can only remove their own files
+struct WorldWritable;
+
+impl PermissionCheck for WorldWritable {
+    fn name(&self) -> &'static str {
+        "world-writable"
+    }
+
+    fn description(&self) -> &'static str {
+        "writable by everyone"
+    }
+
+    fn flags(&self, entry: &EntryMode) -> bool {
+        !entry.is_symlink && entry.mode & 0o002 != 0 && !(entry.is_dir && entry.mode & 0o1000 != 0)
+    }
+}
+
+struct Setuid;
+
+impl PermissionCheck for Setuid {
+    fn name(&self) -> &'static str {
+        "setuid"
+    }
+
+    fn description(&self) -> &'static str {
+        "files run as their owner"
+    }
+
+    fn flags(&self, entry: &EntryMode) -> bool {
+        !entry.is_symlink && !entry.is_dir && entry.mode & 0o4000 != 0
+    }
+}
+
+/// Set-group-ID files; on directories the bit only makes new files inherit
+/// the group, which shared project directories rely on
+struct Setgid;
+
+impl PermissionCheck for Setgid {
+    fn name(&self) -> &'static str {
+        "setgid"
+    }
+
+    fn description(&self) -> &'static str {
+        "files run as their group"
+    }
+
+    fn flags(&self, entry: &EntryMode) -> bool {
+        !entry.is_symlink && !entry.is_dir && entry.mode & 0o2000 != 0
+    }
+}
+
+struct WorldReadableHomes;
+
+impl PermissionCheck for WorldReadableHomes {
+    fn name(&self) -> &'static str {
+        "world-readable-homes"
+    }
+
+    fn description(&self) -> &'static str {
+        "top-level directories anyone may list"
+    }
+
+    fn flags(&self, entry: &EntryMode) -> bool {
+        !entry.is_symlink && entry.is_dir && entry.top_level && entry.mode & 0o004 != 0
+    }
+}
+
+/// Every check, in the order they are reported
+pub fn builtin_checks() -> Vec<Box<dyn PermissionCheck>> {
+    vec![
+        Box::new(WorldWritable),
+        Box::new(Setuid),
+        Box::new(Setgid),
+        Box::new(WorldReadableHomes),
+    ]
+}
+
+/// The built-in check called `name`
+pub fn find_check(name: &str) -> Result<Box<dyn PermissionCheck>> {
+    let checks = builtin_checks();
+    let names: Vec<&str> = checks.iter().map(|c| c.name()).collect();
+    let names = names.join(", ");
+    checks.into_iter()
+        .find(|c| c.name() == name)
+        .with_context(|| format!("Unknown check '{}' (checks: {})", name, names))
+}
+
+/// What [`audit_permissions`] checks
+pub struct PermissionOptions {
+    pub checks: Vec<Box<dyn PermissionCheck>>,
+
+    /// Only audit this path and the entries below it; its children count as
+    /// top-level. Without it, the scan root's children do.
+    pub under: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckCount {
+    pub check: String,
+
+    pub description: String,
+
+    pub findings: u64,
+}
+
+/// Findings under one top-level directory
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TopLevelFindings {
+    pub top_level_dir: String,
+
+    /// By check name; checks without findings are left out
+    pub findings: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PermissionReport {
+    pub under: Option<String>,
+
+    /// Entries audited
+    pub entries: u64,
+
+    /// In the order the checks were given
+    pub checks: Vec<CheckCount>,
+
+    /// Top-level directories with findings, in name order
+    pub by_top_level_dir: Vec<TopLevelFindings>,
+}
+
+/// Layout of the findings output: one row per entry and failed check
+pub fn findings_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("check", DataType::Utf8, false),
+        Field::new("path", DataType::Utf8, false),
+        Field::new("top_level_dir", DataType::Utf8, true),
+        Field::new("file_type", DataType::Utf8, true),
+        Field::new("mode", DataType::Utf8, false),
+        Field::new("uid", DataType::UInt32, true),
+        Field::new("owner", DataType::Utf8, true),
+    ]))
+}
+
+fn validate(schema: &Schema) -> Result<()> {
+    for column in COLUMNS {
+        schema.field_with_name(column)
+            .with_context(|| format!("Auditing permissions requires a '{}' column", column))?;
+    }
+    Ok(())
+}
+
+/// Findings being counted and, optionally, written
+struct Audit<'a> {
+    options: &'a PermissionOptions,
+    entries: u64,
+    counts: Vec<u64>,
+    by_top_level: HashMap<String, Vec<u64>>,
+    writer: Option<ResultWriter>,
+}
+
+impl Audit<'_> {
+    fn add_batch(&mut self, batch: &RecordBatch) -> Result<()> {
+        let column = |name: &str, data_type: &DataType| -> Result<Option<ArrayRef>> {
+            batch.column_by_name(name).map(|c| Ok(cast(c, data_type)?)).transpose()
+        };
+        let strings = |name: &str| -> Result<Option<ArrayRef>> { column(name, &DataType::Utf8) };
+        let (paths, types, parents, top_level, owners, targets) = (
+            strings("path")?.unwrap(), strings("file_type")?.unwrap(), strings("parent_path")?.unwrap(),
+            strings("top_level_dir")?.unwrap(), strings("owner")?.unwrap(), strings(LINK_TARGET_COLUMN)?,
+        );
+        let downcast = |a: &ArrayRef| a.as_any().downcast_ref::<StringArray>().unwrap().clone();
+        let (paths, types, parents, top_level, owners) =
+            (downcast(&paths), downcast(&types), downcast(&parents), downcast(&top_level), downcast(&owners));
+        let targets = targets.as_ref().map(downcast);
+        let modes = column("permissions", &DataType::UInt32)?.unwrap();
+        let modes = modes.as_any().downcast_ref::<UInt32Array>().unwrap();
+        let uids = column("uid", &DataType::UInt32)?.unwrap();
+        let uids = uids.as_any().downcast_ref::<UInt32Array>().unwrap();
+        let depths = column("depth", &DataType::UInt32)?.unwrap();
+        let depths = depths.as_any().downcast_ref::<UInt32Array>().unwrap();
+        let under = self.options.under.as_deref().map(|u| u.trim_end_matches('/'));
+
+        let mut check = StringBuilder::new();
+        let mut path = StringBuilder::new();
+        let mut top_level_dir = StringBuilder::new();
+        let mut file_type = StringBuilder::new();
+        let mut mode = StringBuilder::new();
+        let mut uid = UInt32Builder::new();
+        let mut owner = StringBuilder::new();
+
+        for i in 0..batch.num_rows() {
+            if paths.is_null(i) || modes.is_null(i) {
+                continue;
+            }
+            self.entries += 1;
+            let is_top_level = match under {
+                Some(under) => parents.is_valid(i) && parents.value(i) == under,
+                None => depths.is_valid(i) && depths.value(i) == 1,
+            };
+            let entry = EntryMode {
+                path: paths.value(i),
+                mode: modes.value(i),
+                is_dir: types.is_valid(i) && types.value(i) == "directory",
+                is_symlink: targets.as_ref().is_some_and(|t| t.is_valid(i)),
+                top_level: is_top_level,
+            };
+            let dir = top_level.is_valid(i).then(|| top_level.value(i));
+            for (index, c) in self.options.checks.iter().enumerate() {
+                if !c.flags(&entry) {
+                    continue;
+                }
+                self.counts[index] += 1;
+                let checks = self.options.checks.len();
+                self.by_top_level.entry(dir.unwrap_or_default().to_string())
+                    .or_insert_with(|| vec![0; checks])[index] += 1;
+                if self.writer.is_some() {
+                    check.append_value(c.name());
+                    path.append_value(entry.path);
+                    top_level_dir.append_option(dir);
+                    file_type.append_option(types.is_valid(i).then(|| types.value(i)));
+                    mode.append_value(format!("{:04o}", entry.mode & 0o7777));
+                    uid.append_option(uids.is_valid(i).then(|| uids.value(i)));
+                    owner.append_option(owners.is_valid(i).then(|| owners.value(i)));
+                }
+            }
+        }
+
+        if let Some(ref mut writer) = self.writer {
+            let columns: Vec<ArrayRef> = vec![
+                Arc::new(check.finish()),
+                Arc::new(path.finish()),
+                Arc::new(top_level_dir.finish()),
+                Arc::new(file_type.finish()),
+                Arc::new(mode.finish()),
+                Arc::new(uid.finish()),
+                Arc::new(owner.finish()),
+            ];
+            let findings = RecordBatch::try_new(findings_schema(), columns)?;
+            if findings.num_rows() > 0 {
+                writer.write(&findings)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn into_report(self) -> Result<PermissionReport> {
+        if let Some(writer) = self.writer {
+            writer.finish()?;
+        }
+        let checks = &self.options.checks;
+        let mut by_top_level_dir: Vec<TopLevelFindings> = self.by_top_level.into_iter()
+            .map(|(top_level_dir, counts)| TopLevelFindings {
+                top_level_dir,
+                findings: checks.iter().zip(counts)
+                    .filter(|(_, n)| *n > 0)
+                    .map(|(c, n)| (c.name().to_string(), n))
+                    .collect(),
+            })
+            .collect();
+        by_top_level_dir.sort_by(|a, b| a.top_level_dir.cmp(&b.top_level_dir));
+
+        Ok(PermissionReport {
+            under: self.options.under.clone(),
+            entries: self.entries,
+            checks: checks.iter().zip(self.counts)
+                .map(|(c, findings)| CheckCount {
+                    check: c.name().to_string(),
+                    description: c.description().to_string(),
+                    findings,
+                })
+                .collect(),
+            by_top_level_dir,
+        })
+    }
+}
+
+/// Run `options.checks` over every entry of `chunk_files` (or those under
+/// `options.under`), counting findings per check and top-level directory.
+/// With `output`, every finding is also written there as it is found.
+pub fn audit_permissions(
+    chunk_files: &[PathBuf],
+    options: &PermissionOptions,
+    output: Option<(&Path, QueryFormat)>,
+) -> Result<PermissionReport> {
+    if options.checks.is_empty() {
+        anyhow::bail!("No checks given");
+    }
+    let filter = RowFilter { path_prefix: options.under.clone(), ..Default::default() };
+    let writer = output
+        .map(|(output, format)| ResultWriter::create(output, format, findings_schema()))
+        .transpose()?;
+    let mut audit = Audit {
+        options,
+        entries: 0,
+        counts: vec![0; options.checks.len()],
+        by_top_level: HashMap::new(),
+        writer,
+    };
+
+    for path in chunk_files {
+        let has_link_targets = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)
+            .with_context(|| format!("Failed to read {}", path.display()))?
+            .schema()
+            .field_with_name(LINK_TARGET_COLUMN)
+            .is_ok();
+        let mut columns = COLUMNS.to_vec();
+        if has_link_targets {
+            columns.push(LINK_TARGET_COLUMN);
+        }
+        let reader = read_columns(path, &columns, validate)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        for batch in reader {
+            audit.add_batch(&filter.apply(&batch?)?)?;
+        }
+    }
+    audit.into_report()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ScanOptions;
+    use crate::scanner::scan_directory;
+    use crate::writer::ParquetFileWriter;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+
+    fn chmod(path: &Path, mode: u32) {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode)).unwrap();
+    }
+
+    /// homes/alice is private, homes/bob is world-readable and holds a file
+    /// for each check
+    fn fixture(root: &Path) {
+        fs::create_dir_all(root.join("homes/alice")).unwrap();
+        fs::create_dir_all(root.join("homes/bob/shared")).unwrap();
+        fs::create_dir_all(root.join("homes/bob/tmp")).unwrap();
+        chmod(&root.join("homes"), 0o755);
+        chmod(&root.join("homes/alice"), 0o700);
+        chmod(&root.join("homes/bob"), 0o755);
+        for (name, mode) in [("open.txt", 0o666), ("suid", 0o4755), ("sgid", 0o2755), ("plain", 0o644)] {
+            let path = root.join("homes/bob").join(name);
+            fs::write(&path, "x").unwrap();
+            chmod(&path, mode);
+        }
+        // Group-shared directory: setgid on a directory is not a finding
+        chmod(&root.join("homes/bob/shared"), 0o2775);
+        // Sticky world-writable directories are fine, plain ones are not
+        chmod(&root.join("homes/bob/tmp"), 0o1777);
+        fs::create_dir_all(root.join("homes/bob/dropbox")).unwrap();
+        chmod(&root.join("homes/bob/dropbox"), 0o777);
+    }
+
+    fn scan(root: &Path, output: &Path) -> Vec<PathBuf> {
+        let mut writer = ParquetFileWriter::new(output).unwrap();
+        writer.write_batch(&scan_directory(root, ScanOptions::default()).unwrap()).unwrap();
+        writer.close().unwrap();
+        vec![output.to_path_buf()]
+    }
+
+    fn counts(report: &PermissionReport) -> Vec<(&str, u64)> {
+        report.checks.iter().map(|c| (c.check.as_str(), c.findings)).collect()
+    }
+
+    #[test]
+    fn test_each_check_under_homes() {
+        let files = TempDir::new().unwrap();
+        fixture(files.path());
+        let out = TempDir::new().unwrap();
+        let chunks = scan(files.path(), &out.path().join("scan.parquet"));
+
+        let homes = files.path().join("homes").to_string_lossy().to_string();
+        let options = PermissionOptions { checks: builtin_checks(), under: Some(homes.clone()) };
+        let output = out.path().join("findings.csv");
+        let report = audit_permissions(&chunks, &options, Some((&output, QueryFormat::Csv))).unwrap();
+        assert_eq!(counts(&report), [
+            ("world-writable", 2),
+            ("setuid", 1),
+            ("setgid", 1),
+            ("world-readable-homes", 1),
+        ]);
+        assert_eq!(report.by_top_level_dir.len(), 1);
+        assert_eq!(report.by_top_level_dir[0].top_level_dir, "homes");
+        assert_eq!(report.by_top_level_dir[0].findings["setuid"], 1);
+
+        let csv = fs::read_to_string(&output).unwrap();
+        let rows: Vec<&str> = csv.lines().skip(1).collect();
+        assert_eq!(rows.len(), 5);
+        assert!(rows.iter().any(|r| r.starts_with(&format!("setuid,{}/bob/suid,homes,no_extension,4755,", homes))));
+        assert!(rows.iter().any(|r| r.starts_with(&format!("world-readable-homes,{}/bob,", homes))));
+        assert!(rows.iter().any(|r| r.starts_with(&format!("world-writable,{}/bob/dropbox,", homes))));
+    }
+
+    #[test]
+    fn test_selected_checks_and_unknown_name() {
+        let files = TempDir::new().unwrap();
+        fixture(files.path());
+        let out = TempDir::new().unwrap();
+        let chunks = scan(files.path(), &out.path().join("scan.parquet"));
+
+        // Without --under, the scan root's children are the top level: only homes itself
+        let options = PermissionOptions { checks: vec![find_check("world-readable-homes").unwrap()], under: None };
+        let report = audit_permissions(&chunks, &options, None).unwrap();
+        assert_eq!(counts(&report), [("world-readable-homes", 1)]);
+        assert!(report.entries > 10);
+
+        let err = find_check("world-writeable").err().unwrap();
+        assert!(err.to_string().contains("Unknown check 'world-writeable'"), "{}", err);
+    }
+}
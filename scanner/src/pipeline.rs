@@ -0,0 +1,696 @@
+use crate::aggregate::{self, AggregateOptions};
+use crate::lock::ScanLock;
+use crate::manifest::{RotationParams, ScanManifest};
+use crate::models::ScanOptions;
+use crate::scanner::scan_to_parquet;
+use crate::utils;
+use crate::verify;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tracing::{error, info, warn};
+
+/// What a failed stage does to the rest of the run
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FailurePolicy {
+    /// Stop; later stages are not run
+    #[default]
+    Abort,
+
+    /// Carry on with the next stage
+    Continue,
+}
+
+/// How `publish` places files in the destination
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PublishMethod {
+    #[default]
+    Copy,
+
+    /// Hard links; the destination must be on the same filesystem as the work directory
+    Hardlink,
+}
+
+/// A `pipeline.toml` file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PipelineConfig {
+    /// Directory holding the scan chunks, the aggregate, and the run report
+    pub work_dir: PathBuf,
+
+    /// Base name of the outputs: `<name>_chunk_*.parquet`, `<name>_manifest.json`, and `<name>.parquet`
+    #[serde(default = "default_name")]
+    pub name: String,
+
+    pub scan: ScanStage,
+
+    #[serde(default)]
+    pub aggregate: AggregateStage,
+
+    #[serde(default)]
+    pub verify: VerifyStage,
+
+    /// Omit to stop after verification
+    pub publish: Option<PublishStage>,
+}
+
+fn default_name() -> String {
+    "scan".to_string()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScanStage {
+    /// Directory to scan
+    pub path: PathBuf,
+
+    /// Number of threads (default: number of CPU cores)
+    pub threads: Option<usize>,
+
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+
+    #[serde(default)]
+    pub follow_symlinks: bool,
+
+    pub max_depth: Option<usize>,
+
+    #[serde(default = "default_rows_per_chunk")]
+    pub rows_per_chunk: usize,
+
+    #[serde(default = "default_chunk_interval_secs")]
+    pub chunk_interval_secs: u64,
+
+    #[serde(default)]
+    pub on_failure: FailurePolicy,
+}
+
+fn default_batch_size() -> usize {
+    100_000
+}
+
+fn default_rows_per_chunk() -> usize {
+    500_000
+}
+
+fn default_chunk_interval_secs() -> u64 {
+    300
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AggregateStage {
+    /// Threads decoding chunks (default: one per CPU core)
+    #[serde(default)]
+    pub threads: usize,
+
+    /// Columns to sort the output by, most significant first
+    #[serde(default)]
+    pub sort_by: Vec<String>,
+
+    #[serde(default)]
+    pub on_failure: FailurePolicy,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VerifyStage {
+    /// Decode every row group of every chunk, as with `verify --deep`
+    #[serde(default)]
+    pub deep: bool,
+
+    #[serde(default)]
+    pub on_failure: FailurePolicy,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PublishStage {
+    /// Directory receiving the aggregate and its manifest
+    pub destination: PathBuf,
+
+    #[serde(default)]
+    pub method: PublishMethod,
+
+    #[serde(default)]
+    pub on_failure: FailurePolicy,
+}
+
+impl PipelineConfig {
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let contents = fs::read_to_string(path.as_ref())
+            .with_context(|| format!("Failed to read {}", path.as_ref().display()))?;
+        toml::from_str(&contents)
+            .with_context(|| format!("Failed to parse {}", path.as_ref().display()))
+    }
+
+    /// Base output path of the scan, which the aggregate is also written to
+    pub fn output_path(&self) -> PathBuf {
+        self.work_dir.join(format!("{}.parquet", self.name))
+    }
+
+    pub fn manifest_path(&self) -> PathBuf {
+        self.work_dir.join(format!("{}_manifest.json", self.name))
+    }
+
+    pub fn report_path(&self) -> PathBuf {
+        self.work_dir.join("pipeline_report.json")
+    }
+
+    /// Stages in run order, with their failure policies
+    fn stages(&self) -> Vec<(Stage, FailurePolicy)> {
+        let mut stages = vec![
+            (Stage::Scan, self.scan.on_failure),
+            (Stage::Aggregate, self.aggregate.on_failure),
+            (Stage::Verify, self.verify.on_failure),
+        ];
+        if let Some(ref publish) = self.publish {
+            stages.push((Stage::Publish, publish.on_failure));
+        }
+        stages
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stage {
+    Scan,
+    Aggregate,
+    Verify,
+    Publish,
+}
+
+impl Stage {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Stage::Scan => "scan",
+            Stage::Aggregate => "aggregate",
+            Stage::Verify => "verify",
+            Stage::Publish => "publish",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StageStatus {
+    Succeeded,
+    Failed,
+
+    /// Not reached because an earlier stage failed with the abort policy
+    NotRun,
+}
+
+/// Outcome of one stage
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct StageReport {
+    pub stage: Stage,
+
+    pub status: StageStatus,
+
+    /// Start time (Unix timestamp), if the stage ran
+    pub started_at: Option<i64>,
+
+    pub duration_secs: f64,
+
+    /// Rows scanned, aggregated, verified, or published
+    pub rows: Option<u64>,
+
+    pub error: Option<String>,
+
+    /// Completed by an earlier run and not repeated on resume
+    #[serde(default)]
+    pub resumed: bool,
+}
+
+/// Machine-readable record of a pipeline run, rewritten after every stage
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PipelineReport {
+    pub started_at: i64,
+
+    pub finished_at: Option<i64>,
+
+    /// Whether every stage succeeded
+    pub succeeded: bool,
+
+    pub stages: Vec<StageReport>,
+}
+
+impl PipelineReport {
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let contents = fs::read_to_string(path.as_ref())
+            .context("Failed to read pipeline report")?;
+        serde_json::from_str(&contents).context("Failed to parse pipeline report JSON")
+    }
+
+    /// Write the report next to its final location and rename it into place
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        write_atomically(path.as_ref(), &serde_json::to_vec_pretty(self)?)
+    }
+
+    pub fn stage(&self, stage: Stage) -> Option<&StageReport> {
+        self.stages.iter().find(|s| s.stage == stage)
+    }
+}
+
+/// Description of a published aggregate, written next to it
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PublishedArtifact {
+    /// File name of the aggregate in the destination
+    pub file_name: String,
+
+    pub rows: u64,
+
+    pub file_size: u64,
+
+    /// CRC-32 of the aggregate, as recorded for chunks in scan manifests
+    pub checksum: String,
+
+    pub scan_path: String,
+
+    pub scan_start: i64,
+
+    pub scan_end: Option<i64>,
+
+    /// Errors the scan encountered (entries that could not be read)
+    pub scan_errors: u64,
+
+    pub published_at: i64,
+}
+
+impl PublishedArtifact {
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let contents = fs::read_to_string(path.as_ref())
+            .context("Failed to read published manifest")?;
+        serde_json::from_str(&contents).context("Failed to parse published manifest JSON")
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+/// Write `contents` to a hidden temporary file next to `path`, then rename it into place
+fn write_atomically(path: &Path, contents: &[u8]) -> Result<()> {
+    let temp = temp_path(path);
+    fs::write(&temp, contents)
+        .with_context(|| format!("Failed to write {}", temp.display()))?;
+    fs::rename(&temp, path)
+        .with_context(|| format!("Failed to replace {}", path.display()))
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    path.with_file_name(format!(".{}.tmp", name))
+}
+
+/// Run the pipeline's stages in order.
+///
+/// The report is saved to [`PipelineConfig::report_path`] after every stage.
+/// With `resume`, stages that succeeded in the previous run's report are
+/// kept as they were and the run picks up at the first stage that didn't;
+/// every stage after it runs again. Stage failures are recorded in the
+/// report rather than returned; an error means the pipeline itself could not
+/// run (e.g. the work directory can't be created).
+pub fn run_pipeline(config: &PipelineConfig, resume: bool) -> Result<PipelineReport> {
+    fs::create_dir_all(&config.work_dir)
+        .with_context(|| format!("Failed to create {}", config.work_dir.display()))?;
+
+    let report_path = config.report_path();
+    let previous = if resume && report_path.exists() {
+        Some(PipelineReport::load_from_file(&report_path)?)
+    } else {
+        if resume {
+            warn!("No previous report at {}; running every stage", report_path.display());
+        }
+        None
+    };
+
+    let mut report = PipelineReport {
+        started_at: now(),
+        finished_at: None,
+        succeeded: false,
+        stages: Vec::new(),
+    };
+    let mut reusing = previous.is_some();
+    let mut aborted = false;
+
+    for (stage, policy) in config.stages() {
+        if reusing {
+            match previous.as_ref().and_then(|p| p.stage(stage)) {
+                Some(done) if done.status == StageStatus::Succeeded => {
+                    info!("Stage {}: completed by the previous run", stage.as_str());
+                    report.stages.push(StageReport { resumed: true, ..done.clone() });
+                    continue;
+                }
+                _ => {
+                    info!("Resuming at stage {}", stage.as_str());
+                    reusing = false;
+                }
+            }
+        }
+
+        if aborted {
+            report.stages.push(StageReport {
+                stage,
+                status: StageStatus::NotRun,
+                started_at: None,
+                duration_secs: 0.0,
+                rows: None,
+                error: None,
+                resumed: false,
+            });
+            continue;
+        }
+
+        info!("Stage {}: starting", stage.as_str());
+        let started_at = now();
+        let timer = Instant::now();
+        let outcome = match stage {
+            Stage::Scan => run_scan(config),
+            Stage::Aggregate => run_aggregate(config),
+            Stage::Verify => run_verify(config),
+            Stage::Publish => run_publish(config),
+        };
+
+        let (status, rows, error) = match outcome {
+            Ok(rows) => {
+                info!("Stage {}: succeeded, {} rows", stage.as_str(), utils::format_number(rows));
+                (StageStatus::Succeeded, Some(rows), None)
+            }
+            Err(e) => {
+                error!("Stage {} failed: {:#}", stage.as_str(), e);
+                aborted = policy == FailurePolicy::Abort;
+                (StageStatus::Failed, None, Some(format!("{:#}", e)))
+            }
+        };
+
+        report.stages.push(StageReport {
+            stage,
+            status,
+            started_at: Some(started_at),
+            duration_secs: timer.elapsed().as_secs_f64(),
+            rows,
+            error,
+            resumed: false,
+        });
+        report.save_to_file(&report_path)?;
+    }
+
+    report.succeeded = report.stages.iter().all(|s| s.status == StageStatus::Succeeded);
+    report.finished_at = Some(now());
+    report.save_to_file(&report_path)?;
+    Ok(report)
+}
+
+/// Scan into rotating chunks with a manifest. Chunks left by an earlier
+/// attempt are removed first, so a failed scan starts over.
+fn run_scan(config: &PipelineConfig) -> Result<u64> {
+    let scan = &config.scan;
+    utils::validate_path(&scan.path).context("Invalid scan path")?;
+
+    let output = config.output_path();
+    let _lock = ScanLock::acquire(&output, false)?;
+
+    let stem = glob::Pattern::escape(&config.work_dir.join(&config.name).to_string_lossy());
+    for pattern in [format!("{}_chunk_*", stem), format!("{}_manifest.*", stem)] {
+        for path in glob::glob(&pattern)?.filter_map(|p| p.ok()) {
+            fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove {}", path.display()))?;
+        }
+    }
+
+    let options = ScanOptions {
+        num_threads: scan.threads.unwrap_or_else(num_cpus::get),
+        batch_size: scan.batch_size,
+        follow_symlinks: scan.follow_symlinks,
+        max_depth: scan.max_depth,
+        ..ScanOptions::default()
+    };
+    let chunking = RotationParams {
+        rows_per_chunk: scan.rows_per_chunk,
+        chunk_interval_secs: scan.chunk_interval_secs,
+    };
+    let (stats, rows) = scan_to_parquet(&scan.path, &output, options, Some(chunking))?;
+    if stats.errors_encountered > 0 {
+        warn!("Scan encountered {} errors; see the manifest", stats.errors_encountered);
+    }
+    Ok(rows)
+}
+
+/// Aggregate the chunks listed in the manifest into `<name>.parquet`
+fn run_aggregate(config: &PipelineConfig) -> Result<u64> {
+    let chunk_files = aggregate::find_chunk_files(&config.manifest_path())?;
+    if chunk_files.is_empty() {
+        anyhow::bail!("The scan wrote no chunk files");
+    }
+
+    // A fresh file rather than a truncated one, so an aggregate hard-linked
+    // by an earlier publish is left intact
+    let output = config.output_path();
+    if output.exists() {
+        fs::remove_file(&output)
+            .with_context(|| format!("Failed to remove {}", output.display()))?;
+    }
+
+    let options = AggregateOptions {
+        threads: config.aggregate.threads,
+        sort_by: config.aggregate.sort_by.clone(),
+        ..AggregateOptions::default()
+    };
+    let summary = aggregate::aggregate_chunks_with(&chunk_files, &output, &options)?;
+    Ok(summary.total_rows)
+}
+
+/// Check the chunks against the manifest, and the aggregate's row count against both
+fn run_verify(config: &PipelineConfig) -> Result<u64> {
+    let report = verify::verify_manifest_file(config.manifest_path(), config.verify.deep)?;
+    if !report.is_ok() {
+        let problems: Vec<&String> = report.problems.iter()
+            .chain(report.chunks.iter().flat_map(|c| &c.problems))
+            .collect();
+        anyhow::bail!(
+            "{} problem(s) in the scan: {}",
+            problems.len(),
+            problems.iter().take(5).map(|p| p.as_str()).collect::<Vec<_>>().join("; ")
+        );
+    }
+
+    let output = config.output_path();
+    let rows = if config.verify.deep {
+        verify::count_rows_deep(&output)
+    } else {
+        verify::count_rows_footer(&output)
+    }
+    .with_context(|| format!("Failed to read {}", output.display()))?;
+    if rows != report.found_rows {
+        anyhow::bail!("Aggregate has {} rows but the chunks hold {}", rows, report.found_rows);
+    }
+    Ok(rows)
+}
+
+/// Place the aggregate, then its manifest, in the destination. Each file is
+/// staged under a hidden name and renamed into place, so readers never see a
+/// partial file, and the manifest only ever describes an aggregate already
+/// in place.
+fn run_publish(config: &PipelineConfig) -> Result<u64> {
+    let publish = config.publish.as_ref().expect("publish stage is configured");
+    fs::create_dir_all(&publish.destination)
+        .with_context(|| format!("Failed to create {}", publish.destination.display()))?;
+
+    let source = config.output_path();
+    let manifest = ScanManifest::load_from_file(config.manifest_path())?;
+    let rows = verify::count_rows_footer(&source)
+        .with_context(|| format!("Failed to read {}", source.display()))?;
+
+    let file_name = format!("{}.parquet", config.name);
+    let dest = publish.destination.join(&file_name);
+    let staged = temp_path(&dest);
+    if staged.exists() {
+        fs::remove_file(&staged)?;
+    }
+    match publish.method {
+        PublishMethod::Copy => fs::copy(&source, &staged).map(|_| ()),
+        PublishMethod::Hardlink => fs::hard_link(&source, &staged),
+    }
+    .with_context(|| format!("Failed to stage {} in {}", source.display(), publish.destination.display()))?;
+    fs::rename(&staged, &dest)
+        .with_context(|| format!("Failed to replace {}", dest.display()))?;
+
+    let artifact = PublishedArtifact {
+        file_name,
+        rows,
+        file_size: fs::metadata(&dest)?.len(),
+        checksum: utils::file_checksum(&dest)?,
+        scan_path: manifest.scan_path.clone(),
+        scan_start: manifest.scan_start,
+        scan_end: manifest.scan_end,
+        scan_errors: manifest.errors.total,
+        published_at: now(),
+    };
+    write_atomically(
+        &published_manifest_path(&publish.destination, &config.name),
+        &serde_json::to_vec_pretty(&artifact)?,
+    )?;
+
+    info!("Published {} to {}", dest.display(), publish.destination.display());
+    Ok(rows)
+}
+
+/// Location of the manifest describing a published aggregate
+pub fn published_manifest_path(destination: &Path, name: &str) -> PathBuf {
+    destination.join(format!("{}_published.json", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn fixture_tree() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir_all(dir.path().join("a/deep")).unwrap();
+        fs::create_dir_all(dir.path().join("b")).unwrap();
+        fs::write(dir.path().join("a/one.txt"), "one").unwrap();
+        fs::write(dir.path().join("a/deep/two.log"), "two").unwrap();
+        fs::write(dir.path().join("b/three.dat"), "three").unwrap();
+        fs::write(dir.path().join("root.txt"), "root").unwrap();
+        dir
+    }
+
+    fn config(tree: &Path, work: &Path, destination: &Path) -> PipelineConfig {
+        toml::from_str(&format!(
+            r#"
+            work_dir = "{}"
+            name = "nightly"
+
+            [scan]
+            path = "{}"
+            threads = 2
+            rows_per_chunk = 3
+
+            [verify]
+            deep = true
+
+            [publish]
+            destination = "{}"
+            "#,
+            work.display(), tree.display(), destination.display()
+        ))
+        .unwrap()
+    }
+
+    fn statuses(report: &PipelineReport) -> Vec<(Stage, StageStatus, bool)> {
+        report.stages.iter().map(|s| (s.stage, s.status, s.resumed)).collect()
+    }
+
+    #[test]
+    fn test_full_pipeline_publishes_aggregate_and_manifest() {
+        let tree = fixture_tree();
+        let work = TempDir::new().unwrap();
+        let dest = TempDir::new().unwrap();
+        let config = config(tree.path(), work.path(), &dest.path().join("public"));
+
+        let report = run_pipeline(&config, false).unwrap();
+        assert!(report.succeeded, "{:?}", report);
+        assert_eq!(
+            statuses(&report),
+            vec![
+                (Stage::Scan, StageStatus::Succeeded, false),
+                (Stage::Aggregate, StageStatus::Succeeded, false),
+                (Stage::Verify, StageStatus::Succeeded, false),
+                (Stage::Publish, StageStatus::Succeeded, false),
+            ]
+        );
+        let rows = report.stage(Stage::Scan).unwrap().rows.unwrap();
+        assert!(rows >= 7);
+        assert!(report.stages.iter().all(|s| s.rows == Some(rows)), "{:?}", report);
+        assert_eq!(PipelineReport::load_from_file(config.report_path()).unwrap(), report);
+
+        let public = dest.path().join("public");
+        let mut names: Vec<String> = fs::read_dir(&public).unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["nightly.parquet", "nightly_published.json"]);
+
+        let artifact = PublishedArtifact::load_from_file(published_manifest_path(&public, "nightly")).unwrap();
+        assert_eq!(artifact.rows, rows);
+        assert_eq!(artifact.checksum, utils::file_checksum(&public.join("nightly.parquet")).unwrap());
+        assert_eq!(artifact.scan_path, tree.path().canonicalize().unwrap().to_string_lossy());
+        assert_eq!(fs::read(public.join("nightly.parquet")).unwrap(), fs::read(config.output_path()).unwrap());
+    }
+
+    #[test]
+    fn test_resume_picks_up_at_failed_stage() {
+        let tree = fixture_tree();
+        let work = TempDir::new().unwrap();
+        let dest = TempDir::new().unwrap();
+        // A file where the destination directory should be makes publish fail
+        let blocked = dest.path().join("public");
+        fs::write(&blocked, "not a directory").unwrap();
+        let config = config(tree.path(), work.path(), &blocked);
+
+        let failed = run_pipeline(&config, false).unwrap();
+        assert!(!failed.succeeded);
+        assert_eq!(failed.stage(Stage::Publish).unwrap().status, StageStatus::Failed);
+        assert!(failed.stage(Stage::Publish).unwrap().error.is_some());
+
+        // Resuming repeats only the failed stage
+        fs::remove_file(&blocked).unwrap();
+        let chunk_before = fs::metadata(work.path().join("nightly_chunk_0001.parquet")).unwrap().modified().unwrap();
+        let resumed = run_pipeline(&config, true).unwrap();
+        assert!(resumed.succeeded, "{:?}", resumed);
+        assert_eq!(
+            statuses(&resumed),
+            vec![
+                (Stage::Scan, StageStatus::Succeeded, true),
+                (Stage::Aggregate, StageStatus::Succeeded, true),
+                (Stage::Verify, StageStatus::Succeeded, true),
+                (Stage::Publish, StageStatus::Succeeded, false),
+            ]
+        );
+        assert_eq!(resumed.stage(Stage::Scan), failed.stage(Stage::Scan).map(|s| StageReport { resumed: true, ..s.clone() }).as_ref());
+        let chunk_after = fs::metadata(work.path().join("nightly_chunk_0001.parquet")).unwrap().modified().unwrap();
+        assert_eq!(chunk_before, chunk_after);
+        assert!(blocked.join("nightly.parquet").exists());
+    }
+
+    #[test]
+    fn test_failure_policies() {
+        let work = TempDir::new().unwrap();
+        let dest = TempDir::new().unwrap();
+        let missing = work.path().join("missing");
+        let mut config = config(&missing, work.path(), dest.path());
+
+        let aborted = run_pipeline(&config, false).unwrap();
+        assert!(!aborted.succeeded);
+        assert_eq!(
+            statuses(&aborted),
+            vec![
+                (Stage::Scan, StageStatus::Failed, false),
+                (Stage::Aggregate, StageStatus::NotRun, false),
+                (Stage::Verify, StageStatus::NotRun, false),
+                (Stage::Publish, StageStatus::NotRun, false),
+            ]
+        );
+
+        // Continuing past the scan reaches aggregate, whose abort policy stops the run
+        config.scan.on_failure = FailurePolicy::Continue;
+        let continued = run_pipeline(&config, false).unwrap();
+        assert_eq!(
+            statuses(&continued),
+            vec![
+                (Stage::Scan, StageStatus::Failed, false),
+                (Stage::Aggregate, StageStatus::Failed, false),
+                (Stage::Verify, StageStatus::NotRun, false),
+                (Stage::Publish, StageStatus::NotRun, false),
+            ]
+        );
+        assert!(fs::read_dir(dest.path()).unwrap().next().is_none());
+    }
+}
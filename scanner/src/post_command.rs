@@ -0,0 +1,287 @@
+use crate::manifest::{ChunkMetadata, CommandOutcome, ScanManifest};
+use crate::rotating_writer::ChunkHook;
+use anyhow::{Context, Result};
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Instant;
+use tracing::{error, info, warn};
+
+/// What a failed command does to the scan
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnError {
+    /// Log it and carry on
+    #[default]
+    Warn,
+
+    /// Fail the scan, leaving its manifest incomplete
+    Fail,
+}
+
+/// Commands run on scan output as it is written
+#[derive(Debug, Clone, Default)]
+pub struct PostCommands {
+    /// Run per completed chunk; `{chunk}`, `{manifest}`, `{chunk_number}`,
+    /// and `{rows}` are substituted
+    pub chunk: Option<String>,
+
+    /// Run once the manifest is finalized; `{manifest}` and `{rows}` are substituted
+    pub scan: Option<String>,
+
+    pub on_error: OnError,
+}
+
+/// Quote `value` as one word for `sh`
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// `template` with each `{name}` replaced by its value.
+///
+/// Values go in as given; paths are passed through [`shell_quote`] first so
+/// `{chunk}` stays one argument whatever the file name holds.
+pub fn expand(template: &str, values: &[(&str, String)]) -> String {
+    values.iter().fold(template.to_string(), |command, (name, value)| {
+        command.replace(&format!("{{{}}}", name), value)
+    })
+}
+
+/// Run `command` with `sh -c`, timing it.
+///
+/// The command inherits the scanner's stdout and stderr, so its messages
+/// show up in the scan's log.
+pub fn run_command(command: &str) -> CommandOutcome {
+    let started = Instant::now();
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .status();
+    let duration_ms = started.elapsed().as_millis() as u64;
+
+    match status {
+        Ok(status) => CommandOutcome { exit_code: status.code(), duration_ms, error: None },
+        Err(e) => CommandOutcome { exit_code: None, duration_ms, error: Some(e.to_string()) },
+    }
+}
+
+/// Why a command failed, for logs and errors
+fn describe_failure(what: &str, outcome: &CommandOutcome) -> String {
+    match (outcome.exit_code, &outcome.error) {
+        (_, Some(error)) => format!("{} could not be started: {}", what, error),
+        (Some(code), None) => format!("{} exited with status {}", what, code),
+        (None, None) => format!("{} was killed by a signal", what),
+    }
+}
+
+/// Runs a command on each chunk as the rotating writer closes it.
+///
+/// Commands run one at a time on a background thread in chunk order, so a
+/// slow transfer never holds up writing; finalize waits for the last one.
+/// Each chunk's exit status and duration are recorded in the manifest. With
+/// [`OnError::Fail`], a failed command stops further commands and fails the
+/// scan at the next chunk or at finalize.
+pub struct ChunkCommand {
+    chunks: Option<Sender<ChunkMetadata>>,
+    outcomes: Receiver<(usize, CommandOutcome)>,
+    worker: Option<JoinHandle<()>>,
+    failure: Arc<Mutex<Option<String>>>,
+}
+
+impl ChunkCommand {
+    pub fn new(template: String, manifest_path: PathBuf, on_error: OnError) -> Result<Self> {
+        let (chunks, queue) = unbounded::<ChunkMetadata>();
+        let (sender, outcomes) = unbounded();
+        let failure = Arc::new(Mutex::new(None));
+        let failed = failure.clone();
+        let manifest = shell_quote(&manifest_path.to_string_lossy());
+        let worker = std::thread::Builder::new()
+            .name("post-chunk-cmd".to_string())
+            .spawn(move || {
+                for chunk in queue {
+                    let command = expand(&template, &[
+                        ("chunk", shell_quote(&chunk.file_path)),
+                        ("manifest", manifest.clone()),
+                        ("chunk_number", chunk.chunk_number.to_string()),
+                        ("rows", chunk.row_count.to_string()),
+                    ]);
+                    info!("Running post-chunk command for chunk {}: {}", chunk.chunk_number, command);
+                    let outcome = run_command(&command);
+                    let message = (!outcome.success()).then(|| {
+                        describe_failure(&format!("Post-chunk command for chunk {}", chunk.chunk_number), &outcome)
+                    });
+                    if sender.send((chunk.chunk_number, outcome)).is_err() {
+                        break;
+                    }
+                    match (message, on_error) {
+                        (None, _) => {}
+                        (Some(message), OnError::Warn) => warn!("{}", message),
+                        (Some(message), OnError::Fail) => {
+                            error!("{}", message);
+                            *failed.lock().unwrap() = Some(message);
+                            break;
+                        }
+                    }
+                }
+            })
+            .context("Failed to start post-chunk command thread")?;
+
+        Ok(Self { chunks: Some(chunks), outcomes, worker: Some(worker), failure })
+    }
+
+    /// The failure that stopped the commands, if any
+    fn check(&self) -> Result<()> {
+        match *self.failure.lock().unwrap() {
+            Some(ref message) => Err(anyhow::anyhow!("{}", message)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl ChunkHook for ChunkCommand {
+    fn chunk_closed(&mut self, chunk: &ChunkMetadata) -> Result<()> {
+        self.check()?;
+        self.chunks.as_ref()
+            .context("Post-chunk commands already finished")?
+            .send(chunk.clone())
+            .map_err(|_| anyhow::anyhow!("Post-chunk command thread stopped"))
+    }
+
+    fn record(&mut self, manifest: &mut ScanManifest) {
+        for (chunk_number, outcome) in self.outcomes.try_iter() {
+            if let Some(chunk) = manifest.chunks.iter_mut().find(|c| c.chunk_number == chunk_number) {
+                chunk.post_chunk = Some(outcome);
+            }
+        }
+    }
+
+    fn finish(&mut self, manifest: &mut ScanManifest) -> Result<()> {
+        // Closing the queue ends the thread once it is drained
+        self.chunks = None;
+        if let Some(worker) = self.worker.take() {
+            worker.join().map_err(|_| anyhow::anyhow!("Post-chunk command thread panicked"))?;
+        }
+        self.record(manifest);
+        self.check()
+    }
+}
+
+/// Run the post-scan command for the finalized manifest at `manifest_path`
+pub fn run_post_scan(template: &str, manifest_path: &Path, rows: u64, on_error: OnError) -> Result<CommandOutcome> {
+    let command = expand(template, &[
+        ("manifest", shell_quote(&manifest_path.to_string_lossy())),
+        ("rows", rows.to_string()),
+    ]);
+    info!("Running post-scan command: {}", command);
+    let outcome = run_command(&command);
+    if !outcome.success() {
+        let message = describe_failure("Post-scan command", &outcome);
+        match on_error {
+            OnError::Warn => warn!("{}", message),
+            OnError::Fail => anyhow::bail!(message),
+        }
+    }
+    Ok(outcome)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::FileEntry;
+    use crate::rotating_writer::{RotatingParquetWriter, RotatingWriterConfig};
+    use std::fs;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    fn entry(i: usize) -> FileEntry {
+        FileEntry { inode: i as u64, ..FileEntry::fixture(&format!("/test/file_{}.txt", i)) }
+    }
+
+    /// Write `rows` rows as chunks of 3 into `dir`, running `template` per chunk
+    fn write_scan(dir: &Path, rows: usize, template: &str, on_error: OnError) -> Result<ScanManifest> {
+        let config = RotatingWriterConfig {
+            base_output_path: dir.join("scan.parquet"),
+            rows_per_chunk: 3,
+            time_interval: Duration::from_secs(3600),
+        };
+        let hook = ChunkCommand::new(template.to_string(), dir.join("scan_manifest.json"), on_error)?;
+        let mut writer = RotatingParquetWriter::new(config, "/test".to_string())?
+            .with_chunk_hook(Box::new(hook));
+        for i in 0..rows {
+            writer.write_batch(&[entry(i)])?;
+        }
+        Ok(writer.finalize()?)
+    }
+
+    #[test]
+    fn test_expand() {
+        let values = [("chunk", shell_quote("/out/it's here.parquet")), ("rows", "3".to_string())];
+        assert_eq!(
+            expand("rsync -a {chunk} host:/reports/ # {rows} {unknown}", &values),
+            r"rsync -a '/out/it'\''s here.parquet' host:/reports/ # 3 {unknown}"
+        );
+    }
+
+    #[test]
+    fn test_commands_run_per_chunk() {
+        let dir = TempDir::new().unwrap();
+        let log = dir.path().join("log.txt");
+        let template = format!("echo {{chunk_number}} {{rows}} {{chunk}} {{manifest}} >> {}", log.display());
+        let manifest = write_scan(dir.path(), 7, &template, OnError::Fail).unwrap();
+
+        let expected: Vec<String> = manifest.chunks.iter()
+            .map(|c| format!("{} {} {} {}", c.chunk_number, c.row_count, c.file_path,
+                             dir.path().join("scan_manifest.json").display()))
+            .collect();
+        assert_eq!(fs::read_to_string(&log).unwrap().lines().collect::<Vec<_>>(), expected);
+        assert_eq!(manifest.chunks.len(), 3);
+        for chunk in &manifest.chunks {
+            let outcome = chunk.post_chunk.as_ref().unwrap();
+            assert_eq!(outcome.exit_code, Some(0));
+            assert!(outcome.error.is_none());
+        }
+        assert!(manifest.completed);
+    }
+
+    #[test]
+    fn test_failed_command_warns() {
+        let dir = TempDir::new().unwrap();
+        let manifest = write_scan(dir.path(), 7, "exit 3", OnError::Warn).unwrap();
+
+        assert!(manifest.completed);
+        assert!(manifest.chunks.iter().all(|c| c.post_chunk.as_ref().unwrap().exit_code == Some(3)));
+        let saved = ScanManifest::load_from_file(dir.path().join("scan_manifest.json")).unwrap();
+        assert!(saved.chunks.iter().all(|c| c.post_chunk.as_ref().unwrap().exit_code == Some(3)));
+    }
+
+    #[test]
+    fn test_failed_command_fails_scan() {
+        let dir = TempDir::new().unwrap();
+        let err = write_scan(dir.path(), 2, "exit 3", OnError::Fail).unwrap_err();
+        assert!(err.to_string().contains("exited with status 3"), "{}", err);
+
+        // The outcome is kept and the scan left incomplete
+        let saved = ScanManifest::load_from_file(dir.path().join("scan_manifest.json")).unwrap();
+        assert!(!saved.completed);
+        assert_eq!(saved.chunks[0].post_chunk.as_ref().unwrap().exit_code, Some(3));
+    }
+
+    #[test]
+    fn test_post_scan_command() {
+        let dir = TempDir::new().unwrap();
+        let manifest_path = dir.path().join("scan manifest.json");
+        fs::write(&manifest_path, "{}").unwrap();
+        let copy = dir.path().join("published.json");
+
+        let template = format!("cp {{manifest}} {} && test {{rows}} -eq 42", copy.display());
+        let outcome = run_post_scan(&template, &manifest_path, 42, OnError::Fail).unwrap();
+        assert!(outcome.success());
+        assert!(copy.exists());
+
+        let outcome = run_post_scan("exit 1", &manifest_path, 42, OnError::Warn).unwrap();
+        assert_eq!(outcome.exit_code, Some(1));
+        assert!(run_post_scan("exit 1", &manifest_path, 42, OnError::Fail).is_err());
+    }
+}
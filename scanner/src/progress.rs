@@ -0,0 +1,292 @@
+use crate::dir_progress::ProgressEvent;
+#[cfg(feature = "parquet-writer")]
+use {
+    crate::manifest::ScanManifest,
+    crate::shard::Shard,
+    anyhow::{Context, Result},
+    std::path::Path,
+};
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Percentage shown while a scan is still running but has passed its baseline
+pub const OVERRUN_PERCENT: f64 = 99.0;
+
+/// Seconds of history used for the moving-average rate
+const RATE_WINDOW_SECS: f64 = 60.0;
+
+/// Expected size of a scan, taken from a previous scan's manifest
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Baseline {
+    /// Entries written by the previous scan
+    pub total_rows: u64,
+
+    /// Entries written per top-level directory (empty for old manifests)
+    pub dir_rows: BTreeMap<String, u64>,
+}
+
+impl Baseline {
+    #[cfg(feature = "parquet-writer")]
+    pub fn from_manifest(manifest: &ScanManifest) -> Self {
+        Self {
+            total_rows: manifest.total_rows,
+            dir_rows: manifest.dir_stats.iter()
+                .map(|(dir, stats)| (dir.clone(), stats.rows))
+                .collect(),
+        }
+    }
+
+    #[cfg(feature = "parquet-writer")]
+    /// Load the baseline from a manifest file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let manifest = ScanManifest::load_from_file(path)
+            .with_context(|| format!("Failed to load baseline manifest {}", path.display()))?;
+        Ok(Self::from_manifest(&manifest))
+    }
+
+    #[cfg(feature = "parquet-writer")]
+    /// The part of `manifest`'s scan that `shard` covers: all of it when the
+    /// manifest is of the same shard, else the top-level directories the
+    /// shard owns, or an even share when shards split them further down
+    pub fn for_shard(manifest: &ScanManifest, shard: &Shard) -> Self {
+        let baseline = Self::from_manifest(manifest);
+        if manifest.shard.as_ref() == Some(shard) {
+            return baseline;
+        }
+        if shard.depth == 1 && !baseline.dir_rows.is_empty() {
+            let dir_rows: BTreeMap<String, u64> = baseline.dir_rows.into_iter()
+                .filter(|(dir, _)| shard.owns(Path::new(dir)))
+                .collect();
+            return Self { total_rows: dir_rows.values().sum(), dir_rows };
+        }
+        Self { total_rows: baseline.total_rows / shard.count as u64, dir_rows: BTreeMap::new() }
+    }
+
+    /// Entries beyond the baseline, if the scan outgrew it
+    pub fn growth(&self, entries: u64) -> Option<u64> {
+        entries.checked_sub(self.total_rows).filter(|g| *g > 0)
+    }
+}
+
+/// Percent-complete and ETA estimator driven by a baseline
+#[derive(Debug, Clone)]
+pub struct ProgressEstimator {
+    baseline: Baseline,
+
+    /// (elapsed seconds, entries) samples within the rate window
+    samples: VecDeque<(f64, u64)>,
+}
+
+impl ProgressEstimator {
+    pub fn new(baseline: Baseline) -> Self {
+        Self {
+            baseline,
+            samples: VecDeque::new(),
+        }
+    }
+
+    pub fn baseline(&self) -> &Baseline {
+        &self.baseline
+    }
+
+    /// Record the entry count observed after `elapsed_secs`
+    pub fn record(&mut self, elapsed_secs: f64, entries: u64) {
+        self.samples.push_back((elapsed_secs, entries));
+        while self.samples.len() > 2 {
+            match self.samples.front() {
+                Some((t, _)) if elapsed_secs - t > RATE_WINDOW_SECS => {
+                    self.samples.pop_front();
+                }
+                _ => break,
+            }
+        }
+    }
+
+    /// Overall percent complete, capped at 99% once the baseline is exceeded
+    pub fn percent(&self, entries: u64) -> f64 {
+        capped_percent(entries, self.baseline.total_rows)
+    }
+
+    /// Percent complete for one top-level directory, if the baseline knows it
+    pub fn dir_percent(&self, dir: &str, entries: u64) -> Option<f64> {
+        self.baseline.dir_rows.get(dir).map(|total| capped_percent(entries, *total))
+    }
+
+    /// Entries per second over the moving-average window
+    pub fn rate(&self) -> Option<f64> {
+        let (t0, n0) = *self.samples.front()?;
+        let (t1, n1) = *self.samples.back()?;
+        let dt = t1 - t0;
+        if dt <= 0.0 || n1 <= n0 {
+            return None;
+        }
+        Some((n1 - n0) as f64 / dt)
+    }
+
+    /// Estimated seconds remaining, or None without a rate or past the baseline
+    pub fn eta_secs(&self, entries: u64) -> Option<f64> {
+        let remaining = self.baseline.total_rows.checked_sub(entries).filter(|r| *r > 0)?;
+        Some(remaining as f64 / self.rate()?)
+    }
+}
+
+fn capped_percent(entries: u64, total: u64) -> f64 {
+    if total == 0 || entries >= total {
+        return OVERRUN_PERCENT;
+    }
+    (entries as f64 / total as f64 * 100.0).min(OVERRUN_PERCENT)
+}
+
+/// What a long operation shows while it runs: a terminal display with the
+/// `progress` feature, or nothing
+pub(crate) trait Progress {
+    /// Replace the status message
+    fn set_message(&self, message: String);
+
+    /// Time since the display started
+    fn elapsed(&self) -> Duration;
+
+    /// Count one entry of a scan
+    fn event(&mut self, _event: ProgressEvent) {}
+
+    /// Leave `message` as the final status
+    fn finish(&mut self, message: &'static str);
+}
+
+/// Progress that shows nothing
+pub(crate) struct Silent {
+    started: Instant,
+}
+
+impl Silent {
+    pub(crate) fn new() -> Self {
+        Self { started: Instant::now() }
+    }
+}
+
+impl Progress for Silent {
+    fn set_message(&self, _message: String) {}
+
+    fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    fn finish(&mut self, _message: &'static str) {}
+}
+
+#[cfg(feature = "progress")]
+impl Progress for indicatif::ProgressBar {
+    fn set_message(&self, message: String) {
+        indicatif::ProgressBar::set_message(self, message);
+    }
+
+    fn elapsed(&self) -> Duration {
+        indicatif::ProgressBar::elapsed(self)
+    }
+
+    fn finish(&mut self, message: &'static str) {
+        self.finish_with_message(message);
+    }
+}
+
+/// A spinner on stderr if `shown` and the `progress` feature is on
+#[cfg(feature = "parquet-writer")]
+pub(crate) fn spinner(shown: bool) -> Box<dyn Progress> {
+    #[cfg(feature = "progress")]
+    if shown {
+        return Box::new(crate::dir_progress::spinner());
+    }
+    #[cfg(not(feature = "progress"))]
+    let _ = shown;
+    Box::new(Silent::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "parquet-writer")]
+    use crate::manifest::DirStats;
+
+    fn baseline(total: u64, dirs: &[(&str, u64)]) -> Baseline {
+        Baseline {
+            total_rows: total,
+            dir_rows: dirs.iter().map(|(d, n)| (d.to_string(), *n)).collect(),
+        }
+    }
+
+    #[test]
+    fn test_percent_and_cap() {
+        let estimator = ProgressEstimator::new(baseline(1000, &[]));
+
+        assert_eq!(estimator.percent(0), 0.0);
+        assert_eq!(estimator.percent(250), 25.0);
+        assert_eq!(estimator.percent(995), 99.0);
+        assert_eq!(estimator.percent(1000), OVERRUN_PERCENT);
+        assert_eq!(estimator.percent(5000), OVERRUN_PERCENT);
+    }
+
+    #[test]
+    fn test_dir_percent() {
+        let estimator = ProgressEstimator::new(baseline(300, &[("a", 100), ("b", 200)]));
+
+        assert_eq!(estimator.dir_percent("a", 50), Some(50.0));
+        assert_eq!(estimator.dir_percent("b", 300), Some(OVERRUN_PERCENT));
+        assert_eq!(estimator.dir_percent("new", 10), None);
+    }
+
+    #[test]
+    fn test_eta_from_moving_average() {
+        let mut estimator = ProgressEstimator::new(baseline(10_000, &[]));
+        assert_eq!(estimator.eta_secs(0), None);
+
+        // Slow start falls out of the window once the rate settles
+        estimator.record(0.0, 0);
+        estimator.record(10.0, 100);
+        for t in 1..=10 {
+            estimator.record(10.0 + t as f64 * 10.0, 100 + t * 1000);
+        }
+
+        assert_eq!(estimator.rate(), Some(100.0));
+        assert_eq!(estimator.eta_secs(5000), Some(50.0));
+        assert_eq!(estimator.eta_secs(10_100), None);
+    }
+
+    #[test]
+    fn test_growth() {
+        let base = baseline(1000, &[]);
+        assert_eq!(base.growth(900), None);
+        assert_eq!(base.growth(1000), None);
+        assert_eq!(base.growth(1200), Some(200));
+    }
+
+    #[cfg(feature = "parquet-writer")]
+    #[test]
+    fn test_baseline_from_manifest() {
+        let mut manifest = ScanManifest::new("/scan".to_string());
+        manifest.total_rows = 42;
+        manifest.dir_stats.insert("a".to_string(), DirStats { rows: 40, bytes: 1 });
+
+        let base = Baseline::from_manifest(&manifest);
+        assert_eq!(base, baseline(42, &[("a", 40)]));
+    }
+
+    #[cfg(feature = "parquet-writer")]
+    #[test]
+    fn test_baseline_for_shard() {
+        let mut manifest = ScanManifest::new("/scan".to_string());
+        manifest.total_rows = 600;
+        for dir in ["labA", "labB", "labC"] {
+            manifest.dir_stats.insert(dir.to_string(), DirStats { rows: 200, bytes: 1 });
+        }
+        // labB goes to shard 0 of 2, labA and labC to shard 1
+        let shard = Shard::new(1, 2, 1).unwrap();
+        assert_eq!(Baseline::for_shard(&manifest, &shard), baseline(400, &[("labA", 200), ("labC", 200)]));
+
+        let deeper = Shard::new(0, 3, 2).unwrap();
+        assert_eq!(Baseline::for_shard(&manifest, &deeper), baseline(200, &[]));
+
+        manifest.shard = Some(shard);
+        assert_eq!(Baseline::for_shard(&manifest, &shard).total_rows, 600);
+    }
+}
@@ -0,0 +1,950 @@
+use crate::aggregate::{conform_chunk_batch, reconcile_schemas, with_raw_mode};
+use crate::utils;
+use crate::writer::{schema_version, schema_version_entry};
+use anyhow::{Context, Result};
+use arrow::array::{Array, BooleanArray, StringArray, UInt64Array};
+use arrow::compute::kernels::comparison::{
+    eq_dyn_scalar, eq_dyn_utf8_scalar, gt_dyn_scalar, gt_eq_dyn_scalar, like_utf8_scalar, lt_dyn_scalar,
+    lt_eq_dyn_scalar, neq_dyn_scalar, neq_dyn_utf8_scalar, starts_with_utf8_scalar,
+};
+use arrow::compute::{and_kleene, cast, filter_record_batch, not, or_kleene, sum};
+use arrow::datatypes::{DataType, Schema, SchemaRef};
+use arrow::record_batch::RecordBatch;
+use arrow::{csv, ipc, json};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ArrowWriter;
+use parquet::file::properties::WriterProperties;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How a column's values are written in an expression
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColumnKind {
+    /// Compared with quoted strings
+    Text,
+
+    /// Compared with numbers, optionally with a size unit (10GB)
+    Number,
+
+    /// Unix timestamps, compared with numbers, dates, or `now-365d`
+    Time,
+}
+
+/// Columns an expression may refer to
+const COLUMNS: [(&str, ColumnKind); 19] = [
+    ("path", ColumnKind::Text),
+    ("size", ColumnKind::Number),
+    ("modified_time", ColumnKind::Time),
+    ("accessed_time", ColumnKind::Time),
+    ("created_time", ColumnKind::Time),
+    ("file_type", ColumnKind::Text),
+    ("inode", ColumnKind::Number),
+    ("permissions", ColumnKind::Number),
+    ("uid", ColumnKind::Number),
+    ("gid", ColumnKind::Number),
+    ("owner", ColumnKind::Text),
+    ("group", ColumnKind::Text),
+    ("parent_path", ColumnKind::Text),
+    ("depth", ColumnKind::Number),
+    ("top_level_dir", ColumnKind::Text),
+    ("link_target", ColumnKind::Text),
+    ("device", ColumnKind::Number),
+    ("nlink", ColumnKind::Number),
+    ("raw_mode", ColumnKind::Number),
+];
+
+/// Comparison between a column and a literal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+
+    /// Text starting with the literal
+    StartsWith,
+
+    /// Text matching a glob pattern, where `*` is any run of characters and `?` is one
+    Glob,
+}
+
+impl Op {
+    fn is_ordering(&self) -> bool {
+        matches!(self, Op::Lt | Op::Le | Op::Gt | Op::Ge)
+    }
+}
+
+/// Right-hand side of a comparison, with sizes and dates already resolved
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Literal {
+    Text(String),
+    Number(i64),
+}
+
+/// A parsed `--where` expression
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Compare { column: String, op: Op, value: Literal },
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+}
+
+/// An invalid expression, pointing at the offending token
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub message: String,
+
+    pub input: String,
+
+    /// Byte range of the offending token in `input`
+    pub start: usize,
+    pub end: usize,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pad = self.input[..self.start].chars().count();
+        let width = self.input[self.start..self.end].chars().count().max(1);
+        write!(f, "{}\n  {}\n  {}{}", self.message, self.input, " ".repeat(pad), "^".repeat(width))
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+
+    /// Digits, possibly with a fraction and a unit: 10, 1.5TiB, 365d
+    Number(String),
+
+    Str(String),
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+    Not,
+    Plus,
+    Minus,
+    LParen,
+    RParen,
+    End,
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Token::Ident(name) => format!("'{}'", name),
+            Token::Number(text) => format!("'{}'", text),
+            Token::Str(text) => format!("'{}'", text),
+            Token::End => "end of expression".to_string(),
+            Token::Eq => "'=='".to_string(),
+            Token::Ne => "'!='".to_string(),
+            Token::Lt => "'<'".to_string(),
+            Token::Le => "'<='".to_string(),
+            Token::Gt => "'>'".to_string(),
+            Token::Ge => "'>='".to_string(),
+            Token::And => "'&&'".to_string(),
+            Token::Or => "'||'".to_string(),
+            Token::Not => "'!'".to_string(),
+            Token::Plus => "'+'".to_string(),
+            Token::Minus => "'-'".to_string(),
+            Token::LParen => "'('".to_string(),
+            Token::RParen => "')'".to_string(),
+        }
+    }
+}
+
+/// A token and its byte range in the input
+#[derive(Debug, Clone)]
+struct Spanned {
+    token: Token,
+    start: usize,
+    end: usize,
+}
+
+fn tokenize(input: &str) -> Result<Vec<Spanned>, ParseError> {
+    let error = |message: String, start: usize, end: usize| ParseError {
+        message,
+        input: input.to_string(),
+        start,
+        end,
+    };
+
+    let bytes = input.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        let start = i;
+        let token = match c {
+            b' ' | b'\t' | b'\n' | b'\r' => {
+                i += 1;
+                continue;
+            }
+            b'\'' | b'"' => {
+                let close = input[i + 1..].find(c as char)
+                    .ok_or_else(|| error("Unterminated string".to_string(), start, input.len()))?;
+                i += close + 2;
+                Token::Str(input[start + 1..i - 1].to_string())
+            }
+            b'0'..=b'9' => {
+                while i < bytes.len() && (bytes[i].is_ascii_digit() || bytes[i] == b'.') {
+                    i += 1;
+                }
+                while i < bytes.len() && bytes[i].is_ascii_alphabetic() {
+                    i += 1;
+                }
+                Token::Number(input[start..i].to_string())
+            }
+            b'a'..=b'z' | b'A'..=b'Z' | b'_' => {
+                while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                    i += 1;
+                }
+                Token::Ident(input[start..i].to_string())
+            }
+            _ => {
+                let two = input.get(i..i + 2).unwrap_or("");
+                let (token, len) = match (two, c) {
+                    ("==", _) => (Token::Eq, 2),
+                    ("!=", _) => (Token::Ne, 2),
+                    ("<=", _) => (Token::Le, 2),
+                    (">=", _) => (Token::Ge, 2),
+                    ("&&", _) => (Token::And, 2),
+                    ("||", _) => (Token::Or, 2),
+                    (_, b'<') => (Token::Lt, 1),
+                    (_, b'>') => (Token::Gt, 1),
+                    (_, b'!') => (Token::Not, 1),
+                    (_, b'+') => (Token::Plus, 1),
+                    (_, b'-') => (Token::Minus, 1),
+                    (_, b'(') => (Token::LParen, 1),
+                    (_, b')') => (Token::RParen, 1),
+                    (_, b'=') => return Err(error("Use '==' to compare".to_string(), start, start + 1)),
+                    (_, b'&') => return Err(error("Use '&&' for and".to_string(), start, start + 1)),
+                    (_, b'|') => return Err(error("Use '||' for or".to_string(), start, start + 1)),
+                    _ => {
+                        let len = input[i..].chars().next().map_or(1, char::len_utf8);
+                        let found = &input[i..i + len];
+                        return Err(error(format!("Unexpected character '{}'", found), start, start + len));
+                    }
+                };
+                i += len;
+                token
+            }
+        };
+        tokens.push(Spanned { token, start, end: i });
+    }
+    tokens.push(Spanned { token: Token::End, start: input.len(), end: input.len() });
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    tokens: Vec<Spanned>,
+    position: usize,
+
+    /// Unix timestamp `now` stands for
+    now: i64,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> &Spanned {
+        &self.tokens[self.position]
+    }
+
+    fn next(&mut self) -> Spanned {
+        let token = self.tokens[self.position].clone();
+        if token.token != Token::End {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn error_at(&self, token: &Spanned, message: String) -> ParseError {
+        ParseError {
+            message,
+            input: self.input.to_string(),
+            start: token.start,
+            end: token.end,
+        }
+    }
+
+    fn or_expr(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.and_expr()?;
+        while self.peek().token == Token::Or {
+            self.next();
+            expr = Expr::Or(Box::new(expr), Box::new(self.and_expr()?));
+        }
+        Ok(expr)
+    }
+
+    fn and_expr(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.unary()?;
+        while self.peek().token == Token::And {
+            self.next();
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        match self.peek().token {
+            Token::Not => {
+                self.next();
+                Ok(Expr::Not(Box::new(self.unary()?)))
+            }
+            Token::LParen => {
+                let open = self.next();
+                let expr = self.or_expr()?;
+                let close = self.next();
+                if close.token != Token::RParen {
+                    let message = match close.token {
+                        Token::End => "Unclosed '('".to_string(),
+                        _ => format!("Expected ')' but found {}", close.token.describe()),
+                    };
+                    return Err(self.error_at(if close.token == Token::End { &open } else { &close }, message));
+                }
+                Ok(expr)
+            }
+            _ => self.comparison(),
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Expr, ParseError> {
+        let name = self.next();
+        let Token::Ident(ref column) = name.token else {
+            return Err(self.error_at(&name, format!("Expected a column name but found {}", name.token.describe())));
+        };
+        let Some(&(_, kind)) = COLUMNS.iter().find(|(c, _)| c == column) else {
+            let known: Vec<&str> = COLUMNS.iter().map(|(c, _)| *c).collect();
+            return Err(self.error_at(&name, format!("Unknown column '{}' (known columns: {})", column, known.join(", "))));
+        };
+
+        let op_token = self.next();
+        let op = match op_token.token {
+            Token::Eq => Op::Eq,
+            Token::Ne => Op::Ne,
+            Token::Lt => Op::Lt,
+            Token::Le => Op::Le,
+            Token::Gt => Op::Gt,
+            Token::Ge => Op::Ge,
+            Token::Ident(ref word) if word == "startswith" => Op::StartsWith,
+            Token::Ident(ref word) if word == "glob" => Op::Glob,
+            ref other => {
+                return Err(self.error_at(&op_token, format!(
+                    "Expected a comparison (==, !=, <, <=, >, >=, startswith, glob) after '{}' but found {}",
+                    column, other.describe()
+                )));
+            }
+        };
+        match kind {
+            ColumnKind::Text if op.is_ordering() => {
+                return Err(self.error_at(&op_token, format!(
+                    "'{}' is text; compare it with ==, !=, startswith, or glob", column
+                )));
+            }
+            ColumnKind::Number | ColumnKind::Time if matches!(op, Op::StartsWith | Op::Glob) => {
+                return Err(self.error_at(&op_token, format!(
+                    "'{}' is numeric; compare it with ==, !=, <, <=, >, or >=", column
+                )));
+            }
+            _ => {}
+        }
+
+        let value = match kind {
+            ColumnKind::Text => self.text(column)?,
+            ColumnKind::Number => self.number(column)?,
+            ColumnKind::Time => self.time(column)?,
+        };
+        Ok(Expr::Compare { column: column.clone(), op, value })
+    }
+
+    fn text(&mut self, column: &str) -> Result<Literal, ParseError> {
+        let token = self.next();
+        match token.token {
+            Token::Str(ref text) => Ok(Literal::Text(text.clone())),
+            _ => Err(self.error_at(&token, format!(
+                "Expected a quoted string to compare '{}' with but found {}", column, token.token.describe()
+            ))),
+        }
+    }
+
+    fn number(&mut self, column: &str) -> Result<Literal, ParseError> {
+        let token = self.next();
+        let Token::Number(ref text) = token.token else {
+            return Err(self.error_at(&token, format!(
+                "Expected a number to compare '{}' with but found {}", column, token.token.describe()
+            )));
+        };
+        let value = utils::parse_bytes(text)
+            .map_err(|e| self.error_at(&token, e.to_string()))?;
+        let value = i64::try_from(value)
+            .map_err(|_| self.error_at(&token, format!("'{}' is too large", text)))?;
+        Ok(Literal::Number(value))
+    }
+
+    /// `now`, `now-365d`, `now + 1h`, a Unix timestamp, or a quoted date
+    fn time(&mut self, column: &str) -> Result<Literal, ParseError> {
+        let token = self.next();
+        match token.token {
+            Token::Ident(ref word) if word == "now" => {
+                let sign = match self.peek().token {
+                    Token::Minus => -1,
+                    Token::Plus => 1,
+                    _ => return Ok(Literal::Number(self.now)),
+                };
+                self.next();
+                let amount = self.next();
+                let Token::Number(ref text) = amount.token else {
+                    return Err(self.error_at(&amount, format!(
+                        "Expected a duration such as 365d but found {}", amount.token.describe()
+                    )));
+                };
+                let duration = utils::parse_duration(text)
+                    .map_err(|e| self.error_at(&amount, e.to_string()))?;
+                Ok(Literal::Number(self.now + sign * duration.as_secs() as i64))
+            }
+            Token::Number(ref text) => text.parse().map(Literal::Number).map_err(|_| self.error_at(&token, format!(
+                "Expected a Unix timestamp, a quoted date, or now-<duration> but found '{}'", text
+            ))),
+            Token::Str(ref text) => utils::parse_timestamp(text)
+                .map(Literal::Number)
+                .map_err(|e| self.error_at(&token, e.to_string())),
+            _ => Err(self.error_at(&token, format!(
+                "Expected a time to compare '{}' with (now-30d, a Unix timestamp, or a quoted date) but found {}",
+                column, token.token.describe()
+            ))),
+        }
+    }
+}
+
+/// Translate a glob pattern to a SQL LIKE pattern
+fn glob_to_like(pattern: &str) -> String {
+    let mut like = String::with_capacity(pattern.len());
+    for c in pattern.chars() {
+        match c {
+            '*' => like.push('%'),
+            '?' => like.push('_'),
+            '%' | '_' => {
+                like.push('\\');
+                like.push(c);
+            }
+            c => like.push(c),
+        }
+    }
+    like
+}
+
+impl Expr {
+    /// Parse an expression, resolving `now` to the current time
+    pub fn parse(input: &str) -> Result<Self, ParseError> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+        Self::parse_at(input, now)
+    }
+
+    /// Parse an expression, resolving `now` to the Unix timestamp `now`
+    pub fn parse_at(input: &str, now: i64) -> Result<Self, ParseError> {
+        let mut parser = Parser { input, tokens: tokenize(input)?, position: 0, now };
+        let expr = parser.or_expr()?;
+        let rest = parser.next();
+        if rest.token != Token::End {
+            return Err(parser.error_at(&rest, format!(
+                "Expected '&&', '||', or the end of the expression but found {}", rest.token.describe()
+            )));
+        }
+        Ok(expr)
+    }
+
+    /// Columns the expression reads
+    pub fn columns(&self) -> Vec<&str> {
+        let mut columns = Vec::new();
+        self.collect_columns(&mut columns);
+        columns.sort_unstable();
+        columns.dedup();
+        columns
+    }
+
+    fn collect_columns<'a>(&'a self, columns: &mut Vec<&'a str>) {
+        match self {
+            Expr::Compare { column, .. } => columns.push(column),
+            Expr::And(a, b) | Expr::Or(a, b) => {
+                a.collect_columns(columns);
+                b.collect_columns(columns);
+            }
+            Expr::Not(e) => e.collect_columns(columns),
+        }
+    }
+
+    /// Check that the schema has the columns the expression reads
+    pub fn validate(&self, schema: &Schema) -> Result<()> {
+        for column in self.columns() {
+            schema.field_with_name(column)
+                .with_context(|| format!("Query requires a '{}' column", column))?;
+        }
+        Ok(())
+    }
+
+    /// Rows of `batch` the expression holds for; comparisons with null values
+    /// are null, and null rows are not matched
+    pub fn mask(&self, batch: &RecordBatch) -> Result<BooleanArray> {
+        Ok(match self {
+            Expr::Compare { column, op, value } => {
+                let array = batch.column_by_name(column)
+                    .ok_or_else(|| anyhow::anyhow!("Query requires a '{}' column", column))?;
+                match value {
+                    Literal::Text(text) => {
+                        let array = cast(array, &DataType::Utf8)?;
+                        let strings = array.as_any().downcast_ref::<StringArray>().unwrap();
+                        match op {
+                            Op::Eq => eq_dyn_utf8_scalar(strings, text)?,
+                            Op::Ne => neq_dyn_utf8_scalar(strings, text)?,
+                            Op::StartsWith => starts_with_utf8_scalar(strings, text)?,
+                            Op::Glob => like_utf8_scalar(strings, &glob_to_like(text))?,
+                            _ => unreachable!("ordering of text is rejected by the parser"),
+                        }
+                    }
+                    Literal::Number(n) => {
+                        let array = array.as_ref();
+                        match op {
+                            Op::Eq => eq_dyn_scalar(array, *n)?,
+                            Op::Ne => neq_dyn_scalar(array, *n)?,
+                            Op::Lt => lt_dyn_scalar(array, *n)?,
+                            Op::Le => lt_eq_dyn_scalar(array, *n)?,
+                            Op::Gt => gt_dyn_scalar(array, *n)?,
+                            Op::Ge => gt_eq_dyn_scalar(array, *n)?,
+                            _ => unreachable!("text matching of numbers is rejected by the parser"),
+                        }
+                    }
+                }
+            }
+            Expr::And(a, b) => and_kleene(&a.mask(batch)?, &b.mask(batch)?)?,
+            Expr::Or(a, b) => or_kleene(&a.mask(batch)?, &b.mask(batch)?)?,
+            Expr::Not(e) => not(&e.mask(batch)?)?,
+        })
+    }
+}
+
+/// File format of query results
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueryFormat {
+    Parquet,
+    Csv,
+
+    /// One JSON object per line
+    Jsonl,
+
+    /// Arrow IPC file (Feather v2)
+    Arrow,
+}
+
+impl QueryFormat {
+    /// Format named by the output file's extension. CSV and JSONL may be
+    /// gzip-compressed by adding .gz, e.g. out.csv.gz.
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("").to_ascii_lowercase();
+        let (name, gzip) = match name.strip_suffix(".gz") {
+            Some(name) => (name, true),
+            None => (name.as_str(), false),
+        };
+        match Path::new(name).extension().and_then(|e| e.to_str()) {
+            Some("parquet") if gzip => anyhow::bail!("Parquet output is already compressed; drop the .gz: {}", path.display()),
+            Some("parquet") => Ok(QueryFormat::Parquet),
+            Some("csv") => Ok(QueryFormat::Csv),
+            Some("jsonl") | Some("ndjson") => Ok(QueryFormat::Jsonl),
+            Some("arrow") | Some("feather") if gzip => anyhow::bail!("Arrow output cannot be gzip-compressed; drop the .gz: {}", path.display()),
+            Some("arrow") | Some("feather") => Ok(QueryFormat::Arrow),
+            _ => anyhow::bail!("Output must end in .parquet, .csv, .jsonl, or .arrow (CSV and JSONL may add .gz): {}", path.display()),
+        }
+    }
+}
+
+/// Whether output written to `path` is gzip-compressed
+pub fn is_gzip(path: &Path) -> bool {
+    path.extension().and_then(|e| e.to_str()).is_some_and(|e| e.eq_ignore_ascii_case("gz"))
+}
+
+/// Rows read and matched by [`run_query`]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct QuerySummary {
+    pub rows_scanned: u64,
+
+    pub rows_matched: u64,
+
+    /// Combined `size` of the matched rows
+    pub bytes_matched: u64,
+}
+
+/// Destination of CSV and JSONL output
+enum TextSink {
+    Plain(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+}
+
+impl Write for TextSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            TextSink::Plain(w) => w.write(buf),
+            TextSink::Gzip(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            TextSink::Plain(w) => w.flush(),
+            TextSink::Gzip(w) => w.flush(),
+        }
+    }
+}
+
+impl TextSink {
+    fn new(file: File, gzip: bool) -> Self {
+        let file = BufWriter::new(file);
+        if gzip {
+            TextSink::Gzip(GzEncoder::new(file, Compression::default()))
+        } else {
+            TextSink::Plain(file)
+        }
+    }
+
+    /// Write the gzip trailer, if any, and flush
+    fn finish(self) -> io::Result<()> {
+        match self {
+            TextSink::Plain(mut w) => w.flush(),
+            TextSink::Gzip(w) => w.finish()?.flush(),
+        }
+    }
+}
+
+// Only one exists per output, so its size does not matter
+#[allow(clippy::large_enum_variant)]
+enum Encoder {
+    Parquet(ArrowWriter<File>),
+    Csv(csv::Writer<TextSink>),
+    Jsonl(json::LineDelimitedWriter<TextSink>),
+    Arrow(ipc::writer::FileWriter<BufWriter<File>>),
+}
+
+/// Writes record batches to a Parquet, CSV, or JSONL file
+pub(crate) struct ResultWriter {
+    encoder: Encoder,
+    schema: SchemaRef,
+    rows: u64,
+}
+
+impl ResultWriter {
+    /// Create `output` for batches with `schema`, gzip-compressing text
+    /// formats when the name ends in .gz
+    pub(crate) fn create(output: &Path, format: QueryFormat, schema: SchemaRef) -> Result<Self> {
+        Self::with_properties(output, format, schema, None)
+    }
+
+    /// As [`ResultWriter::create`], with `properties` for Parquet output
+    pub(crate) fn with_properties(
+        output: &Path,
+        format: QueryFormat,
+        schema: SchemaRef,
+        properties: Option<WriterProperties>,
+    ) -> Result<Self> {
+        let file = File::create(output)
+            .with_context(|| format!("Failed to create {}", output.display()))?;
+        let gzip = is_gzip(output);
+        let encoder = match format {
+            QueryFormat::Parquet => {
+                let mut writer = ArrowWriter::try_new(file, schema.clone(), properties)?;
+                writer.append_key_value_metadata(schema_version_entry());
+                Encoder::Parquet(writer)
+            }
+            QueryFormat::Csv => Encoder::Csv(csv::WriterBuilder::new().has_headers(true).build(TextSink::new(file, gzip))),
+            QueryFormat::Jsonl => Encoder::Jsonl(json::LineDelimitedWriter::new(TextSink::new(file, gzip))),
+            QueryFormat::Arrow => Encoder::Arrow(ipc::writer::FileWriter::try_new(BufWriter::new(file), &schema)?),
+        };
+        Ok(Self { encoder, schema, rows: 0 })
+    }
+
+    pub(crate) fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        self.rows += batch.num_rows() as u64;
+        match self.encoder {
+            Encoder::Parquet(ref mut w) => w.write(batch)?,
+            Encoder::Csv(ref mut w) => w.write(batch)?,
+            Encoder::Jsonl(ref mut w) => w.write(batch)?,
+            Encoder::Arrow(ref mut w) => w.write(batch)?,
+        }
+        Ok(())
+    }
+
+    /// Complete the file
+    pub(crate) fn finish(self) -> Result<()> {
+        match self.encoder {
+            Encoder::Parquet(w) => {
+                w.close()?;
+            }
+            Encoder::Csv(mut w) => {
+                // The header is written with the first batch, so write one even if nothing matched
+                if self.rows == 0 {
+                    w.write(&RecordBatch::new_empty(self.schema))?;
+                }
+                w.into_inner().finish()?;
+            }
+            Encoder::Jsonl(mut w) => {
+                w.finish()?;
+                w.into_inner().finish()?;
+            }
+            Encoder::Arrow(mut w) => {
+                w.finish()?;
+                w.into_inner()?.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Stream the rows of `chunk_files` that match `expr` to `output`, one record
+/// batch at a time, with the chunks' reconciled schema
+pub fn run_query(chunk_files: &[PathBuf], expr: &Expr, output: &Path, format: QueryFormat) -> Result<QuerySummary> {
+    let mut summary = QuerySummary::default();
+    summary.rows_scanned = write_matching(
+        chunk_files,
+        output,
+        format,
+        |schema| expr.validate(schema),
+        |batch| expr.mask(batch),
+        |matched| {
+            summary.rows_matched += matched.num_rows() as u64;
+            if let Some(sizes) = matched.column_by_name("size") {
+                let sizes = cast(sizes, &DataType::UInt64)?;
+                summary.bytes_matched += sum(sizes.as_any().downcast_ref::<UInt64Array>().unwrap()).unwrap_or(0);
+            }
+            Ok(())
+        },
+    )?;
+    Ok(summary)
+}
+
+/// Write the rows of `chunk_files` selected by `mask` to `output`, passing
+/// each non-empty batch of matches to `on_match` first. Chunks are read in
+/// order, one record batch at a time, and conformed to their reconciled
+/// schema, which `validate` checks before anything is written.
+///
+/// Returns the number of rows read.
+pub(crate) fn write_matching(
+    chunk_files: &[PathBuf],
+    output: &Path,
+    format: QueryFormat,
+    validate: impl FnOnce(&Schema) -> Result<()>,
+    mut mask: impl FnMut(&RecordBatch) -> Result<BooleanArray>,
+    mut on_match: impl FnMut(&RecordBatch) -> Result<()>,
+) -> Result<u64> {
+    let schema = with_raw_mode(reconcile_schemas(chunk_files)?);
+    validate(&schema)?;
+
+    let mut writer = ResultWriter::create(output, format, schema.clone())?;
+
+    let mut rows_scanned = 0;
+    for path in chunk_files {
+        let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let legacy = schema_version(builder.metadata().file_metadata()).is_none();
+        for batch in builder.build()? {
+            let batch = conform_chunk_batch(&batch?, &schema, legacy)?;
+            rows_scanned += batch.num_rows() as u64;
+
+            let matched = filter_record_batch(&batch, &mask(&batch)?)?;
+            if matched.num_rows() == 0 {
+                continue;
+            }
+            on_match(&matched)?;
+            writer.write(&matched)?;
+        }
+    }
+
+    writer.finish()?;
+    Ok(rows_scanned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::FileEntry;
+    use crate::writer::ParquetFileWriter;
+    use tempfile::TempDir;
+
+    const NOW: i64 = 1_700_000_000;
+    const DAY: i64 = 86_400;
+
+    fn parse(input: &str) -> Expr {
+        Expr::parse_at(input, NOW).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    fn compare(column: &str, op: Op, value: Literal) -> Expr {
+        Expr::Compare { column: column.to_string(), op, value }
+    }
+
+    /// The message and the text the caret points at
+    fn parse_error(input: &str) -> (String, String) {
+        let err = Expr::parse_at(input, NOW).unwrap_err();
+        (err.message.clone(), input[err.start..err.end].to_string())
+    }
+
+    #[test]
+    fn test_parse_values_and_precedence() {
+        assert_eq!(
+            parse("file_type == 'bam' && size > 10GB && accessed_time < now-365d"),
+            Expr::And(
+                Box::new(Expr::And(
+                    Box::new(compare("file_type", Op::Eq, Literal::Text("bam".into()))),
+                    Box::new(compare("size", Op::Gt, Literal::Number(10_000_000_000))),
+                )),
+                Box::new(compare("accessed_time", Op::Lt, Literal::Number(NOW - 365 * DAY))),
+            )
+        );
+
+        // && binds tighter than ||; parentheses and ! group
+        assert_eq!(
+            parse("uid == 1 || uid == 2 && !(path startswith \"/tmp\")"),
+            Expr::Or(
+                Box::new(compare("uid", Op::Eq, Literal::Number(1))),
+                Box::new(Expr::And(
+                    Box::new(compare("uid", Op::Eq, Literal::Number(2))),
+                    Box::new(Expr::Not(Box::new(compare("path", Op::StartsWith, Literal::Text("/tmp".into()))))),
+                )),
+            )
+        );
+
+        assert_eq!(parse("size>=1.5KiB"), compare("size", Op::Ge, Literal::Number(1536)));
+        assert_eq!(parse("modified_time >= now + 1h"), compare("modified_time", Op::Ge, Literal::Number(NOW + 3600)));
+        assert_eq!(parse("modified_time < '2024-01-01'"), compare("modified_time", Op::Lt, Literal::Number(1_704_067_200)));
+        assert_eq!(parse("created_time != 0"), compare("created_time", Op::Ne, Literal::Number(0)));
+        assert_eq!(parse("path glob '*.fastq'").columns(), ["path"]);
+    }
+
+    #[test]
+    fn test_parse_errors_point_at_token() {
+        let cases = [
+            ("sizee > 10GB", "Unknown column 'sizee'", "sizee"),
+            ("size > 10XB", "Unknown size unit", "10XB"),
+            ("size = 10", "Use '=='", "="),
+            ("path < 'x'", "'path' is text", "<"),
+            ("size glob '*'", "'size' is numeric", "glob"),
+            ("file_type == bam", "Expected a quoted string", "bam"),
+            ("accessed_time < now-soon", "Expected a duration", "soon"),
+            ("accessed_time < now-365q", "Invalid duration", "365q"),
+            ("modified_time < 'yesterday'", "Invalid date", "'yesterday'"),
+            ("path == 'a' size > 1", "Expected '&&', '||'", "size"),
+            ("(size > 1", "Unclosed '('", "("),
+            ("path == 'a", "Unterminated string", "'a"),
+            ("size > 1 & uid == 2", "Use '&&'", "&"),
+            ("", "Expected a column name", ""),
+        ];
+        for (input, message, token) in cases {
+            let (actual_message, actual_token) = parse_error(input);
+            assert!(actual_message.contains(message), "{}: {}", input, actual_message);
+            assert_eq!(actual_token, token, "{}", input);
+        }
+
+        let err = Expr::parse_at("size > 1 && sizee > 2", NOW).unwrap_err();
+        assert_eq!(err.to_string().lines().nth(2), Some("              ^^^^^"));
+    }
+
+    fn entry(path: &str, size: u64, file_type: &str, accessed_days_ago: i64) -> FileEntry {
+        FileEntry {
+            size,
+            modified_time: NOW - accessed_days_ago * DAY,
+            accessed_time: NOW - accessed_days_ago * DAY,
+            file_type: file_type.to_string(),
+            ..FileEntry::fixture(path)
+        }
+    }
+
+    fn fixture(dir: &Path) -> Vec<PathBuf> {
+        let chunks = [
+            vec![
+                entry("/project/labX/old.bam", 20_000_000_000, "bam", 400),
+                entry("/project/labX/new.bam", 30_000_000_000, "bam", 10),
+                entry("/project/labX/small.bam", 1_000, "bam", 400),
+            ],
+            vec![
+                entry("/project/labY/old.bam", 20_000_000_000, "bam", 400),
+                entry("/project/labX/reads_R1.fastq", 5_000, "fastq", 400),
+                entry("/project/labX/readsXR1.fastq", 5_000, "fastq", 400),
+            ],
+        ];
+        chunks.iter().enumerate().map(|(i, entries)| {
+            let path = dir.join(format!("scan_chunk_{:04}.parquet", i + 1));
+            let mut writer = ParquetFileWriter::new(&path).unwrap();
+            writer.write_batch(entries).unwrap();
+            writer.close().unwrap();
+            path
+        }).collect()
+    }
+
+    fn matched_paths(chunks: &[PathBuf], expr: &str, output: &Path) -> Vec<String> {
+        run_query(chunks, &parse(expr), output, QueryFormat::Parquet).unwrap();
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(output).unwrap()).unwrap().build().unwrap();
+        let mut paths = Vec::new();
+        for batch in reader {
+            let batch = batch.unwrap();
+            let column = batch.column_by_name("path").unwrap();
+            let column = column.as_any().downcast_ref::<StringArray>().unwrap();
+            paths.extend(column.iter().flatten().map(str::to_string));
+        }
+        paths
+    }
+
+    #[test]
+    fn test_query_filters_across_chunks() {
+        let temp_dir = TempDir::new().unwrap();
+        let chunks = fixture(temp_dir.path());
+        let output = temp_dir.path().join("result.parquet");
+
+        let expr = "file_type == 'bam' && size > 10GB && accessed_time < now-365d && path startswith '/project/labX/'";
+        assert_eq!(matched_paths(&chunks, expr, &output), ["/project/labX/old.bam"]);
+
+        // '_' in a glob is literal, '?' is any one character
+        assert_eq!(matched_paths(&chunks, "path glob '*_R1.fastq'", &output), ["/project/labX/reads_R1.fastq"]);
+        assert_eq!(matched_paths(&chunks, "path glob '*reads?R1.*'", &output).len(), 2);
+
+        assert_eq!(
+            matched_paths(&chunks, "!(file_type == 'bam') || (top_level_dir == 'labY')", &output),
+            ["/project/labY/old.bam", "/project/labX/reads_R1.fastq", "/project/labX/readsXR1.fastq"]
+        );
+
+        // Null owners match neither a comparison nor its negation
+        assert!(matched_paths(&chunks, "owner == 'alice' || !(owner == 'alice')", &output).is_empty());
+    }
+
+    #[test]
+    fn test_query_csv_summary_and_missing_column() {
+        let temp_dir = TempDir::new().unwrap();
+        let chunks = fixture(temp_dir.path());
+        let output = temp_dir.path().join("result.csv");
+
+        let summary = run_query(&chunks, &parse("size >= 20GB"), &output, QueryFormat::Csv).unwrap();
+        assert_eq!(summary, QuerySummary { rows_scanned: 6, rows_matched: 3, bytes_matched: 70_000_000_000 });
+        let csv = std::fs::read_to_string(&output).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert!(lines[0].starts_with("path,size,"));
+        assert_eq!(lines.len(), 4);
+        assert!(lines[2].starts_with("/project/labX/new.bam,30000000000,"));
+
+        assert_eq!(QueryFormat::from_path(Path::new("out.CSV")).unwrap(), QueryFormat::Csv);
+        assert!(QueryFormat::from_path(Path::new("out.json")).is_err());
+
+        // Chunks written before a column existed cannot be queried on it
+        let schema = Schema::new(vec![arrow::datatypes::Field::new("path", DataType::Utf8, false)]);
+        let err = parse("size > 1").validate(&schema).unwrap_err();
+        assert!(err.to_string().contains("'size'"), "{}", err);
+    }
+}
@@ -0,0 +1,440 @@
+use crate::report::read_columns;
+use crate::utils;
+use anyhow::{Context, Result};
+use arrow::array::{Array, StringArray, UInt64Array};
+use arrow::compute::cast;
+use arrow::datatypes::{DataType, Schema};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn default_warn_percent() -> f64 {
+    80.0
+}
+
+fn default_critical_percent() -> f64 {
+    90.0
+}
+
+/// A `quotas.toml` file: default thresholds and one `[[quota]]` table per allocation
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QuotaConfig {
+    /// Usage at or above this percentage of a quota is a warning
+    #[serde(default = "default_warn_percent")]
+    pub warn_percent: f64,
+
+    /// Usage at or above this percentage of a quota is critical
+    #[serde(default = "default_critical_percent")]
+    pub critical_percent: f64,
+
+    #[serde(rename = "quota", default)]
+    pub quotas: Vec<Quota>,
+}
+
+/// One allocation, matched either by path prefix or by top-level directory
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Quota {
+    /// Label in the report (default: the path or top-level directory)
+    pub name: Option<String>,
+
+    /// Directory the quota covers
+    pub path: Option<String>,
+
+    /// Value of the `top_level_dir` column the quota covers
+    pub top_level_dir: Option<String>,
+
+    /// Byte limit (e.g., "100TB")
+    pub bytes: Option<String>,
+
+    /// Entry limit: files and directories
+    pub inodes: Option<u64>,
+
+    /// Overrides the file-wide warning threshold
+    pub warn_percent: Option<f64>,
+
+    /// Overrides the file-wide critical threshold
+    pub critical_percent: Option<f64>,
+}
+
+impl Quota {
+    pub fn label(&self) -> &str {
+        self.name.as_deref()
+            .or(self.path.as_deref())
+            .or(self.top_level_dir.as_deref())
+            .unwrap_or("")
+    }
+
+    fn byte_limit(&self) -> Result<Option<u64>> {
+        self.bytes.as_deref().map(utils::parse_bytes).transpose()
+    }
+}
+
+impl QuotaConfig {
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let contents = fs::read_to_string(path.as_ref())
+            .with_context(|| format!("Failed to read {}", path.as_ref().display()))?;
+        let config: Self = toml::from_str(&contents)
+            .with_context(|| format!("Failed to parse {}", path.as_ref().display()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.quotas.is_empty() {
+            anyhow::bail!("No [[quota]] entries in the quota file");
+        }
+        for (i, quota) in self.quotas.iter().enumerate() {
+            let label = quota.label();
+            match (&quota.path, &quota.top_level_dir) {
+                (Some(path), None) if path.starts_with('/') => {}
+                (Some(path), None) => anyhow::bail!("Quota '{}': path '{}' must be absolute", label, path),
+                (None, Some(_)) => {}
+                _ => anyhow::bail!("Quota {} must set exactly one of path and top_level_dir", i + 1),
+            }
+            if self.quotas[..i].iter().any(|other| other.label() == label) {
+                anyhow::bail!("Duplicate quota '{}'", label);
+            }
+            if quota.bytes.is_none() && quota.inodes.is_none() {
+                anyhow::bail!("Quota '{}' sets neither bytes nor inodes", label);
+            }
+            quota.byte_limit().with_context(|| format!("Invalid byte limit for quota '{}'", label))?;
+            let (warn, critical) = self.thresholds(quota);
+            if !(0.0..=critical).contains(&warn) {
+                anyhow::bail!("Quota '{}': warn_percent ({}) must be between 0 and critical_percent ({})",
+                              label, warn, critical);
+            }
+        }
+        Ok(())
+    }
+
+    /// (warn, critical) percentages of a quota
+    fn thresholds(&self, quota: &Quota) -> (f64, f64) {
+        (
+            quota.warn_percent.unwrap_or(self.warn_percent),
+            quota.critical_percent.unwrap_or(self.critical_percent),
+        )
+    }
+
+    fn uses_top_level_dir(&self) -> bool {
+        self.quotas.iter().any(|q| q.top_level_dir.is_some())
+    }
+}
+
+/// Which quota an entry is charged to: the one with the longest path prefix
+/// containing it, else the one naming its top-level directory
+struct Matcher<'a> {
+    /// (prefix without a trailing '/', quota index), longest first
+    prefixes: Vec<(&'a str, usize)>,
+    top_level_dirs: Vec<(&'a str, usize)>,
+}
+
+impl<'a> Matcher<'a> {
+    fn new(config: &'a QuotaConfig) -> Self {
+        let mut prefixes: Vec<(&str, usize)> = config.quotas.iter().enumerate()
+            .filter_map(|(i, q)| q.path.as_deref().map(|p| (p.trim_end_matches('/'), i)))
+            .collect();
+        prefixes.sort_by_key(|p| std::cmp::Reverse(p.0.len()));
+        let top_level_dirs = config.quotas.iter().enumerate()
+            .filter_map(|(i, q)| q.top_level_dir.as_deref().map(|d| (d, i)))
+            .collect();
+        Self { prefixes, top_level_dirs }
+    }
+
+    fn find(&self, path: &str, top_level_dir: Option<&str>) -> Option<usize> {
+        self.prefixes.iter()
+            .find(|(prefix, _)| utils::is_within(path, prefix))
+            .or_else(|| self.top_level_dirs.iter().find(|(dir, _)| Some(*dir) == top_level_dir))
+            .map(|(_, i)| *i)
+    }
+}
+
+/// (bytes, inodes) per quota, in config order
+type Usage = Vec<(u64, u64)>;
+
+fn validate_schema(schema: &Schema, config: &QuotaConfig) -> Result<()> {
+    let mut columns = vec!["path", "size", "file_type"];
+    if config.uses_top_level_dir() {
+        columns.push("top_level_dir");
+    }
+    for column in columns {
+        schema.field_with_name(column)
+            .with_context(|| format!("Checking quotas requires a '{}' column", column))?;
+    }
+    Ok(())
+}
+
+fn chunk_usage(path: &Path, config: &QuotaConfig, matcher: &Matcher) -> Result<Usage> {
+    let mut usage = vec![(0, 0); config.quotas.len()];
+    let mut columns = vec!["path", "size", "file_type"];
+    if config.uses_top_level_dir() {
+        columns.push("top_level_dir");
+    }
+    for batch in read_columns(path, &columns, |schema| validate_schema(schema, config))? {
+        let batch = batch?;
+        let strings = |name: &str| -> Result<Option<StringArray>> {
+            Ok(batch.column_by_name(name)
+                .map(|c| cast(c, &DataType::Utf8)).transpose()?
+                .map(|c| c.as_any().downcast_ref::<StringArray>().unwrap().clone()))
+        };
+        let paths = strings("path")?.unwrap();
+        let types = strings("file_type")?.unwrap();
+        let dirs = strings("top_level_dir")?;
+        let sizes = cast(batch.column_by_name("size").unwrap(), &DataType::UInt64)?;
+        let sizes = sizes.as_any().downcast_ref::<UInt64Array>().unwrap();
+
+        for i in 0..batch.num_rows() {
+            if paths.is_null(i) {
+                continue;
+            }
+            let dir = dirs.as_ref().filter(|d| d.is_valid(i)).map(|d| d.value(i));
+            let Some(quota) = matcher.find(paths.value(i), dir) else { continue };
+            let is_dir = types.is_valid(i) && types.value(i) == "directory";
+            if !is_dir && sizes.is_valid(i) {
+                usage[quota].0 += sizes.value(i);
+            }
+            usage[quota].1 += 1;
+        }
+    }
+    Ok(usage)
+}
+
+/// Outcome of one quota, or of the whole check (the worst quota)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaLevel {
+    Ok,
+    Warn,
+    Critical,
+}
+
+impl QuotaLevel {
+    /// Nagios-style exit code: 0, 1, or 2
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            QuotaLevel::Ok => 0,
+            QuotaLevel::Warn => 1,
+            QuotaLevel::Critical => 2,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QuotaLevel::Ok => "OK",
+            QuotaLevel::Warn => "WARN",
+            QuotaLevel::Critical => "CRITICAL",
+        }
+    }
+}
+
+/// Usage of one quota
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QuotaStatus {
+    pub name: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_level_dir: Option<String>,
+
+    pub bytes_used: u64,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes_limit: Option<u64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes_percent: Option<f64>,
+
+    pub inodes_used: u64,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inodes_limit: Option<u64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inodes_percent: Option<f64>,
+
+    pub warn_percent: f64,
+
+    pub critical_percent: f64,
+
+    /// From the higher of the byte and inode percentages
+    pub status: QuotaLevel,
+}
+
+/// Result of [`check_quotas`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QuotaReport {
+    /// In config order
+    pub quotas: Vec<QuotaStatus>,
+
+    /// Worst status of any quota
+    pub status: QuotaLevel,
+}
+
+fn level(percent: f64, warn: f64, critical: f64) -> QuotaLevel {
+    if percent >= critical {
+        QuotaLevel::Critical
+    } else if percent >= warn {
+        QuotaLevel::Warn
+    } else {
+        QuotaLevel::Ok
+    }
+}
+
+/// Charge every entry of `chunk_files` to at most one quota and compare each
+/// quota's usage with its limits. Files count toward bytes; files and
+/// directories count toward inodes.
+pub fn check_quotas(chunk_files: &[PathBuf], config: &QuotaConfig) -> Result<QuotaReport> {
+    let matcher = Matcher::new(config);
+    let usage = chunk_files.par_iter()
+        .map(|path| chunk_usage(path, config, &matcher)
+            .with_context(|| format!("Failed to read {}", path.display())))
+        .try_reduce(|| vec![(0, 0); config.quotas.len()], |mut a, b| {
+            for (ours, theirs) in a.iter_mut().zip(b) {
+                ours.0 += theirs.0;
+                ours.1 += theirs.1;
+            }
+            Ok(a)
+        })?;
+
+    let mut quotas = Vec::new();
+    for (quota, (bytes_used, inodes_used)) in config.quotas.iter().zip(usage) {
+        let (warn_percent, critical_percent) = config.thresholds(quota);
+        let bytes_limit = quota.byte_limit()?;
+        let percent = |used: u64, limit: u64| if limit == 0 { f64::INFINITY } else { used as f64 * 100.0 / limit as f64 };
+        let bytes_percent = bytes_limit.map(|limit| percent(bytes_used, limit));
+        let inodes_percent = quota.inodes.map(|limit| percent(inodes_used, limit));
+        let worst = bytes_percent.into_iter().chain(inodes_percent).fold(0.0, f64::max);
+        quotas.push(QuotaStatus {
+            name: quota.label().to_string(),
+            path: quota.path.clone(),
+            top_level_dir: quota.top_level_dir.clone(),
+            bytes_used,
+            bytes_limit,
+            bytes_percent,
+            inodes_used,
+            inodes_limit: quota.inodes,
+            inodes_percent,
+            warn_percent,
+            critical_percent,
+            status: level(worst, warn_percent, critical_percent),
+        });
+    }
+
+    Ok(QuotaReport {
+        status: quotas.iter().map(|q| q.status).max().unwrap_or(QuotaLevel::Ok),
+        quotas,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::FileEntry;
+    use crate::writer::ParquetFileWriter;
+    use tempfile::TempDir;
+
+    fn entry(path: &str, size: u64) -> FileEntry {
+        let entry = if size == 0 { FileEntry::dir_fixture(path) } else { FileEntry::fixture(path) };
+        FileEntry { size, gid: 100, ..entry }
+    }
+
+    fn fixture(dir: &Path) -> Vec<PathBuf> {
+        let path = dir.join("scan.parquet");
+        let mut writer = ParquetFileWriter::new(&path).unwrap();
+        writer.write_batch(&[
+            entry("/project/labA", 0),
+            entry("/project/labA/a.dat", 700),
+            entry("/project/labA/shared", 0),
+            entry("/project/labA/shared/s.dat", 200),
+            entry("/project/labA2/x.dat", 50),
+            entry("/project/labB", 0),
+            entry("/project/labB/b.dat", 950),
+            entry("/project/labC/c.dat", 10),
+        ]).unwrap();
+        writer.close().unwrap();
+        vec![path]
+    }
+
+    fn config(toml: &str) -> QuotaConfig {
+        let config: QuotaConfig = toml::from_str(toml).unwrap();
+        config.validate().unwrap();
+        config
+    }
+
+    #[test]
+    fn test_longest_prefix_wins_then_top_level_dir() {
+        let temp_dir = TempDir::new().unwrap();
+        let chunks = fixture(temp_dir.path());
+        let config = config(r#"
+[[quota]]
+top_level_dir = "labA"
+bytes = "10KB"
+
+[[quota]]
+path = "/project/labA/"
+bytes = "1000"
+
+[[quota]]
+name = "shared"
+path = "/project/labA/shared"
+bytes = "1000"
+inodes = 4
+"#);
+
+        let report = check_quotas(&chunks, &config).unwrap();
+        let usage: Vec<(&str, u64, u64)> = report.quotas.iter()
+            .map(|q| (q.name.as_str(), q.bytes_used, q.inodes_used))
+            .collect();
+        // Everything below a path quota is charged there, never to the
+        // top_level_dir quota; labA2 only shares a string prefix with labA
+        assert_eq!(usage, [("labA", 0, 0), ("/project/labA/", 700, 2), ("shared", 200, 2)]);
+        assert_eq!(report.quotas[2].inodes_percent, Some(50.0));
+        assert_eq!(report.quotas[1].status, QuotaLevel::Ok);
+        assert_eq!(report.status, QuotaLevel::Ok);
+    }
+
+    #[test]
+    fn test_status_tiers_and_exit_codes() {
+        let temp_dir = TempDir::new().unwrap();
+        let chunks = fixture(temp_dir.path());
+        let check = |toml: &str| check_quotas(&chunks, &config(toml)).unwrap();
+
+        // labB holds 950 bytes in 2 entries
+        let ok = check("[[quota]]\ntop_level_dir = \"labB\"\nbytes = \"2000\"\n");
+        assert_eq!((ok.status, ok.status.exit_code()), (QuotaLevel::Ok, 0));
+
+        let warn = check("[[quota]]\ntop_level_dir = \"labB\"\nbytes = \"1100\"\ninodes = 10\n");
+        assert_eq!((warn.status, warn.status.exit_code()), (QuotaLevel::Warn, 1));
+        let warn = check("critical_percent = 96\n[[quota]]\ntop_level_dir = \"labB\"\nbytes = \"1000\"\n");
+        assert_eq!(warn.quotas[0].bytes_percent, Some(95.0));
+        assert_eq!(warn.status, QuotaLevel::Warn);
+
+        // Critical on inodes alone; the worst quota decides the overall status
+        let critical = check("[[quota]]\ntop_level_dir = \"labB\"\ninodes = 2\n\n\
+                              [[quota]]\ntop_level_dir = \"labC\"\nbytes = \"1000\"\n");
+        assert_eq!(critical.quotas[0].status, QuotaLevel::Critical);
+        assert_eq!(critical.quotas[1].status, QuotaLevel::Ok);
+        assert_eq!((critical.status, critical.status.exit_code()), (QuotaLevel::Critical, 2));
+
+        // Exactly at a per-quota warning threshold warns
+        let at = check("[[quota]]\ntop_level_dir = \"labB\"\nbytes = \"1900\"\nwarn_percent = 50\n");
+        assert_eq!(at.status, QuotaLevel::Warn);
+    }
+
+    #[test]
+    fn test_config_validation() {
+        let invalid = |toml: &str| {
+            let config: QuotaConfig = toml::from_str(toml).unwrap();
+            config.validate().unwrap_err().to_string()
+        };
+        assert!(invalid("").contains("No [[quota]]"));
+        assert!(invalid("[[quota]]\npath = \"/a\"\ntop_level_dir = \"a\"\nbytes = \"1\"\n").contains("exactly one"));
+        assert!(invalid("[[quota]]\npath = \"a\"\nbytes = \"1\"\n").contains("absolute"));
+        assert!(invalid("[[quota]]\npath = \"/a\"\n").contains("neither"));
+        assert!(invalid("warn_percent = 95\n[[quota]]\npath = \"/a\"\ninodes = 1\n").contains("warn_percent"));
+    }
+}
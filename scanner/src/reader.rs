@@ -0,0 +1,264 @@
+//! Reading scan output back into [`FileEntry`] records.
+//!
+//! Files written before a column was added to the schema are read with that
+//! column's values empty: `None` for optional fields and zero otherwise.
+
+use crate::error::{self, Error};
+use crate::manifest::ScanManifest;
+use crate::models::FileEntry;
+use crate::writer::entries_from_batch;
+use parquet::arrow::arrow_reader::{ParquetRecordBatchReader, ParquetRecordBatchReaderBuilder};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// Rows per batch yielded by [`ParquetEntryReader`]
+pub const READ_BATCH_SIZE: usize = 8192;
+
+/// Batches of entries from one Parquet file, as written by a scan
+pub struct ParquetEntryReader {
+    path: PathBuf,
+    rows: u64,
+    batches: ParquetRecordBatchReader,
+}
+
+impl ParquetEntryReader {
+    pub fn open<P: AsRef<Path>>(path: P) -> error::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = File::open(&path).map_err(|source| Error::InputIo { path: path.clone(), source })?;
+        let builder = ParquetRecordBatchReaderBuilder::try_new(file)
+            .map_err(Error::input_corrupt(&path))?;
+        let rows = builder.metadata().file_metadata().num_rows() as u64;
+        let batches = builder.with_batch_size(READ_BATCH_SIZE)
+            .build()
+            .map_err(Error::input_corrupt(&path))?;
+        Ok(Self { path, rows, batches })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Entries in the file, from its footer
+    pub fn rows(&self) -> u64 {
+        self.rows
+    }
+}
+
+impl Iterator for ParquetEntryReader {
+    type Item = error::Result<Vec<FileEntry>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let batch = self.batches.next()?;
+        Some(batch.map_err(Error::input_corrupt(&self.path))
+            .and_then(|batch| entries_from_batch(&batch).map_err(Error::input_corrupt(&self.path))))
+    }
+}
+
+/// Every entry in the Parquet file at `path`, for files that fit in memory
+pub fn read_all<P: AsRef<Path>>(path: P) -> error::Result<Vec<FileEntry>> {
+    let reader = ParquetEntryReader::open(path)?;
+    let mut entries = Vec::with_capacity(reader.rows() as usize);
+    for batch in reader {
+        entries.extend(batch?);
+    }
+    Ok(entries)
+}
+
+/// Batches of entries from every chunk of an incremental scan, in chunk
+/// order.
+///
+/// Chunks a retention policy deleted are left out; their entries live on in
+/// the aggregate they went into.
+pub struct ChunkSetReader {
+    chunks: std::vec::IntoIter<PathBuf>,
+    current: Option<ParquetEntryReader>,
+}
+
+impl ChunkSetReader {
+    /// Read the chunks listed in the manifest at `manifest_path`
+    pub fn open<P: AsRef<Path>>(manifest_path: P) -> error::Result<Self> {
+        Ok(Self::from_manifest(&ScanManifest::load_from_file(manifest_path)?))
+    }
+
+    pub fn from_manifest(manifest: &ScanManifest) -> Self {
+        let mut chunks: Vec<_> = manifest.chunks.iter().filter(|c| !c.is_deleted()).collect();
+        chunks.sort_by_key(|c| c.chunk_number);
+        let chunks: Vec<PathBuf> = chunks.into_iter().map(|c| PathBuf::from(&c.file_path)).collect();
+        Self { chunks: chunks.into_iter(), current: None }
+    }
+
+    /// The chunk being read, once reading has started
+    pub fn current_chunk(&self) -> Option<&Path> {
+        self.current.as_ref().map(ParquetEntryReader::path)
+    }
+}
+
+impl Iterator for ChunkSetReader {
+    type Item = error::Result<Vec<FileEntry>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(batch) = self.current.as_mut().and_then(Iterator::next) {
+                return Some(batch);
+            }
+            match ParquetEntryReader::open(self.chunks.next()?) {
+                Ok(reader) => self.current = Some(reader),
+                Err(e) => {
+                    self.current = None;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::RotationParams;
+    use crate::models::ScanOptions;
+    use crate::run::{run_scan_to_parquet, SinkConfig};
+    use crate::writer::{entry_columns, ParquetFileWriter, LATER_COLUMNS};
+    use arrow::record_batch::RecordBatch;
+    use parquet::arrow::ArrowWriter;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn entry(i: u64) -> FileEntry {
+        // Every column varies, so a column read back wrong shows
+        FileEntry {
+            size: i * 1000,
+            modified_time: 1_700_000_000 + i as i64,
+            accessed_time: 1_700_100_000 + i as i64,
+            created_time: i.is_multiple_of(2).then_some(1_600_000_000),
+            file_type: "file".to_string(),
+            inode: 5000 + i,
+            uid: 1000 + i as u32,
+            gid: 100,
+            owner: (i % 2 == 1).then(|| format!("user{}", i)),
+            group: Some("staff".to_string()),
+            link_target: i.is_multiple_of(5).then(|| "/elsewhere".to_string()),
+            device: 64768,
+            nlink: 1 + i % 2,
+            ..FileEntry::fixture(&format!("/data/project{}/file{}.dat", i % 3, i))
+        }
+    }
+
+    fn assert_same(read: &FileEntry, written: &FileEntry) {
+        assert_eq!(read.path, written.path);
+        assert_eq!(read.size, written.size);
+        assert_eq!(read.modified_time, written.modified_time);
+        assert_eq!(read.accessed_time, written.accessed_time);
+        assert_eq!(read.created_time, written.created_time);
+        assert_eq!(read.file_type, written.file_type);
+        assert_eq!(read.inode, written.inode);
+        assert_eq!(read.permissions, written.permissions);
+        assert_eq!(read.uid, written.uid);
+        assert_eq!(read.gid, written.gid);
+        assert_eq!(read.owner, written.owner);
+        assert_eq!(read.group, written.group);
+        assert_eq!(read.parent_path, written.parent_path);
+        assert_eq!(read.depth, written.depth);
+        assert_eq!(read.top_level_dir, written.top_level_dir);
+        assert_eq!(read.link_target, written.link_target);
+        assert_eq!(read.device, written.device);
+        assert_eq!(read.nlink, written.nlink);
+        assert_eq!(read.raw_mode, written.raw_mode);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("scan.parquet");
+        let entries: Vec<FileEntry> = (0..20_000).map(entry).collect();
+        let mut writer = ParquetFileWriter::new(&path).unwrap();
+        writer.write_batch(&entries).unwrap();
+        writer.close().unwrap();
+
+        let reader = ParquetEntryReader::open(&path).unwrap();
+        assert_eq!(reader.rows(), 20_000);
+        let batches: Vec<_> = reader.map(Result::unwrap).collect();
+        assert!(batches.len() > 1);
+        assert!(batches.iter().all(|b| b.len() <= READ_BATCH_SIZE));
+
+        let read = read_all(&path).unwrap();
+        assert_eq!(read.len(), entries.len());
+        for (read, written) in read.iter().zip(&entries) {
+            assert_same(read, written);
+        }
+    }
+
+    #[test]
+    fn test_reads_files_of_the_earlier_schema() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("old.parquet");
+        // Scans then stored the full mode in `permissions`
+        let entries: Vec<FileEntry> = (0..10)
+            .map(|i| FileEntry { permissions: 0o100644, ..entry(i) })
+            .collect();
+
+        // Write the columns that scans had before the later ones were added
+        let schema = ParquetFileWriter::create_schema();
+        let batch = RecordBatch::try_new(schema.clone(), entry_columns(&entries)).unwrap();
+        let kept: Vec<usize> = (0..schema.fields().len())
+            .filter(|&i| !LATER_COLUMNS.contains(&schema.field(i).name().as_str()))
+            .collect();
+        let batch = batch.project(&kept).unwrap();
+        let mut writer = ArrowWriter::try_new(File::create(&path).unwrap(), batch.schema(), None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+
+        let read = read_all(&path).unwrap();
+        assert_eq!(read.len(), entries.len());
+        for (read, written) in read.iter().zip(&entries) {
+            let expected = FileEntry {
+                permissions: 0o644,
+                link_target: None,
+                device: 0,
+                nlink: 0,
+                raw_mode: 0o100644,
+                ..written.clone()
+            };
+            assert_same(read, &expected);
+        }
+    }
+
+    #[test]
+    fn test_chunk_set_reads_chunks_in_order() {
+        let tree = TempDir::new().unwrap();
+        for i in 0..30 {
+            let dir = tree.path().join(format!("dir{}", i % 3));
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join(format!("file{}", i)), vec![0u8; i]).unwrap();
+        }
+        let out = TempDir::new().unwrap();
+        let output = out.path().join("scan.parquet");
+        let params = RotationParams { rows_per_chunk: 8, chunk_interval_secs: 600 };
+        let options = ScanOptions::builder().batch_size(4).build().unwrap();
+        let result = run_scan_to_parquet(options, tree.path(), SinkConfig::chunked(&output, params)).unwrap();
+        let manifest = result.manifest.unwrap();
+        assert!(manifest.chunks.len() > 1);
+
+        let mut expected = Vec::new();
+        for chunk in &manifest.chunks {
+            expected.extend(read_all(&chunk.file_path).unwrap());
+        }
+        let manifest_path = out.path().join("scan_manifest.json");
+        let read: Vec<FileEntry> = ChunkSetReader::open(&manifest_path).unwrap()
+            .flat_map(Result::unwrap)
+            .collect();
+        assert_eq!(read.len() as u64, manifest.total_rows);
+        assert_eq!(read, expected);
+    }
+
+    #[test]
+    fn test_read_errors() {
+        let temp_dir = TempDir::new().unwrap();
+        let missing = temp_dir.path().join("missing.parquet");
+        assert!(matches!(read_all(&missing), Err(Error::InputIo { ref path, .. }) if *path == missing));
+
+        let text = temp_dir.path().join("notes.parquet");
+        fs::write(&text, "not parquet").unwrap();
+        assert!(matches!(ParquetEntryReader::open(&text), Err(Error::InputCorrupt { .. })));
+    }
+}
@@ -0,0 +1,511 @@
+use crate::manifest::{ChunkMetadata, ChunkUpload, ScanManifest};
+use crate::rotating_writer::ChunkHook;
+use crate::utils::file_checksum;
+use anyhow::{Context, Result};
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use reqwest::blocking::{Body, Client};
+use reqwest::Url;
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+/// A remote scan downloaded to local disk
+#[derive(Debug)]
+pub struct FetchedScan {
+    /// Copy of the manifest whose chunk paths point at the downloaded files
+    pub manifest_path: PathBuf,
+
+    /// Downloaded chunk files, in manifest order
+    pub chunk_files: Vec<PathBuf>,
+
+    /// Bytes downloaded, manifest included
+    pub bytes: u64,
+}
+
+fn client() -> Result<Client> {
+    Client::builder()
+        .user_agent(concat!("storage-scanner/", env!("CARGO_PKG_VERSION")))
+        // Chunks can be large; the default 30s limit covers the whole body
+        .timeout(None)
+        .build()
+        .context("Failed to build HTTP client")
+}
+
+/// Stream the body of `url` to `path`, returning its size
+fn download(client: &Client, url: &Url, path: &Path) -> Result<u64> {
+    let mut response = client.get(url.clone()).send()
+        .and_then(|r| r.error_for_status())
+        .with_context(|| format!("Failed to fetch {}", url))?;
+
+    let mut writer = BufWriter::new(File::create(path)
+        .with_context(|| format!("Failed to create {}", path.display()))?);
+    let bytes = response.copy_to(&mut writer)
+        .with_context(|| format!("Failed to download {}", url))?;
+    writer.flush()?;
+
+    Ok(bytes)
+}
+
+/// URL of a chunk listed in the manifest at `manifest_url`.
+///
+/// Chunk paths are paths on the scanning machine, so only their file name is
+/// kept; chunks are expected next to the manifest.
+fn chunk_url(manifest_url: &Url, file_path: &str) -> Result<Url> {
+    let name = Path::new(file_path).file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| anyhow::anyhow!("Invalid chunk path '{}'", file_path))?;
+    manifest_url.join(name)
+        .with_context(|| format!("Invalid chunk path '{}' for {}", file_path, manifest_url))
+}
+
+/// Download the manifest at `url` and every chunk it lists into `dir`.
+///
+/// Each chunk is checked against the manifest's file size and, when the
+/// manifest records one, its checksum. Deleted or archived chunks are skipped.
+pub fn fetch_scan(url: &str, dir: &Path) -> Result<FetchedScan> {
+    let manifest_url = Url::parse(url)
+        .with_context(|| format!("Invalid URL '{}'", url))?;
+    let file_name = manifest_url.path_segments()
+        .and_then(|mut s| s.next_back())
+        .filter(|name| name.ends_with(".json"))
+        .ok_or_else(|| anyhow::anyhow!("Remote input must be a manifest .json URL: {}", url))?
+        .to_string();
+
+    fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create {}", dir.display()))?;
+    let client = client()?;
+
+    let manifest_path = dir.join(&file_name);
+    let mut bytes = download(&client, &manifest_url, &manifest_path)?;
+    let mut manifest = ScanManifest::load_from_file(&manifest_path)
+        .with_context(|| format!("Invalid manifest at {}", url))?;
+    info!("Fetched manifest {} ({} chunks)", url, manifest.chunks.len());
+
+    let mut chunk_files = Vec::new();
+    for chunk in manifest.chunks.iter_mut().filter(|c| !c.is_deleted()) {
+        let chunk_url = chunk_url(&manifest_url, &chunk.file_path)?;
+        let path = dir.join(Path::new(&chunk.file_path).file_name().unwrap());
+
+        let size = download(&client, &chunk_url, &path)?;
+        if size != chunk.file_size {
+            anyhow::bail!(
+                "{} is {} bytes but the manifest lists {}",
+                chunk_url, size, chunk.file_size
+            );
+        }
+        if let Some(ref expected) = chunk.checksum {
+            let actual = file_checksum(&path)?;
+            if !actual.eq_ignore_ascii_case(expected) {
+                anyhow::bail!(
+                    "Checksum mismatch for {}: expected {}, got {}",
+                    chunk_url, expected, actual
+                );
+            }
+        }
+        info!("Fetched {} ({} bytes)", chunk_url, size);
+
+        bytes += size;
+        chunk.file_path = path.to_string_lossy().to_string();
+        chunk_files.push(path);
+    }
+
+    // Point the local copy at the downloaded chunks
+    manifest.save_to_file(&manifest_path)?;
+
+    Ok(FetchedScan { manifest_path, chunk_files, bytes })
+}
+
+/// Where and how persistently chunks are uploaded
+#[derive(Debug, Clone)]
+pub struct UploadOptions {
+    /// Endpoint chunks are PUT under, by file name
+    pub url: String,
+
+    /// Further attempts after a failed one
+    pub retries: u32,
+
+    /// Wait before the first retry, doubled before each later one
+    pub backoff: Duration,
+}
+
+impl UploadOptions {
+    /// The endpoint as a directory URL, so chunk names join below it
+    fn base_url(&self) -> Result<Url> {
+        let mut url = Url::parse(&self.url)
+            .with_context(|| format!("Invalid upload URL '{}'", self.url))?;
+        if !url.path().ends_with('/') {
+            url.set_path(&format!("{}/", url.path()));
+        }
+        Ok(url)
+    }
+}
+
+/// PUT one chunk with its metadata as headers
+fn put_chunk(client: &Client, url: &Url, chunk: &ChunkMetadata) -> Result<()> {
+    let file = File::open(&chunk.file_path)
+        .with_context(|| format!("Failed to open {}", chunk.file_path))?;
+    let mut request = client.put(url.clone())
+        .header("X-Chunk-Number", chunk.chunk_number)
+        .header("X-Chunk-Rows", chunk.row_count)
+        .body(Body::sized(file, chunk.file_size));
+    if let Some(ref checksum) = chunk.checksum {
+        request = request.header("X-Chunk-Checksum", checksum.as_str());
+    }
+    request.send()
+        .and_then(|r| r.error_for_status())
+        .with_context(|| format!("Failed to upload to {}", url))?;
+    Ok(())
+}
+
+/// PUT one chunk until it is accepted or the retries run out, returning
+/// the number of attempts and the last result
+fn put_with_retries(client: &Client, url: &Url, options: &UploadOptions, chunk: &ChunkMetadata) -> (u32, Result<()>) {
+    let mut attempts = 1;
+    let mut backoff = options.backoff;
+    let mut result = put_chunk(client, url, chunk);
+    while let Err(ref e) = result {
+        if attempts > options.retries {
+            break;
+        }
+        warn!("Upload of chunk {} failed, retrying in {:?}: {:#}", chunk.chunk_number, backoff, e);
+        std::thread::sleep(backoff);
+        backoff *= 2;
+        attempts += 1;
+        result = put_chunk(client, url, chunk);
+    }
+    (attempts, result)
+}
+
+/// Upload `chunk` below `base`, describing the outcome for the manifest
+fn upload_chunk(client: &Client, base: &Url, options: &UploadOptions, chunk: &ChunkMetadata) -> ChunkUpload {
+    let (url, attempts, result) = match chunk_url(base, &chunk.file_path) {
+        Ok(url) => {
+            let (attempts, result) = put_with_retries(client, &url, options, chunk);
+            (url.to_string(), attempts, result)
+        }
+        Err(e) => (base.to_string(), 0, Err(e)),
+    };
+
+    let at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+    match result {
+        Ok(()) => {
+            info!("Uploaded chunk {} to {}", chunk.chunk_number, url);
+            ChunkUpload::Uploaded { url, at, attempts }
+        }
+        Err(e) => {
+            warn!("Giving up on uploading chunk {} after {} attempts: {:#}", chunk.chunk_number, attempts, e);
+            ChunkUpload::Failed { url, at, attempts, error: format!("{:#}", e) }
+        }
+    }
+}
+
+/// Set the upload outcome of chunk `chunk_number` in `manifest`
+fn record_upload(manifest: &mut ScanManifest, chunk_number: usize, upload: ChunkUpload) {
+    if let Some(chunk) = manifest.chunks.iter_mut().find(|c| c.chunk_number == chunk_number) {
+        chunk.upload = Some(upload);
+    }
+}
+
+/// Uploads chunks on a background thread as the rotating writer closes them.
+///
+/// Closing a chunk only queues it, so a slow or unreachable endpoint never
+/// holds up the scan; finalize waits for the queue to drain. Failed chunks
+/// are marked in the manifest for [`retry_uploads`].
+pub struct ChunkUploader {
+    chunks: Option<Sender<ChunkMetadata>>,
+    outcomes: Receiver<(usize, ChunkUpload)>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl ChunkUploader {
+    pub fn new(options: UploadOptions) -> Result<Self> {
+        let base = options.base_url()?;
+        let client = client()?;
+        let (chunks, queue) = unbounded::<ChunkMetadata>();
+        let (sender, outcomes) = unbounded();
+        let worker = std::thread::Builder::new()
+            .name("chunk-uploader".to_string())
+            .spawn(move || {
+                for chunk in queue {
+                    let upload = upload_chunk(&client, &base, &options, &chunk);
+                    if sender.send((chunk.chunk_number, upload)).is_err() {
+                        break;
+                    }
+                }
+            })
+            .context("Failed to start upload thread")?;
+
+        Ok(Self { chunks: Some(chunks), outcomes, worker: Some(worker) })
+    }
+}
+
+impl ChunkHook for ChunkUploader {
+    fn chunk_closed(&mut self, chunk: &ChunkMetadata) -> Result<()> {
+        self.chunks.as_ref()
+            .context("Uploader already finished")?
+            .send(chunk.clone())
+            .map_err(|_| anyhow::anyhow!("Upload thread stopped"))
+    }
+
+    fn record(&mut self, manifest: &mut ScanManifest) {
+        for (chunk_number, upload) in self.outcomes.try_iter() {
+            record_upload(manifest, chunk_number, upload);
+        }
+    }
+
+    fn finish(&mut self, manifest: &mut ScanManifest) -> Result<()> {
+        let pending = self.chunks.as_ref().map_or(0, |c| c.len());
+        if pending > 0 {
+            info!("Waiting for {} chunk upload(s) to finish", pending);
+        }
+        // Closing the queue ends the thread once it is drained
+        self.chunks = None;
+        if let Some(worker) = self.worker.take() {
+            worker.join().map_err(|_| anyhow::anyhow!("Upload thread panicked"))?;
+        }
+        self.record(manifest);
+        Ok(())
+    }
+}
+
+/// Chunks handled by [`retry_uploads`]
+#[derive(Debug, Clone, Default, Serialize, PartialEq, Eq)]
+pub struct UploadSummary {
+    /// Chunks accepted in this pass
+    pub uploaded: u64,
+
+    /// Chunks that failed again
+    pub failed: u64,
+
+    /// Chunks already uploaded, deleted, or archived, left alone
+    pub skipped: u64,
+}
+
+/// Upload the chunks of the manifest at `manifest_path` that are not yet
+/// uploaded, recording each outcome in the manifest.
+///
+/// This finishes what a scan's `--upload-url` left undone, whether chunks
+/// failed or the scan ran without uploading at all.
+pub fn retry_uploads(manifest_path: &Path, options: &UploadOptions) -> Result<UploadSummary> {
+    let mut manifest = ScanManifest::load_from_file(manifest_path)
+        .with_context(|| format!("Failed to load manifest {}", manifest_path.display()))?;
+    let base = options.base_url()?;
+    let client = client()?;
+
+    let mut summary = UploadSummary::default();
+    for i in 0..manifest.chunks.len() {
+        let chunk = &manifest.chunks[i];
+        if chunk.is_uploaded() || chunk.retention.is_some() {
+            summary.skipped += 1;
+            continue;
+        }
+        let upload = upload_chunk(&client, &base, options, chunk);
+        match upload {
+            ChunkUpload::Uploaded { .. } => summary.uploaded += 1,
+            ChunkUpload::Failed { .. } => summary.failed += 1,
+        }
+        manifest.chunks[i].upload = Some(upload);
+        // Keep what is done even if a later chunk never finishes
+        manifest.save_to_file(manifest_path)?;
+    }
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aggregate::{self, AggregateOptions};
+    use crate::models::FileEntry;
+    use crate::rotating_writer::{RotatingParquetWriter, RotatingWriterConfig};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use tempfile::TempDir;
+
+    fn entry(i: usize) -> FileEntry {
+        FileEntry { inode: i as u64, ..FileEntry::fixture(&format!("/test/file_{}.txt", i)) }
+    }
+
+    /// Write `rows` rows as chunks of 3 into `dir` under the stem "scan"
+    fn write_scan(dir: &Path, rows: usize) {
+        let config = RotatingWriterConfig {
+            base_output_path: dir.join("scan.parquet"),
+            rows_per_chunk: 3,
+            time_interval: Duration::from_secs(3600),
+        };
+        let mut writer = RotatingParquetWriter::new(config, "/test".to_string()).unwrap();
+        for i in 0..rows {
+            writer.write_batch(&[entry(i)]).unwrap();
+        }
+        writer.finalize().unwrap();
+    }
+
+    /// Write `rows` rows as chunks of 3 into `dir`, uploading them as they close
+    fn write_uploaded_scan(dir: &Path, rows: usize, options: UploadOptions) -> ScanManifest {
+        let config = RotatingWriterConfig {
+            base_output_path: dir.join("scan.parquet"),
+            rows_per_chunk: 3,
+            time_interval: Duration::from_secs(3600),
+        };
+        let mut writer = RotatingParquetWriter::new(config, "/test".to_string()).unwrap()
+            .with_chunk_hook(Box::new(ChunkUploader::new(options).unwrap()));
+        for i in 0..rows {
+            writer.write_batch(&[entry(i)]).unwrap();
+        }
+        writer.finalize().unwrap()
+    }
+
+    /// Uploads a capture server accepted, as (path, X-Chunk-Rows, body)
+    type Received = Arc<Mutex<Vec<(String, String, Vec<u8>)>>>;
+
+    /// Accept uploads under /ingest, answering 503 to the first `fail_first`
+    /// requests, and return the endpoint URL
+    fn capture(fail_first: usize) -> (String, Received) {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/ingest", server.server_addr().to_ip().unwrap());
+        let received = Received::default();
+        let log = received.clone();
+        thread::spawn(move || {
+            for (i, mut request) in server.incoming_requests().enumerate() {
+                let mut body = Vec::new();
+                request.as_reader().read_to_end(&mut body).unwrap();
+                if i < fail_first {
+                    let _ = request.respond(tiny_http::Response::empty(503));
+                    continue;
+                }
+                let rows = request.headers().iter()
+                    .find(|h| h.field.equiv("X-Chunk-Rows"))
+                    .map(|h| h.value.to_string())
+                    .unwrap_or_default();
+                log.lock().unwrap().push((request.url().to_string(), rows, body));
+                let _ = request.respond(tiny_http::Response::empty(201));
+            }
+        });
+        (url, received)
+    }
+
+    fn upload_options(url: String, retries: u32) -> UploadOptions {
+        UploadOptions { url, retries, backoff: Duration::from_millis(10) }
+    }
+
+    #[test]
+    fn test_chunks_uploaded_once_after_transient_failure() {
+        let (url, received) = capture(1);
+        let dir = TempDir::new().unwrap();
+        let manifest = write_uploaded_scan(dir.path(), 7, upload_options(url.clone(), 3));
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 3);
+        for chunk in &manifest.chunks {
+            let name = Path::new(&chunk.file_path).file_name().unwrap().to_string_lossy();
+            let copies: Vec<_> = received.iter().filter(|(path, _, _)| *path == format!("/ingest/{}", name)).collect();
+            assert_eq!(copies.len(), 1, "{}", name);
+            assert_eq!(copies[0].1, chunk.row_count.to_string());
+            assert_eq!(copies[0].2, fs::read(&chunk.file_path).unwrap());
+        }
+
+        // The first chunk met the injected failure and went through on retry
+        let attempts: Vec<u32> = manifest.chunks.iter()
+            .map(|c| match c.upload {
+                Some(ChunkUpload::Uploaded { attempts, .. }) => attempts,
+                ref other => panic!("chunk {} not uploaded: {:?}", c.chunk_number, other),
+            })
+            .collect();
+        assert_eq!(attempts, [2, 1, 1]);
+        let saved = ScanManifest::load_from_file(dir.path().join("scan_manifest.json")).unwrap();
+        assert!(saved.chunks.iter().all(|c| c.is_uploaded()));
+    }
+
+    #[test]
+    fn test_retry_uploads_finishes_failures() {
+        // Nothing listens on a port just released
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let dir = TempDir::new().unwrap();
+        let manifest = write_uploaded_scan(dir.path(), 7, upload_options(format!("http://127.0.0.1:{}/ingest", port), 1));
+        for chunk in &manifest.chunks {
+            assert!(matches!(chunk.upload, Some(ChunkUpload::Failed { attempts: 2, .. })), "{:?}", chunk.upload);
+        }
+
+        let (url, received) = capture(0);
+        let manifest_path = dir.path().join("scan_manifest.json");
+        let summary = retry_uploads(&manifest_path, &upload_options(url.clone(), 0)).unwrap();
+        assert_eq!(summary, UploadSummary { uploaded: 3, failed: 0, skipped: 0 });
+        let saved = ScanManifest::load_from_file(&manifest_path).unwrap();
+        assert!(saved.chunks.iter().all(|c| c.is_uploaded()));
+
+        // A second pass has nothing left to send
+        let summary = retry_uploads(&manifest_path, &upload_options(url, 0)).unwrap();
+        assert_eq!(summary, UploadSummary { uploaded: 0, failed: 0, skipped: 3 });
+        assert_eq!(received.lock().unwrap().len(), 3);
+    }
+
+    /// Serve the files in `root` over HTTP and return the base URL
+    fn serve(root: PathBuf) -> String {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let base = format!("http://{}/", server.server_addr().to_ip().unwrap());
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                let path = root.join(request.url().trim_start_matches('/'));
+                let _ = match File::open(&path) {
+                    Ok(file) => request.respond(tiny_http::Response::from_file(file)),
+                    Err(_) => request.respond(tiny_http::Response::empty(404)),
+                };
+            }
+        });
+        base
+    }
+
+    #[test]
+    fn test_fetch_and_aggregate() {
+        let served = TempDir::new().unwrap();
+        write_scan(served.path(), 7);
+        let base = serve(served.path().to_path_buf());
+
+        let local = TempDir::new().unwrap();
+        let fetched = fetch_scan(&format!("{}scan_manifest.json", base), &local.path().join("fetch")).unwrap();
+        assert_eq!(fetched.chunk_files.len(), 3);
+        assert!(fetched.chunk_files.iter().all(|c| c.starts_with(local.path())));
+
+        // The local manifest resolves to the downloaded chunks
+        assert_eq!(aggregate::find_chunk_files(&fetched.manifest_path).unwrap(), fetched.chunk_files);
+
+        let output = local.path().join("aggregate.parquet");
+        let summary = aggregate::aggregate_chunks_with(&fetched.chunk_files, &output, &AggregateOptions::default()).unwrap();
+        assert_eq!(summary.total_rows, 7);
+    }
+
+    #[test]
+    fn test_checksum_mismatch_fails() {
+        let served = TempDir::new().unwrap();
+        write_scan(served.path(), 4);
+
+        let manifest_path = served.path().join("scan_manifest.json");
+        let mut manifest = ScanManifest::load_from_file(&manifest_path).unwrap();
+        assert!(manifest.chunks.iter().all(|c| c.checksum.is_some()));
+        manifest.chunks[1].checksum = Some("00000000".to_string());
+        manifest.save_to_file(&manifest_path).unwrap();
+        let base = serve(served.path().to_path_buf());
+
+        let local = TempDir::new().unwrap();
+        let err = fetch_scan(&format!("{}scan_manifest.json", base), local.path()).unwrap_err();
+        assert!(err.to_string().contains("Checksum mismatch"), "{}", err);
+    }
+
+    #[test]
+    fn test_missing_chunk_fails() {
+        let served = TempDir::new().unwrap();
+        write_scan(served.path(), 4);
+        fs::remove_file(served.path().join("scan_chunk_0001.parquet")).unwrap();
+        let base = serve(served.path().to_path_buf());
+
+        let local = TempDir::new().unwrap();
+        let err = fetch_scan(&format!("{}scan_manifest.json", base), local.path()).unwrap_err();
+        assert!(format!("{:#}", err).contains("404"), "{:#}", err);
+
+        assert!(fetch_scan(&format!("{}scan.parquet", base), local.path()).is_err());
+    }
+}
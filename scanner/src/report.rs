@@ -0,0 +1,352 @@
+use crate::stats::{self, AgeBand, GroupStats, UsageStats};
+use crate::utils;
+use anyhow::{Context, Result};
+use arrow::datatypes::Schema;
+use parquet::arrow::arrow_reader::{ParquetRecordBatchReader, ParquetRecordBatchReaderBuilder};
+use parquet::arrow::ProjectionMask;
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Read the usage columns of every chunk, in parallel, into one set of statistics.
+///
+/// Only the columns [`UsageStats::add_batch`] needs are decoded.
+pub fn collect_stats(chunk_files: &[PathBuf], as_of: i64) -> Result<UsageStats> {
+    chunk_files.par_iter()
+        .map(|path| chunk_stats(path, as_of)
+            .with_context(|| format!("Failed to read {}", path.display())))
+        .try_reduce(|| UsageStats::as_of(as_of), |mut a, b| {
+            a.merge(b);
+            Ok(a)
+        })
+}
+
+/// Reader over only `columns` of a Parquet file, after `validate` accepts its schema
+pub(crate) fn read_columns(
+    path: &Path,
+    columns: &[&str],
+    validate: impl FnOnce(&Schema) -> Result<()>,
+) -> Result<ParquetRecordBatchReader> {
+    let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?;
+    validate(builder.schema())?;
+
+    let roots = columns.iter()
+        .map(|name| builder.schema().index_of(name))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    let mask = ProjectionMask::roots(builder.parquet_schema(), roots);
+    Ok(builder.with_projection(mask).build()?)
+}
+
+fn chunk_stats(path: &Path, as_of: i64) -> Result<UsageStats> {
+    let mut stats = UsageStats::as_of(as_of);
+    for batch in read_columns(path, &stats::COLUMNS, UsageStats::validate)? {
+        stats.add_batch(&batch?)?;
+    }
+    Ok(stats)
+}
+
+/// One line of a report table
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ReportRow {
+    pub name: String,
+
+    /// Entries in a group table, files in a histogram
+    pub count: u64,
+
+    pub bytes: u64,
+
+    /// Share of the total file bytes, in percent
+    pub percent: f64,
+}
+
+/// A titled table of a [`StorageReport`]
+#[derive(Debug, Clone, Serialize)]
+pub struct ReportTable {
+    #[serde(skip)]
+    pub title: &'static str,
+
+    /// Heading of the count column
+    #[serde(skip)]
+    pub count_label: &'static str,
+
+    pub rows: Vec<ReportRow>,
+
+    /// Groups left out by the row limit
+    pub omitted: usize,
+}
+
+/// Storage breakdown of [`UsageStats`], with group tables cut to the largest groups
+#[derive(Debug, Clone, Serialize)]
+pub struct StorageReport {
+    /// Unix timestamp ages are measured from
+    pub as_of: i64,
+
+    pub rows: u64,
+
+    pub files: u64,
+
+    pub directories: u64,
+
+    pub total_bytes: u64,
+
+    pub by_file_type: ReportTable,
+
+    pub by_top_level_dir: ReportTable,
+
+    pub size_histogram: ReportTable,
+
+    pub by_modified_age: ReportTable,
+
+    pub by_accessed_age: ReportTable,
+}
+
+impl StorageReport {
+    /// Report on `stats`, keeping the `top` largest file types and top-level directories
+    pub fn new(stats: &UsageStats, top: usize) -> Self {
+        let row = |name: String, count: u64, bytes: u64| ReportRow {
+            name,
+            count,
+            bytes,
+            percent: utils::percentage(bytes, stats.total_bytes),
+        };
+        let groups = |title, groups: &BTreeMap<String, GroupStats>| ReportTable {
+            title,
+            count_label: "Entries",
+            rows: UsageStats::top(groups, top).into_iter()
+                .map(|(name, group)| row(name.to_string(), group.rows, group.bytes))
+                .collect(),
+            omitted: groups.len().saturating_sub(top),
+        };
+        let ages = |title, bands: &[AgeBand]| ReportTable {
+            title,
+            count_label: "Files",
+            rows: bands.iter()
+                .map(|band| row(age_range(band.min_days, band.max_days), band.files, band.bytes))
+                .collect(),
+            omitted: 0,
+        };
+
+        Self {
+            as_of: stats.as_of,
+            rows: stats.rows,
+            files: stats.files,
+            directories: stats.directories,
+            total_bytes: stats.total_bytes,
+            by_file_type: groups("File type", &stats.by_file_type),
+            by_top_level_dir: groups("Top-level directory", &stats.by_top_level_dir),
+            size_histogram: ReportTable {
+                title: "File size",
+                count_label: "Files",
+                rows: stats.size_histogram.iter()
+                    .map(|bucket| row(size_range(bucket.min, bucket.max), bucket.files, bucket.bytes))
+                    .collect(),
+                omitted: 0,
+            },
+            by_modified_age: ages("Last modified", &stats.by_modified_age),
+            by_accessed_age: ages("Last accessed", &stats.by_accessed_age),
+        }
+    }
+
+    /// The tables in display order, with the section name used in CSV output
+    pub fn tables(&self) -> [(&'static str, &ReportTable); 5] {
+        [
+            ("file_type", &self.by_file_type),
+            ("top_level_dir", &self.by_top_level_dir),
+            ("size", &self.size_histogram),
+            ("modified_age", &self.by_modified_age),
+            ("accessed_age", &self.by_accessed_age),
+        ]
+    }
+
+    /// Totals followed by each table, aligned for a terminal
+    pub fn write_table(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "Files:                 {}", utils::format_number(self.files))?;
+        writeln!(out, "Directories:           {}", utils::format_number(self.directories))?;
+        writeln!(out, "Total size:            {}", utils::format_bytes(self.total_bytes))?;
+
+        for (_, table) in self.tables() {
+            writeln!(out)?;
+            writeln!(out, "{:<30}  {:>12}  {:>12}  {:>7}", table.title, table.count_label, "Size", "Share")?;
+            for row in &table.rows {
+                writeln!(out, "{:<30}  {:>12}  {:>12}  {:>6.1}%",
+                         row.name,
+                         utils::format_number(row.count),
+                         utils::format_bytes(row.bytes),
+                         row.percent)?;
+            }
+            if table.omitted > 0 {
+                writeln!(out, "... and {} more", table.omitted)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// One `section,name,count,bytes,percent` line per table row, after the totals
+    pub fn write_csv(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "section,name,count,bytes,percent")?;
+        writeln!(out, "total,files,{},{},{:.2}", self.files, self.total_bytes, if self.files > 0 { 100.0 } else { 0.0 })?;
+        writeln!(out, "total,directories,{},0,0.00", self.directories)?;
+        for (section, table) in self.tables() {
+            for row in &table.rows {
+                writeln!(out, "{},{},{},{},{:.2}", section, csv_field(&row.name), row.count, row.bytes, row.percent)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Quote a CSV field if it needs it
+pub(crate) fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn size_range(min: u64, max: Option<u64>) -> String {
+    match max {
+        Some(max) => format!("{} - {}", utils::format_bytes(min), utils::format_bytes(max)),
+        None => format!(">= {}", utils::format_bytes(min)),
+    }
+}
+
+/// "30 days", or whole years as "2 years"
+fn days(days: u64) -> String {
+    match (days % 365, days / 365) {
+        (0, 1) => "1 year".to_string(),
+        (0, years) if years > 1 => format!("{} years", years),
+        _ => format!("{} days", days),
+    }
+}
+
+pub(crate) fn age_range(min_days: u64, max_days: Option<u64>) -> String {
+    match (min_days, max_days) {
+        (0, Some(max)) => format!("< {}", days(max)),
+        (min, Some(max)) => format!("{} - {}", days(min), days(max)),
+        (min, None) => format!(">= {}", days(min)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aggregate;
+    use crate::models::FileEntry;
+    use crate::rotating_writer::{RotatingParquetWriter, RotatingWriterConfig};
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    const NOW: i64 = 1_700_000_000;
+    const DAY: i64 = 86_400;
+
+    fn entry(path: &str, size: u64, age_days: i64) -> FileEntry {
+        FileEntry {
+            size,
+            modified_time: NOW - age_days * DAY,
+            accessed_time: NOW - age_days * DAY,
+            ..FileEntry::fixture(path)
+        }
+    }
+
+    /// Chunks of 2 rows holding files of known sizes and ages
+    fn fixture(dir: &Path) -> Vec<PathBuf> {
+        let config = RotatingWriterConfig {
+            base_output_path: dir.join("scan.parquet"),
+            rows_per_chunk: 2,
+            time_interval: Duration::from_secs(3600),
+        };
+        let mut writer = RotatingParquetWriter::new(config, "/data".to_string()).unwrap();
+        let entries = [
+            entry("/data/lab/a.dat", 6_000_000, 10),
+            entry("/data/lab/b.dat", 2_000_000, 100),
+            entry("/data/lab/c.txt", 500, 400),
+            entry("/data/home/d.txt", 1_500, 2000),
+            entry("/data/home/e.csv", 20_000, 40),
+        ];
+        for entry in entries {
+            writer.write_batch(&[entry]).unwrap();
+        }
+        writer.finalize().unwrap();
+        aggregate::find_chunk_files(&dir.join("scan_manifest.json")).unwrap()
+    }
+
+    #[test]
+    fn test_report_from_chunks() {
+        let temp_dir = TempDir::new().unwrap();
+        let chunks = fixture(temp_dir.path());
+        assert_eq!(chunks.len(), 3);
+
+        let stats = collect_stats(&chunks, NOW).unwrap();
+        assert_eq!(stats.files, 5);
+        assert_eq!(stats.total_bytes, 8_022_000);
+
+        let report = StorageReport::new(&stats, 2);
+        let types: Vec<(&str, u64, u64)> = report.by_file_type.rows.iter()
+            .map(|r| (r.name.as_str(), r.count, r.bytes))
+            .collect();
+        assert_eq!(types, [("dat", 2, 8_000_000), ("csv", 1, 20_000)]);
+        assert_eq!(report.by_file_type.omitted, 1);
+        assert!((report.by_file_type.rows[0].percent - 99.73).abs() < 0.01);
+
+        let dirs: Vec<&str> = report.by_top_level_dir.rows.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(dirs, ["lab", "home"]);
+
+        let modified: Vec<(&str, u64)> = report.by_modified_age.rows.iter()
+            .filter(|r| r.count > 0)
+            .map(|r| (r.name.as_str(), r.bytes))
+            .collect();
+        assert_eq!(modified, [
+            ("< 30 days", 6_000_000),
+            ("30 days - 90 days", 20_000),
+            ("90 days - 180 days", 2_000_000),
+            ("1 year - 2 years", 500),
+            (">= 5 years", 1_500),
+        ]);
+
+        let sizes: Vec<u64> = report.size_histogram.rows.iter().map(|r| r.count).collect();
+        assert_eq!(sizes, [1, 1, 1, 0, 2, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_csv_and_json_output() {
+        let temp_dir = TempDir::new().unwrap();
+        let stats = collect_stats(&fixture(temp_dir.path()), NOW).unwrap();
+        let report = StorageReport::new(&stats, 10);
+
+        let mut csv = Vec::new();
+        report.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "section,name,count,bytes,percent");
+        assert_eq!(lines[1], "total,files,5,8022000,100.00");
+        assert!(lines.contains(&"top_level_dir,lab,3,8000500,99.73"));
+        assert!(lines.contains(&"size,1.00 MB - 10.00 MB,2,8000000,99.73"));
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["total_bytes"], 8_022_000);
+        assert_eq!(json["by_top_level_dir"]["rows"][1]["name"], "home");
+        assert_eq!(json["by_accessed_age"]["rows"].as_array().unwrap().len(), 7);
+
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn test_missing_column_is_an_error() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("other.parquet");
+        let batch = arrow::record_batch::RecordBatch::try_from_iter([
+            ("size", std::sync::Arc::new(arrow::array::UInt64Array::from(vec![1])) as _),
+        ]).unwrap();
+        let mut writer = parquet::arrow::ArrowWriter::try_new(File::create(&path).unwrap(), batch.schema(), None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+
+        let err = collect_stats(&[path], NOW).unwrap_err();
+        assert!(format!("{:#}", err).contains("'file_type'"), "{:#}", err);
+    }
+}
@@ -0,0 +1,242 @@
+use crate::manifest::{DirStats, ScanManifest};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+/// Where a top-level directory stands in an interrupted scan
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DirState {
+    /// Fully scanned and written; skipped on resume
+    Completed,
+
+    /// Being scanned when the scan stopped; re-scanned from scratch on resume
+    InProgress,
+
+    /// Not reached yet
+    Pending,
+}
+
+impl DirState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DirState::Completed => "completed",
+            DirState::InProgress => "in-progress",
+            DirState::Pending => "pending",
+        }
+    }
+}
+
+/// Status of one top-level directory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirStatus {
+    /// Directory name relative to the scan root
+    pub name: String,
+
+    pub state: DirState,
+
+    /// Rows and bytes written for this directory so far
+    pub written: DirStats,
+
+    /// Rows and bytes the baseline scan found in this directory
+    pub baseline: Option<DirStats>,
+
+    /// Rows and bytes still to scan (None without baseline stats for the directory)
+    pub remaining: Option<DirStats>,
+}
+
+/// What a resume of a scan would still have to do
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResumeStatus {
+    /// Scan root the status was computed for
+    pub scan_path: String,
+
+    /// Per-directory status, sorted by name
+    pub dirs: Vec<DirStatus>,
+
+    /// Rows and bytes written for completed directories
+    pub written: DirStats,
+
+    /// Estimated rows and bytes left, summed over directories with baseline stats
+    pub remaining: Option<DirStats>,
+
+    /// Directories not completed and missing from the baseline
+    pub unestimated_dirs: usize,
+}
+
+impl ResumeStatus {
+    /// Number of directories in a given state
+    pub fn count(&self, state: DirState) -> usize {
+        self.dirs.iter().filter(|d| d.state == state).count()
+    }
+}
+
+/// Classify the top-level directories under `root` against a manifest.
+///
+/// Every directory of a completed scan counts as completed.
+///
+/// Rows already written for the in-progress directory are discarded when the
+/// scan is resumed, so only completed directories count as written and the
+/// in-progress directory's full baseline counts as remaining.
+pub fn resume_status(
+    manifest: &ScanManifest,
+    root: &Path,
+    baseline: Option<&ScanManifest>,
+) -> Result<ResumeStatus> {
+    let mut names = BTreeSet::new();
+    for entry in fs::read_dir(root)
+        .with_context(|| format!("Failed to read scan root {}", root.display()))?
+    {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            names.insert(entry.file_name().to_string_lossy().to_string());
+        }
+    }
+
+    // Completed directories may have been removed since they were scanned
+    names.extend(manifest.completed_top_level_dirs.iter().cloned());
+
+    let mut written = DirStats::default();
+    let mut remaining: Option<DirStats> = None;
+    let mut unestimated_dirs = 0;
+
+    let dirs = names.into_iter()
+        .map(|name| {
+            let state = if manifest.completed || manifest.is_dir_completed(&name) {
+                DirState::Completed
+            } else if manifest.current_top_level_dir.as_deref() == Some(name.as_str()) {
+                DirState::InProgress
+            } else {
+                DirState::Pending
+            };
+
+            let dir_written = manifest.dir_stats.get(&name).copied().unwrap_or_default();
+            let dir_baseline = baseline.and_then(|b| b.dir_stats.get(&name).copied());
+
+            let dir_remaining = if state == DirState::Completed {
+                add(&mut written, dir_written);
+                Some(DirStats::default())
+            } else {
+                if dir_baseline.is_none() {
+                    unestimated_dirs += 1;
+                }
+                dir_baseline
+            };
+
+            if let Some(r) = dir_remaining {
+                add(remaining.get_or_insert_with(DirStats::default), r);
+            }
+
+            DirStatus {
+                name,
+                state,
+                written: dir_written,
+                baseline: dir_baseline,
+                remaining: dir_remaining,
+            }
+        })
+        .collect();
+
+    Ok(ResumeStatus {
+        scan_path: root.to_string_lossy().to_string(),
+        dirs,
+        written,
+        remaining: baseline.and(remaining),
+        unestimated_dirs,
+    })
+}
+
+fn add(total: &mut DirStats, stats: DirStats) {
+    total.rows += stats.rows;
+    total.bytes += stats.bytes;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn stats(rows: u64, bytes: u64) -> DirStats {
+        DirStats { rows, bytes }
+    }
+
+    fn fixture() -> (TempDir, ScanManifest) {
+        let temp_dir = TempDir::new().unwrap();
+        for dir in ["alpha", "beta", "gamma", "delta"] {
+            fs::create_dir(temp_dir.path().join(dir)).unwrap();
+        }
+        fs::write(temp_dir.path().join("root_file.txt"), "x").unwrap();
+
+        let mut manifest = ScanManifest::new(temp_dir.path().to_string_lossy().to_string());
+        manifest.completed_top_level_dirs.insert("alpha".to_string());
+        manifest.completed_top_level_dirs.insert("beta".to_string());
+        manifest.current_top_level_dir = Some("gamma".to_string());
+        manifest.dir_stats.insert("alpha".to_string(), stats(10, 1000));
+        manifest.dir_stats.insert("beta".to_string(), stats(5, 500));
+        manifest.dir_stats.insert("gamma".to_string(), stats(3, 300));
+
+        (temp_dir, manifest)
+    }
+
+    #[test]
+    fn test_classification() {
+        let (temp_dir, manifest) = fixture();
+        let status = resume_status(&manifest, temp_dir.path(), None).unwrap();
+
+        let states: Vec<(&str, DirState)> = status.dirs.iter()
+            .map(|d| (d.name.as_str(), d.state))
+            .collect();
+        assert_eq!(states, vec![
+            ("alpha", DirState::Completed),
+            ("beta", DirState::Completed),
+            ("delta", DirState::Pending),
+            ("gamma", DirState::InProgress),
+        ]);
+
+        assert_eq!(status.count(DirState::Completed), 2);
+        // In-progress rows are discarded on resume, so they don't count
+        assert_eq!(status.written, stats(15, 1500));
+        assert_eq!(status.remaining, None);
+    }
+
+    #[test]
+    fn test_remaining_from_baseline() {
+        let (temp_dir, manifest) = fixture();
+
+        let mut baseline = ScanManifest::new(manifest.scan_path.clone());
+        baseline.dir_stats.insert("alpha".to_string(), stats(9, 900));
+        baseline.dir_stats.insert("gamma".to_string(), stats(20, 2000));
+        baseline.dir_stats.insert("delta".to_string(), stats(7, 700));
+
+        let status = resume_status(&manifest, temp_dir.path(), Some(&baseline)).unwrap();
+
+        assert_eq!(status.remaining, Some(stats(27, 2700)));
+        assert_eq!(status.unestimated_dirs, 0);
+
+        fs::create_dir(temp_dir.path().join("epsilon")).unwrap();
+        let status = resume_status(&manifest, temp_dir.path(), Some(&baseline)).unwrap();
+        assert_eq!(status.unestimated_dirs, 1);
+        assert_eq!(status.dirs.iter().find(|d| d.name == "epsilon").unwrap().remaining, None);
+    }
+
+    #[test]
+    fn test_completed_dir_removed_from_disk() {
+        let (temp_dir, manifest) = fixture();
+        fs::remove_dir(temp_dir.path().join("beta")).unwrap();
+
+        let status = resume_status(&manifest, temp_dir.path(), None).unwrap();
+        let beta = status.dirs.iter().find(|d| d.name == "beta").unwrap();
+        assert_eq!(beta.state, DirState::Completed);
+    }
+
+    #[test]
+    fn test_completed_scan() {
+        let (temp_dir, mut manifest) = fixture();
+        manifest.complete();
+
+        let status = resume_status(&manifest, temp_dir.path(), None).unwrap();
+        assert_eq!(status.count(DirState::Completed), status.dirs.len());
+    }
+}
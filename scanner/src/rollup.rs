@@ -0,0 +1,276 @@
+use crate::filter::RowFilter;
+use crate::report::read_columns;
+use crate::utils::{is_within, parent_dir};
+use anyhow::{Context, Result};
+use arrow::array::{Array, StringArray, UInt32Array, UInt64Array};
+use arrow::compute::cast;
+use arrow::datatypes::{DataType, Schema};
+use arrow::record_batch::RecordBatch;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Columns read by [`Rollup::collect`]
+const COLUMNS: [&str; 5] = ["path", "size", "file_type", "parent_path", "depth"];
+
+/// `file_type` of directory entries
+const DIRECTORY_TYPE: &str = "directory";
+
+/// Order of sibling directories in [`Rollup::tree`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DirOrder {
+    /// By path
+    #[default]
+    Path,
+
+    /// Largest cumulative size first
+    Size,
+
+    /// Most files first
+    Count,
+}
+
+/// A directory of [`Rollup::tree`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct DirNode {
+    pub path: String,
+
+    /// Level below the directory its tree starts at
+    pub depth: usize,
+
+    /// Combined size of the files anywhere below the directory
+    pub bytes: u64,
+
+    /// Files anywhere below the directory
+    pub files: u64,
+
+    /// Bytes of the parent directory (None at the top of a tree)
+    pub parent_bytes: Option<u64>,
+
+    /// Whether no sibling follows it
+    pub last: bool,
+
+    /// Whether it is the largest of two or more siblings (the first in path
+    /// order on a tie)
+    pub largest: bool,
+}
+
+/// Cumulative file bytes and counts per directory, down to a fixed level
+#[derive(Debug, Default)]
+pub struct Rollup {
+    /// (bytes, files) per directory
+    dirs: HashMap<String, (u64, u64)>,
+}
+
+/// Level of `dir` below `base` ("" for the filesystem root)
+fn depth_below(dir: &str, base: &str) -> usize {
+    if dir == "/" {
+        return 0;
+    }
+    dir[base.len()..].matches('/').count()
+}
+
+fn validate(schema: &Schema) -> Result<()> {
+    for column in COLUMNS {
+        schema.field_with_name(column)
+            .with_context(|| format!("Disk usage requires a '{}' column", column))?;
+    }
+    Ok(())
+}
+
+impl Rollup {
+    fn add(&mut self, dir: &str, bytes: u64, files: u64) {
+        // Avoid allocating a key for directories already seen
+        let totals = match self.dirs.get_mut(dir) {
+            Some(totals) => totals,
+            None => self.dirs.entry(dir.to_string()).or_default(),
+        };
+        totals.0 += bytes;
+        totals.1 += files;
+    }
+
+    fn merge(mut self, other: Rollup) -> Rollup {
+        for (dir, (bytes, files)) in other.dirs {
+            self.add(&dir, bytes, files);
+        }
+        self
+    }
+
+    /// Add a batch already restricted to `under`. Only directories down to
+    /// `max_depth` are kept, so memory grows with the shape of the tree down to
+    /// that level, not with the number of rows.
+    fn add_batch(&mut self, batch: &RecordBatch, under: Option<&str>, max_depth: usize) -> Result<()> {
+        let column = |name: &str, data_type: &DataType| {
+            let column = batch.column_by_name(name)
+                .with_context(|| format!("Disk usage requires a '{}' column", name))?;
+            Ok::<_, anyhow::Error>(cast(column, data_type)?)
+        };
+        let paths = column("path", &DataType::Utf8)?;
+        let paths = paths.as_any().downcast_ref::<StringArray>().unwrap();
+        let sizes = column("size", &DataType::UInt64)?;
+        let sizes = sizes.as_any().downcast_ref::<UInt64Array>().unwrap();
+        let types = column("file_type", &DataType::Utf8)?;
+        let types = types.as_any().downcast_ref::<StringArray>().unwrap();
+        let parents = column("parent_path", &DataType::Utf8)?;
+        let parents = parents.as_any().downcast_ref::<StringArray>().unwrap();
+        let depths = column("depth", &DataType::UInt32)?;
+        let depths = depths.as_any().downcast_ref::<UInt32Array>().unwrap();
+
+        let under = under.map(|u| u.trim_end_matches('/'));
+        // Level of a directory at absolute `depth`, or None if it is outside `under`
+        let level = |dir: &str, depth: usize| match under {
+            Some(under) => is_within(dir, under).then(|| depth_below(dir, under)),
+            None => Some(depth),
+        };
+
+        for i in 0..batch.num_rows() {
+            if paths.is_null(i) || depths.is_null(i) {
+                continue;
+            }
+            let depth = depths.value(i) as usize;
+
+            // Directories are listed even when no file below them was scanned
+            if types.is_valid(i) && types.value(i) == DIRECTORY_TYPE {
+                let path = paths.value(i);
+                if level(path, depth).is_some_and(|l| l <= max_depth) {
+                    self.add(path, 0, 0);
+                }
+                continue;
+            }
+            if parents.is_null(i) {
+                continue;
+            }
+
+            // A file at depth d has d ancestors within the scan root
+            let size = if sizes.is_valid(i) { sizes.value(i) } else { 0 };
+            let mut dir = Some(parents.value(i));
+            for ancestor_depth in (0..depth).rev() {
+                let Some(current) = dir else { break };
+                match level(current, ancestor_depth) {
+                    Some(l) if l <= max_depth => self.add(current, size, 1),
+                    Some(_) => {}
+                    None => break,
+                }
+                dir = parent_dir(current);
+            }
+        }
+        Ok(())
+    }
+
+    fn collect_chunk(path: &Path, under: Option<&str>, max_depth: usize, filter: &RowFilter) -> Result<Rollup> {
+        let mut rollup = Rollup::default();
+        for batch in read_columns(path, &COLUMNS, validate)? {
+            rollup.add_batch(&filter.apply(&batch?)?, under, max_depth)?;
+        }
+        Ok(rollup)
+    }
+
+    /// Roll up the files of `chunk_files` into every directory down to
+    /// `max_depth` levels below the scan root, or below `under` and only
+    /// within it. Chunks are read in parallel.
+    pub fn collect(chunk_files: &[PathBuf], under: Option<&str>, max_depth: usize) -> Result<Rollup> {
+        let filter = RowFilter { path_prefix: under.map(str::to_string), ..Default::default() };
+        chunk_files.par_iter()
+            .map(|path| Self::collect_chunk(path, under, max_depth, &filter)
+                .with_context(|| format!("Failed to read {}", path.display())))
+            .try_reduce(Rollup::default, |a, b| Ok(a.merge(b)))
+    }
+
+    /// The directories of at least `min_size` bytes in tree order: each
+    /// directory is followed by its subdirectories, siblings in `order`.
+    /// A directory whose parent is not rolled up starts a tree of its own.
+    pub fn tree(&self, order: DirOrder, min_size: u64) -> Vec<DirNode> {
+        let mut children: HashMap<Option<&str>, Vec<&str>> = HashMap::new();
+        for (dir, &(bytes, _)) in &self.dirs {
+            if bytes < min_size {
+                continue;
+            }
+            let parent = parent_dir(dir).filter(|p| self.dirs.contains_key(*p));
+            children.entry(parent).or_default().push(dir);
+        }
+        for siblings in children.values_mut() {
+            siblings.sort_by(|a, b| {
+                let (a_stats, b_stats) = (self.dirs[*a], self.dirs[*b]);
+                let key = match order {
+                    DirOrder::Path => std::cmp::Ordering::Equal,
+                    DirOrder::Size => b_stats.0.cmp(&a_stats.0),
+                    DirOrder::Count => b_stats.1.cmp(&a_stats.1),
+                };
+                key.then_with(|| a.cmp(b))
+            });
+        }
+
+        let mut nodes = Vec::new();
+        // (directory, depth, parent bytes, last, largest)
+        let mut stack: Vec<(&str, usize, Option<u64>, bool, bool)> = Vec::new();
+        if let Some(roots) = children.get(&None) {
+            // Separate trees are not siblings of one another
+            stack.extend(roots.iter().rev().map(|root| (*root, 0, None, true, false)));
+        }
+        while let Some((dir, depth, parent_bytes, last, largest)) = stack.pop() {
+            let (bytes, files) = self.dirs[dir];
+            nodes.push(DirNode { path: dir.to_string(), depth, bytes, files, parent_bytes, last, largest });
+
+            let Some(subdirs) = children.get(&Some(dir)) else { continue };
+            let largest = (subdirs.len() > 1).then(|| {
+                subdirs.iter().copied().fold(subdirs[0], |best, dir| {
+                    let (best_bytes, dir_bytes) = (self.dirs[best].0, self.dirs[dir].0);
+                    if dir_bytes > best_bytes || (dir_bytes == best_bytes && dir < best) { dir } else { best }
+                })
+            });
+            for (i, subdir) in subdirs.iter().enumerate().rev() {
+                stack.push((*subdir, depth + 1, Some(bytes), i + 1 == subdirs.len(), Some(*subdir) == largest));
+            }
+        }
+        nodes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rollup(dirs: &[(&str, u64, u64)]) -> Rollup {
+        let mut rollup = Rollup::default();
+        for &(dir, bytes, files) in dirs {
+            rollup.add(dir, bytes, files);
+        }
+        rollup
+    }
+
+    #[test]
+    fn test_tree_flags_last_and_largest_sibling() {
+        let rollup = rollup(&[
+            ("/p", 60, 6),
+            ("/p/a", 20, 1),
+            ("/p/b", 30, 4),
+            ("/p/b/x", 30, 4),
+            ("/p/c", 10, 1),
+            ("/other", 5, 1),
+        ]);
+
+        let nodes = rollup.tree(DirOrder::Count, 0);
+        let flags: Vec<(&str, usize, bool, bool)> = nodes.iter()
+            .map(|n| (n.path.as_str(), n.depth, n.last, n.largest))
+            .collect();
+        assert_eq!(flags, [
+            ("/p", 0, true, false),
+            ("/p/b", 1, false, true),
+            // An only child is not highlighted
+            ("/p/b/x", 2, true, false),
+            ("/p/a", 1, false, false),
+            ("/p/c", 1, true, false),
+            ("/other", 0, true, false),
+        ]);
+
+        // Hidden directories don't count as siblings
+        let nodes = rollup.tree(DirOrder::Path, 15);
+        let flags: Vec<(&str, bool, bool)> = nodes.iter().map(|n| (n.path.as_str(), n.last, n.largest)).collect();
+        assert_eq!(flags, [
+            ("/p", true, false),
+            ("/p/a", false, false),
+            ("/p/b", true, true),
+            ("/p/b/x", true, false),
+        ]);
+    }
+}
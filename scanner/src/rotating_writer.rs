@@ -1,15 +1,22 @@
-use crate::models::FileEntry;
+use crate::error::{self, Error};
+use crate::lock;
+use crate::models::{self, ErrorSummary, FileEntry, ScanTag};
+use crate::status::{ScanCounters, StatusTracker};
 use crate::writer::ParquetFileWriter;
 use anyhow::{Context, Result};
-use crossbeam_channel::Receiver;
-use serde::{Deserialize, Serialize};
+use arrow::array::{Array, BooleanArray, StringArray, UInt32Array};
+use arrow::compute::filter_record_batch;
+use crossbeam_channel::{Receiver, RecvTimeoutError};
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::basic::Compression;
 use std::collections::HashSet;
-use std::fs::File;
-use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tracing::{info, warn};
 
+pub use crate::manifest::{ChunkMetadata, ParameterChange, RotationParams, ScanManifest};
+
 /// Configuration for rotating Parquet writer
 #[derive(Debug, Clone)]
 pub struct RotatingWriterConfig {
@@ -23,135 +30,18 @@ pub struct RotatingWriterConfig {
     pub time_interval: Duration,
 }
 
-/// Metadata about a chunk file
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ChunkMetadata {
-    /// Chunk number (0-indexed)
-    pub chunk_number: usize,
-
-    /// File path
-    pub file_path: String,
-
-    /// Number of rows in this chunk
-    pub row_count: u64,
-
-    /// Size of chunk file in bytes
-    pub file_size: u64,
-
-    /// Timestamp when chunk was created
-    pub created_at: i64,
-}
-
-/// Manifest file tracking all chunks
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ScanManifest {
-    /// Base scan path
-    pub scan_path: String,
-
-    /// Total rows across all chunks
-    pub total_rows: u64,
-
-    /// Number of chunks
-    pub chunk_count: usize,
-
-    /// List of chunk metadata
-    pub chunks: Vec<ChunkMetadata>,
-
-    /// Scan start timestamp
-    pub scan_start: i64,
-
-    /// Scan end timestamp (if complete)
-    pub scan_end: Option<i64>,
-
-    /// Scan completed successfully
-    pub completed: bool,
-
-    /// Top-level directories that have been fully scanned and written
-    #[serde(default)]
-    pub completed_top_level_dirs: HashSet<String>,
-
-    /// Currently scanning top-level directory (may be incomplete)
-    #[serde(default)]
-    pub current_top_level_dir: Option<String>,
-}
-
-impl ScanManifest {
-    pub fn new(scan_path: String) -> Self {
-        use std::time::SystemTime;
-        let now = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as i64;
-
-        Self {
-            scan_path,
-            total_rows: 0,
-            chunk_count: 0,
-            chunks: Vec::new(),
-            scan_start: now,
-            scan_end: None,
-            completed: false,
-            completed_top_level_dirs: HashSet::new(),
-            current_top_level_dir: None,
+impl RotatingWriterConfig {
+    /// Rotation parameters as recorded in the manifest
+    pub fn rotation_params(&self) -> RotationParams {
+        RotationParams {
+            rows_per_chunk: self.rows_per_chunk,
+            chunk_interval_secs: self.time_interval.as_secs(),
         }
     }
 
-    /// Load an existing manifest from a file
-    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let contents = std::fs::read_to_string(path.as_ref())
-            .context("Failed to read manifest file")?;
-
-        let manifest: Self = serde_json::from_str(&contents)
-            .context("Failed to parse manifest JSON")?;
-
-        Ok(manifest)
-    }
-
-    /// Check if a top-level directory has been completed
-    pub fn is_dir_completed(&self, dir: &str) -> bool {
-        self.completed_top_level_dirs.contains(dir)
-    }
-
-    /// Mark a top-level directory as in progress
-    pub fn start_directory(&mut self, dir: String) {
-        self.current_top_level_dir = Some(dir);
-    }
-
-    /// Mark the current directory as completed
-    pub fn complete_current_directory(&mut self) {
-        if let Some(dir) = self.current_top_level_dir.take() {
-            self.completed_top_level_dirs.insert(dir);
-        }
-    }
-
-    pub fn add_chunk(&mut self, metadata: ChunkMetadata) {
-        self.total_rows += metadata.row_count;
-        self.chunk_count += 1;
-        self.chunks.push(metadata);
-    }
-
-    pub fn complete(&mut self) {
-        use std::time::SystemTime;
-        let now = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as i64;
-
-        self.scan_end = Some(now);
-        self.completed = true;
-    }
-
-    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
-        let json = serde_json::to_string_pretty(self)
-            .context("Failed to serialize manifest")?;
-
-        let mut file = File::create(path.as_ref())
-            .context("Failed to create manifest file")?;
-
-        file.write_all(json.as_bytes())
-            .context("Failed to write manifest file")?;
-
-        Ok(())
+    fn apply_rotation_params(&mut self, params: RotationParams) {
+        self.rows_per_chunk = params.rows_per_chunk;
+        self.time_interval = Duration::from_secs(params.chunk_interval_secs);
     }
 }
 
@@ -164,23 +54,80 @@ pub struct RotatingParquetWriter {
     last_rotation: Instant,
     pub manifest: ScanManifest,
     last_top_level_dir: Option<String>,
+    status: Option<Arc<StatusTracker>>,
+    hooks: Vec<Box<dyn ChunkHook>>,
+    compression: Compression,
+    scan_counts: Option<ScanCounters>,
+}
+
+/// Work done on each chunk once it is closed, such as copying it elsewhere.
+///
+/// Hooks run on the writer thread, so `chunk_closed` should hand the chunk
+/// to a worker rather than block writing. Outcomes go into the manifest
+/// through `record`, called before each manifest save, and `finish`, called
+/// at finalize once the last chunk is closed.
+pub trait ChunkHook: Send {
+    /// Take on a chunk just written; it is listed in the manifest next
+    fn chunk_closed(&mut self, chunk: &ChunkMetadata) -> Result<()>;
+
+    /// Record outcomes finished since the last call in the manifest
+    fn record(&mut self, manifest: &mut ScanManifest);
+
+    /// Wait for pending work and record its outcomes
+    fn finish(&mut self, manifest: &mut ScanManifest) -> Result<()>;
 }
 
 impl RotatingParquetWriter {
-    pub fn new(config: RotatingWriterConfig, scan_path: String) -> Result<Self> {
+    pub fn new(config: RotatingWriterConfig, scan_path: String) -> error::Result<Self> {
+        let mut manifest = ScanManifest::new(scan_path);
+        manifest.rotation = Some(config.rotation_params());
+        manifest.hostname = Some(lock::hostname());
+
+        // A fresh scan would overwrite the manifest listing an earlier scan's
+        // chunks and leave them orphaned beside its own
+        if let Some((_, path)) = chunk_files_on_disk(&config.base_output_path)?.into_iter()
+            .chain(chunk_files_ending(&config.base_output_path, ".torn")?)
+            .next()
+        {
+            return Err(Error::OutputIo {
+                path,
+                source: std::io::Error::new(
+                    std::io::ErrorKind::AlreadyExists,
+                    "an earlier scan's chunks are already here; resume that scan, or remove them or pick another output",
+                ),
+            });
+        }
+
         Ok(Self {
             config,
             current_writer: None,
             current_chunk: 0,
             current_chunk_rows: 0,
             last_rotation: Instant::now(),
-            manifest: ScanManifest::new(scan_path),
+            manifest,
             last_top_level_dir: None,
+            status: None,
+            hooks: Vec::new(),
+            compression: Compression::SNAPPY,
+            scan_counts: None,
         })
     }
 
-    /// Resume from an existing manifest
-    pub fn resume(config: RotatingWriterConfig, scan_path: String) -> Result<Self> {
+    /// Resume from an existing manifest, inheriting its rotation parameters
+    pub fn resume(config: RotatingWriterConfig, scan_path: String) -> error::Result<Self> {
+        Self::resume_with(config, scan_path, false)
+    }
+
+    /// Resume from an existing manifest.
+    ///
+    /// If the requested rotation parameters differ from those recorded in the
+    /// manifest, the recorded ones win unless `override_chunking` is set, in
+    /// which case the new ones are used and the change is logged in the manifest.
+    pub fn resume_with(
+        mut config: RotatingWriterConfig,
+        scan_path: String,
+        override_chunking: bool,
+    ) -> error::Result<Self> {
         let manifest_path = Self::get_manifest_path_static(&config.base_output_path);
 
         let manifest = if manifest_path.exists() {
@@ -196,13 +143,29 @@ impl RotatingParquetWriter {
             info!("  - Existing chunks: {}", m.chunk_count);
             info!("  - Rows already scanned: {}", m.total_rows);
 
+            Self::reconcile_rotation(&mut config, &mut m, override_chunking);
+            Self::recover_torn_state(&config, &mut m)?;
+
+            // Persist the recovered state before any new chunk is written
+            m.save_to_file(&manifest_path)?;
+
             m
         } else {
             info!("No existing manifest found, starting fresh scan");
-            ScanManifest::new(scan_path)
+            let mut m = ScanManifest::new(scan_path);
+            m.rotation = Some(config.rotation_params());
+            m.hostname = Some(lock::hostname());
+            m
         };
 
-        let current_chunk = manifest.chunk_count;
+        // Continue numbering after the highest chunk listed or on disk,
+        // quarantined ones included, so no chunk file is reused
+        let current_chunk = manifest.chunks.iter()
+            .map(|c| c.chunk_number)
+            .max()
+            .unwrap_or(0)
+            .max(manifest.chunk_count)
+            .max(highest_chunk_on_disk(&config.base_output_path)?);
 
         Ok(Self {
             config,
@@ -212,9 +175,162 @@ impl RotatingParquetWriter {
             last_rotation: Instant::now(),
             manifest,
             last_top_level_dir: None,
+            status: None,
+            hooks: Vec::new(),
+            compression: Compression::SNAPPY,
+            scan_counts: None,
         })
     }
 
+    /// Settle which rotation parameters a resumed scan uses
+    fn reconcile_rotation(config: &mut RotatingWriterConfig, manifest: &mut ScanManifest, override_chunking: bool) {
+        let requested = config.rotation_params();
+
+        let recorded = match manifest.rotation {
+            Some(recorded) => recorded,
+            None => {
+                // Manifests written before rotation parameters were recorded
+                manifest.rotation = Some(requested);
+                return;
+            }
+        };
+
+        if recorded == requested {
+            return;
+        }
+
+        if override_chunking {
+            warn!(
+                "Overriding chunking from the original scan: rows_per_chunk {} -> {}, interval {}s -> {}s",
+                recorded.rows_per_chunk, requested.rows_per_chunk,
+                recorded.chunk_interval_secs, requested.chunk_interval_secs
+            );
+
+            use std::time::SystemTime;
+            let now = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs() as i64;
+
+            manifest.parameter_changes.push(ParameterChange {
+                changed_at: now,
+                from_chunk: manifest.chunk_count + 1,
+                previous: recorded,
+                current: requested,
+            });
+            manifest.rotation = Some(requested);
+        } else {
+            warn!(
+                "Ignoring requested chunking (rows_per_chunk {}, interval {}s); inheriting rows_per_chunk {}, interval {}s from the original scan. Use --override-chunking to change it.",
+                requested.rows_per_chunk, requested.chunk_interval_secs,
+                recorded.rows_per_chunk, recorded.chunk_interval_secs
+            );
+            config.apply_rotation_params(recorded);
+        }
+    }
+
+    /// Repair the state left behind by a crash mid-chunk.
+    ///
+    /// The directory that was in progress is not complete, so it is removed
+    /// from the completed set to be re-scanned. Chunk files on disk that the
+    /// manifest doesn't list are the torn in-flight chunk and are quarantined
+    /// with a `.torn` suffix. Rows from directories that will be re-scanned
+    /// are purged from listed chunks so the resumed scan can't duplicate them.
+    fn recover_torn_state(config: &RotatingWriterConfig, manifest: &mut ScanManifest) -> Result<()> {
+        if let Some(dir) = manifest.current_top_level_dir.take() {
+            manifest.completed_top_level_dirs.remove(&dir);
+            warn!("Directory '{}' was in progress when the previous run stopped; it will be re-scanned", dir);
+        }
+
+        let listed: HashSet<PathBuf> = manifest.chunks.iter()
+            .map(|c| PathBuf::from(&c.file_path))
+            .collect();
+
+        for (_, path) in chunk_files_on_disk(&config.base_output_path)? {
+            if !listed.contains(&path) {
+                let quarantined = PathBuf::from(format!("{}.torn", path.display()));
+                std::fs::rename(&path, &quarantined)
+                    .with_context(|| format!("Failed to quarantine torn chunk {}", path.display()))?;
+                warn!(
+                    "Quarantined chunk not listed in the manifest (torn by the crash): {} -> {}",
+                    path.display(),
+                    quarantined.display()
+                );
+            }
+        }
+
+        let completed = manifest.completed_top_level_dirs.clone();
+        let mut kept_chunks = Vec::with_capacity(manifest.chunks.len());
+        let mut purged_rows = 0u64;
+
+        for mut chunk in std::mem::take(&mut manifest.chunks) {
+            let removed = purge_incomplete_rows(Path::new(&chunk.file_path), &completed)
+                .with_context(|| format!("Failed to recover chunk {}", chunk.file_path))?;
+
+            if removed > 0 {
+                purged_rows += removed;
+                chunk.row_count -= removed;
+                warn!(
+                    "Discarded {} rows from chunk {} belonging to directories that will be re-scanned",
+                    removed, chunk.chunk_number
+                );
+
+                if chunk.row_count == 0 {
+                    std::fs::remove_file(&chunk.file_path)?;
+                    warn!("Removed chunk {} which held no completed rows", chunk.chunk_number);
+                    continue;
+                }
+                chunk.file_size = std::fs::metadata(&chunk.file_path)?.len();
+                if chunk.checksum.is_some() {
+                    chunk.checksum = Some(crate::utils::file_checksum(Path::new(&chunk.file_path))?);
+                }
+            }
+
+            kept_chunks.push(chunk);
+        }
+
+        if purged_rows > 0 {
+            info!("Recovery discarded {} rows in total; they will be re-scanned", purged_rows);
+        }
+
+        manifest.total_rows = kept_chunks.iter().map(|c| c.row_count).sum();
+        manifest.chunk_count = kept_chunks.len();
+        manifest.chunks = kept_chunks;
+        manifest.dir_stats.retain(|dir, _| completed.contains(dir));
+
+        Ok(())
+    }
+
+    /// Report each chunk as it is opened to `status`
+    pub fn with_status(mut self, status: Arc<StatusTracker>) -> Self {
+        self.status = Some(status);
+        self
+    }
+
+    /// Compress chunks opened from now on with `compression` instead of snappy
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Record the error and skip counts of the scan behind `counters`, as
+    /// they stand when each chunk is closed, in the chunk's footer
+    pub fn with_scan_counts(mut self, counters: ScanCounters) -> Self {
+        self.scan_counts = Some(counters);
+        self
+    }
+
+    /// Run `hook` on each chunk as it is closed
+    pub fn with_chunk_hook(mut self, hook: Box<dyn ChunkHook>) -> Self {
+        self.hooks.push(hook);
+        self
+    }
+
+    /// Rotation configuration in effect (after any resume reconciliation)
+    pub fn config(&self) -> &RotatingWriterConfig {
+        &self.config
+    }
+
     /// Get manifest path (static version for resume)
     fn get_manifest_path_static(base_output_path: &Path) -> PathBuf {
         let parent = base_output_path.parent().unwrap_or_else(|| Path::new("."));
@@ -232,8 +348,15 @@ impl RotatingParquetWriter {
         parent.join(format!("{}_chunk_{:04}.{}", stem, chunk_number, extension))
     }
 
-    /// Check if rotation is needed
+    /// Check if rotation is needed.
+    ///
+    /// An empty chunk is never rotated, so time-based rotation can't produce
+    /// empty files; the interval counts from the chunk's first row.
     fn should_rotate(&self) -> bool {
+        if self.current_chunk_rows == 0 {
+            return false;
+        }
+
         // Rotate if we've hit the row limit
         if self.current_chunk_rows >= self.config.rows_per_chunk as u64 {
             return true;
@@ -247,51 +370,28 @@ impl RotatingParquetWriter {
         false
     }
 
-    /// Rotate to a new chunk file
-    fn rotate(&mut self) -> Result<()> {
-        // Close current writer if exists
-        if let Some(writer) = self.current_writer.take() {
-            let rows = writer.rows_written();
-            writer.close()?;
-
-            // Record chunk metadata
-            let chunk_path = self.get_chunk_path(self.current_chunk);
-            let file_size = std::fs::metadata(&chunk_path)
-                .map(|m| m.len())
-                .unwrap_or(0);
-
-            use std::time::SystemTime;
-            let now = SystemTime::now()
-                .duration_since(SystemTime::UNIX_EPOCH)
-                .unwrap()
-                .as_secs() as i64;
-
-            let metadata = ChunkMetadata {
-                chunk_number: self.current_chunk,
-                file_path: chunk_path.to_string_lossy().to_string(),
-                row_count: rows,
-                file_size,
-                created_at: now,
-            };
-
-            self.manifest.add_chunk(metadata);
-
-            info!(
-                "Completed chunk {}: {} rows, {:.2} MB",
-                self.current_chunk,
-                rows,
-                file_size as f64 / 1_048_576.0
-            );
+    /// Time left before the open chunk is due for rotation (None when no chunk has rows)
+    fn time_until_rotation(&self) -> Option<Duration> {
+        if self.current_chunk_rows == 0 {
+            return None;
+        }
+        Some(self.config.time_interval.saturating_sub(self.last_rotation.elapsed()))
+    }
 
-            // Save manifest after each chunk
-            let manifest_path = self.get_manifest_path();
-            self.manifest.save_to_file(&manifest_path)
-                .unwrap_or_else(|e| {
-                    warn!("Failed to save manifest: {}", e);
-                });
+    /// Close the open chunk if it is due for rotation.
+    ///
+    /// `write_all` calls this while waiting for batches so a stalled scan
+    /// doesn't hold a chunk open past the interval; callers driving
+    /// `write_batch` themselves can call it periodically.
+    pub fn poll_rotation(&mut self) -> error::Result<()> {
+        if self.should_rotate() {
+            self.close_current_chunk()?;
         }
+        Ok(())
+    }
 
-        // Start new chunk
+    /// Open the next chunk file
+    fn open_chunk(&mut self) -> error::Result<()> {
         self.current_chunk += 1;
         self.current_chunk_rows = 0;
         self.last_rotation = Instant::now();
@@ -299,14 +399,92 @@ impl RotatingParquetWriter {
         let chunk_path = self.get_chunk_path(self.current_chunk);
         info!("Starting new chunk: {}", chunk_path.display());
 
-        let writer = ParquetFileWriter::new(&chunk_path)
-            .context("Failed to create new chunk writer")?;
+        // Tag the chunk so aggregates of several scans can tell them apart
+        let tag = ScanTag::from_scan_start(self.manifest.scan_start);
+        let mut writer = ParquetFileWriter::create_new(&chunk_path, tag.to_metadata(), self.compression)
+            .map_err(|e| e.in_chunk(self.current_chunk))?;
+        if let Some(ref counters) = self.scan_counts {
+            writer = writer.with_scan_counts(counters.clone());
+        }
 
         self.current_writer = Some(writer);
+        if let Some(ref status) = self.status {
+            status.set_chunk(self.current_chunk, &chunk_path);
+        }
 
         Ok(())
     }
 
+    /// Close the open chunk and record it in the manifest. This is the only
+    /// place chunks are listed, for rotation and finalize alike, and the
+    /// writer is taken so a chunk can't be listed twice.
+    ///
+    /// The next chunk is only opened when more rows arrive. A chunk without
+    /// rows is removed instead of being listed.
+    fn close_current_chunk(&mut self) -> error::Result<()> {
+        let writer = match self.current_writer.take() {
+            Some(writer) => writer,
+            None => return Ok(()),
+        };
+
+        let rows = writer.rows_written();
+        writer.close().map_err(|e| e.in_chunk(self.current_chunk))?;
+        self.current_chunk_rows = 0;
+
+        let chunk_path = self.get_chunk_path(self.current_chunk);
+        if rows == 0 {
+            std::fs::remove_file(&chunk_path).map_err(Error::output_io(&chunk_path))?;
+            return Ok(());
+        }
+
+        // Record chunk metadata
+        let file_size = std::fs::metadata(&chunk_path)
+            .map(|m| m.len())
+            .unwrap_or(0);
+
+        use std::time::SystemTime;
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        let metadata = ChunkMetadata {
+            chunk_number: self.current_chunk,
+            file_path: chunk_path.to_string_lossy().to_string(),
+            row_count: rows,
+            file_size,
+            created_at: now,
+            checksum: Some(crate::utils::file_checksum(&chunk_path)?),
+            retention: None,
+            upload: None,
+            post_chunk: None,
+        };
+
+        // Hand the listed chunk to hooks; a hook's error is returned once
+        // the manifest is saved, so the chunk is never left unlisted
+        let handed = self.hooks.iter_mut().try_for_each(|hook| hook.chunk_closed(&metadata));
+        self.manifest.add_chunk(metadata);
+
+        info!(
+            "Completed chunk {}: {} rows, {:.2} MB",
+            self.current_chunk,
+            rows,
+            file_size as f64 / 1_048_576.0
+        );
+
+        // Save manifest after each chunk, with what hooks have finished so far
+        for hook in &mut self.hooks {
+            hook.record(&mut self.manifest);
+        }
+        let manifest_path = self.get_manifest_path();
+        self.manifest.save_to_file(&manifest_path)
+            .unwrap_or_else(|e| {
+                warn!("Failed to save manifest: {}", e);
+            });
+
+        Ok(handed?)
+    }
+
     /// Get manifest file path
     fn get_manifest_path(&self) -> PathBuf {
         let base = &self.config.base_output_path;
@@ -317,61 +495,92 @@ impl RotatingParquetWriter {
     }
 
     /// Write a batch of entries
-    pub fn write_batch(&mut self, entries: &[FileEntry]) -> Result<()> {
+    pub fn write_batch(&mut self, entries: &[FileEntry]) -> error::Result<()> {
         if entries.is_empty() {
             return Ok(());
         }
 
-        // Track directory transitions
-        if let Some(first_entry) = entries.first() {
-            let current_dir = first_entry.top_level_dir.clone();
-
-            // If we've moved to a new top-level directory, mark the previous one as complete
-            if let Some(ref last_dir) = self.last_top_level_dir {
-                if last_dir != &current_dir {
+        // Track directory transitions at every entry, since one batch can
+        // span several directories; the root's own files come between its
+        // directories, and are only completed once the scan is
+        let mut completed_any = false;
+        for entry in entries {
+            let current_dir = entry.resume_unit();
+            match &self.last_top_level_dir {
+                Some(last_dir) if last_dir == current_dir => continue,
+                // We've moved to a new top-level directory, so the previous one is complete
+                Some(last_dir) => {
                     info!("Completed scanning directory: {}", last_dir);
                     self.manifest.complete_current_directory();
-                    self.manifest.start_directory(current_dir.clone());
-
-                    // Save checkpoint after completing a directory
-                    let manifest_path = self.get_manifest_path();
-                    self.manifest.save_to_file(&manifest_path)
-                        .unwrap_or_else(|e| {
-                            warn!("Failed to save checkpoint: {}", e);
-                        });
+                    completed_any = true;
                 }
-            } else {
-                // First directory
-                self.manifest.start_directory(current_dir.clone());
+                None => {}
             }
+            self.manifest.start_directory(current_dir.to_string());
+            self.last_top_level_dir = Some(current_dir.to_string());
+        }
 
-            self.last_top_level_dir = Some(current_dir);
+        // Save checkpoint after completing a directory
+        if completed_any {
+            let manifest_path = self.get_manifest_path();
+            self.manifest.save_to_file(&manifest_path)
+                .unwrap_or_else(|e| {
+                    warn!("Failed to save checkpoint: {}", e);
+                });
         }
 
-        // Initialize first writer if needed
+        // Open a chunk for these rows if the last one was closed
         if self.current_writer.is_none() {
-            self.rotate()?;
+            self.open_chunk()?;
         }
 
         // Write batch to current writer first
         if let Some(writer) = &mut self.current_writer {
-            writer.write_batch(entries)?;
+            writer.write_batch(entries).map_err(|e| e.in_chunk(self.current_chunk))?;
             self.current_chunk_rows += entries.len() as u64;
+            self.manifest.record_entries(entries);
         }
 
         // Check if we need to rotate after writing
         if self.should_rotate() {
-            self.rotate()?;
+            self.close_current_chunk()?;
         }
 
         Ok(())
     }
 
-    /// Consume batches from a channel
-    pub fn consume_batches(mut self, rx: Receiver<Vec<FileEntry>>) -> Result<ScanManifest> {
+    /// Consume batches from a channel, then finalize the scan
+    pub fn consume_batches(mut self, rx: Receiver<Vec<FileEntry>>) -> error::Result<ScanManifest> {
+        self.write_all(rx)?;
+
+        // Close final writer and finalize manifest
+        self.finalize()
+    }
+
+    /// Write every batch from a channel until it closes, without finalizing.
+    ///
+    /// Use this instead of `consume_batches` when scan results (such as the
+    /// error summary) must be recorded before `finalize()`.
+    pub fn write_all(&mut self, rx: Receiver<Vec<FileEntry>>) -> error::Result<()> {
         let mut batches_processed = 0;
 
-        for batch in rx {
+        loop {
+            // Wake up when the open chunk is due so an idle chunk still rotates
+            let batch = match self.time_until_rotation() {
+                Some(timeout) => match rx.recv_timeout(timeout) {
+                    Ok(batch) => batch,
+                    Err(RecvTimeoutError::Timeout) => {
+                        self.poll_rotation()?;
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => break,
+                },
+                None => match rx.recv() {
+                    Ok(batch) => batch,
+                    Err(_) => break,
+                },
+            };
+
             self.write_batch(&batch)?;
             batches_processed += 1;
 
@@ -385,54 +594,43 @@ impl RotatingParquetWriter {
             }
         }
 
-        // Close final writer and finalize manifest
-        let manifest = self.finalize()?;
-
-        Ok(manifest)
+        Ok(())
     }
 
-    /// Finalize the scan and close all writers
-    pub fn finalize(mut self) -> Result<ScanManifest> {
-        // Close current writer
-        if let Some(writer) = self.current_writer.take() {
-            let rows = writer.rows_written();
-            writer.close()?;
-
-            // Record final chunk metadata
-            let chunk_path = self.get_chunk_path(self.current_chunk);
-            let file_size = std::fs::metadata(&chunk_path)
-                .map(|m| m.len())
-                .unwrap_or(0);
-
-            use std::time::SystemTime;
-            let now = SystemTime::now()
-                .duration_since(SystemTime::UNIX_EPOCH)
-                .unwrap()
-                .as_secs() as i64;
+    /// Add the scan's error summary to the manifest.
+    ///
+    /// On resume the manifest already holds the previous run's errors, so
+    /// these are appended rather than replacing them.
+    pub fn record_errors(&mut self, errors: &ErrorSummary) {
+        self.manifest.errors.merge(errors);
+    }
 
-            let metadata = ChunkMetadata {
-                chunk_number: self.current_chunk,
-                file_path: chunk_path.to_string_lossy().to_string(),
-                row_count: rows,
-                file_size,
-                created_at: now,
-            };
+    /// Record how many entries this run skipped as already written
+    pub fn record_skipped(&mut self, skipped: u64) {
+        self.manifest.entries_skipped = skipped;
+    }
 
-            self.manifest.add_chunk(metadata);
+    /// Finalize the scan and close all writers
+    pub fn finalize(mut self) -> error::Result<ScanManifest> {
+        // Close the final chunk (if it has rows)
+        self.close_current_chunk()?;
 
-            info!(
-                "Completed final chunk {}: {} rows, {:.2} MB",
-                self.current_chunk,
-                rows,
-                file_size as f64 / 1_048_576.0
-            );
+        // Wait for hooks still working on chunks
+        let manifest_path = self.get_manifest_path();
+        for hook in &mut self.hooks {
+            if let Err(e) = hook.finish(&mut self.manifest) {
+                // Keep what the hooks recorded; the scan stays incomplete
+                self.manifest.save_to_file(&manifest_path)?;
+                return Err(e.into());
+            }
         }
 
-        // Mark manifest as complete
+        // Every direct child of the root is written; mark manifest as complete
+        self.manifest.complete_current_directory();
+        self.manifest.complete_root_files();
         self.manifest.complete();
 
         // Save final manifest
-        let manifest_path = self.get_manifest_path();
         self.manifest.save_to_file(&manifest_path)?;
 
         info!("Scan completed: {} total rows across {} chunks",
@@ -444,32 +642,113 @@ impl RotatingParquetWriter {
     }
 }
 
+/// Chunk files for a base output path present on disk, sorted by chunk number
+pub fn chunk_files_on_disk(base_output_path: &Path) -> Result<Vec<(usize, PathBuf)>> {
+    chunk_files_ending(base_output_path, "")
+}
+
+/// Highest chunk number on disk for a base output path, counting chunks
+/// quarantined as `.torn`, or 0 if there are none
+fn highest_chunk_on_disk(base_output_path: &Path) -> Result<usize> {
+    let listed = chunk_files_on_disk(base_output_path)?;
+    let torn = chunk_files_ending(base_output_path, ".torn")?;
+    Ok(listed.iter().chain(&torn).map(|(number, _)| *number).max().unwrap_or(0))
+}
+
+/// Chunk files for a base output path whose names end in `tail` after the
+/// extension, sorted by chunk number
+fn chunk_files_ending(base_output_path: &Path, tail: &str) -> Result<Vec<(usize, PathBuf)>> {
+    let parent = base_output_path.parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    let stem = base_output_path.file_stem().unwrap().to_string_lossy();
+    let extension = base_output_path.extension().unwrap_or_default().to_string_lossy();
+
+    let prefix = format!("{}_chunk_", stem);
+    let suffix = format!(".{}{}", extension, tail);
+
+    let mut chunks = Vec::new();
+    if !parent.exists() {
+        return Ok(chunks);
+    }
+
+    for entry in std::fs::read_dir(parent)? {
+        let path = entry?.path();
+        let name = match path.file_name() {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => continue,
+        };
+
+        let number = name.strip_prefix(&prefix)
+            .and_then(|rest| rest.strip_suffix(&suffix))
+            .filter(|digits| !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()))
+            .and_then(|digits| digits.parse::<usize>().ok());
+
+        if let Some(number) = number {
+            chunks.push((number, parent.join(&name)));
+        }
+    }
+
+    chunks.sort();
+    Ok(chunks)
+}
+
+/// Rewrite a chunk without rows whose resume unit isn't completed.
+///
+/// Returns the number of rows removed; the file is untouched when none are.
+fn purge_incomplete_rows(path: &Path, completed: &HashSet<String>) -> Result<u64> {
+    let reader = ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(path)?)?.build()?;
+
+    let mut kept = Vec::new();
+    let mut removed = 0u64;
+
+    for batch in reader {
+        let batch = batch?;
+        let strings = |name: &str| batch.column_by_name(name)
+            .and_then(|c| c.as_any().downcast_ref::<StringArray>())
+            .ok_or_else(|| anyhow::anyhow!("Chunk has no {} column", name));
+        let (dirs, file_types) = (strings("top_level_dir")?, strings("file_type")?);
+        let depths = batch.column_by_name("depth")
+            .and_then(|c| c.as_any().downcast_ref::<UInt32Array>())
+            .ok_or_else(|| anyhow::anyhow!("Chunk has no depth column"))?;
+
+        let mask: BooleanArray = (0..dirs.len())
+            .map(|i| {
+                let unit = models::resume_unit(dirs.value(i), depths.value(i), file_types.value(i) == "directory");
+                Some(dirs.is_valid(i) && completed.contains(unit))
+            })
+            .collect();
+
+        let filtered = filter_record_batch(&batch, &mask)?;
+        removed += (batch.num_rows() - filtered.num_rows()) as u64;
+        kept.push(filtered);
+    }
+
+    if removed == 0 {
+        return Ok(0);
+    }
+
+    let temp_path = PathBuf::from(format!("{}.recovering", path.display()));
+    let mut writer = ParquetFileWriter::new(&temp_path)?;
+    for batch in &kept {
+        writer.write_record_batch(batch)?;
+    }
+    writer.close()?;
+    std::fs::rename(&temp_path, path)?;
+
+    Ok(removed)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::models::FileEntry;
+    use crate::models::{ErrorSummary, FileEntry};
     use crossbeam_channel::bounded;
     use std::fs;
     use tempfile::TempDir;
 
     fn create_test_entry(path: &str, size: u64) -> FileEntry {
-        FileEntry {
-            path: path.to_string(),
-            size,
-            modified_time: 1700000000,
-            accessed_time: 1700000000,
-            created_time: Some(1700000000),
-            file_type: "txt".to_string(),
-            inode: 12345,
-            permissions: 0o644,
-            uid: 1000,
-            gid: 1000,
-            owner: Some("testuser".to_string()),
-            group: Some("testgroup".to_string()),
-            parent_path: "/parent".to_string(),
-            depth: 1,
-            top_level_dir: "root".to_string(),
-        }
+        FileEntry { size, ..FileEntry::fixture(path) }
     }
 
     #[test]
@@ -514,6 +793,358 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_errors_recorded_and_appended_on_resume() {
+        let temp_dir = TempDir::new().unwrap();
+        let output_path = temp_dir.path().join("output.parquet");
+
+        let config = RotatingWriterConfig {
+            base_output_path: output_path,
+            rows_per_chunk: 5,
+            time_interval: Duration::from_secs(3600),
+        };
+
+        let mut first_run = ErrorSummary::default();
+        first_run.record("EACCES", "/test/locked", "Permission denied".to_string());
+
+        let mut writer = RotatingParquetWriter::new(config.clone(), "/test".to_string()).unwrap();
+        writer.write_batch(&[create_test_entry("/test/a.txt", 1)]).unwrap();
+        writer.record_errors(&first_run);
+        writer.finalize().unwrap();
+
+        let mut second_run = ErrorSummary::default();
+        second_run.record("ENOENT", "/test/gone", "No such file".to_string());
+
+        let mut writer = RotatingParquetWriter::resume(config, "/test".to_string()).unwrap();
+        writer.write_batch(&[create_test_entry("/test/b.txt", 1)]).unwrap();
+        writer.record_errors(&second_run);
+        let manifest = writer.finalize().unwrap();
+
+        assert_eq!(manifest.errors.total, 2);
+        assert_eq!(manifest.errors.by_kind["EACCES"], 1);
+        assert_eq!(manifest.errors.by_kind["ENOENT"], 1);
+
+        let saved = ScanManifest::load_from_file(temp_dir.path().join("output_manifest.json")).unwrap();
+        assert_eq!(saved.errors, manifest.errors);
+    }
+
+    /// Every chunk file on disk is listed once with at least one row, and
+    /// vice versa, and the listed rows add up to the manifest's total
+    fn assert_no_empty_chunks(base: &Path, manifest: &ScanManifest) {
+        assert!(manifest.chunks.iter().all(|c| c.row_count > 0), "{:?}", manifest.chunks);
+        assert_eq!(manifest.total_rows, manifest.chunks.iter().map(|c| c.row_count).sum::<u64>());
+        assert_eq!(manifest.chunk_count, manifest.chunks.len());
+        let on_disk: Vec<PathBuf> = chunk_files_on_disk(base).unwrap().into_iter().map(|(_, p)| p).collect();
+        let listed: Vec<PathBuf> = manifest.chunks.iter().map(|c| PathBuf::from(&c.file_path)).collect();
+        assert_eq!(on_disk, listed);
+    }
+
+    #[test]
+    fn test_idle_chunk_flushed_without_new_batches() {
+        let temp_dir = TempDir::new().unwrap();
+        let output_path = temp_dir.path().join("output.parquet");
+        let manifest_path = temp_dir.path().join("output_manifest.json");
+
+        let config = RotatingWriterConfig {
+            base_output_path: output_path.clone(),
+            rows_per_chunk: 1000,
+            time_interval: Duration::from_millis(100),
+        };
+
+        let (tx, rx) = bounded(10);
+        let sender = std::thread::spawn(move || {
+            tx.send(vec![create_test_entry("/test/a.txt", 1)]).unwrap();
+
+            // Stall well past the interval; the chunk must be flushed meanwhile
+            std::thread::sleep(Duration::from_millis(500));
+            let flushed = ScanManifest::load_from_file(&manifest_path).unwrap();
+
+            tx.send(vec![create_test_entry("/test/b.txt", 1)]).unwrap();
+            flushed
+        });
+
+        let writer = RotatingParquetWriter::new(config, "/test".to_string()).unwrap();
+        let manifest = writer.consume_batches(rx).unwrap();
+        let flushed = sender.join().unwrap();
+
+        assert_eq!(flushed.chunk_count, 1);
+        assert_eq!(flushed.chunks[0].row_count, 1);
+
+        assert_eq!(manifest.chunk_count, 2);
+        assert_eq!(manifest.total_rows, 2);
+        assert_no_empty_chunks(&output_path, &manifest);
+    }
+
+    #[test]
+    fn test_slow_sender_produces_no_empty_chunks() {
+        let temp_dir = TempDir::new().unwrap();
+        let output_path = temp_dir.path().join("output.parquet");
+
+        let config = RotatingWriterConfig {
+            base_output_path: output_path.clone(),
+            rows_per_chunk: 1000,
+            time_interval: Duration::from_millis(30),
+        };
+
+        let (tx, rx) = bounded(10);
+        let sender = std::thread::spawn(move || {
+            for i in 0..8 {
+                tx.send(vec![create_test_entry(&format!("/test/file{}.txt", i), 1)]).unwrap();
+                std::thread::sleep(Duration::from_millis(if i % 2 == 0 { 10 } else { 70 }));
+            }
+        });
+
+        let writer = RotatingParquetWriter::new(config, "/test".to_string()).unwrap();
+        let manifest = writer.consume_batches(rx).unwrap();
+        sender.join().unwrap();
+
+        assert_eq!(manifest.total_rows, 8);
+        assert!(manifest.chunk_count > 1);
+        assert_no_empty_chunks(&output_path, &manifest);
+    }
+
+    #[test]
+    fn test_finalize_right_after_row_rotation() {
+        let temp_dir = TempDir::new().unwrap();
+        let output_path = temp_dir.path().join("output.parquet");
+
+        let config = RotatingWriterConfig {
+            base_output_path: output_path.clone(),
+            rows_per_chunk: 2,
+            time_interval: Duration::from_secs(3600),
+        };
+
+        let mut writer = RotatingParquetWriter::new(config, "/test".to_string()).unwrap();
+        for i in 0..4 {
+            writer.write_batch(&[create_test_entry(&format!("/test/file{}.txt", i), 1)]).unwrap();
+        }
+        let manifest = writer.finalize().unwrap();
+
+        assert_eq!(manifest.chunk_count, 2);
+        assert_no_empty_chunks(&output_path, &manifest);
+    }
+
+    #[test]
+    fn test_batches_that_exactly_fill_chunks() {
+        let temp_dir = TempDir::new().unwrap();
+        let output_path = temp_dir.path().join("output.parquet");
+
+        let config = RotatingWriterConfig {
+            base_output_path: output_path.clone(),
+            rows_per_chunk: 3,
+            time_interval: Duration::from_secs(3600),
+        };
+
+        let mut writer = RotatingParquetWriter::new(config, "/test".to_string()).unwrap();
+        for batch in 0..2 {
+            let entries: Vec<_> = (0..3)
+                .map(|i| create_test_entry(&format!("/test/file{}_{}.txt", batch, i), 1))
+                .collect();
+            writer.write_batch(&entries).unwrap();
+        }
+        let manifest = writer.finalize().unwrap();
+
+        assert_eq!(manifest.chunk_count, 2);
+        assert_eq!(manifest.total_rows, 6);
+        assert_eq!(manifest.chunks.iter().map(|c| c.chunk_number).collect::<Vec<_>>(), vec![1, 2]);
+        assert_no_empty_chunks(&output_path, &manifest);
+
+        let saved = ScanManifest::load_from_file(temp_dir.path().join("output_manifest.json")).unwrap();
+        assert_eq!(saved.total_rows, 6);
+        assert_eq!(saved.chunk_count, 2);
+    }
+
+    #[test]
+    fn test_channel_closed_after_an_idle_rotation() {
+        let temp_dir = TempDir::new().unwrap();
+        let output_path = temp_dir.path().join("output.parquet");
+
+        let config = RotatingWriterConfig {
+            base_output_path: output_path.clone(),
+            rows_per_chunk: 1000,
+            time_interval: Duration::from_millis(50),
+        };
+
+        let (tx, rx) = bounded(10);
+        let sender = std::thread::spawn(move || {
+            tx.send(vec![create_test_entry("/test/a.txt", 1)]).unwrap();
+
+            // The chunk rotates while idle; then the channel closes with no rows pending
+            std::thread::sleep(Duration::from_millis(250));
+        });
+
+        let writer = RotatingParquetWriter::new(config, "/test".to_string()).unwrap();
+        let manifest = writer.consume_batches(rx).unwrap();
+        sender.join().unwrap();
+
+        assert_eq!(manifest.chunk_count, 1);
+        assert_eq!(manifest.total_rows, 1);
+        assert!(!temp_dir.path().join("output_chunk_0002.parquet").exists());
+        assert_no_empty_chunks(&output_path, &manifest);
+    }
+
+    fn write_and_finalize(config: RotatingWriterConfig, rows: usize) {
+        let mut writer = RotatingParquetWriter::new(config, "/test".to_string()).unwrap();
+        for i in 0..rows {
+            writer.write_batch(&[create_test_entry(&format!("/test/file{}.txt", i), 1)]).unwrap();
+        }
+        writer.finalize().unwrap();
+    }
+
+    fn entries_in(dir: &str, count: usize) -> Vec<FileEntry> {
+        (0..count)
+            .map(|i| FileEntry {
+                depth: 2,
+                top_level_dir: dir.to_string(),
+                ..create_test_entry(&format!("/test/{}/file{}.txt", dir, i), 1)
+            })
+            .collect()
+    }
+
+    fn writer_config() -> RotatingWriterConfig {
+        RotatingWriterConfig {
+            base_output_path: PathBuf::new(),
+            rows_per_chunk: 5,
+            time_interval: Duration::from_secs(3600),
+        }
+    }
+
+    #[test]
+    fn test_batch_spanning_directories_completes_each() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = RotatingWriterConfig { base_output_path: temp_dir.path().join("scan.parquet"), ..writer_config() };
+        let mut writer = RotatingParquetWriter::new(config, "/test".to_string()).unwrap();
+
+        let batch = [entries_in("a", 2), entries_in("b", 2), entries_in("c", 2)].concat();
+        writer.write_batch(&batch).unwrap();
+        assert!(writer.manifest.is_dir_completed("a"));
+        assert!(writer.manifest.is_dir_completed("b"));
+        assert!(!writer.manifest.is_dir_completed("c"));
+        assert_eq!(writer.manifest.current_top_level_dir.as_deref(), Some("c"));
+    }
+
+    #[test]
+    fn test_resume_numbers_chunks_after_an_unlisted_one() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = RotatingWriterConfig { base_output_path: temp_dir.path().join("scan.parquet"), ..writer_config() };
+
+        // The first run lists chunk 1, then dies with chunk 2 created but unlisted
+        let mut writer = RotatingParquetWriter::new(config.clone(), "/test".to_string()).unwrap();
+        writer.write_batch(&entries_in("a", 5)).unwrap();
+        writer.write_batch(&entries_in("b", 2)).unwrap();
+        std::mem::forget(writer);
+        let unlisted = temp_dir.path().join("scan_chunk_0002.parquet");
+        let contents = fs::read(&unlisted).unwrap();
+
+        let mut writer = RotatingParquetWriter::resume(config.clone(), "/test".to_string()).unwrap();
+        writer.write_batch(&entries_in("b", 2)).unwrap();
+        let manifest = writer.finalize().unwrap();
+
+        // Its file is kept as it was, and its number is not used again
+        let torn = temp_dir.path().join("scan_chunk_0002.parquet.torn");
+        assert_eq!(fs::read(&torn).unwrap(), contents);
+        assert!(!unlisted.exists());
+        assert_eq!(manifest.chunks.iter().map(|c| c.chunk_number).collect::<Vec<_>>(), vec![1, 3]);
+        assert_eq!(manifest.total_rows, 7);
+
+        // Nor by a later resume, once the torn chunk is all that's left of it
+        let mut writer = RotatingParquetWriter::resume(config, "/test".to_string()).unwrap();
+        writer.write_batch(&entries_in("c", 1)).unwrap();
+        let manifest = writer.finalize().unwrap();
+        assert_eq!(manifest.chunks.last().unwrap().chunk_number, 4);
+        assert_eq!(fs::read(&torn).unwrap(), contents);
+    }
+
+    #[test]
+    fn test_existing_chunk_files_are_never_overwritten() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = RotatingWriterConfig { base_output_path: temp_dir.path().join("scan.parquet"), ..writer_config() };
+
+        // A fresh scan refuses an output holding an earlier scan's chunks,
+        // leaving them and their manifest alone
+        write_and_finalize(config.clone(), 3);
+        let earlier = temp_dir.path().join("scan_chunk_0001.parquet");
+        let manifest_path = temp_dir.path().join("scan_manifest.json");
+        let (chunk, manifest) = (fs::read(&earlier).unwrap(), fs::read(&manifest_path).unwrap());
+        let err = RotatingParquetWriter::new(config.clone(), "/test".to_string()).err().unwrap();
+        match err {
+            Error::OutputIo { ref path, ref source } => {
+                assert_eq!(path, &earlier);
+                assert_eq!(source.kind(), std::io::ErrorKind::AlreadyExists);
+            }
+            ref other => panic!("expected OutputIo, got {:?}", other),
+        }
+        assert!(err.to_string().contains("resume that scan"), "{}", err);
+        assert_eq!(fs::read(&earlier).unwrap(), chunk);
+        assert_eq!(fs::read(&manifest_path).unwrap(), manifest);
+
+        // Resuming numbers new chunks after the earlier ones
+        let mut writer = RotatingParquetWriter::resume(config, "/test".to_string()).unwrap();
+        writer.write_batch(&entries_in("a", 2)).unwrap();
+        let manifest = writer.finalize().unwrap();
+        assert_eq!(manifest.chunks.iter().map(|c| c.chunk_number).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(fs::read(&earlier).unwrap(), chunk);
+
+        // A chunk file that appears once the number is chosen fails the batch
+        let config = RotatingWriterConfig { base_output_path: temp_dir.path().join("other.parquet"), ..writer_config() };
+        let mut writer = RotatingParquetWriter::new(config, "/test".to_string()).unwrap();
+        let squatter = temp_dir.path().join("other_chunk_0001.parquet");
+        fs::write(&squatter, "not a chunk").unwrap();
+        let err = writer.write_batch(&entries_in("a", 2)).unwrap_err();
+        match err {
+            Error::OutputIo { ref path, ref source } => {
+                assert_eq!(path, &squatter);
+                assert_eq!(source.kind(), std::io::ErrorKind::AlreadyExists);
+            }
+            ref other => panic!("expected OutputIo, got {:?}", other),
+        }
+        assert!(err.to_string().contains("refusing to overwrite"), "{}", err);
+        assert_eq!(fs::read_to_string(&squatter).unwrap(), "not a chunk");
+    }
+
+    #[test]
+    fn test_resume_inherits_rotation_params() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = RotatingWriterConfig {
+            base_output_path: temp_dir.path().join("output.parquet"),
+            rows_per_chunk: 5,
+            time_interval: Duration::from_secs(3600),
+        };
+        write_and_finalize(config.clone(), 3);
+
+        let requested = RotatingWriterConfig { rows_per_chunk: 50, ..config };
+        let writer = RotatingParquetWriter::resume(requested, "/test".to_string()).unwrap();
+
+        assert_eq!(writer.config().rows_per_chunk, 5);
+        assert_eq!(writer.manifest.rotation.unwrap().rows_per_chunk, 5);
+        assert!(writer.manifest.parameter_changes.is_empty());
+    }
+
+    #[test]
+    fn test_resume_override_chunking() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = RotatingWriterConfig {
+            base_output_path: temp_dir.path().join("output.parquet"),
+            rows_per_chunk: 5,
+            time_interval: Duration::from_secs(3600),
+        };
+        write_and_finalize(config.clone(), 3);
+
+        let requested = RotatingWriterConfig { rows_per_chunk: 50, ..config };
+        let mut writer = RotatingParquetWriter::resume_with(requested, "/test".to_string(), true).unwrap();
+        writer.write_batch(&[create_test_entry("/test/more.txt", 1)]).unwrap();
+        let manifest = writer.finalize().unwrap();
+
+        assert_eq!(manifest.rotation.unwrap().rows_per_chunk, 50);
+        assert_eq!(manifest.parameter_changes.len(), 1);
+        assert_eq!(manifest.parameter_changes[0].previous.rows_per_chunk, 5);
+        assert_eq!(manifest.parameter_changes[0].current.rows_per_chunk, 50);
+
+        // The change survives a reload
+        let saved = ScanManifest::load_from_file(temp_dir.path().join("output_manifest.json")).unwrap();
+        assert_eq!(saved.parameter_changes, manifest.parameter_changes);
+    }
+
     #[test]
     fn test_manifest_serialization() {
         let mut manifest = ScanManifest::new("/test/path".to_string());
@@ -524,6 +1155,10 @@ mod tests {
             row_count: 1000,
             file_size: 50000,
             created_at: 1700000000,
+            checksum: None,
+            retention: None,
+            upload: None,
+            post_chunk: None,
         });
 
         manifest.complete();
@@ -540,4 +1175,29 @@ mod tests {
         assert!(content.contains("test/path"));
         assert!(content.contains("chunk_0"));
     }
+
+    #[test]
+    fn test_typed_errors() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = |base: PathBuf| RotatingWriterConfig {
+            base_output_path: base,
+            rows_per_chunk: 5,
+            time_interval: Duration::from_secs(3600),
+        };
+
+        // A resume from a corrupt manifest names it
+        let base = temp_dir.path().join("scan.parquet");
+        fs::write(temp_dir.path().join("scan_manifest.json"), "not json").unwrap();
+        let err = RotatingParquetWriter::resume(config(base), "/test".to_string()).err().unwrap();
+        assert!(matches!(err, Error::ManifestCorrupt { .. }), "{:?}", err);
+
+        // A chunk that can't be created fails the batch meant for it
+        let base = temp_dir.path().join("gone/scan.parquet");
+        let mut writer = RotatingParquetWriter::new(config(base), "/test".to_string()).unwrap();
+        let err = writer.write_batch(&[create_test_entry("/test/a.txt", 1)]).unwrap_err();
+        match err {
+            Error::OutputIo { path, .. } => assert!(path.ends_with("gone/scan_chunk_0001.parquet"), "{:?}", path),
+            other => panic!("expected OutputIo, got {:?}", other),
+        }
+    }
 }
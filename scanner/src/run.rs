@@ -0,0 +1,377 @@
+//! Scanning straight to Parquet output.
+//!
+//! [`run_scan_to_parquet`] runs the scanner and a writer on threads of their
+//! own, connected by a channel, and collects what both produced into one
+//! [`ScanResult`].
+
+use crate::error::{self, Error};
+use crate::manifest::{RotationParams, ScanManifest};
+use crate::models::{ScanOptions, ScanStats};
+use crate::rotating_writer::{RotatingParquetWriter, RotatingWriterConfig};
+use crate::scanner::{ScanError, Scanner};
+use crate::writer::ParquetFileWriter;
+use crossbeam_channel::bounded;
+use parquet::basic::Compression;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Where [`run_scan_to_parquet`] writes a scan's entries
+pub enum SinkConfig {
+    /// A single Parquet file
+    File { path: PathBuf, compression: Compression },
+
+    /// Rotating chunk files named after `base_output_path`, with a manifest,
+    /// as with `scan --incremental`
+    Chunked(RotatingWriterConfig),
+
+    /// Continue the chunked scan whose manifest is next to `base_output_path`,
+    /// skipping the top-level directories it completed
+    Resume(RotatingWriterConfig),
+
+    /// A rotating writer already set up, with its hooks or status attached.
+    /// The top-level directories its manifest lists as completed are skipped.
+    Writer(Box<RotatingParquetWriter>),
+}
+
+impl SinkConfig {
+    /// A single Snappy-compressed Parquet file
+    pub fn file(path: impl Into<PathBuf>) -> Self {
+        SinkConfig::File { path: path.into(), compression: Compression::SNAPPY }
+    }
+
+    /// Chunk files named after `base_output_path`, rotated per `params`
+    pub fn chunked(base_output_path: impl Into<PathBuf>, params: RotationParams) -> Self {
+        SinkConfig::Chunked(RotatingWriterConfig {
+            base_output_path: base_output_path.into(),
+            rows_per_chunk: params.rows_per_chunk,
+            time_interval: Duration::from_secs(params.chunk_interval_secs),
+        })
+    }
+}
+
+/// Everything a scan to Parquet produced
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct ScanResult {
+    pub stats: ScanStats,
+
+    /// Entries written, including those of earlier runs of a resumed scan
+    pub rows_written: u64,
+
+    /// The Parquet file, or every chunk file in the manifest
+    pub output_files: Vec<PathBuf>,
+
+    /// The final manifest, for chunked output
+    pub manifest: Option<ScanManifest>,
+
+    /// The first paths that could not be read, as sampled in
+    /// `stats.error_summary`; `stats.errors_encountered` counts them all
+    pub errors: Vec<ScanError>,
+}
+
+/// Scan `root_path` with `options` into the output `sink` describes
+pub fn run_scan_to_parquet(options: ScanOptions, root_path: &Path, sink: SinkConfig) -> error::Result<ScanResult> {
+    Scanner::new(options).run_to_parquet(root_path, sink)
+}
+
+impl Scanner {
+    /// Scan `root_path` into the output `sink` describes, writing on a
+    /// thread of its own while the scan runs
+    pub fn run_to_parquet(&self, root_path: &Path, sink: SinkConfig) -> error::Result<ScanResult> {
+        let (tx, rx) = bounded(self.options().batch_channel_capacity());
+        let scan_path = root_path.to_string_lossy().to_string();
+
+        let writer = match sink {
+            SinkConfig::File { path, compression } => {
+                let output = path.clone();
+                let counters = self.counters().clone();
+                let writer_handle = std::thread::spawn(move || {
+                    ParquetFileWriter::with_options(&output, Vec::new(), compression)?
+                        .with_scan_counts(counters)
+                        .consume_batches(rx)
+                });
+
+                let scanned = self.scan(root_path, tx);
+                let written = writer_handle
+                    .join()
+                    .map_err(|_| anyhow::anyhow!("Writer thread panicked"))?;
+                let (stats, rows_written) = writer_outcome(scanned, written)?;
+
+                return Ok(ScanResult {
+                    errors: sampled_errors(&stats),
+                    stats,
+                    rows_written,
+                    output_files: vec![path],
+                    manifest: None,
+                });
+            }
+            SinkConfig::Chunked(config) => RotatingParquetWriter::new(config, scan_path)?,
+            SinkConfig::Resume(config) => RotatingParquetWriter::resume(config, scan_path)?,
+            SinkConfig::Writer(writer) => *writer,
+        };
+
+        let writer = writer.with_scan_counts(self.counters().clone());
+        let completed = &writer.manifest.completed_top_level_dirs;
+        let skip_dirs = (!completed.is_empty()).then(|| completed.clone());
+        let writer_handle = std::thread::spawn(move || {
+            let mut writer = writer;
+            writer.write_all(rx)?;
+            Ok::<RotatingParquetWriter, Error>(writer)
+        });
+
+        let scanned = self.scan_with_filter(root_path, tx, skip_dirs);
+
+        // Wait for writer to drain, then record errors before finalizing
+        let written = writer_handle
+            .join()
+            .map_err(|_| anyhow::anyhow!("Writer thread panicked"))?;
+        let (stats, mut writer) = writer_outcome(scanned, written)?;
+        writer.record_errors(&stats.error_summary);
+        writer.record_skipped(stats.entries_skipped);
+        let manifest = writer.finalize()?;
+
+        Ok(ScanResult {
+            errors: sampled_errors(&stats),
+            stats,
+            rows_written: manifest.total_rows,
+            output_files: manifest.chunks.iter().map(|c| PathBuf::from(&c.file_path)).collect(),
+            manifest: Some(manifest),
+        })
+    }
+}
+
+/// The results of a scan and of the writer it fed, together. A failed
+/// writer closes the channel, which stops the scan, so its error comes first
+/// with the stats of the scan so far.
+fn writer_outcome<T>(scanned: error::Result<ScanStats>, written: error::Result<T>) -> error::Result<(ScanStats, T)> {
+    match (scanned, written) {
+        (Ok(stats), Ok(written)) => Ok((stats, written)),
+        (Ok(stats), Err(e)) => Err(Error::WriterFailed { source: Box::new(e), stats: Box::new(stats) }),
+        (Err(Error::Cancelled { stats, .. }), Err(e)) => Err(Error::WriterFailed { source: Box::new(e), stats }),
+        (Err(e), _) => Err(e),
+    }
+}
+
+fn sampled_errors(stats: &ScanStats) -> Vec<ScanError> {
+    stats.error_summary.samples.iter()
+        .map(|sample| ScanError {
+            kind: sample.kind.clone(),
+            path: PathBuf::from(&sample.path),
+            message: sample.message.clone(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::ChunkMetadata;
+    use crate::rotating_writer::ChunkHook;
+    use crate::writer::{ERRORS_KEY, SKIPPED_KEY};
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn create_tree(files: usize) -> TempDir {
+        let temp_dir = TempDir::new().unwrap();
+        for i in 0..files {
+            let dir = temp_dir.path().join(format!("dir{}", i % 4));
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join(format!("file{}.txt", i)), "data").unwrap();
+        }
+        temp_dir
+    }
+
+    #[test]
+    fn test_run_to_a_single_file() {
+        let tree = create_tree(20);
+        let out = TempDir::new().unwrap();
+        let output = out.path().join("scan.parquet");
+
+        let result = run_scan_to_parquet(ScanOptions::default(), tree.path(), SinkConfig::file(&output)).unwrap();
+        assert_eq!(result.stats.files_scanned, 20);
+        assert_eq!(result.rows_written, 20 + 4 + 1);
+        assert_eq!(result.output_files, vec![output.clone()]);
+        assert!(result.manifest.is_none());
+        assert!(result.errors.is_empty());
+        assert!(output.exists());
+    }
+
+    #[test]
+    fn test_run_to_chunks() {
+        let tree = create_tree(20);
+        let out = TempDir::new().unwrap();
+        let output = out.path().join("scan.parquet");
+        let params = RotationParams { rows_per_chunk: 10, chunk_interval_secs: 600 };
+        let options = ScanOptions::builder().batch_size(5).build().unwrap();
+
+        let result = run_scan_to_parquet(options, tree.path(), SinkConfig::chunked(&output, params)).unwrap();
+        let manifest = result.manifest.as_ref().unwrap();
+        assert_eq!(result.rows_written, 25);
+        assert_eq!(manifest.total_rows, 25);
+        assert_eq!(manifest.chunks.iter().map(|c| c.row_count).sum::<u64>(), 25);
+        assert_eq!(result.output_files.len(), manifest.chunks.len());
+        assert!(result.output_files.len() >= 3);
+        assert!(result.output_files.iter().all(|f| f.exists()));
+    }
+
+    #[test]
+    fn test_outputs_inside_the_tree_are_not_scanned() {
+        let tree = create_tree(40);
+        fs::write(tree.path().join("dir0/scanner.txt"), "not an output").unwrap();
+        let name = |e: &crate::models::FileEntry| Path::new(&e.path).file_name().unwrap().to_string_lossy().to_string();
+        let is_output = |e: &crate::models::FileEntry| name(e).starts_with("scan_") || name(e).starts_with("scan.");
+
+        let output = tree.path().join("dir0/scan.parquet");
+        let params = RotationParams { rows_per_chunk: 10, chunk_interval_secs: 600 };
+        let options = ScanOptions::builder().batch_size(5).exclude_output(&output).build().unwrap();
+        let result = run_scan_to_parquet(options, tree.path(), SinkConfig::chunked(&output, params)).unwrap();
+        assert!(result.output_files.len() >= 4);
+        assert_eq!(result.rows_written, 41 + 4 + 1);
+        let mut rows = Vec::new();
+        for chunk in &result.output_files {
+            rows.extend(crate::reader::read_all(chunk).unwrap());
+        }
+        assert_eq!(rows.len(), 46);
+        assert!(!rows.iter().any(is_output), "{:?}", rows.iter().filter(|e| is_output(e)).collect::<Vec<_>>());
+        assert!(rows.iter().any(|e| name(e) == "scanner.txt"));
+
+        let tree = create_tree(40);
+        let output = tree.path().join("scan.parquet");
+        let options = ScanOptions::builder().exclude_output(&output).build().unwrap();
+        let result = run_scan_to_parquet(options, tree.path(), SinkConfig::file(&output)).unwrap();
+        let rows = crate::reader::read_all(&output).unwrap();
+        assert_eq!(result.rows_written, 40 + 4 + 1);
+        assert!(!rows.iter().any(is_output));
+    }
+
+    /// A chunk hook that fails on the first chunk, as an upload to a full
+    /// disk would
+    struct FailingHook;
+
+    impl ChunkHook for FailingHook {
+        fn chunk_closed(&mut self, _chunk: &ChunkMetadata) -> anyhow::Result<()> {
+            anyhow::bail!("No space left on device")
+        }
+
+        fn record(&mut self, _manifest: &mut ScanManifest) {}
+
+        fn finish(&mut self, _manifest: &mut ScanManifest) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn create_wide_tree() -> TempDir {
+        let temp_dir = TempDir::new().unwrap();
+        for i in 0..50 {
+            let dir = temp_dir.path().join(format!("dir{}", i));
+            fs::create_dir(&dir).unwrap();
+            for j in 0..40 {
+                fs::write(dir.join(format!("file{}", j)), "x").unwrap();
+            }
+        }
+        temp_dir
+    }
+
+    #[test]
+    fn test_writer_failure_stops_the_scan() {
+        let tree = create_wide_tree();
+        let out = TempDir::new().unwrap();
+        let config = RotatingWriterConfig {
+            base_output_path: out.path().join("scan.parquet"),
+            rows_per_chunk: 10,
+            time_interval: Duration::from_secs(600),
+        };
+        let writer = RotatingParquetWriter::new(config, tree.path().to_string_lossy().to_string()).unwrap()
+            .with_chunk_hook(Box::new(FailingHook));
+        let options = ScanOptions::builder().threads(2).batch_size(10).channel_capacity_entries(20).build().unwrap();
+
+        let started = std::time::Instant::now();
+        let err = run_scan_to_parquet(options, tree.path(), SinkConfig::Writer(Box::new(writer))).unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(10), "took {:?}", started.elapsed());
+        match err {
+            Error::WriterFailed { ref source, ref stats } => {
+                assert!(source.to_string().contains("No space left on device"), "{}", source);
+                assert!(stats.files_scanned < 2000, "{} files scanned", stats.files_scanned);
+            }
+            ref other => panic!("expected WriterFailed, got {:?}", other),
+        }
+        assert!(err.to_string().contains("the scan stopped with"), "{}", err);
+    }
+
+    #[test]
+    fn test_unwritable_output_stops_the_scan() {
+        let tree = create_wide_tree();
+        let out = TempDir::new().unwrap();
+        let output = out.path().join("missing/scan.parquet");
+
+        let options = ScanOptions::builder().threads(2).batch_size(10).build().unwrap();
+
+        let err = run_scan_to_parquet(options, tree.path(), SinkConfig::file(&output)).unwrap_err();
+        match err {
+            Error::WriterFailed { ref source, ref stats } => {
+                assert!(matches!(**source, Error::OutputIo { ref path, .. } if *path == output), "{:?}", source);
+                assert!(stats.files_scanned < 2000, "{} files scanned", stats.files_scanned);
+            }
+            ref other => panic!("expected WriterFailed, got {:?}", other),
+        }
+        assert_eq!(err.io_error().unwrap().kind(), std::io::ErrorKind::NotFound);
+    }
+
+    /// The value of `key` in the footer of the Parquet file at `path`
+    fn footer_value(path: &Path, key: &str) -> Option<String> {
+        let file = fs::File::open(path).unwrap();
+        let reader = parquet::file::reader::SerializedFileReader::new(file).unwrap();
+        parquet::file::reader::FileReader::metadata(&reader).file_metadata().key_value_metadata()?
+            .iter()
+            .find(|kv| kv.key == key)
+            .and_then(|kv| kv.value.clone())
+    }
+
+    #[test]
+    fn test_scan_errors_are_recorded_in_the_footer() {
+        // Followed links to nothing fail with ENOENT, one error each
+        let tree = create_tree(8);
+        for i in 0..3 {
+            std::os::unix::fs::symlink(format!("/nonexistent/target{}", i), tree.path().join(format!("dir0/broken{}", i))).unwrap();
+        }
+        let out = TempDir::new().unwrap();
+        let output = out.path().join("scan.parquet");
+        let options = ScanOptions::builder().follow_symlinks(true).build().unwrap();
+
+        let result = run_scan_to_parquet(options, tree.path(), SinkConfig::file(&output)).unwrap();
+        assert_eq!(result.stats.errors_encountered, 3);
+        assert_eq!(result.errors.len(), 3);
+        assert_eq!(footer_value(&output, ERRORS_KEY).as_deref(), Some("3"));
+        assert_eq!(footer_value(&output, SKIPPED_KEY).as_deref(), Some("0"));
+    }
+
+    #[test]
+    fn test_resumed_run_records_what_it_skipped() {
+        let tree = create_tree(20);
+        let out = TempDir::new().unwrap();
+        let config = RotatingWriterConfig {
+            base_output_path: out.path().join("scan.parquet"),
+            rows_per_chunk: 10,
+            time_interval: Duration::from_secs(600),
+        };
+        let options = ScanOptions::builder().batch_size(5).build().unwrap();
+        let first = run_scan_to_parquet(options.clone(), tree.path(), SinkConfig::Chunked(config.clone())).unwrap();
+        assert_eq!(first.stats.entries_skipped, 0);
+        assert_eq!(first.manifest.unwrap().entries_skipped, 0);
+
+        // The completed directories are skipped rather than written again
+        let resumed = run_scan_to_parquet(options, tree.path(), SinkConfig::Resume(config)).unwrap();
+        let manifest = resumed.manifest.unwrap();
+        assert!(resumed.stats.entries_skipped > 0);
+        assert_eq!(manifest.entries_skipped, resumed.stats.entries_skipped);
+        assert_eq!(resumed.rows_written, 25);
+    }
+
+    #[test]
+    fn test_run_reports_a_missing_root() {
+        let out = TempDir::new().unwrap();
+        let missing = out.path().join("missing");
+        let result = run_scan_to_parquet(ScanOptions::default(), &missing, SinkConfig::file(out.path().join("scan.parquet")));
+        assert!(matches!(result, Err(Error::InvalidRoot { .. })), "{:?}", result.err());
+    }
+}
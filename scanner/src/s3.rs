@@ -0,0 +1,138 @@
+use crate::spool::ObjectStore;
+use anyhow::{Context, Result};
+use ::s3::creds::Credentials;
+use ::s3::{Bucket, Region};
+use std::fs::File;
+use std::path::Path;
+
+/// An `s3://bucket/prefix/name` output location
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct S3Location {
+    pub bucket: String,
+
+    /// Key prefix the outputs are stored under, empty or ending in `/`
+    pub prefix: String,
+
+    /// Output name the chunk and manifest names derive from (e.g. scan.parquet)
+    pub file_name: String,
+}
+
+impl S3Location {
+    pub fn parse(url: &str) -> Result<Self> {
+        let rest = url.strip_prefix("s3://")
+            .ok_or_else(|| anyhow::anyhow!("Not an s3:// URL: {}", url))?;
+        let (bucket, key) = rest.split_once('/')
+            .filter(|(bucket, _)| !bucket.is_empty())
+            .ok_or_else(|| anyhow::anyhow!("S3 output must be s3://bucket/[prefix/]name: {}", url))?;
+        let (prefix, file_name) = match key.rsplit_once('/') {
+            Some((prefix, name)) => (format!("{}/", prefix), name),
+            None => (String::new(), key),
+        };
+        if file_name.is_empty() {
+            anyhow::bail!("S3 output must name a file, such as s3://{}/{}scan.parquet", bucket, prefix);
+        }
+
+        Ok(Self { bucket: bucket.to_string(), prefix, file_name: file_name.to_string() })
+    }
+
+    /// Object key of `name` under the prefix
+    pub fn key(&self, name: &str) -> String {
+        format!("{}{}", self.prefix, name)
+    }
+}
+
+/// An S3 bucket prefix, or one on an S3-compatible server such as MinIO
+pub struct S3Store {
+    bucket: Bucket,
+    location: S3Location,
+}
+
+impl S3Store {
+    /// Connect with credentials from the standard environment variables or
+    /// profile. `endpoint` overrides the AWS endpoint, using path-style
+    /// requests as MinIO expects.
+    pub fn connect(location: S3Location, endpoint: Option<&str>) -> Result<Self> {
+        let region_name = std::env::var("AWS_REGION")
+            .or_else(|_| std::env::var("AWS_DEFAULT_REGION"))
+            .unwrap_or_else(|_| "us-east-1".to_string());
+        let region = match endpoint {
+            Some(endpoint) => Region::Custom { region: region_name, endpoint: endpoint.to_string() },
+            None => region_name.parse().context("Invalid AWS region")?,
+        };
+        let credentials = Credentials::default().context("No S3 credentials found")?;
+
+        let mut bucket = Bucket::new(&location.bucket, region, credentials)
+            .with_context(|| format!("Failed to open bucket {}", location.bucket))?;
+        if endpoint.is_some() {
+            bucket = bucket.with_path_style();
+        }
+
+        Ok(Self { bucket, location })
+    }
+}
+
+impl ObjectStore for S3Store {
+    fn url(&self, name: &str) -> String {
+        format!("s3://{}/{}", self.location.bucket, self.location.key(name))
+    }
+
+    /// Upload by multipart upload, reading the file in parts
+    fn put_file(&self, name: &str, path: &Path) -> Result<()> {
+        let mut file = File::open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        let status = self.bucket.put_object_stream(&mut file, self.location.key(name))
+            .with_context(|| format!("Failed to upload {}", self.url(name)))?;
+        if !(200..300).contains(&status) {
+            anyhow::bail!("Upload of {} returned HTTP {}", self.url(name), status);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_location() {
+        let location = S3Location::parse("s3://reports/cil/nightly/scan.parquet").unwrap();
+        assert_eq!(location, S3Location {
+            bucket: "reports".to_string(),
+            prefix: "cil/nightly/".to_string(),
+            file_name: "scan.parquet".to_string(),
+        });
+        assert_eq!(location.key("scan_chunk_0001.parquet"), "cil/nightly/scan_chunk_0001.parquet");
+
+        let location = S3Location::parse("s3://reports/scan.parquet").unwrap();
+        assert_eq!(location.key("scan_manifest.json"), "scan_manifest.json");
+
+        assert!(S3Location::parse("s3://reports/prefix/").is_err());
+        assert!(S3Location::parse("s3:///scan.parquet").is_err());
+        assert!(S3Location::parse("s3://reports").is_err());
+        assert!(S3Location::parse("/local/scan.parquet").is_err());
+    }
+
+    /// Round trip against a real or mock server, e.g. MinIO:
+    /// S3_TEST_URL=s3://bucket/prefix/ S3_TEST_ENDPOINT=http://127.0.0.1:9000
+    #[test]
+    fn test_put_file_against_server() {
+        let Ok(url) = std::env::var("S3_TEST_URL") else {
+            eprintln!("S3_TEST_URL not set; skipping");
+            return;
+        };
+        let endpoint = std::env::var("S3_TEST_ENDPOINT").ok();
+        let location = S3Location::parse(&format!("{}scan.parquet", url)).unwrap();
+        let store = S3Store::connect(location.clone(), endpoint.as_deref()).unwrap();
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("object.bin");
+        // Large enough to need several parts
+        let contents: Vec<u8> = (0..12 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&path, &contents).unwrap();
+        store.put_file("object.bin", &path).unwrap();
+
+        let response = store.bucket.get_object(location.key("object.bin")).unwrap();
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(response.bytes().as_ref(), contents.as_slice());
+    }
+}
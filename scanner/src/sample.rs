@@ -0,0 +1,378 @@
+use crate::aggregate::{conform_batch, reconcile_schemas};
+use crate::query::{Expr, QueryFormat, ResultWriter};
+use anyhow::{Context, Result};
+use arrow::array::{Array, ArrayRef, UInt32Array};
+use arrow::compute::{filter_record_batch, interleave, take};
+use arrow::datatypes::SchemaRef;
+use arrow::record_batch::RecordBatch;
+use arrow::util::display::array_value_to_string;
+use arrow::{csv, json};
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use rand::rngs::StdRng;
+use rand::{RngExt, SeedableRng};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// How rows are picked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleMode {
+    /// A uniform random sample of this many rows; the same seed gives the
+    /// same sample of the same input
+    Reservoir { size: usize, seed: u64 },
+
+    /// The first rows, in chunk order
+    Head(usize),
+}
+
+#[derive(Debug, Clone)]
+pub struct SampleOptions {
+    pub mode: SampleMode,
+
+    /// Only sample rows this expression holds for
+    pub filter: Option<Expr>,
+}
+
+/// Rows picked by [`sample`]
+#[derive(Debug, Clone)]
+pub struct Sample {
+    /// Sampled rows in input order, with the chunks' reconciled schema
+    pub batch: RecordBatch,
+
+    pub rows_scanned: u64,
+
+    /// Rows that passed the filter and were eligible for the sample
+    pub rows_matched: u64,
+}
+
+/// Reservoir slot: a row of one of the kept pieces, and its position among
+/// the eligible rows
+#[derive(Clone, Copy)]
+struct Slot {
+    piece: usize,
+    row: usize,
+    seen: u64,
+}
+
+/// Algorithm R over record batches. Chosen rows are copied out of each
+/// batch, so a sample never holds on to the batches it was drawn from, and
+/// the copies are compacted once replaced rows outnumber live ones.
+struct Reservoir {
+    size: usize,
+    rng: StdRng,
+    seen: u64,
+    slots: Vec<Slot>,
+    pieces: Vec<RecordBatch>,
+    piece_rows: usize,
+}
+
+impl Reservoir {
+    fn new(size: usize, seed: u64) -> Self {
+        Reservoir {
+            size,
+            rng: StdRng::seed_from_u64(seed),
+            seen: 0,
+            slots: Vec::with_capacity(size),
+            pieces: Vec::new(),
+            piece_rows: 0,
+        }
+    }
+
+    fn add(&mut self, batch: &RecordBatch) -> Result<()> {
+        // Row of this batch and its position among eligible rows, by slot;
+        // a later pick for a slot replaces an earlier one
+        let mut picks: BTreeMap<usize, (u32, u64)> = BTreeMap::new();
+        let mut filled = self.slots.len();
+        for row in 0..batch.num_rows() {
+            self.seen += 1;
+            let slot = if filled < self.size {
+                filled += 1;
+                filled - 1
+            } else {
+                let r = self.rng.random_range(0..self.seen);
+                if r >= self.size as u64 {
+                    continue;
+                }
+                r as usize
+            };
+            picks.insert(slot, (row as u32, self.seen));
+        }
+        if picks.is_empty() {
+            return Ok(());
+        }
+
+        let rows: Vec<u32> = picks.values().map(|(row, _)| *row).collect();
+        let piece = take_rows(batch, &rows)?;
+        let piece_index = self.pieces.len();
+        self.piece_rows += piece.num_rows();
+        self.pieces.push(piece);
+
+        // New slots follow the filled ones, so they are pushed in order
+        for (position, (slot, (_, seen))) in picks.into_iter().enumerate() {
+            let new = Slot { piece: piece_index, row: position, seen };
+            match self.slots.get_mut(slot) {
+                Some(existing) => *existing = new,
+                None => self.slots.push(new),
+            }
+        }
+
+        if self.piece_rows > 2 * self.size.max(1024) {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    /// Copy the live rows into one piece, in slot order
+    fn compact(&mut self) -> Result<()> {
+        let indices: Vec<(usize, usize)> = self.slots.iter().map(|s| (s.piece, s.row)).collect();
+        let batch = interleave_rows(&self.pieces, &indices)?;
+        for (row, slot) in self.slots.iter_mut().enumerate() {
+            slot.piece = 0;
+            slot.row = row;
+        }
+        self.piece_rows = batch.num_rows();
+        self.pieces = vec![batch];
+        Ok(())
+    }
+
+    fn finish(mut self, schema: &SchemaRef) -> Result<RecordBatch> {
+        if self.slots.is_empty() {
+            return Ok(RecordBatch::new_empty(schema.clone()));
+        }
+        self.slots.sort_by_key(|s| s.seen);
+        let indices: Vec<(usize, usize)> = self.slots.iter().map(|s| (s.piece, s.row)).collect();
+        interleave_rows(&self.pieces, &indices)
+    }
+}
+
+fn take_rows(batch: &RecordBatch, rows: &[u32]) -> Result<RecordBatch> {
+    let indices = UInt32Array::from(rows.to_vec());
+    let columns = batch.columns().iter()
+        .map(|column| take(column.as_ref(), &indices, None))
+        .collect::<std::result::Result<Vec<ArrayRef>, _>>()?;
+    Ok(RecordBatch::try_new(batch.schema(), columns)?)
+}
+
+/// Rows `(piece, row)` of `pieces`, which share a schema, as one batch
+fn interleave_rows(pieces: &[RecordBatch], indices: &[(usize, usize)]) -> Result<RecordBatch> {
+    let schema = pieces[0].schema();
+    let columns = (0..schema.fields().len())
+        .map(|c| {
+            let arrays: Vec<&dyn Array> = pieces.iter().map(|p| p.column(c).as_ref()).collect();
+            interleave(&arrays, indices)
+        })
+        .collect::<std::result::Result<Vec<ArrayRef>, _>>()?;
+    Ok(RecordBatch::try_new(schema, columns)?)
+}
+
+/// Sample rows of `chunk_files`, streaming them in chunk order one record
+/// batch at a time. The filter is applied before sampling, so a sample of
+/// matching rows is as large as the number of matches allows.
+pub fn sample(chunk_files: &[PathBuf], options: &SampleOptions) -> Result<Sample> {
+    let schema = reconcile_schemas(chunk_files)?;
+    if let Some(ref expr) = options.filter {
+        expr.validate(&schema)?;
+    }
+
+    let mut rows_scanned = 0;
+    let mut rows_matched = 0;
+    let mut reservoir = match options.mode {
+        SampleMode::Reservoir { size, seed } => Some(Reservoir::new(size, seed)),
+        SampleMode::Head(_) => None,
+    };
+    let mut head = Vec::new();
+
+    'chunks: for path in chunk_files {
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)
+            .with_context(|| format!("Failed to read {}", path.display()))?
+            .build()?;
+        for batch in reader {
+            let batch = conform_batch(&batch?, &schema)?;
+            rows_scanned += batch.num_rows() as u64;
+            let batch = match options.filter {
+                Some(ref expr) => filter_record_batch(&batch, &expr.mask(&batch)?)?,
+                None => batch,
+            };
+            rows_matched += batch.num_rows() as u64;
+
+            match (options.mode, reservoir.as_mut()) {
+                (SampleMode::Head(limit), _) => {
+                    let kept: usize = head.iter().map(RecordBatch::num_rows).sum();
+                    let wanted = limit.saturating_sub(kept).min(batch.num_rows());
+                    head.push(batch.slice(0, wanted));
+                    if kept + wanted >= limit {
+                        break 'chunks;
+                    }
+                }
+                (_, Some(reservoir)) => reservoir.add(&batch)?,
+                (_, None) => unreachable!("a reservoir exists unless taking the head"),
+            }
+        }
+    }
+
+    let batch = match reservoir {
+        Some(reservoir) => reservoir.finish(&schema)?,
+        None => arrow::compute::concat_batches(&schema, &head)?,
+    };
+    Ok(Sample { batch, rows_scanned, rows_matched })
+}
+
+/// Write `batch` to `output`, in the format its extension names (see
+/// [`QueryFormat::from_path`])
+pub fn write_file(batch: &RecordBatch, output: &Path) -> Result<()> {
+    let format = QueryFormat::from_path(output)?;
+    let mut writer = ResultWriter::create(output, format, batch.schema())?;
+    writer.write(batch)?;
+    writer.finish()
+}
+
+/// Rows of `batch` as CSV, with a header line
+pub fn write_csv(batch: &RecordBatch, out: impl Write) -> Result<()> {
+    let mut writer = csv::WriterBuilder::new().has_headers(true).build(out);
+    writer.write(batch)?;
+    Ok(())
+}
+
+/// Rows of `batch` as one JSON object per line
+pub fn write_jsonl(batch: &RecordBatch, out: impl Write) -> Result<()> {
+    let mut writer = json::LineDelimitedWriter::new(out);
+    writer.write(batch)?;
+    writer.finish()?;
+    Ok(())
+}
+
+/// Rows of `batch` as an aligned table, long values truncated
+pub fn write_table(batch: &RecordBatch, out: &mut impl Write) -> io::Result<()> {
+    const MAX_WIDTH: usize = 60;
+
+    let cell = |column: &ArrayRef, row: usize| -> String {
+        if column.is_null(row) {
+            return String::new();
+        }
+        let value = array_value_to_string(column, row).unwrap_or_else(|e| e.to_string());
+        match value.char_indices().nth(MAX_WIDTH - 1) {
+            Some((end, _)) => format!("{}…", &value[..end]),
+            None => value,
+        }
+    };
+
+    let schema = batch.schema();
+    let cells: Vec<Vec<String>> = batch.columns().iter()
+        .map(|column| (0..batch.num_rows()).map(|row| cell(column, row)).collect())
+        .collect();
+    let widths: Vec<usize> = schema.fields().iter().zip(&cells)
+        .map(|(field, cells)| cells.iter().map(|c| c.chars().count()).chain([field.name().len()]).max().unwrap_or(0))
+        .collect();
+
+    let line = |out: &mut dyn Write, values: &mut dyn Iterator<Item = &str>| -> io::Result<()> {
+        let padded: Vec<String> = values.zip(&widths).map(|(v, w)| format!("{:<w$}", v, w = *w)).collect();
+        writeln!(out, "{}", padded.join("  ").trim_end())
+    };
+    line(out, &mut schema.fields().iter().map(|f| f.name().as_str()))?;
+    for row in 0..batch.num_rows() {
+        line(out, &mut cells.iter().map(|c| c[row].as_str()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::FileEntry;
+    use crate::writer::ParquetFileWriter;
+    use arrow::array::{StringArray, UInt64Array};
+    use tempfile::TempDir;
+
+    fn entry(i: u64) -> FileEntry {
+        let parity = if i.is_multiple_of(2) { "even" } else { "odd" };
+        FileEntry { size: i, inode: i, ..FileEntry::fixture(&format!("/data/file{:05}.{}", i, parity)) }
+    }
+
+    /// 5,000 rows over three chunks, in batches small enough to exercise
+    /// compaction
+    fn fixture(dir: &Path) -> Vec<PathBuf> {
+        let bounds = [(0, 1500), (1500, 3000), (3000, 5000)];
+        bounds.iter().enumerate().map(|(i, (start, end))| {
+            let path = dir.join(format!("scan_chunk_{:04}.parquet", i + 1));
+            let mut writer = ParquetFileWriter::new(&path).unwrap();
+            for batch_start in (*start..*end).step_by(100) {
+                let entries: Vec<FileEntry> = (batch_start..batch_start + 100).map(entry).collect();
+                writer.write_batch(&entries).unwrap();
+            }
+            writer.close().unwrap();
+            path
+        }).collect()
+    }
+
+    fn sizes(sample: &Sample) -> Vec<u64> {
+        let column = sample.batch.column_by_name("size").unwrap();
+        column.as_any().downcast_ref::<UInt64Array>().unwrap().values().to_vec()
+    }
+
+    fn reservoir(size: usize, seed: u64, filter: Option<&str>) -> SampleOptions {
+        SampleOptions {
+            mode: SampleMode::Reservoir { size, seed },
+            filter: filter.map(|f| Expr::parse(f).unwrap()),
+        }
+    }
+
+    #[test]
+    fn test_reservoir_size_and_determinism() {
+        let temp_dir = TempDir::new().unwrap();
+        let chunks = fixture(temp_dir.path());
+
+        let first = sample(&chunks, &reservoir(300, 42, None)).unwrap();
+        assert_eq!(first.batch.num_rows(), 300);
+        assert_eq!((first.rows_scanned, first.rows_matched), (5000, 5000));
+
+        // Distinct rows, in input order, drawn from the whole input
+        let picked = sizes(&first);
+        assert!(picked.windows(2).all(|w| w[0] < w[1]));
+        assert!(picked[0] < 1000 && picked[299] >= 4000, "{:?}", picked);
+
+        assert_eq!(sizes(&sample(&chunks, &reservoir(300, 42, None)).unwrap()), picked);
+        assert_ne!(sizes(&sample(&chunks, &reservoir(300, 43, None)).unwrap()), picked);
+
+        // Asking for more rows than there are returns them all
+        assert_eq!(sample(&chunks, &reservoir(10_000, 1, None)).unwrap().batch.num_rows(), 5000);
+        assert_eq!(sample(&chunks, &reservoir(0, 1, None)).unwrap().batch.num_rows(), 0);
+    }
+
+    #[test]
+    fn test_filter_applies_before_sampling() {
+        let temp_dir = TempDir::new().unwrap();
+        let chunks = fixture(temp_dir.path());
+
+        let filtered = sample(&chunks, &reservoir(200, 7, Some("file_type == 'odd' && size < 4000"))).unwrap();
+        assert_eq!(filtered.rows_matched, 2000);
+        assert_eq!(filtered.batch.num_rows(), 200);
+        assert!(sizes(&filtered).iter().all(|size| size % 2 == 1 && *size < 4000));
+
+        let types = filtered.batch.column_by_name("file_type").unwrap();
+        let types = types.as_any().downcast_ref::<StringArray>().unwrap();
+        assert!(types.iter().all(|t| t == Some("odd")));
+
+        let mut jsonl = Vec::new();
+        write_jsonl(&filtered.batch, &mut jsonl).unwrap();
+        assert_eq!(String::from_utf8(jsonl).unwrap().lines().count(), 200);
+    }
+
+    #[test]
+    fn test_head_stops_early() {
+        let temp_dir = TempDir::new().unwrap();
+        let chunks = fixture(temp_dir.path());
+
+        let options = SampleOptions { mode: SampleMode::Head(5), filter: Some(Expr::parse("size >= 1498").unwrap()) };
+        let head = sample(&chunks, &options).unwrap();
+        assert_eq!(sizes(&head), [1498, 1499, 1500, 1501, 1502]);
+        // Reading stopped partway through the second chunk
+        assert!(head.rows_scanned < 3000, "{}", head.rows_scanned);
+
+        let mut table = Vec::new();
+        write_table(&head.batch.project(&[0, 1]).unwrap(), &mut table).unwrap();
+        let table = String::from_utf8(table).unwrap();
+        assert_eq!(table.lines().next(), Some("path                  size"));
+        assert_eq!(table.lines().nth(1), Some("/data/file01498.even  1498"));
+    }
+}
@@ -1,51 +1,523 @@
-use crate::models::{FileEntry, ScanOptions, ScanStats};
+#[cfg(feature = "progress")]
+use {
+    crate::dir_progress::{ScanDisplay, MAX_DIR_LINES},
+    std::io::IsTerminal,
+};
+use crate::dir_progress::ProgressEvent;
+use crate::error::{self, Error};
+use crate::models::{classify_io_error, ErrorSummary, FileEntry, ScanOptions, ScanStats, ENTRY_BYTES_ESTIMATE};
+use crate::progress::{Baseline, Progress, ProgressEstimator};
+use crate::shard::Shard;
+use crate::sink::EntrySink;
+use crate::status::ScanCounters;
+use crate::utils;
 use anyhow::{Context, Result};
-use crossbeam_channel::{bounded, Sender};
-use indicatif::{ProgressBar, ProgressStyle};
-use jwalk::WalkDir;
-use rayon::prelude::*;
-use std::collections::HashSet;
-use std::path::Path;
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Arc;
+use crossbeam_channel::{bounded, never, select, Receiver, Sender};
+use jwalk::WalkDirGeneric;
+use rayon::ThreadPool;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 use tracing::{debug, error, info, warn};
+#[cfg(feature = "serde")]
+use crate::status::{ScanState, StatusTracker, STATUS_INTERVAL};
+#[cfg(feature = "parquet-writer")]
+use crate::{manifest::RotationParams, run::{run_scan_to_parquet, SinkConfig}};
+
+/// How often the progress message is refreshed while entries arrive
+const PROGRESS_MESSAGE_INTERVAL: Duration = Duration::from_millis(250);
 
 /// Main scanner that traverses filesystem and collects file entries
+#[derive(Clone)]
 pub struct Scanner {
     options: ScanOptions,
-    stats: Arc<ScanStats>,
+
+    /// Counts of the running or last scan, shared by clones
+    live: Arc<LiveStats>,
+    baseline: Option<Baseline>,
+    #[cfg(feature = "serde")]
+    status: Option<Arc<StatusTracker>>,
+    shard: Option<Shard>,
+    cancel: Arc<AtomicBool>,
+    errors: Option<Sender<ScanError>>,
+
+    /// Threads every scan runs on, shared by clones
+    pool: Arc<OnceLock<Arc<ThreadPool>>>,
+
+    /// Called with each directory once it is listed, before its entries are read
+    #[cfg(test)]
+    after_listing: Option<DirHook>,
+}
+
+/// Test hook run on a directory during the walk
+#[cfg(test)]
+type DirHook = Arc<dyn Fn(&Path) + Send + Sync>;
+
+/// A path the scan could not read, or the failure that ended the scan
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanError {
+    /// Class of the failure, as counted in [`ErrorSummary::by_kind`]
+    pub kind: String,
+
+    pub path: PathBuf,
+
+    pub message: String,
+}
+
+impl std::fmt::Display for ScanError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {} ({})", self.path.display(), self.message, self.kind)
+    }
+}
+
+impl std::error::Error for ScanError {}
+
+/// Counts of the scan a [`Scanner`] is running, or ran last. The progress
+/// display, status file, and final [`ScanStats`] all read these counters.
+#[derive(Default)]
+struct LiveStats {
+    counters: ScanCounters,
+    error_summary: Mutex<ErrorSummary>,
+    phase: Mutex<Phase>,
+
+    /// Most entries seen waiting in the entry channel at once
+    peak_queued: AtomicUsize,
+}
+
+enum Phase {
+    /// Running since the start time in these stats
+    Started(ScanStats),
+
+    /// Over, with these final stats
+    Ended(ScanStats),
+}
+
+impl Default for Phase {
+    fn default() -> Self {
+        Phase::Ended(ScanStats::default())
+    }
+}
+
+impl LiveStats {
+    fn with_counters(counters: ScanCounters) -> Self {
+        Self { counters, ..Default::default() }
+    }
+
+    /// Zero the counts and start timing a new scan
+    fn start(&self) {
+        let counters = &self.counters;
+        for counter in [
+            &counters.files,
+            &counters.directories,
+            &counters.symlinks,
+            &counters.bytes,
+            &counters.unique_bytes,
+            &counters.errors,
+            &counters.skipped,
+            &counters.vanished,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+        self.peak_queued.store(0, Ordering::Relaxed);
+        *self.error_summary.lock().unwrap_or_else(|e| e.into_inner()) = ErrorSummary::default();
+        *self.phase.lock().unwrap_or_else(|e| e.into_inner()) = Phase::Started(ScanStats::new());
+    }
+
+    /// The counts so far, timed up to now while the scan runs
+    fn snapshot(&self) -> ScanStats {
+        let phase = self.phase.lock().unwrap_or_else(|e| e.into_inner());
+        let mut stats = match &*phase {
+            Phase::Ended(stats) => return stats.clone(),
+            Phase::Started(started) => started.clone(),
+        };
+        let counts = self.counters.load();
+        stats.files_scanned = counts.files;
+        stats.directories_scanned = counts.directories;
+        stats.symlinks_scanned = counts.symlinks;
+        stats.total_size = counts.bytes;
+        stats.unique_total_size = counts.unique_bytes;
+        stats.errors_encountered = counts.errors;
+        stats.entries_skipped = counts.skipped;
+        stats.entries_vanished = counts.vanished;
+        stats.error_summary = self.error_summary.lock()
+            .map(|summary| summary.clone())
+            .unwrap_or_default();
+        stats.finish();
+        stats
+    }
+
+    /// Stop timing and keep the final stats
+    fn end(&self) -> ScanStats {
+        let stats = self.snapshot();
+        *self.phase.lock().unwrap_or_else(|e| e.into_inner()) = Phase::Ended(stats.clone());
+        stats
+    }
+}
+
+/// Progress against a baseline, shared by the walker threads
+struct BaselineTracker {
+    estimator: ProgressEstimator,
+    dir_entries: HashMap<String, u64>,
+}
+
+impl BaselineTracker {
+    fn count(&mut self, dir: &str) {
+        match self.dir_entries.get_mut(dir) {
+            Some(count) => *count += 1,
+            None => {
+                self.dir_entries.insert(dir.to_string(), 1);
+            }
+        }
+    }
+
+    fn message(&mut self, elapsed_secs: f64, entries: u64, dir: &str) -> String {
+        self.estimator.record(elapsed_secs, entries);
+
+        let mut msg = format!(" | {:.1}%", self.estimator.percent(entries));
+        if let Some(eta) = self.estimator.eta_secs(entries) {
+            msg.push_str(&format!(", ETA {}", utils::format_duration(eta)));
+        }
+
+        let dir_entries = self.dir_entries.get(dir).copied().unwrap_or(0);
+        if let Some(percent) = self.estimator.dir_percent(dir, dir_entries) {
+            msg.push_str(&format!(" | {}: {:.0}%", dir, percent));
+        }
+        msg
+    }
+}
+
+/// Compiled [`ScanOptions::exclude_globs`]
+struct ExcludeGlobs {
+    names: Vec<glob::Pattern>,
+    paths: Vec<glob::Pattern>,
+}
+
+impl ExcludeGlobs {
+    fn new(patterns: &[String]) -> error::Result<Self> {
+        let mut excludes = Self { names: Vec::new(), paths: Vec::new() };
+        for pattern in patterns {
+            let compiled = glob::Pattern::new(pattern)
+                .map_err(|e| Error::invalid_option("exclude_glob", format!("{:?} does not parse: {}", pattern, e)))?;
+            if pattern.contains('/') {
+                excludes.paths.push(compiled);
+            } else {
+                excludes.names.push(compiled);
+            }
+        }
+        Ok(excludes)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.names.is_empty() && self.paths.is_empty()
+    }
+
+    /// Whether the entry at `relative` (to the scan root) is excluded
+    fn matches(&self, relative: &Path) -> bool {
+        let options = glob::MatchOptions { require_literal_separator: true, ..Default::default() };
+        let name = relative.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+        self.names.iter().any(|p| p.matches_with(&name, options))
+            || self.paths.iter().any(|p| p.matches_path_with(relative, options))
+    }
+}
+
+/// Files of [`ScanOptions::exclude_outputs`] that lie in the scanned tree
+struct OutputFiles {
+    /// Canonical directory of each output, with its file name and stem
+    outputs: Vec<(PathBuf, String, String)>,
+}
+
+impl OutputFiles {
+    fn new(outputs: &[PathBuf], root_path: &Path) -> Self {
+        let outputs = outputs.iter()
+            .filter_map(|output| {
+                let parent = match output.parent() {
+                    Some(parent) if !parent.as_os_str().is_empty() => parent,
+                    _ => Path::new("."),
+                };
+                let dir = parent.canonicalize().ok()?;
+                if !dir.starts_with(root_path) {
+                    return None;
+                }
+                let name = output.file_name()?.to_string_lossy().to_string();
+                let stem = output.file_stem()?.to_string_lossy().to_string();
+                debug!("Leaving {} and the files named after it out of the scan", output.display());
+                Some((dir, name, stem))
+            })
+            .collect();
+        Self { outputs }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.outputs.is_empty()
+    }
+
+    /// Whether `name`, in the directory `dir`, is one of the outputs' files:
+    /// the output itself, a file named `<stem>_*` or `<stem>.*`, or a hidden
+    /// `.<name>.*` temporary file
+    fn contains(&self, dir: &Path, name: &str) -> bool {
+        self.outputs.iter().any(|(output_dir, output_name, stem)| {
+            dir == output_dir
+                && (name == output_name
+                    || name.strip_prefix(stem.as_str()).is_some_and(|rest| rest.starts_with(['_', '.']))
+                    || name.strip_prefix('.').and_then(|rest| rest.strip_prefix(output_name.as_str()))
+                        .is_some_and(|rest| rest.starts_with('.')))
+        })
+    }
+}
+
+/// An entry's metadata, read on the pool as its directory is listed
+// Nearly every value is an Entry, so boxing it would only add an allocation
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
+enum Inspected {
+    Entry { entry: FileEntry, is_dir: bool, is_symlink: bool, size: u64 },
+    MetadataFailed(std::io::Error),
+    EntryFailed(anyhow::Error),
+
+    /// Removed since its directory was listed
+    Vanished(std::io::Error),
+}
+
+impl Inspected {
+    fn read(path: &Path, is_symlink: bool, follow_symlinks: bool, root_path: &Path) -> Self {
+        // Links not followed are recorded with their own metadata
+        let stat = || if is_symlink && !follow_symlinks {
+            std::fs::symlink_metadata(path)
+        } else {
+            std::fs::metadata(path)
+        };
+
+        // A busy filesystem may ask to be asked again, once
+        let metadata = stat().or_else(|e| match e.raw_os_error() {
+            Some(libc::EAGAIN) | Some(libc::EINTR) => stat(),
+            _ => Err(e),
+        });
+        let metadata = match metadata {
+            Ok(metadata) => metadata,
+            Err(e) if vanished(path, &e) => return Inspected::Vanished(e),
+            Err(e) => return Inspected::MetadataFailed(e),
+        };
+        match FileEntry::from_path(path, &metadata, root_path) {
+            Ok(mut entry) => {
+                if is_symlink {
+                    entry.link_target = std::fs::read_link(path).ok()
+                        .map(|target| target.to_string_lossy().to_string());
+                }
+                Inspected::Entry {
+                    entry,
+                    is_dir: metadata.is_dir(),
+                    is_symlink: metadata.file_type().is_symlink(),
+                    size: metadata.len(),
+                }
+            }
+            Err(e) => Inspected::EntryFailed(e),
+        }
+    }
+}
+
+/// Whether `e`, from reading the entry at `path`, means the entry itself is
+/// gone: removed or renamed after its directory was listed. A link to a
+/// missing target fails the same way but is still there.
+fn vanished(path: &Path, e: &std::io::Error) -> bool {
+    e.raw_os_error() == Some(libc::ENOENT)
+        && !path.as_os_str().is_empty()
+        && std::fs::symlink_metadata(path).is_err_and(|e| e.kind() == std::io::ErrorKind::NotFound)
 }
 
 impl Scanner {
     pub fn new(options: ScanOptions) -> Self {
         Self {
             options,
-            stats: Arc::new(ScanStats::new()),
+            live: Arc::default(),
+            baseline: None,
+            #[cfg(feature = "serde")]
+            status: None,
+            shard: None,
+            cancel: Arc::new(AtomicBool::new(false)),
+            errors: None,
+            pool: Arc::new(OnceLock::new()),
+            #[cfg(test)]
+            after_listing: None,
+        }
+    }
+
+    pub fn options(&self) -> &ScanOptions {
+        &self.options
+    }
+
+    /// Run scans on `pool` instead of a pool of the scanner's own.
+    ///
+    /// Directories are read and entries inspected as tasks on the pool, so
+    /// [`ScanOptions::num_threads`] no longer applies. Call [`Scanner::scan`]
+    /// from outside the pool: the calling thread waits on the pool's work.
+    pub fn with_thread_pool(self, pool: Arc<ThreadPool>) -> Self {
+        Self { pool: Arc::new(OnceLock::from(pool)), ..self }
+    }
+
+    /// The pool scans run on, built with [`ScanOptions::num_threads`] threads
+    /// on first use and kept for later scans
+    fn thread_pool(&self) -> Result<Arc<ThreadPool>> {
+        if let Some(pool) = self.pool.get() {
+            return Ok(pool.clone());
+        }
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(self.options.num_threads)
+            .thread_name(|i| format!("scanner-{}", i))
+            .build()
+            .context("Failed to build thread pool")?;
+        Ok(self.pool.get_or_init(|| Arc::new(pool)).clone())
+    }
+
+    /// Show percent complete and ETA relative to a previous scan
+    pub fn with_baseline(mut self, baseline: Baseline) -> Self {
+        self.baseline = Some(baseline);
+        self
+    }
+
+    /// Count entries and errors in `status`, and rewrite its status file every
+    /// [`STATUS_INTERVAL`] while scanning
+    #[cfg(feature = "serde")]
+    pub fn with_status(mut self, status: Arc<StatusTracker>) -> Self {
+        self.status = Some(status);
+        self
+    }
+
+    /// Count into `counters` instead of counters of its own, so others can
+    /// read them while the scan runs. They are zeroed as each scan starts.
+    pub fn with_counters(mut self, counters: ScanCounters) -> Self {
+        self.live = Arc::new(LiveStats::with_counters(counters));
+        self
+    }
+
+    /// The counters this scanner's scans update
+    #[cfg(feature = "parquet-writer")]
+    pub(crate) fn counters(&self) -> &ScanCounters {
+        &self.live.counters
+    }
+
+    /// Statistics of the scan running now, up to this moment, or of the last
+    /// scan once it has ended. Callable from any thread; clones of this
+    /// scanner, such as the one behind [`Scanner::iter`], report here too.
+    pub fn stats_snapshot(&self) -> ScanStats {
+        self.live.snapshot()
+    }
+
+    /// Scan only `shard`'s part of the tree. Directories of other shards
+    /// are not descended into, so each task of a sharded scan walks only
+    /// its own part.
+    pub fn with_shard(mut self, shard: Shard) -> Self {
+        self.shard = Some(shard);
+        self
+    }
+
+    /// Scan `root_path` in the background, yielding its entries as they are
+    /// found.
+    ///
+    /// Paths that cannot be read are yielded as errors and the scan goes on;
+    /// a scan that cannot start or fails outright yields one last error.
+    /// Entries arrive a batch at a time, so a smaller
+    /// [`batch_size`](ScanOptions::batch_size) yields the first ones sooner.
+    /// Dropping the iterator stops the scan.
+    pub fn iter<P: AsRef<Path>>(&self, root_path: P) -> ScanIter {
+        let (tx, entries) = bounded(2);
+        let (error_tx, errors) = bounded(1024);
+        let root_path = root_path.as_ref().to_path_buf();
+        let (cancel, handle) = self.spawn(root_path.clone(), tx, Some(error_tx));
+
+        ScanIter {
+            root_path,
+            entries,
+            errors,
+            entries_open: true,
+            errors_open: true,
+            batch: Vec::new().into_iter(),
+            cancel,
+            handle: Some(handle),
+            stats: None,
         }
     }
 
-    /// Scan a directory and send FileEntry records through the channel
+    /// Scan `root_path`, handing its entries to `sink` a batch at a time.
+    ///
+    /// The walk runs on the scanner's threads while this thread calls the
+    /// sink. If the sink fails, the scan is stopped and its error is returned
+    /// as [`Error::Sink`]. The sink is not finalized; its owner does that once
+    /// this returns.
+    pub fn scan_into<P: AsRef<Path>>(&self, root_path: P, sink: &mut dyn EntrySink) -> error::Result<ScanStats> {
+        let (tx, rx) = bounded(2);
+        let (cancel, handle) = self.spawn(root_path.as_ref().to_path_buf(), tx, self.errors.clone());
+
+        let mut failure = None;
+        for batch in &rx {
+            if let Err(e) = sink.write_batch(&batch) {
+                cancel.store(true, Ordering::Relaxed);
+                failure = Some(e);
+                break;
+            }
+        }
+        // Unblock the walk's sends so it winds down
+        drop(rx);
+
+        let result = handle.join().map_err(|_| anyhow::anyhow!("Scanner thread panicked"))?;
+        match failure {
+            Some(source) => Err(Error::Sink { source }),
+            None => result,
+        }
+    }
+
+    /// Run a copy of this scanner on a thread of its own, sending unreadable
+    /// paths to `errors` if given. Setting the returned flag stops the scan.
+    pub(crate) fn spawn(
+        &self,
+        root_path: PathBuf,
+        tx: Sender<Vec<FileEntry>>,
+        errors: Option<Sender<ScanError>>,
+    ) -> (Arc<AtomicBool>, JoinHandle<error::Result<ScanStats>>) {
+        let cancel = Arc::new(AtomicBool::new(false));
+        let mut scanner = self.clone();
+        scanner.cancel = cancel.clone();
+        scanner.errors = errors;
+        (cancel, std::thread::spawn(move || scanner.scan(root_path, tx)))
+    }
+
+    /// Scan a directory and send FileEntry records through the channel.
+    ///
+    /// If the receiver is dropped, the walk stops at the next batch it can't
+    /// send, and the scan returns [`Error::Cancelled`] with the counts so far.
     pub fn scan<P: AsRef<Path>>(
         &self,
         root_path: P,
         tx: Sender<Vec<FileEntry>>,
-    ) -> Result<ScanStats> {
+    ) -> error::Result<ScanStats> {
         self.scan_with_filter(root_path, tx, None)
     }
 
-    /// Scan a directory with optional filter for skipping completed directories
+    /// Scan a directory with optional filter for skipping completed directories.
+    ///
+    /// An entry is skipped when its [`FileEntry::resume_unit`] is in
+    /// `skip_dirs`, so files directly in the root are only skipped along with
+    /// [`ROOT_FILES_UNIT`](crate::models::ROOT_FILES_UNIT).
     pub fn scan_with_filter<P: AsRef<Path>>(
         &self,
         root_path: P,
         tx: Sender<Vec<FileEntry>>,
         skip_dirs: Option<HashSet<String>>,
-    ) -> Result<ScanStats> {
+    ) -> error::Result<ScanStats> {
         let root_path = root_path.as_ref().canonicalize()
-            .context("Failed to canonicalize root path")?;
+            .map_err(|source| Error::InvalidRoot { path: root_path.as_ref().to_path_buf(), source })?;
+        let excludes = ExcludeGlobs::new(&self.options.exclude_globs)?;
+        let outputs = OutputFiles::new(&self.options.exclude_outputs, &root_path);
 
         info!("Starting scan of: {}", root_path.display());
         info!("Scan configuration: threads={}, batch_size={}",
               self.options.num_threads, self.options.batch_size);
+        info!("Entry queues hold up to {} entries (about {} at {} bytes each)",
+              self.options.max_queued_entries(),
+              utils::format_bytes(self.options.queue_memory_estimate()),
+              ENTRY_BYTES_ESTIMATE);
 
         if let Some(ref dirs) = skip_dirs {
             if !dirs.is_empty() {
@@ -59,61 +531,73 @@ impl Scanner {
             }
         }
 
-        // Setup progress bar
-        let progress = ProgressBar::new_spinner();
-        progress.set_style(
-            ProgressStyle::default_spinner()
-                .template("{spinner:.green} [{elapsed_precise}] {msg}")
-                .unwrap()
-        );
+        // Setup progress display
+        let mut progress = self.progress_display(&root_path, skip_dirs.as_ref(), &excludes);
 
-        // Atomic counters for statistics
-        let files_counter = Arc::new(AtomicU64::new(0));
-        let dirs_counter = Arc::new(AtomicU64::new(0));
-        let size_counter = Arc::new(AtomicU64::new(0));
-        let errors_counter = Arc::new(AtomicU64::new(0));
-        let skipped_counter = Arc::new(AtomicU64::new(0));
+        self.live.start();
+        let counters = &self.live.counters;
 
-        // Configure rayon thread pool
-        rayon::ThreadPoolBuilder::new()
-            .num_threads(self.options.num_threads)
-            .build()
-            .context("Failed to build thread pool")?
-            .install(|| {
-                self.scan_parallel(
-                    &root_path,
-                    tx,
-                    &progress,
-                    files_counter.clone(),
-                    dirs_counter.clone(),
-                    size_counter.clone(),
-                    errors_counter.clone(),
-                    skipped_counter.clone(),
-                    skip_dirs,
-                )
-            })?;
-
-        progress.finish_with_message("Scan complete");
-
-        // Build final statistics
-        let mut final_stats = ScanStats::new();
-        final_stats.files_scanned = files_counter.load(Ordering::Relaxed);
-        final_stats.directories_scanned = dirs_counter.load(Ordering::Relaxed);
-        final_stats.total_size = size_counter.load(Ordering::Relaxed);
-        final_stats.errors_encountered = errors_counter.load(Ordering::Relaxed);
-        final_stats.finish();
-
-        let skipped = skipped_counter.load(Ordering::Relaxed);
-
-        info!("Scan completed: {} files, {} directories, {:.2} GB total",
+        // Rewrite the status file until the sender is dropped after the walk
+        #[cfg(feature = "serde")]
+        let (status_stop, status_thread) = match self.status {
+            Some(ref status) => {
+                let (stop_tx, stop_rx) = bounded::<()>(1);
+                let status = status.clone();
+                let counters = counters.clone();
+                let thread = std::thread::spawn(move || loop {
+                    if let Err(e) = status.write(counters.load(), ScanState::Running) {
+                        warn!("Failed to update scan status: {:#}", e);
+                    }
+                    if stop_rx.recv_timeout(STATUS_INTERVAL).is_err_and(|e| e.is_disconnected()) {
+                        break;
+                    }
+                });
+                (Some(stop_tx), Some(thread))
+            }
+            None => (None, None),
+        };
+
+        let output_closed = self.scan_parallel(&root_path, tx, progress.as_mut(), skip_dirs, excludes, outputs)
+            .inspect_err(|_| { self.live.end(); })?;
+
+        progress.finish("Scan complete");
+
+        #[cfg(feature = "serde")]
+        {
+            drop(status_stop);
+            if let Some(thread) = status_thread {
+                thread.join().map_err(|_| anyhow::anyhow!("Status thread panicked"))?;
+            }
+        }
+
+        let final_stats = self.live.end();
+
+        if self.cancel.load(Ordering::Relaxed) || output_closed {
+            let entries = final_stats.files_scanned + final_stats.directories_scanned + final_stats.symlinks_scanned;
+            if output_closed {
+                warn!("Entries are no longer being received; scan stopped after {} entries", entries);
+            } else {
+                info!("Scan cancelled after {} entries", entries);
+            }
+            return Err(Error::Cancelled { root: root_path, stats: Box::new(final_stats) });
+        }
+
+        let skipped = counters.skipped.load(Ordering::Relaxed);
+
+        info!("Scan completed: {} files, {} directories, {:.2} GB total ({:.2} GB unique)",
               final_stats.files_scanned,
               final_stats.directories_scanned,
-              final_stats.total_size as f64 / 1_073_741_824.0);
+              final_stats.total_size as f64 / 1_073_741_824.0,
+              final_stats.unique_total_size as f64 / 1_073_741_824.0);
 
         if skipped > 0 {
             info!("Skipped {} files from already-completed directories", skipped);
         }
 
+        if final_stats.entries_vanished > 0 {
+            info!("{} entries vanished during the scan", final_stats.entries_vanished);
+        }
+
         info!("Performance: {:.2} files/second, duration: {:.2}s",
               final_stats.files_per_second(),
               final_stats.duration_secs);
@@ -125,36 +609,153 @@ impl Scanner {
         Ok(final_stats)
     }
 
+    /// What a scan of `root_path` shows on the terminal, if anything
+    fn progress_display(
+        &self,
+        root_path: &Path,
+        skip_dirs: Option<&HashSet<String>>,
+        excludes: &ExcludeGlobs,
+    ) -> Box<dyn Progress> {
+        #[cfg(feature = "progress")]
+        if std::io::stderr().is_terminal() {
+            return Box::new(ScanDisplay::new(
+                || self.top_level_dirs(root_path, skip_dirs, excludes),
+                self.baseline.as_ref(),
+            ));
+        }
+        let _ = (root_path, skip_dirs, excludes);
+        Box::new(crate::progress::Silent::new())
+    }
+
+    /// Names of the directories directly under `root_path` that a scan walks,
+    /// sorted; empty if it has more than [`MAX_DIR_LINES`] of them
+    #[cfg(feature = "progress")]
+    fn top_level_dirs(&self, root_path: &Path, skip_dirs: Option<&HashSet<String>>, excludes: &ExcludeGlobs) -> Vec<String> {
+        if self.options.max_depth == Some(0) {
+            return Vec::new();
+        }
+        let Ok(read_dir) = std::fs::read_dir(root_path) else { return Vec::new() };
+        let mut dirs = Vec::new();
+        for entry in read_dir.flatten() {
+            let is_dir = match entry.file_type() {
+                Ok(file_type) if file_type.is_symlink() && self.options.follow_symlinks => {
+                    entry.path().is_dir()
+                }
+                Ok(file_type) => file_type.is_dir(),
+                Err(_) => false,
+            };
+            let name = entry.file_name().to_string_lossy().to_string();
+            let walked = !excludes.matches(Path::new(&name))
+                && !skip_dirs.is_some_and(|skip| skip.contains(&name))
+                && !self.shard.is_some_and(|shard| shard.depth == 1 && !shard.owns(Path::new(&name)));
+            if is_dir && walked {
+                dirs.push(name);
+                if dirs.len() > MAX_DIR_LINES {
+                    return Vec::new();
+                }
+            }
+        }
+        dirs.sort();
+        dirs
+    }
+
+    /// Walk `root_path`, sending its entries to `tx` in batches. Returns
+    /// whether the walk stopped early because `tx` was closed.
     fn scan_parallel(
         &self,
         root_path: &Path,
         tx: Sender<Vec<FileEntry>>,
-        progress: &ProgressBar,
-        files_counter: Arc<AtomicU64>,
-        dirs_counter: Arc<AtomicU64>,
-        size_counter: Arc<AtomicU64>,
-        errors_counter: Arc<AtomicU64>,
-        skipped_counter: Arc<AtomicU64>,
+        progress: &mut dyn Progress,
         skip_dirs: Option<HashSet<String>>,
-    ) -> Result<()> {
+        excludes: ExcludeGlobs,
+        outputs: OutputFiles,
+    ) -> Result<bool> {
         let batch_size = self.options.batch_size;
         let follow_symlinks = self.options.follow_symlinks;
         let max_depth = self.options.max_depth;
+        let min_size = self.options.min_size;
+        let ScanCounters {
+            files: files_counter,
+            directories: dirs_counter,
+            symlinks: symlinks_counter,
+            bytes: size_counter,
+            unique_bytes: unique_size_counter,
+            errors: errors_counter,
+            skipped: skipped_counter,
+            vanished: vanished_counter,
+        } = &self.live.counters;
+        let error_summary = &self.live.error_summary;
 
-        // Configure jwalk
-        let mut walker = WalkDir::new(root_path)
+        // Configure jwalk to read directories on the scanner's pool
+        let mut walker = WalkDirGeneric::<((), Option<Inspected>)>::new(root_path)
             .follow_links(follow_symlinks)
-            .parallelism(jwalk::Parallelism::RayonNewPool(self.options.num_threads));
+            .parallelism(jwalk::Parallelism::RayonExistingPool {
+                pool: self.thread_pool()?,
+                busy_timeout: None,
+            });
 
+        // jwalk counts depth as FileEntry::depth does, from 0 at the root,
+        // and yields entries at max_depth without reading below them
         if let Some(depth) = max_depth {
             walker = walker.max_depth(depth);
         }
 
-        // Collect entries in batches
-        let (batch_tx, batch_rx) = bounded::<FileEntry>(batch_size * 2);
+        // Set once `tx` is closed, as when the writer receiving the entries
+        // failed: nothing more found could be kept
+        let output_closed = Arc::new(AtomicBool::new(false));
+
+        // Leave other shards' entries at the shard level, and excluded
+        // entries, unread along with all below them, and the files of the
+        // scan's own outputs unread; once cancelled or the output is closed,
+        // read nothing more. Read the metadata of the rest here, on the pool.
+        let shard = self.shard;
+        let root = root_path.to_path_buf();
+        let cancel = self.cancel.clone();
+        let closed = output_closed.clone();
+        #[cfg(test)]
+        let after_listing = self.after_listing.clone();
+        walker = walker.process_read_dir(move |_, dir, _, children| {
+            if cancel.load(Ordering::Relaxed) || closed.load(Ordering::Relaxed) {
+                children.clear();
+                return;
+            }
+            if !outputs.is_empty() {
+                children.retain(|child| {
+                    let Ok(entry) = child else { return true };
+                    !outputs.contains(dir, &entry.file_name.to_string_lossy())
+                });
+            }
+            if shard.is_some() || !excludes.is_empty() {
+                children.retain(|child| {
+                    let Ok(entry) = child else { return true };
+                    let path = entry.path();
+                    let Ok(relative) = path.strip_prefix(&root) else { return true };
+                    if excludes.matches(relative) {
+                        return false;
+                    }
+                    match shard {
+                        Some(shard) if entry.depth == shard.depth => shard.owns(relative),
+                        _ => true,
+                    }
+                });
+            }
+            #[cfg(test)]
+            if let Some(ref after_listing) = after_listing {
+                after_listing(dir);
+            }
+            for entry in children.iter_mut().flatten() {
+                entry.client_state = Some(Inspected::read(&entry.path(), entry.path_is_symlink(), follow_symlinks, &root));
+            }
+        });
+
+        // Collect entries in batches. The channel is sized in entries, not
+        // batches, so a large batch size doesn't let more of them pile up.
+        let (batch_tx, batch_rx) = bounded::<FileEntry>(self.options.channel_capacity_entries);
+        let peak_queued = &self.live.peak_queued;
 
         // Spawn thread to collect and send batches
         let tx_clone = tx.clone();
+        let closed = output_closed.clone();
         let batch_thread = std::thread::spawn(move || {
             let mut batch = Vec::with_capacity(batch_size);
 
@@ -164,90 +765,181 @@ impl Scanner {
                 if batch.len() >= batch_size {
                     let send_batch = std::mem::replace(&mut batch, Vec::with_capacity(batch_size));
                     if tx_clone.send(send_batch).is_err() {
-                        break;
+                        closed.store(true, Ordering::Relaxed);
+                        return;
                     }
                 }
             }
 
             // Send remaining entries
-            if !batch.is_empty() {
-                let _ = tx_clone.send(batch);
+            if !batch.is_empty() && tx_clone.send(batch).is_err() {
+                closed.store(true, Ordering::Relaxed);
             }
         });
 
-        // Process directory entries in parallel
+        let tracker = self.baseline.clone().map(|baseline| Mutex::new(BaselineTracker {
+            estimator: ProgressEstimator::new(baseline),
+            dir_entries: HashMap::new(),
+        }));
+
+        let record_error = |kind: &str, path: &Path, message: String| {
+            errors_counter.fetch_add(1, Ordering::Relaxed);
+            #[cfg(feature = "serde")]
+            if let Some(ref status) = self.status {
+                status.record_error(kind, &path.to_string_lossy(), &message);
+            }
+            if let Some(ref errors) = self.errors {
+                let error = ScanError { kind: kind.to_string(), path: path.to_path_buf(), message: message.clone() };
+                let _ = errors.send(error);
+            }
+            if let Ok(mut summary) = error_summary.lock() {
+                summary.record(kind, &path.to_string_lossy(), message);
+            }
+        };
+
+        // Count and send entries in the order the walk yields them. Files
+        // with more than one link are remembered by inode, so the unique
+        // size counts each once; files with a single link need no entry.
+        let mut last_message: Option<Instant> = None;
+        let mut linked_inodes: HashSet<(u64, u64)> = HashSet::new();
         walker.into_iter()
-            .par_bridge()
             .for_each(|entry_result| {
+                if self.cancel.load(Ordering::Relaxed) || output_closed.load(Ordering::Relaxed) {
+                    return;
+                }
                 match entry_result {
-                    Ok(entry) => {
+                    Ok(mut entry) => {
                         let path = entry.path();
 
-                        match std::fs::metadata(&path) {
-                            Ok(metadata) => {
-                                // Create FileEntry first to check top_level_dir
-                                match FileEntry::from_path(&path, &metadata, root_path) {
-                                    Ok(file_entry) => {
-                                        // Skip if this top-level directory is already completed
-                                        if let Some(ref skip_set) = skip_dirs {
-                                            if skip_set.contains(&file_entry.top_level_dir) {
-                                                skipped_counter.fetch_add(1, Ordering::Relaxed);
-                                                return; // Skip this entry
-                                            }
-                                        }
-
-                                        // Update counters
-                                        if metadata.is_dir() {
-                                            dirs_counter.fetch_add(1, Ordering::Relaxed);
-                                        } else {
-                                            files_counter.fetch_add(1, Ordering::Relaxed);
-                                            size_counter.fetch_add(metadata.len(), Ordering::Relaxed);
-                                        }
-
-                                        // Update progress
-                                        let total = files_counter.load(Ordering::Relaxed)
-                                                  + dirs_counter.load(Ordering::Relaxed);
-                                        if total % 10000 == 0 {
-                                            let skipped = skipped_counter.load(Ordering::Relaxed);
-                                            let msg = if skipped > 0 {
-                                                format!(
-                                                    "Scanned: {} files, {} dirs, {:.2} GB (skipped: {})",
-                                                    files_counter.load(Ordering::Relaxed),
-                                                    dirs_counter.load(Ordering::Relaxed),
-                                                    size_counter.load(Ordering::Relaxed) as f64 / 1_073_741_824.0,
-                                                    skipped
-                                                )
-                                            } else {
-                                                format!(
-                                                    "Scanned: {} files, {} dirs, {:.2} GB",
-                                                    files_counter.load(Ordering::Relaxed),
-                                                    dirs_counter.load(Ordering::Relaxed),
-                                                    size_counter.load(Ordering::Relaxed) as f64 / 1_073_741_824.0
-                                                )
-                                            };
-                                            progress.set_message(msg);
-                                        }
-
-                                        // Send the entry
-                                        if batch_tx.send(file_entry).is_err() {
-                                            debug!("Batch channel closed, stopping scan");
-                                        }
+                        // Entries above the shard level belong to shard 0
+                        if let Some(shard) = self.shard {
+                            if entry.depth < shard.depth && shard.index != 0 {
+                                return;
+                            }
+                        }
+
+                        // Directories whose contents could not be listed still yield
+                        // an entry; the failure is attached to it instead
+                        if let Some(ref e) = entry.read_children_error {
+                            debug!("Failed to read directory {}: {}", path.display(), e);
+                            match e.io_error() {
+                                Some(io) if vanished(&path, io) => {
+                                    vanished_counter.fetch_add(1, Ordering::Relaxed);
+                                }
+                                io => record_error(io.map(classify_io_error).unwrap_or("other"), &path, e.to_string()),
+                            }
+                        }
+
+                        // Metadata was read on the walker's threads, except the root's
+                        let inspected = entry.client_state.take()
+                            .unwrap_or_else(|| Inspected::read(&path, entry.path_is_symlink(), follow_symlinks, root_path));
+                        match inspected {
+                            Inspected::Entry { is_dir, size, .. } if !is_dir
+                                && min_size.is_some_and(|min| size < min) => {}
+                            // The recorded depth is what max_depth promises to bound
+                            Inspected::Entry { ref entry, .. } if max_depth
+                                .is_some_and(|max| entry.depth as usize > max) => {
+                                debug!("Leaving out {} at depth {}, below max_depth", entry.path, entry.depth);
+                            }
+                            Inspected::Entry { entry: file_entry, is_dir, is_symlink, size } => {
+                                // Skip if this entry's unit is already completed
+                                if let Some(ref skip_set) = skip_dirs {
+                                    if skip_set.contains(file_entry.resume_unit()) {
+                                        skipped_counter.fetch_add(1, Ordering::Relaxed);
+                                        return; // Skip this entry
+                                    }
+                                }
+
+                                // Update counters
+                                let counts_size = !is_dir && !is_symlink;
+                                if is_dir {
+                                    dirs_counter.fetch_add(1, Ordering::Relaxed);
+                                } else if is_symlink {
+                                    symlinks_counter.fetch_add(1, Ordering::Relaxed);
+                                } else {
+                                    files_counter.fetch_add(1, Ordering::Relaxed);
+                                    size_counter.fetch_add(size, Ordering::Relaxed);
+                                    if file_entry.nlink <= 1 || linked_inodes.insert((file_entry.device, file_entry.inode)) {
+                                        unique_size_counter.fetch_add(size, Ordering::Relaxed);
                                     }
-                                    Err(e) => {
-                                        errors_counter.fetch_add(1, Ordering::Relaxed);
-                                        error!("Failed to create entry for {}: {}", path.display(), e);
+                                }
+
+                                if let Some(Ok(mut tracker)) = tracker.as_ref().map(|t| t.lock()) {
+                                    tracker.count(&file_entry.top_level_dir);
+                                }
+                                #[cfg(feature = "serde")]
+                                if let Some(ref status) = self.status {
+                                    status.count(&file_entry.top_level_dir);
+                                }
+
+                                // Update progress
+                                if entry.depth > 0 {
+                                    progress.event(ProgressEvent::Entry {
+                                        top_level_dir: &file_entry.top_level_dir,
+                                        bytes: if counts_size { size } else { 0 },
+                                    });
+                                }
+
+                                // Refresh the message on the first entry and then by
+                                // time, however many entries that takes
+                                if last_message.is_none_or(|at| at.elapsed() >= PROGRESS_MESSAGE_INTERVAL) {
+                                    last_message = Some(Instant::now());
+                                    let files = files_counter.load(Ordering::Relaxed);
+                                    let dirs = dirs_counter.load(Ordering::Relaxed);
+                                    let gb = size_counter.load(Ordering::Relaxed) as f64 / 1_073_741_824.0;
+                                    let skipped = skipped_counter.load(Ordering::Relaxed);
+                                    let mut msg = if skipped > 0 {
+                                        format!("Scanned: {} files, {} dirs, {:.2} GB (skipped: {})", files, dirs, gb, skipped)
+                                    } else {
+                                        format!("Scanned: {} files, {} dirs, {:.2} GB", files, dirs, gb)
+                                    };
+                                    if let Some(Ok(mut tracker)) = tracker.as_ref().map(|t| t.lock()) {
+                                        msg.push_str(&tracker.message(
+                                            progress.elapsed().as_secs_f64(),
+                                            files + dirs + skipped,
+                                            &file_entry.top_level_dir,
+                                        ));
                                     }
+                                    progress.set_message(msg);
+                                }
+
+                                // Send the entry
+                                if batch_tx.send(file_entry).is_err() {
+                                    debug!("Batch channel closed, stopping scan");
+                                    output_closed.store(true, Ordering::Relaxed);
                                 }
+                                peak_queued.fetch_max(batch_tx.len(), Ordering::Relaxed);
+                            }
+                            Inspected::EntryFailed(e) => {
+                                let kind = e.downcast_ref::<std::io::Error>()
+                                    .map(classify_io_error)
+                                    .unwrap_or("other");
+                                error!("Failed to create entry for {}: {}", path.display(), e);
+                                record_error(kind, &path, e.to_string());
                             }
-                            Err(e) => {
-                                errors_counter.fetch_add(1, Ordering::Relaxed);
+                            Inspected::MetadataFailed(e) => {
                                 debug!("Failed to get metadata for {}: {}", path.display(), e);
+                                record_error(classify_io_error(&e), &path, e.to_string());
+                            }
+                            Inspected::Vanished(e) => {
+                                debug!("{} vanished during the scan: {}", path.display(), e);
+                                vanished_counter.fetch_add(1, Ordering::Relaxed);
                             }
                         }
                     }
                     Err(e) => {
-                        errors_counter.fetch_add(1, Ordering::Relaxed);
                         debug!("Failed to read directory entry: {}", e);
+                        let path = e.path().map(Path::to_path_buf).unwrap_or_default();
+                        if e.io_error().is_some_and(|io| vanished(&path, io)) {
+                            vanished_counter.fetch_add(1, Ordering::Relaxed);
+                            return;
+                        }
+                        let kind = if e.loop_ancestor().is_some() {
+                            "ELOOP"
+                        } else {
+                            e.io_error().map(classify_io_error).unwrap_or("other")
+                        };
+                        record_error(kind, &path, e.to_string());
                     }
                 }
             });
@@ -256,7 +948,88 @@ impl Scanner {
         drop(batch_tx);
         batch_thread.join().map_err(|_| anyhow::anyhow!("Batch thread panicked"))?;
 
-        Ok(())
+        Ok(output_closed.load(Ordering::Relaxed))
+    }
+}
+
+/// Entries of a scan running in the background, from [`Scanner::iter`]
+pub struct ScanIter {
+    root_path: PathBuf,
+    entries: Receiver<Vec<FileEntry>>,
+    errors: Receiver<ScanError>,
+    entries_open: bool,
+    errors_open: bool,
+    batch: std::vec::IntoIter<FileEntry>,
+    cancel: Arc<AtomicBool>,
+    handle: Option<JoinHandle<error::Result<ScanStats>>>,
+    stats: Option<ScanStats>,
+}
+
+impl ScanIter {
+    /// Statistics of the finished scan, once every entry has been yielded
+    pub fn stats(&self) -> Option<&ScanStats> {
+        self.stats.as_ref()
+    }
+
+    /// Wait for the scan thread once it has sent everything, keeping its
+    /// statistics or returning its failure
+    fn finish(&mut self) -> Option<ScanError> {
+        let handle = self.handle.take()?;
+        let result = handle.join().map_err(|_| Error::Other(anyhow::anyhow!("Scanner thread panicked")));
+        match result.and_then(|r| r) {
+            Ok(stats) => {
+                self.stats = Some(stats);
+                None
+            }
+            Err(e) => Some(ScanError {
+                kind: e.io_error().map(classify_io_error).unwrap_or("other").to_string(),
+                path: self.root_path.clone(),
+                message: e.to_string(),
+            }),
+        }
+    }
+}
+
+impl Iterator for ScanIter {
+    type Item = std::result::Result<FileEntry, ScanError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.batch.next() {
+                return Some(Ok(entry));
+            }
+            if !self.entries_open && !self.errors_open {
+                return self.finish().map(Err);
+            }
+            select! {
+                recv(self.entries) -> batch => match batch {
+                    Ok(batch) => self.batch = batch.into_iter(),
+                    Err(_) => {
+                        self.entries_open = false;
+                        self.entries = never();
+                    }
+                },
+                recv(self.errors) -> error => match error {
+                    Ok(error) => return Some(Err(error)),
+                    Err(_) => {
+                        self.errors_open = false;
+                        self.errors = never();
+                    }
+                },
+            }
+        }
+    }
+}
+
+impl Drop for ScanIter {
+    fn drop(&mut self) {
+        // Stop the walk and unblock its senders, then wait for it to wind down
+        self.cancel.store(true, Ordering::Relaxed);
+        self.entries = never();
+        self.errors = never();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
     }
 }
 
@@ -264,8 +1037,8 @@ impl Scanner {
 pub fn scan_directory<P: AsRef<Path>>(
     root_path: P,
     options: ScanOptions,
-) -> Result<Vec<FileEntry>> {
-    let (tx, rx) = bounded(options.batch_size);
+) -> error::Result<Vec<FileEntry>> {
+    let (tx, rx) = bounded(options.batch_channel_capacity());
     let scanner = Scanner::new(options);
 
     let root_path_clone = root_path.as_ref().to_path_buf();
@@ -288,10 +1061,33 @@ pub fn scan_directory<P: AsRef<Path>>(
     Ok(entries)
 }
 
+/// Scan `root_path` into `output`, returning the scan statistics and the rows written.
+///
+/// With `chunking`, rows go to rotating chunk files named after `output` with
+/// a manifest, as with `scan --incremental`; otherwise to the single Parquet
+/// file `output`. See [`run_scan_to_parquet`] for everything the scan produced.
+#[cfg(feature = "parquet-writer")]
+pub fn scan_to_parquet(
+    root_path: &Path,
+    output: &Path,
+    options: ScanOptions,
+    chunking: Option<RotationParams>,
+) -> Result<(ScanStats, u64)> {
+    let sink = match chunking {
+        Some(params) => SinkConfig::chunked(output, params),
+        None => SinkConfig::file(output),
+    };
+    let result = run_scan_to_parquet(options, root_path, sink).context("Scan failed")?;
+    Ok((result.stats, result.rows_written))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crossbeam_channel::unbounded;
     use std::fs;
+    use std::sync::atomic::AtomicU64;
+    use std::time::Duration;
     use tempfile::TempDir;
 
     fn create_test_structure() -> TempDir {
@@ -360,6 +1156,533 @@ mod tests {
         assert!(!deep_file_exists);
     }
 
+    #[test]
+    fn test_shards_partition_the_scan() {
+        let temp_dir = create_test_structure();
+        fs::create_dir_all(temp_dir.path().join("dir3/a/b")).unwrap();
+        fs::write(temp_dir.path().join("dir3/a/b/file5.dat"), "content5").unwrap();
+        fs::create_dir_all(temp_dir.path().join("dir4")).unwrap();
+
+        let scan = |shard: Option<Shard>| {
+            let (tx, rx) = bounded(16);
+            let mut scanner = Scanner::new(ScanOptions { num_threads: 2, batch_size: 4, ..Default::default() });
+            if let Some(shard) = shard {
+                scanner = scanner.with_shard(shard);
+            }
+            let root = temp_dir.path().to_path_buf();
+            let handle = std::thread::spawn(move || scanner.scan(root, tx));
+            let paths: Vec<String> = rx.iter().flatten().map(|e| e.path).collect();
+            handle.join().unwrap().unwrap();
+            paths
+        };
+
+        let mut all = scan(None);
+        all.sort();
+        for depth in [1, 2] {
+            for count in [2, 3] {
+                let mut seen: Vec<String> = (0..count)
+                    .flat_map(|index| scan(Some(Shard::new(index, count, depth).unwrap())))
+                    .collect();
+                seen.sort();
+                assert_eq!(seen, all, "{} shards at depth {}", count, depth);
+            }
+        }
+    }
+
+    #[test]
+    fn test_exclude_globs_and_min_size() {
+        let temp_dir = create_test_structure();
+        fs::write(temp_dir.path().join("dir2/big.log"), "a much larger file").unwrap();
+
+        let options = ScanOptions::builder()
+            .threads(2)
+            .batch_size(4)
+            .exclude_glob("subdir1")
+            .exclude_glob("dir2/*.log")
+            .build()
+            .unwrap();
+        let root = temp_dir.path().canonicalize().unwrap();
+        let mut paths: Vec<String> = scan_directory(&root, options).unwrap().into_iter()
+            .filter_map(|e| Path::new(&e.path).strip_prefix(&root).ok().map(|p| p.to_string_lossy().to_string()))
+            .filter(|p| !p.is_empty())
+            .collect();
+        paths.sort();
+        assert_eq!(paths, ["dir1", "dir1/file2.txt", "dir2", "file1.txt"]);
+
+        // Small files are left out, directories kept
+        let options = ScanOptions::builder().threads(2).min_size(10).build().unwrap();
+        let entries = scan_directory(&root, options).unwrap();
+        let files: Vec<_> = entries.iter().filter(|e| e.file_type != "directory").map(|e| e.path.as_str()).collect();
+        assert_eq!(files, [root.join("dir2/big.log").to_string_lossy()]);
+        assert!(entries.iter().any(|e| e.path.ends_with("subdir1")));
+    }
+
+    /// A sparse 1 GiB file, a link to it, and a dangling link
+    fn create_linked_tree() -> TempDir {
+        let temp_dir = TempDir::new().unwrap();
+        let base = temp_dir.path();
+        fs::create_dir_all(base.join("data")).unwrap();
+        fs::create_dir_all(base.join("links")).unwrap();
+        fs::File::create(base.join("data/big.dat")).unwrap().set_len(1 << 30).unwrap();
+        std::os::unix::fs::symlink(base.join("data/big.dat"), base.join("links/big")).unwrap();
+        std::os::unix::fs::symlink("missing.dat", base.join("links/dangling")).unwrap();
+        temp_dir
+    }
+
+    fn scan_with_stats(root: &Path, options: ScanOptions) -> (ScanStats, Vec<FileEntry>) {
+        let (tx, rx) = unbounded();
+        let stats = Scanner::new(options).scan(root, tx).unwrap();
+        (stats, rx.iter().flatten().collect())
+    }
+
+    #[test]
+    fn test_symlinks_are_not_followed_or_sized() {
+        let temp_dir = create_linked_tree();
+        let root = temp_dir.path().canonicalize().unwrap();
+        let (stats, entries) = scan_with_stats(&root, ScanOptions::builder().threads(2).build().unwrap());
+
+        assert_eq!(stats.files_scanned, 1);
+        assert_eq!(stats.directories_scanned, 3);
+        assert_eq!(stats.symlinks_scanned, 2);
+        assert_eq!(stats.total_size, 1 << 30);
+        assert_eq!(entries.len() as u64, stats.files_scanned + stats.directories_scanned + stats.symlinks_scanned);
+
+        let find = |name: &str| entries.iter().find(|e| e.path.ends_with(name)).unwrap();
+        let (target, link) = (find("data/big.dat"), find("links/big"));
+        assert_eq!(link.file_type, "symlink");
+        assert_ne!(link.inode, target.inode);
+        let link_target = link.link_target.as_deref().unwrap();
+        assert!(link_target.ends_with("data/big.dat"), "{}", link_target);
+        assert_eq!(link.size, link_target.len() as u64);
+        let dangling = find("links/dangling");
+        assert_eq!(dangling.file_type, "symlink");
+        assert_eq!(dangling.link_target.as_deref(), Some("missing.dat"));
+    }
+
+    #[test]
+    fn test_followed_symlinks_count_their_targets() {
+        let temp_dir = create_linked_tree();
+        let root = temp_dir.path().canonicalize().unwrap();
+        let options = ScanOptions::builder().threads(2).follow_symlinks(true).build().unwrap();
+        let (stats, entries) = scan_with_stats(&root, options);
+
+        assert_eq!(stats.files_scanned, 2);
+        assert_eq!(stats.total_size, 2 << 30);
+        let link = entries.iter().find(|e| e.path.ends_with("links/big")).unwrap();
+        assert_eq!(link.file_type, "no_extension");
+        assert_eq!(link.size, 1 << 30);
+    }
+
+    #[test]
+    fn test_hard_links_count_once_in_the_unique_size() {
+        let temp_dir = TempDir::new().unwrap();
+        let base = temp_dir.path();
+        fs::create_dir_all(base.join("a")).unwrap();
+        fs::create_dir_all(base.join("b")).unwrap();
+        fs::write(base.join("a/shared.dat"), vec![0u8; 1000]).unwrap();
+        fs::hard_link(base.join("a/shared.dat"), base.join("a/again.dat")).unwrap();
+        fs::hard_link(base.join("a/shared.dat"), base.join("b/shared.dat")).unwrap();
+        fs::write(base.join("b/single.dat"), vec![0u8; 10]).unwrap();
+
+        let (stats, entries) = scan_with_stats(base, ScanOptions::builder().threads(2).build().unwrap());
+
+        assert_eq!(stats.files_scanned, 4);
+        assert_eq!(stats.total_size, 3 * 1000 + 10);
+        assert_eq!(stats.unique_total_size, 1000 + 10);
+        let linked: Vec<_> = entries.iter().filter(|e| e.size == 1000).collect();
+        assert_eq!(linked.len(), 3);
+        assert!(linked.iter().all(|e| e.nlink == 3 && e.inode == linked[0].inode));
+    }
+
+    fn create_wide_tree(files: usize) -> TempDir {
+        let temp_dir = TempDir::new().unwrap();
+        for i in 0..files {
+            let dir = temp_dir.path().join(format!("dir{}", i % 10));
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join(format!("file{}.txt", i)), "x").unwrap();
+        }
+        temp_dir
+    }
+
+    #[test]
+    fn test_iter_matches_scan_directory() {
+        let temp_dir = create_wide_tree(1000);
+        let options = ScanOptions::builder().threads(2).batch_size(64).build().unwrap();
+
+        let mut iter = Scanner::new(options.clone()).iter(temp_dir.path());
+        let mut seen: Vec<String> = iter.by_ref().map(|e| e.unwrap().path).collect();
+        let stats = iter.stats().unwrap();
+        assert_eq!(stats.files_scanned, 1000);
+        assert_eq!(stats.files_scanned + stats.directories_scanned, seen.len() as u64);
+
+        let mut expected: Vec<String> = scan_directory(temp_dir.path(), options).unwrap()
+            .into_iter().map(|e| e.path).collect();
+        seen.sort();
+        expected.sort();
+        assert_eq!(seen, expected);
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn test_iter_dropped_early_stops_the_scan() {
+        let temp_dir = create_wide_tree(1000);
+        let options = ScanOptions::builder().threads(2).batch_size(4).build().unwrap();
+
+        let started = std::time::Instant::now();
+        let mut iter = Scanner::new(options).iter(temp_dir.path());
+        let first: Vec<_> = iter.by_ref().take(10).collect();
+        assert_eq!(first.len(), 10);
+        assert!(first.iter().all(|e| e.is_ok()));
+        assert!(iter.stats().is_none());
+        drop(iter);
+        assert!(started.elapsed() < Duration::from_secs(10), "took {:?}", started.elapsed());
+    }
+
+    #[test]
+    fn test_closed_receiver_stops_the_scan() {
+        let temp_dir = create_wide_tree(1000);
+        let scanner = Scanner::new(ScanOptions::builder().threads(2).batch_size(4).channel_capacity_entries(8).build().unwrap());
+
+        // Wait on the result with a timeout, so a hung scan fails the test
+        let (tx, rx) = bounded(1);
+        let (result_tx, result_rx) = bounded(1);
+        let path = temp_dir.path().to_path_buf();
+        std::thread::spawn(move || {
+            let _ = result_tx.send(scanner.scan(path, tx));
+        });
+        assert_eq!(rx.recv().unwrap().len(), 4);
+        drop(rx);
+
+        match result_rx.recv_timeout(Duration::from_secs(10)).expect("scan did not return") {
+            Err(Error::Cancelled { stats, .. }) => {
+                assert!(stats.files_scanned < 1000, "{} files scanned", stats.files_scanned);
+            }
+            other => panic!("expected Cancelled, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_iter_yields_the_failure_of_a_scan() {
+        let temp_dir = TempDir::new().unwrap();
+        let missing = temp_dir.path().join("missing");
+        let results: Vec<_> = Scanner::new(ScanOptions::default()).iter(&missing).collect();
+        assert_eq!(results.len(), 1);
+        let error = results[0].as_ref().unwrap_err();
+        assert_eq!(error.path, missing);
+        assert_eq!(error.kind, "ENOENT");
+        assert!(error.message.starts_with("Cannot scan"), "{}", error);
+    }
+
+    #[test]
+    fn test_stats_snapshot_during_and_after_a_scan() {
+        let temp_dir = create_wide_tree(1000);
+        let scanner = Scanner::new(ScanOptions::builder().threads(2).batch_size(16).channel_capacity_entries(32).build().unwrap());
+        assert_eq!(scanner.stats_snapshot().files_scanned, 0);
+
+        // The walk blocks on the full channel until batches are taken
+        let (tx, rx) = bounded(1);
+        let running = scanner.clone();
+        let root = temp_dir.path().to_path_buf();
+        let handle = std::thread::spawn(move || running.scan(root, tx));
+
+        let first = rx.recv().unwrap();
+        let during = scanner.stats_snapshot();
+        assert!(during.files_scanned + during.directories_scanned >= first.len() as u64);
+        assert!(during.files_scanned < 1000, "{:?}", during);
+        assert!(during.duration_secs > 0.0);
+
+        let received = first.len() + rx.iter().map(|batch| batch.len()).sum::<usize>();
+        let stats = handle.join().unwrap().unwrap();
+        assert_eq!(received as u64, stats.files_scanned + stats.directories_scanned);
+        assert_eq!(stats.files_scanned, 1000);
+
+        let after = scanner.stats_snapshot();
+        assert_eq!(after.files_scanned, stats.files_scanned);
+        assert_eq!(after.directories_scanned, stats.directories_scanned);
+        assert_eq!(after.total_size, stats.total_size);
+        assert_eq!(after.errors_encountered, stats.errors_encountered);
+        assert_eq!(after.duration_secs, stats.duration_secs);
+        assert_eq!(after.end_time, stats.end_time);
+    }
+
+    #[cfg(feature = "progress")]
+    #[test]
+    fn test_top_level_dirs_are_those_walked() {
+        let temp_dir = create_test_structure();
+        fs::create_dir(temp_dir.path().join("cache")).unwrap();
+        let options = ScanOptions::builder().exclude_glob("cache").build().unwrap();
+        let scanner = Scanner::new(options);
+        let excludes = ExcludeGlobs::new(&scanner.options.exclude_globs).unwrap();
+
+        let dirs = scanner.top_level_dirs(temp_dir.path(), None, &excludes);
+        assert_eq!(dirs, vec!["dir1", "dir2"]);
+
+        let skip = HashSet::from(["dir1".to_string()]);
+        assert_eq!(scanner.top_level_dirs(temp_dir.path(), Some(&skip), &excludes), vec!["dir2"]);
+
+        for i in 0..MAX_DIR_LINES {
+            fs::create_dir(temp_dir.path().join(format!("more{}", i))).unwrap();
+        }
+        assert!(scanner.top_level_dirs(temp_dir.path(), None, &excludes).is_empty());
+    }
+
+    #[test]
+    fn test_scans_share_a_supplied_pool() {
+        let temp_dir = create_wide_tree(100);
+        let started = Arc::new(AtomicU64::new(0));
+        let counted = started.clone();
+        let pool = Arc::new(rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .start_handler(move |_| {
+                counted.fetch_add(1, Ordering::Relaxed);
+            })
+            .build()
+            .unwrap());
+
+        let scanner = Scanner::new(ScanOptions::builder().threads(8).batch_size(16).build().unwrap())
+            .with_thread_pool(pool.clone());
+        let scan = || {
+            let (tx, rx) = bounded(16);
+            let stats = std::thread::scope(|s| {
+                let handle = s.spawn(|| scanner.scan(temp_dir.path(), tx));
+                assert_eq!(rx.iter().flatten().count(), 111);
+                handle.join().unwrap().unwrap()
+            });
+            assert_eq!(stats.files_scanned, 100);
+            assert_eq!(stats.directories_scanned, 11);
+        };
+        scan();
+        scan();
+        assert_eq!(scanner.iter(temp_dir.path()).count(), 111);
+
+        assert!(Arc::ptr_eq(&scanner.thread_pool().unwrap(), &pool));
+        assert_eq!(pool.current_num_threads(), 2);
+        assert_eq!(started.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn test_scanner_builds_its_pool_once() {
+        let temp_dir = create_test_structure();
+        let scanner = Scanner::new(ScanOptions::builder().threads(3).build().unwrap());
+        let first = scanner.iter(temp_dir.path()).count();
+        let pool = scanner.thread_pool().unwrap();
+        assert_eq!(pool.current_num_threads(), 3);
+
+        assert_eq!(scanner.iter(temp_dir.path()).count(), first);
+        assert!(Arc::ptr_eq(&scanner.thread_pool().unwrap(), &pool));
+    }
+
+    #[test]
+    fn test_scan_errors() {
+        let temp_dir = TempDir::new().unwrap();
+        let (tx, _rx) = bounded(16);
+        let missing = temp_dir.path().join("missing");
+        match Scanner::new(ScanOptions::default()).scan(&missing, tx.clone()) {
+            Err(Error::InvalidRoot { path, source }) => {
+                assert_eq!(path, missing);
+                assert_eq!(source.kind(), std::io::ErrorKind::NotFound);
+            }
+            other => panic!("expected InvalidRoot, got {:?}", other),
+        }
+
+        let options = ScanOptions { exclude_globs: vec!["[a-".to_string()], ..Default::default() };
+        let err = Scanner::new(options).scan(temp_dir.path(), tx.clone()).unwrap_err();
+        assert!(matches!(err, Error::InvalidOptions { field: "exclude_glob", .. }), "{:?}", err);
+
+        let scanner = Scanner::new(ScanOptions::default());
+        scanner.cancel.store(true, Ordering::Relaxed);
+        match scanner.scan(temp_dir.path(), tx) {
+            Err(Error::Cancelled { root, stats }) => {
+                assert_eq!(root, temp_dir.path().canonicalize().unwrap());
+                assert_eq!(stats.files_scanned, 0);
+            }
+            other => panic!("expected Cancelled, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_entries_removed_after_listing_vanish() {
+        let temp_dir = TempDir::new().unwrap();
+        let base = temp_dir.path();
+        std::os::unix::fs::symlink("missing.dat", base.join("dangling")).unwrap();
+
+        let gone = base.join("gone.dat");
+        assert!(matches!(Inspected::read(&gone, false, false, base), Inspected::Vanished(_)));
+
+        // stat() failing on a link to nothing is not the link vanishing
+        let dangling = base.join("dangling");
+        let e = std::io::Error::from_raw_os_error(libc::ENOENT);
+        assert!(!vanished(&dangling, &e));
+        assert!(vanished(&gone, &e));
+        assert!(!vanished(Path::new(""), &e));
+    }
+
+    #[test]
+    fn test_files_deleted_during_the_scan_are_not_errors() {
+        let temp_dir = create_wide_tree(200);
+        let root = temp_dir.path().canonicalize().unwrap();
+
+        // Remove every other file of each directory between listing it and
+        // reading its entries, as a deletion racing the scan would
+        let mut scanner = Scanner::new(ScanOptions::builder().threads(4).batch_size(16).build().unwrap());
+        scanner.after_listing = Some(Arc::new(|dir: &Path| {
+            for entry in fs::read_dir(dir).unwrap().flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                let number: Option<usize> = name.strip_prefix("file").and_then(|n| n.strip_suffix(".txt")?.parse().ok());
+                if number.is_some_and(|n| n % 20 >= 10) {
+                    fs::remove_file(entry.path()).unwrap();
+                }
+            }
+        }));
+        let (tx, rx) = unbounded();
+        let stats = scanner.scan(&root, tx).unwrap();
+        let entries: Vec<FileEntry> = rx.iter().flatten().collect();
+
+        assert_eq!(stats.errors_encountered, 0, "{:?}", stats.error_summary);
+        assert_eq!(stats.entries_vanished, 100);
+        assert_eq!(stats.files_scanned, 100);
+        assert_eq!(entries.len() as u64, stats.files_scanned + stats.directories_scanned);
+    }
+
+    /// Counts what it is given, failing at batch `fail_at` if set
+    struct CountingSink {
+        batches: usize,
+        entries: usize,
+        fail_at: Option<usize>,
+    }
+
+    impl EntrySink for CountingSink {
+        fn write_batch(&mut self, entries: &[FileEntry]) -> anyhow::Result<()> {
+            if self.fail_at == Some(self.batches) {
+                anyhow::bail!("broker unavailable");
+            }
+            self.batches += 1;
+            self.entries += entries.len();
+            Ok(())
+        }
+
+        fn finalize(self: Box<Self>) -> anyhow::Result<u64> {
+            Ok(self.entries as u64)
+        }
+    }
+
+    #[test]
+    fn test_scan_into_a_sink() {
+        let temp_dir = create_test_structure();
+        let scanner = Scanner::new(ScanOptions { batch_size: 2, ..Default::default() });
+        let mut sink = Box::new(CountingSink { batches: 0, entries: 0, fail_at: None });
+
+        let stats = scanner.scan_into(temp_dir.path(), sink.as_mut()).unwrap();
+        assert_eq!(sink.entries as u64, stats.files_scanned + stats.directories_scanned);
+        assert_eq!(sink.batches, sink.entries.div_ceil(2));
+        assert_eq!(sink.finalize().unwrap(), stats.files_scanned + stats.directories_scanned);
+    }
+
+    #[test]
+    fn test_failing_sink_stops_the_scan() {
+        let temp_dir = TempDir::new().unwrap();
+        for i in 0..50 {
+            let dir = temp_dir.path().join(format!("dir{}", i));
+            fs::create_dir(&dir).unwrap();
+            for j in 0..40 {
+                fs::write(dir.join(format!("file{}", j)), "x").unwrap();
+            }
+        }
+        let scanner = Scanner::new(ScanOptions { batch_size: 10, ..Default::default() });
+        let mut sink = CountingSink { batches: 0, entries: 0, fail_at: Some(1) };
+
+        let err = scanner.scan_into(temp_dir.path(), &mut sink).unwrap_err();
+        match err {
+            Error::Sink { ref source } => assert_eq!(source.to_string(), "broker unavailable"),
+            ref other => panic!("expected Sink, got {:?}", other),
+        }
+        assert!(err.to_string().contains("broker unavailable"));
+        assert_eq!(sink.entries, 10);
+
+        // The walk stopped well short of the tree
+        let stats = scanner.stats_snapshot();
+        assert!(stats.files_scanned < 2000, "{} files scanned", stats.files_scanned);
+    }
+
+    #[test]
+    fn test_queued_entries_stay_within_the_channel_capacity() {
+        let temp_dir = TempDir::new().unwrap();
+        for i in 0..40 {
+            let dir = temp_dir.path().join(format!("dir{}", i));
+            fs::create_dir(&dir).unwrap();
+            for j in 0..100 {
+                fs::write(dir.join(format!("file{}", j)), "x").unwrap();
+            }
+        }
+        let options = ScanOptions::builder().threads(4).batch_size(16).channel_capacity_entries(64).build().unwrap();
+        let max_queued = options.max_queued_entries() as u64;
+        let scanner = Scanner::new(options.clone());
+        let (tx, rx) = bounded::<Vec<FileEntry>>(options.batch_channel_capacity());
+
+        // A slow sink: everything the walk counted and the sink hasn't taken
+        // yet is queued somewhere in between
+        let (stats, received, most_in_flight) = std::thread::scope(|scope| {
+            let sink = scope.spawn(|| {
+                let (mut received, mut most_in_flight) = (0u64, 0u64);
+                for batch in rx {
+                    let counts = scanner.live.counters.load();
+                    most_in_flight = most_in_flight.max(counts.files + counts.directories - received);
+                    received += batch.len() as u64;
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+                (received, most_in_flight)
+            });
+            let stats = scanner.scan(temp_dir.path(), tx).unwrap();
+            let (received, most_in_flight) = sink.join().unwrap();
+            (stats, received, most_in_flight)
+        });
+
+        assert_eq!(received, stats.files_scanned + stats.directories_scanned);
+        let peak = scanner.live.peak_queued.load(Ordering::Relaxed);
+        assert!(peak > 0 && peak <= 64, "entry channel peaked at {}", peak);
+        // One more entry may be counted while its send waits for room
+        assert!(most_in_flight <= max_queued + 1, "{} entries in flight", most_in_flight);
+    }
+
+    /// Progress that keeps every message it is given
+    struct RecordingProgress {
+        started: Instant,
+        messages: std::cell::RefCell<Vec<String>>,
+    }
+
+    impl Progress for RecordingProgress {
+        fn set_message(&self, message: String) {
+            self.messages.borrow_mut().push(message);
+        }
+
+        fn elapsed(&self) -> Duration {
+            self.started.elapsed()
+        }
+
+        fn finish(&mut self, _message: &'static str) {}
+    }
+
+    #[test]
+    fn test_progress_message_is_set_without_a_round_count() {
+        // 9,999 entries with the root: never a multiple of 10,000
+        let temp_dir = TempDir::new().unwrap();
+        for i in 0..9_998 {
+            fs::write(temp_dir.path().join(format!("file{}", i)), "").unwrap();
+        }
+        let scanner = Scanner::new(ScanOptions::builder().threads(2).batch_size(1000).build().unwrap());
+        let mut progress = RecordingProgress { started: Instant::now(), messages: Default::default() };
+
+        let (tx, rx) = unbounded();
+        let root = temp_dir.path().canonicalize().unwrap();
+        scanner.live.start();
+        scanner.scan_parallel(&root, tx, &mut progress, None, ExcludeGlobs::new(&[]).unwrap(), OutputFiles::new(&[], &root)).unwrap();
+        assert_eq!(rx.iter().map(|batch| batch.len()).sum::<usize>(), 9_999);
+
+        let messages = progress.messages.into_inner();
+        assert!(!messages.is_empty());
+        assert!(messages[0].starts_with("Scanned: "), "{}", messages[0]);
+    }
+
     #[test]
     fn test_scan_empty_directory() {
         let temp_dir = TempDir::new().unwrap();
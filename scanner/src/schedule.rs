@@ -0,0 +1,705 @@
+use crate::lock::ScanLock;
+use crate::manifest::RotationParams;
+use crate::models::{ScanOptions, ScanStats};
+use crate::scanner::scan_to_parquet;
+use crate::utils;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{error, info, warn};
+
+/// How often the scheduler checks for due jobs and a stop request
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// A `schedule.toml` file: one `[[job]]` table per job
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleConfig {
+    #[serde(rename = "job", default)]
+    pub jobs: Vec<JobConfig>,
+}
+
+/// One recurring scan
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JobConfig {
+    /// Unique name, used by `--run-once` and for the job's lock file
+    pub name: String,
+
+    /// Directory to scan
+    pub path: PathBuf,
+
+    /// Output path. `{date}` (2024-05-20), `{time}` (020000), and
+    /// `{datetime}` (2024-05-20T020000) in the file name are replaced with
+    /// the run's UTC start time
+    pub output: String,
+
+    /// Run every this long (e.g., "6h"); the first run is one interval after startup
+    pub interval: Option<String>,
+
+    /// Run at the times matching this cron expression ("minute hour day month weekday", UTC)
+    pub cron: Option<String>,
+
+    /// Keep only the newest this many outputs of the job
+    pub retain: Option<usize>,
+
+    /// Number of threads (default: number of CPU cores)
+    pub threads: Option<usize>,
+
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+
+    #[serde(default)]
+    pub follow_symlinks: bool,
+
+    pub max_depth: Option<usize>,
+
+    /// Write rotating chunks with a manifest, as with `scan --incremental`
+    #[serde(default)]
+    pub incremental: bool,
+
+    #[serde(default = "default_rows_per_chunk")]
+    pub rows_per_chunk: usize,
+
+    #[serde(default = "default_chunk_interval_secs")]
+    pub chunk_interval_secs: u64,
+}
+
+fn default_batch_size() -> usize {
+    100_000
+}
+
+fn default_rows_per_chunk() -> usize {
+    500_000
+}
+
+fn default_chunk_interval_secs() -> u64 {
+    300
+}
+
+impl ScheduleConfig {
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let contents = fs::read_to_string(path.as_ref())
+            .with_context(|| format!("Failed to read {}", path.as_ref().display()))?;
+        let config: Self = toml::from_str(&contents)
+            .with_context(|| format!("Failed to parse {}", path.as_ref().display()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Check every job, so a typo fails at startup rather than at the job's first run
+    pub fn validate(&self) -> Result<()> {
+        if self.jobs.is_empty() {
+            anyhow::bail!("No [[job]] entries in the schedule");
+        }
+        for (i, job) in self.jobs.iter().enumerate() {
+            if self.jobs[..i].iter().any(|other| other.name == job.name) {
+                anyhow::bail!("Duplicate job name '{}'", job.name);
+            }
+            job.schedule().with_context(|| format!("Invalid schedule for job '{}'", job.name))?;
+            let output = Path::new(&job.output);
+            if output.parent().is_some_and(|dir| dir.to_string_lossy().contains('{')) {
+                anyhow::bail!("Job '{}': placeholders are only allowed in the output file name", job.name);
+            }
+            if output.file_name().is_none() {
+                anyhow::bail!("Job '{}': output '{}' has no file name", job.name, job.output);
+            }
+            if job.retain == Some(0) {
+                anyhow::bail!("Job '{}': retain must be at least 1", job.name);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn job(&self, name: &str) -> Result<&JobConfig> {
+        self.jobs.iter().find(|job| job.name == name).ok_or_else(|| {
+            let names: Vec<&str> = self.jobs.iter().map(|job| job.name.as_str()).collect();
+            anyhow::anyhow!("No job named '{}' (jobs: {})", name, names.join(", "))
+        })
+    }
+}
+
+/// When a job runs
+#[derive(Debug, Clone, PartialEq)]
+pub enum JobSchedule {
+    /// Every this many seconds
+    Interval(u64),
+
+    Cron(CronSchedule),
+}
+
+impl JobSchedule {
+    /// First run time after `now` for a scheduler started at `now`
+    fn first(&self, now: i64) -> i64 {
+        match self {
+            JobSchedule::Interval(secs) => now + *secs as i64,
+            JobSchedule::Cron(cron) => cron.next_after(now),
+        }
+    }
+
+    /// Next run time after the one due at `due`, skipping any already missed by `now`
+    fn next(&self, due: i64, now: i64) -> i64 {
+        match self {
+            JobSchedule::Interval(secs) => {
+                let secs = *secs as i64;
+                let missed = (now - due).max(0) / secs;
+                due + (missed + 1) * secs
+            }
+            JobSchedule::Cron(cron) => cron.next_after(now.max(due)),
+        }
+    }
+}
+
+/// UTC calendar fields of a Unix timestamp: (month, day, hour, minute, weekday with Sunday = 0)
+fn calendar(ts: i64) -> (u32, u32, u32, u32, u32) {
+    let days = ts.div_euclid(86_400);
+    let secs = ts.rem_euclid(86_400);
+
+    // Days to civil date, after Howard Hinnant's algorithm
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+
+    // 1970-01-01 was a Thursday
+    let weekday = (days + 4).rem_euclid(7);
+
+    (month as u32, day as u32, (secs / 3600) as u32, (secs % 3600 / 60) as u32, weekday as u32)
+}
+
+/// A five-field cron expression. Fields accept `*`, numbers, ranges
+/// (`1-5`), lists (`1,15`), and steps (`*/15`, `0-30/10`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct CronSchedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether the day and weekday fields were restricted; when both are, a
+    /// time matching either runs, as in cron
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl CronSchedule {
+    pub fn parse(expr: &str) -> Result<Self> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        if fields.len() != 5 {
+            anyhow::bail!("Cron expression '{}' must have 5 fields (minute hour day month weekday)", expr);
+        }
+
+        let mut weekdays = parse_field(fields[4], 0, 7)?;
+        // Both 0 and 7 are Sunday
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+
+        Ok(Self {
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            weekdays,
+            days_restricted: fields[2] != "*",
+            weekdays_restricted: fields[4] != "*",
+        })
+    }
+
+    fn matches(&self, ts: i64) -> bool {
+        let (month, day, hour, minute, weekday) = calendar(ts);
+        let day_ok = self.days & (1 << day) != 0;
+        let weekday_ok = self.weekdays & (1 << weekday) != 0;
+        let date_ok = if self.days_restricted && self.weekdays_restricted {
+            day_ok || weekday_ok
+        } else {
+            day_ok && weekday_ok
+        };
+
+        self.minutes & (1 << minute) != 0
+            && self.hours & (1 << hour) != 0
+            && self.months & (1 << month) != 0
+            && date_ok
+    }
+
+    /// Start of the first matching minute after `ts`
+    pub fn next_after(&self, ts: i64) -> i64 {
+        let mut minute = ts.div_euclid(60) * 60 + 60;
+        // Every valid expression matches within a leap-year cycle
+        for _ in 0..(4 * 366 * 24 * 60) {
+            if self.matches(minute) {
+                return minute;
+            }
+            minute += 60;
+        }
+        i64::MAX
+    }
+}
+
+/// Bitmask of the values a cron field selects
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64> {
+    let mut mask = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step: u32 = step.parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid step in cron field '{}'", field))?;
+                if step == 0 {
+                    anyhow::bail!("Step of 0 in cron field '{}'", field);
+                }
+                (range, step)
+            }
+            None => (part, 1),
+        };
+
+        let value = |s: &str| -> Result<u32> {
+            let v: u32 = s.parse()
+                .map_err(|_| anyhow::anyhow!("Invalid value '{}' in cron field '{}'", s, field))?;
+            if v < min || v > max {
+                anyhow::bail!("Value {} in cron field '{}' is outside {}-{}", v, field, min, max);
+            }
+            Ok(v)
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((a, b)) => (value(a)?, value(b)?),
+                None if step > 1 => (value(range)?, max),
+                None => {
+                    let v = value(range)?;
+                    (v, v)
+                }
+            },
+        };
+        if start > end {
+            anyhow::bail!("Empty range '{}' in cron field '{}'", range, field);
+        }
+
+        for v in (start..=end).step_by(step as usize) {
+            mask |= 1 << v;
+        }
+    }
+    Ok(mask)
+}
+
+impl JobConfig {
+    pub fn schedule(&self) -> Result<JobSchedule> {
+        match (&self.interval, &self.cron) {
+            (Some(interval), None) => {
+                let secs = utils::parse_duration(interval)?.as_secs();
+                if secs == 0 {
+                    anyhow::bail!("Interval must be at least 1s");
+                }
+                Ok(JobSchedule::Interval(secs))
+            }
+            (None, Some(cron)) => Ok(JobSchedule::Cron(CronSchedule::parse(cron)?)),
+            _ => anyhow::bail!("Set exactly one of interval or cron"),
+        }
+    }
+
+    fn scan_options(&self) -> ScanOptions {
+        ScanOptions {
+            num_threads: self.threads.unwrap_or_else(num_cpus::get),
+            batch_size: self.batch_size,
+            follow_symlinks: self.follow_symlinks,
+            max_depth: self.max_depth,
+            ..ScanOptions::default()
+        }
+    }
+
+    fn chunking(&self) -> Option<RotationParams> {
+        self.incremental.then_some(RotationParams {
+            rows_per_chunk: self.rows_per_chunk,
+            chunk_interval_secs: self.chunk_interval_secs,
+        })
+    }
+
+    /// Output path of a run starting at `ts`
+    pub fn output_for(&self, ts: i64) -> PathBuf {
+        let stamp = humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(ts.max(0) as u64)).to_string();
+        let date = &stamp[..10];
+        let time = stamp[11..19].replace(':', "");
+        PathBuf::from(self.output
+            .replace("{datetime}", &format!("{}T{}", date, time))
+            .replace("{date}", date)
+            .replace("{time}", &time))
+    }
+
+    fn output_dir(&self) -> PathBuf {
+        match Path::new(&self.output).parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        }
+    }
+
+    /// File name pattern matching every run's output (the manifest, for
+    /// incremental jobs)
+    fn output_pattern(&self) -> String {
+        let name = Path::new(&self.output).file_name().unwrap().to_string_lossy().to_string();
+        let name = if self.incremental {
+            let stem = Path::new(&name).file_stem().unwrap().to_string_lossy().to_string();
+            format!("{}_manifest.json", stem)
+        } else {
+            name
+        };
+
+        let mut pattern = glob::Pattern::escape(&name);
+        for placeholder in ["{datetime}", "{date}", "{time}"] {
+            pattern = pattern.replace(&glob::Pattern::escape(placeholder), "*");
+        }
+        format!("{}/{}", glob::Pattern::escape(&self.output_dir().to_string_lossy()), pattern)
+    }
+
+    /// Lock file target, held while a run is active
+    fn lock_target(&self) -> PathBuf {
+        self.output_dir().join(format!(".{}", self.name))
+    }
+}
+
+/// Outcome of one run of a job
+#[derive(Debug, Clone)]
+pub struct JobSummary {
+    pub job: String,
+
+    pub output: PathBuf,
+
+    pub stats: ScanStats,
+
+    pub rows_written: u64,
+
+    /// Outputs of earlier runs removed by retention
+    pub pruned: Vec<PathBuf>,
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+/// Run `job` once now: scan, then apply its retention.
+///
+/// Fails without scanning if another run of the job holds its lock.
+pub fn run_job(job: &JobConfig) -> Result<JobSummary> {
+    utils::validate_path(&job.path)
+        .with_context(|| format!("Job '{}': invalid path", job.name))?;
+
+    let dir = job.output_dir();
+    fs::create_dir_all(&dir)
+        .with_context(|| format!("Failed to create {}", dir.display()))?;
+    let _lock = ScanLock::acquire(&job.lock_target(), false)?;
+
+    let output = job.output_for(now());
+    info!("Job '{}': scanning {} into {}", job.name, job.path.display(), output.display());
+    let (stats, rows_written) = scan_to_parquet(&job.path, &output, job.scan_options(), job.chunking())
+        .with_context(|| format!("Job '{}' failed", job.name))?;
+
+    let pruned = match job.retain {
+        Some(retain) => prune(job, retain)?,
+        None => Vec::new(),
+    };
+
+    Ok(JobSummary { job: job.name.clone(), output, stats, rows_written, pruned })
+}
+
+/// Remove all but the newest `retain` outputs of `job`
+fn prune(job: &JobConfig, retain: usize) -> Result<Vec<PathBuf>> {
+    let mut outputs: Vec<(SystemTime, PathBuf)> = glob::glob(&job.output_pattern())?
+        .filter_map(|path| path.ok())
+        .filter_map(|path| fs::metadata(&path).and_then(|m| m.modified()).ok().map(|t| (t, path)))
+        .collect();
+    outputs.sort();
+
+    let excess = outputs.len().saturating_sub(retain);
+    let mut pruned = Vec::with_capacity(excess);
+    for (_, path) in outputs.into_iter().take(excess) {
+        if job.incremental {
+            remove_chunked_output(&path)?;
+        } else {
+            fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove {}", path.display()))?;
+        }
+        info!("Job '{}': removed old output {}", job.name, path.display());
+        pruned.push(path);
+    }
+    Ok(pruned)
+}
+
+/// Remove an incremental output given its manifest: the chunks, then the manifest and its sidecar
+fn remove_chunked_output(manifest: &Path) -> Result<()> {
+    let name = manifest.file_name().unwrap().to_string_lossy();
+    let stem = name.strip_suffix("_manifest.json").unwrap_or(&name);
+    let dir = manifest.parent().unwrap_or_else(|| Path::new("."));
+    let escaped = glob::Pattern::escape(&dir.join(stem).to_string_lossy());
+
+    for pattern in [format!("{}_chunk_*", escaped), format!("{}_manifest.*", escaped)] {
+        for path in glob::glob(&pattern)?.filter_map(|p| p.ok()) {
+            fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove {}", path.display()))?;
+        }
+    }
+    Ok(())
+}
+
+fn log_summary(summary: &JobSummary) {
+    info!(
+        "Job '{}' finished in {}: {} files, {} directories, {}, {} rows -> {}",
+        summary.job,
+        utils::format_duration(summary.stats.duration_secs),
+        utils::format_number(summary.stats.files_scanned),
+        utils::format_number(summary.stats.directories_scanned),
+        utils::format_bytes(summary.stats.total_size),
+        utils::format_number(summary.rows_written),
+        summary.output.display(),
+    );
+    if summary.stats.errors_encountered > 0 {
+        warn!("Job '{}': {} errors during the scan", summary.job, summary.stats.errors_encountered);
+    }
+    if !summary.pruned.is_empty() {
+        info!("Job '{}': retention removed {} old output(s)", summary.job, summary.pruned.len());
+    }
+}
+
+/// Runs the jobs of a schedule at their times
+pub struct Scheduler {
+    jobs: Vec<(JobConfig, JobSchedule)>,
+}
+
+impl Scheduler {
+    pub fn new(config: ScheduleConfig) -> Result<Self> {
+        config.validate()?;
+        let jobs = config.jobs.into_iter()
+            .map(|job| {
+                let schedule = job.schedule()?;
+                Ok((job, schedule))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { jobs })
+    }
+
+    /// Run jobs as they fall due until `stop` is set, then wait for running
+    /// jobs to finish. Each run gets its own thread, so a long scan doesn't
+    /// delay other jobs; a run due while the previous run of the same job is
+    /// still going is skipped. Returns the summaries of completed runs.
+    pub fn run(&self, stop: &AtomicBool) -> Result<Vec<JobSummary>> {
+        let started = now();
+        let mut due: Vec<i64> = self.jobs.iter().map(|(_, schedule)| schedule.first(started)).collect();
+        let mut running: Vec<JoinHandle<Option<JobSummary>>> = Vec::new();
+        let mut summaries = Vec::new();
+
+        for ((job, _), at) in self.jobs.iter().zip(&due) {
+            info!(
+                "Job '{}': first run at {}",
+                job.name,
+                humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(*at as u64))
+            );
+        }
+
+        let mut last_poll = Instant::now();
+        while !stop.load(Ordering::Relaxed) {
+            let now = now();
+            for ((job, schedule), at) in self.jobs.iter().zip(due.iter_mut()) {
+                if now < *at {
+                    continue;
+                }
+                *at = schedule.next(*at, now);
+
+                let job = job.clone();
+                running.push(std::thread::spawn(move || match run_job(&job) {
+                    Ok(summary) => {
+                        log_summary(&summary);
+                        Some(summary)
+                    }
+                    Err(e) => {
+                        // A held lock means the previous run is still going
+                        error!("Job '{}' skipped or failed: {:#}", job.name, e);
+                        None
+                    }
+                }));
+            }
+
+            let (finished, still_running): (Vec<_>, Vec<_>) = running.into_iter().partition(|h| h.is_finished());
+            running = still_running;
+            for handle in finished {
+                summaries.extend(handle.join().map_err(|_| anyhow::anyhow!("Job thread panicked"))?);
+            }
+
+            std::thread::sleep(POLL_INTERVAL.saturating_sub(last_poll.elapsed()));
+            last_poll = Instant::now();
+        }
+
+        if !running.is_empty() {
+            info!("Waiting for {} running job(s) to finish", running.len());
+        }
+        for handle in running {
+            summaries.extend(handle.join().map_err(|_| anyhow::anyhow!("Job thread panicked"))?);
+        }
+        Ok(summaries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    fn job(output: &str) -> JobConfig {
+        toml::from_str(&format!(
+            "name = \"hot\"\npath = \"/tmp\"\noutput = \"{}\"\ninterval = \"1s\"\n",
+            output
+        ))
+        .unwrap()
+    }
+
+    // 2024-05-20T02:03:04Z, a Monday
+    const MONDAY: i64 = 1_716_170_584;
+
+    #[test]
+    fn test_calendar_fields() {
+        assert_eq!(calendar(0), (1, 1, 0, 0, 4));
+        assert_eq!(calendar(MONDAY), (5, 20, 2, 3, 1));
+        // Leap day
+        assert_eq!(calendar(1_709_164_800), (2, 29, 0, 0, 4));
+    }
+
+    #[test]
+    fn test_cron_next_after() {
+        let nightly = CronSchedule::parse("0 2 * * *").unwrap();
+        assert_eq!(nightly.next_after(MONDAY), MONDAY - 184 + 86_400);
+
+        let quarter_hours = CronSchedule::parse("*/15 * * * *").unwrap();
+        assert_eq!(quarter_hours.next_after(MONDAY), MONDAY - 184 + 15 * 60);
+
+        // Sunday is 0 or 7
+        let sunday = CronSchedule::parse("30 1 * * 7").unwrap();
+        assert_eq!(calendar(sunday.next_after(MONDAY)), (5, 26, 1, 30, 0));
+
+        // With both day and weekday restricted, either one matches
+        let either = CronSchedule::parse("0 0 1 * 3").unwrap();
+        assert_eq!(calendar(either.next_after(MONDAY)).4, 3);
+
+        for bad in ["* * * *", "60 * * * *", "*/0 * * * *", "5-1 * * * *", "a * * * *"] {
+            assert!(CronSchedule::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_interval_next_skips_missed_runs() {
+        let every_10s = JobSchedule::Interval(10);
+        assert_eq!(every_10s.first(100), 110);
+        assert_eq!(every_10s.next(110, 110), 120);
+        assert_eq!(every_10s.next(110, 135), 140);
+    }
+
+    #[test]
+    fn test_output_placeholders_and_pattern() {
+        let job = job("/scans/hot_{date}_{time}.parquet");
+        assert_eq!(job.output_for(MONDAY), PathBuf::from("/scans/hot_2024-05-20_020304.parquet"));
+        assert_eq!(job.output_pattern(), "/scans/hot_*_*.parquet");
+
+        let job = job_with_datetime();
+        assert_eq!(job.output_for(MONDAY), PathBuf::from("out/scan_2024-05-20T020304.parquet"));
+        assert_eq!(job.output_pattern(), "out/scan_*_manifest.json");
+        assert_eq!(job.lock_target(), PathBuf::from("out/.hot"));
+    }
+
+    fn job_with_datetime() -> JobConfig {
+        let mut job = job("out/scan_{datetime}.parquet");
+        job.incremental = true;
+        job
+    }
+
+    #[test]
+    fn test_config_validation() {
+        let parse = |s: &str| toml::from_str::<ScheduleConfig>(s).map_err(anyhow::Error::from).and_then(|c| c.validate());
+        let base = "[[job]]\nname = \"a\"\npath = \"/tmp\"\noutput = \"/tmp/a_{date}.parquet\"\n";
+
+        assert!(parse(&format!("{}interval = \"6h\"\n", base)).is_ok());
+        assert!(parse(&format!("{}cron = \"0 2 * * *\"\nretain = 7\n", base)).is_ok());
+        // Neither or both schedules
+        assert!(parse(base).is_err());
+        assert!(parse(&format!("{}interval = \"6h\"\ncron = \"0 2 * * *\"\n", base)).is_err());
+        assert!(parse(&format!("{}interval = \"6h\"\nretain = 0\n", base)).is_err());
+        assert!(parse(&format!("{}interval = \"6h\"\nunknown = 1\n", base)).is_err());
+        assert!(parse(&format!("{0}interval = \"6h\"\n{0}interval = \"1h\"\n", base)).is_err());
+        assert!(parse("").is_err());
+    }
+
+    #[test]
+    fn test_scheduler_runs_interval_job_and_prunes() {
+        let tree = TempDir::new().unwrap();
+        fs::create_dir(tree.path().join("sub")).unwrap();
+        fs::write(tree.path().join("sub/a.txt"), "a").unwrap();
+        fs::write(tree.path().join("b.log"), "bb").unwrap();
+        let out = TempDir::new().unwrap();
+
+        let mut job = job(&format!("{}/scan_{{datetime}}.parquet", out.path().display()));
+        job.path = tree.path().to_path_buf();
+        job.retain = Some(2);
+        job.threads = Some(1);
+        let scheduler = Scheduler::new(ScheduleConfig { jobs: vec![job] }).unwrap();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let stop = stop.clone();
+            std::thread::spawn(move || scheduler.run(&stop))
+        };
+        std::thread::sleep(Duration::from_millis(4500));
+        stop.store(true, Ordering::Relaxed);
+        let summaries = handle.join().unwrap().unwrap();
+
+        assert!(summaries.len() >= 3, "{:?}", summaries);
+        // Root, sub, and two files
+        assert!(summaries.iter().all(|s| s.rows_written == 4), "{:?}", summaries);
+        let mut outputs: Vec<&PathBuf> = summaries.iter().map(|s| &s.output).collect();
+        outputs.dedup();
+        assert_eq!(outputs.len(), summaries.len(), "outputs must be dated per run");
+
+        // Retention kept the two newest and removed the oldest
+        assert!(!summaries[0].output.exists());
+        assert!(summaries.iter().any(|s| s.pruned.contains(&summaries[0].output)));
+        let mut remaining: Vec<PathBuf> = fs::read_dir(out.path()).unwrap()
+            .map(|e| e.unwrap().path())
+            .collect();
+        remaining.sort();
+        let newest: Vec<PathBuf> = summaries[summaries.len() - 2..].iter().map(|s| s.output.clone()).collect();
+        assert_eq!(remaining, newest);
+    }
+
+    #[test]
+    fn test_run_job_is_skipped_while_locked() {
+        let tree = TempDir::new().unwrap();
+        fs::write(tree.path().join("a.txt"), "a").unwrap();
+        let out = TempDir::new().unwrap();
+
+        let mut job = job_with_datetime();
+        job.output = format!("{}/scan_{{datetime}}.parquet", out.path().display());
+        job.path = tree.path().to_path_buf();
+
+        let held = ScanLock::acquire(&job.lock_target(), false).unwrap();
+        assert!(run_job(&job).is_err());
+        drop(held);
+
+        let summary = run_job(&job).unwrap();
+        assert!(summary.rows_written > 0);
+        let stem = summary.output.file_stem().unwrap().to_string_lossy().to_string();
+        assert!(out.path().join(format!("{}_manifest.json", stem)).exists());
+
+        // Pruning an incremental output removes its chunks and manifest
+        job.retain = Some(1);
+        std::thread::sleep(Duration::from_millis(1100));
+        let second = run_job(&job).unwrap();
+        assert_eq!(second.pruned.len(), 1);
+        let names: Vec<String> = fs::read_dir(out.path()).unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        assert!(names.iter().all(|n| !n.starts_with(&stem)), "{:?}", names);
+    }
+}
@@ -0,0 +1,470 @@
+use crate::aggregate::{self, conform_batch, reconcile_schemas};
+use crate::du::{self, DuOptions};
+use crate::manifest::ScanManifest;
+use crate::query::Expr;
+use crate::report::collect_stats;
+use crate::sample::write_jsonl;
+use crate::stats::UsageStats;
+use crate::top::{self, TopOptions};
+use anyhow::{Context, Result};
+use arrow::compute::filter_record_batch;
+use arrow::datatypes::SchemaRef;
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs::File;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tiny_http::{Header, Method, Response, Server};
+use tracing::{info, warn};
+
+/// Threads answering requests
+const WORKERS: usize = 4;
+
+/// Depth of `/dirs` when the request sets none
+const DEFAULT_DIR_DEPTH: usize = 1;
+
+/// Files and directories of `/top` when the request sets no `n`
+const DEFAULT_TOP: usize = 20;
+
+/// How [`Api`] reads the scan
+#[derive(Debug, Clone)]
+pub struct ServeOptions {
+    /// Most rows, files, or directories in one response
+    pub max_rows: usize,
+
+    /// Hold every row in memory and compute `/stats` once, instead of reading
+    /// the chunks for each request
+    pub preload: bool,
+}
+
+impl Default for ServeOptions {
+    fn default() -> Self {
+        Self { max_rows: 10_000, preload: false }
+    }
+}
+
+/// Rows held by a preloaded [`Api`]
+struct Preloaded {
+    batches: Vec<RecordBatch>,
+    stats: UsageStats,
+}
+
+/// An error answered with a status code and `{"error": message}`
+#[derive(Debug)]
+struct ApiError {
+    status: u16,
+    message: String,
+}
+
+impl ApiError {
+    fn bad_request(message: impl Into<String>) -> Self {
+        Self { status: 400, message: message.into() }
+    }
+
+    fn not_found(message: impl Into<String>) -> Self {
+        Self { status: 404, message: message.into() }
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(err: anyhow::Error) -> Self {
+        Self { status: 500, message: format!("{:#}", err) }
+    }
+}
+
+/// Result of `/query`
+#[derive(Debug, Serialize)]
+struct QueryResponse {
+    rows_scanned: u64,
+
+    rows_matched: u64,
+
+    /// Whether more rows matched than `rows` holds
+    truncated: bool,
+
+    rows: Vec<Value>,
+}
+
+/// Read-only JSON endpoints over one scan:
+///
+/// - `/stats`: usage statistics
+/// - `/top?n=&under=`: largest files and heaviest directories
+/// - `/dirs?under=&depth=&min_size=`: cumulative directory sizes
+/// - `/query?where=`: rows matching a filter expression, as in `query`
+/// - `/manifest`: the scan's manifest, when it has one
+pub struct Api {
+    chunk_files: Vec<PathBuf>,
+    schema: SchemaRef,
+    manifest: Option<ScanManifest>,
+    options: ServeOptions,
+    preloaded: Option<Preloaded>,
+}
+
+impl Api {
+    /// Open the scan at `input` (anything `find_chunk_files` accepts),
+    /// reading it into memory if `options.preload` is set
+    pub fn open(input: &Path, options: ServeOptions) -> Result<Self> {
+        let chunk_files = aggregate::find_chunk_files(input)?;
+        if chunk_files.is_empty() {
+            anyhow::bail!("No Parquet files found in: {}", input.display());
+        }
+        let schema = reconcile_schemas(&chunk_files)?;
+
+        let manifest_path = aggregate::get_manifest_path(input);
+        let manifest = if manifest_path.exists() {
+            Some(ScanManifest::load_from_file(&manifest_path)
+                .with_context(|| format!("Failed to load {}", manifest_path.display()))?)
+        } else {
+            None
+        };
+
+        let preloaded = if options.preload {
+            let mut batches = Vec::new();
+            let mut stats = UsageStats::as_of(now());
+            UsageStats::validate(&schema)?;
+            for path in &chunk_files {
+                for batch in read_chunk(path)? {
+                    let batch = conform_batch(&batch?, &schema)?;
+                    stats.add_batch(&batch)?;
+                    batches.push(batch);
+                }
+            }
+            info!("Loaded {} rows into memory", stats.rows);
+            Some(Preloaded { batches, stats })
+        } else {
+            None
+        };
+
+        Ok(Self { chunk_files, schema, manifest, options, preloaded })
+    }
+
+    /// Answer a GET of `url` (path and query string) with a status code and
+    /// a JSON body
+    pub fn handle(&self, url: &str) -> (u16, Value) {
+        let (path, query) = url.split_once('?').unwrap_or((url, ""));
+        let result = parse_query(query).and_then(|params| match path.trim_end_matches('/') {
+            "/stats" => self.stats(),
+            "/top" => self.top(&params),
+            "/dirs" => self.dirs(&params),
+            "/query" => self.query(&params),
+            "/manifest" => self.manifest(),
+            _ => Err(ApiError::not_found(format!("No endpoint {}", path))),
+        });
+        match result {
+            Ok(body) => (200, body),
+            Err(err) => (err.status, json!({ "error": err.message })),
+        }
+    }
+
+    fn stats(&self) -> Result<Value, ApiError> {
+        let stats = match self.preloaded {
+            Some(ref preloaded) => preloaded.stats.clone(),
+            None => collect_stats(&self.chunk_files, now())?,
+        };
+        to_value(&stats)
+    }
+
+    fn top(&self, params: &HashMap<String, String>) -> Result<Value, ApiError> {
+        let n = number(params, "n")?.unwrap_or(DEFAULT_TOP).min(self.options.max_rows);
+        let options = TopOptions {
+            files: n,
+            dirs: n,
+            under: params.get("under").cloned(),
+            cumulative: params.get("cumulative").is_some_and(|c| c == "true"),
+        };
+        to_value(&top::top_entries(&self.chunk_files, &options)?)
+    }
+
+    fn dirs(&self, params: &HashMap<String, String>) -> Result<Value, ApiError> {
+        let min_size = params.get("min_size")
+            .map(|s| crate::utils::parse_bytes(s).map_err(|e| ApiError::bad_request(format!("Invalid min_size: {}", e))))
+            .transpose()?;
+        let options = DuOptions {
+            depth: number(params, "depth")?.unwrap_or(DEFAULT_DIR_DEPTH),
+            under: params.get("under").cloned(),
+            min_size: min_size.unwrap_or(0),
+            ..Default::default()
+        };
+        let mut entries = du::disk_usage(&self.chunk_files, &options)?;
+        let truncated = entries.len() > self.options.max_rows;
+        entries.truncate(self.options.max_rows);
+        Ok(json!({ "truncated": truncated, "entries": to_value(&entries)? }))
+    }
+
+    fn query(&self, params: &HashMap<String, String>) -> Result<Value, ApiError> {
+        let filter = params.get("where")
+            .ok_or_else(|| ApiError::bad_request("Missing 'where' parameter"))?;
+        let expr = Expr::parse(filter)
+            .map_err(|e| ApiError::bad_request(format!("Invalid where expression: {}", e)))?;
+        expr.validate(&self.schema).map_err(|e| ApiError::bad_request(format!("{:#}", e)))?;
+
+        let mut response = QueryResponse { rows_scanned: 0, rows_matched: 0, truncated: false, rows: Vec::new() };
+        let mut add = |batch: &RecordBatch| -> Result<()> {
+            response.rows_scanned += batch.num_rows() as u64;
+            let matched = filter_record_batch(batch, &expr.mask(batch)?)?;
+            response.rows_matched += matched.num_rows() as u64;
+
+            let room = self.options.max_rows - response.rows.len();
+            if matched.num_rows() > room {
+                response.truncated = true;
+            }
+            let kept = matched.slice(0, matched.num_rows().min(room));
+            if kept.num_rows() > 0 {
+                let mut lines = Vec::new();
+                write_jsonl(&kept, &mut lines)?;
+                for line in lines.split(|&b| b == b'\n').filter(|l| !l.is_empty()) {
+                    response.rows.push(serde_json::from_slice(line)?);
+                }
+            }
+            Ok(())
+        };
+
+        let mut scan = || -> Result<()> {
+            match self.preloaded {
+                Some(ref preloaded) => {
+                    for batch in &preloaded.batches {
+                        add(batch)?;
+                    }
+                }
+                None => {
+                    for path in &self.chunk_files {
+                        for batch in read_chunk(path)? {
+                            add(&conform_batch(&batch?, &self.schema)?)?;
+                        }
+                    }
+                }
+            }
+            Ok(())
+        };
+        scan()?;
+        to_value(&response)
+    }
+
+    fn manifest(&self) -> Result<Value, ApiError> {
+        match self.manifest {
+            Some(ref manifest) => to_value(manifest),
+            None => Err(ApiError::not_found("The scan has no manifest")),
+        }
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+fn read_chunk(path: &Path) -> Result<impl Iterator<Item = std::result::Result<RecordBatch, arrow::error::ArrowError>>> {
+    Ok(ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)
+        .with_context(|| format!("Failed to read {}", path.display()))?
+        .build()?)
+}
+
+fn to_value<T: Serialize>(value: &T) -> Result<Value, ApiError> {
+    serde_json::to_value(value).map_err(|e| ApiError::from(anyhow::Error::from(e)))
+}
+
+fn number(params: &HashMap<String, String>, name: &str) -> Result<Option<usize>, ApiError> {
+    params.get(name)
+        .map(|v| v.parse().map_err(|_| ApiError::bad_request(format!("Invalid {}: '{}'", name, v))))
+        .transpose()
+}
+
+/// Decode `%XX` escapes and `+` (a space) in a query string component
+fn percent_decode(s: &str) -> Result<String, ApiError> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' => {
+                let byte = s.get(i + 1..i + 3)
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or_else(|| ApiError::bad_request(format!("Invalid escape in '{}'", s)))?;
+                out.push(byte);
+                i += 2;
+            }
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8(out).map_err(|_| ApiError::bad_request(format!("Invalid UTF-8 in '{}'", s)))
+}
+
+fn parse_query(query: &str) -> Result<HashMap<String, String>, ApiError> {
+    let mut params = HashMap::new();
+    for pair in query.split('&').filter(|p| !p.is_empty()) {
+        let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+        params.insert(percent_decode(name)?, percent_decode(value)?);
+    }
+    Ok(params)
+}
+
+/// An HTTP server answering GET requests with [`Api::handle`]
+pub struct ApiServer {
+    server: Server,
+    api: Api,
+}
+
+impl ApiServer {
+    /// Listen on `addr` (e.g., "127.0.0.1:8080"; port 0 picks a free one)
+    pub fn bind(addr: &str, api: Api) -> Result<Self> {
+        let server = Server::http(addr)
+            .map_err(|e| anyhow::anyhow!("Failed to listen on {}: {}", addr, e))?;
+        Ok(Self { server, api })
+    }
+
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.server.server_addr().to_ip()
+    }
+
+    /// Answer requests until the process exits
+    pub fn run(&self) {
+        std::thread::scope(|scope| {
+            for _ in 0..WORKERS {
+                scope.spawn(|| loop {
+                    let request = match self.server.recv() {
+                        Ok(request) => request,
+                        Err(e) => {
+                            warn!("Failed to accept a request: {}", e);
+                            continue;
+                        }
+                    };
+                    let (status, body) = match request.method() {
+                        Method::Get => self.api.handle(request.url()),
+                        _ => (405, json!({ "error": "Only GET is supported" })),
+                    };
+                    info!("{} {} {}", request.method(), request.url(), status);
+
+                    let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+                    let response = Response::from_data(body.to_string())
+                        .with_status_code(status)
+                        .with_header(content_type);
+                    if let Err(e) = request.respond(response) {
+                        warn!("Failed to send a response: {}", e);
+                    }
+                });
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::FileEntry;
+    use crate::rotating_writer::{RotatingParquetWriter, RotatingWriterConfig};
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    fn entry(path: &str, size: u64) -> FileEntry {
+        let entry = if size == 0 { FileEntry::dir_fixture(path) } else { FileEntry::fixture(path) };
+        FileEntry { size, gid: 100, ..entry }
+    }
+
+    /// Scan of /project in two chunks with a manifest; returns the manifest path
+    fn write_scan(dir: &Path) -> PathBuf {
+        let config = RotatingWriterConfig {
+            base_output_path: dir.join("scan.parquet"),
+            rows_per_chunk: 4,
+            time_interval: Duration::from_secs(3600),
+        };
+        let mut writer = RotatingParquetWriter::new(config, "/project".to_string()).unwrap();
+        let entries = [
+            entry("/project", 0),
+            entry("/project/labA", 0),
+            entry("/project/labA/a1.dat", 300),
+            entry("/project/labA/a2.dat", 100),
+            entry("/project/labB", 0),
+            entry("/project/labB/b1.dat", 500),
+            entry("/project/labB/b2.dat", 50),
+        ];
+        for entry in entries {
+            writer.write_batch(&[entry]).unwrap();
+        }
+        writer.finalize().unwrap();
+        dir.join("scan_manifest.json")
+    }
+
+    /// GET `path` from a server on `addr`, returning the status and JSON body
+    fn get(addr: SocketAddr, path: &str) -> (u16, Value) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        let status = response[9..12].parse().unwrap();
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        (status, serde_json::from_str(body).unwrap())
+    }
+
+    fn start(input: &Path, options: ServeOptions) -> SocketAddr {
+        let server = ApiServer::bind("127.0.0.1:0", Api::open(input, options).unwrap()).unwrap();
+        let addr = server.local_addr().unwrap();
+        std::thread::spawn(move || server.run());
+        addr
+    }
+
+    #[test]
+    fn test_endpoints() {
+        let temp_dir = TempDir::new().unwrap();
+        let manifest = write_scan(temp_dir.path());
+
+        for preload in [false, true] {
+            let addr = start(&manifest, ServeOptions { max_rows: 100, preload });
+
+            let (status, stats) = get(addr, "/stats");
+            assert_eq!(status, 200);
+            assert_eq!(stats["rows"], 7);
+            assert_eq!(stats["total_bytes"], 950);
+
+            let (_, top) = get(addr, "/top?n=2");
+            assert_eq!(top["files"][0]["path"], "/project/labB/b1.dat");
+            assert_eq!(top["files"].as_array().unwrap().len(), 2);
+
+            let (_, dirs) = get(addr, "/dirs?under=%2Fproject&depth=1");
+            let dirs: Vec<(&str, u64)> = dirs["entries"].as_array().unwrap().iter()
+                .map(|d| (d["path"].as_str().unwrap(), d["bytes"].as_u64().unwrap()))
+                .collect();
+            assert_eq!(dirs, [("/project", 950), ("/project/labA", 400), ("/project/labB", 550)]);
+
+            let (status, query) = get(addr, "/query?where=size+%3E+90+%26%26+top_level_dir+%3D%3D+%27labB%27");
+            assert_eq!(status, 200, "{}", query);
+            assert_eq!(query["rows_scanned"], 7);
+            assert_eq!(query["rows_matched"], 1);
+            assert_eq!(query["rows"][0]["path"], "/project/labB/b1.dat");
+
+            let (_, manifest) = get(addr, "/manifest");
+            assert_eq!(manifest["scan_path"], "/project");
+            assert_eq!(manifest["total_rows"], 7);
+        }
+    }
+
+    #[test]
+    fn test_max_rows_and_errors() {
+        let temp_dir = TempDir::new().unwrap();
+        let manifest = write_scan(temp_dir.path());
+        let addr = start(&manifest, ServeOptions { max_rows: 2, preload: false });
+
+        let (_, query) = get(addr, "/query?where=size+%3E+0");
+        assert_eq!(query["rows_matched"], 4);
+        assert_eq!(query["rows"].as_array().unwrap().len(), 2);
+        assert_eq!(query["truncated"], true);
+
+        let (_, top) = get(addr, "/top?n=50");
+        assert_eq!(top["files"].as_array().unwrap().len(), 2);
+
+        let (status, err) = get(addr, "/query?where=size+%3E");
+        assert_eq!(status, 400);
+        assert!(err["error"].as_str().unwrap().contains("Invalid where expression"));
+        assert_eq!(get(addr, "/query?where=color+%3D%3D+1").0, 400);
+        assert_eq!(get(addr, "/dirs?depth=deep").0, 400);
+        assert_eq!(get(addr, "/nothing").0, 404);
+    }
+}
@@ -0,0 +1,333 @@
+use anyhow::Result;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "parquet-writer")]
+use {
+    crate::du::{self, DuOptions},
+    anyhow::Context,
+    std::collections::BTreeMap,
+};
+
+/// The part of a scan one task of a sharded scan (such as a SLURM array
+/// job) covers.
+///
+/// Directories (and files) `depth` levels below the scan root are assigned
+/// to shards by a stable hash of their path below the root, and each takes
+/// everything beneath it along. Entries above that level, the root among
+/// them, go to shard 0, so every entry belongs to exactly one shard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Shard {
+    /// This task's shard, from 0
+    pub index: u32,
+
+    /// Shards the scan is split into
+    pub count: u32,
+
+    /// Level below the scan root whose entries are assigned (1: top-level directories)
+    pub depth: usize,
+}
+
+impl Shard {
+    pub fn new(index: u32, count: u32, depth: usize) -> Result<Self> {
+        if count == 0 {
+            anyhow::bail!("--shard-count must be at least 1");
+        }
+        if index >= count {
+            anyhow::bail!("--shard-index {} is out of range for {} shards (0 to {})", index, count, count - 1);
+        }
+        if depth == 0 {
+            anyhow::bail!("--shard-depth must be at least 1");
+        }
+        Ok(Self { index, count, depth })
+    }
+
+    /// Shard of the entry at `key`, a path below the scan root with `/`
+    /// separators. FNV-1a rather than std's hasher, whose output may change
+    /// between Rust releases: tasks of one scan must agree, and so must
+    /// scans a release apart.
+    pub fn assign(key: &str, count: u32) -> u32 {
+        let hash = key.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+        });
+        (hash % count as u64) as u32
+    }
+
+    /// Assignment key of the entry at `relative` (a path below the scan
+    /// root), or None above the shard level
+    pub fn key(&self, relative: &Path) -> Option<String> {
+        let components: Vec<_> = relative.components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect();
+        (components.len() >= self.depth).then(|| components[..self.depth].join("/"))
+    }
+
+    /// Whether this shard scans the entry at `relative`, a path below the scan root
+    pub fn owns(&self, relative: &Path) -> bool {
+        match self.key(relative) {
+            Some(key) => Self::assign(&key, self.count) == self.index,
+            None => self.index == 0,
+        }
+    }
+
+    /// Where this shard writes a scan given `output`: scan.parquet becomes
+    /// scan_shard_3.parquet, so chunks and manifest carry the shard too
+    pub fn output_path(&self, output: &Path) -> PathBuf {
+        let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+        let name = match output.extension() {
+            Some(ext) => format!("{}_shard_{}.{}", stem, self.index, ext.to_string_lossy()),
+            None => format!("{}_shard_{}", stem, self.index),
+        };
+        output.with_file_name(name)
+    }
+}
+
+/// Check that manifests about to be merged are all of one sharded scan,
+/// each shard once, or none of them sharded
+pub fn check_complete(shards: &[Option<Shard>]) -> Result<()> {
+    let sharded: Vec<Shard> = shards.iter().flatten().copied().collect();
+    let Some(first) = sharded.first() else { return Ok(()) };
+    if sharded.len() < shards.len() {
+        anyhow::bail!("Only some of the manifests are of a sharded scan");
+    }
+    if let Some(other) = sharded.iter().find(|s| (s.count, s.depth) != (first.count, first.depth)) {
+        anyhow::bail!(
+            "Manifests are of different shardings ({} shards at depth {}, and {} at depth {})",
+            first.count, first.depth, other.count, other.depth
+        );
+    }
+
+    let mut seen = BTreeSet::new();
+    for shard in &sharded {
+        if !seen.insert(shard.index) {
+            anyhow::bail!("Shard {} of {} is given more than once", shard.index, shard.count);
+        }
+    }
+    let missing: Vec<String> = (0..first.count)
+        .filter(|index| !seen.contains(index))
+        .map(|index| index.to_string())
+        .collect();
+    if !missing.is_empty() {
+        anyhow::bail!("Missing shard{} {} of {}", if missing.len() == 1 { "" } else { "s" }, missing.join(", "), first.count);
+    }
+    Ok(())
+}
+
+#[cfg(feature = "parquet-writer")]
+/// One entry at the shard level and the shard it goes to
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ShardUnit {
+    /// Path below the scan root
+    pub path: String,
+
+    pub shard: u32,
+
+    /// Files below it in the baseline scan (None without one, or if it is new)
+    pub files: Option<u64>,
+
+    /// Bytes below it in the baseline scan
+    pub bytes: Option<u64>,
+}
+
+#[cfg(feature = "parquet-writer")]
+/// What one shard of a [`ShardPlan`] gets
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ShardTotals {
+    pub index: u32,
+
+    /// Entries at the shard level assigned to it
+    pub units: u64,
+
+    /// Estimated files, from the baseline
+    pub files: u64,
+
+    /// Estimated bytes, from the baseline
+    pub bytes: u64,
+}
+
+#[cfg(feature = "parquet-writer")]
+/// How a sharded scan of a tree would split it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ShardPlan {
+    pub root: String,
+    pub count: u32,
+    pub depth: usize,
+
+    /// Entries at the shard level, by path
+    pub units: Vec<ShardUnit>,
+
+    /// Per shard, by index
+    pub shards: Vec<ShardTotals>,
+}
+
+#[cfg(feature = "parquet-writer")]
+impl ShardPlan {
+    /// Largest shard over the mean, by estimated bytes, or by units without
+    /// estimates; 1.0 is a perfect balance
+    pub fn imbalance(&self) -> f64 {
+        let estimated = self.shards.iter().any(|s| s.bytes > 0);
+        let sizes: Vec<u64> = self.shards.iter()
+            .map(|s| if estimated { s.bytes } else { s.units })
+            .collect();
+        let total: u64 = sizes.iter().sum();
+        if total == 0 {
+            return 1.0;
+        }
+        let max = sizes.iter().copied().max().unwrap_or(0);
+        max as f64 * self.count as f64 / total as f64
+    }
+}
+
+#[cfg(feature = "parquet-writer")]
+/// Plan a scan of `root` in `count` shards at `depth`, with estimates from
+/// the scan output `baseline` (chunk files of a previous scan of `root`)
+pub fn plan(root: &Path, count: u32, depth: usize, baseline: Option<&[PathBuf]>) -> Result<ShardPlan> {
+    Shard::new(0, count, depth)?;
+    let root = root.canonicalize()
+        .with_context(|| format!("Failed to resolve {}", root.display()))?;
+
+    let mut paths = Vec::new();
+    list_level(&root, Path::new(""), depth, &mut paths)?;
+    paths.sort();
+
+    let estimates: BTreeMap<String, (u64, u64)> = match baseline {
+        Some(chunk_files) => {
+            let options = DuOptions { depth, ..Default::default() };
+            let prefix = format!("{}/", root.to_string_lossy().trim_end_matches('/'));
+            du::disk_usage(chunk_files, &options)?.into_iter()
+                .filter(|entry| entry.depth == depth)
+                .filter_map(|entry| Some((entry.path.strip_prefix(&prefix)?.to_string(), (entry.files, entry.bytes))))
+                .collect()
+        }
+        None => BTreeMap::new(),
+    };
+
+    let mut shards: Vec<ShardTotals> = (0..count).map(|index| ShardTotals { index, ..Default::default() }).collect();
+    let units: Vec<ShardUnit> = paths.into_iter()
+        .map(|path| {
+            let shard = Shard::assign(&path, count);
+            let estimate = estimates.get(&path).copied();
+            let totals = &mut shards[shard as usize];
+            totals.units += 1;
+            if let Some((files, bytes)) = estimate {
+                totals.files += files;
+                totals.bytes += bytes;
+            }
+            ShardUnit { path, shard, files: estimate.map(|e| e.0), bytes: estimate.map(|e| e.1) }
+        })
+        .collect();
+
+    Ok(ShardPlan { root: root.to_string_lossy().to_string(), count, depth, units, shards })
+}
+
+#[cfg(feature = "parquet-writer")]
+/// Add the paths (below the root) of the entries `levels` below `dir` to `paths`
+fn list_level(dir: &Path, relative: &Path, levels: usize, paths: &mut Vec<String>) -> Result<()> {
+    let entries = std::fs::read_dir(dir).with_context(|| format!("Failed to list {}", dir.display()))?;
+    for entry in entries {
+        let entry = entry.with_context(|| format!("Failed to list {}", dir.display()))?;
+        let relative = relative.join(entry.file_name());
+        if levels == 1 {
+            paths.push(relative.to_string_lossy().to_string());
+        } else if entry.file_type()?.is_dir() {
+            list_level(&entry.path(), &relative, levels - 1, paths)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assignment_is_stable() {
+        // Changing these splits a sharded scan differently from its previous run
+        let assigned: Vec<u32> = ["labA", "labB", "labC", "home/alice", ""].iter()
+            .map(|key| Shard::assign(key, 8))
+            .collect();
+        assert_eq!(assigned, [7, 2, 5, 5, 5]);
+        assert_eq!(Shard::assign("labA", 1), 0);
+    }
+
+    #[test]
+    fn test_every_entry_in_exactly_one_shard() {
+        for depth in 1..=3 {
+            for count in [1, 2, 3, 7] {
+                let shards: Vec<Shard> = (0..count).map(|i| Shard::new(i, count, depth).unwrap()).collect();
+                for path in ["", "a", "a/b", "a/b/c", "a/b/c/d.txt", "b", "b/x", "file.txt", "c/y/z"] {
+                    let owners: Vec<u32> = shards.iter().filter(|s| s.owns(Path::new(path))).map(|s| s.index).collect();
+                    assert_eq!(owners.len(), 1, "{} at depth {} in {} shards: {:?}", path, depth, count, owners);
+                }
+                // Everything below an entry at the shard level goes with it
+                let owner = |path: &str| shards.iter().find(|s| s.owns(Path::new(path))).unwrap().index;
+                if depth == 1 {
+                    assert_eq!(owner("a/b/c/d.txt"), owner("a"));
+                }
+            }
+        }
+        let shard = Shard::new(1, 4, 2).unwrap();
+        assert!(!shard.owns(Path::new("")));
+        assert!(!shard.owns(Path::new("a")));
+        assert_eq!(shard.key(Path::new("a/b/c")), Some("a/b".to_string()));
+    }
+
+    #[test]
+    fn test_new_and_output_path() {
+        assert!(Shard::new(4, 4, 1).is_err());
+        assert!(Shard::new(0, 0, 1).is_err());
+        assert!(Shard::new(0, 4, 0).is_err());
+        let shard = Shard::new(3, 4, 1).unwrap();
+        assert_eq!(shard.output_path(Path::new("/out/scan.parquet")), PathBuf::from("/out/scan_shard_3.parquet"));
+        assert_eq!(shard.output_path(Path::new("scan")), PathBuf::from("scan_shard_3"));
+    }
+
+    #[test]
+    fn test_check_complete() {
+        let shard = |index| Some(Shard::new(index, 3, 1).unwrap());
+        assert!(check_complete(&[None, None]).is_ok());
+        assert!(check_complete(&[shard(2), shard(0), shard(1)]).is_ok());
+
+        let err = check_complete(&[shard(0), shard(2)]).unwrap_err();
+        assert_eq!(err.to_string(), "Missing shard 1 of 3");
+        let err = check_complete(&[shard(0), shard(0), shard(1), shard(2)]).unwrap_err();
+        assert_eq!(err.to_string(), "Shard 0 of 3 is given more than once");
+        assert!(check_complete(&[shard(0), None]).is_err());
+        let deeper = Some(Shard::new(1, 3, 2).unwrap());
+        assert!(check_complete(&[shard(0), deeper, shard(2)]).unwrap_err().to_string().contains("different shardings"));
+    }
+
+    #[cfg(feature = "parquet-writer")]
+    #[test]
+    fn test_plan_lists_every_unit_once() {
+        use std::fs;
+        use tempfile::TempDir;
+
+        let temp_dir = TempDir::new().unwrap();
+        for dir in ["a/x", "a/y", "b/z", "c"] {
+            fs::create_dir_all(temp_dir.path().join(dir)).unwrap();
+        }
+        fs::write(temp_dir.path().join("a/file.txt"), "data").unwrap();
+
+        let plan = plan(temp_dir.path(), 2, 2, None).unwrap();
+        let paths: Vec<&str> = plan.units.iter().map(|u| u.path.as_str()).collect();
+        assert_eq!(paths, ["a/file.txt", "a/x", "a/y", "b/z"]);
+        assert_eq!(plan.shards.iter().map(|s| s.units).sum::<u64>(), 4);
+        for unit in &plan.units {
+            assert_eq!(unit.shard, Shard::assign(&unit.path, 2));
+            assert_eq!(unit.bytes, None);
+        }
+        assert!(plan.imbalance() >= 1.0);
+
+        // Estimates come from a previous scan of the tree
+        let output = TempDir::new().unwrap();
+        let baseline = output.path().join("scan.parquet");
+        crate::scanner::scan_to_parquet(temp_dir.path(), &baseline, Default::default(), None).unwrap();
+        let plan = super::plan(temp_dir.path(), 2, 1, Some(&[baseline])).unwrap();
+        let a = plan.units.iter().find(|u| u.path == "a").unwrap();
+        assert_eq!((a.files, a.bytes), (Some(1), Some(4)));
+        assert_eq!(plan.shards.iter().map(|s| s.bytes).sum::<u64>(), 4);
+    }
+}
@@ -0,0 +1,199 @@
+//! Consumers of scan entries.
+//!
+//! [`Scanner::scan_into`](crate::Scanner::scan_into) feeds an [`EntrySink`]
+//! the batches of a scan; `convert` writes its output through one.
+
+use crate::models::FileEntry;
+use anyhow::Result;
+#[cfg(feature = "parquet-writer")]
+use {
+    crate::query::{QueryFormat, ResultWriter},
+    crate::writer::{entry_columns, ParquetFileWriter},
+    arrow::datatypes::{Schema, SchemaRef},
+    arrow::record_batch::RecordBatch,
+    parquet::file::properties::WriterProperties,
+    std::path::Path,
+    std::sync::Arc,
+};
+
+/// Consumer of scan entries, fed one batch at a time from a single thread.
+///
+/// It need not be `Send`: the thread that hands it to
+/// [`Scanner::scan_into`](crate::Scanner::scan_into) makes every call while
+/// the walk runs on the scanner's threads.
+pub trait EntrySink {
+    /// Take the next batch. An error stops the scan.
+    fn write_batch(&mut self, entries: &[FileEntry]) -> Result<()>;
+
+    /// Complete the output, returning the number of entries written
+    fn finalize(self: Box<Self>) -> Result<u64>;
+}
+
+#[cfg(feature = "parquet-writer")]
+impl EntrySink for ParquetFileWriter {
+    fn write_batch(&mut self, entries: &[FileEntry]) -> Result<()> {
+        Ok(ParquetFileWriter::write_batch(self, entries)?)
+    }
+
+    fn finalize(self: Box<Self>) -> Result<u64> {
+        let rows = self.rows_written();
+        self.close()?;
+        Ok(rows)
+    }
+}
+
+/// The FileEntry schema restricted to `columns`, in that order; None keeps
+/// every column
+#[cfg(feature = "parquet-writer")]
+pub(crate) fn entry_schema(columns: Option<&[String]>) -> Result<SchemaRef> {
+    let schema = ParquetFileWriter::create_schema();
+    let Some(columns) = columns else { return Ok(schema) };
+    let fields = columns.iter()
+        .map(|name| schema.field_with_name(name).cloned().map_err(|_| {
+            let known: Vec<&str> = schema.fields().iter().map(|f| f.name().as_str()).collect();
+            anyhow::anyhow!("Unknown column '{}' (columns: {})", name, known.join(", "))
+        }))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Arc::new(Schema::new(fields)))
+}
+
+/// Writes entries, or some of their columns, as Parquet, CSV, JSONL, or Arrow
+#[cfg(feature = "parquet-writer")]
+pub(crate) struct FormatSink {
+    writer: ResultWriter,
+
+    /// Indices of the written columns in the FileEntry schema
+    columns: Vec<usize>,
+
+    schema: SchemaRef,
+    rows: u64,
+}
+
+#[cfg(feature = "parquet-writer")]
+impl FormatSink {
+    /// Create `output` for the FileEntry `columns` (all when None), with
+    /// `properties` for Parquet output
+    pub(crate) fn create(
+        output: &Path,
+        format: QueryFormat,
+        columns: Option<&[String]>,
+        properties: Option<WriterProperties>,
+    ) -> Result<Self> {
+        let schema = entry_schema(columns)?;
+        let full = ParquetFileWriter::create_schema();
+        let columns = schema.fields().iter()
+            .map(|f| full.index_of(f.name()))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let writer = ResultWriter::with_properties(output, format, schema.clone(), properties)?;
+        Ok(Self { writer, columns, schema, rows: 0 })
+    }
+}
+
+#[cfg(feature = "parquet-writer")]
+impl EntrySink for FormatSink {
+    fn write_batch(&mut self, entries: &[FileEntry]) -> Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let all = entry_columns(entries);
+        let columns = self.columns.iter().map(|&i| all[i].clone()).collect();
+        self.writer.write(&RecordBatch::try_new(self.schema.clone(), columns)?)?;
+        self.rows += entries.len() as u64;
+        Ok(())
+    }
+
+    fn finalize(self: Box<Self>) -> Result<u64> {
+        self.writer.finish()?;
+        Ok(self.rows)
+    }
+}
+
+/// Writes entries to an `entries` table of a new SQLite database
+#[cfg(feature = "sqlite")]
+pub(crate) struct SqliteSink {
+    connection: rusqlite::Connection,
+    columns: Vec<usize>,
+    insert: String,
+    rows: u64,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteSink {
+    pub(crate) fn create(output: &Path, columns: Option<&[String]>) -> Result<Self> {
+        use anyhow::Context;
+        use arrow::datatypes::DataType;
+
+        if output.exists() {
+            anyhow::bail!("{} already exists", output.display());
+        }
+        let schema = entry_schema(columns)?;
+        let full = ParquetFileWriter::create_schema();
+        let connection = rusqlite::Connection::open(output)
+            .with_context(|| format!("Failed to create {}", output.display()))?;
+
+        let definitions: Vec<String> = schema.fields().iter()
+            .map(|f| {
+                let sql_type = if *f.data_type() == DataType::Utf8 { "TEXT" } else { "INTEGER" };
+                let null = if f.is_nullable() { "" } else { " NOT NULL" };
+                format!("\"{}\" {}{}", f.name(), sql_type, null)
+            })
+            .collect();
+        connection.execute(&format!("CREATE TABLE entries ({})", definitions.join(", ")), [])?;
+
+        let names: Vec<String> = schema.fields().iter().map(|f| format!("\"{}\"", f.name())).collect();
+        let placeholders: Vec<&str> = vec!["?"; names.len()];
+        let insert = format!("INSERT INTO entries ({}) VALUES ({})", names.join(", "), placeholders.join(", "));
+        let columns = schema.fields().iter()
+            .map(|f| full.index_of(f.name()))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(Self { connection, columns, insert, rows: 0 })
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl EntrySink for SqliteSink {
+    fn write_batch(&mut self, entries: &[FileEntry]) -> Result<()> {
+        use rusqlite::types::Value;
+
+        let values = |entry: &FileEntry| -> Vec<Value> {
+            let all = [
+                Value::Text(entry.path.clone()),
+                Value::Integer(entry.size as i64),
+                Value::Integer(entry.modified_time),
+                Value::Integer(entry.accessed_time),
+                entry.created_time.map_or(Value::Null, Value::Integer),
+                Value::Text(entry.file_type.clone()),
+                Value::Integer(entry.inode as i64),
+                Value::Integer(entry.permissions as i64),
+                Value::Integer(entry.uid as i64),
+                Value::Integer(entry.gid as i64),
+                entry.owner.clone().map_or(Value::Null, Value::Text),
+                entry.group.clone().map_or(Value::Null, Value::Text),
+                Value::Text(entry.parent_path.clone()),
+                Value::Integer(entry.depth as i64),
+                Value::Text(entry.top_level_dir.clone()),
+                entry.link_target.clone().map_or(Value::Null, Value::Text),
+                Value::Integer(entry.device as i64),
+                Value::Integer(entry.nlink as i64),
+                Value::Integer(entry.raw_mode as i64),
+            ];
+            self.columns.iter().map(|&i| all[i].clone()).collect()
+        };
+
+        let transaction = self.connection.transaction()?;
+        {
+            let mut insert = transaction.prepare_cached(&self.insert)?;
+            for entry in entries {
+                insert.execute(rusqlite::params_from_iter(values(entry)))?;
+            }
+        }
+        transaction.commit()?;
+        self.rows += entries.len() as u64;
+        Ok(())
+    }
+
+    fn finalize(self: Box<Self>) -> Result<u64> {
+        self.connection.close().map_err(|(_, e)| e)?;
+        Ok(self.rows)
+    }
+}
@@ -0,0 +1,251 @@
+use crate::notify::{self, ScanNotification, ScanOutcome, WebhookOptions};
+use crate::utils::{format_bytes, format_duration, format_number};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+/// Error counts at which a completed scan's message turns yellow, then red
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorThresholds {
+    pub warning: u64,
+    pub danger: u64,
+}
+
+impl Default for ErrorThresholds {
+    fn default() -> Self {
+        Self { warning: 1, danger: 1000 }
+    }
+}
+
+/// How a message is colored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Good,
+    Warning,
+    Danger,
+}
+
+impl Severity {
+    /// Of the scan `notification` reports: red unless it completed, then by its errors
+    pub fn of(notification: &ScanNotification, thresholds: ErrorThresholds) -> Self {
+        let errors = notification.errors.total;
+        match notification.status {
+            ScanOutcome::Completed if errors < thresholds.warning => Severity::Good,
+            ScanOutcome::Completed if errors < thresholds.danger => Severity::Warning,
+            _ => Severity::Danger,
+        }
+    }
+
+    /// Attachment color; hex rather than Slack's "good"/"warning"/"danger"
+    /// names, which Mattermost doesn't know
+    pub fn color(self) -> &'static str {
+        match self {
+            Severity::Good => "#2eb886",
+            Severity::Warning => "#daa038",
+            Severity::Danger => "#a30200",
+        }
+    }
+}
+
+/// Incoming-webhook body: a one-line `text` and an attachment with the
+/// details, a shape both Slack and Mattermost render
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SlackMessage {
+    pub text: String,
+    pub attachments: Vec<Attachment>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Attachment {
+    pub color: String,
+
+    /// Plain text shown where attachments aren't
+    pub fallback: String,
+
+    pub fields: Vec<Field>,
+
+    pub footer: String,
+
+    /// Scan start time (Unix timestamp), shown next to the footer
+    pub ts: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Field {
+    pub title: String,
+    pub value: String,
+
+    /// Shown side by side with the next short field
+    pub short: bool,
+}
+
+impl SlackMessage {
+    /// The message for how `notification`'s scan ended
+    pub fn new(notification: &ScanNotification, thresholds: ErrorThresholds) -> Self {
+        let n = notification;
+        let run = if n.resumed { "Resumed scan" } else { "Scan" };
+        let text = match n.status {
+            ScanOutcome::Completed => format!(
+                "{} of {} completed: {} files, {}, {} errors in {}",
+                run, n.root, format_number(n.files), format_bytes(n.bytes),
+                format_number(n.errors.total), format_duration(n.duration_secs)
+            ),
+            ScanOutcome::Failed => format!("{} of {} failed after {}", run, n.root, format_duration(n.duration_secs)),
+            ScanOutcome::Interrupted => format!(
+                "{} of {} was interrupted after {}", run, n.root, format_duration(n.duration_secs)
+            ),
+        };
+
+        let field = |title: &str, value: String, short: bool| Field { title: title.to_string(), value, short };
+        let mut fields = vec![
+            field("Scan ID", n.scan_id.clone(), true),
+            field("Root", n.root.clone(), true),
+        ];
+        if n.status == ScanOutcome::Completed {
+            fields.extend([
+                field("Files", format_number(n.files), true),
+                field("Size", format_bytes(n.bytes), true),
+                field("Errors", format_number(n.errors.total), true),
+            ]);
+        }
+        fields.extend([
+            field("Duration", format_duration(n.duration_secs), true),
+            field("Resumed", if n.resumed { "yes" } else { "no" }.to_string(), true),
+        ]);
+        if let Some(ref error) = n.error {
+            fields.push(field("Error", error.clone(), false));
+        }
+
+        Self {
+            attachments: vec![Attachment {
+                color: Severity::of(n, thresholds).color().to_string(),
+                fallback: text.clone(),
+                fields,
+                footer: concat!("storage-scanner ", env!("CARGO_PKG_VERSION")).to_string(),
+                ts: n.started_at,
+            }],
+            text,
+        }
+    }
+}
+
+/// Post `notification` to a Slack or Mattermost incoming webhook; a message
+/// that can't be delivered is logged, never allowed to change how the scan ends
+pub fn notify_slack(options: &WebhookOptions, thresholds: ErrorThresholds, notification: &ScanNotification) {
+    let message = SlackMessage::new(notification, thresholds);
+    match notify::post_json(options, notification.event(), &message) {
+        Ok(()) => info!("Sent {} message to Slack", notification.event()),
+        Err(e) => warn!("Failed to send {} message to Slack: {:#}", notification.event(), e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ScanStats;
+
+    fn completed(errors: u64) -> ScanNotification {
+        let mut stats = ScanStats::new();
+        stats.files_scanned = 12345;
+        stats.total_size = 5_000_000_000;
+        stats.duration_secs = 3725.0;
+        for i in 0..errors {
+            stats.error_summary.record("permission_denied", &format!("/data/locked{}", i), "denied".to_string());
+        }
+        ScanNotification::completed("/data".to_string(), 1716170400, &stats, 12400)
+    }
+
+    fn field<'a>(message: &'a SlackMessage, title: &str) -> Option<&'a str> {
+        message.attachments[0].fields.iter().find(|f| f.title == title).map(|f| f.value.as_str())
+    }
+
+    #[test]
+    fn test_severity() {
+        let thresholds = ErrorThresholds { warning: 1, danger: 10 };
+        assert_eq!(Severity::of(&completed(0), thresholds), Severity::Good);
+        assert_eq!(Severity::of(&completed(1), thresholds), Severity::Warning);
+        assert_eq!(Severity::of(&completed(10), thresholds), Severity::Danger);
+        let failed = ScanNotification::failed("/data".to_string(), 0, ScanOutcome::Interrupted, "Interrupted".to_string());
+        assert_eq!(Severity::of(&failed, thresholds), Severity::Danger);
+    }
+
+    #[test]
+    fn test_completed_message() {
+        let mut notification = completed(0);
+        notification.resumed = true;
+        let message = SlackMessage::new(&notification, ErrorThresholds::default());
+        assert_eq!(message.text, "Resumed scan of /data completed: 12,345 files, 5.00 GB, 0 errors in 1h 2m");
+        assert_eq!(message.attachments[0].color, "#2eb886");
+        assert_eq!(field(&message, "Scan ID"), Some("2024-05-20T02:00:00Z"));
+        assert_eq!(field(&message, "Files"), Some("12,345"));
+        assert_eq!(field(&message, "Resumed"), Some("yes"));
+        assert_eq!(field(&message, "Error"), None);
+    }
+
+    #[test]
+    fn test_failed_message() {
+        let notification = ScanNotification::failed(
+            "/data".to_string(), 1716170400, ScanOutcome::Failed, "Scan failed: disk full".to_string(),
+        );
+        let message = SlackMessage::new(&notification, ErrorThresholds::default());
+        assert!(message.text.starts_with("Scan of /data failed after "), "{}", message.text);
+        assert_eq!(message.attachments[0].color, "#a30200");
+        assert_eq!(field(&message, "Error"), Some("Scan failed: disk full"));
+        assert_eq!(field(&message, "Files"), None);
+        assert_eq!(field(&message, "Resumed"), Some("no"));
+    }
+
+    /// Bodies posted to a local capture server, one per message
+    #[cfg(feature = "remote")]
+    fn capture(messages: &[ScanNotification], thresholds: ErrorThresholds) -> Vec<serde_json::Value> {
+        use std::time::Duration;
+
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let options = WebhookOptions {
+            url: format!("http://{}/hooks/abc", server.server_addr().to_ip().unwrap()),
+            secret: None,
+            timeout: Duration::from_secs(5),
+            retries: 0,
+        };
+        let count = messages.len();
+        let handle = std::thread::spawn(move || {
+            (0..count).map(|_| {
+                let mut request = server.recv().unwrap();
+                let mut body = String::new();
+                request.as_reader().read_to_string(&mut body).unwrap();
+                request.respond(tiny_http::Response::from_string("ok")).unwrap();
+                serde_json::from_str(&body).unwrap()
+            }).collect()
+        });
+        for notification in messages {
+            notify_slack(&options, thresholds, notification);
+        }
+        handle.join().unwrap()
+    }
+
+    #[cfg(feature = "remote")]
+    #[test]
+    fn test_payloads_posted() {
+        let failed = ScanNotification::failed(
+            "/data".to_string(), 1716170400, ScanOutcome::Failed, "Scan failed: disk full".to_string(),
+        );
+        let thresholds = ErrorThresholds { warning: 1, danger: 100 };
+        let bodies = capture(&[completed(0), completed(3), failed], thresholds);
+
+        let colors: Vec<&str> = bodies.iter().map(|b| b["attachments"][0]["color"].as_str().unwrap()).collect();
+        assert_eq!(colors, ["#2eb886", "#daa038", "#a30200"]);
+        for body in &bodies {
+            assert!(body["text"].as_str().unwrap().starts_with("Scan of /data "), "{}", body);
+            let attachment = &body["attachments"][0];
+            assert_eq!(attachment["fallback"], body["text"]);
+            assert_eq!(attachment["ts"], 1716170400);
+            assert_eq!(attachment["fields"][0]["title"], "Scan ID");
+            assert_eq!(attachment["fields"][0]["value"], "2024-05-20T02:00:00Z");
+            assert_eq!(attachment["fields"][0]["short"], true);
+        }
+        assert!(bodies[1]["text"].as_str().unwrap().contains(", 3 errors in "), "{}", bodies[1]);
+        let fields = bodies[2]["attachments"][0]["fields"].as_array().unwrap();
+        let error = fields.iter().find(|f| f["title"] == "Error").unwrap();
+        assert_eq!(error["value"], "Scan failed: disk full");
+        assert_eq!(error["short"], false);
+    }
+}
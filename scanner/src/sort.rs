@@ -0,0 +1,383 @@
+use crate::writer::schema_version_entry;
+use anyhow::{Context, Result};
+use arrow::array::{new_empty_array, Array, ArrayRef};
+use arrow::compute::{concat_batches, interleave, lexsort_to_indices, take, SortColumn};
+use arrow::datatypes::SchemaRef;
+use arrow::record_batch::RecordBatch;
+use arrow::row::{OwnedRow, RowConverter, Rows, SortField};
+use parquet::arrow::arrow_reader::{ParquetRecordBatchReader, ParquetRecordBatchReaderBuilder};
+use parquet::arrow::ArrowWriter;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// Default memory budget for sorting
+pub const DEFAULT_SORT_MEMORY: u64 = 1024 * 1024 * 1024;
+
+/// Rows per output batch when merging runs
+const MERGE_OUTPUT_ROWS: usize = 65_536;
+
+/// External merge sort of record batches by one or more columns.
+///
+/// Batches are buffered until the memory budget is reached, then sorted and
+/// spilled as a run; `finish` k-way merges the runs. Output that fits in the
+/// budget is sorted in memory without spilling.
+pub struct ExternalSorter {
+    spill_dir: PathBuf,
+    schema: SchemaRef,
+    key_columns: Vec<usize>,
+    memory_budget: u64,
+    buffered: Vec<RecordBatch>,
+    buffered_bytes: u64,
+    buffered_rows: u64,
+    runs: Vec<PathBuf>,
+
+    /// Average in-memory bytes per row seen so far, for sizing merge reads
+    bytes_per_row: f64,
+}
+
+impl ExternalSorter {
+    /// Create a sorter spilling into `spill_dir`, which must not exist yet
+    pub fn new(spill_dir: &Path, schema: SchemaRef, sort_by: &[String], memory_budget: u64) -> Result<Self> {
+        if sort_by.is_empty() {
+            anyhow::bail!("No sort columns given");
+        }
+        let key_columns = sort_by.iter()
+            .map(|name| schema.index_of(name)
+                .map_err(|_| anyhow::anyhow!("Cannot sort by '{}': no such column", name)))
+            .collect::<Result<Vec<usize>>>()?;
+
+        Ok(Self {
+            spill_dir: spill_dir.to_path_buf(),
+            schema,
+            key_columns,
+            memory_budget: memory_budget.max(1),
+            buffered: Vec::new(),
+            buffered_bytes: 0,
+            buffered_rows: 0,
+            runs: Vec::new(),
+            bytes_per_row: 0.0,
+        })
+    }
+
+    /// Add a batch, spilling a sorted run when the budget is exceeded
+    pub fn push(&mut self, batch: RecordBatch) -> Result<()> {
+        if batch.num_rows() == 0 {
+            return Ok(());
+        }
+
+        self.buffered_bytes += batch.get_array_memory_size() as u64;
+        self.buffered_rows += batch.num_rows() as u64;
+        self.buffered.push(batch);
+
+        if self.buffered_bytes >= self.memory_budget {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    fn sort_buffered(&mut self) -> Result<Option<RecordBatch>> {
+        if self.buffered.is_empty() {
+            return Ok(None);
+        }
+
+        self.bytes_per_row = self.buffered_bytes as f64 / self.buffered_rows as f64;
+
+        let batch = concat_batches(&self.schema, &self.buffered)?;
+        self.buffered.clear();
+        self.buffered_bytes = 0;
+        self.buffered_rows = 0;
+
+        let sort_columns: Vec<SortColumn> = self.key_columns.iter()
+            .map(|&i| SortColumn { values: batch.column(i).clone(), options: None })
+            .collect();
+        let indices = lexsort_to_indices(&sort_columns, None)?;
+
+        let columns = batch.columns().iter()
+            .map(|c| Ok(take(c.as_ref(), &indices, None)?))
+            .collect::<Result<Vec<ArrayRef>>>()?;
+        Ok(Some(RecordBatch::try_new(self.schema.clone(), columns)?))
+    }
+
+    fn spill(&mut self) -> Result<()> {
+        let sorted = match self.sort_buffered()? {
+            Some(sorted) => sorted,
+            None => return Ok(()),
+        };
+
+        if self.runs.is_empty() {
+            fs::create_dir(&self.spill_dir)
+                .with_context(|| format!("Failed to create spill directory {}", self.spill_dir.display()))?;
+        }
+
+        let path = self.spill_dir.join(format!("run_{:04}.parquet", self.runs.len()));
+        let mut writer = ArrowWriter::try_new(File::create(&path)?, self.schema.clone(), None)?;
+        writer.append_key_value_metadata(schema_version_entry());
+        writer.write(&sorted)?;
+        writer.close()?;
+
+        info!("Spilled sorted run {} ({} rows)", self.runs.len(), sorted.num_rows());
+        self.runs.push(path);
+        Ok(())
+    }
+
+    /// Emit every row in sorted order.
+    ///
+    /// Returns the number of runs merged (0 if everything fit in memory).
+    pub fn finish(mut self, mut emit: impl FnMut(RecordBatch) -> Result<()>) -> Result<usize> {
+        if self.runs.is_empty() {
+            if let Some(sorted) = self.sort_buffered()? {
+                emit(sorted)?;
+            }
+            return Ok(0);
+        }
+
+        self.spill()?;
+        self.merge_runs(&mut emit)?;
+        Ok(self.runs.len())
+    }
+
+    fn merge_runs(&mut self, emit: &mut impl FnMut(RecordBatch) -> Result<()>) -> Result<()> {
+        // Each run holds one batch in memory at a time
+        let per_run_bytes = self.memory_budget / (self.runs.len() as u64 + 1);
+        let read_rows = ((per_run_bytes as f64 / self.bytes_per_row.max(1.0)) as usize).clamp(64, MERGE_OUTPUT_ROWS);
+
+        let fields = self.key_columns.iter()
+            .map(|&i| SortField::new(self.schema.field(i).data_type().clone()))
+            .collect();
+        let mut converter = RowConverter::new(fields)?;
+
+        let mut cursors = Vec::with_capacity(self.runs.len());
+        for path in &self.runs {
+            let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?
+                .with_batch_size(read_rows)
+                .build()?;
+            cursors.push(RunCursor::new(reader, &mut converter, &self.key_columns)?);
+        }
+
+        let mut heap = BinaryHeap::new();
+        for (run, cursor) in cursors.iter().enumerate() {
+            if let Some(row) = cursor.current_row() {
+                heap.push(Reverse((row, run)));
+            }
+        }
+
+        // Pending output rows as (run, row within the run's current batch)
+        let mut pending: Vec<(usize, usize)> = Vec::with_capacity(MERGE_OUTPUT_ROWS);
+
+        while let Some(Reverse((_, run))) = heap.pop() {
+            pending.push((run, cursors[run].position));
+
+            if cursors[run].position + 1 >= cursors[run].len() {
+                // The run's batch is about to be replaced; flush rows that reference it
+                flush(&self.schema, &cursors, &mut pending, emit)?;
+            }
+            cursors[run].advance(&mut converter, &self.key_columns)?;
+            if let Some(row) = cursors[run].current_row() {
+                heap.push(Reverse((row, run)));
+            }
+
+            if pending.len() >= MERGE_OUTPUT_ROWS {
+                flush(&self.schema, &cursors, &mut pending, emit)?;
+            }
+        }
+
+        flush(&self.schema, &cursors, &mut pending, emit)
+    }
+}
+
+impl Drop for ExternalSorter {
+    fn drop(&mut self) {
+        if !self.runs.is_empty() {
+            if let Err(e) = fs::remove_dir_all(&self.spill_dir) {
+                warn!("Failed to remove spill directory {}: {}", self.spill_dir.display(), e);
+            }
+        }
+    }
+}
+
+/// Read position within one sorted run
+struct RunCursor {
+    reader: ParquetRecordBatchReader,
+    batch: Option<RecordBatch>,
+    rows: Option<Rows>,
+    position: usize,
+}
+
+impl RunCursor {
+    fn new(reader: ParquetRecordBatchReader, converter: &mut RowConverter, key_columns: &[usize]) -> Result<Self> {
+        let mut cursor = Self { reader, batch: None, rows: None, position: 0 };
+        cursor.load(converter, key_columns)?;
+        Ok(cursor)
+    }
+
+    fn load(&mut self, converter: &mut RowConverter, key_columns: &[usize]) -> Result<()> {
+        self.position = 0;
+        match self.reader.next().transpose()? {
+            Some(batch) => {
+                let keys: Vec<ArrayRef> = key_columns.iter().map(|&i| batch.column(i).clone()).collect();
+                self.rows = Some(converter.convert_columns(&keys)?);
+                self.batch = Some(batch);
+            }
+            None => {
+                self.rows = None;
+                self.batch = None;
+            }
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.batch.as_ref().map(|b| b.num_rows()).unwrap_or(0)
+    }
+
+    fn current_row(&self) -> Option<OwnedRow> {
+        self.rows.as_ref().map(|rows| rows.row(self.position).owned())
+    }
+
+    fn advance(&mut self, converter: &mut RowConverter, key_columns: &[usize]) -> Result<()> {
+        self.position += 1;
+        if self.position >= self.len() {
+            self.load(converter, key_columns)?;
+        }
+        Ok(())
+    }
+}
+
+/// Assemble pending rows from the runs' current batches and emit them
+fn flush(
+    schema: &SchemaRef,
+    cursors: &[RunCursor],
+    pending: &mut Vec<(usize, usize)>,
+    emit: &mut impl FnMut(RecordBatch) -> Result<()>,
+) -> Result<()> {
+    if pending.is_empty() {
+        return Ok(());
+    }
+
+    let columns = schema.fields().iter().enumerate()
+        .map(|(col, field)| {
+            // Exhausted runs are never referenced but still need a slot
+            let arrays: Vec<ArrayRef> = cursors.iter()
+                .map(|c| match c.batch {
+                    Some(ref batch) => batch.column(col).clone(),
+                    None => new_empty_array(field.data_type()),
+                })
+                .collect();
+            let arrays: Vec<&dyn Array> = arrays.iter().map(|a| a.as_ref()).collect();
+            Ok(interleave(&arrays, pending)?)
+        })
+        .collect::<Result<Vec<ArrayRef>>>()?;
+
+    pending.clear();
+    emit(RecordBatch::try_new(schema.clone(), columns)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::{StringArray, UInt64Array};
+    use arrow::datatypes::{DataType, Field, Schema};
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new("top_level_dir", DataType::Utf8, false),
+            Field::new("path", DataType::Utf8, false),
+            Field::new("size", DataType::UInt64, false),
+        ]))
+    }
+
+    /// Rows of (top_level_dir, path, size)
+    fn batch(rows: &[(String, String, u64)]) -> RecordBatch {
+        RecordBatch::try_new(schema(), vec![
+            Arc::new(StringArray::from(rows.iter().map(|r| r.0.as_str()).collect::<Vec<_>>())),
+            Arc::new(StringArray::from(rows.iter().map(|r| r.1.as_str()).collect::<Vec<_>>())),
+            Arc::new(UInt64Array::from(rows.iter().map(|r| r.2).collect::<Vec<_>>())),
+        ]).unwrap()
+    }
+
+    /// Deterministically shuffled rows spread over several top-level dirs
+    fn rows(count: u64) -> Vec<(String, String, u64)> {
+        (0..count)
+            .map(|i| (i * 7919) % count)
+            .map(|n| (format!("dir{}", n % 5), format!("/data/file{:05}", n), n))
+            .collect()
+    }
+
+    /// Sort the rows in batches of 100; returns the output rows and the run count
+    fn sort(rows: &[(String, String, u64)], sort_by: &[&str], budget: u64) -> (Vec<(String, String, u64)>, usize) {
+        let temp_dir = TempDir::new().unwrap();
+        let spill_dir = temp_dir.path().join("spill");
+        let sort_by: Vec<String> = sort_by.iter().map(|s| s.to_string()).collect();
+        let mut sorter = ExternalSorter::new(&spill_dir, schema(), &sort_by, budget).unwrap();
+        for chunk in rows.chunks(100) {
+            sorter.push(batch(chunk)).unwrap();
+        }
+
+        let mut output = Vec::new();
+        let runs = sorter.finish(|b| {
+            assert_eq!(b.schema(), schema());
+            let dirs = b.column(0).as_any().downcast_ref::<StringArray>().unwrap();
+            let paths = b.column(1).as_any().downcast_ref::<StringArray>().unwrap();
+            let sizes = b.column(2).as_any().downcast_ref::<UInt64Array>().unwrap();
+            for i in 0..b.num_rows() {
+                output.push((dirs.value(i).to_string(), paths.value(i).to_string(), sizes.value(i)));
+            }
+            Ok(())
+        }).unwrap();
+
+        assert!(!spill_dir.exists());
+        (output, runs)
+    }
+
+    #[test]
+    fn test_in_memory_sort() {
+        let input = rows(1000);
+        let (output, runs) = sort(&input, &["path"], DEFAULT_SORT_MEMORY);
+        assert_eq!(runs, 0);
+
+        let mut expected = input.clone();
+        expected.sort_by(|a, b| a.1.cmp(&b.1));
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn test_spilled_runs_are_merged_in_order() {
+        let input = rows(5000);
+        let (output, runs) = sort(&input, &["path"], 16 * 1024);
+        assert!(runs > 2, "expected several runs, got {}", runs);
+
+        assert!(output.windows(2).all(|w| w[0].1 <= w[1].1), "output is not sorted by path");
+
+        // Same multiset of rows
+        let mut expected = input.clone();
+        expected.sort();
+        let mut actual = output.clone();
+        actual.sort();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_multiple_sort_columns() {
+        let input = rows(3000);
+        let (output, runs) = sort(&input, &["top_level_dir", "path"], 16 * 1024);
+        assert!(runs > 1);
+
+        let mut expected = input.clone();
+        expected.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn test_unknown_sort_column() {
+        let temp_dir = TempDir::new().unwrap();
+        let spill_dir = temp_dir.path().join("spill");
+        let err = ExternalSorter::new(&spill_dir, schema(), &["owner".to_string()], 1024).err().unwrap();
+        assert!(err.to_string().contains("owner"));
+        assert!(ExternalSorter::new(&spill_dir, schema(), &[], 1024).is_err());
+    }
+}
@@ -0,0 +1,269 @@
+use crate::manifest::{ChunkMetadata, ChunkUpload, ScanManifest};
+use crate::rotating_writer::ChunkHook;
+use anyhow::{Context, Result};
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use std::path::Path;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+/// Destination that takes whole files by name, such as an S3 prefix
+pub trait ObjectStore: Send + Sync {
+    /// Where an object of this name ends up, for the manifest and messages
+    fn url(&self, name: &str) -> String;
+
+    /// Store the file at `path` as `name`, replacing any object of that name
+    fn put_file(&self, name: &str, path: &Path) -> Result<()>;
+}
+
+/// How persistently spooled chunks are uploaded
+#[derive(Debug, Clone, Copy)]
+pub struct SpoolOptions {
+    /// Further attempts after a failed one
+    pub retries: u32,
+
+    /// Wait before the first retry, doubled before each later one
+    pub backoff: Duration,
+}
+
+impl Default for SpoolOptions {
+    fn default() -> Self {
+        Self { retries: 5, backoff: Duration::from_secs(1) }
+    }
+}
+
+/// Name a chunk is stored under: its file name in the spool
+fn object_name(path: &str) -> Result<&str> {
+    Path::new(path).file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| anyhow::anyhow!("Invalid chunk path '{}'", path))
+}
+
+/// Upload one spooled chunk, then remove the local copy.
+///
+/// A chunk moves from spooled to uploaded, with its local file deleted, or
+/// to failed, with its local file kept for another attempt.
+fn upload_spooled(store: &dyn ObjectStore, options: &SpoolOptions, chunk: &ChunkMetadata) -> ChunkUpload {
+    let at = || SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+    let name = match object_name(&chunk.file_path) {
+        Ok(name) => name,
+        Err(e) => {
+            let url = store.url("");
+            return ChunkUpload::Failed { url, at: at(), attempts: 0, error: format!("{:#}", e) };
+        }
+    };
+    let url = store.url(name);
+
+    let mut attempts = 1;
+    let mut backoff = options.backoff;
+    let mut result = store.put_file(name, Path::new(&chunk.file_path));
+    while let Err(ref e) = result {
+        if attempts > options.retries {
+            break;
+        }
+        warn!("Upload of chunk {} failed, retrying in {:?}: {:#}", chunk.chunk_number, backoff, e);
+        std::thread::sleep(backoff);
+        backoff *= 2;
+        attempts += 1;
+        result = store.put_file(name, Path::new(&chunk.file_path));
+    }
+
+    match result {
+        Ok(()) => {
+            info!("Uploaded chunk {} to {}", chunk.chunk_number, url);
+            if let Err(e) = std::fs::remove_file(&chunk.file_path) {
+                warn!("Failed to remove spooled chunk {}: {}", chunk.file_path, e);
+            }
+            ChunkUpload::Uploaded { url, at: at(), attempts }
+        }
+        Err(e) => {
+            warn!("Giving up on uploading chunk {} after {} attempts; it stays in the spool: {:#}",
+                  chunk.chunk_number, attempts, e);
+            ChunkUpload::Failed { url, at: at(), attempts, error: format!("{:#}", e) }
+        }
+    }
+}
+
+/// Moves chunks from a local spool directory to an object store as the
+/// rotating writer closes them.
+///
+/// Uploads run on a background thread and finalize waits for them. Each
+/// chunk's outcome is recorded in the manifest, which keeps listing the
+/// spool path; chunks that failed to upload are still there.
+pub struct SpoolUploader {
+    chunks: Option<Sender<ChunkMetadata>>,
+    outcomes: Receiver<(usize, ChunkUpload)>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl SpoolUploader {
+    pub fn new(store: Arc<dyn ObjectStore>, options: SpoolOptions) -> Result<Self> {
+        let (chunks, queue) = unbounded::<ChunkMetadata>();
+        let (sender, outcomes) = unbounded();
+        let worker = std::thread::Builder::new()
+            .name("spool-uploader".to_string())
+            .spawn(move || {
+                for chunk in queue {
+                    let upload = upload_spooled(store.as_ref(), &options, &chunk);
+                    if sender.send((chunk.chunk_number, upload)).is_err() {
+                        break;
+                    }
+                }
+            })
+            .context("Failed to start upload thread")?;
+
+        Ok(Self { chunks: Some(chunks), outcomes, worker: Some(worker) })
+    }
+}
+
+impl ChunkHook for SpoolUploader {
+    fn chunk_closed(&mut self, chunk: &ChunkMetadata) -> Result<()> {
+        self.chunks.as_ref()
+            .context("Uploader already finished")?
+            .send(chunk.clone())
+            .map_err(|_| anyhow::anyhow!("Upload thread stopped"))
+    }
+
+    fn record(&mut self, manifest: &mut ScanManifest) {
+        for (chunk_number, upload) in self.outcomes.try_iter() {
+            if let Some(chunk) = manifest.chunks.iter_mut().find(|c| c.chunk_number == chunk_number) {
+                chunk.upload = Some(upload);
+            }
+        }
+    }
+
+    fn finish(&mut self, manifest: &mut ScanManifest) -> Result<()> {
+        let pending = self.chunks.as_ref().map_or(0, |c| c.len());
+        if pending > 0 {
+            info!("Waiting for {} chunk upload(s) to finish", pending);
+        }
+        // Closing the queue ends the thread once it is drained
+        self.chunks = None;
+        if let Some(worker) = self.worker.take() {
+            worker.join().map_err(|_| anyhow::anyhow!("Upload thread panicked"))?;
+        }
+        self.record(manifest);
+        Ok(())
+    }
+}
+
+/// Upload the finalized manifest at `manifest_path` next to its chunks,
+/// keeping the local copy. Returns where it was stored.
+pub fn upload_manifest(store: &dyn ObjectStore, manifest_path: &Path) -> Result<String> {
+    let name = object_name(&manifest_path.to_string_lossy())?.to_string();
+    store.put_file(&name, manifest_path)
+        .with_context(|| format!("Failed to upload manifest {}", manifest_path.display()))?;
+    Ok(store.url(&name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::FileEntry;
+    use crate::rotating_writer::{RotatingParquetWriter, RotatingWriterConfig};
+    use std::collections::BTreeMap;
+    use std::sync::Mutex;
+    use tempfile::TempDir;
+
+    /// Keeps objects in memory, failing the first `failures` puts of each name
+    #[derive(Default)]
+    struct MockStore {
+        failures: u32,
+        attempts: Mutex<BTreeMap<String, u32>>,
+        objects: Mutex<BTreeMap<String, Vec<u8>>>,
+    }
+
+    impl ObjectStore for MockStore {
+        fn url(&self, name: &str) -> String {
+            format!("mock://bucket/{}", name)
+        }
+
+        fn put_file(&self, name: &str, path: &Path) -> Result<()> {
+            let mut attempts = self.attempts.lock().unwrap();
+            let attempt = attempts.entry(name.to_string()).or_default();
+            *attempt += 1;
+            if *attempt <= self.failures {
+                anyhow::bail!("injected failure {} for {}", attempt, name);
+            }
+            self.objects.lock().unwrap().insert(name.to_string(), std::fs::read(path)?);
+            Ok(())
+        }
+    }
+
+    fn entry(i: usize) -> FileEntry {
+        FileEntry { inode: i as u64, ..FileEntry::fixture(&format!("/test/file_{}.txt", i)) }
+    }
+
+    /// Write `rows` rows as chunks of 3 into the spool `dir`, uploading to `store`
+    fn write_spooled_scan(dir: &Path, rows: usize, store: Arc<MockStore>, retries: u32) -> ScanManifest {
+        let config = RotatingWriterConfig {
+            base_output_path: dir.join("scan.parquet"),
+            rows_per_chunk: 3,
+            time_interval: Duration::from_secs(3600),
+        };
+        let options = SpoolOptions { retries, backoff: Duration::from_millis(1) };
+        let mut writer = RotatingParquetWriter::new(config, "/test".to_string()).unwrap()
+            .with_chunk_hook(Box::new(SpoolUploader::new(store, options).unwrap()));
+        for i in 0..rows {
+            writer.write_batch(&[entry(i)]).unwrap();
+        }
+        writer.finalize().unwrap()
+    }
+
+    #[test]
+    fn test_uploaded_chunks_leave_the_spool() {
+        let spool = TempDir::new().unwrap();
+        let store = Arc::new(MockStore { failures: 1, ..Default::default() });
+        let manifest = write_spooled_scan(spool.path(), 7, store.clone(), 2);
+
+        let objects = store.objects.lock().unwrap();
+        let names: Vec<&str> = objects.keys().map(String::as_str).collect();
+        assert_eq!(names, ["scan_chunk_0001.parquet", "scan_chunk_0002.parquet", "scan_chunk_0003.parquet"]);
+        for chunk in &manifest.chunks {
+            assert!(!Path::new(&chunk.file_path).exists(), "{} still spooled", chunk.file_path);
+            let name = object_name(&chunk.file_path).unwrap();
+            match chunk.upload {
+                Some(ChunkUpload::Uploaded { ref url, attempts, .. }) => {
+                    assert_eq!(*url, format!("mock://bucket/{}", name));
+                    assert_eq!(attempts, 2);
+                }
+                ref other => panic!("chunk {} not uploaded: {:?}", chunk.chunk_number, other),
+            }
+            assert!(objects[name].starts_with(b"PAR1"));
+        }
+
+        drop(objects);
+
+        // The manifest goes last, in one attempt, and stays local too
+        store.attempts.lock().unwrap().insert("scan_manifest.json".to_string(), 1);
+        let manifest_path = spool.path().join("scan_manifest.json");
+        let url = upload_manifest(store.as_ref(), &manifest_path).unwrap();
+        assert_eq!(url, "mock://bucket/scan_manifest.json");
+        assert!(manifest_path.exists());
+        let uploaded: ScanManifest = serde_json::from_slice(&store.objects.lock().unwrap()["scan_manifest.json"]).unwrap();
+        assert!(uploaded.completed);
+        assert!(uploaded.chunks.iter().all(|c| c.is_uploaded()));
+    }
+
+    #[test]
+    fn test_failed_chunks_stay_in_the_spool() {
+        let spool = TempDir::new().unwrap();
+        let store = Arc::new(MockStore { failures: 3, ..Default::default() });
+        let manifest = write_spooled_scan(spool.path(), 4, store.clone(), 1);
+
+        assert!(store.objects.lock().unwrap().is_empty());
+        for chunk in &manifest.chunks {
+            assert!(Path::new(&chunk.file_path).exists());
+            match chunk.upload {
+                Some(ChunkUpload::Failed { attempts, ref error, .. }) => {
+                    assert_eq!(attempts, 2);
+                    assert!(error.contains("injected failure 2"), "{}", error);
+                }
+                ref other => panic!("chunk {} not failed: {:?}", chunk.chunk_number, other),
+            }
+        }
+        let saved = ScanManifest::load_from_file(spool.path().join("scan_manifest.json")).unwrap();
+        assert!(saved.chunks.iter().all(|c| matches!(c.upload, Some(ChunkUpload::Failed { .. }))));
+    }
+}
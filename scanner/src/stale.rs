@@ -0,0 +1,342 @@
+use crate::filter::{RowFilter, TypeFilter};
+use crate::query::{self, QueryFormat};
+use crate::report::read_columns;
+use anyhow::{Context, Result};
+use arrow::array::{Array, Int64Array, StringArray, UInt64Array};
+use arrow::compute::cast;
+use arrow::datatypes::{DataType, Schema};
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// Group label of files whose group column is null
+const UNKNOWN_GROUP: &str = "(unknown)";
+
+/// Column stale files are totalled by
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StaleGroupBy {
+    /// Owning user id
+    #[default]
+    Uid,
+
+    TopLevelDir,
+}
+
+impl StaleGroupBy {
+    pub fn column(&self) -> &'static str {
+        match self {
+            StaleGroupBy::Uid => "uid",
+            StaleGroupBy::TopLevelDir => "top_level_dir",
+        }
+    }
+}
+
+/// Which files count as stale, and how they are totalled
+#[derive(Debug, Clone)]
+pub struct StaleOptions {
+    /// Files last accessed before this Unix timestamp are stale
+    pub accessed_before: i64,
+
+    /// Ignore files smaller than this
+    pub min_size: u64,
+
+    pub group_by: StaleGroupBy,
+}
+
+impl StaleOptions {
+    fn filter(&self) -> RowFilter {
+        RowFilter {
+            file_type: Some(TypeFilter::File),
+            min_size: Some(self.min_size),
+            accessed_before: Some(self.accessed_before),
+            ..Default::default()
+        }
+    }
+
+    /// Columns read when only totals are needed
+    fn columns(&self) -> Vec<&'static str> {
+        let mut columns = vec!["file_type", "size", "accessed_time", self.group_by.column()];
+        if self.group_by == StaleGroupBy::Uid {
+            columns.push("owner");
+        }
+        columns
+    }
+
+    fn validate(&self, schema: &Schema) -> Result<()> {
+        for column in ["file_type", "size", "accessed_time", self.group_by.column()] {
+            schema.field_with_name(column)
+                .with_context(|| format!("Finding stale files requires a '{}' column", column))?;
+        }
+        Ok(())
+    }
+}
+
+/// Stale files of one group
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StaleGroup {
+    /// Value of the group column, e.g. a uid or a top-level directory
+    pub group: String,
+
+    /// User name of the uid, when grouping by uid and the scan resolved one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+
+    pub files: u64,
+
+    pub bytes: u64,
+
+    /// Earliest `accessed_time` in the group
+    pub oldest_access: i64,
+}
+
+/// Stale files found by [`stale_files`], by group
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StaleReport {
+    /// Files last accessed before this Unix timestamp are stale
+    pub accessed_before: i64,
+
+    pub min_size: u64,
+
+    pub group_by: StaleGroupBy,
+
+    /// Largest total first, then by group
+    pub groups: Vec<StaleGroup>,
+
+    pub files: u64,
+
+    pub bytes: u64,
+}
+
+/// Per-group totals of stale files
+#[derive(Default)]
+struct Totals(HashMap<String, StaleGroup>);
+
+impl Totals {
+    /// Add a batch already restricted to stale files
+    fn add_batch(&mut self, batch: &RecordBatch, group_by: StaleGroupBy) -> Result<()> {
+        let column = |name: &str, data_type: &DataType| {
+            let column = batch.column_by_name(name)
+                .with_context(|| format!("Finding stale files requires a '{}' column", name))?;
+            Ok::<_, anyhow::Error>(cast(column, data_type)?)
+        };
+        let sizes = column("size", &DataType::UInt64)?;
+        let sizes = sizes.as_any().downcast_ref::<UInt64Array>().unwrap();
+        let accessed = column("accessed_time", &DataType::Int64)?;
+        let accessed = accessed.as_any().downcast_ref::<Int64Array>().unwrap();
+        let keys = column(group_by.column(), &DataType::Utf8)?;
+        let keys = keys.as_any().downcast_ref::<StringArray>().unwrap();
+        let owners = match batch.column_by_name("owner") {
+            Some(owners) if group_by == StaleGroupBy::Uid => Some(cast(owners, &DataType::Utf8)?),
+            _ => None,
+        };
+        let owners = owners.as_ref().map(|o| o.as_any().downcast_ref::<StringArray>().unwrap());
+
+        for i in 0..batch.num_rows() {
+            let key = if keys.is_valid(i) { keys.value(i) } else { UNKNOWN_GROUP };
+            let group = match self.0.get_mut(key) {
+                Some(group) => group,
+                None => self.0.entry(key.to_string()).or_insert_with(|| StaleGroup {
+                    group: key.to_string(),
+                    owner: None,
+                    files: 0,
+                    bytes: 0,
+                    oldest_access: i64::MAX,
+                }),
+            };
+            group.files += 1;
+            group.bytes += sizes.value(i);
+            group.oldest_access = group.oldest_access.min(accessed.value(i));
+            if group.owner.is_none() {
+                group.owner = owners.filter(|o| o.is_valid(i)).map(|o| o.value(i).to_string());
+            }
+        }
+        Ok(())
+    }
+
+    fn merge(mut self, other: Totals) -> Totals {
+        for (key, theirs) in other.0 {
+            match self.0.get_mut(&key) {
+                Some(ours) => {
+                    ours.files += theirs.files;
+                    ours.bytes += theirs.bytes;
+                    ours.oldest_access = ours.oldest_access.min(theirs.oldest_access);
+                    if ours.owner.is_none() {
+                        ours.owner = theirs.owner;
+                    }
+                }
+                None => {
+                    self.0.insert(key, theirs);
+                }
+            }
+        }
+        self
+    }
+
+    fn into_report(self, options: &StaleOptions) -> StaleReport {
+        let mut groups: Vec<StaleGroup> = self.0.into_values().collect();
+        groups.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.group.cmp(&b.group)));
+        StaleReport {
+            accessed_before: options.accessed_before,
+            min_size: options.min_size,
+            group_by: options.group_by,
+            files: groups.iter().map(|g| g.files).sum(),
+            bytes: groups.iter().map(|g| g.bytes).sum(),
+            groups,
+        }
+    }
+}
+
+fn chunk_totals(path: &Path, options: &StaleOptions, filter: &RowFilter) -> Result<Totals> {
+    // The owner column only labels groups, so chunks without it are still read
+    let schema = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?.schema().clone();
+    let columns: Vec<&str> = options.columns().into_iter()
+        .filter(|c| *c != "owner" || schema.field_with_name(c).is_ok())
+        .collect();
+
+    let mut totals = Totals::default();
+    for batch in read_columns(path, &columns, |schema| options.validate(schema))? {
+        totals.add_batch(&filter.apply(&batch?)?, options.group_by)?;
+    }
+    Ok(totals)
+}
+
+/// Total the files matching the stale policy by group. Chunks are read in
+/// parallel and only the columns the policy needs are read.
+pub fn stale_files(chunk_files: &[PathBuf], options: &StaleOptions) -> Result<StaleReport> {
+    let filter = options.filter();
+    let totals = chunk_files.par_iter()
+        .map(|path| chunk_totals(path, options, &filter)
+            .with_context(|| format!("Failed to read {}", path.display())))
+        .try_reduce(Totals::default, |a, b| Ok(a.merge(b)))?;
+    Ok(totals.into_report(options))
+}
+
+/// Like [`stale_files`], also writing every stale file, with all its columns,
+/// to `output`
+pub fn write_stale_files(
+    chunk_files: &[PathBuf],
+    options: &StaleOptions,
+    output: &Path,
+    format: QueryFormat,
+) -> Result<StaleReport> {
+    let filter = options.filter();
+    let mut totals = Totals::default();
+    query::write_matching(
+        chunk_files,
+        output,
+        format,
+        |schema| options.validate(schema),
+        |batch| filter.mask(batch),
+        |matched| totals.add_batch(matched, options.group_by),
+    )?;
+    Ok(totals.into_report(options))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::FileEntry;
+    use crate::writer::ParquetFileWriter;
+    use tempfile::TempDir;
+
+    const AS_OF: i64 = 1_700_000_000;
+    const DAY: i64 = 86_400;
+    const MB: u64 = 1_000_000;
+
+    fn entry(path: &str, size: u64, accessed_days_ago: i64, uid: u32) -> FileEntry {
+        let entry = if size == 0 { FileEntry::dir_fixture(path) } else { FileEntry::fixture(path) };
+        FileEntry {
+            size,
+            modified_time: AS_OF - 1000 * DAY,
+            accessed_time: AS_OF - accessed_days_ago * DAY,
+            uid,
+            gid: 100,
+            owner: (uid == 1000).then(|| "alice".to_string()),
+            ..entry
+        }
+    }
+
+    /// Two chunks; the cut-off is 365 days before AS_OF
+    fn fixture(dir: &Path) -> Vec<PathBuf> {
+        let chunks = [
+            vec![
+                entry("/project/labA/old_big.dat", 500 * MB, 400, 1000),
+                // Accessed exactly at the cut-off is not stale
+                entry("/project/labA/boundary.dat", 500 * MB, 365, 1000),
+                entry("/project/labA/old_small.dat", 100 * MB - 1, 400, 1000),
+                // Exactly the minimum size counts
+                entry("/project/labB/exact_min.dat", 100 * MB, 366, 2000),
+            ],
+            vec![
+                entry("/project/labB/recent.dat", 900 * MB, 10, 2000),
+                entry("/project/labB/ancient.dat", 300 * MB, 2000, 1000),
+                entry("/project/labB/old_dir", 0, 900, 1000),
+                entry("/project/labC/old.dat", 200 * MB, 700, 3000),
+            ],
+        ];
+        chunks.iter().enumerate().map(|(i, entries)| {
+            let path = dir.join(format!("scan_chunk_{:04}.parquet", i + 1));
+            let mut writer = ParquetFileWriter::new(&path).unwrap();
+            writer.write_batch(entries).unwrap();
+            writer.close().unwrap();
+            path
+        }).collect()
+    }
+
+    fn options(group_by: StaleGroupBy) -> StaleOptions {
+        StaleOptions { accessed_before: AS_OF - 365 * DAY, min_size: 100 * MB, group_by }
+    }
+
+    fn totals(report: &StaleReport) -> Vec<(&str, u64, u64)> {
+        report.groups.iter().map(|g| (g.group.as_str(), g.files, g.bytes)).collect()
+    }
+
+    #[test]
+    fn test_policy_boundaries_by_uid() {
+        let temp_dir = TempDir::new().unwrap();
+        let chunks = fixture(temp_dir.path());
+
+        let report = stale_files(&chunks, &options(StaleGroupBy::Uid)).unwrap();
+        assert_eq!(totals(&report), [
+            ("1000", 2, 800 * MB),
+            ("3000", 1, 200 * MB),
+            ("2000", 1, 100 * MB),
+        ]);
+        assert_eq!(report.groups[0].owner.as_deref(), Some("alice"));
+        assert_eq!(report.groups[0].oldest_access, AS_OF - 2000 * DAY);
+        assert_eq!(report.groups[1].owner, None);
+        assert_eq!((report.files, report.bytes), (4, 1100 * MB));
+    }
+
+    #[test]
+    fn test_group_by_directory_and_file_list() {
+        let temp_dir = TempDir::new().unwrap();
+        let chunks = fixture(temp_dir.path());
+
+        let options = options(StaleGroupBy::TopLevelDir);
+        let expected = stale_files(&chunks, &options).unwrap();
+        assert_eq!(totals(&expected), [
+            ("labA", 1, 500 * MB),
+            ("labB", 2, 400 * MB),
+            ("labC", 1, 200 * MB),
+        ]);
+
+        let output = temp_dir.path().join("stale.csv");
+        let report = write_stale_files(&chunks, &options, &output, QueryFormat::Csv).unwrap();
+        assert_eq!(report, expected);
+
+        let csv = std::fs::read_to_string(&output).unwrap();
+        let paths: Vec<&str> = csv.lines().skip(1).map(|l| l.split(',').next().unwrap()).collect();
+        assert_eq!(paths, [
+            "/project/labA/old_big.dat",
+            "/project/labB/exact_min.dat",
+            "/project/labB/ancient.dat",
+            "/project/labC/old.dat",
+        ]);
+    }
+}
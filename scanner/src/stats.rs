@@ -0,0 +1,406 @@
+use crate::models::FileEntry;
+use anyhow::{Context, Result};
+use arrow::array::{Array, Int64Array, StringArray, UInt64Array};
+use arrow::compute::cast;
+use arrow::datatypes::{DataType, Schema};
+use arrow::record_batch::RecordBatch;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// `file_type` of directory entries
+const DIRECTORY_TYPE: &str = "directory";
+
+/// Group name for rows whose grouping column is null
+const NULL_GROUP: &str = "(null)";
+
+/// Upper bounds (exclusive) of the size histogram buckets; a final bucket
+/// holds everything from the last bound up
+pub const SIZE_BUCKET_BOUNDS: [u64; 9] = [
+    1_000,
+    10_000,
+    100_000,
+    1_000_000,
+    10_000_000,
+    100_000_000,
+    1_000_000_000,
+    10_000_000_000,
+    100_000_000_000,
+];
+
+/// Lower bounds, in days, of the age bands after the first; the first band
+/// holds everything younger than 30 days
+pub const AGE_BAND_DAYS: [u64; 6] = [30, 90, 180, 365, 730, 1825];
+
+const SECS_PER_DAY: i64 = 86_400;
+
+/// Columns read by [`UsageStats::add_batch`]
+pub const COLUMNS: [&str; 5] = ["size", "file_type", "top_level_dir", "modified_time", "accessed_time"];
+
+/// Entry count and file bytes for one group
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub struct GroupStats {
+    /// Entries, directories included
+    pub rows: u64,
+
+    /// Combined size of the files (directories count as 0)
+    pub bytes: u64,
+}
+
+/// Files whose size falls in `[min, max)`
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct SizeBucket {
+    pub min: u64,
+
+    /// None for the last, open-ended bucket
+    pub max: Option<u64>,
+
+    pub files: u64,
+
+    pub bytes: u64,
+}
+
+/// Files whose age, in days, falls in `[min_days, max_days)`
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct AgeBand {
+    pub min_days: u64,
+
+    /// None for the last, open-ended band
+    pub max_days: Option<u64>,
+
+    pub files: u64,
+
+    pub bytes: u64,
+}
+
+/// Storage usage accumulated over scanned entries.
+///
+/// Entries are counted the way the scan summary counts them: directories add
+/// to `directories`, everything else to `files` and `total_bytes`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct UsageStats {
+    pub rows: u64,
+
+    pub files: u64,
+
+    pub directories: u64,
+
+    /// Combined size of the files
+    pub total_bytes: u64,
+
+    pub by_file_type: BTreeMap<String, GroupStats>,
+
+    pub by_top_level_dir: BTreeMap<String, GroupStats>,
+
+    /// File sizes, one bucket per [`SIZE_BUCKET_BOUNDS`] entry plus a last one
+    pub size_histogram: Vec<SizeBucket>,
+
+    /// Unix timestamp ages are measured from
+    pub as_of: i64,
+
+    /// Files by time since last modification, one band per [`AGE_BAND_DAYS`] entry plus a first one
+    pub by_modified_age: Vec<AgeBand>,
+
+    /// Files by time since last access
+    pub by_accessed_age: Vec<AgeBand>,
+}
+
+impl Default for UsageStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Empty bands, one per [`AGE_BAND_DAYS`] entry plus a first one
+pub(crate) fn age_bands() -> Vec<AgeBand> {
+    let mut min_days = 0;
+    let mut bands = Vec::with_capacity(AGE_BAND_DAYS.len() + 1);
+    for max_days in AGE_BAND_DAYS.iter().copied().map(Some).chain([None]) {
+        bands.push(AgeBand { min_days, max_days, ..Default::default() });
+        min_days = max_days.unwrap_or(min_days);
+    }
+    bands
+}
+
+/// Index into [`age_bands`] of a file last touched at `time`, measured from
+/// `as_of`; future times count as age 0
+pub(crate) fn age_band(as_of: i64, time: i64) -> usize {
+    let days = (as_of.saturating_sub(time) / SECS_PER_DAY).max(0) as u64;
+    AGE_BAND_DAYS.partition_point(|bound| *bound <= days)
+}
+
+impl UsageStats {
+    /// Empty statistics with ages measured from now
+    pub fn new() -> Self {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+        Self::as_of(now)
+    }
+
+    /// Empty statistics with ages measured from the Unix timestamp `as_of`
+    pub fn as_of(as_of: i64) -> Self {
+        let mut min = 0;
+        let mut size_histogram = Vec::with_capacity(SIZE_BUCKET_BOUNDS.len() + 1);
+        for max in SIZE_BUCKET_BOUNDS.iter().copied().map(Some).chain([None]) {
+            size_histogram.push(SizeBucket { min, max, ..Default::default() });
+            min = max.unwrap_or(min);
+        }
+
+        Self {
+            rows: 0,
+            files: 0,
+            directories: 0,
+            total_bytes: 0,
+            by_file_type: BTreeMap::new(),
+            by_top_level_dir: BTreeMap::new(),
+            size_histogram,
+            as_of,
+            by_modified_age: age_bands(),
+            by_accessed_age: age_bands(),
+        }
+    }
+
+    /// Check that the schema has the columns [`UsageStats::add_batch`] reads
+    pub fn validate(schema: &Schema) -> Result<()> {
+        for column in COLUMNS {
+            schema.field_with_name(column)
+                .with_context(|| format!("Usage statistics require a '{}' column", column))?;
+        }
+        Ok(())
+    }
+
+    fn age_band(&self, time: i64) -> usize {
+        age_band(self.as_of, time)
+    }
+
+    fn add(&mut self, size: u64, file_type: &str, top_level_dir: &str, modified: i64, accessed: i64) {
+        let is_dir = file_type == DIRECTORY_TYPE;
+        let bytes = if is_dir { 0 } else { size };
+
+        self.rows += 1;
+        if is_dir {
+            self.directories += 1;
+        } else {
+            self.files += 1;
+            self.total_bytes += size;
+            let bucket = SIZE_BUCKET_BOUNDS.partition_point(|bound| *bound <= size);
+            self.size_histogram[bucket].files += 1;
+            self.size_histogram[bucket].bytes += size;
+
+            let (modified, accessed) = (self.age_band(modified), self.age_band(accessed));
+            self.by_modified_age[modified].files += 1;
+            self.by_modified_age[modified].bytes += size;
+            self.by_accessed_age[accessed].files += 1;
+            self.by_accessed_age[accessed].bytes += size;
+        }
+
+        for (groups, key) in [(&mut self.by_file_type, file_type), (&mut self.by_top_level_dir, top_level_dir)] {
+            // Avoid allocating a key for groups already seen
+            let group = match groups.get_mut(key) {
+                Some(group) => group,
+                None => groups.entry(key.to_string()).or_default(),
+            };
+            group.rows += 1;
+            group.bytes += bytes;
+        }
+    }
+
+    pub fn add_entry(&mut self, entry: &FileEntry) {
+        self.add(entry.size, &entry.file_type, &entry.top_level_dir, entry.modified_time, entry.accessed_time);
+    }
+
+    /// Add every row of a batch with the [`COLUMNS`] columns
+    pub fn add_batch(&mut self, batch: &RecordBatch) -> Result<()> {
+        let column = |name: &str, data_type: &DataType| {
+            let column = batch.column_by_name(name)
+                .with_context(|| format!("Usage statistics require a '{}' column", name))?;
+            Ok::<_, anyhow::Error>(cast(column, data_type)?)
+        };
+        let sizes = column("size", &DataType::UInt64)?;
+        let sizes = sizes.as_any().downcast_ref::<UInt64Array>().unwrap();
+        let types = column("file_type", &DataType::Utf8)?;
+        let types = types.as_any().downcast_ref::<StringArray>().unwrap();
+        let dirs = column("top_level_dir", &DataType::Utf8)?;
+        let dirs = dirs.as_any().downcast_ref::<StringArray>().unwrap();
+        let modified = column("modified_time", &DataType::Int64)?;
+        let modified = modified.as_any().downcast_ref::<Int64Array>().unwrap();
+        let accessed = column("accessed_time", &DataType::Int64)?;
+        let accessed = accessed.as_any().downcast_ref::<Int64Array>().unwrap();
+
+        for i in 0..batch.num_rows() {
+            let size = if sizes.is_valid(i) { sizes.value(i) } else { 0 };
+            let file_type = if types.is_valid(i) { types.value(i) } else { NULL_GROUP };
+            let dir = if dirs.is_valid(i) { dirs.value(i) } else { NULL_GROUP };
+            // A missing time counts as "now", the youngest band
+            let modified = if modified.is_valid(i) { modified.value(i) } else { self.as_of };
+            let accessed = if accessed.is_valid(i) { accessed.value(i) } else { self.as_of };
+            self.add(size, file_type, dir, modified, accessed);
+        }
+        Ok(())
+    }
+
+    /// Add the counts of `other`, which must measure ages from the same time
+    pub fn merge(&mut self, other: UsageStats) {
+        debug_assert_eq!(self.as_of, other.as_of);
+        self.rows += other.rows;
+        self.files += other.files;
+        self.directories += other.directories;
+        self.total_bytes += other.total_bytes;
+
+        for (groups, other) in [(&mut self.by_file_type, other.by_file_type), (&mut self.by_top_level_dir, other.by_top_level_dir)] {
+            for (key, stats) in other {
+                let group = groups.entry(key).or_default();
+                group.rows += stats.rows;
+                group.bytes += stats.bytes;
+            }
+        }
+        for (bucket, other) in self.size_histogram.iter_mut().zip(other.size_histogram) {
+            bucket.files += other.files;
+            bucket.bytes += other.bytes;
+        }
+        let bands = [(&mut self.by_modified_age, other.by_modified_age), (&mut self.by_accessed_age, other.by_accessed_age)];
+        for (bands, other) in bands {
+            for (band, other) in bands.iter_mut().zip(other) {
+                band.files += other.files;
+                band.bytes += other.bytes;
+            }
+        }
+    }
+
+    /// The `n` largest groups by bytes, then by rows
+    pub fn top(groups: &BTreeMap<String, GroupStats>, n: usize) -> Vec<(&str, GroupStats)> {
+        let mut top: Vec<(&str, GroupStats)> = groups.iter().map(|(k, v)| (k.as_str(), *v)).collect();
+        top.sort_by(|a, b| (b.1.bytes, b.1.rows).cmp(&(a.1.bytes, a.1.rows)).then(a.0.cmp(b.0)));
+        top.truncate(n);
+        top
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::datatypes::Field;
+    use std::sync::Arc;
+
+    const NOW: i64 = 1_700_000_000;
+    const DAY: i64 = 86_400;
+
+    /// Rows of (size, file_type, top_level_dir, modified_time, accessed_time)
+    fn timed_batch(rows: &[(u64, &str, &str, i64, i64)]) -> RecordBatch {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("size", DataType::UInt64, false),
+            Field::new("file_type", DataType::Utf8, false),
+            Field::new("top_level_dir", DataType::Utf8, false),
+            Field::new("modified_time", DataType::Int64, false),
+            Field::new("accessed_time", DataType::Int64, false),
+        ]));
+        RecordBatch::try_new(schema, vec![
+            Arc::new(UInt64Array::from(rows.iter().map(|r| r.0).collect::<Vec<_>>())),
+            Arc::new(StringArray::from(rows.iter().map(|r| r.1).collect::<Vec<_>>())),
+            Arc::new(StringArray::from(rows.iter().map(|r| r.2).collect::<Vec<_>>())),
+            Arc::new(Int64Array::from(rows.iter().map(|r| r.3).collect::<Vec<_>>())),
+            Arc::new(Int64Array::from(rows.iter().map(|r| r.4).collect::<Vec<_>>())),
+        ]).unwrap()
+    }
+
+    /// Rows of (size, file_type, top_level_dir), all modified and accessed at NOW
+    fn batch(rows: &[(u64, &str, &str)]) -> RecordBatch {
+        let rows: Vec<_> = rows.iter().map(|&(size, t, d)| (size, t, d, NOW, NOW)).collect();
+        timed_batch(&rows)
+    }
+
+    #[test]
+    fn test_add_batch() {
+        let mut stats = UsageStats::as_of(NOW);
+        stats.add_batch(&batch(&[
+            (4096, "directory", "home"),
+            (0, "txt", "home"),
+            (999, "txt", "home"),
+            (1_000, "dat", "projects"),
+            (5_000_000, "dat", "projects"),
+        ])).unwrap();
+        stats.add_batch(&batch(&[(200_000_000_000, "tar", "archive")])).unwrap();
+
+        assert_eq!(stats.rows, 6);
+        assert_eq!(stats.files, 5);
+        assert_eq!(stats.directories, 1);
+        assert_eq!(stats.total_bytes, 200_005_001_999);
+
+        assert_eq!(stats.by_file_type["txt"], GroupStats { rows: 2, bytes: 999 });
+        assert_eq!(stats.by_file_type["directory"], GroupStats { rows: 1, bytes: 0 });
+        assert_eq!(stats.by_top_level_dir["home"], GroupStats { rows: 3, bytes: 999 });
+        assert_eq!(stats.by_top_level_dir["projects"], GroupStats { rows: 2, bytes: 5_001_000 });
+
+        let files: Vec<u64> = stats.size_histogram.iter().map(|b| b.files).collect();
+        assert_eq!(files, [2, 1, 0, 0, 1, 0, 0, 0, 0, 1]);
+        assert_eq!(stats.size_histogram[1], SizeBucket { min: 1_000, max: Some(10_000), files: 1, bytes: 1_000 });
+        assert_eq!(stats.size_histogram[9].max, None);
+
+        let top: Vec<&str> = UsageStats::top(&stats.by_top_level_dir, 2).iter().map(|g| g.0).collect();
+        assert_eq!(top, ["archive", "projects"]);
+    }
+
+    #[test]
+    fn test_age_bands() {
+        let mut stats = UsageStats::as_of(NOW);
+        stats.add_batch(&timed_batch(&[
+            (10, "txt", "a", NOW - 5 * DAY, NOW - DAY),
+            (20, "txt", "a", NOW - 30 * DAY, NOW - 400 * DAY),
+            (30, "txt", "a", NOW - 3000 * DAY, NOW - 3000 * DAY),
+            (40, "txt", "a", NOW + DAY, NOW),
+            (4096, "directory", "a", NOW - 3000 * DAY, NOW - 3000 * DAY),
+        ])).unwrap();
+
+        let modified: Vec<u64> = stats.by_modified_age.iter().map(|b| b.bytes).collect();
+        assert_eq!(modified, [50, 20, 0, 0, 0, 0, 30]);
+        let accessed: Vec<u64> = stats.by_accessed_age.iter().map(|b| b.files).collect();
+        assert_eq!(accessed, [2, 0, 0, 0, 1, 0, 1]);
+        assert_eq!(stats.by_modified_age[1], AgeBand { min_days: 30, max_days: Some(90), files: 1, bytes: 20 });
+        assert_eq!(stats.by_modified_age[6].max_days, None);
+    }
+
+    #[test]
+    fn test_merge_matches_single_pass() {
+        let rows = [(10, "txt", "a"), (4096, "directory", "a"), (5_000, "dat", "b"), (7, "txt", "c")];
+
+        let mut whole = UsageStats::as_of(NOW);
+        whole.add_batch(&batch(&rows)).unwrap();
+
+        let mut merged = UsageStats::as_of(NOW);
+        for half in rows.chunks(2) {
+            let mut part = UsageStats::as_of(NOW);
+            part.add_batch(&batch(half)).unwrap();
+            merged.merge(part);
+        }
+        assert_eq!(merged, whole);
+    }
+
+    #[test]
+    fn test_entries_and_batches_agree() {
+        let mut from_batch = UsageStats::as_of(NOW);
+        from_batch.add_batch(&batch(&[(10, "txt", "a"), (4096, "directory", "a")])).unwrap();
+
+        let mut from_entries = UsageStats::as_of(NOW);
+        for (size, file_type) in [(10, "txt"), (4096, "directory")] {
+            from_entries.add_entry(&FileEntry {
+                size,
+                modified_time: NOW,
+                accessed_time: NOW,
+                file_type: file_type.to_string(),
+                uid: 0,
+                gid: 0,
+                top_level_dir: "a".to_string(),
+                ..FileEntry::fixture("/a/x")
+            });
+        }
+        assert_eq!(from_batch, from_entries);
+    }
+
+    #[test]
+    fn test_validate() {
+        let schema = Schema::new(vec![Field::new("size", DataType::UInt64, false)]);
+        assert!(UsageStats::validate(&schema).is_err());
+        assert!(UsageStats::validate(&batch(&[]).schema()).is_ok());
+    }
+}
@@ -0,0 +1,471 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+#[cfg(feature = "serde")]
+use {
+    crate::http_server::HttpServer,
+    crate::progress::{Baseline, ProgressEstimator},
+    anyhow::{Context, Result},
+    serde::{Deserialize, Serialize},
+    std::collections::{BTreeMap, VecDeque},
+    std::fs,
+    std::path::{Path, PathBuf},
+    std::sync::Mutex,
+    std::time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// How often a running scan rewrites its status file
+pub const STATUS_INTERVAL: Duration = Duration::from_secs(1);
+
+#[cfg(feature = "serde")]
+/// Errors kept in [`ScanStatus::recent_errors`]
+const RECENT_ERRORS: usize = 20;
+
+#[cfg(feature = "serde")]
+/// How long a running scan may go without a status update and still be
+/// healthy; the scanner updates it every [`STATUS_INTERVAL`]
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Whether the scan writing a status file is still going
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum ScanState {
+    Running,
+    Completed,
+    Failed,
+}
+
+#[cfg(feature = "serde")]
+/// Entries seen so far below one top-level directory
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DirProgress {
+    pub name: String,
+
+    pub entries: u64,
+
+    /// Entries the baseline scan found there, if it knows the directory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub baseline_entries: Option<u64>,
+
+    /// Percent complete, capped while the scan is running
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub percent: Option<f64>,
+}
+
+#[cfg(feature = "serde")]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecentError {
+    /// Unix timestamp
+    pub time: i64,
+
+    /// e.g. `EACCES`
+    pub kind: String,
+
+    pub path: String,
+
+    pub message: String,
+}
+
+#[cfg(feature = "serde")]
+/// Chunk the scan is writing
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChunkProgress {
+    pub number: usize,
+
+    pub path: String,
+}
+
+#[cfg(feature = "serde")]
+/// One snapshot of a running scan, as written to `scan --status-file`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScanStatus {
+    pub scan_path: String,
+
+    /// Process writing the status
+    pub pid: u32,
+
+    pub state: ScanState,
+
+    /// Unix timestamps
+    pub started_at: i64,
+
+    pub updated_at: i64,
+
+    /// Seconds since the scan started, with sub-second precision
+    pub elapsed_secs: f64,
+
+    pub files: u64,
+
+    pub directories: u64,
+
+    /// Symbolic links recorded without being followed
+    #[serde(default)]
+    pub symlinks: u64,
+
+    /// Combined size of the files
+    pub bytes: u64,
+
+    pub errors: u64,
+
+    /// Entries of top-level directories a resumed scan already completed
+    pub skipped: u64,
+
+    /// Entries per second over the last minute
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entries_per_sec: Option<f64>,
+
+    /// Percent complete against the baseline, if one was given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub percent: Option<f64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub eta_secs: Option<f64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current_chunk: Option<ChunkProgress>,
+
+    /// In name order
+    pub top_level_dirs: Vec<DirProgress>,
+
+    /// The latest errors, oldest first
+    pub recent_errors: Vec<RecentError>,
+}
+
+#[cfg(feature = "serde")]
+impl ScanStatus {
+    /// Entries seen so far, skipped ones included
+    pub fn entries(&self) -> u64 {
+        self.files + self.directories + self.symlinks + self.skipped
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let contents = fs::read_to_string(path.as_ref())
+            .with_context(|| format!("Failed to read {}", path.as_ref().display()))?;
+        serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse {}", path.as_ref().display()))
+    }
+
+    /// Write the status beside `path` and rename it into place, so readers
+    /// never see a partial file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        fs::rename(&tmp, path)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(())
+    }
+}
+
+/// Counters the scanner keeps itself
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StatusCounts {
+    pub files: u64,
+    pub directories: u64,
+    pub symlinks: u64,
+    pub bytes: u64,
+
+    /// Size of the files counting each hard-linked inode once
+    pub unique_bytes: u64,
+    pub errors: u64,
+    pub skipped: u64,
+
+    /// Entries removed between being listed and being read
+    pub vanished: u64,
+}
+
+/// The counters a running scan updates, shared with whatever reports on it
+/// (the status file, the metrics endpoint)
+#[derive(Debug, Clone, Default)]
+pub struct ScanCounters {
+    pub files: Arc<AtomicU64>,
+    pub directories: Arc<AtomicU64>,
+    pub symlinks: Arc<AtomicU64>,
+    pub bytes: Arc<AtomicU64>,
+    pub unique_bytes: Arc<AtomicU64>,
+    pub errors: Arc<AtomicU64>,
+    pub skipped: Arc<AtomicU64>,
+    pub vanished: Arc<AtomicU64>,
+}
+
+impl ScanCounters {
+    pub fn load(&self) -> StatusCounts {
+        StatusCounts {
+            files: self.files.load(Ordering::Relaxed),
+            directories: self.directories.load(Ordering::Relaxed),
+            symlinks: self.symlinks.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            unique_bytes: self.unique_bytes.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            skipped: self.skipped.load(Ordering::Relaxed),
+            vanished: self.vanished.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+struct TrackerState {
+    dir_entries: BTreeMap<String, u64>,
+    recent_errors: VecDeque<RecentError>,
+    current_chunk: Option<ChunkProgress>,
+    estimator: ProgressEstimator,
+    last_counts: StatusCounts,
+    /// The last status written, and when
+    latest: Option<(Instant, ScanStatus)>,
+}
+
+#[cfg(feature = "serde")]
+/// What a scan has done so far, shared by the walker threads, the chunk
+/// writer, and whoever writes the status file or serves the status
+pub struct StatusTracker {
+    path: Option<PathBuf>,
+    scan_path: String,
+    started_at: i64,
+    started: Instant,
+    has_baseline: bool,
+    state: Mutex<TrackerState>,
+}
+
+#[cfg(feature = "serde")]
+fn unix_now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+#[cfg(feature = "serde")]
+impl StatusTracker {
+    /// Track a scan of `scan_path` whose status goes to `path`, if any
+    pub fn new(path: Option<PathBuf>, scan_path: String, baseline: Option<Baseline>) -> Self {
+        Self {
+            path,
+            scan_path,
+            started_at: unix_now(),
+            started: Instant::now(),
+            has_baseline: baseline.is_some(),
+            state: Mutex::new(TrackerState {
+                dir_entries: BTreeMap::new(),
+                recent_errors: VecDeque::new(),
+                current_chunk: None,
+                estimator: ProgressEstimator::new(baseline.unwrap_or_default()),
+                last_counts: StatusCounts::default(),
+                latest: None,
+            }),
+        }
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Count one entry below `top_level_dir`
+    pub fn count(&self, top_level_dir: &str) {
+        let Ok(mut state) = self.state.lock() else { return };
+        match state.dir_entries.get_mut(top_level_dir) {
+            Some(count) => *count += 1,
+            None => {
+                state.dir_entries.insert(top_level_dir.to_string(), 1);
+            }
+        }
+    }
+
+    pub fn record_error(&self, kind: &str, path: &str, message: &str) {
+        let Ok(mut state) = self.state.lock() else { return };
+        if state.recent_errors.len() == RECENT_ERRORS {
+            state.recent_errors.pop_front();
+        }
+        state.recent_errors.push_back(RecentError {
+            time: unix_now(),
+            kind: kind.to_string(),
+            path: path.to_string(),
+            message: message.to_string(),
+        });
+    }
+
+    /// Note the chunk file now being written
+    pub fn set_chunk(&self, number: usize, path: &Path) {
+        if let Ok(mut state) = self.state.lock() {
+            state.current_chunk = Some(ChunkProgress { number, path: path.to_string_lossy().to_string() });
+        }
+    }
+
+    pub fn snapshot(&self, counts: StatusCounts, scan_state: ScanState) -> ScanStatus {
+        let elapsed_secs = self.started.elapsed().as_secs_f64();
+        let entries = counts.files + counts.directories + counts.symlinks + counts.skipped;
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.estimator.record(elapsed_secs, entries);
+        state.last_counts = counts;
+
+        let estimator = &state.estimator;
+        let top_level_dirs = state.dir_entries.iter()
+            .map(|(name, &entries)| DirProgress {
+                name: name.clone(),
+                entries,
+                baseline_entries: estimator.baseline().dir_rows.get(name).copied(),
+                percent: match scan_state {
+                    ScanState::Completed => Some(100.0),
+                    _ => estimator.dir_percent(name, entries),
+                },
+            })
+            .collect();
+
+        let (percent, eta_secs) = match (self.has_baseline, scan_state) {
+            (_, ScanState::Completed) => (Some(100.0), None),
+            (true, _) => (Some(estimator.percent(entries)), estimator.eta_secs(entries)),
+            (false, _) => (None, None),
+        };
+
+        ScanStatus {
+            scan_path: self.scan_path.clone(),
+            pid: std::process::id(),
+            state: scan_state,
+            started_at: self.started_at,
+            updated_at: unix_now(),
+            elapsed_secs,
+            files: counts.files,
+            directories: counts.directories,
+            symlinks: counts.symlinks,
+            bytes: counts.bytes,
+            errors: counts.errors,
+            skipped: counts.skipped,
+            entries_per_sec: estimator.rate(),
+            percent,
+            eta_secs,
+            current_chunk: state.current_chunk.clone(),
+            top_level_dirs,
+            recent_errors: state.recent_errors.iter().cloned().collect(),
+        }
+    }
+
+    /// Take a snapshot as the latest status and write it to the status file
+    pub fn write(&self, counts: StatusCounts, scan_state: ScanState) -> Result<()> {
+        let status = self.snapshot(counts, scan_state);
+        let saved = match self.path {
+            Some(ref path) => status.save(path),
+            None => Ok(()),
+        };
+        if let Ok(mut state) = self.state.lock() {
+            state.latest = Some((Instant::now(), status));
+        }
+        saved
+    }
+
+    /// The status last written, none before the scan starts
+    pub fn latest(&self) -> Option<ScanStatus> {
+        self.state.lock().ok()?.latest.as_ref().map(|(_, status)| status.clone())
+    }
+
+    /// Whether the scan is running and still updating its status
+    pub fn healthy(&self) -> bool {
+        let Ok(state) = self.state.lock() else { return false };
+        state.latest.as_ref().is_some_and(|(at, status)| {
+            status.state == ScanState::Running && at.elapsed() < HEALTH_TIMEOUT
+        })
+    }
+
+    /// Counts of the last snapshot
+    pub fn last_counts(&self) -> StatusCounts {
+        self.state.lock().map(|s| s.last_counts).unwrap_or_default()
+    }
+
+    /// Mark the scan failed, keeping the counts last written
+    pub fn write_failed(&self) -> Result<()> {
+        self.write(self.last_counts(), ScanState::Failed)
+    }
+}
+
+/// Serve the latest status on `/status`, and 200 on `/healthz` while the
+/// scan is healthy (503 otherwise), until [`HttpServer::finish`]
+#[cfg(all(feature = "serde", feature = "status-server"))]
+pub fn serve(listen: &str, tracker: Arc<StatusTracker>) -> Result<HttpServer> {
+    use crate::http_server::Reply;
+
+    let text = |status, body: &str| Reply::new(status, "text/plain", format!("{}\n", body));
+    HttpServer::start(listen, "status", move |url| match url {
+        "/status" => match tracker.latest().map(|status| serde_json::to_string_pretty(&status)) {
+            Some(Ok(json)) => Reply::new(200, "application/json", json + "\n"),
+            Some(Err(e)) => text(500, &e.to_string()),
+            None => text(503, "Scan starting"),
+        },
+        "/healthz" => match tracker.healthy() {
+            true => text(200, "ok"),
+            false => text(503, "Scan not running"),
+        },
+        _ => Reply::not_found(),
+    })
+}
+
+#[cfg(all(feature = "serde", not(feature = "status-server")))]
+pub fn serve(_listen: &str, _tracker: Arc<StatusTracker>) -> Result<HttpServer> {
+    anyhow::bail!("--status-listen requires building with --features status-server")
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("status.json");
+        let baseline = Baseline {
+            total_rows: 10,
+            dir_rows: [("labA".to_string(), 4)].into_iter().collect(),
+        };
+        let tracker = StatusTracker::new(Some(path.clone()), "/project".to_string(), Some(baseline));
+
+        for dir in ["labA", "labA", "labB"] {
+            tracker.count(dir);
+        }
+        for i in 0..RECENT_ERRORS + 2 {
+            tracker.record_error("EACCES", &format!("/project/locked{}", i), "Permission denied");
+        }
+        tracker.set_chunk(3, Path::new("/out/scan_chunk_0003.parquet"));
+
+        let counts = StatusCounts { files: 2, directories: 3, symlinks: 0, bytes: 100, unique_bytes: 100, errors: 22, skipped: 0, vanished: 0 };
+        tracker.write(counts, ScanState::Running).unwrap();
+        let status = ScanStatus::load(&path).unwrap();
+
+        assert_eq!(status.state, ScanState::Running);
+        assert_eq!(status.entries(), 5);
+        assert_eq!(status.percent, Some(50.0));
+        assert_eq!(status.current_chunk.as_ref().unwrap().number, 3);
+        assert_eq!(status.top_level_dirs[0], DirProgress {
+            name: "labA".to_string(),
+            entries: 2,
+            baseline_entries: Some(4),
+            percent: Some(50.0),
+        });
+        assert_eq!(status.top_level_dirs[1].percent, None);
+        assert_eq!(status.recent_errors.len(), RECENT_ERRORS);
+        assert_eq!(status.recent_errors[0].path, "/project/locked2");
+
+        tracker.write(counts, ScanState::Completed).unwrap();
+        let status = ScanStatus::load(&path).unwrap();
+        assert_eq!(status.state, ScanState::Completed);
+        assert_eq!(status.percent, Some(100.0));
+        assert_eq!(status.eta_secs, None);
+    }
+
+    #[test]
+    fn test_latest_and_health_without_file() {
+        let tracker = StatusTracker::new(None, "/project".to_string(), None);
+        assert!(tracker.latest().is_none());
+        assert!(!tracker.healthy());
+
+        let counts = StatusCounts { files: 2, ..Default::default() };
+        tracker.write(counts, ScanState::Running).unwrap();
+        assert_eq!(tracker.latest().unwrap().files, 2);
+        assert!(tracker.healthy());
+
+        // No update for too long: the scan has stalled
+        tracker.state.lock().unwrap().latest.as_mut().unwrap().0 -= HEALTH_TIMEOUT;
+        assert!(!tracker.healthy());
+
+        tracker.write(counts, ScanState::Completed).unwrap();
+        assert_eq!(tracker.latest().unwrap().state, ScanState::Completed);
+        assert!(!tracker.healthy());
+    }
+}
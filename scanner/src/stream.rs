@@ -0,0 +1,210 @@
+//! Async boundaries around the blocking scanner and writer, for embedding
+//! in tokio services: scan into a [`Stream`] of batches, and write such a
+//! stream to Parquet. The walk still runs on rayon and writing on a blocking
+//! thread; only the hand-offs are async.
+
+use crate::error::{Error, Result};
+use crate::models::{FileEntry, ScanStats};
+use crate::scanner::Scanner;
+use crate::writer::ParquetFileWriter;
+use futures_core::Stream;
+use parquet::basic::Compression;
+use std::future::poll_fn;
+use std::path::Path;
+use std::pin::{pin, Pin};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::Poll;
+use tokio::sync::mpsc;
+use tracing::info;
+
+/// Batches of a scan running in the background, from [`Scanner::scan_stream`]
+pub struct EntryStream {
+    rx: mpsc::Receiver<Result<Vec<FileEntry>>>,
+    cancel: Arc<AtomicBool>,
+    stats: Arc<Mutex<Option<ScanStats>>>,
+}
+
+impl EntryStream {
+    /// Statistics of the finished scan, once the stream has ended
+    pub fn stats(&self) -> Option<ScanStats> {
+        self.stats.lock().ok().and_then(|stats| stats.clone())
+    }
+}
+
+impl Stream for EntryStream {
+    type Item = Result<Vec<FileEntry>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+impl Drop for EntryStream {
+    fn drop(&mut self) {
+        // The scan winds down on its own threads; nothing here blocks
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Scanner {
+    /// Scan `root_path` in the background, yielding entries in the batches
+    /// a writer would receive.
+    ///
+    /// Unreadable paths are counted in the final statistics, as with
+    /// [`Scanner::scan`]; a scan that fails outright yields one last error.
+    /// Dropping the stream stops the scan.
+    pub fn scan_stream<P: AsRef<Path>>(&self, root_path: P) -> EntryStream {
+        let (tx, rx) = crossbeam_channel::bounded(2);
+        let (cancel, scan) = self.spawn(root_path.as_ref().to_path_buf(), tx, None);
+        let (stream_tx, stream_rx) = mpsc::channel(2);
+        let stats = Arc::new(Mutex::new(None));
+
+        let finished = stats.clone();
+        let stop = cancel.clone();
+        std::thread::spawn(move || {
+            for batch in rx {
+                if stream_tx.blocking_send(Ok(batch)).is_err() {
+                    stop.store(true, Ordering::Relaxed);
+                    break;
+                }
+            }
+            let result = scan.join().map_err(|_| Error::Other(anyhow::anyhow!("Scanner thread panicked")));
+            match result.and_then(|r| r) {
+                Ok(stats) => {
+                    if let Ok(mut finished) = finished.lock() {
+                        *finished = Some(stats);
+                    }
+                }
+                Err(e) => {
+                    let _ = stream_tx.blocking_send(Err(e));
+                }
+            }
+        });
+
+        EntryStream { rx: stream_rx, cancel, stats }
+    }
+}
+
+/// Write a stream of entry batches to the Parquet file `output_path`,
+/// returning the rows written
+pub async fn write_to_parquet<P, S>(output_path: P, batches: S) -> Result<u64>
+where
+    P: AsRef<Path>,
+    S: Stream<Item = Result<Vec<FileEntry>>>,
+{
+    write_to_parquet_with(output_path, batches, Compression::SNAPPY).await
+}
+
+/// Write a stream of entry batches to a Parquet file, compressed with
+/// `compression`.
+///
+/// The stream is polled on the calling task and the batches are written on
+/// a blocking thread. An error from the stream ends writing and is returned
+/// once the file is closed.
+pub async fn write_to_parquet_with<P, S>(output_path: P, batches: S, compression: Compression) -> Result<u64>
+where
+    P: AsRef<Path>,
+    S: Stream<Item = Result<Vec<FileEntry>>>,
+{
+    let mut writer = ParquetFileWriter::with_options(output_path, Vec::new(), compression)?;
+    let (tx, mut rx) = mpsc::channel::<Vec<FileEntry>>(2);
+    let writing = tokio::task::spawn_blocking(move || {
+        while let Some(batch) = rx.blocking_recv() {
+            writer.write_batch(&batch)?;
+        }
+        let total_rows = writer.rows_written();
+        writer.close()?;
+        Ok::<u64, Error>(total_rows)
+    });
+
+    let mut batches = pin!(batches);
+    let mut failure = None;
+    let mut batches_sent = 0;
+    while let Some(batch) = poll_fn(|cx| batches.as_mut().poll_next(cx)).await {
+        match batch {
+            Ok(batch) => {
+                // A closed channel means the writer failed; its error follows
+                if tx.send(batch).await.is_err() {
+                    break;
+                }
+                batches_sent += 1;
+                if batches_sent % 10 == 0 {
+                    info!("Sent {} batches to the writer", batches_sent);
+                }
+            }
+            Err(e) => {
+                failure = Some(e);
+                break;
+            }
+        }
+    }
+    drop(tx);
+
+    let total_rows = writing.await.map_err(|e| Error::Other(anyhow::anyhow!("Parquet writer task failed: {}", e)))??;
+    match failure {
+        Some(e) => Err(e),
+        None => Ok(total_rows),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ScanOptions;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn create_tree(files: usize) -> TempDir {
+        let temp_dir = TempDir::new().unwrap();
+        for i in 0..files {
+            let dir = temp_dir.path().join(format!("dir{}", i % 5));
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join(format!("file{}.txt", i)), "content").unwrap();
+        }
+        temp_dir
+    }
+
+    fn scanner() -> Scanner {
+        Scanner::new(ScanOptions::builder().threads(2).batch_size(16).build().unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_scan_stream_to_parquet() {
+        let temp_dir = create_tree(200);
+        let output = TempDir::new().unwrap();
+        let path = output.path().join("scan.parquet");
+
+        let mut batches = scanner().scan_stream(temp_dir.path());
+        let rows = write_to_parquet(&path, &mut batches).await.unwrap();
+
+        let stats = batches.stats().unwrap();
+        assert_eq!(stats.files_scanned, 200);
+        assert_eq!(rows, stats.files_scanned + stats.directories_scanned);
+
+        let reader = SerializedFileReader::new(fs::File::open(&path).unwrap()).unwrap();
+        assert_eq!(reader.metadata().file_metadata().num_rows() as u64, rows);
+    }
+
+    #[tokio::test]
+    async fn test_scan_stream_failure() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("scan.parquet");
+        let batches = scanner().scan_stream(temp_dir.path().join("missing"));
+
+        let err = write_to_parquet(&path, batches).await.unwrap_err();
+        assert!(matches!(err, Error::InvalidRoot { .. }), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_scan_stream_dropped_early() {
+        let temp_dir = create_tree(1000);
+        let mut batches = scanner().scan_stream(temp_dir.path());
+
+        let first = poll_fn(|cx| Pin::new(&mut batches).poll_next(cx)).await.unwrap().unwrap();
+        assert!(!first.is_empty());
+        assert!(batches.stats().is_none());
+        drop(batches);
+    }
+}
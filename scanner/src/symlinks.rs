@@ -0,0 +1,272 @@
+use crate::query::{QueryFormat, ResultWriter};
+use crate::report::read_columns;
+use crate::utils::{implied_root, is_within, parent_dir};
+use anyhow::{Context, Result};
+use arrow::array::{Array, ArrayRef, BooleanArray, StringArray, UInt64Array};
+use arrow::compute::cast;
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use arrow::record_batch::RecordBatch;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Columns read by [`audit_symlinks`]
+const COLUMNS: [&str; 3] = ["path", "depth", "link_target"];
+
+/// A symbolic link recorded by the scan
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Symlink {
+    pub path: String,
+
+    /// Target as stored in the link
+    pub target: String,
+
+    /// Absolute target, with `.` and `..` resolved lexically
+    pub resolved: String,
+
+    /// Whether the stored target is an absolute path
+    pub absolute: bool,
+
+    /// Whether the target lies under the scan root
+    pub internal: bool,
+
+    /// Whether the target no longer exists; None unless checked live
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub broken: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SymlinkReport {
+    /// Root implied by the scanned paths and their depths
+    pub scan_root: String,
+
+    pub links: u64,
+
+    pub relative: u64,
+
+    pub absolute: u64,
+
+    /// Links pointing under the scan root
+    pub internal: u64,
+
+    pub external: u64,
+
+    /// Links whose target is gone; None unless checked live
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub broken: Option<u64>,
+
+    /// In path order
+    pub symlinks: Vec<Symlink>,
+}
+
+fn validate(schema: &Schema) -> Result<()> {
+    if schema.field_with_name("link_target").is_err() {
+        anyhow::bail!("This scan did not record symlink targets; rescan with this version to audit symlinks");
+    }
+    for column in ["path", "depth"] {
+        schema.field_with_name(column)
+            .with_context(|| format!("Auditing symlinks requires a '{}' column", column))?;
+    }
+    Ok(())
+}
+
+/// Absolute path `target` points to from the link at `path`, resolving `.`
+/// and `..` without following other links on the way
+fn resolve(path: &str, target: &str) -> String {
+    let base = if target.starts_with('/') { "" } else { parent_dir(path).unwrap_or("/") };
+    let mut components: Vec<&str> = base.split('/').filter(|c| !c.is_empty()).collect();
+    for component in target.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            other => components.push(other),
+        }
+    }
+    format!("/{}", components.join("/"))
+}
+
+/// Links of one record batch, as (path, target)
+fn batch_links(batch: &RecordBatch) -> Result<Vec<(String, String)>> {
+    let column = |name: &str, data_type: &DataType| -> Result<ArrayRef> {
+        Ok(cast(batch.column_by_name(name).context("missing column")?, data_type)?)
+    };
+    let paths = column("path", &DataType::Utf8)?;
+    let paths = paths.as_any().downcast_ref::<StringArray>().unwrap();
+    let targets = column("link_target", &DataType::Utf8)?;
+    let targets = targets.as_any().downcast_ref::<StringArray>().unwrap();
+
+    Ok((0..batch.num_rows())
+        .filter(|&i| paths.is_valid(i) && targets.is_valid(i))
+        .map(|i| (paths.value(i).to_string(), targets.value(i).to_string()))
+        .collect())
+}
+
+/// Classify the symbolic links of `chunk_files` as relative or absolute and
+/// as internal or external to the scan root. With `check_live`, each link is
+/// also followed on the filesystem as it is now to find broken ones.
+pub fn audit_symlinks(chunk_files: &[PathBuf], check_live: bool) -> Result<SymlinkReport> {
+    let mut links = Vec::new();
+    let mut scan_root: Option<String> = None;
+    for path in chunk_files {
+        let reader = read_columns(path, &COLUMNS, validate)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        for batch in reader {
+            let batch = batch?;
+            if scan_root.is_none() && batch.num_rows() > 0 {
+                let paths = cast(batch.column_by_name("path").unwrap(), &DataType::Utf8)?;
+                let paths = paths.as_any().downcast_ref::<StringArray>().unwrap();
+                let depths = cast(batch.column_by_name("depth").unwrap(), &DataType::UInt64)?;
+                let depths = depths.as_any().downcast_ref::<UInt64Array>().unwrap();
+                scan_root = implied_root(paths.value(0), depths.value(0)).map(str::to_string);
+            }
+            links.extend(batch_links(&batch)?);
+        }
+    }
+    let scan_root = scan_root.unwrap_or_default();
+
+    let mut symlinks: Vec<Symlink> = links.into_par_iter()
+        .map(|(path, target)| {
+            let resolved = resolve(&path, &target);
+            Symlink {
+                absolute: target.starts_with('/'),
+                internal: is_within(&resolved, &scan_root),
+                broken: check_live.then(|| std::fs::metadata(&path).is_err()),
+                path,
+                target,
+                resolved,
+            }
+        })
+        .collect();
+    symlinks.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let count = |f: fn(&Symlink) -> bool| symlinks.iter().filter(|s| f(s)).count() as u64;
+    Ok(SymlinkReport {
+        links: symlinks.len() as u64,
+        relative: count(|s| !s.absolute),
+        absolute: count(|s| s.absolute),
+        internal: count(|s| s.internal),
+        external: count(|s| !s.internal),
+        broken: check_live.then(|| count(|s| s.broken == Some(true))),
+        scan_root: if scan_root.is_empty() { "/".to_string() } else { scan_root },
+        symlinks,
+    })
+}
+
+/// Layout of the file output: one row per link
+pub fn symlinks_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("path", DataType::Utf8, false),
+        Field::new("target", DataType::Utf8, false),
+        Field::new("resolved", DataType::Utf8, false),
+        Field::new("absolute", DataType::Boolean, false),
+        Field::new("internal", DataType::Boolean, false),
+        Field::new("broken", DataType::Boolean, true),
+    ]))
+}
+
+/// Write every link of the report to a Parquet, CSV, or JSONL file
+pub fn write_symlinks(report: &SymlinkReport, output: &Path, format: QueryFormat) -> Result<()> {
+    let links = &report.symlinks;
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(links.iter().map(|s| Some(s.path.as_str())).collect::<StringArray>()),
+        Arc::new(links.iter().map(|s| Some(s.target.as_str())).collect::<StringArray>()),
+        Arc::new(links.iter().map(|s| Some(s.resolved.as_str())).collect::<StringArray>()),
+        Arc::new(links.iter().map(|s| Some(s.absolute)).collect::<BooleanArray>()),
+        Arc::new(links.iter().map(|s| Some(s.internal)).collect::<BooleanArray>()),
+        Arc::new(links.iter().map(|s| s.broken).collect::<BooleanArray>()),
+    ];
+    let mut writer = ResultWriter::create(output, format, symlinks_schema())?;
+    writer.write(&RecordBatch::try_new(symlinks_schema(), columns)?)?;
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ScanOptions;
+    use crate::scanner::scan_directory;
+    use crate::writer::ParquetFileWriter;
+    use std::os::unix::fs::symlink;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_resolve() {
+        assert_eq!(resolve("/p/a/link", "../b/file"), "/p/b/file");
+        assert_eq!(resolve("/p/a/link", "./file"), "/p/a/file");
+        assert_eq!(resolve("/p/a/link", "/etc//hosts"), "/etc/hosts");
+        assert_eq!(resolve("/link", "../../x"), "/x");
+    }
+
+    #[test]
+    fn test_classification_and_live_check() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("root");
+        let outside = temp_dir.path().join("outside.txt");
+        std::fs::create_dir_all(root.join("lab/data")).unwrap();
+        std::fs::write(root.join("lab/data/file.txt"), "data").unwrap();
+        std::fs::write(&outside, "elsewhere").unwrap();
+
+        symlink("data/file.txt", root.join("lab/relative_internal")).unwrap();
+        symlink(root.join("lab/data/file.txt"), root.join("lab/absolute_internal")).unwrap();
+        symlink("../../outside.txt", root.join("lab/relative_external")).unwrap();
+        symlink(&outside, root.join("lab/absolute_external")).unwrap();
+        symlink("data/missing.txt", root.join("lab/dangling")).unwrap();
+
+        let chunk = temp_dir.path().join("scan.parquet");
+        let mut writer = ParquetFileWriter::new(&chunk).unwrap();
+        writer.write_batch(&scan_directory(&root, ScanOptions::default()).unwrap()).unwrap();
+        writer.close().unwrap();
+
+        let report = audit_symlinks(std::slice::from_ref(&chunk), false).unwrap();
+        let root = root.to_string_lossy().to_string();
+        assert_eq!(report.scan_root, root);
+        assert_eq!((report.links, report.relative, report.absolute), (5, 3, 2));
+        assert_eq!((report.internal, report.external), (3, 2));
+        assert_eq!(report.broken, None);
+        let classes: Vec<(&str, bool, bool)> = report.symlinks.iter()
+            .map(|s| (s.path.rsplit('/').next().unwrap(), s.absolute, s.internal))
+            .collect();
+        assert_eq!(classes, [
+            ("absolute_external", true, false),
+            ("absolute_internal", true, true),
+            ("dangling", false, true),
+            ("relative_external", false, false),
+            ("relative_internal", false, true),
+        ]);
+
+        // The target was there at scan time only to be removed later
+        std::fs::remove_file(&outside).unwrap();
+        let report = audit_symlinks(std::slice::from_ref(&chunk), true).unwrap();
+        assert_eq!(report.broken, Some(3));
+        let broken: Vec<&str> = report.symlinks.iter()
+            .filter(|s| s.broken == Some(true))
+            .map(|s| s.path.rsplit('/').next().unwrap())
+            .collect();
+        assert_eq!(broken, ["absolute_external", "dangling", "relative_external"]);
+
+        let output = temp_dir.path().join("links.csv");
+        write_symlinks(&report, &output, QueryFormat::Csv).unwrap();
+        let csv = std::fs::read_to_string(&output).unwrap();
+        assert_eq!(csv.lines().next().unwrap(), "path,target,resolved,absolute,internal,broken");
+        assert_eq!(csv.lines().count(), 6);
+    }
+
+    #[test]
+    fn test_scan_without_link_targets() {
+        let temp_dir = TempDir::new().unwrap();
+        let chunk = temp_dir.path().join("old.parquet");
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("path", DataType::Utf8, false),
+            Field::new("depth", DataType::UInt32, false),
+        ]));
+        let file = std::fs::File::create(&chunk).unwrap();
+        let writer = parquet::arrow::ArrowWriter::try_new(file, schema, None).unwrap();
+        writer.close().unwrap();
+
+        let err = audit_symlinks(&[chunk], false).unwrap_err();
+        assert!(format!("{:#}", err).contains("rescan"), "{:#}", err);
+    }
+}
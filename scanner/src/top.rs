@@ -0,0 +1,370 @@
+use crate::filter::{RowFilter, TypeFilter};
+use crate::report::read_columns;
+use crate::utils::{is_within, parent_dir};
+use anyhow::{Context, Result};
+use arrow::array::{Array, StringArray, UInt32Array, UInt64Array};
+use arrow::compute::cast;
+use arrow::datatypes::{DataType, Schema};
+use arrow::record_batch::RecordBatch;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap};
+use std::path::{Path, PathBuf};
+
+/// Columns read by [`top_entries`]
+const COLUMNS: [&str; 5] = ["path", "size", "file_type", "parent_path", "depth"];
+
+/// What [`top_entries`] ranks
+#[derive(Debug, Clone, Default)]
+pub struct TopOptions {
+    /// Number of largest files to keep
+    pub files: usize,
+
+    /// Number of heaviest directories to keep
+    pub dirs: usize,
+
+    /// Only rank this path and the entries below it
+    pub under: Option<String>,
+
+    /// Count each file toward every ancestor directory up to the scan root
+    /// (or `under`), not just its parent
+    pub cumulative: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TopFile {
+    pub path: String,
+
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TopDir {
+    pub path: String,
+
+    /// Combined size of the files counted toward the directory
+    pub bytes: u64,
+
+    pub files: u64,
+}
+
+// Larger first, then by path so ties rank the same on every run
+impl Ord for TopFile {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.size.cmp(&other.size).then_with(|| other.path.cmp(&self.path))
+    }
+}
+
+impl PartialOrd for TopFile {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for TopDir {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.bytes.cmp(&other.bytes).then_with(|| other.path.cmp(&self.path))
+    }
+}
+
+impl PartialOrd for TopDir {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Largest files and heaviest directories, largest first
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TopReport {
+    pub under: Option<String>,
+
+    pub cumulative: bool,
+
+    pub files: Vec<TopFile>,
+
+    pub directories: Vec<TopDir>,
+}
+
+/// Keeps the `n` greatest items pushed into it, in O(n) memory
+pub(crate) struct TopN<T: Ord> {
+    n: usize,
+    heap: BinaryHeap<Reverse<T>>,
+}
+
+impl<T: Ord> TopN<T> {
+    pub(crate) fn new(n: usize) -> Self {
+        Self { n, heap: BinaryHeap::with_capacity(n + 1) }
+    }
+
+    pub(crate) fn push(&mut self, item: T) {
+        if self.heap.len() < self.n {
+            self.heap.push(Reverse(item));
+        } else if self.heap.peek().is_some_and(|least| item > least.0) {
+            self.heap.pop();
+            self.heap.push(Reverse(item));
+        }
+    }
+
+    /// Greatest first
+    pub(crate) fn into_sorted_vec(self) -> Vec<T> {
+        // Ascending order of Reverse<T> is descending order of T
+        self.heap.into_sorted_vec().into_iter().map(|r| r.0).collect()
+    }
+}
+
+/// Per-chunk accumulation, merged across chunks
+struct Accumulator {
+    files: TopN<TopFile>,
+
+    /// (bytes, files) per directory
+    dirs: HashMap<String, (u64, u64)>,
+}
+
+impl Accumulator {
+    fn new(options: &TopOptions) -> Self {
+        Self { files: TopN::new(options.files), dirs: HashMap::new() }
+    }
+
+    fn add_to_dir(&mut self, dir: &str, size: u64, files: u64) {
+        // Avoid allocating a key for directories already seen
+        let totals = match self.dirs.get_mut(dir) {
+            Some(totals) => totals,
+            None => self.dirs.entry(dir.to_string()).or_default(),
+        };
+        totals.0 += size;
+        totals.1 += files;
+    }
+
+    /// Add a batch of file rows already restricted to `options.under`
+    fn add_batch(&mut self, batch: &RecordBatch, options: &TopOptions) -> Result<()> {
+        let column = |name: &str, data_type: &DataType| {
+            let column = batch.column_by_name(name)
+                .with_context(|| format!("Ranking requires a '{}' column", name))?;
+            Ok::<_, anyhow::Error>(cast(column, data_type)?)
+        };
+        let paths = column("path", &DataType::Utf8)?;
+        let paths = paths.as_any().downcast_ref::<StringArray>().unwrap();
+        let sizes = column("size", &DataType::UInt64)?;
+        let sizes = sizes.as_any().downcast_ref::<UInt64Array>().unwrap();
+        let parents = column("parent_path", &DataType::Utf8)?;
+        let parents = parents.as_any().downcast_ref::<StringArray>().unwrap();
+        let depths = column("depth", &DataType::UInt32)?;
+        let depths = depths.as_any().downcast_ref::<UInt32Array>().unwrap();
+
+        let under = options.under.as_deref().map(|u| u.trim_end_matches('/'));
+        for i in 0..batch.num_rows() {
+            if paths.is_null(i) {
+                continue;
+            }
+            let size = if sizes.is_valid(i) { sizes.value(i) } else { 0 };
+            if self.files.n > 0 {
+                self.files.push(TopFile { path: paths.value(i).to_string(), size });
+            }
+            if options.dirs == 0 || parents.is_null(i) {
+                continue;
+            }
+
+            let parent = parents.value(i);
+            if !options.cumulative {
+                self.add_to_dir(parent, size, 1);
+                continue;
+            }
+            // A file at depth d has d ancestors within the scan root
+            let levels = if depths.is_valid(i) { depths.value(i) } else { u32::MAX };
+            let mut dir = Some(parent);
+            for _ in 0..levels {
+                let Some(current) = dir.filter(|d| under.is_none_or(|u| is_within(d, u))) else {
+                    break;
+                };
+                self.add_to_dir(current, size, 1);
+                dir = parent_dir(current);
+            }
+        }
+        Ok(())
+    }
+
+    fn merge(&mut self, other: Accumulator) {
+        for Reverse(file) in other.files.heap {
+            self.files.push(file);
+        }
+        for (dir, (bytes, files)) in other.dirs {
+            let totals = self.dirs.entry(dir).or_default();
+            totals.0 += bytes;
+            totals.1 += files;
+        }
+    }
+}
+
+fn validate(schema: &Schema) -> Result<()> {
+    for column in COLUMNS {
+        schema.field_with_name(column)
+            .with_context(|| format!("Ranking requires a '{}' column", column))?;
+    }
+    Ok(())
+}
+
+fn chunk_top(path: &Path, options: &TopOptions, filter: &RowFilter) -> Result<Accumulator> {
+    let mut acc = Accumulator::new(options);
+    for batch in read_columns(path, &COLUMNS, validate)? {
+        acc.add_batch(&filter.apply(&batch?)?, options)?;
+    }
+    Ok(acc)
+}
+
+/// Rank the files of `chunk_files` by size, and their directories by the
+/// bytes of the files counted toward them, reading chunks in parallel
+pub fn top_entries(chunk_files: &[PathBuf], options: &TopOptions) -> Result<TopReport> {
+    let filter = RowFilter {
+        file_type: Some(TypeFilter::File),
+        path_prefix: options.under.clone(),
+        ..Default::default()
+    };
+
+    let acc = chunk_files.par_iter()
+        .map(|path| chunk_top(path, options, &filter)
+            .with_context(|| format!("Failed to read {}", path.display())))
+        .try_reduce(|| Accumulator::new(options), |mut a, b| {
+            a.merge(b);
+            Ok(a)
+        })?;
+
+    let mut dirs = TopN::new(options.dirs);
+    for (path, (bytes, files)) in acc.dirs {
+        dirs.push(TopDir { path, bytes, files });
+    }
+
+    Ok(TopReport {
+        under: options.under.clone(),
+        cumulative: options.cumulative,
+        files: acc.files.into_sorted_vec(),
+        directories: dirs.into_sorted_vec(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aggregate;
+    use crate::models::FileEntry;
+    use crate::rotating_writer::{RotatingParquetWriter, RotatingWriterConfig};
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    fn entry(path: &str, size: u64, file_type: &str) -> FileEntry {
+        FileEntry { size, file_type: file_type.to_string(), ..FileEntry::fixture(path) }
+    }
+
+    /// Chunks of 3 rows:
+    ///
+    /// ```text
+    /// /project                    (dir)
+    /// /project/labA/a1.dat        500
+    /// /project/labA/raw/r1.dat    300
+    /// /project/labA/raw/r2.dat    300
+    /// /project/labB/b1.dat        700
+    /// /project/labB/b2.dat        100
+    /// /project/labAB/x.dat        900
+    /// /project/top.txt            50
+    /// ```
+    fn fixture(dir: &Path) -> Vec<PathBuf> {
+        let config = RotatingWriterConfig {
+            base_output_path: dir.join("scan.parquet"),
+            rows_per_chunk: 3,
+            time_interval: Duration::from_secs(3600),
+        };
+        let mut writer = RotatingParquetWriter::new(config, "/project".to_string()).unwrap();
+        let entries = [
+            entry("/project", 4096, "directory"),
+            entry("/project/labA/a1.dat", 500, "dat"),
+            entry("/project/labA/raw/r1.dat", 300, "dat"),
+            entry("/project/labA/raw/r2.dat", 300, "dat"),
+            entry("/project/labB/b1.dat", 700, "dat"),
+            entry("/project/labB/b2.dat", 100, "dat"),
+            entry("/project/labAB/x.dat", 900, "dat"),
+            entry("/project/top.txt", 50, "txt"),
+        ];
+        for entry in entries {
+            writer.write_batch(&[entry]).unwrap();
+        }
+        writer.finalize().unwrap();
+        aggregate::find_chunk_files(&dir.join("scan_manifest.json")).unwrap()
+    }
+
+    fn dirs(report: &TopReport) -> Vec<(&str, u64, u64)> {
+        report.directories.iter().map(|d| (d.path.as_str(), d.bytes, d.files)).collect()
+    }
+
+    #[test]
+    fn test_largest_files_and_parent_dirs() {
+        let temp_dir = TempDir::new().unwrap();
+        let chunks = fixture(temp_dir.path());
+        assert!(chunks.len() > 1);
+
+        let options = TopOptions { files: 3, dirs: 3, ..Default::default() };
+        let report = top_entries(&chunks, &options).unwrap();
+
+        let files: Vec<(&str, u64)> = report.files.iter().map(|f| (f.path.as_str(), f.size)).collect();
+        assert_eq!(files, [
+            ("/project/labAB/x.dat", 900),
+            ("/project/labB/b1.dat", 700),
+            ("/project/labA/a1.dat", 500),
+        ]);
+
+        // The 4096-byte directory entry is not a file and counts nowhere
+        assert_eq!(dirs(&report), [
+            ("/project/labAB", 900, 1),
+            ("/project/labB", 800, 2),
+            ("/project/labA/raw", 600, 2),
+        ]);
+    }
+
+    #[test]
+    fn test_cumulative_rolls_up_to_ancestors() {
+        let temp_dir = TempDir::new().unwrap();
+        let chunks = fixture(temp_dir.path());
+
+        let options = TopOptions { files: 0, dirs: 4, cumulative: true, ..Default::default() };
+        let report = top_entries(&chunks, &options).unwrap();
+        assert!(report.files.is_empty());
+        // Directories above the scan root are left out
+        assert_eq!(dirs(&report), [
+            ("/project", 2850, 7),
+            ("/project/labA", 1100, 3),
+            ("/project/labAB", 900, 1),
+            ("/project/labB", 800, 2),
+        ]);
+
+        // --under stops the rollup at that directory and excludes siblings sharing its prefix
+        let options = TopOptions { under: Some("/project/labA/".to_string()), ..options };
+        let report = top_entries(&chunks, &options).unwrap();
+        assert_eq!(dirs(&report), [("/project/labA", 1100, 3), ("/project/labA/raw", 600, 2)]);
+    }
+
+    #[test]
+    fn test_under_and_ties() {
+        let temp_dir = TempDir::new().unwrap();
+        let chunks = fixture(temp_dir.path());
+
+        let options = TopOptions { files: 10, dirs: 10, under: Some("/project/labA".to_string()), cumulative: false };
+        let report = top_entries(&chunks, &options).unwrap();
+
+        // Equal sizes rank by path
+        let files: Vec<&str> = report.files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(files, ["/project/labA/a1.dat", "/project/labA/raw/r1.dat", "/project/labA/raw/r2.dat"]);
+        assert_eq!(dirs(&report), [("/project/labA/raw", 600, 2), ("/project/labA", 500, 1)]);
+    }
+
+    #[test]
+    fn test_top_n_keeps_greatest() {
+        let mut top = TopN::new(3);
+        for n in [5, 1, 9, 7, 3, 9, 2] {
+            top.push(n);
+        }
+        assert_eq!(top.into_sorted_vec(), [9, 9, 7]);
+
+        let mut none = TopN::new(0);
+        none.push(1);
+        assert!(none.into_sorted_vec().is_empty());
+    }
+}
@@ -0,0 +1,176 @@
+use crate::rollup::{DirNode, DirOrder, Rollup};
+use crate::utils;
+use anyhow::Result;
+use std::path::PathBuf;
+
+/// ANSI escape highlighting the largest subdirectory (bold yellow)
+const HIGHLIGHT: &str = "\x1b[1;33m";
+
+const RESET: &str = "\x1b[0m";
+
+/// What [`directory_tree`] shows
+#[derive(Debug, Clone, Default)]
+pub struct TreeOptions {
+    /// Deepest level shown, counted from the scan root (or `under`) at 0
+    pub depth: usize,
+
+    /// Only show this directory and the directories below it
+    pub under: Option<String>,
+
+    /// Hide directories with fewer cumulative bytes than this
+    pub min_size: u64,
+}
+
+/// How [`render`] draws a tree
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TreeStyle {
+    /// Draw branches with `|--` and `` `-- `` instead of box-drawing characters
+    pub ascii: bool,
+
+    /// Highlight the largest subdirectory with ANSI colors rather than a
+    /// trailing `*`
+    pub color: bool,
+}
+
+impl TreeStyle {
+    /// (continuing ancestor, finished ancestor, branch, last branch)
+    fn branches(&self) -> [&'static str; 4] {
+        if self.ascii {
+            ["|   ", "    ", "|-- ", "`-- "]
+        } else {
+            ["│   ", "    ", "├── ", "└── "]
+        }
+    }
+}
+
+/// Directories down to `options.depth` with cumulative sizes and file
+/// counts, largest first among siblings, in the order [`render`] draws them
+pub fn directory_tree(chunk_files: &[PathBuf], options: &TreeOptions) -> Result<Vec<DirNode>> {
+    let rollup = Rollup::collect(chunk_files, options.under.as_deref(), options.depth)?;
+    Ok(rollup.tree(DirOrder::Size, options.min_size))
+}
+
+/// Draw `nodes`, as returned by [`directory_tree`], one directory per line.
+/// The top of each tree shows its full path, the rest their names.
+pub fn render(nodes: &[DirNode], style: TreeStyle) -> String {
+    let [continuing, finished, branch, last_branch] = style.branches();
+
+    let mut out = String::new();
+    // Whether the ancestor at each level below the top has siblings still to come
+    let mut open: Vec<bool> = Vec::new();
+    for node in nodes {
+        let name = if node.depth == 0 {
+            node.path.as_str()
+        } else {
+            node.path.rsplit('/').next().unwrap_or(&node.path)
+        };
+
+        if node.depth > 0 {
+            open.truncate(node.depth - 1);
+            for &more in &open {
+                out.push_str(if more { continuing } else { finished });
+            }
+            out.push_str(if node.last { last_branch } else { branch });
+            open.push(!node.last);
+        } else {
+            open.clear();
+        }
+
+        let files = match node.files {
+            1 => "1 file".to_string(),
+            n => format!("{} files", utils::format_number(n)),
+        };
+        let annotation = format!("({}, {})", utils::format_bytes(node.bytes), files);
+        match (node.largest, style.color) {
+            (true, true) => out.push_str(&format!("{}{}{} {}", HIGHLIGHT, name, RESET, annotation)),
+            (true, false) => out.push_str(&format!("{} {} *", name, annotation)),
+            (false, _) => out.push_str(&format!("{} {}", name, annotation)),
+        }
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::FileEntry;
+    use crate::utils::format_bytes;
+    use crate::writer::ParquetFileWriter;
+    use tempfile::TempDir;
+
+    fn entry(path: &str, size: u64) -> FileEntry {
+        let entry = if size == 0 { FileEntry::dir_fixture(path) } else { FileEntry::fixture(path) };
+        FileEntry { size, ..entry }
+    }
+
+    /// Scan of /project; size 0 marks a directory
+    fn fixture(dir: &std::path::Path) -> Vec<PathBuf> {
+        let path = dir.join("scan.parquet");
+        let mut writer = ParquetFileWriter::new(&path).unwrap();
+        writer.write_batch(&[
+            entry("/project", 0),
+            entry("/project/top.txt", 100),
+            entry("/project/labA", 0),
+            entry("/project/labA/a1.dat", 500_000),
+            entry("/project/labA/raw", 0),
+            entry("/project/labA/raw/r1.dat", 300_000),
+            entry("/project/labA/raw/deep", 0),
+            entry("/project/labA/raw/deep/d1.dat", 50_000),
+            entry("/project/labA/sims", 0),
+            entry("/project/labA/sims/s1.dat", 400_000),
+            entry("/project/labB", 0),
+            entry("/project/labB/b1.dat", 2_000_000),
+            entry("/project/empty", 0),
+        ]).unwrap();
+        writer.close().unwrap();
+        vec![path]
+    }
+
+    #[test]
+    fn test_render_golden_tree() {
+        let temp_dir = TempDir::new().unwrap();
+        let chunks = fixture(temp_dir.path());
+        let nodes = directory_tree(&chunks, &TreeOptions { depth: 2, ..Default::default() }).unwrap();
+
+        let expected = format!(
+            "/project ({}, 6 files)\n\
+             ├── labB ({}, 1 file) *\n\
+             ├── labA ({}, 4 files)\n\
+             │   ├── sims ({}, 1 file) *\n\
+             │   └── raw ({}, 2 files)\n\
+             └── empty ({}, 0 files)\n",
+            format_bytes(3_250_100),
+            format_bytes(2_000_000),
+            format_bytes(1_250_000),
+            format_bytes(400_000),
+            format_bytes(350_000),
+            format_bytes(0),
+        );
+        assert_eq!(render(&nodes, TreeStyle::default()), expected);
+
+        let colored = render(&nodes, TreeStyle { ascii: true, color: true });
+        let lines: Vec<&str> = colored.lines().collect();
+        assert_eq!(lines[1], format!("|-- {}labB{} ({}, 1 file)", HIGHLIGHT, RESET, format_bytes(2_000_000)));
+        assert_eq!(lines[4], format!("|   `-- raw ({}, 2 files)", format_bytes(350_000)));
+        assert_eq!(lines[5], format!("`-- empty ({}, 0 files)", format_bytes(0)));
+    }
+
+    #[test]
+    fn test_under_and_min_size() {
+        let temp_dir = TempDir::new().unwrap();
+        let chunks = fixture(temp_dir.path());
+        let options = TreeOptions { depth: 5, under: Some("/project/labA".to_string()), min_size: 100_000 };
+        let nodes = directory_tree(&chunks, &options).unwrap();
+
+        let expected = format!(
+            "/project/labA ({}, 4 files)\n\
+             ├── sims ({}, 1 file) *\n\
+             └── raw ({}, 2 files)\n",
+            format_bytes(1_250_000),
+            format_bytes(400_000),
+            format_bytes(350_000),
+        );
+        assert_eq!(render(&nodes, TreeStyle::default()), expected);
+    }
+}
@@ -0,0 +1,210 @@
+use crate::monitor::{MonitorLayout, MonitorState};
+use crate::status::ScanStatus;
+use crate::utils;
+use anyhow::Result;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::execute;
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen};
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, Paragraph, Sparkline};
+use ratatui::{Frame, Terminal};
+use std::io::{self, Stdout};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How long to wait for a key press before redrawing
+const TICK: Duration = Duration::from_millis(250);
+
+/// Puts the terminal back however `run` returns
+struct TerminalGuard {
+    terminal: Terminal<CrosstermBackend<Stdout>>,
+}
+
+impl TerminalGuard {
+    fn enter() -> Result<Self> {
+        enable_raw_mode()?;
+        let mut stdout = io::stdout();
+        if let Err(e) = execute!(stdout, EnterAlternateScreen) {
+            let _ = disable_raw_mode();
+            return Err(e.into());
+        }
+        Ok(Self { terminal: Terminal::new(CrosstermBackend::new(stdout))? })
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = disable_raw_mode();
+        let _ = execute!(self.terminal.backend_mut(), LeaveAlternateScreen);
+        let _ = self.terminal.show_cursor();
+    }
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+/// Show a live dashboard of the scan writing `status_file`, re-reading it
+/// every `refresh`, until q, Esc, or Ctrl-C is pressed
+pub fn run(status_file: &Path, refresh: Duration) -> Result<()> {
+    let mut guard = TerminalGuard::enter()?;
+    let mut state = MonitorState::new();
+    let mut last_read: Option<Instant> = None;
+
+    loop {
+        if last_read.is_none_or(|t| t.elapsed() >= refresh) {
+            match ScanStatus::load(status_file) {
+                Ok(status) => state.update(status),
+                Err(e) => state.load_failed(format!("{:#}", e)),
+            }
+            last_read = Some(Instant::now());
+        }
+
+        guard.terminal.draw(|frame| draw(frame, &state, status_file))?;
+
+        if event::poll(TICK)? {
+            if let Event::Key(key) = event::read()? {
+                let ctrl_c = key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+                if key.kind == KeyEventKind::Press && (matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) || ctrl_c) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+fn summary_line(state: &MonitorState, now: i64) -> String {
+    match state.status() {
+        Some(s) => {
+            let mut line = format!(
+                "{} | {} files, {} dirs, {}",
+                state.state_label(now),
+                utils::format_number(s.files),
+                utils::format_number(s.directories),
+                utils::format_bytes(s.bytes),
+            );
+            if let Some(percent) = s.percent {
+                line.push_str(&format!(" | {:.1}%", percent));
+            }
+            if let Some(eta) = s.eta_secs {
+                line.push_str(&format!(" | ETA {}", utils::format_duration(eta)));
+            }
+            line
+        }
+        None => state.load_error().unwrap_or("Waiting for status").to_string(),
+    }
+}
+
+fn draw(frame: &mut Frame, state: &MonitorState, status_file: &Path) {
+    let area = frame.size();
+    let now = unix_now();
+    let layout = MonitorLayout::for_size(area.width, area.height);
+    if layout.compact {
+        frame.render_widget(Paragraph::new(summary_line(state, now)), area);
+        return;
+    }
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(layout.summary),
+            Constraint::Length(layout.throughput),
+            Constraint::Length(layout.dirs),
+            Constraint::Length(layout.errors),
+            Constraint::Length(1),
+        ])
+        .split(area);
+
+    draw_summary(frame, chunks[0], state, status_file, now);
+    if layout.throughput > 0 {
+        let data = state.throughput();
+        // Show the latest samples that fit inside the borders
+        let width = chunks[1].width.saturating_sub(2) as usize;
+        let latest = &data[data.len().saturating_sub(width)..];
+        let rate = state.status()
+            .and_then(|s| s.entries_per_sec)
+            .map(|r| format!(" Throughput: {:.0} entries/s ", r))
+            .unwrap_or_else(|| " Throughput ".to_string());
+        frame.render_widget(
+            Sparkline::default()
+                .block(Block::default().title(rate).borders(Borders::ALL))
+                .style(Style::default().fg(Color::Green))
+                .data(latest),
+            chunks[1],
+        );
+    }
+    draw_dirs(frame, chunks[2], state);
+    if layout.errors > 0 {
+        draw_errors(frame, chunks[3], state);
+    }
+    frame.render_widget(
+        Paragraph::new("q: quit").style(Style::default().add_modifier(Modifier::DIM)),
+        chunks[4],
+    );
+}
+
+fn draw_summary(frame: &mut Frame, area: Rect, state: &MonitorState, status_file: &Path, now: i64) {
+    let title = match state.status() {
+        Some(s) => format!(" {} (pid {}) ", s.scan_path, s.pid),
+        None => format!(" {} ", status_file.display()),
+    };
+    let mut lines = vec![Line::from(summary_line(state, now))];
+    if let Some(s) = state.status() {
+        lines.push(Line::from(format!(
+            "Elapsed {} | {} errors{}",
+            utils::format_duration(s.elapsed_secs),
+            utils::format_number(s.errors),
+            if s.skipped > 0 { format!(" | {} skipped", utils::format_number(s.skipped)) } else { String::new() },
+        )));
+        lines.push(Line::from(match s.current_chunk {
+            Some(ref chunk) => format!("Chunk {}: {}", chunk.number, chunk.path),
+            None => "Chunk: -".to_string(),
+        }));
+    }
+    if let (Some(error), true) = (state.load_error(), state.status().is_some()) {
+        lines.truncate(2);
+        lines.push(Line::styled(error.to_string(), Style::default().fg(Color::Red)));
+    }
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::default().title(title).borders(Borders::ALL)),
+        area,
+    );
+}
+
+fn draw_dirs(frame: &mut Frame, area: Rect, state: &MonitorState) {
+    let rows = area.height.saturating_sub(2) as usize;
+    let name_width = (area.width as usize / 3).max(8);
+    let lines: Vec<Line> = state.busiest_dirs(rows).into_iter()
+        .map(|dir| {
+            let progress = match (dir.percent, dir.baseline_entries) {
+                (Some(percent), Some(baseline)) => format!(
+                    "{} / {} ({:.0}%)",
+                    utils::format_number(dir.entries),
+                    utils::format_number(baseline),
+                    percent
+                ),
+                _ => utils::format_number(dir.entries),
+            };
+            let name: String = dir.name.chars().take(name_width).collect();
+            Line::from(format!("{:<width$}  {}", name, progress, width = name_width))
+        })
+        .collect();
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::default().title(" Top-level directories ").borders(Borders::ALL)),
+        area,
+    );
+}
+
+fn draw_errors(frame: &mut Frame, area: Rect, state: &MonitorState) {
+    let rows = area.height.saturating_sub(2) as usize;
+    let lines: Vec<Line> = state.latest_errors(rows).into_iter()
+        .map(|e| Line::styled(format!("{:<8} {}", e.kind, e.path), Style::default().fg(Color::Red)))
+        .collect();
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::default().title(" Recent errors ").borders(Borders::ALL)),
+        area,
+    );
+}
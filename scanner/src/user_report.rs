@@ -0,0 +1,555 @@
+use crate::filter::RowFilter;
+use crate::models::{get_groupname, get_username};
+use crate::report::{age_range, csv_field, read_columns};
+use crate::stats::{age_band, age_bands, AgeBand};
+use crate::utils;
+use anyhow::{Context, Result};
+use arrow::array::{Array, Int64Array, StringArray, UInt32Array, UInt64Array};
+use arrow::compute::cast;
+use arrow::datatypes::{DataType, Schema};
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+const DIRECTORY_TYPE: &str = "directory";
+
+/// What to total, and how owners are named
+#[derive(Debug, Clone, Default)]
+pub struct UserReportOptions {
+    /// Only count this path and the paths below it
+    pub under: Option<String>,
+
+    /// Split each owner's bytes into modification-age bands measured from
+    /// this Unix timestamp
+    pub age_bands_as_of: Option<i64>,
+
+    /// User names by uid, taking precedence over the scan and the system
+    pub user_names: HashMap<u32, String>,
+
+    /// Group names by gid, taking precedence over the scan and the system
+    pub group_names: HashMap<u32, String>,
+}
+
+impl UserReportOptions {
+    fn columns(&self) -> Vec<&'static str> {
+        let mut columns = vec!["uid", "gid", "file_type", "size", "owner", "group"];
+        if self.under.is_some() {
+            columns.push("path");
+        }
+        if self.age_bands_as_of.is_some() {
+            columns.push("modified_time");
+        }
+        columns
+    }
+
+    fn validate(&self, schema: &Schema) -> Result<()> {
+        for column in ["uid", "gid"] {
+            if schema.field_with_name(column).is_err() {
+                anyhow::bail!(
+                    "The scan has no '{}' column, so usage cannot be attributed to owners; \
+                     rescan with ownership capture to record uid and gid",
+                    column
+                );
+            }
+        }
+        for column in self.columns() {
+            if column != "owner" && column != "group" {
+                schema.field_with_name(column)
+                    .with_context(|| format!("A user report requires a '{}' column", column))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Usage of one uid or gid
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OwnerUsage {
+    pub id: u32,
+
+    /// From the mapping, the scan, or the system, in that order; None when
+    /// the id resolves nowhere
+    pub name: Option<String>,
+
+    /// Rows owned, directories included
+    pub entries: u64,
+
+    pub files: u64,
+
+    /// Bytes of files; directories count as 0
+    pub bytes: u64,
+
+    /// Files and bytes by modification age, when age bands were requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub by_modified_age: Option<Vec<AgeBand>>,
+}
+
+/// Usage per owner found by [`user_report`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UserReport {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub under: Option<String>,
+
+    /// Unix timestamp ages are measured from, when age bands were requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub as_of: Option<i64>,
+
+    /// Largest total first, then by uid
+    pub users: Vec<OwnerUsage>,
+
+    /// Largest total first, then by gid
+    pub groups: Vec<OwnerUsage>,
+
+    pub entries: u64,
+
+    pub files: u64,
+
+    pub bytes: u64,
+}
+
+impl OwnerUsage {
+    /// "name (id)", or just the id when it has no name
+    pub fn label(&self) -> String {
+        match self.name {
+            Some(ref name) => format!("{} ({})", name, self.id),
+            None => self.id.to_string(),
+        }
+    }
+}
+
+impl UserReport {
+    /// Users then groups, with the section name used in CSV output
+    pub fn sections(&self) -> [(&'static str, &[OwnerUsage]); 2] {
+        [("user", &self.users), ("group", &self.groups)]
+    }
+
+    /// Bands of the age columns, when age bands were requested
+    fn bands(&self) -> Vec<AgeBand> {
+        if self.as_of.is_some() { age_bands() } else { Vec::new() }
+    }
+
+    /// Totals followed by a users and a groups table, aligned for a terminal
+    pub fn write_table(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "Entries:               {}", utils::format_number(self.entries))?;
+        writeln!(out, "Files:                 {}", utils::format_number(self.files))?;
+        writeln!(out, "Total size:            {}", utils::format_bytes(self.bytes))?;
+
+        let bands = self.bands();
+        for (title, owners) in [("User", &self.users), ("Group", &self.groups)] {
+            writeln!(out)?;
+            write!(out, "{:<30}  {:>12}  {:>12}  {:>7}", title, "Entries", "Size", "Share")?;
+            for band in &bands {
+                write!(out, "  {:>18}", age_range(band.min_days, band.max_days))?;
+            }
+            writeln!(out)?;
+            for owner in owners.iter() {
+                write!(out, "{:<30}  {:>12}  {:>12}  {:>6.1}%",
+                       owner.label(),
+                       utils::format_number(owner.entries),
+                       utils::format_bytes(owner.bytes),
+                       utils::percentage(owner.bytes, self.bytes))?;
+                for band in owner.by_modified_age.iter().flatten() {
+                    write!(out, "  {:>18}", utils::format_bytes(band.bytes))?;
+                }
+                writeln!(out)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// One `kind,id,name,entries,files,bytes,percent` line per user and group,
+    /// followed by the bytes of each modification-age band when requested
+    pub fn write_csv(&self, out: &mut impl Write) -> io::Result<()> {
+        write!(out, "kind,id,name,entries,files,bytes,percent")?;
+        for band in self.bands() {
+            match band.max_days {
+                Some(max) => write!(out, ",modified_{}_{}d", band.min_days, max)?,
+                None => write!(out, ",modified_{}d_plus", band.min_days)?,
+            }
+        }
+        writeln!(out)?;
+        for (kind, owners) in self.sections() {
+            for owner in owners {
+                write!(out, "{},{},{},{},{},{},{:.2}",
+                       kind,
+                       owner.id,
+                       csv_field(owner.name.as_deref().unwrap_or("")),
+                       owner.entries,
+                       owner.files,
+                       owner.bytes,
+                       utils::percentage(owner.bytes, self.bytes))?;
+                for band in owner.by_modified_age.iter().flatten() {
+                    write!(out, ",{}", band.bytes)?;
+                }
+                writeln!(out)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn name_at(names: Option<&StringArray>, i: usize) -> Option<&str> {
+    names.filter(|n| n.is_valid(i)).map(|n| n.value(i))
+}
+
+/// Per-id usage of either users or groups
+#[derive(Default)]
+struct Owners(HashMap<u32, OwnerUsage>);
+
+impl Owners {
+    fn add(&mut self, id: u32, name: Option<&str>, size: u64, is_dir: bool, band: Option<usize>) {
+        let usage = self.0.entry(id).or_insert_with(|| OwnerUsage {
+            id,
+            name: None,
+            entries: 0,
+            files: 0,
+            bytes: 0,
+            by_modified_age: band.map(|_| age_bands()),
+        });
+        usage.entries += 1;
+        if usage.name.is_none() {
+            usage.name = name.map(str::to_string);
+        }
+        if !is_dir {
+            usage.files += 1;
+            usage.bytes += size;
+            if let (Some(bands), Some(band)) = (usage.by_modified_age.as_mut(), band) {
+                bands[band].files += 1;
+                bands[band].bytes += size;
+            }
+        }
+    }
+
+    fn merge(mut self, other: Owners) -> Owners {
+        for (id, theirs) in other.0 {
+            match self.0.get_mut(&id) {
+                Some(ours) => {
+                    ours.entries += theirs.entries;
+                    ours.files += theirs.files;
+                    ours.bytes += theirs.bytes;
+                    if ours.name.is_none() {
+                        ours.name = theirs.name;
+                    }
+                    if let (Some(ours), Some(theirs)) = (ours.by_modified_age.as_mut(), theirs.by_modified_age) {
+                        for (band, other) in ours.iter_mut().zip(theirs) {
+                            band.files += other.files;
+                            band.bytes += other.bytes;
+                        }
+                    }
+                }
+                None => {
+                    self.0.insert(id, theirs);
+                }
+            }
+        }
+        self
+    }
+
+    /// Sorted usage, named from `names` first, then the scan, then `lookup`
+    fn into_sorted(self, names: &HashMap<u32, String>, lookup: fn(u32) -> Option<String>) -> Vec<OwnerUsage> {
+        let mut owners: Vec<OwnerUsage> = self.0.into_values().collect();
+        for owner in &mut owners {
+            if let Some(name) = names.get(&owner.id) {
+                owner.name = Some(name.clone());
+            } else if owner.name.is_none() {
+                owner.name = lookup(owner.id);
+            }
+        }
+        owners.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.id.cmp(&b.id)));
+        owners
+    }
+}
+
+#[derive(Default)]
+struct Totals {
+    users: Owners,
+    groups: Owners,
+}
+
+impl Totals {
+    /// Add a batch already restricted to `options.under`
+    fn add_batch(&mut self, batch: &RecordBatch, as_of: Option<i64>) -> Result<()> {
+        let column = |name: &str, data_type: &DataType| {
+            let column = batch.column_by_name(name)
+                .with_context(|| format!("A user report requires a '{}' column", name))?;
+            Ok::<_, anyhow::Error>(cast(column, data_type)?)
+        };
+        let optional = |name: &str| batch.column_by_name(name)
+            .map(|column| cast(column, &DataType::Utf8))
+            .transpose();
+
+        let uids = column("uid", &DataType::UInt32)?;
+        let uids = uids.as_any().downcast_ref::<UInt32Array>().unwrap();
+        let gids = column("gid", &DataType::UInt32)?;
+        let gids = gids.as_any().downcast_ref::<UInt32Array>().unwrap();
+        let sizes = column("size", &DataType::UInt64)?;
+        let sizes = sizes.as_any().downcast_ref::<UInt64Array>().unwrap();
+        let types = column("file_type", &DataType::Utf8)?;
+        let types = types.as_any().downcast_ref::<StringArray>().unwrap();
+        let modified = as_of.map(|_| column("modified_time", &DataType::Int64)).transpose()?;
+        let modified = modified.as_ref().map(|m| m.as_any().downcast_ref::<Int64Array>().unwrap());
+        let owners = optional("owner")?;
+        let owners = owners.as_ref().map(|o| o.as_any().downcast_ref::<StringArray>().unwrap());
+        let groups = optional("group")?;
+        let groups = groups.as_ref().map(|g| g.as_any().downcast_ref::<StringArray>().unwrap());
+
+        for i in 0..batch.num_rows() {
+            let size = if sizes.is_valid(i) { sizes.value(i) } else { 0 };
+            let is_dir = types.is_valid(i) && types.value(i) == DIRECTORY_TYPE;
+            // A missing time counts as "now", the youngest band
+            let band = as_of.map(|as_of| {
+                let time = modified.filter(|m| m.is_valid(i)).map_or(as_of, |m| m.value(i));
+                age_band(as_of, time)
+            });
+            if uids.is_valid(i) {
+                self.users.add(uids.value(i), name_at(owners, i), size, is_dir, band);
+            }
+            if gids.is_valid(i) {
+                self.groups.add(gids.value(i), name_at(groups, i), size, is_dir, band);
+            }
+        }
+        Ok(())
+    }
+
+    fn merge(self, other: Totals) -> Totals {
+        Totals {
+            users: self.users.merge(other.users),
+            groups: self.groups.merge(other.groups),
+        }
+    }
+}
+
+fn chunk_totals(path: &Path, options: &UserReportOptions, filter: &RowFilter) -> Result<Totals> {
+    // Names in the scan are only a fallback, so chunks without them are still read
+    let schema = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?.schema().clone();
+    let columns: Vec<&str> = options.columns().into_iter()
+        .filter(|c| !matches!(*c, "owner" | "group") || schema.field_with_name(c).is_ok())
+        .collect();
+
+    let mut totals = Totals::default();
+    for batch in read_columns(path, &columns, |schema| options.validate(schema))? {
+        let batch = batch?;
+        let batch = if filter.is_empty() { batch } else { filter.apply(&batch)? };
+        totals.add_batch(&batch, options.age_bands_as_of)?;
+    }
+    Ok(totals)
+}
+
+/// Total entries and bytes per uid and per gid. Chunks are read in parallel
+/// and only the columns the report needs are read.
+pub fn user_report(chunk_files: &[PathBuf], options: &UserReportOptions) -> Result<UserReport> {
+    let filter = RowFilter { path_prefix: options.under.clone(), ..Default::default() };
+    let totals = chunk_files.par_iter()
+        .map(|path| chunk_totals(path, options, &filter)
+            .with_context(|| format!("Failed to read {}", path.display())))
+        .try_reduce(Totals::default, |a, b| Ok(a.merge(b)))?;
+
+    let users = totals.users.into_sorted(&options.user_names, get_username);
+    let groups = totals.groups.into_sorted(&options.group_names, get_groupname);
+    Ok(UserReport {
+        under: options.under.clone(),
+        as_of: options.age_bands_as_of,
+        entries: users.iter().map(|u| u.entries).sum(),
+        files: users.iter().map(|u| u.files).sum(),
+        bytes: users.iter().map(|u| u.bytes).sum(),
+        users,
+        groups,
+    })
+}
+
+/// Parse an /etc/passwd or /etc/group style mapping (`name:password:id:...`)
+/// into names by id. Blank lines and lines starting with '#' are skipped.
+pub fn parse_id_map(text: &str) -> Result<HashMap<u32, String>> {
+    let mut names = HashMap::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(':').collect();
+        let id = match fields.as_slice() {
+            [name, _, id, ..] if !name.is_empty() => id.trim().parse::<u32>().ok(),
+            _ => None,
+        };
+        let id = id.with_context(|| format!(
+            "Line {}: expected 'name:password:id:...', found '{}'", number + 1, line
+        ))?;
+        // The first entry for an id wins, as with getpwuid
+        names.entry(id).or_insert_with(|| fields[0].to_string());
+    }
+    Ok(names)
+}
+
+/// Read a mapping file for [`parse_id_map`]
+pub fn load_id_map(path: &Path) -> Result<HashMap<u32, String>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read mapping file {}", path.display()))?;
+    parse_id_map(&text).with_context(|| format!("Invalid mapping file {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::FileEntry;
+    use crate::writer::ParquetFileWriter;
+    use tempfile::TempDir;
+
+    const AS_OF: i64 = 1_700_000_000;
+    const DAY: i64 = 86_400;
+
+    fn entry(path: &str, size: u64, modified_days_ago: i64, uid: u32, gid: u32) -> FileEntry {
+        let entry = if size == 0 { FileEntry::dir_fixture(path) } else { FileEntry::fixture(path) };
+        FileEntry {
+            size,
+            modified_time: AS_OF - modified_days_ago * DAY,
+            accessed_time: AS_OF,
+            uid,
+            gid,
+            // The scan only resolved uid 1000
+            owner: (uid == 1000).then(|| "scanned_alice".to_string()),
+            ..entry
+        }
+    }
+
+    fn fixture(dir: &Path) -> Vec<PathBuf> {
+        let chunks = [
+            vec![
+                entry("/project/labA", 0, 0, 1000, 100),
+                entry("/project/labA/a.dat", 400, 10, 1000, 100),
+                entry("/project/labA/b.dat", 100, 400, 1000, 100),
+                entry("/project/labB/c.dat", 700, 2000, 2000, 200),
+            ],
+            vec![
+                entry("/project/labB/d.dat", 50, 100, 3000, 200),
+                entry("/project/labA/e.dat", 500, 40, 1000, 100),
+                entry("/project/labB/f.dat", 200, 1, 2000, 200),
+            ],
+        ];
+        chunks.iter().enumerate().map(|(i, entries)| {
+            let path = dir.join(format!("scan_chunk_{:04}.parquet", i + 1));
+            let mut writer = ParquetFileWriter::new(&path).unwrap();
+            writer.write_batch(entries).unwrap();
+            writer.close().unwrap();
+            path
+        }).collect()
+    }
+
+    const PASSWD: &str = "\
+# synthetic passwd
+alice:x:1000:100:Alice:/home/alice:/bin/bash
+
+bob:x:2000:200::/home/bob:/bin/sh
+";
+
+    fn usage(owners: &[OwnerUsage]) -> Vec<(u32, Option<&str>, u64, u64)> {
+        owners.iter().map(|o| (o.id, o.name.as_deref(), o.entries, o.bytes)).collect()
+    }
+
+    #[test]
+    fn test_parse_id_map() {
+        let names = parse_id_map(PASSWD).unwrap();
+        assert_eq!(names.len(), 2);
+        assert_eq!(names[&1000], "alice");
+        assert_eq!(names[&2000], "bob");
+
+        // Group files have the same leading fields
+        assert_eq!(parse_id_map("staff:x:200:alice,bob").unwrap()[&200], "staff");
+
+        let err = parse_id_map("alice:x:1000\nbroken line\n").unwrap_err().to_string();
+        assert!(err.starts_with("Line 2:"), "{}", err);
+        assert!(parse_id_map("alice:x:notanumber:100").is_err());
+    }
+
+    #[test]
+    fn test_totals_and_names() {
+        let temp_dir = TempDir::new().unwrap();
+        let chunks = fixture(temp_dir.path());
+        let mut options = UserReportOptions {
+            user_names: parse_id_map(PASSWD).unwrap(),
+            ..Default::default()
+        };
+        options.user_names.remove(&1000);
+
+        let report = user_report(&chunks, &options).unwrap();
+        // 1000 falls back to the scan's name; 3000 resolves nowhere on a test
+        // machine
+        assert_eq!(usage(&report.users)[..2], [
+            (1000, Some("scanned_alice"), 4, 1000),
+            (2000, Some("bob"), 2, 900),
+        ]);
+        assert_eq!((report.users[2].id, report.users[2].entries, report.users[2].bytes), (3000, 1, 50));
+        assert_eq!(report.users[0].files, 3);
+        assert!(report.users[0].by_modified_age.is_none());
+
+        // The mapping takes precedence over the scan
+        let options = UserReportOptions { user_names: parse_id_map(PASSWD).unwrap(), ..options };
+        let report = user_report(&chunks, &options).unwrap();
+        assert_eq!(report.users[0].name.as_deref(), Some("alice"));
+
+        let groups: Vec<(u32, u64)> = report.groups.iter().map(|g| (g.id, g.bytes)).collect();
+        assert_eq!(groups, [(100, 1000), (200, 950)]);
+        assert_eq!((report.entries, report.files, report.bytes), (7, 6, 1950));
+    }
+
+    #[test]
+    fn test_under_and_age_bands() {
+        let temp_dir = TempDir::new().unwrap();
+        let chunks = fixture(temp_dir.path());
+        let options = UserReportOptions {
+            under: Some("/project/labA".to_string()),
+            age_bands_as_of: Some(AS_OF),
+            ..Default::default()
+        };
+
+        let report = user_report(&chunks, &options).unwrap();
+        assert_eq!(report.users.len(), 1);
+        assert_eq!((report.users[0].entries, report.users[0].bytes), (4, 1000));
+
+        // Bands: < 30 days, 30 - 90 days, ..., 1 - 2 years, ...
+        let bands: Vec<u64> = report.users[0].by_modified_age.as_ref().unwrap()
+            .iter().map(|b| b.bytes).collect();
+        assert_eq!(bands, [400, 500, 0, 0, 100, 0, 0]);
+        assert_eq!(report.as_of, Some(AS_OF));
+
+        let mut csv = Vec::new();
+        report.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "kind,id,name,entries,files,bytes,percent,modified_0_30d,modified_30_90d,\
+                              modified_90_180d,modified_180_365d,modified_365_730d,modified_730_1825d,modified_1825d_plus");
+        assert_eq!(lines[1], "user,1000,scanned_alice,4,3,1000,100.00,400,500,0,0,100,0,0");
+        assert!(lines[2].starts_with("group,100,"), "{}", lines[2]);
+    }
+
+    #[test]
+    fn test_missing_ownership_columns() {
+        use arrow::array::StringArray;
+        use arrow::datatypes::Field;
+        use parquet::arrow::ArrowWriter;
+        use std::sync::Arc;
+
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("scan.parquet");
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("path", DataType::Utf8, false),
+            Field::new("size", DataType::UInt64, false),
+            Field::new("file_type", DataType::Utf8, false),
+        ]));
+        let batch = RecordBatch::try_new(schema.clone(), vec![
+            Arc::new(StringArray::from(vec!["/a"])),
+            Arc::new(UInt64Array::from(vec![1u64])),
+            Arc::new(StringArray::from(vec!["dat"])),
+        ]).unwrap();
+        let mut writer = ArrowWriter::try_new(File::create(&path).unwrap(), schema, None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+
+        let err = user_report(&[path], &UserReportOptions::default()).unwrap_err();
+        assert!(format!("{:#}", err).contains("rescan with ownership capture"), "{:#}", err);
+    }
+}
@@ -16,6 +16,105 @@ pub fn format_bytes(bytes: u64) -> String {
     format!("{:.2} {}", value, UNITS[exponent])
 }
 
+/// Parse a human-readable size (e.g., "2GB", "512 MiB", "1.5G", "4096")
+///
+/// Decimal units (KB, MB, GB, TB) match `format_bytes`; KiB, MiB, GiB and TiB
+/// are binary. A bare number is bytes.
+pub fn parse_bytes(input: &str) -> anyhow::Result<u64> {
+    let input = input.trim();
+    let split = input.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(input.len());
+    let (number, unit) = input.split_at(split);
+
+    let value: f64 = number.parse()
+        .map_err(|_| anyhow::anyhow!("Invalid size '{}'", input))?;
+
+    let multiplier: f64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1.0,
+        "K" | "KB" => 1e3,
+        "M" | "MB" => 1e6,
+        "G" | "GB" => 1e9,
+        "T" | "TB" => 1e12,
+        "KIB" => 1024.0,
+        "MIB" => 1024.0 * 1024.0,
+        "GIB" => 1024.0 * 1024.0 * 1024.0,
+        "TIB" => 1024.0 * 1024.0 * 1024.0 * 1024.0,
+        other => anyhow::bail!("Unknown size unit '{}' in '{}'", other, input),
+    };
+
+    Ok((value * multiplier) as u64)
+}
+
+/// Parse a human-readable duration (e.g., "30d", "2weeks", "12h 30m")
+pub fn parse_duration(input: &str) -> anyhow::Result<std::time::Duration> {
+    humantime::parse_duration(input.trim())
+        .map_err(|e| anyhow::anyhow!("Invalid duration '{}': {}", input, e))
+}
+
+/// Parse a non-negative percentage, with or without the sign (e.g., "10%", "2.5")
+pub fn parse_percent(input: &str) -> anyhow::Result<f64> {
+    let number = input.trim().trim_end_matches('%').trim();
+    match number.parse::<f64>() {
+        Ok(value) if value.is_finite() && value >= 0.0 => Ok(value),
+        _ => anyhow::bail!("Invalid percentage '{}'", input),
+    }
+}
+
+/// Parse a Unix timestamp or a UTC date (e.g., "1700000000", "2024-01-01",
+/// "2024-01-01 12:00:00") into seconds since the epoch
+pub fn parse_timestamp(input: &str) -> anyhow::Result<i64> {
+    let input = input.trim();
+    if let Ok(seconds) = input.parse::<i64>() {
+        return Ok(seconds);
+    }
+
+    // A bare date stands for midnight
+    let full = if input.len() == 10 { format!("{} 00:00:00", input) } else { input.to_string() };
+    let time = humantime::parse_rfc3339_weak(&full)
+        .map_err(|e| anyhow::anyhow!("Invalid date '{}' (expected a Unix timestamp or a date such as 2024-01-01): {}", input, e))?;
+    Ok(match time.duration_since(std::time::UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    })
+}
+
+/// Whether `path` is `dir` or below it, matching whole components (`dir`
+/// without a trailing '/'; "" stands for the filesystem root)
+pub fn is_within(path: &str, dir: &str) -> bool {
+    path.strip_prefix(dir).is_some_and(|rest| rest.is_empty() || rest.starts_with('/') || dir.is_empty())
+}
+
+/// Parent of an absolute path: "/a/b" -> "/a" -> "/" -> None
+pub fn parent_dir(path: &str) -> Option<&str> {
+    match path.rfind('/') {
+        Some(0) if path.len() > 1 => Some("/"),
+        Some(0) | None => None,
+        Some(i) => Some(&path[..i]),
+    }
+}
+
+/// Scan root of a row: its path with `depth` trailing components removed
+pub fn implied_root(path: &str, depth: u64) -> Option<&str> {
+    let mut root = path.trim_end_matches('/');
+    for _ in 0..depth {
+        root = &root[..root.rfind('/')?];
+    }
+    Some(root)
+}
+
+/// Whether an input names an HTTP(S) URL rather than a local path
+pub fn is_url(input: &str) -> bool {
+    input.starts_with("http://") || input.starts_with("https://")
+}
+
+/// CRC-32 of a file's contents, as hex
+#[cfg(feature = "parquet-writer")]
+pub fn file_checksum(path: &std::path::Path) -> anyhow::Result<String> {
+    use anyhow::Context;
+
+    crate::hash::hash_file(path, crate::hash::HashAlgo::Crc32)
+        .with_context(|| format!("Failed to read {}", path.display()))
+}
+
 /// Format duration in human-readable format
 pub fn format_duration(seconds: f64) -> String {
     if seconds < 60.0 {
@@ -73,21 +172,131 @@ pub fn validate_path(path: &std::path::Path) -> anyhow::Result<()> {
     Ok(())
 }
 
-/// Create output directory if it doesn't exist
-pub fn ensure_output_dir(path: &std::path::Path) -> anyhow::Result<()> {
-    if let Some(parent) = path.parent() {
-        if !parent.exists() {
-            std::fs::create_dir_all(parent)
-                .map_err(|e| anyhow::anyhow!("Failed to create output directory: {}", e))?;
+/// What an output path should name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputKind {
+    /// A file, created or replaced
+    File,
+
+    /// A directory, such as a partitioned dataset
+    Directory,
+}
+
+/// Check that `path` can be written as `kind` and create the directories it
+/// needs: the parent of a file, or the directory itself. Fails before any
+/// work is done if the path can't be used, naming why.
+pub fn prepare_output_path(path: &std::path::Path, kind: OutputKind) -> anyhow::Result<()> {
+    let dir = match kind {
+        OutputKind::File => {
+            if path.as_os_str().to_string_lossy().ends_with(std::path::MAIN_SEPARATOR) {
+                anyhow::bail!(
+                    "Output {} ends with a slash, but a file path is expected (e.g., {}scan.parquet)",
+                    path.display(), path.display()
+                );
+            }
+            if path.is_dir() {
+                anyhow::bail!("Output {} is a directory, but a file path is expected", path.display());
+            }
+            match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => std::path::Path::new("."),
+            }
+        }
+        OutputKind::Directory => {
+            if path.exists() && !path.is_dir() {
+                anyhow::bail!("Output {} is a file, but a directory is expected", path.display());
+            }
+            path
+        }
+    };
+
+    // The nearest existing ancestor must be a directory to create the rest in
+    if let Some(existing) = dir.ancestors().find(|a| a.exists()) {
+        if !existing.is_dir() {
+            anyhow::bail!(
+                "Cannot create output directory {}: {} is a file",
+                dir.display(), existing.display()
+            );
+        }
+        if !is_writable(existing) {
+            anyhow::bail!("Output directory {} is not writable", existing.display());
         }
     }
+    std::fs::create_dir_all(dir)
+        .map_err(|e| anyhow::anyhow!("Failed to create output directory {}: {}", dir.display(), e))?;
     Ok(())
 }
 
+/// Whether the file at `output` would be written inside the tree at `root`,
+/// comparing canonical paths so links and `..` don't hide it. The output's
+/// directory must exist, as after [`prepare_output_path`].
+pub fn output_in_tree(output: &std::path::Path, root: &std::path::Path) -> bool {
+    let dir = match output.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => std::path::Path::new("."),
+    };
+    match (dir.canonicalize(), root.canonicalize()) {
+        (Ok(dir), Ok(root)) => dir.starts_with(root),
+        _ => false,
+    }
+}
+
+fn is_writable(dir: &std::path::Path) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+    let Ok(path) = std::ffi::CString::new(dir.as_os_str().as_bytes()) else { return false };
+    unsafe { libc::access(path.as_ptr(), libc::W_OK) == 0 }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_parse_bytes() {
+        assert_eq!(parse_bytes("4096").unwrap(), 4096);
+        assert_eq!(parse_bytes("2GB").unwrap(), 2_000_000_000);
+        assert_eq!(parse_bytes("1.5 G").unwrap(), 1_500_000_000);
+        assert_eq!(parse_bytes("512MiB").unwrap(), 512 * 1024 * 1024);
+        assert!(parse_bytes("12 parsecs").is_err());
+        assert!(parse_bytes("GB").is_err());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("30d").unwrap().as_secs(), 30 * 86400);
+        assert_eq!(parse_duration("1h 30m").unwrap().as_secs(), 5400);
+        assert!(parse_duration("soon").is_err());
+    }
+
+    #[test]
+    fn test_parse_percent() {
+        assert_eq!(parse_percent("10%").unwrap(), 10.0);
+        assert_eq!(parse_percent(" 2.5 % ").unwrap(), 2.5);
+        assert_eq!(parse_percent("0").unwrap(), 0.0);
+        assert!(parse_percent("-5%").is_err());
+        assert!(parse_percent("ten").is_err());
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(parse_timestamp("1700000000").unwrap(), 1_700_000_000);
+        assert_eq!(parse_timestamp("2024-01-01").unwrap(), 1_704_067_200);
+        assert_eq!(parse_timestamp("2024-01-01 00:00:10").unwrap(), 1_704_067_210);
+        assert!(parse_timestamp("yesterday").is_err());
+    }
+
+    #[test]
+    fn test_path_helpers() {
+        assert_eq!(parent_dir("/a/b"), Some("/a"));
+        assert_eq!(parent_dir("/a"), Some("/"));
+        assert_eq!(parent_dir("/"), None);
+
+        assert!(is_within("/a/b", "/a"));
+        assert!(is_within("/a", "/a"));
+        assert!(!is_within("/ab", "/a"));
+        assert!(is_within("/ab", ""));
+    }
+
     #[test]
     fn test_format_bytes() {
         assert_eq!(format_bytes(0), "0 B");
@@ -131,4 +340,80 @@ mod tests {
         let non_existent = temp_dir.path().join("does_not_exist");
         assert!(validate_path(&non_existent).is_err());
     }
+
+    #[test]
+    fn test_output_in_tree() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let root = temp_dir.path().join("data");
+        std::fs::create_dir_all(root.join("reports")).unwrap();
+        std::fs::create_dir_all(temp_dir.path().join("out")).unwrap();
+        std::os::unix::fs::symlink(root.join("reports"), temp_dir.path().join("link")).unwrap();
+
+        assert!(output_in_tree(&root.join("scan.parquet"), &root));
+        assert!(output_in_tree(&root.join("reports/scan.parquet"), &root));
+        assert!(output_in_tree(&temp_dir.path().join("link/scan.parquet"), &root));
+        assert!(output_in_tree(&temp_dir.path().join("out/../data/scan.parquet"), &root));
+        assert!(!output_in_tree(&temp_dir.path().join("out/scan.parquet"), &root));
+        assert!(!output_in_tree(&temp_dir.path().join("scan.parquet"), &root));
+        assert!(!output_in_tree(&temp_dir.path().join("missing/scan.parquet"), &root));
+    }
+
+    #[test]
+    fn test_prepare_output_path() {
+        use tempfile::TempDir;
+
+        let temp_dir = TempDir::new().unwrap();
+        let base = temp_dir.path();
+
+        // Missing parents of a file are created, and a directory itself
+        prepare_output_path(&base.join("reports/nightly/scan.parquet"), OutputKind::File).unwrap();
+        assert!(base.join("reports/nightly").is_dir());
+        assert!(!base.join("reports/nightly/scan.parquet").exists());
+        prepare_output_path(&base.join("dataset/by_dir"), OutputKind::Directory).unwrap();
+        assert!(base.join("dataset/by_dir").is_dir());
+        prepare_output_path(&base.join("dataset/by_dir"), OutputKind::Directory).unwrap();
+        prepare_output_path(std::path::Path::new("scan.parquet"), OutputKind::File).unwrap();
+
+        // A trailing slash names a directory: fine for one, not for a file
+        let slashed = std::path::PathBuf::from(format!("{}/slashed/", base.display()));
+        let error = prepare_output_path(&slashed, OutputKind::File).unwrap_err().to_string();
+        assert!(error.contains("ends with a slash"), "{}", error);
+        assert!(!base.join("slashed").exists());
+        prepare_output_path(&slashed, OutputKind::Directory).unwrap();
+        assert!(base.join("slashed").is_dir());
+
+        // An existing directory where a file is expected
+        let error = prepare_output_path(&base.join("reports/nightly"), OutputKind::File).unwrap_err().to_string();
+        assert!(error.contains("is a directory, but a file path is expected"), "{}", error);
+
+        // A file in the way of the directories
+        std::fs::write(base.join("taken"), "").unwrap();
+        let error = prepare_output_path(&base.join("taken/scan.parquet"), OutputKind::File).unwrap_err().to_string();
+        assert!(error.contains("taken is a file"), "{}", error);
+        let error = prepare_output_path(&base.join("taken/deeper/scan.parquet"), OutputKind::File).unwrap_err().to_string();
+        assert!(error.contains("taken is a file"), "{}", error);
+        let error = prepare_output_path(&base.join("taken"), OutputKind::Directory).unwrap_err().to_string();
+        assert!(error.contains("is a file, but a directory is expected"), "{}", error);
+    }
+
+    #[test]
+    fn test_prepare_output_path_in_a_read_only_directory() {
+        use std::os::unix::fs::PermissionsExt;
+        use tempfile::TempDir;
+
+        // Root bypasses permission checks, so the directory stays writable
+        if unsafe { libc::geteuid() } == 0 {
+            eprintln!("skipping: running as root");
+            return;
+        }
+
+        let temp_dir = TempDir::new().unwrap();
+        let locked = temp_dir.path().join("locked");
+        std::fs::create_dir(&locked).unwrap();
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o555)).unwrap();
+
+        let error = prepare_output_path(&locked.join("sub/scan.parquet"), OutputKind::File).unwrap_err().to_string();
+        assert!(error.contains("is not writable"), "{}", error);
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
 }
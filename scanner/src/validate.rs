@@ -0,0 +1,602 @@
+use crate::aggregate::{self, scan_tag_fields, value_type};
+use crate::manifest::ScanManifest;
+use crate::utils::{implied_root, is_within};
+use crate::verify;
+use crate::writer::{ParquetFileWriter, LATER_COLUMNS};
+use anyhow::{Context, Result};
+use arrow::array::{Array, Int64Array, StringArray};
+use arrow::compute::cast;
+use arrow::datatypes::{DataType, Schema};
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Failures listed per check; the rest are only counted
+pub const MAX_EXAMPLES: usize = 5;
+
+/// Timestamps before the Unix epoch are implausible
+const EARLIEST_TIME: i64 = 0;
+
+/// Timestamps further than this past the reference time are implausible,
+/// allowing for clock skew between file servers
+const FUTURE_TOLERANCE_SECS: i64 = 86_400;
+
+const TIME_COLUMNS: [&str; 3] = ["modified_time", "accessed_time", "created_time"];
+
+/// Outcome of one check
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Passed,
+    Failed,
+
+    /// The check could not apply, e.g. row counts without a manifest
+    Skipped,
+}
+
+/// One integrity check over the whole input
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Check {
+    pub name: String,
+
+    pub status: CheckStatus,
+
+    /// Files, rows, or chunks examined, depending on the check
+    pub checked: u64,
+
+    pub failures: u64,
+
+    /// The first [`MAX_EXAMPLES`] failures
+    pub examples: Vec<String>,
+
+    /// Why the check was skipped
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+impl Check {
+    fn skipped(name: &str, note: &str) -> Self {
+        Check {
+            name: name.to_string(),
+            status: CheckStatus::Skipped,
+            checked: 0,
+            failures: 0,
+            examples: Vec::new(),
+            note: Some(note.to_string()),
+        }
+    }
+}
+
+/// Result of [`validate`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ValidateReport {
+    pub files: usize,
+
+    /// Rows decoded across all files
+    pub rows: u64,
+
+    /// Manifest the chunk list and scan roots came from, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manifest: Option<String>,
+
+    /// Timestamps after this Unix time, plus a day, are implausible
+    pub as_of: i64,
+
+    /// Checks in the order they are listed
+    pub checks: Vec<Check>,
+}
+
+impl ValidateReport {
+    /// True when no check failed
+    pub fn is_ok(&self) -> bool {
+        self.checks.iter().all(|c| c.status != CheckStatus::Failed)
+    }
+
+    pub fn failed_checks(&self) -> impl Iterator<Item = &Check> {
+        self.checks.iter().filter(|c| c.status == CheckStatus::Failed)
+    }
+}
+
+/// Count of items examined and failed by one check
+#[derive(Default)]
+struct Tally {
+    checked: u64,
+    failures: u64,
+    examples: Vec<String>,
+}
+
+impl Tally {
+    /// Record a failure; the message is only built while examples are kept
+    fn fail(&mut self, message: impl FnOnce() -> String) {
+        self.fail_many(1, message);
+    }
+
+    /// Record `count` failures described by one message
+    fn fail_many(&mut self, count: u64, message: impl FnOnce() -> String) {
+        if count == 0 {
+            return;
+        }
+        self.failures += count;
+        if self.examples.len() < MAX_EXAMPLES {
+            self.examples.push(message());
+        }
+    }
+
+    fn merge(&mut self, other: Tally) {
+        self.checked += other.checked;
+        self.failures += other.failures;
+        let room = MAX_EXAMPLES.saturating_sub(self.examples.len());
+        self.examples.extend(other.examples.into_iter().take(room));
+    }
+
+    fn into_check(self, name: &str) -> Check {
+        Check {
+            name: name.to_string(),
+            status: if self.failures == 0 { CheckStatus::Passed } else { CheckStatus::Failed },
+            checked: self.checked,
+            failures: self.failures,
+            examples: self.examples,
+            note: None,
+        }
+    }
+}
+
+/// Per-file results of the row-level checks
+#[derive(Default)]
+struct Tallies {
+    rows: u64,
+    parse: Tally,
+    schema: Tally,
+    nulls: Tally,
+    depth: Tally,
+    parent_path: Tally,
+    timestamps: Tally,
+}
+
+impl Tallies {
+    fn merge(mut self, other: Tallies) -> Tallies {
+        self.rows += other.rows;
+        self.parse.merge(other.parse);
+        self.schema.merge(other.schema);
+        self.nulls.merge(other.nulls);
+        self.depth.merge(other.depth);
+        self.parent_path.merge(other.parent_path);
+        self.timestamps.merge(other.timestamps);
+        self
+    }
+}
+
+/// Problems with a file's schema against the supported ones: the scan
+/// schema, optionally followed by the `scan_id`/`scan_date` columns that
+/// aggregation adds
+fn schema_problems(schema: &Schema) -> Vec<String> {
+    let scan = ParquetFileWriter::create_schema();
+    let tags = scan_tag_fields();
+
+    let mut problems = Vec::new();
+    for field in scan.fields() {
+        match schema.field_with_name(field.name()) {
+            Ok(found) if value_type(found.data_type()) != *field.data_type() => problems.push(format!(
+                "column '{}' is {:?}, expected {:?}", field.name(), found.data_type(), field.data_type()
+            )),
+            Ok(_) => {}
+            Err(_) if LATER_COLUMNS.contains(&field.name().as_str()) => {}
+            Err(_) => problems.push(format!("missing column '{}'", field.name())),
+        }
+    }
+    for found in schema.fields() {
+        if scan.field_with_name(found.name()).is_ok() {
+            continue;
+        }
+        match tags.iter().find(|f| f.name() == found.name()) {
+            Some(tag) if value_type(found.data_type()) != *tag.data_type() => problems.push(format!(
+                "column '{}' is {:?}, expected {:?}", found.name(), found.data_type(), tag.data_type()
+            )),
+            Some(_) => {}
+            None => problems.push(format!("unexpected column '{}'", found.name())),
+        }
+    }
+    problems
+}
+
+/// Number of components of `path` below `root`
+fn levels_below(path: &str, root: &str) -> u64 {
+    path[root.len()..].split('/').filter(|c| !c.is_empty()).count() as u64
+}
+
+fn format_time(time: i64) -> String {
+    match u64::try_from(time) {
+        Ok(secs) => humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(secs)).to_string(),
+        Err(_) => format!("{} seconds before 1970", time.unsigned_abs()),
+    }
+}
+
+struct RowChecker<'a> {
+    file: &'a str,
+
+    /// Scan root; None until inferred from the first row
+    root: Option<String>,
+
+    latest_time: i64,
+}
+
+impl RowChecker<'_> {
+    fn check_batch(&mut self, batch: &RecordBatch, tallies: &mut Tallies) -> Result<()> {
+        let rows = batch.num_rows();
+        let scan = ParquetFileWriter::create_schema();
+        tallies.nulls.checked += rows as u64;
+        for field in scan.fields().iter().filter(|f| !f.is_nullable()) {
+            if let Some(column) = batch.column_by_name(field.name()) {
+                let nulls = column.null_count() as u64;
+                tallies.nulls.fail_many(nulls, || format!(
+                    "{}: {} null(s) in non-nullable column '{}'", self.file, nulls, field.name()
+                ));
+            }
+        }
+
+        // Columns missing from the schema have already failed that check
+        let text = |name: &str| batch.column_by_name(name).map(|c| cast(c, &DataType::Utf8)).transpose();
+        let number = |name: &str| batch.column_by_name(name).map(|c| cast(c, &DataType::Int64)).transpose();
+        let Some(paths) = text("path")? else {
+            return Ok(());
+        };
+        let paths = paths.as_any().downcast_ref::<StringArray>().unwrap();
+        let path = |i: usize| if paths.is_valid(i) { paths.value(i) } else { "(null path)" };
+
+        if let Some(depths) = number("depth")? {
+            let depths = depths.as_any().downcast_ref::<Int64Array>().unwrap();
+            for i in (0..rows).filter(|i| paths.is_valid(*i) && depths.is_valid(*i)) {
+                let (path, depth) = (paths.value(i), depths.value(i).max(0) as u64);
+                if self.root.is_none() {
+                    self.root = implied_root(path, depth).map(str::to_string);
+                }
+                tallies.depth.checked += 1;
+                let root = self.root.as_deref().unwrap_or("");
+                if !is_within(path, root) {
+                    tallies.depth.fail(|| format!("{}: '{}' is outside the scan root '{}'", self.file, path, root));
+                } else if levels_below(path, root) != depth {
+                    tallies.depth.fail(|| format!(
+                        "{}: '{}' has depth {} but is {} level(s) below '{}'",
+                        self.file, path, depth, levels_below(path, root), root
+                    ));
+                }
+            }
+        }
+
+        if let Some(parents) = text("parent_path")? {
+            let parents = parents.as_any().downcast_ref::<StringArray>().unwrap();
+            for i in (0..rows).filter(|i| paths.is_valid(*i)) {
+                tallies.parent_path.checked += 1;
+                let parent = parents.is_valid(i).then(|| parents.value(i));
+                let ok = parent.is_some_and(|parent| {
+                    let path = path(i);
+                    // The filesystem root is its own parent
+                    (path != parent || path == "/") && is_within(path, parent.trim_end_matches('/'))
+                });
+                if !ok {
+                    tallies.parent_path.fail(|| format!(
+                        "{}: '{}' has parent_path '{}'", self.file, path(i), parent.unwrap_or("(null)")
+                    ));
+                }
+            }
+        }
+
+        let mut counted = false;
+        for column in TIME_COLUMNS {
+            let Some(times) = number(column)? else {
+                continue;
+            };
+            if !counted {
+                tallies.timestamps.checked += rows as u64;
+                counted = true;
+            }
+            let times = times.as_any().downcast_ref::<Int64Array>().unwrap();
+            for i in (0..rows).filter(|i| times.is_valid(*i)) {
+                let time = times.value(i);
+                if !(EARLIEST_TIME..=self.latest_time).contains(&time) {
+                    tallies.timestamps.fail(|| format!(
+                        "{}: '{}' has {} {} ({})", self.file, path(i), column, time, format_time(time)
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn check_file(path: &Path, root: Option<&str>, as_of: i64) -> Tallies {
+    let mut tallies = Tallies::default();
+    let file = path.display().to_string();
+    tallies.parse.checked += 1;
+
+    let builder = match File::open(path).map_err(anyhow::Error::from)
+        .and_then(|f| Ok(ParquetRecordBatchReaderBuilder::try_new(f)?))
+    {
+        Ok(builder) => builder,
+        Err(e) => {
+            tallies.parse.fail(|| format!("{}: {}", file, e));
+            return tallies;
+        }
+    };
+
+    tallies.schema.checked += 1;
+    for problem in schema_problems(builder.schema()) {
+        tallies.schema.fail(|| format!("{}: {}", file, problem));
+    }
+
+    let reader = match builder.build() {
+        Ok(reader) => reader,
+        Err(e) => {
+            tallies.parse.fail(|| format!("{}: {}", file, e));
+            return tallies;
+        }
+    };
+    let mut checker = RowChecker {
+        file: &file,
+        root: root.map(|r| r.trim_end_matches('/').to_string()),
+        latest_time: as_of.saturating_add(FUTURE_TOLERANCE_SECS),
+    };
+    for batch in reader {
+        let result = batch.map_err(anyhow::Error::from)
+            .and_then(|batch| {
+                tallies.rows += batch.num_rows() as u64;
+                checker.check_batch(&batch, &mut tallies)
+            });
+        if let Err(e) = result {
+            tallies.parse.fail(|| format!("{}: {}", file, e));
+            break;
+        }
+    }
+    tallies
+}
+
+/// Chunk files of a manifest with the scan root each was written under
+fn manifest_chunks(manifest: &ScanManifest) -> Vec<(PathBuf, String)> {
+    manifest.chunks.iter()
+        .filter(|c| !c.is_deleted())
+        .map(|chunk| {
+            // A merged manifest records the root of each source's chunks
+            let root = manifest.merged_from.iter()
+                .find(|s| (s.chunk_offset..s.chunk_offset + s.chunk_count).contains(&chunk.chunk_number))
+                .map_or(&manifest.scan_path, |s| &s.scan_path);
+            (PathBuf::from(&chunk.file_path), root.clone())
+        })
+        .collect()
+}
+
+fn row_count_check(manifest: &ScanManifest) -> Check {
+    let report = verify::verify_manifest(manifest, false);
+    let mut tally = Tally::default();
+    for chunk in report.chunks.iter().filter(|c| !c.deleted) {
+        tally.checked += 1;
+        for problem in &chunk.problems {
+            tally.fail(|| format!("chunk {} ({}): {}", chunk.chunk_number, chunk.file_path, problem));
+        }
+    }
+    for problem in &report.problems {
+        tally.fail(|| format!("manifest: {}", problem));
+    }
+    tally.into_check("row_counts")
+}
+
+/// Check a scan output before it is published.
+///
+/// `input` is anything [`aggregate::find_chunk_files`] accepts. When it is,
+/// or sits next to, a manifest, depths are checked against the manifest's
+/// scan root and chunk row counts against the manifest; otherwise each
+/// file's scan root is inferred from its first row and row counts are
+/// skipped. Every file is fully decoded, in parallel. Timestamps must fall
+/// between the Unix epoch and a day after `as_of`.
+pub fn validate(input: &Path, as_of: i64) -> Result<ValidateReport> {
+    let manifest_path = aggregate::get_manifest_path(input);
+    let manifest = match manifest_path.is_file() {
+        true => Some(ScanManifest::load_from_file(&manifest_path)
+            .with_context(|| format!("Failed to load manifest {}", manifest_path.display()))?),
+        false => None,
+    };
+
+    let files: Vec<(PathBuf, Option<String>)> = match manifest {
+        Some(ref manifest) => manifest_chunks(manifest).into_iter().map(|(f, root)| (f, Some(root))).collect(),
+        None => aggregate::find_chunk_files(input)?.into_iter().map(|f| (f, None)).collect(),
+    };
+    if files.is_empty() {
+        anyhow::bail!("No Parquet files found in: {}", input.display());
+    }
+
+    let tallies = files.par_iter()
+        .map(|(path, root)| check_file(path, root.as_deref(), as_of))
+        .reduce(Tallies::default, Tallies::merge);
+
+    let row_counts = match manifest {
+        Some(ref manifest) => row_count_check(manifest),
+        None => Check::skipped("row_counts", "no manifest to compare against"),
+    };
+    Ok(ValidateReport {
+        files: files.len(),
+        rows: tallies.rows,
+        manifest: manifest.map(|_| manifest_path.display().to_string()),
+        as_of,
+        checks: vec![
+            tallies.parse.into_check("parse"),
+            tallies.schema.into_check("schema"),
+            tallies.nulls.into_check("nulls"),
+            tallies.depth.into_check("depth"),
+            tallies.parent_path.into_check("parent_path"),
+            tallies.timestamps.into_check("timestamps"),
+            row_counts,
+        ],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::FileEntry;
+    use crate::rotating_writer::{RotatingParquetWriter, RotatingWriterConfig};
+    use arrow::array::UInt64Array;
+    use arrow::datatypes::Field;
+    use parquet::arrow::ArrowWriter;
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    const AS_OF: i64 = 1_700_000_000;
+
+    fn entry(path: &str, depth: u32) -> FileEntry {
+        FileEntry {
+            modified_time: AS_OF - 1000,
+            accessed_time: AS_OF - 10,
+            depth,
+            top_level_dir: "lab".to_string(),
+            ..FileEntry::fixture(path)
+        }
+    }
+
+    fn write_file(path: &Path, entries: &[FileEntry]) {
+        let mut writer = ParquetFileWriter::new(path).unwrap();
+        writer.write_batch(entries).unwrap();
+        writer.close().unwrap();
+    }
+
+    fn status(report: &ValidateReport, name: &str) -> CheckStatus {
+        report.checks.iter().find(|c| c.name == name).unwrap().status
+    }
+
+    fn failed(report: &ValidateReport) -> Vec<&str> {
+        report.failed_checks().map(|c| c.name.as_str()).collect()
+    }
+
+    fn healthy() -> Vec<FileEntry> {
+        vec![
+            entry("/data/lab", 1),
+            entry("/data/lab/a.txt", 2),
+            entry("/data/lab/sub/b.txt", 3),
+        ]
+    }
+
+    #[test]
+    fn test_healthy_scan_with_manifest() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = RotatingWriterConfig {
+            base_output_path: temp_dir.path().join("scan.parquet"),
+            rows_per_chunk: 2,
+            time_interval: Duration::from_secs(3600),
+        };
+        let mut writer = RotatingParquetWriter::new(config, "/data".to_string()).unwrap();
+        for entry in healthy() {
+            writer.write_batch(&[entry]).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let manifest = temp_dir.path().join("scan_manifest.json");
+        let report = validate(&manifest, AS_OF).unwrap();
+        assert!(report.is_ok(), "{:#?}", report);
+        assert_eq!((report.files, report.rows), (2, 3));
+        assert!(report.checks.iter().all(|c| c.status == CheckStatus::Passed), "{:#?}", report);
+        assert_eq!(report.checks.last().unwrap().checked, 2);
+
+        // A manifest whose row counts disagree with the chunks fails that check only
+        let mut loaded = ScanManifest::load_from_file(&manifest).unwrap();
+        loaded.chunks[1].row_count = 5;
+        loaded.save_to_file(&manifest).unwrap();
+        let report = validate(&manifest, AS_OF).unwrap();
+        assert_eq!(failed(&report), ["row_counts"]);
+    }
+
+    #[test]
+    fn test_mismatched_depth_fails_depth_check() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("scan.parquet");
+        let mut entries = healthy();
+        entries[2].depth = 2;
+        write_file(&path, &entries);
+
+        let report = validate(&path, AS_OF).unwrap();
+        assert!(!report.is_ok());
+        assert_eq!(failed(&report), ["depth"]);
+        let depth = &report.checks[3];
+        assert_eq!((depth.checked, depth.failures), (3, 1));
+        assert!(depth.examples[0].contains("'/data/lab/sub/b.txt' has depth 2 but is 3 level(s) below '/data'"),
+                "{}", depth.examples[0]);
+        // Without a manifest the root is inferred and row counts cannot be checked
+        assert_eq!(status(&report, "row_counts"), CheckStatus::Skipped);
+    }
+
+    #[test]
+    fn test_parent_path_and_timestamps() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("scan.parquet");
+        let mut entries = healthy();
+        entries[1].parent_path = "/data/other".to_string();
+        entries[2].modified_time = AS_OF + 2 * 86_400;
+        entries[2].created_time = Some(-5);
+        write_file(&path, &entries);
+
+        let report = validate(&path, AS_OF).unwrap();
+        assert_eq!(failed(&report), ["parent_path", "timestamps"]);
+        assert_eq!(report.checks[5].failures, 2);
+        assert!(report.checks[4].examples[0].contains("'/data/lab/a.txt' has parent_path '/data/other'"));
+
+        // Exactly a day in the future is still plausible
+        entries[2].modified_time = AS_OF + 86_400;
+        entries[2].created_time = Some(0);
+        entries[1].parent_path = "/data/lab".to_string();
+        write_file(&path, &entries);
+        assert!(validate(&path, AS_OF).unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_schema_nulls_and_unreadable_files() {
+        let temp_dir = TempDir::new().unwrap();
+        write_file(&temp_dir.path().join("scan_chunk_0001.parquet"), &healthy());
+
+        // A foreign file: an unknown column, most scan columns missing, and
+        // nulls where the scan schema forbids them
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("path", DataType::Utf8, true),
+            Field::new("size", DataType::UInt64, true),
+            Field::new("bogus", DataType::Utf8, true),
+        ]));
+        let batch = RecordBatch::try_new(schema.clone(), vec![
+            Arc::new(StringArray::from(vec![Some("/data/x"), None])),
+            Arc::new(UInt64Array::from(vec![None, None])),
+            Arc::new(StringArray::from(vec![Some("?"), None])),
+        ]).unwrap();
+        let file = File::create(temp_dir.path().join("scan_chunk_0002.parquet")).unwrap();
+        let mut writer = ArrowWriter::try_new(file, schema, None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+
+        std::fs::write(temp_dir.path().join("scan_chunk_0003.parquet"), b"not parquet").unwrap();
+
+        let report = validate(temp_dir.path(), AS_OF).unwrap();
+        assert_eq!(failed(&report), ["parse", "schema", "nulls"]);
+        assert_eq!((report.checks[0].checked, report.checks[0].failures), (3, 1));
+        let schema = &report.checks[1];
+        assert!(schema.examples.iter().any(|e| e.ends_with("missing column 'modified_time'")), "{:?}", schema.examples);
+        assert!(schema.failures > MAX_EXAMPLES as u64);
+        assert_eq!(schema.examples.len(), MAX_EXAMPLES);
+        assert_eq!(report.checks[2].failures, 3);
+    }
+
+    #[test]
+    fn test_supported_schemas() {
+        assert!(schema_problems(&ParquetFileWriter::create_schema()).is_empty());
+
+        let mut fields: Vec<Field> = ParquetFileWriter::create_schema().fields().iter()
+            .map(|f| f.as_ref().clone())
+            .collect();
+        fields.extend(scan_tag_fields());
+        assert!(schema_problems(&Schema::new(fields.clone())).is_empty());
+
+        fields[1] = Field::new("size", DataType::Utf8, false);
+        assert_eq!(schema_problems(&Schema::new(fields)), ["column 'size' is Utf8, expected UInt64"]);
+
+        assert_eq!(implied_root("/data/lab/a.txt", 2), Some("/data"));
+        assert_eq!(implied_root("/data", 0), Some("/data"));
+        assert_eq!(implied_root("/data", 3), None);
+    }
+}
@@ -0,0 +1,268 @@
+use crate::manifest::{ChunkMetadata, ScanManifest};
+use anyhow::Result;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::path::Path;
+
+/// Outcome of checking a single chunk against its manifest entry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChunkCheck {
+    /// Chunk number from the manifest
+    pub chunk_number: usize,
+
+    /// Chunk file path from the manifest
+    pub file_path: String,
+
+    /// Row count recorded in the manifest
+    pub expected_rows: u64,
+
+    /// Row count found in the file (None if it could not be read)
+    pub actual_rows: Option<u64>,
+
+    /// File size recorded in the manifest
+    pub expected_size: u64,
+
+    /// File size found on disk (None if the file is missing)
+    pub actual_size: Option<u64>,
+
+    /// Chunk was deleted by a retention policy and was not checked
+    #[serde(default)]
+    pub deleted: bool,
+
+    /// Human-readable description of every mismatch found
+    pub problems: Vec<String>,
+}
+
+impl ChunkCheck {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Result of verifying a manifest against its chunk files
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VerifyReport {
+    /// Manifest that was verified
+    pub manifest_path: String,
+
+    /// Whether every row group was decoded
+    pub deep: bool,
+
+    /// Per-chunk results in manifest order
+    pub chunks: Vec<ChunkCheck>,
+
+    /// `total_rows` recorded in the manifest
+    pub manifest_total_rows: u64,
+
+    /// Sum of the per-chunk `row_count` values in the manifest
+    pub listed_rows: u64,
+
+    /// Sum of the row counts found in the chunk files
+    pub found_rows: u64,
+
+    /// Manifest-level problems (totals that don't add up)
+    pub problems: Vec<String>,
+}
+
+impl VerifyReport {
+    /// True when no chunk or manifest-level problem was found
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty() && self.chunks.iter().all(|c| c.is_ok())
+    }
+
+    /// Number of problems across the manifest and all chunks
+    pub fn problem_count(&self) -> usize {
+        self.problems.len() + self.chunks.iter().map(|c| c.problems.len()).sum::<usize>()
+    }
+}
+
+/// Verify a manifest file against the chunk files it lists
+pub fn verify_manifest_file<P: AsRef<Path>>(manifest_path: P, deep: bool) -> Result<VerifyReport> {
+    let manifest = ScanManifest::load_from_file(manifest_path.as_ref())?;
+    let mut report = verify_manifest(&manifest, deep);
+    report.manifest_path = manifest_path.as_ref().to_string_lossy().to_string();
+    Ok(report)
+}
+
+/// Verify a loaded manifest against the chunk files it lists.
+///
+/// Shallow mode compares file sizes and the row counts stored in each
+/// Parquet footer; deep mode additionally decodes every row group so
+/// corruption in the middle of a file is caught.
+pub fn verify_manifest(manifest: &ScanManifest, deep: bool) -> VerifyReport {
+    let chunks: Vec<ChunkCheck> = manifest.chunks.iter()
+        .map(|chunk| check_chunk(chunk, deep))
+        .collect();
+
+    let listed_rows: u64 = manifest.chunks.iter().map(|c| c.row_count).sum();
+    let found_rows: u64 = chunks.iter().filter_map(|c| c.actual_rows).sum();
+
+    let mut problems = Vec::new();
+    if listed_rows != manifest.total_rows {
+        problems.push(format!(
+            "manifest total_rows is {} but chunk row counts sum to {}",
+            manifest.total_rows, listed_rows
+        ));
+    }
+    if manifest.chunk_count != manifest.chunks.len() {
+        problems.push(format!(
+            "manifest chunk_count is {} but {} chunks are listed",
+            manifest.chunk_count,
+            manifest.chunks.len()
+        ));
+    }
+
+    VerifyReport {
+        manifest_path: String::new(),
+        deep,
+        chunks,
+        manifest_total_rows: manifest.total_rows,
+        listed_rows,
+        found_rows,
+        problems,
+    }
+}
+
+fn check_chunk(chunk: &ChunkMetadata, deep: bool) -> ChunkCheck {
+    let mut check = ChunkCheck {
+        chunk_number: chunk.chunk_number,
+        file_path: chunk.file_path.clone(),
+        expected_rows: chunk.row_count,
+        actual_rows: None,
+        expected_size: chunk.file_size,
+        actual_size: None,
+        deleted: chunk.is_deleted(),
+        problems: Vec::new(),
+    };
+
+    // Deleted by a retention policy after aggregation; nothing to check
+    if check.deleted {
+        return check;
+    }
+
+    let path = Path::new(&chunk.file_path);
+    match std::fs::metadata(path) {
+        Ok(metadata) => check.actual_size = Some(metadata.len()),
+        Err(e) => {
+            check.problems.push(format!("missing: {}", e));
+            return check;
+        }
+    }
+
+    if check.actual_size != Some(chunk.file_size) {
+        check.problems.push(format!(
+            "size mismatch: manifest {} bytes, file {} bytes",
+            chunk.file_size,
+            check.actual_size.unwrap_or(0)
+        ));
+    }
+
+    let rows = if deep { count_rows_deep(path) } else { count_rows_footer(path) };
+    match rows {
+        Ok(rows) => {
+            check.actual_rows = Some(rows);
+            if rows != chunk.row_count {
+                check.problems.push(format!(
+                    "row count mismatch: manifest {}, file {}",
+                    chunk.row_count, rows
+                ));
+            }
+        }
+        Err(e) => check.problems.push(format!("unreadable: {}", e)),
+    }
+
+    check
+}
+
+/// Row count recorded in the Parquet footer
+pub fn count_rows_footer(path: &Path) -> Result<u64> {
+    let reader = SerializedFileReader::new(File::open(path)?)?;
+    Ok(reader.metadata().file_metadata().num_rows() as u64)
+}
+
+/// Row count obtained by decoding every row group
+pub fn count_rows_deep(path: &Path) -> Result<u64> {
+    let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?.build()?;
+    let mut rows = 0u64;
+    for batch in reader {
+        rows += batch?.num_rows() as u64;
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::FileEntry;
+    use crate::rotating_writer::{RotatingParquetWriter, RotatingWriterConfig};
+    use std::path::PathBuf;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    fn create_test_entry(path: &str) -> FileEntry {
+        FileEntry::fixture(path)
+    }
+
+    fn write_scan(dir: &Path) -> PathBuf {
+        let config = RotatingWriterConfig {
+            base_output_path: dir.join("scan.parquet"),
+            rows_per_chunk: 3,
+            time_interval: Duration::from_secs(3600),
+        };
+
+        let mut writer = RotatingParquetWriter::new(config, "/test".to_string()).unwrap();
+        for i in 0..7 {
+            writer.write_batch(&[create_test_entry(&format!("/test/file{}.txt", i))]).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        dir.join("scan_manifest.json")
+    }
+
+    #[test]
+    fn test_verify_healthy_manifest() {
+        let temp_dir = TempDir::new().unwrap();
+        let manifest_path = write_scan(temp_dir.path());
+
+        for deep in [false, true] {
+            let report = verify_manifest_file(&manifest_path, deep).unwrap();
+            assert!(report.is_ok(), "unexpected problems: {:?}", report);
+            assert_eq!(report.found_rows, 7);
+            assert_eq!(report.listed_rows, 7);
+        }
+    }
+
+    #[test]
+    fn test_verify_missing_chunk() {
+        let temp_dir = TempDir::new().unwrap();
+        let manifest_path = write_scan(temp_dir.path());
+
+        let manifest = ScanManifest::load_from_file(&manifest_path).unwrap();
+        std::fs::remove_file(&manifest.chunks[1].file_path).unwrap();
+
+        let report = verify_manifest_file(&manifest_path, false).unwrap();
+        assert!(!report.is_ok());
+        assert!(report.chunks[0].is_ok());
+        assert!(report.chunks[1].problems[0].starts_with("missing"));
+        assert_eq!(report.chunks[1].actual_rows, None);
+    }
+
+    #[test]
+    fn test_verify_row_count_mismatch() {
+        let temp_dir = TempDir::new().unwrap();
+        let manifest_path = write_scan(temp_dir.path());
+
+        let mut manifest = ScanManifest::load_from_file(&manifest_path).unwrap();
+        manifest.chunks[0].row_count += 5;
+        manifest.save_to_file(&manifest_path).unwrap();
+
+        let report = verify_manifest_file(&manifest_path, false).unwrap();
+        assert!(!report.is_ok());
+        assert!(report.chunks[0].problems.iter().any(|p| p.contains("row count mismatch")));
+        // The listed counts no longer add up to total_rows either
+        assert_eq!(report.problems.len(), 1);
+        assert_eq!(report.problem_count(), 2);
+    }
+}
@@ -0,0 +1,845 @@
+use crate::models::{path_fields, FileEntry};
+use crate::writer::{entry_columns, schema_version_entry, ParquetFileWriter};
+use anyhow::{Context, Result};
+use arrow::array::{ArrayRef, Int64Array, StringArray};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use arrow::record_batch::RecordBatch;
+use crossbeam_channel::{Receiver, RecvTimeoutError};
+use jwalk::WalkDir;
+use notify::event::{ModifyKind, RenameMode};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{debug, info, warn};
+
+/// How often the event loop checks for a stop request, rotation, and re-scans
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// How change records are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WatchFormat {
+    /// One JSON object per line, flushed as changes arrive
+    Jsonl,
+
+    /// Parquet files, readable once rotated
+    ParquetChunks,
+}
+
+impl WatchFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            WatchFormat::Jsonl => "jsonl",
+            WatchFormat::ParquetChunks => "parquet",
+        }
+    }
+}
+
+/// Kind of change a record describes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventType {
+    Create,
+    Modify,
+    Delete,
+}
+
+impl EventType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EventType::Create => "create",
+            EventType::Modify => "modify",
+            EventType::Delete => "delete",
+        }
+    }
+}
+
+/// One change observed under the watched path
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ChangeRecord {
+    /// The entry after the change. A deleted entry can't be stat'ed, so only
+    /// its path columns are filled in
+    #[serde(flatten)]
+    pub entry: FileEntry,
+
+    pub event_type: EventType,
+
+    /// When the change was observed (Unix timestamp in seconds)
+    pub event_time: i64,
+}
+
+/// Configuration for [`Watch`]
+#[derive(Debug, Clone)]
+pub struct WatchConfig {
+    /// Directory to watch, recursively
+    pub path: PathBuf,
+
+    /// Directory receiving the change files and their manifest
+    pub output_dir: PathBuf,
+
+    pub format: WatchFormat,
+
+    /// Records per change file before rotation
+    pub rows_per_file: usize,
+
+    /// Time a change file stays open before rotation
+    pub file_interval: Duration,
+
+    /// How often subtrees that can't be watched are re-scanned instead
+    pub rescan_interval: Duration,
+}
+
+/// A closed change file
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct WatchFile {
+    /// File name within the output directory
+    pub file_name: String,
+
+    pub row_count: u64,
+
+    pub file_size: u64,
+
+    /// Event time of the first and last record
+    pub first_event: i64,
+    pub last_event: i64,
+}
+
+/// Lists the change files written by `watch`, oldest first.
+///
+/// The file still being written is not listed until it is rotated.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct WatchManifest {
+    pub watched_path: String,
+
+    pub format: WatchFormat,
+
+    pub started_at: i64,
+
+    pub updated_at: i64,
+
+    pub total_records: u64,
+
+    pub files: Vec<WatchFile>,
+
+    /// Subtrees polled by re-scanning because inotify ran out of watches
+    #[serde(default)]
+    pub rescanned_paths: Vec<String>,
+}
+
+impl WatchManifest {
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let contents = fs::read_to_string(path.as_ref())
+            .context("Failed to read watch manifest")?;
+        serde_json::from_str(&contents).context("Failed to parse watch manifest JSON")
+    }
+
+    /// Write the manifest next to its final location and rename it into place
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let json = serde_json::to_string_pretty(self)
+            .context("Failed to serialize watch manifest")?;
+        let temp = path.with_extension("json.tmp");
+        fs::write(&temp, json).context("Failed to write watch manifest")?;
+        fs::rename(&temp, path).context("Failed to replace watch manifest")
+    }
+}
+
+/// Location of the manifest in a watch output directory
+pub fn manifest_path(output_dir: &Path) -> PathBuf {
+    output_dir.join("watch_manifest.json")
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+/// Schema of Parquet change files: the FileEntry columns, then the event's
+fn change_schema() -> SchemaRef {
+    let mut fields: Vec<Field> = ParquetFileWriter::create_schema().fields().iter()
+        .map(|f| f.as_ref().clone())
+        .collect();
+    fields.push(Field::new("event_type", DataType::Utf8, false));
+    fields.push(Field::new("event_time", DataType::Int64, false));
+    Arc::new(Schema::new(fields))
+}
+
+enum ChangeWriter {
+    Jsonl(BufWriter<File>),
+    Parquet(Box<ArrowWriter<File>>),
+}
+
+/// The change file being written
+struct OpenFile {
+    writer: ChangeWriter,
+    path: PathBuf,
+    rows: u64,
+    first_event: i64,
+    last_event: i64,
+    opened: Instant,
+}
+
+/// Change files rotated by rows and time, listed in a manifest
+struct ChangeLog {
+    config: WatchConfig,
+    schema: SchemaRef,
+    manifest: WatchManifest,
+    open: Option<OpenFile>,
+    next_file: usize,
+}
+
+impl ChangeLog {
+    /// Start a new manifest, or continue one left by an earlier watch of the same path
+    fn open(config: WatchConfig, root: &Path) -> Result<Self> {
+        fs::create_dir_all(&config.output_dir)
+            .with_context(|| format!("Failed to create {}", config.output_dir.display()))?;
+
+        let watched_path = root.to_string_lossy().to_string();
+        let path = manifest_path(&config.output_dir);
+        let manifest = if path.exists() {
+            let mut m = WatchManifest::load_from_file(&path)?;
+            if m.watched_path != watched_path {
+                anyhow::bail!(
+                    "{} already holds changes for {}, not {}",
+                    config.output_dir.display(), m.watched_path, watched_path
+                );
+            }
+            if m.format != config.format {
+                anyhow::bail!(
+                    "{} already holds {} change files",
+                    config.output_dir.display(), m.format.extension()
+                );
+            }
+            info!("Continuing watch manifest with {} existing file(s)", m.files.len());
+            m.rescanned_paths.clear();
+            m
+        } else {
+            WatchManifest {
+                watched_path,
+                format: config.format,
+                started_at: now(),
+                updated_at: now(),
+                total_records: 0,
+                files: Vec::new(),
+                rescanned_paths: Vec::new(),
+            }
+        };
+
+        let next_file = manifest.files.len() + 1;
+        let log = Self { config, schema: change_schema(), manifest, open: None, next_file };
+        log.save_manifest()?;
+        Ok(log)
+    }
+
+    fn save_manifest(&self) -> Result<()> {
+        self.manifest.save_to_file(manifest_path(&self.config.output_dir))
+    }
+
+    fn open_file(&mut self) -> Result<()> {
+        // Skip over a file left unlisted by a watch that was killed
+        let path = loop {
+            let path = self.config.output_dir.join(format!(
+                "changes_{:04}.{}", self.next_file, self.config.format.extension()
+            ));
+            self.next_file += 1;
+            if !path.exists() {
+                break path;
+            }
+        };
+
+        let file = File::create(&path)
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        let writer = match self.config.format {
+            WatchFormat::Jsonl => ChangeWriter::Jsonl(BufWriter::new(file)),
+            WatchFormat::ParquetChunks => {
+                let props = WriterProperties::builder()
+                    .set_compression(Compression::SNAPPY)
+                    .build();
+                let mut writer = ArrowWriter::try_new(file, self.schema.clone(), Some(props))
+                    .context("Failed to create Arrow writer")?;
+                writer.append_key_value_metadata(schema_version_entry());
+                ChangeWriter::Parquet(Box::new(writer))
+            }
+        };
+        info!("Writing changes to {}", path.display());
+
+        self.open = Some(OpenFile {
+            writer,
+            path,
+            rows: 0,
+            first_event: 0,
+            last_event: 0,
+            opened: Instant::now(),
+        });
+        Ok(())
+    }
+
+    fn write(&mut self, records: &[ChangeRecord]) -> Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        if self.open.is_none() {
+            self.open_file()?;
+        }
+        let schema = self.schema.clone();
+        let file = self.open.as_mut().unwrap();
+
+        match file.writer {
+            ChangeWriter::Jsonl(ref mut out) => {
+                for record in records {
+                    serde_json::to_writer(&mut *out, record)?;
+                    out.write_all(b"\n")?;
+                }
+                // Keep the file tailable
+                out.flush()?;
+            }
+            ChangeWriter::Parquet(ref mut out) => {
+                let entries: Vec<FileEntry> = records.iter().map(|r| r.entry.clone()).collect();
+                let mut columns = entry_columns(&entries);
+                let event_types: StringArray = records.iter().map(|r| Some(r.event_type.as_str())).collect();
+                let event_times: Int64Array = records.iter().map(|r| Some(r.event_time)).collect();
+                columns.push(Arc::new(event_types) as ArrayRef);
+                columns.push(Arc::new(event_times) as ArrayRef);
+                let batch = RecordBatch::try_new(schema, columns)
+                    .context("Failed to create record batch")?;
+                out.write(&batch).context("Failed to write record batch")?;
+            }
+        }
+
+        if file.rows == 0 {
+            file.first_event = records[0].event_time;
+        }
+        file.last_event = records[records.len() - 1].event_time;
+        file.rows += records.len() as u64;
+
+        if file.rows >= self.config.rows_per_file as u64 {
+            self.close_file()?;
+        }
+        Ok(())
+    }
+
+    /// Rotate the open file if it has been open for the configured interval
+    fn poll_rotation(&mut self) -> Result<()> {
+        if self.open.as_ref().is_some_and(|f| f.opened.elapsed() >= self.config.file_interval) {
+            self.close_file()?;
+        }
+        Ok(())
+    }
+
+    fn close_file(&mut self) -> Result<()> {
+        let file = match self.open.take() {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+
+        match file.writer {
+            ChangeWriter::Jsonl(mut out) => out.flush()?,
+            ChangeWriter::Parquet(out) => {
+                out.close().context("Failed to close Parquet writer")?;
+            }
+        }
+
+        let file_size = fs::metadata(&file.path).map(|m| m.len()).unwrap_or(0);
+        self.manifest.files.push(WatchFile {
+            file_name: file.path.file_name().unwrap().to_string_lossy().to_string(),
+            row_count: file.rows,
+            file_size,
+            first_event: file.first_event,
+            last_event: file.last_event,
+        });
+        self.manifest.total_records += file.rows;
+        self.manifest.updated_at = now();
+        self.save_manifest()?;
+
+        info!("Completed {}: {} change(s)", file.path.display(), file.rows);
+        Ok(())
+    }
+
+    fn record_rescanned(&mut self, path: &Path) -> Result<()> {
+        self.manifest.rescanned_paths.push(path.to_string_lossy().to_string());
+        self.manifest.updated_at = now();
+        self.save_manifest()
+    }
+
+    fn finish(mut self) -> Result<WatchManifest> {
+        self.close_file()?;
+        self.manifest.updated_at = now();
+        self.save_manifest()?;
+        Ok(self.manifest)
+    }
+}
+
+/// Entry for `path`, or None if it no longer exists
+fn stat(path: &Path, root: &Path) -> Option<FileEntry> {
+    let metadata = fs::metadata(path).ok()?;
+    FileEntry::from_path(path, &metadata, root)
+        .map_err(|e| debug!("Failed to read {}: {}", path.display(), e))
+        .ok()
+}
+
+/// Entry for a path that is gone; whether it was a directory is not known
+fn removed_entry(path: &Path, root: &Path) -> FileEntry {
+    let (parent_path, depth, top_level_dir) = path_fields(path, root);
+    FileEntry {
+        path: path.to_string_lossy().to_string(),
+        size: 0,
+        modified_time: 0,
+        accessed_time: 0,
+        created_time: None,
+        file_type: path.extension()
+            .map(|e| e.to_string_lossy().to_string())
+            .unwrap_or_else(|| "no_extension".to_string()),
+        inode: 0,
+        permissions: 0,
+        uid: 0,
+        gid: 0,
+        owner: None,
+        group: None,
+        parent_path,
+        depth,
+        top_level_dir,
+        link_target: None,
+        device: 1,
+        nlink: 1,
+        raw_mode: 0,
+    }
+}
+
+/// Every entry under `dir`, `dir` itself included
+fn walk(dir: &Path, root: &Path) -> Vec<FileEntry> {
+    WalkDir::new(dir)
+        .skip_hidden(false)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| stat(&entry.path(), root))
+        .collect()
+}
+
+/// Whether a re-scan should report `current` as a modification of `previous`.
+/// Access times are left out so reading a file doesn't count.
+fn changed(previous: &FileEntry, current: &FileEntry) -> bool {
+    previous.size != current.size
+        || previous.modified_time != current.modified_time
+        || previous.inode != current.inode
+        || previous.permissions != current.permissions
+        || previous.uid != current.uid
+        || previous.gid != current.gid
+}
+
+/// Subtrees that inotify can't watch, polled by comparing successive re-scans
+#[derive(Default)]
+struct Rescans {
+    /// Entries seen by the last re-scan of each subtree
+    subtrees: HashMap<PathBuf, HashMap<String, FileEntry>>,
+}
+
+impl Rescans {
+    fn covers(&self, path: &Path) -> bool {
+        self.subtrees.keys().any(|dir| path.starts_with(dir))
+    }
+
+    /// Start polling `dir`; its current contents are the baseline and are not reported
+    fn add(&mut self, dir: &Path, root: &Path) {
+        self.subtrees.retain(|existing, _| !existing.starts_with(dir));
+        let snapshot = walk(dir, root).into_iter().map(|e| (e.path.clone(), e)).collect();
+        self.subtrees.insert(dir.to_path_buf(), snapshot);
+    }
+
+    /// Re-scan every subtree and report what changed since the last re-scan
+    fn rescan(&mut self, root: &Path) -> Vec<ChangeRecord> {
+        let event_time = now();
+        let mut records = Vec::new();
+
+        for (dir, snapshot) in self.subtrees.iter_mut() {
+            let mut previous = std::mem::take(snapshot);
+            for entry in walk(dir, root) {
+                let event_type = match previous.remove(&entry.path) {
+                    None => Some(EventType::Create),
+                    Some(ref old) if changed(old, &entry) => Some(EventType::Modify),
+                    Some(_) => None,
+                };
+                if let Some(event_type) = event_type {
+                    records.push(ChangeRecord { entry: entry.clone(), event_type, event_time });
+                }
+                snapshot.insert(entry.path.clone(), entry);
+            }
+            for (path, _) in previous {
+                records.push(ChangeRecord {
+                    entry: removed_entry(Path::new(&path), root),
+                    event_type: EventType::Delete,
+                    event_time,
+                });
+            }
+        }
+
+        records
+    }
+}
+
+/// Whether `error` means inotify ran out of watch descriptors
+fn is_watch_limit(error: &notify::Error) -> bool {
+    matches!(error.kind, notify::ErrorKind::MaxFilesWatch)
+}
+
+/// A running watch of one directory
+pub struct Watch {
+    root: PathBuf,
+    output_dir: PathBuf,
+    rescan_interval: Duration,
+    watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    log: ChangeLog,
+    rescans: Rescans,
+}
+
+impl Watch {
+    /// Start watching `config.path`.
+    ///
+    /// Changes made after this returns are recorded once [`Watch::run`] is
+    /// called. If inotify runs out of watch descriptors, the whole path is
+    /// re-scanned every `rescan_interval` instead.
+    pub fn start(config: &WatchConfig) -> Result<Self> {
+        let root = fs::canonicalize(&config.path)
+            .with_context(|| format!("Failed to resolve {}", config.path.display()))?;
+        let log = ChangeLog::open(config.clone(), &root)?;
+        let output_dir = fs::canonicalize(&config.output_dir)?;
+
+        let (tx, events) = crossbeam_channel::unbounded();
+        let watcher = notify::recommended_watcher(move |event| {
+            let _ = tx.send(event);
+        })
+        .context("Failed to create file watcher")?;
+
+        let mut watch = Self {
+            root: root.clone(),
+            output_dir,
+            rescan_interval: config.rescan_interval,
+            watcher,
+            events,
+            log,
+            rescans: Rescans::default(),
+        };
+
+        match watch.watcher.watch(&root, RecursiveMode::Recursive) {
+            Ok(()) => info!("Watching {}", root.display()),
+            Err(e) if is_watch_limit(&e) => {
+                // Drop whatever part of the tree was watched before the limit hit
+                let _ = watch.watcher.unwatch(&root);
+                watch.fall_back(&root)?;
+            }
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to watch {}", root.display()));
+            }
+        }
+
+        Ok(watch)
+    }
+
+    /// Re-scan `dir` periodically since it can't be watched
+    fn fall_back(&mut self, dir: &Path) -> Result<()> {
+        if self.rescans.covers(dir) {
+            return Ok(());
+        }
+        warn!(
+            "Out of inotify watches (see fs.inotify.max_user_watches); re-scanning {} every {} instead",
+            dir.display(),
+            humantime::format_duration(self.rescan_interval)
+        );
+        self.rescans.add(dir, &self.root);
+        self.log.record_rescanned(dir)
+    }
+
+    /// Records for one notify event. Renames are a delete of the old path and
+    /// a create of the new one.
+    fn records(&self, event: Event) -> Vec<ChangeRecord> {
+        let event_time = now();
+        let root = &self.root;
+        let created = |path: &Path| stat(path, root)
+            .map(|entry| ChangeRecord { entry, event_type: EventType::Create, event_time });
+        let deleted = |path: &Path| ChangeRecord {
+            entry: removed_entry(path, root),
+            event_type: EventType::Delete,
+            event_time,
+        };
+
+        let paths: Vec<&PathBuf> = event.paths.iter()
+            .filter(|p| !p.starts_with(&self.output_dir))
+            .collect();
+
+        let mut records = Vec::new();
+        match event.kind {
+            EventKind::Create(_) => {
+                for path in paths {
+                    let Some(record) = created(path) else { continue };
+                    let is_dir = record.entry.file_type == "directory";
+                    records.push(record);
+
+                    // Entries made inside a new directory before its watch
+                    // was added produce no events, so list them here. Ones
+                    // made after may be reported twice.
+                    if is_dir {
+                        records.extend(walk(path, root).into_iter()
+                            .filter(|entry| Path::new(&entry.path) != path.as_path())
+                            .map(|entry| ChangeRecord { entry, event_type: EventType::Create, event_time }));
+                    }
+                }
+            }
+            EventKind::Modify(ModifyKind::Name(mode)) => {
+                match (mode, paths.as_slice()) {
+                    (RenameMode::From, _) => records.extend(paths.iter().map(|p| deleted(p))),
+                    (RenameMode::To, _) => records.extend(paths.iter().filter_map(|p| created(p))),
+                    (RenameMode::Both, [from, to]) => {
+                        records.push(deleted(from));
+                        records.extend(created(to));
+                    }
+                    // Which side of the rename this is isn't known; check
+                    _ => records.extend(paths.iter().map(|p| created(p).unwrap_or_else(|| deleted(p)))),
+                }
+            }
+            EventKind::Modify(_) => {
+                // A path already gone will be reported by its remove event
+                records.extend(paths.iter().filter_map(|p| stat(p, root)).map(|entry| {
+                    ChangeRecord { entry, event_type: EventType::Modify, event_time }
+                }));
+            }
+            EventKind::Remove(_) => records.extend(paths.iter().map(|p| deleted(p))),
+            EventKind::Access(_) | EventKind::Any | EventKind::Other => {}
+        }
+        records
+    }
+
+    fn handle(&mut self, event: notify::Result<Event>) -> Result<()> {
+        match event {
+            Ok(event) => {
+                let records = self.records(event);
+                self.log.write(&records)
+            }
+            Err(e) if is_watch_limit(&e) => {
+                let paths = if e.paths.is_empty() { vec![self.root.clone()] } else { e.paths };
+                for path in paths {
+                    self.fall_back(&path)?;
+                }
+                Ok(())
+            }
+            Err(e) => {
+                warn!("Watch error: {}", e);
+                Ok(())
+            }
+        }
+    }
+
+    /// Record changes until `stop` is set, then close the open change file
+    /// and return the final manifest
+    pub fn run(mut self, stop: &AtomicBool) -> Result<WatchManifest> {
+        let mut next_rescan = Instant::now() + self.rescan_interval;
+
+        while !stop.load(Ordering::Relaxed) {
+            match self.events.recv_timeout(POLL_INTERVAL) {
+                Ok(event) => self.handle(event)?,
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => anyhow::bail!("File watcher stopped unexpectedly"),
+            }
+
+            if Instant::now() >= next_rescan {
+                let records = self.rescans.rescan(&self.root);
+                self.log.write(&records)?;
+                next_rescan = Instant::now() + self.rescan_interval;
+            }
+
+            self.log.poll_rotation()?;
+        }
+
+        // Record what was already observed before stopping
+        while let Ok(event) = self.events.try_recv() {
+            self.handle(event)?;
+        }
+
+        drop(self.watcher);
+        self.log.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use std::thread;
+    use tempfile::TempDir;
+
+    fn config(path: &Path, output_dir: &Path, format: WatchFormat) -> WatchConfig {
+        WatchConfig {
+            path: path.to_path_buf(),
+            output_dir: output_dir.to_path_buf(),
+            format,
+            rows_per_file: 100_000,
+            file_interval: Duration::from_secs(3600),
+            rescan_interval: Duration::from_secs(3600),
+        }
+    }
+
+    /// Run a watch while `changes` touches the tree, then stop it
+    fn watch_while(config: &WatchConfig, changes: impl FnOnce()) -> WatchManifest {
+        let watch = Watch::start(config).unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let stop = stop.clone();
+            thread::spawn(move || watch.run(&stop))
+        };
+
+        changes();
+        // Give inotify time to deliver the last events
+        thread::sleep(Duration::from_millis(500));
+        stop.store(true, Ordering::Relaxed);
+        handle.join().unwrap().unwrap()
+    }
+
+    fn read_jsonl(output_dir: &Path, manifest: &WatchManifest) -> Vec<ChangeRecord> {
+        manifest.files.iter()
+            .flat_map(|f| fs::read_to_string(output_dir.join(&f.file_name)).unwrap()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect::<Vec<ChangeRecord>>())
+            .collect()
+    }
+
+    fn has(records: &[ChangeRecord], path: &Path, event_type: EventType) -> bool {
+        let path = path.to_string_lossy();
+        records.iter().any(|r| r.entry.path == path && r.event_type == event_type)
+    }
+
+    #[test]
+    fn test_watch_records_create_modify_delete() {
+        let dir = TempDir::new().unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        // Output inside the watched tree must not record its own writes
+        let output_dir = root.join("live");
+        let config = config(&root, &output_dir, WatchFormat::Jsonl);
+        let file = root.join("data.bin");
+
+        let manifest = watch_while(&config, || {
+            fs::write(&file, "a").unwrap();
+            thread::sleep(Duration::from_millis(200));
+            fs::write(&file, "abcdef").unwrap();
+            thread::sleep(Duration::from_millis(200));
+            fs::remove_file(&file).unwrap();
+        });
+
+        let records = read_jsonl(&output_dir, &manifest);
+        assert!(has(&records, &file, EventType::Create), "{:?}", records);
+        assert!(has(&records, &file, EventType::Modify), "{:?}", records);
+        assert!(has(&records, &file, EventType::Delete), "{:?}", records);
+        assert!(records.iter().all(|r| !r.entry.path.starts_with(&*output_dir.to_string_lossy())));
+        assert!(records.iter().all(|r| r.event_time > 0));
+
+        let modified = records.iter().rev()
+            .find(|r| r.event_type == EventType::Modify)
+            .unwrap();
+        assert_eq!(modified.entry.size, 6);
+        assert_eq!(modified.entry.file_type, "bin");
+        assert_eq!(manifest.total_records, records.len() as u64);
+
+        let saved = WatchManifest::load_from_file(manifest_path(&output_dir)).unwrap();
+        assert_eq!(saved, manifest);
+    }
+
+    #[test]
+    fn test_watch_new_directory_lists_its_contents() {
+        let dir = TempDir::new().unwrap();
+        let output_dir = TempDir::new().unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        let config = config(&root, output_dir.path(), WatchFormat::Jsonl);
+
+        let manifest = watch_while(&config, || {
+            fs::create_dir_all(root.join("a/b")).unwrap();
+            fs::write(root.join("a/b/inner.txt"), "x").unwrap();
+        });
+
+        let records = read_jsonl(output_dir.path(), &manifest);
+        assert!(has(&records, &root.join("a"), EventType::Create), "{:?}", records);
+        assert!(has(&records, &root.join("a/b/inner.txt"), EventType::Create), "{:?}", records);
+    }
+
+    #[test]
+    fn test_parquet_chunks_rotate_and_append_to_manifest() {
+        let dir = TempDir::new().unwrap();
+        let output_dir = TempDir::new().unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        let mut config = config(&root, output_dir.path(), WatchFormat::ParquetChunks);
+        config.rows_per_file = 1;
+
+        let manifest = watch_while(&config, || {
+            fs::write(root.join("one.txt"), "1").unwrap();
+            thread::sleep(Duration::from_millis(100));
+            fs::remove_file(root.join("one.txt")).unwrap();
+        });
+        assert!(manifest.files.len() >= 2, "{:?}", manifest);
+        assert!(manifest.files.iter().all(|f| f.row_count == 1 && f.file_name.ends_with(".parquet")));
+
+        let mut event_types = Vec::new();
+        for f in &manifest.files {
+            let file = File::open(output_dir.path().join(&f.file_name)).unwrap();
+            for batch in ParquetRecordBatchReaderBuilder::try_new(file).unwrap().build().unwrap() {
+                let batch = batch.unwrap();
+                assert_eq!(batch.schema().fields(), change_schema().fields());
+                let column = batch.column_by_name("event_type").unwrap()
+                    .as_any().downcast_ref::<StringArray>().unwrap();
+                event_types.extend(column.iter().map(|v| v.unwrap().to_string()));
+            }
+        }
+        assert_eq!(event_types.first().map(String::as_str), Some("create"));
+        assert_eq!(event_types.last().map(String::as_str), Some("delete"));
+
+        // A second watch of the same path continues the manifest
+        let again = watch_while(&config, || fs::write(root.join("two.txt"), "2").unwrap());
+        assert!(again.files.len() > manifest.files.len());
+        assert_eq!(again.files[..manifest.files.len()], manifest.files[..]);
+        assert_eq!(again.started_at, manifest.started_at);
+
+        let mismatched = WatchConfig { format: WatchFormat::Jsonl, ..config };
+        assert!(Watch::start(&mismatched).is_err());
+    }
+
+    #[test]
+    fn test_rescan_reports_changes_since_last_rescan() {
+        let dir = TempDir::new().unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        fs::create_dir(root.join("sub")).unwrap();
+        fs::write(root.join("sub/kept.txt"), "same").unwrap();
+        fs::write(root.join("sub/grown.txt"), "a").unwrap();
+        fs::write(root.join("sub/gone.txt"), "a").unwrap();
+
+        let mut rescans = Rescans::default();
+        rescans.add(&root.join("sub"), &root);
+        assert!(rescans.covers(&root.join("sub/kept.txt")));
+        assert!(!rescans.covers(&root));
+        // The starting contents are the baseline
+        assert!(rescans.rescan(&root).is_empty());
+
+        fs::write(root.join("sub/grown.txt"), "abc").unwrap();
+        fs::remove_file(root.join("sub/gone.txt")).unwrap();
+        fs::write(root.join("sub/new.txt"), "n").unwrap();
+
+        let records = rescans.rescan(&root);
+        assert!(has(&records, &root.join("sub/new.txt"), EventType::Create));
+        assert!(has(&records, &root.join("sub/grown.txt"), EventType::Modify));
+        assert!(has(&records, &root.join("sub/gone.txt"), EventType::Delete));
+        assert!(!records.iter().any(|r| r.entry.path.ends_with("kept.txt")));
+
+        let gone = records.iter().find(|r| r.event_type == EventType::Delete).unwrap();
+        assert_eq!(gone.entry.top_level_dir, "sub");
+        assert_eq!(gone.entry.depth, 2);
+
+        assert!(rescans.rescan(&root).is_empty());
+    }
+}
@@ -1,54 +1,139 @@
-use crate::models::FileEntry;
+use crate::aggregate::conform_batch;
+use crate::error::{self, Error};
+use crate::models::{FileEntry, PERMISSION_BITS};
+use crate::status::ScanCounters;
 use anyhow::{Context, Result};
 use arrow::array::{
     ArrayRef, Int64Array, StringArray, UInt32Array, UInt64Array,
 };
-use arrow::datatypes::{DataType, Field, Schema};
+use arrow::compute::{cast, unary};
+use arrow::datatypes::{DataType, Field, Schema, UInt32Type};
 use arrow::record_batch::RecordBatch;
 use crossbeam_channel::Receiver;
 use parquet::arrow::ArrowWriter;
 use parquet::basic::{Compression, Encoding};
+use parquet::file::metadata::{FileMetaData, KeyValue};
 use parquet::file::properties::WriterProperties;
 use std::fs::File;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tracing::info;
 
+/// FileEntry columns that scans made by earlier versions lack
+pub(crate) const LATER_COLUMNS: [&str; 4] = ["link_target", "device", "nlink", "raw_mode"];
+
+/// Parquet key-value metadata key holding the version of the scan schema
+pub const SCHEMA_VERSION_KEY: &str = "schema_version";
+
+/// Version of the scan schema written in every file's footer. Version 2 has
+/// only the permission bits in `permissions`, and the full mode in
+/// `raw_mode`; files without a version hold the full mode in `permissions`.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// Parquet key-value metadata key holding the errors the scan had counted
+/// when the file was closed
+pub const ERRORS_KEY: &str = "errors_encountered";
+
+/// Parquet key-value metadata key holding the entries a resumed scan had
+/// skipped when the file was closed
+pub const SKIPPED_KEY: &str = "entries_skipped";
+
 /// Parquet writer for FileEntry records
 pub struct ParquetFileWriter {
+    path: PathBuf,
     writer: ArrowWriter<File>,
     schema: Arc<Schema>,
     rows_written: u64,
+
+    /// Counters of the scan feeding this file, recorded in its footer on close
+    scan_counts: Option<ScanCounters>,
 }
 
 impl ParquetFileWriter {
     /// Create a new Parquet writer
-    pub fn new<P: AsRef<Path>>(output_path: P) -> Result<Self> {
+    pub fn new<P: AsRef<Path>>(output_path: P) -> error::Result<Self> {
+        Self::with_metadata(output_path, Vec::new())
+    }
+
+    /// Create a new Parquet writer that stores extra key-value pairs in the footer
+    pub fn with_metadata<P: AsRef<Path>>(output_path: P, metadata: Vec<(String, String)>) -> error::Result<Self> {
+        Self::with_options(output_path, metadata, Compression::SNAPPY)
+    }
+
+    /// Create a new Parquet writer with footer metadata and a codec other than snappy
+    pub fn with_options<P: AsRef<Path>>(
+        output_path: P,
+        metadata: Vec<(String, String)>,
+        compression: Compression,
+    ) -> error::Result<Self> {
+        let path = output_path.as_ref().to_path_buf();
+        let file = File::create(&path).map_err(Error::output_io(&path))?;
+        Self::from_file(path, file, metadata, compression)
+    }
+
+    /// Like [`Self::with_options`], but fail instead of replacing a file
+    /// already at `output_path`
+    pub(crate) fn create_new<P: AsRef<Path>>(
+        output_path: P,
+        metadata: Vec<(String, String)>,
+        compression: Compression,
+    ) -> error::Result<Self> {
+        let path = output_path.as_ref().to_path_buf();
+        let file = File::options().write(true).create_new(true).open(&path)
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::AlreadyExists => std::io::Error::new(
+                    e.kind(),
+                    "the file already exists; refusing to overwrite it",
+                ),
+                _ => e,
+            })
+            .map_err(Error::output_io(&path))?;
+        Self::from_file(path, file, metadata, compression)
+    }
+
+    fn from_file(
+        path: PathBuf,
+        file: File,
+        metadata: Vec<(String, String)>,
+        compression: Compression,
+    ) -> error::Result<Self> {
         let schema = Self::create_schema();
-        let file = File::create(output_path.as_ref())
-            .context("Failed to create output file")?;
 
         let props = WriterProperties::builder()
-            .set_compression(Compression::SNAPPY)
+            .set_compression(compression)
             .set_encoding(Encoding::PLAIN)
             .set_dictionary_enabled(true)
             .set_max_row_group_size(100_000)  // Smaller row groups for faster visibility
+            .set_key_value_metadata(Some(
+                std::iter::once(schema_version_entry())
+                    .chain(metadata.into_iter().map(|(k, v)| KeyValue::new(k, v)))
+                    .collect(),
+            ))
             .build();
 
         let writer = ArrowWriter::try_new(file, schema.clone(), Some(props))
-            .context("Failed to create Arrow writer")?;
+            .map_err(Error::writer(&path))?;
 
-        info!("Created Parquet writer for: {}", output_path.as_ref().display());
+        info!("Created Parquet writer for: {}", path.display());
 
         Ok(Self {
+            path,
             writer,
             schema,
             rows_written: 0,
+            scan_counts: None,
         })
     }
 
+    /// Record the error and skip counts of the scan behind `counters` in the
+    /// footer, as they stand when the file is closed
+    pub fn with_scan_counts(mut self, counters: ScanCounters) -> Self {
+        self.scan_counts = Some(counters);
+        self
+    }
+
     /// Create the Arrow schema for FileEntry
-    fn create_schema() -> Arc<Schema> {
+    pub(crate) fn create_schema() -> Arc<Schema> {
         Arc::new(Schema::new(vec![
             Field::new("path", DataType::Utf8, false),
             Field::new("size", DataType::UInt64, false),
@@ -65,70 +150,57 @@ impl ParquetFileWriter {
             Field::new("parent_path", DataType::Utf8, false),
             Field::new("depth", DataType::UInt32, false),
             Field::new("top_level_dir", DataType::Utf8, false),
+            Field::new("link_target", DataType::Utf8, true),
+            Field::new("device", DataType::UInt64, true),
+            Field::new("nlink", DataType::UInt64, true),
+            Field::new("raw_mode", DataType::UInt32, true),
         ]))
     }
 
     /// Write a batch of FileEntry records
-    pub fn write_batch(&mut self, entries: &[FileEntry]) -> Result<()> {
+    pub fn write_batch(&mut self, entries: &[FileEntry]) -> error::Result<()> {
         if entries.is_empty() {
             return Ok(());
         }
 
         let batch = self.entries_to_record_batch(entries)?;
-        self.writer.write(&batch)
-            .context("Failed to write record batch")?;
+        self.writer.write(&batch).map_err(Error::writer(&self.path))?;
 
         self.rows_written += entries.len() as u64;
 
         Ok(())
     }
 
-    /// Convert FileEntry records to Arrow RecordBatch
-    fn entries_to_record_batch(&self, entries: &[FileEntry]) -> Result<RecordBatch> {
-        let _len = entries.len();
-
-        // Build arrays
-        let paths: StringArray = entries.iter().map(|e| Some(e.path.as_str())).collect();
-        let sizes: UInt64Array = entries.iter().map(|e| Some(e.size)).collect();
-        let modified_times: Int64Array = entries.iter().map(|e| Some(e.modified_time)).collect();
-        let accessed_times: Int64Array = entries.iter().map(|e| Some(e.accessed_time)).collect();
-        let created_times: Int64Array = entries.iter().map(|e| e.created_time).collect();
-        let file_types: StringArray = entries.iter().map(|e| Some(e.file_type.as_str())).collect();
-        let inodes: UInt64Array = entries.iter().map(|e| Some(e.inode)).collect();
-        let permissions: UInt32Array = entries.iter().map(|e| Some(e.permissions)).collect();
-        let uids: UInt32Array = entries.iter().map(|e| Some(e.uid)).collect();
-        let gids: UInt32Array = entries.iter().map(|e| Some(e.gid)).collect();
-        let owners: StringArray = entries.iter().map(|e| e.owner.as_deref()).collect();
-        let groups: StringArray = entries.iter().map(|e| e.group.as_deref()).collect();
-        let parent_paths: StringArray = entries.iter().map(|e| Some(e.parent_path.as_str())).collect();
-        let depths: UInt32Array = entries.iter().map(|e| Some(e.depth)).collect();
-        let top_level_dirs: StringArray = entries.iter().map(|e| Some(e.top_level_dir.as_str())).collect();
-
-        // Create arrays vector
-        let arrays: Vec<ArrayRef> = vec![
-            Arc::new(paths),
-            Arc::new(sizes),
-            Arc::new(modified_times),
-            Arc::new(accessed_times),
-            Arc::new(created_times),
-            Arc::new(file_types),
-            Arc::new(inodes),
-            Arc::new(permissions),
-            Arc::new(uids),
-            Arc::new(gids),
-            Arc::new(owners),
-            Arc::new(groups),
-            Arc::new(parent_paths),
-            Arc::new(depths),
-            Arc::new(top_level_dirs),
-        ];
+    /// Write an Arrow RecordBatch with the FileEntry columns; those missing
+    /// from older scans are written as nulls, except that the full modes of
+    /// scans without `raw_mode` are moved there from `permissions`
+    pub fn write_record_batch(&mut self, batch: &RecordBatch) -> error::Result<()> {
+        if batch.num_rows() == 0 {
+            return Ok(());
+        }
+
+        let batch = if batch.schema() == self.schema {
+            batch.clone()
+        } else if batch.column_by_name("raw_mode").is_none() && batch.column_by_name("permissions").is_some() {
+            split_modes(&conform_batch(batch, &self.schema)?)?
+        } else {
+            conform_batch(batch, &self.schema)?
+        };
+        self.writer.write(&batch).map_err(Error::writer(&self.path))?;
 
-        RecordBatch::try_new(self.schema.clone(), arrays)
-            .context("Failed to create record batch")
+        self.rows_written += batch.num_rows() as u64;
+
+        Ok(())
+    }
+
+    /// Convert FileEntry records to Arrow RecordBatch
+    fn entries_to_record_batch(&self, entries: &[FileEntry]) -> error::Result<RecordBatch> {
+        RecordBatch::try_new(self.schema.clone(), entry_columns(entries))
+            .map_err(|e| Error::writer(&self.path)(e.into()))
     }
 
     /// Consume batches from a channel and write them
-    pub fn consume_batches(mut self, rx: Receiver<Vec<FileEntry>>) -> Result<u64> {
+    pub fn consume_batches(mut self, rx: Receiver<Vec<FileEntry>>) -> error::Result<u64> {
         let mut batches_processed = 0;
 
         for batch in rx {
@@ -147,9 +219,13 @@ impl ParquetFileWriter {
     }
 
     /// Close the writer and finalize the file
-    pub fn close(self) -> Result<()> {
-        self.writer.close()
-            .context("Failed to close Parquet writer")?;
+    pub fn close(mut self) -> error::Result<()> {
+        if let Some(ref counters) = self.scan_counts {
+            let counts = counters.load();
+            self.writer.append_key_value_metadata(KeyValue::new(ERRORS_KEY.to_string(), counts.errors.to_string()));
+            self.writer.append_key_value_metadata(KeyValue::new(SKIPPED_KEY.to_string(), counts.skipped.to_string()));
+        }
+        self.writer.close().map_err(Error::writer(&self.path))?;
 
         info!("Parquet file finalized: {} rows written", self.rows_written);
         Ok(())
@@ -160,12 +236,176 @@ impl ParquetFileWriter {
     }
 }
 
+/// Footer entry stamping a file with [`SCHEMA_VERSION`]; every Parquet file
+/// the crate writes carries one
+pub(crate) fn schema_version_entry() -> KeyValue {
+    KeyValue::new(SCHEMA_VERSION_KEY.to_string(), SCHEMA_VERSION.to_string())
+}
+
+/// Schema version stamped in a file's footer, or `None` for files written
+/// before versions were
+pub(crate) fn schema_version(metadata: &FileMetaData) -> Option<u32> {
+    metadata.key_value_metadata()?
+        .iter()
+        .find(|kv| kv.key == SCHEMA_VERSION_KEY)
+        .and_then(|kv| kv.value.as_deref()?.parse().ok())
+}
+
+/// A batch of an earlier scan, with both `permissions` and `raw_mode`
+/// columns, whose `permissions` hold full modes: those modes move to
+/// `raw_mode` where it is null, and `permissions` keep their permission bits
+pub(crate) fn split_modes(batch: &RecordBatch) -> Result<RecordBatch> {
+    let schema = batch.schema();
+    let (permissions, raw_mode) = (schema.index_of("permissions")?, schema.index_of("raw_mode")?);
+    let modes = batch.column(permissions).as_any().downcast_ref::<UInt32Array>()
+        .context("Column 'permissions' is not UInt32")?;
+    let raw_modes = batch.column(raw_mode).as_any().downcast_ref::<UInt32Array>()
+        .context("Column 'raw_mode' is not UInt32")?;
+    let mut columns = batch.columns().to_vec();
+    columns[permissions] = Arc::new(unary::<_, _, UInt32Type>(modes, |mode| mode & PERMISSION_BITS));
+    columns[raw_mode] = Arc::new(raw_modes.iter().zip(modes).map(|(raw, mode)| raw.or(mode)).collect::<UInt32Array>());
+    Ok(RecordBatch::try_new(schema, columns)?)
+}
+
+/// FileEntry records as one Arrow record batch, in the schema scans write
+pub fn entries_to_batch(entries: &[FileEntry]) -> arrow::error::Result<RecordBatch> {
+    RecordBatch::try_new(ParquetFileWriter::create_schema(), entry_columns(entries))
+}
+
+/// Arrow columns for FileEntry records, in schema order
+pub(crate) fn entry_columns(entries: &[FileEntry]) -> Vec<ArrayRef> {
+    // Build arrays
+    let paths: StringArray = entries.iter().map(|e| Some(e.path.as_str())).collect();
+    let sizes: UInt64Array = entries.iter().map(|e| Some(e.size)).collect();
+    let modified_times: Int64Array = entries.iter().map(|e| Some(e.modified_time)).collect();
+    let accessed_times: Int64Array = entries.iter().map(|e| Some(e.accessed_time)).collect();
+    let created_times: Int64Array = entries.iter().map(|e| e.created_time).collect();
+    let file_types: StringArray = entries.iter().map(|e| Some(e.file_type.as_str())).collect();
+    let inodes: UInt64Array = entries.iter().map(|e| Some(e.inode)).collect();
+    let permissions: UInt32Array = entries.iter().map(|e| Some(e.permissions)).collect();
+    let uids: UInt32Array = entries.iter().map(|e| Some(e.uid)).collect();
+    let gids: UInt32Array = entries.iter().map(|e| Some(e.gid)).collect();
+    let owners: StringArray = entries.iter().map(|e| e.owner.as_deref()).collect();
+    let groups: StringArray = entries.iter().map(|e| e.group.as_deref()).collect();
+    let parent_paths: StringArray = entries.iter().map(|e| Some(e.parent_path.as_str())).collect();
+    let depths: UInt32Array = entries.iter().map(|e| Some(e.depth)).collect();
+    let top_level_dirs: StringArray = entries.iter().map(|e| Some(e.top_level_dir.as_str())).collect();
+    let link_targets: StringArray = entries.iter().map(|e| e.link_target.as_deref()).collect();
+    let devices: UInt64Array = entries.iter().map(|e| Some(e.device)).collect();
+    let nlinks: UInt64Array = entries.iter().map(|e| Some(e.nlink)).collect();
+    let raw_modes: UInt32Array = entries.iter().map(|e| Some(e.raw_mode)).collect();
+
+    vec![
+        Arc::new(paths),
+        Arc::new(sizes),
+        Arc::new(modified_times),
+        Arc::new(accessed_times),
+        Arc::new(created_times),
+        Arc::new(file_types),
+        Arc::new(inodes),
+        Arc::new(permissions),
+        Arc::new(uids),
+        Arc::new(gids),
+        Arc::new(owners),
+        Arc::new(groups),
+        Arc::new(parent_paths),
+        Arc::new(depths),
+        Arc::new(top_level_dirs),
+        Arc::new(link_targets),
+        Arc::new(devices),
+        Arc::new(nlinks),
+        Arc::new(raw_modes),
+    ]
+}
+
+/// FileEntry records from a batch with any subset of the FileEntry columns,
+/// cast to their schema types. Missing columns and nulls in required ones
+/// become empty strings and zeros; only `path` must be present.
+pub(crate) fn entries_from_batch(batch: &RecordBatch) -> Result<Vec<FileEntry>> {
+    let rows = batch.num_rows();
+    let column = |name: &str, data_type: &DataType| -> Result<Option<ArrayRef>> {
+        batch.column_by_name(name)
+            .map(|c| cast(c, data_type).with_context(|| format!("Column '{}' is not {}", name, data_type)))
+            .transpose()
+    };
+    let strings = |name: &str| -> Result<Vec<Option<String>>> {
+        Ok(match column(name, &DataType::Utf8)? {
+            Some(c) => c.as_any().downcast_ref::<StringArray>().unwrap().iter().map(|v| v.map(str::to_string)).collect(),
+            None => vec![None; rows],
+        })
+    };
+    let u64s = |name: &str| -> Result<Vec<Option<u64>>> {
+        Ok(match column(name, &DataType::UInt64)? {
+            Some(c) => c.as_any().downcast_ref::<UInt64Array>().unwrap().iter().collect(),
+            None => vec![None; rows],
+        })
+    };
+    let i64s = |name: &str| -> Result<Vec<Option<i64>>> {
+        Ok(match column(name, &DataType::Int64)? {
+            Some(c) => c.as_any().downcast_ref::<Int64Array>().unwrap().iter().collect(),
+            None => vec![None; rows],
+        })
+    };
+    let u32s = |name: &str| -> Result<Vec<Option<u32>>> {
+        Ok(match column(name, &DataType::UInt32)? {
+            Some(c) => c.as_any().downcast_ref::<UInt32Array>().unwrap().iter().collect(),
+            None => vec![None; rows],
+        })
+    };
+
+    if batch.column_by_name("path").is_none() {
+        anyhow::bail!("Entries require a 'path' column");
+    }
+    let (paths, file_types, owners, groups, parent_paths, top_level_dirs, link_targets) = (
+        strings("path")?, strings("file_type")?, strings("owner")?, strings("group")?,
+        strings("parent_path")?, strings("top_level_dir")?, strings("link_target")?,
+    );
+    let (sizes, inodes, devices, nlinks) = (u64s("size")?, u64s("inode")?, u64s("device")?, u64s("nlink")?);
+    let (modified, accessed, created) = (i64s("modified_time")?, i64s("accessed_time")?, i64s("created_time")?);
+    let (permissions, uids, gids, depths) = (u32s("permissions")?, u32s("uid")?, u32s("gid")?, u32s("depth")?);
+    let raw_modes = u32s("raw_mode")?;
+
+    Ok((0..rows)
+        .map(|i| FileEntry {
+            path: paths[i].clone().unwrap_or_default(),
+            size: sizes[i].unwrap_or_default(),
+            modified_time: modified[i].unwrap_or_default(),
+            accessed_time: accessed[i].unwrap_or_default(),
+            created_time: created[i],
+            file_type: file_types[i].clone().unwrap_or_default(),
+            inode: inodes[i].unwrap_or_default(),
+            // Scans without `raw_mode` stored the full mode in `permissions`
+            permissions: permissions[i].unwrap_or_default() & PERMISSION_BITS,
+            uid: uids[i].unwrap_or_default(),
+            gid: gids[i].unwrap_or_default(),
+            owner: owners[i].clone(),
+            group: groups[i].clone(),
+            parent_path: parent_paths[i].clone().unwrap_or_default(),
+            depth: depths[i].unwrap_or_default(),
+            top_level_dir: top_level_dirs[i].clone().unwrap_or_default(),
+            link_target: link_targets[i].clone(),
+            device: devices[i].unwrap_or_default(),
+            nlink: nlinks[i].unwrap_or_default(),
+            raw_mode: raw_modes[i].or(permissions[i]).unwrap_or_default(),
+        })
+        .collect())
+}
+
 /// Write entries to a Parquet file from a channel
 pub fn write_to_parquet<P: AsRef<Path>>(
     output_path: P,
     rx: Receiver<Vec<FileEntry>>,
-) -> Result<u64> {
-    let writer = ParquetFileWriter::new(output_path)?;
+) -> error::Result<u64> {
+    write_to_parquet_with(output_path, rx, Compression::SNAPPY)
+}
+
+/// Write entries to a Parquet file from a channel, compressed with `compression`
+pub fn write_to_parquet_with<P: AsRef<Path>>(
+    output_path: P,
+    rx: Receiver<Vec<FileEntry>>,
+    compression: Compression,
+) -> error::Result<u64> {
+    let writer = ParquetFileWriter::with_options(output_path, Vec::new(), compression)?;
     writer.consume_batches(rx)
 }
 
@@ -175,26 +415,16 @@ mod tests {
     use crate::models::FileEntry;
     use crossbeam_channel::bounded;
     use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
-    use std::fs;
     use tempfile::TempDir;
 
     fn create_test_entry(path: &str, size: u64) -> FileEntry {
+        // Names and a creation time, so the optional columns hold values
         FileEntry {
-            path: path.to_string(),
             size,
-            modified_time: 1700000000,
-            accessed_time: 1700000000,
-            created_time: Some(1700000000),
-            file_type: "txt".to_string(),
-            inode: 12345,
-            permissions: 0o644,
-            uid: 1000,
-            gid: 1000,
+            created_time: Some(1_700_000_000),
             owner: Some("testuser".to_string()),
             group: Some("testgroup".to_string()),
-            parent_path: "/parent".to_string(),
-            depth: 1,
-            top_level_dir: "root".to_string(),
+            ..FileEntry::fixture(path)
         }
     }
 
@@ -276,12 +506,26 @@ mod tests {
         assert_eq!(writer.rows_written(), 0);
     }
 
+    #[test]
+    fn test_compression() {
+        let temp_dir = TempDir::new().unwrap();
+        let output_path = temp_dir.path().join("test_zstd.parquet");
+        let (tx, rx) = bounded(1);
+        tx.send(vec![create_test_entry("/test/a.txt", 1)]).unwrap();
+        drop(tx);
+
+        let codec = Compression::ZSTD(parquet::basic::ZstdLevel::default());
+        assert_eq!(write_to_parquet_with(&output_path, rx, codec).unwrap(), 1);
+        let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(&output_path).unwrap()).unwrap();
+        assert!(matches!(builder.metadata().row_group(0).column(0).compression(), Compression::ZSTD(_)));
+    }
+
     #[test]
     fn test_schema_creation() {
         let schema = ParquetFileWriter::create_schema();
 
         // Verify all expected fields exist
-        assert_eq!(schema.fields().len(), 15);
+        assert_eq!(schema.fields().len(), 19);
         assert!(schema.field_with_name("path").is_ok());
         assert!(schema.field_with_name("size").is_ok());
         assert!(schema.field_with_name("modified_time").is_ok());
@@ -290,5 +534,98 @@ mod tests {
         assert!(schema.field_with_name("gid").is_ok());
         assert!(schema.field_with_name("owner").is_ok());
         assert!(schema.field_with_name("group").is_ok());
+        assert!(schema.field_with_name("raw_mode").is_ok());
+    }
+
+    #[test]
+    fn test_footer_holds_the_schema_version() {
+        let temp_dir = TempDir::new().unwrap();
+        let output_path = temp_dir.path().join("versioned.parquet");
+        let metadata = vec![("scan_id".to_string(), "2024-05-20".to_string())];
+        let mut writer = ParquetFileWriter::with_metadata(&output_path, metadata).unwrap();
+        writer.write_batch(&[create_test_entry("/test/a.txt", 1)]).unwrap();
+        writer.close().unwrap();
+
+        let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(&output_path).unwrap()).unwrap();
+        let pairs: Vec<(String, Option<String>)> = builder.metadata().file_metadata().key_value_metadata().unwrap()
+            .iter()
+            .filter(|kv| kv.key != "ARROW:schema")
+            .map(|kv| (kv.key.clone(), kv.value.clone()))
+            .collect();
+        assert_eq!(pairs, [
+            (SCHEMA_VERSION_KEY.to_string(), Some(SCHEMA_VERSION.to_string())),
+            ("scan_id".to_string(), Some("2024-05-20".to_string())),
+        ]);
+    }
+
+    #[test]
+    fn test_footer_holds_the_scan_counts_at_close() {
+        let temp_dir = TempDir::new().unwrap();
+        let output_path = temp_dir.path().join("counted.parquet");
+        let counters = ScanCounters::default();
+        let mut writer = ParquetFileWriter::new(&output_path).unwrap().with_scan_counts(counters.clone());
+        writer.write_batch(&[create_test_entry("/test/a.txt", 1)]).unwrap();
+
+        // Counts reached after the rows were written still make it in
+        counters.errors.store(7, std::sync::atomic::Ordering::Relaxed);
+        counters.skipped.store(2, std::sync::atomic::Ordering::Relaxed);
+        writer.close().unwrap();
+
+        let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(&output_path).unwrap()).unwrap();
+        let value = |key: &str| builder.metadata().file_metadata().key_value_metadata().unwrap()
+            .iter()
+            .find(|kv| kv.key == key)
+            .and_then(|kv| kv.value.clone());
+        assert_eq!(value(ERRORS_KEY).as_deref(), Some("7"));
+        assert_eq!(value(SKIPPED_KEY).as_deref(), Some("2"));
+    }
+
+    #[test]
+    fn test_batches_of_earlier_scans_get_permission_bits() {
+        let temp_dir = TempDir::new().unwrap();
+        let output_path = temp_dir.path().join("upgraded.parquet");
+
+        // Before `raw_mode`, `permissions` held the whole st_mode
+        let entries = [create_test_entry("/test/a.txt", 1), create_test_entry("/test/b.txt", 2)];
+        let batch = entries_to_batch(&entries).unwrap();
+        let schema = batch.schema();
+        let modes: UInt32Array = vec![0o100644, 0o40755].into();
+        let mut columns = batch.columns().to_vec();
+        columns[schema.index_of("permissions").unwrap()] = Arc::new(modes);
+        let old = RecordBatch::try_new(schema.clone(), columns).unwrap()
+            .project(&(0..schema.fields().len() - 1).collect::<Vec<_>>())
+            .unwrap();
+
+        let mut writer = ParquetFileWriter::new(&output_path).unwrap();
+        writer.write_record_batch(&old).unwrap();
+        writer.close().unwrap();
+
+        let read = crate::reader::read_all(&output_path).unwrap();
+        assert_eq!(read.iter().map(|e| (e.permissions, e.raw_mode)).collect::<Vec<_>>(),
+                   [(0o644, 0o100644), (0o755, 0o40755)]);
+    }
+
+    #[test]
+    fn test_entries_to_batch_round_trip() {
+        let entries = vec![create_test_entry("/test/a.txt", 1), create_test_entry("/test/b.txt", 2)];
+        let batch = entries_to_batch(&entries).unwrap();
+        assert_eq!(batch.schema(), ParquetFileWriter::create_schema());
+        assert_eq!(entries_from_batch(&batch).unwrap(), entries);
+        assert_eq!(entries_to_batch(&[]).unwrap().num_rows(), 0);
+    }
+
+    #[test]
+    fn test_unwritable_output_is_an_output_error() {
+        let temp_dir = TempDir::new().unwrap();
+        let output = temp_dir.path().join("missing/scan.parquet");
+
+        let err = ParquetFileWriter::new(&output).err().unwrap();
+        match err {
+            Error::OutputIo { ref path, ref source } => {
+                assert_eq!(path, &output);
+                assert_eq!(source.kind(), std::io::ErrorKind::NotFound);
+            }
+            other => panic!("expected OutputIo, got {:?}", other),
+        }
     }
 }
@@ -1,12 +1,20 @@
 use crossbeam_channel::bounded;
-use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
-use std::fs::{self, File};
+use std::fs;
 use storage_scanner::{
     models::{FileEntry, ScanOptions},
     scanner::{scan_directory, Scanner},
-    writer::write_to_parquet,
 };
 use tempfile::TempDir;
+#[cfg(feature = "parquet-writer")]
+use {
+    parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder,
+    std::fs::File,
+    storage_scanner::{
+        manifest::ScanManifest,
+        rotating_writer::{RotatingParquetWriter, RotatingWriterConfig},
+        writer::write_to_parquet,
+    },
+};
 
 /// Helper function to create a test directory structure
 fn create_test_structure() -> TempDir {
@@ -31,17 +39,14 @@ fn create_test_structure() -> TempDir {
     temp_dir
 }
 
+#[cfg(feature = "parquet-writer")]
 #[test]
 fn test_end_to_end_scan_and_write() {
     let test_dir = create_test_structure();
     let output_dir = TempDir::new().unwrap();
     let output_file = output_dir.path().join("scan_output.parquet");
 
-    let options = ScanOptions {
-        num_threads: 2,
-        batch_size: 10,
-        ..Default::default()
-    };
+    let options = ScanOptions::builder().threads(2).batch_size(10).build().unwrap();
 
     let (tx, rx) = bounded::<Vec<FileEntry>>(20);
     let scanner = Scanner::new(options);
@@ -80,37 +85,61 @@ fn test_end_to_end_scan_and_write() {
     assert_eq!(total_rows as u64, rows_written);
 }
 
+/// Paths below the root of a scan limited to `max_depth`, each with its
+/// recorded depth, sorted
+fn scan_to_depth(root: &std::path::Path, max_depth: usize) -> Vec<(String, u32)> {
+    let options = ScanOptions::builder().threads(2).batch_size(10).max_depth(max_depth).build().unwrap();
+    let root = root.canonicalize().unwrap();
+    let mut entries: Vec<(String, u32)> = scan_directory(&root, options).unwrap()
+        .into_iter()
+        .map(|e| (std::path::Path::new(&e.path).strip_prefix(&root).unwrap().to_string_lossy().to_string(), e.depth))
+        .collect();
+    entries.sort();
+    entries
+}
+
 #[test]
 fn test_scan_with_max_depth() {
     let test_dir = create_test_structure();
+    let expected = |paths: &[(&str, u32)]| paths.iter().map(|&(p, d)| (p.to_string(), d)).collect::<Vec<_>>();
 
-    let options = ScanOptions {
-        num_threads: 2,
-        batch_size: 10,
-        max_depth: Some(2),
-        ..Default::default()
-    };
-
-    let entries = scan_directory(test_dir.path(), options).unwrap();
-
-    // With max_depth=2, should not include deeply nested files
-    let deep_files: Vec<_> = entries
-        .iter()
-        .filter(|e| e.path.contains("deep"))
-        .collect();
+    // Depth counts components below the root, which is depth 0
+    assert_eq!(scan_to_depth(test_dir.path(), 0), expected(&[("", 0)]));
+    assert_eq!(scan_to_depth(test_dir.path(), 1), expected(&[
+        ("", 0),
+        ("dir1", 1),
+        ("dir2", 1),
+        ("dir3", 1),
+        ("file1.txt", 1),
+        ("file2.log", 1),
+    ]));
+    assert_eq!(scan_to_depth(test_dir.path(), 2), expected(&[
+        ("", 0),
+        ("dir1", 1),
+        ("dir1/file3.txt", 2),
+        ("dir1/subdir1", 2),
+        ("dir2", 1),
+        ("dir2/file5.py", 2),
+        ("dir3", 1),
+        ("dir3/file6.json", 2),
+        ("dir3/subdir2", 2),
+        ("file1.txt", 1),
+        ("file2.log", 1),
+    ]));
 
-    assert_eq!(deep_files.len(), 0, "Should not scan beyond max depth");
+    // The deepest entry is at 4, so a limit of 4 or more changes nothing
+    let all = scan_to_depth(test_dir.path(), 4);
+    assert_eq!(all.len(), 15);
+    assert_eq!(all.iter().map(|e| e.1).max(), Some(4));
+    assert_eq!(scan_to_depth(test_dir.path(), 10), all);
+    assert!(all.iter().all(|(path, depth)| std::path::Path::new(path).components().count() as u32 == *depth));
 }
 
 #[test]
 fn test_scan_file_types() {
     let test_dir = create_test_structure();
 
-    let options = ScanOptions {
-        num_threads: 2,
-        batch_size: 10,
-        ..Default::default()
-    };
+    let options = ScanOptions::builder().threads(2).batch_size(10).build().unwrap();
 
     let entries = scan_directory(test_dir.path(), options).unwrap();
 
@@ -122,7 +151,7 @@ fn test_scan_file_types() {
     let log_files: Vec<_> = entries.iter().filter(|e| e.file_type == "log").collect();
     let directories: Vec<_> = entries.iter().filter(|e| e.file_type == "directory").collect();
 
-    assert_eq!(txt_files.len(), 5);
+    assert_eq!(txt_files.len(), 4);
     assert_eq!(py_files.len(), 1);
     assert_eq!(json_files.len(), 1);
     assert_eq!(csv_files.len(), 1);
@@ -185,6 +214,7 @@ fn test_scan_empty_directory() {
     assert!(entries.is_empty() || entries.len() == 1);
 }
 
+#[cfg(feature = "parquet-writer")]
 #[test]
 fn test_multiple_batches() {
     let test_dir = create_test_structure();
@@ -192,11 +222,7 @@ fn test_multiple_batches() {
     let output_file = output_dir.path().join("batched_output.parquet");
 
     // Use very small batch size to force multiple batches
-    let options = ScanOptions {
-        num_threads: 2,
-        batch_size: 2,
-        ..Default::default()
-    };
+    let options = ScanOptions::builder().threads(2).batch_size(2).build().unwrap();
 
     let (tx, rx) = bounded(4);
     let scanner = Scanner::new(options);
@@ -216,11 +242,7 @@ fn test_multiple_batches() {
 fn test_scan_statistics() {
     let test_dir = create_test_structure();
 
-    let options = ScanOptions {
-        num_threads: 2,
-        batch_size: 10,
-        ..Default::default()
-    };
+    let options = ScanOptions::builder().threads(2).batch_size(10).build().unwrap();
 
     let (tx, rx) = bounded::<Vec<FileEntry>>(20);
     let scanner = Scanner::new(options);
@@ -250,3 +272,2142 @@ fn test_scan_statistics() {
     assert!(stats.duration_secs > 0.0);
     assert!(stats.files_per_second() > 0.0);
 }
+
+/// Builds without `parquet-writer` (or `serde`) scan into the channel alone
+#[test]
+fn test_scan_collects_entries_with_core_features() {
+    let test_dir = create_test_structure();
+    let options = ScanOptions::builder().threads(2).batch_size(3).build().unwrap();
+
+    let (tx, rx) = bounded::<Vec<FileEntry>>(4);
+    let scanner = Scanner::new(options);
+    let scan_path = test_dir.path().to_path_buf();
+    let scan_handle = std::thread::spawn(move || scanner.scan(scan_path, tx));
+
+    let batches: Vec<Vec<FileEntry>> = rx.iter().collect();
+    let stats = scan_handle.join().unwrap().unwrap();
+
+    assert!(batches.len() > 1);
+    assert!(batches.iter().all(|batch| !batch.is_empty() && batch.len() <= 3));
+    let entries: Vec<FileEntry> = batches.into_iter().flatten().collect();
+    assert_eq!(entries.len() as u64, stats.files_scanned + stats.directories_scanned);
+
+    let deep = entries.iter().find(|e| e.path.ends_with("dir3/subdir2/deep/file8.txt")).unwrap();
+    assert_eq!(deep.size, "deep content".len() as u64);
+    assert_eq!(deep.top_level_dir, "dir3");
+    assert_eq!(deep.file_type, "txt");
+}
+
+#[cfg(feature = "parquet-writer")]
+#[test]
+fn test_permission_errors_recorded_in_manifest() {
+    use std::os::unix::fs::PermissionsExt;
+
+    // Root bypasses permission checks, so EACCES cannot be forced
+    if unsafe { libc::geteuid() } == 0 {
+        eprintln!("skipping: running as root");
+        return;
+    }
+
+    let test_dir = create_test_structure();
+    let locked = test_dir.path().join("dir2");
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+
+    let output_dir = TempDir::new().unwrap();
+    let config = RotatingWriterConfig {
+        base_output_path: output_dir.path().join("scan.parquet"),
+        rows_per_chunk: 100,
+        time_interval: std::time::Duration::from_secs(3600),
+    };
+
+    let (tx, rx) = bounded::<Vec<FileEntry>>(20);
+    let scanner = Scanner::new(ScanOptions::builder().threads(2).batch_size(10).build().unwrap());
+    let mut writer = RotatingParquetWriter::new(config, test_dir.path().to_string_lossy().to_string()).unwrap();
+
+    let writer_handle = std::thread::spawn(move || {
+        writer.write_all(rx).unwrap();
+        writer
+    });
+    let stats = scanner.scan(test_dir.path(), tx).unwrap();
+    let mut writer = writer_handle.join().unwrap();
+    writer.record_errors(&stats.error_summary);
+    writer.finalize().unwrap();
+
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let manifest = ScanManifest::load_from_file(output_dir.path().join("scan_manifest.json")).unwrap();
+    assert!(manifest.errors.total > 0);
+    assert_eq!(manifest.errors.total, stats.errors_encountered);
+    assert!(manifest.errors.by_kind.get("EACCES").copied().unwrap_or(0) > 0);
+    assert!(manifest.errors.samples.iter().any(|s| s.path.contains("dir2")));
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_scan_refuses_locked_output() {
+    let test_dir = create_test_structure();
+    let output_dir = TempDir::new().unwrap();
+    let output = output_dir.path().join("scan.parquet");
+
+    // Two scans start at the same output together; the post-scan command
+    // keeps the winner's lock held until the other has tried for it
+    let scans: Vec<_> = (0..2)
+        .map(|_| {
+            std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+                .args(["scan", "--incremental", "--post-scan-cmd", "sleep 2", "-p"])
+                .arg(test_dir.path())
+                .arg("-o")
+                .arg(&output)
+                .stdout(std::process::Stdio::null())
+                .stderr(std::process::Stdio::piped())
+                .spawn()
+                .unwrap()
+        })
+        .collect();
+    let outcomes: Vec<_> = scans.into_iter().map(|scan| scan.wait_with_output().unwrap()).collect();
+
+    let (ran, refused): (Vec<_>, Vec<_>) = outcomes.iter().partition(|outcome| outcome.status.success());
+    assert_eq!((ran.len(), refused.len()), (1, 1));
+    assert!(String::from_utf8_lossy(&refused[0].stderr).contains("locked"));
+
+    // The winner wrote one scan's chunks and cleaned up its own lock
+    let manifest = ScanManifest::load_from_file(output_dir.path().join("scan_manifest.json")).unwrap();
+    let entries = scan_directory(test_dir.path(), ScanOptions::default()).unwrap();
+    assert_eq!(manifest.total_rows, entries.len() as u64);
+    assert!(!output_dir.path().join("scan.lock").exists());
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_scan_refuses_output_without_a_file_name() {
+    let test_dir = create_test_structure();
+
+    let result = std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+        .args(["scan", "-p"])
+        .arg(test_dir.path())
+        .args(["-o", ".."])
+        .output()
+        .unwrap();
+
+    assert_eq!(result.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&result.stderr).contains("output path has no file name"));
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_scan_writes_status_file() {
+    let test_dir = create_test_structure();
+    let output_dir = TempDir::new().unwrap();
+    let status_file = output_dir.path().join("status.json");
+
+    let result = std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+        .args(["scan", "--incremental", "--rows-per-chunk", "5", "-p"])
+        .arg(test_dir.path())
+        .arg("-o")
+        .arg(output_dir.path().join("scan.parquet"))
+        .arg("--status-file")
+        .arg(&status_file)
+        .output()
+        .unwrap();
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+
+    let status = storage_scanner::status::ScanStatus::load(&status_file).unwrap();
+    assert_eq!(status.state, storage_scanner::status::ScanState::Completed);
+    assert_eq!(status.files, 8);
+    assert!(status.current_chunk.is_some());
+    let dirs: Vec<&str> = status.top_level_dirs.iter().map(|d| d.name.as_str()).collect();
+    assert!(["dir1", "dir2", "dir3"].iter().all(|d| dirs.contains(d)), "{:?}", dirs);
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_scan_errors_reach_every_report() {
+    use storage_scanner::writer::{ERRORS_KEY, SKIPPED_KEY};
+
+    // Root can read anything, so unreadable directories count as errors
+    // only for other users; followed links to nothing fail for everyone
+    let test_dir = create_test_structure();
+    for i in 0..3 {
+        std::os::unix::fs::symlink(format!("/nonexistent/target{}", i), test_dir.path().join(format!("dir1/broken{}", i)))
+            .unwrap();
+    }
+    let output_dir = TempDir::new().unwrap();
+    let scan = |name: &str, extra: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+            .args(["scan", "--follow-symlinks", "--incremental", "--rows-per-chunk", "4", "-p"])
+            .arg(test_dir.path())
+            .arg("-o")
+            .arg(output_dir.path().join(name))
+            .args(extra)
+            .output()
+            .unwrap()
+    };
+
+    let stats_path = output_dir.path().join("stats.json");
+    let result = scan("scan.parquet", &["--stats-output", stats_path.to_str().unwrap()]);
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+
+    let manifest = ScanManifest::load_from_file(output_dir.path().join("scan_manifest.json")).unwrap();
+    assert_eq!(manifest.errors.total, 3);
+    assert_eq!(manifest.errors.by_kind["ENOENT"], 3);
+    assert_eq!(manifest.entries_skipped, 0);
+
+    let stats: serde_json::Value = serde_json::from_str(&fs::read_to_string(&stats_path).unwrap()).unwrap();
+    assert_eq!(stats["errors_encountered"], 3);
+    assert_eq!(stats["error_summary"]["by_kind"]["ENOENT"], 3);
+    assert_eq!(stats["entries_skipped"], 0);
+    assert_eq!(stats["rows_written"], manifest.total_rows);
+
+    // Each chunk has the counts as they stood when it was closed
+    let footer = |path: &str, key: &str| -> u64 {
+        let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap()).unwrap();
+        let kv = builder.metadata().file_metadata().key_value_metadata().unwrap();
+        kv.iter().find(|kv| kv.key == key).and_then(|kv| kv.value.as_ref()).unwrap().parse().unwrap()
+    };
+    let errors: Vec<u64> = manifest.chunks.iter().map(|c| footer(&c.file_path, ERRORS_KEY)).collect();
+    assert!(errors.windows(2).all(|w| w[0] <= w[1]), "{:?}", errors);
+    assert_eq!(errors.last(), Some(&3));
+    assert!(manifest.chunks.iter().all(|c| footer(&c.file_path, SKIPPED_KEY) == 0));
+
+    // More errors than allowed fail the run with a status of its own
+    let strict = scan("strict.parquet", &["--fail-on-errors", "2"]);
+    assert_eq!(strict.status.code(), Some(3), "{}", String::from_utf8_lossy(&strict.stderr));
+    let log = format!("{}{}", String::from_utf8_lossy(&strict.stdout), String::from_utf8_lossy(&strict.stderr));
+    assert!(log.contains("more than --fail-on-errors 2"), "{}", log);
+    assert!(output_dir.path().join("strict_manifest.json").exists());
+
+    let lenient = scan("lenient.parquet", &["--fail-on-errors", "3"]);
+    assert!(lenient.status.success(), "{}", String::from_utf8_lossy(&lenient.stderr));
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_scan_runs_post_chunk_and_post_scan_commands() {
+    let test_dir = create_test_structure();
+    let output_dir = TempDir::new().unwrap();
+    let log = output_dir.path().join("hook.log");
+    let published = output_dir.path().join("published.json");
+
+    let result = std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+        .args(["scan", "--incremental", "--rows-per-chunk", "5", "-p"])
+        .arg(test_dir.path())
+        .arg("-o")
+        .arg(output_dir.path().join("scan.parquet"))
+        .arg("--post-chunk-cmd")
+        .arg(format!("echo {{chunk_number}} {{rows}} >> {}", log.display()))
+        .arg("--post-scan-cmd")
+        .arg(format!("cp {{manifest}} {}", published.display()))
+        .output()
+        .unwrap();
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+
+    let manifest = ScanManifest::load_from_file(output_dir.path().join("scan_manifest.json")).unwrap();
+    let expected: Vec<String> = manifest.chunks.iter()
+        .map(|c| format!("{} {}", c.chunk_number, c.row_count))
+        .collect();
+    assert_eq!(fs::read_to_string(&log).unwrap().lines().collect::<Vec<_>>(), expected);
+    assert!(manifest.chunks.iter().all(|c| c.post_chunk.as_ref().is_some_and(|o| o.success())));
+    // The post-scan command saw the finalized manifest
+    let published: serde_json::Value = serde_json::from_slice(&fs::read(&published).unwrap()).unwrap();
+    assert_eq!(published["completed"], true);
+
+    let result = std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+        .args(["scan", "--incremental", "--post-chunk-cmd", "exit 7", "--post-chunk-on-error", "fail", "-p"])
+        .arg(test_dir.path())
+        .arg("-o")
+        .arg(output_dir.path().join("failing.parquet"))
+        .output()
+        .unwrap();
+    assert!(!result.status.success());
+    let manifest = ScanManifest::load_from_file(output_dir.path().join("failing_manifest.json")).unwrap();
+    assert!(!manifest.completed);
+    assert_eq!(manifest.chunks[0].post_chunk.as_ref().unwrap().exit_code, Some(7));
+}
+
+#[cfg(all(feature = "cli", feature = "remote"))]
+#[test]
+fn test_scan_notifies_webhook_on_success_and_failure() {
+    let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/hook", server.server_addr().to_ip().unwrap());
+    let capture = std::thread::spawn(move || {
+        (0..2).map(|_| {
+            let mut request = server.recv().unwrap();
+            let mut body = String::new();
+            request.as_reader().read_to_string(&mut body).unwrap();
+            let signed = request.headers().iter()
+                .find(|h| h.field.equiv("X-Storage-Scanner-Signature"))
+                .map(|h| h.value.to_string());
+            request.respond(tiny_http::Response::empty(200)).unwrap();
+            (signed, serde_json::from_str::<serde_json::Value>(&body).unwrap(), body)
+        }).collect::<Vec<_>>()
+    });
+
+    let test_dir = create_test_structure();
+    let output_dir = TempDir::new().unwrap();
+    let secret_file = output_dir.path().join("secret");
+    fs::write(&secret_file, "s3cret\n").unwrap();
+    let scan = |path: &std::path::Path| std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+        .args(["scan", "--incremental", "--rows-per-chunk", "5", "--notify-webhook", &url, "-p"])
+        .arg(path)
+        .arg("-o")
+        .arg(output_dir.path().join("scan.parquet"))
+        .arg("--notify-secret-file")
+        .arg(&secret_file)
+        .output()
+        .unwrap();
+
+    assert!(scan(test_dir.path()).status.success());
+    assert!(!scan(&test_dir.path().join("missing")).status.success());
+
+    let received = capture.join().unwrap();
+    let manifest = ScanManifest::load_from_file(output_dir.path().join("scan_manifest.json")).unwrap();
+    let (ref signed, ref completed, ref body) = received[0];
+    assert_eq!(signed.as_deref(), Some(storage_scanner::notify::signature("s3cret", body.as_bytes()).as_str()));
+    assert_eq!(completed["status"], "completed");
+    assert_eq!(completed["rows"], manifest.total_rows);
+    assert_eq!(completed["outputs"].as_array().unwrap().len(), manifest.chunks.len());
+    assert!(completed["manifest"].as_str().unwrap().ends_with("scan_manifest.json"));
+
+    let (_, ref failed, _) = received[1];
+    assert_eq!(failed["status"], "failed");
+    assert!(failed["error"].as_str().unwrap().contains("Invalid input path"), "{}", failed);
+}
+
+#[cfg(all(feature = "cli", feature = "email"))]
+#[test]
+fn test_scan_succeeds_when_email_report_cannot_be_sent() {
+    // Nothing listens on a port just released
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let test_dir = create_test_structure();
+    let output_dir = TempDir::new().unwrap();
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+        .args(["scan", "--email-report", "admins@example.edu", "--smtp-server"])
+        .arg(format!("127.0.0.1:{}", port))
+        .arg("-p")
+        .arg(test_dir.path())
+        .arg("-o")
+        .arg(output_dir.path().join("scan.parquet"))
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let logs = String::from_utf8_lossy(&output.stdout).to_string() + &String::from_utf8_lossy(&output.stderr);
+    assert!(logs.contains("Failed to email scan report"), "{}", logs);
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn test_metrics_endpoint_during_scan() {
+    use std::io::{Read, Write};
+    use std::sync::Arc;
+    use storage_scanner::metrics::{self, ScanMetrics};
+    use storage_scanner::status::ScanCounters;
+
+    let scrape = |addr: std::net::SocketAddr| {
+        let mut stream = std::net::TcpStream::connect(addr).unwrap();
+        write!(stream, "GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        body.lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| {
+                let (name, value) = line.rsplit_once(' ').unwrap();
+                (name.split('{').next().unwrap().to_string(), value.parse::<f64>().unwrap())
+            })
+            .collect::<std::collections::BTreeMap<_, _>>()
+    };
+
+    let test_dir = create_test_structure();
+    let counters = ScanCounters::default();
+    let scan_metrics = Arc::new(ScanMetrics::new(counters.clone(), test_dir.path().to_string_lossy().to_string(), 0));
+    let server = metrics::serve("127.0.0.1:0", scan_metrics).unwrap();
+    let addr = server.local_addr().unwrap();
+
+    // One entry per batch and room for one batch: the scan waits on each receive
+    let options = ScanOptions::builder().threads(1).batch_size(1).build().unwrap();
+    let (tx, rx) = bounded(1);
+    let path = test_dir.path().to_path_buf();
+    let scan = std::thread::spawn(move || Scanner::new(options).with_counters(counters).scan(path, tx).unwrap());
+
+    let mut previous = scrape(addr);
+    for expected in ["files_scanned_total", "dirs_scanned_total", "bytes_scanned_total", "errors_total",
+                     "chunks_written_total", "current_rate", "scan_info"] {
+        assert!(previous.contains_key(expected), "{} missing: {:?}", expected, previous);
+    }
+    let mut grew = false;
+    for batch in rx.iter() {
+        assert_eq!(batch.len(), 1);
+        let current = scrape(addr);
+        for name in ["files_scanned_total", "dirs_scanned_total", "bytes_scanned_total"] {
+            assert!(current[name] >= previous[name], "{} went from {} to {}", name, previous[name], current[name]);
+        }
+        grew |= current["files_scanned_total"] > previous["files_scanned_total"];
+        previous = current;
+    }
+    let stats = scan.join().unwrap();
+    assert!(grew);
+
+    let last = scrape(addr);
+    assert_eq!(last["files_scanned_total"], stats.files_scanned as f64);
+    assert_eq!(last["dirs_scanned_total"], stats.directories_scanned as f64);
+    server.finish(std::time::Instant::now()).unwrap();
+}
+
+#[cfg(all(feature = "cli", feature = "status-server"))]
+#[test]
+fn test_status_endpoint_reports_completion() {
+    use std::io::{Read, Write};
+    use std::time::{Duration, Instant};
+    use storage_scanner::status::{ScanState, ScanStatus};
+
+    let get = |port: u16, url: &str| -> Option<(u16, String)> {
+        let mut stream = std::net::TcpStream::connect(("127.0.0.1", port)).ok()?;
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", url).ok()?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).ok()?;
+        let (head, body) = response.split_once("\r\n\r\n")?;
+        Some((head[9..12].parse().ok()?, body.to_string()))
+    };
+
+    let test_dir = TempDir::new().unwrap();
+    for dir in 0..20 {
+        let dir_path = test_dir.path().join(format!("dir{:02}", dir));
+        fs::create_dir(&dir_path).unwrap();
+        for file in 0..150 {
+            fs::write(dir_path.join(format!("file{:03}.txt", file)), "x").unwrap();
+        }
+    }
+    let output_dir = TempDir::new().unwrap();
+    // Nothing listens on a port just released
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let mut scan = std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+        .args(["scan", "--incremental", "--rows-per-chunk", "500", "--status-grace", "30s", "--status-listen"])
+        .arg(format!("127.0.0.1:{}", port))
+        .arg("-p")
+        .arg(test_dir.path())
+        .arg("-o")
+        .arg(output_dir.path().join("scan.parquet"))
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+
+    // Poll until the scan reports itself completed
+    let deadline = Instant::now() + Duration::from_secs(60);
+    let status = loop {
+        assert!(Instant::now() < deadline, "scan never reported completion");
+        if let Some((200, body)) = get(port, "/status") {
+            let status: ScanStatus = serde_json::from_str(&body).unwrap();
+            assert!(status.started_at > 0);
+            if status.state == ScanState::Completed {
+                break status;
+            }
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    };
+    assert_eq!(status.files, 3000);
+    assert_eq!(status.directories, 21);
+    assert_eq!(status.percent, Some(100.0));
+    assert_eq!(status.top_level_dirs.len(), 21);
+    assert_eq!(get(port, "/healthz").map(|(code, _)| code), Some(503));
+    assert_eq!(get(port, "/missing").map(|(code, _)| code), Some(404));
+
+    scan.kill().unwrap();
+    scan.wait().unwrap();
+    let manifest = ScanManifest::load_from_file(output_dir.path().join("scan_manifest.json")).unwrap();
+    assert_eq!(manifest.total_rows, 3021);
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_scan_driven_by_config_file() {
+    let test_dir = create_test_structure();
+    let output_dir = TempDir::new().unwrap();
+    let config = output_dir.path().join("scan.toml");
+    fs::write(&config, format!(
+        "[scan]\npath = {:?}\noutput = {:?}\nincremental = true\nrows_per_chunk = 4\nthreads = 2\n",
+        test_dir.path().to_string_lossy(),
+        output_dir.path().join("scan.parquet").to_string_lossy(),
+    )).unwrap();
+    let run = |args: &[&str]| std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+        .arg("--config")
+        .arg(&config)
+        .args(args)
+        .output()
+        .unwrap();
+
+    // Everything, the required path included, from the file
+    let output = run(&["scan"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let manifest = ScanManifest::load_from_file(output_dir.path().join("scan_manifest.json")).unwrap();
+    assert_eq!(manifest.scan_path, test_dir.path().to_string_lossy());
+    assert_eq!(manifest.rotation.unwrap().rows_per_chunk, 4);
+
+    // Flags override the file
+    let other = output_dir.path().join("other.parquet");
+    let output = run(&["scan", "--rows-per-chunk", "100", "-o", other.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let manifest = ScanManifest::load_from_file(output_dir.path().join("other_manifest.json")).unwrap();
+    assert_eq!(manifest.rotation.unwrap().rows_per_chunk, 100);
+
+    // A misspelled key is named
+    fs::write(&config, "[scan]\nrows_per_chunck = 4\n").unwrap();
+    let output = run(&["scan", "-p", "/tmp", "-o", "x.parquet"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Unknown key scan.rows_per_chunck"), "{}", stderr);
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_config_print_default() {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+        .args(["config", "print-default"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let template = String::from_utf8(output.stdout).unwrap();
+    assert!(template.contains("\n[scan]\n"), "{}", template);
+    assert!(template.contains("# rows_per_chunk = 500000\n"), "{}", template);
+    assert!(template.contains("\n[manifest.merge]\n"), "{}", template);
+    // Only comments and sections: the template as printed sets nothing
+    fn sets_nothing(table: &toml::Table) -> bool {
+        table.values().all(|v| v.as_table().is_some_and(sets_nothing))
+    }
+    assert!(sets_nothing(&toml::from_str(&template).unwrap()));
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_sharded_scan_merges_into_the_whole_tree() {
+    let test_dir = create_test_structure();
+    let output_dir = TempDir::new().unwrap();
+    let output = output_dir.path().join("scan.parquet");
+    let run = |args: &[&str]| {
+        let result = std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+        result.stdout
+    };
+    let root = test_dir.path().to_str().unwrap();
+
+    let mut merge = vec!["manifest".to_string(), "merge".to_string()];
+    for index in ["0", "1", "2"] {
+        run(&[
+            "scan", "-p", root, "-o", output.to_str().unwrap(), "--incremental",
+            "--shard-index", index, "--shard-count", "3", "--shard-depth", "2",
+        ]);
+        let manifest = output_dir.path().join(format!("scan_shard_{}_manifest.json", index));
+        assert!(output_dir.path().join(format!("scan_shard_{}_chunk_0001.parquet", index)).exists());
+        merge.extend(["-i".to_string(), manifest.to_string_lossy().to_string()]);
+    }
+    let merged_path = output_dir.path().join("merged_manifest.json");
+    merge.extend(["-o".to_string(), merged_path.to_string_lossy().to_string()]);
+    run(&merge.iter().map(String::as_str).collect::<Vec<_>>());
+
+    let mut sharded = read_manifest_paths(&ScanManifest::load_from_file(&merged_path).unwrap());
+    sharded.sort();
+    let mut whole: Vec<String> = scan_directory(test_dir.path(), ScanOptions::default()).unwrap()
+        .into_iter().map(|e| e.path).collect();
+    whole.sort();
+    assert_eq!(sharded, whole);
+
+    // The plan lists each directory two levels down once, in the shard the scan used
+    let plan: serde_json::Value = serde_json::from_slice(&run(&[
+        "shard-plan", "-p", root, "--shard-count", "3", "--shard-depth", "2", "--json",
+    ])).unwrap();
+    let units: Vec<&str> = plan["units"].as_array().unwrap().iter().map(|u| u["path"].as_str().unwrap()).collect();
+    assert_eq!(units, ["dir1/file3.txt", "dir1/subdir1", "dir2/file5.py", "dir3/file6.json", "dir3/subdir2"]);
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_resume_with_another_shard_count_leaves_the_output_alone() {
+    let test_dir = create_test_structure();
+    let output_dir = TempDir::new().unwrap();
+    let output = output_dir.path().join("scan.parquet");
+    let scan = |shard_count: &str, extra: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+            .args(["scan", "--incremental", "--shard-index", "0", "--shard-count", shard_count, "-p"])
+            .arg(test_dir.path())
+            .arg("-o")
+            .arg(&output)
+            .args(extra)
+            .output()
+            .unwrap()
+    };
+    assert!(scan("3", &[]).status.success());
+
+    // A chunk the manifest doesn't list, which a resume would quarantine
+    let stray = output_dir.path().join("scan_shard_0_chunk_0099.parquet");
+    fs::copy(output_dir.path().join("scan_shard_0_chunk_0001.parquet"), &stray).unwrap();
+    let manifest_path = output_dir.path().join("scan_shard_0_manifest.json");
+    let manifest = fs::read(&manifest_path).unwrap();
+
+    let refused = scan("2", &["--resume"]);
+    assert!(!refused.status.success());
+    assert!(String::from_utf8_lossy(&refused.stderr).contains("different --shard-count"));
+    assert!(stray.exists());
+    assert_eq!(fs::read(&manifest_path).unwrap(), manifest);
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_emitted_checksums_match_files_and_verify() {
+    use sha2::{Digest, Sha256};
+
+    let test_dir = create_test_structure();
+    let output_dir = TempDir::new().unwrap();
+    let output = output_dir.path().join("scan.parquet");
+    let run = |args: &[&std::ffi::OsStr]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner")).args(args).output().unwrap()
+    };
+
+    let scanned = run(&[
+        "scan".as_ref(), "-p".as_ref(), test_dir.path().as_os_str(), "-o".as_ref(), output.as_os_str(),
+        "--incremental".as_ref(), "--rows-per-chunk".as_ref(), "4".as_ref(), "--emit-checksums".as_ref(), "sha256".as_ref(),
+    ]);
+    assert!(scanned.status.success(), "{}", String::from_utf8_lossy(&scanned.stderr));
+
+    // One line per chunk, in order, then the manifest's sidecar and the manifest
+    let manifest = ScanManifest::load_from_file(output_dir.path().join("scan_manifest.json")).unwrap();
+    let mut expected: Vec<String> = manifest.chunks.iter()
+        .map(|c| std::path::Path::new(&c.file_path).file_name().unwrap().to_string_lossy().to_string())
+        .collect();
+    expected.extend(["scan_manifest.parquet".to_string(), "scan_manifest.json".to_string()]);
+    let checksums_path = output_dir.path().join("scan.CHECKSUMS");
+    let contents = fs::read_to_string(&checksums_path).unwrap();
+    let lines: Vec<(&str, &str)> = contents.lines().map(|l| l.split_once("  ").unwrap()).collect();
+    assert_eq!(lines.iter().map(|(_, name)| name.to_string()).collect::<Vec<_>>(), expected);
+    for (hash, name) in &lines {
+        let digest = Sha256::digest(fs::read(output_dir.path().join(name)).unwrap());
+        let independent: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(*hash, independent, "{}", name);
+    }
+
+    let verified = run(&["verify".as_ref(), "--checksums".as_ref(), checksums_path.as_os_str()]);
+    assert!(verified.status.success(), "{}", String::from_utf8_lossy(&verified.stdout));
+
+    // xxh3 for an aggregate, which lists the aggregated file
+    let aggregated = output_dir.path().join("all.parquet");
+    let aggregate = run(&[
+        "aggregate".as_ref(), "-i".as_ref(), output.as_os_str(), "-o".as_ref(), aggregated.as_os_str(),
+        "--emit-checksums".as_ref(), "xxh3".as_ref(),
+    ]);
+    assert!(aggregate.status.success(), "{}", String::from_utf8_lossy(&aggregate.stderr));
+    let contents = fs::read_to_string(output_dir.path().join("all.CHECKSUMS")).unwrap();
+    let independent = xxhash_rust::xxh3::xxh3_64(&fs::read(&aggregated).unwrap());
+    assert_eq!(contents, format!("{:016x}  all.parquet\n", independent));
+
+    // A chunk altered after the fact fails verification
+    let chunk = output_dir.path().join(&expected[0]);
+    let mut bytes = fs::read(&chunk).unwrap();
+    let last = bytes.len() - 1;
+    bytes[last] ^= 1;
+    fs::write(&chunk, bytes).unwrap();
+    let tampered = run(&["verify".as_ref(), "--checksums".as_ref(), checksums_path.as_os_str()]);
+    assert!(!tampered.status.success());
+    assert!(String::from_utf8_lossy(&tampered.stdout).contains("checksum does not match"));
+}
+
+/// Read the `path` column of every chunk listed in a manifest
+#[cfg(feature = "parquet-writer")]
+fn read_manifest_paths(manifest: &ScanManifest) -> Vec<String> {
+    use arrow::array::{Array, StringArray};
+
+    let mut paths = Vec::new();
+    for chunk in &manifest.chunks {
+        let file = File::open(&chunk.file_path).unwrap();
+        let reader = ParquetRecordBatchReaderBuilder::try_new(file).unwrap().build().unwrap();
+        for batch in reader {
+            let batch = batch.unwrap();
+            let column = batch.column_by_name("path").unwrap();
+            let column = column.as_any().downcast_ref::<StringArray>().unwrap();
+            paths.extend((0..column.len()).map(|i| column.value(i).to_string()));
+        }
+    }
+    paths
+}
+
+#[cfg(feature = "parquet-writer")]
+#[test]
+fn test_resume_recovers_torn_chunk() {
+    let test_dir = TempDir::new().unwrap();
+    for (dir, files) in [("a", 3), ("b", 2), ("c", 4)] {
+        fs::create_dir_all(test_dir.path().join(dir)).unwrap();
+        for i in 0..files {
+            fs::write(test_dir.path().join(format!("{}/file{}.txt", dir, i)), "x").unwrap();
+        }
+    }
+
+    let options = ScanOptions::builder().threads(2).batch_size(10).build().unwrap();
+    let all_entries = scan_directory(test_dir.path(), options.clone()).unwrap();
+    let by_dir = |dir: &str| -> Vec<FileEntry> {
+        all_entries.iter().filter(|e| e.top_level_dir == dir).cloned().collect()
+    };
+    let (a, c) = (by_dir("a"), by_dir("c"));
+
+    let output_dir = TempDir::new().unwrap();
+    let config = RotatingWriterConfig {
+        base_output_path: output_dir.path().join("scan.parquet"),
+        rows_per_chunk: a.len() + 2,
+        time_interval: std::time::Duration::from_secs(3600),
+    };
+    let scan_path = test_dir.path().canonicalize().unwrap().to_string_lossy().to_string();
+
+    // First run: all of "a" and part of "c" land in a listed chunk, then more of
+    // "c" goes into the next chunk and the process dies before closing it
+    {
+        let mut writer = RotatingParquetWriter::new(config.clone(), scan_path.clone()).unwrap();
+        writer.write_batch(&a).unwrap();
+        writer.write_batch(&c[..2]).unwrap();
+        writer.write_batch(&c[2..]).unwrap();
+        std::mem::forget(writer);
+    }
+
+    let manifest = ScanManifest::load_from_file(output_dir.path().join("scan_manifest.json")).unwrap();
+    assert_eq!(manifest.current_top_level_dir.as_deref(), Some("c"));
+    assert_eq!(manifest.chunk_count, 1);
+
+    // Resume: "c" must be re-scanned, its partial rows discarded, and the torn chunk quarantined
+    let writer = RotatingParquetWriter::resume(config, scan_path).unwrap();
+    let skip_dirs = writer.manifest.completed_top_level_dirs.clone();
+    assert!(!skip_dirs.contains("c"));
+
+    let (tx, rx) = bounded::<Vec<FileEntry>>(20);
+    let writer_handle = std::thread::spawn(move || writer.consume_batches(rx).unwrap());
+    Scanner::new(options).scan_with_filter(test_dir.path(), tx, Some(skip_dirs)).unwrap();
+    let manifest = writer_handle.join().unwrap();
+
+    let mut resumed: Vec<String> = read_manifest_paths(&manifest);
+    let mut expected: Vec<String> = all_entries.iter().map(|e| e.path.clone()).collect();
+    resumed.sort();
+    expected.sort();
+    assert_eq!(resumed, expected);
+    assert_eq!(manifest.total_rows, expected.len() as u64);
+
+    let torn: Vec<_> = fs::read_dir(output_dir.path()).unwrap()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_name().to_string_lossy().ends_with(".torn"))
+        .collect();
+    assert_eq!(torn.len(), 1);
+}
+
+/// A root named "lab" holding files directly in it, a directory named after
+/// the root, and files named after directories
+#[cfg(feature = "parquet-writer")]
+fn create_root_files_tree() -> (TempDir, std::path::PathBuf) {
+    let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path().join("lab");
+    for dir in ["a", "lab", "z"] {
+        fs::create_dir_all(root.join(dir)).unwrap();
+        fs::write(root.join(dir).join("run.csv"), "a,b").unwrap();
+    }
+    fs::write(root.join("a/a"), "x").unwrap();
+    fs::write(root.join("lab/lab"), "x").unwrap();
+    fs::write(root.join("a.txt"), "x").unwrap();
+    fs::write(root.join("m"), "x").unwrap();
+    fs::write(root.join("zz"), "x").unwrap();
+    (temp_dir, root.canonicalize().unwrap())
+}
+
+/// Resume a scan of `root` whose first run wrote `written`, in batches of
+/// `batch_rows`, and then died, and return the paths in the finished
+/// manifest, sorted
+#[cfg(feature = "parquet-writer")]
+fn resume_after(root: &std::path::Path, written: &[FileEntry], batch_rows: usize, options: &ScanOptions) -> Vec<String> {
+    let output_dir = TempDir::new().unwrap();
+    let config = RotatingWriterConfig {
+        base_output_path: output_dir.path().join("scan.parquet"),
+        rows_per_chunk: 1,
+        time_interval: std::time::Duration::from_secs(3600),
+    };
+    let scan_path = root.to_string_lossy().to_string();
+
+    {
+        let mut writer = RotatingParquetWriter::new(config.clone(), scan_path.clone()).unwrap();
+        for batch in written.chunks(batch_rows) {
+            writer.write_batch(batch).unwrap();
+        }
+        std::mem::forget(writer);
+    }
+
+    let writer = RotatingParquetWriter::resume(config, scan_path).unwrap();
+    let skip_dirs = writer.manifest.completed_top_level_dirs.clone();
+    let (tx, rx) = bounded::<Vec<FileEntry>>(20);
+    let writer_handle = std::thread::spawn(move || writer.consume_batches(rx).unwrap());
+    Scanner::new(options.clone()).scan_with_filter(root, tx, Some(skip_dirs)).unwrap();
+    let manifest = writer_handle.join().unwrap();
+
+    let mut paths = read_manifest_paths(&manifest);
+    paths.sort();
+    assert_eq!(manifest.total_rows, paths.len() as u64);
+    paths
+}
+
+#[cfg(feature = "parquet-writer")]
+#[test]
+fn test_resume_with_files_in_the_root() {
+    let (_temp_dir, root) = create_root_files_tree();
+    let all_entries = scan_directory(&root, ScanOptions::default()).unwrap();
+    let mut expected: Vec<String> = all_entries.iter().map(|e| e.path.clone()).collect();
+    expected.sort();
+
+    // Stop the first run after every possible entry; the resumed scan must
+    // write each entry it didn't keep exactly once, whether the first run
+    // wrote an entry at a time or batches holding several directories and
+    // the root's files together
+    for batch_rows in [1, all_entries.len() + 1] {
+        let options = ScanOptions::builder().threads(2).batch_size(batch_rows).build().unwrap();
+        for written in 0..=all_entries.len() {
+            let resumed = resume_after(&root, &all_entries[..written], batch_rows, &options);
+            assert_eq!(resumed, expected, "first run stopped after {} entries in batches of {}", written, batch_rows);
+        }
+    }
+}
+
+#[cfg(feature = "parquet-writer")]
+#[test]
+fn test_root_files_complete_with_the_scan() {
+    let (_temp_dir, root) = create_root_files_tree();
+    let scan_path = root.to_string_lossy().to_string();
+    let entries = scan_directory(&root, ScanOptions::default()).unwrap();
+
+    // An entry at a time, or the whole tree in one batch
+    for batch_rows in [1, entries.len() + 1] {
+        let output_dir = TempDir::new().unwrap();
+        let config = RotatingWriterConfig {
+            base_output_path: output_dir.path().join("scan.parquet"),
+            rows_per_chunk: 1,
+            time_interval: std::time::Duration::from_secs(3600),
+        };
+
+        let mut writer = RotatingParquetWriter::new(config.clone(), scan_path.clone()).unwrap();
+        for batch in entries.chunks(batch_rows) {
+            writer.write_batch(batch).unwrap();
+            assert!(!writer.manifest.is_dir_completed(storage_scanner::models::ROOT_FILES_UNIT));
+        }
+        let manifest = writer.finalize().unwrap();
+        let mut completed: Vec<&str> = manifest.completed_top_level_dirs.iter().map(String::as_str).collect();
+        completed.sort();
+        assert_eq!(completed, [".", "a", "lab", "z"], "batches of {}", batch_rows);
+
+        // Resuming a finished scan finds nothing left to write, and keeps every row
+        let writer = RotatingParquetWriter::resume(config, scan_path.clone()).unwrap();
+        let skip_dirs = writer.manifest.completed_top_level_dirs.clone();
+        let (tx, rx) = bounded::<Vec<FileEntry>>(20);
+        let writer_handle = std::thread::spawn(move || writer.consume_batches(rx).unwrap());
+        let stats = Scanner::new(ScanOptions::default()).scan_with_filter(&root, tx, Some(skip_dirs)).unwrap();
+        let manifest = writer_handle.join().unwrap();
+        assert_eq!(stats.files_scanned + stats.directories_scanned, 0);
+        assert_eq!(manifest.total_rows, entries.len() as u64);
+        assert_eq!(read_manifest_paths(&manifest).len(), entries.len());
+    }
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_aggregate_delete_blocked_by_manifest_mismatch() {
+    let test_dir = create_test_structure();
+    let entries = scan_directory(test_dir.path(), ScanOptions::default()).unwrap();
+
+    let output_dir = TempDir::new().unwrap();
+    let config = RotatingWriterConfig {
+        base_output_path: output_dir.path().join("scan.parquet"),
+        rows_per_chunk: 5,
+        time_interval: std::time::Duration::from_secs(3600),
+    };
+    let mut writer = RotatingParquetWriter::new(config, "/test".to_string()).unwrap();
+    for entry in &entries {
+        writer.write_batch(std::slice::from_ref(entry)).unwrap();
+    }
+    writer.finalize().unwrap();
+
+    // The manifest claims a row the chunks don't have
+    let manifest_path = output_dir.path().join("scan_manifest.json");
+    let mut manifest = ScanManifest::load_from_file(&manifest_path).unwrap();
+    manifest.chunks[0].row_count += 1;
+    manifest.total_rows += 1;
+    manifest.save_to_file(&manifest_path).unwrap();
+
+    let aggregate = |extra: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+            .args(["aggregate", "-d", "-i"])
+            .arg(&manifest_path)
+            .arg("-o")
+            .arg(output_dir.path().join("aggregated.parquet"))
+            .args(extra)
+            .output()
+            .unwrap()
+    };
+
+    let output = aggregate(&[]);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let disagreeing: Vec<&str> = stdout.lines().filter(|l| l.contains("ERROR")).collect();
+    assert!(disagreeing.iter().any(|l| l.contains(&manifest.chunks[0].file_path)), "{}", stdout);
+    assert!(!disagreeing.iter().any(|l| l.contains(&manifest.chunks[1].file_path)), "{}", stdout);
+    assert!(stderr.contains("--force-delete"), "{}", stderr);
+    for chunk in &manifest.chunks {
+        assert!(std::path::Path::new(&chunk.file_path).exists());
+    }
+
+    let output = aggregate(&["--force-delete"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    for chunk in &manifest.chunks {
+        assert!(!std::path::Path::new(&chunk.file_path).exists());
+    }
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_aggregate_multiple_scans_with_scan_ids() {
+    use arrow::array::{Array, StringArray};
+
+    let test_dir = create_test_structure();
+    let entries = scan_directory(test_dir.path(), ScanOptions::default()).unwrap();
+
+    // Two nightly scans that both claim the same start time, so their chunks
+    // can't be told apart without explicit ids
+    let output_dir = TempDir::new().unwrap();
+    let inputs: Vec<_> = ["night1", "night2"].iter()
+        .map(|night| {
+            let dir = output_dir.path().join(night);
+            fs::create_dir(&dir).unwrap();
+            let config = RotatingWriterConfig {
+                base_output_path: dir.join("scan.parquet"),
+                rows_per_chunk: 4,
+                time_interval: std::time::Duration::from_secs(3600),
+            };
+            let mut writer = RotatingParquetWriter::new(config, "/test".to_string()).unwrap();
+            writer.manifest.scan_start = 1_716_170_400;
+            writer.write_batch(&entries).unwrap();
+            writer.finalize().unwrap();
+            dir
+        })
+        .collect();
+
+    let output = output_dir.path().join("history.parquet");
+    let aggregate = |extra: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+            .arg("aggregate")
+            .arg("-i").arg(&inputs[0])
+            .arg("-i").arg(&inputs[1])
+            .arg("-o").arg(&output)
+            .args(extra)
+            .output()
+            .unwrap()
+    };
+
+    let untagged = aggregate(&[]);
+    assert!(untagged.status.success());
+    assert!(String::from_utf8_lossy(&untagged.stderr).contains("WARNING"));
+
+    let tagged = aggregate(&["--scan-id", "2024-05-20", "--scan-id", "2024-05-21"]);
+    assert!(tagged.status.success(), "{}", String::from_utf8_lossy(&tagged.stderr));
+    assert!(!String::from_utf8_lossy(&tagged.stderr).contains("WARNING"));
+
+    let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(&output).unwrap()).unwrap().build().unwrap();
+    let mut per_scan = std::collections::BTreeMap::new();
+    for batch in reader {
+        let batch = batch.unwrap();
+        let ids = batch.column_by_name("scan_id").unwrap();
+        let ids = ids.as_any().downcast_ref::<StringArray>().unwrap();
+        assert!(batch.column_by_name("scan_date").unwrap().null_count() == 0);
+        for i in 0..ids.len() {
+            *per_scan.entry(ids.value(i).to_string()).or_insert(0) += 1;
+        }
+    }
+    assert_eq!(per_scan.len(), 2);
+    assert!(per_scan.values().all(|n| *n == entries.len()));
+
+    // A scan id per input, or none at all
+    assert!(!aggregate(&["--scan-id", "2024-05-20"]).status.success());
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_aggregate_merge_scans_from_several_hosts() {
+    use arrow::array::StringArray;
+
+    let test_dir = create_test_structure();
+    let output_dir = TempDir::new().unwrap();
+
+    // Scans of dir1 and dir3 from two hosts, and of the whole tree
+    let scan = |root: std::path::PathBuf, name: &str, host: &str| {
+        let entries = scan_directory(&root, ScanOptions::default()).unwrap();
+        let config = RotatingWriterConfig {
+            base_output_path: output_dir.path().join(format!("{}.parquet", name)),
+            rows_per_chunk: 3,
+            time_interval: std::time::Duration::from_secs(3600),
+        };
+        let mut writer = RotatingParquetWriter::new(config, root.to_string_lossy().to_string()).unwrap();
+        writer.manifest.hostname = Some(host.to_string());
+        writer.write_batch(&entries).unwrap();
+        writer.finalize().unwrap();
+        (output_dir.path().join(format!("{}_manifest.json", name)), entries.len())
+    };
+    let (dir1, dir1_rows) = scan(test_dir.path().join("dir1"), "dir1", "midway3");
+    let (dir3, dir3_rows) = scan(test_dir.path().join("dir3"), "dir3", "beagle");
+    let (whole, whole_rows) = scan(test_dir.path().to_path_buf(), "whole", "midway3");
+
+    let output = output_dir.path().join("merged.parquet");
+    let merge = |inputs: &[&std::path::Path], extra: &[&str]| {
+        let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"));
+        command.args(["aggregate", "--merge", "-o"]).arg(&output);
+        for input in inputs {
+            command.arg("-i").arg(input);
+        }
+        command.args(extra).output().unwrap()
+    };
+
+    let merged = merge(&[&dir1, &dir3], &[]);
+    assert!(merged.status.success(), "{}", String::from_utf8_lossy(&merged.stderr));
+    let stdout = String::from_utf8_lossy(&merged.stdout);
+    assert!(stdout.contains("Rows per Source"), "{}", stdout);
+    assert!(stdout.contains("beagle"), "{}", stdout);
+
+    // Every row keeps the scan and host it came from
+    let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(&output).unwrap()).unwrap().build().unwrap();
+    let mut per_host = std::collections::BTreeMap::new();
+    for batch in reader {
+        let batch = batch.unwrap();
+        assert!(batch.column_by_name("scan_id").is_some());
+        let paths = batch.column_by_name("path").unwrap();
+        let paths = paths.as_any().downcast_ref::<StringArray>().unwrap();
+        let hosts = batch.column_by_name("hostname").unwrap();
+        let hosts = hosts.as_any().downcast_ref::<StringArray>().unwrap();
+        for i in 0..batch.num_rows() {
+            let expected = if paths.value(i).contains("/dir1") { "midway3" } else { "beagle" };
+            assert_eq!(hosts.value(i), expected, "{}", paths.value(i));
+            *per_host.entry(hosts.value(i).to_string()).or_insert(0) += 1;
+        }
+    }
+    assert_eq!(per_host["midway3"], dir1_rows);
+    assert_eq!(per_host["beagle"], dir3_rows);
+
+    let manifest = ScanManifest::load_from_file(output_dir.path().join("merged_manifest.json")).unwrap();
+    let sources: Vec<_> = manifest.merged_from.iter()
+        .map(|s| (s.manifest_path.clone(), s.hostname.clone().unwrap()))
+        .collect();
+    assert_eq!(sources, [
+        (dir1.to_string_lossy().to_string(), "midway3".to_string()),
+        (dir3.to_string_lossy().to_string(), "beagle".to_string()),
+    ]);
+
+    // The whole tree covers both: refused unless duplicates are dropped
+    let overlapping = merge(&[&dir1, &dir3, &whole], &[]);
+    assert!(!overlapping.status.success());
+    assert!(String::from_utf8_lossy(&overlapping.stderr).contains("--allow-overlap"));
+
+    let allowed = merge(&[&dir1, &dir3, &whole], &["--allow-overlap"]);
+    assert!(allowed.status.success(), "{}", String::from_utf8_lossy(&allowed.stderr));
+    assert!(String::from_utf8_lossy(&allowed.stderr).contains("WARNING"));
+    let rows: usize = ParquetRecordBatchReaderBuilder::try_new(File::open(&output).unwrap()).unwrap()
+        .build().unwrap().map(|b| b.unwrap().num_rows()).sum();
+    assert_eq!(rows, whole_rows);
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_aggregate_delete_spares_decoy_parquet() {
+    let test_dir = create_test_structure();
+    let entries = scan_directory(test_dir.path(), ScanOptions::default()).unwrap();
+
+    let output_dir = TempDir::new().unwrap();
+    let config = RotatingWriterConfig {
+        base_output_path: output_dir.path().join("scan.parquet"),
+        rows_per_chunk: 5,
+        time_interval: std::time::Duration::from_secs(3600),
+    };
+    let mut writer = RotatingParquetWriter::new(config, "/test".to_string()).unwrap();
+    for entry in &entries {
+        writer.write_batch(std::slice::from_ref(entry)).unwrap();
+    }
+    let manifest = writer.finalize().unwrap();
+
+    // Hand-placed files next to the chunks, one of them named like a chunk;
+    // with the manifest in the directory, neither is selected
+    let decoys = [output_dir.path().join("scan_chunk_0099.parquet"), output_dir.path().join("export_2024_data.parquet")];
+    for decoy in &decoys {
+        fs::copy(&manifest.chunks[0].file_path, decoy).unwrap();
+    }
+
+    let aggregate = |extra: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+            .args(["aggregate", "-d", "-i"])
+            .arg(output_dir.path())
+            .arg("-o")
+            .arg(output_dir.path().join("out").join("aggregated.parquet"))
+            .args(extra)
+            .output()
+            .unwrap()
+    };
+    let chunks_exist = || manifest.chunks.iter().all(|c| std::path::Path::new(&c.file_path).exists());
+
+    let dry_run = aggregate(&["--dry-run-delete"]);
+    assert!(dry_run.status.success(), "{}", String::from_utf8_lossy(&dry_run.stderr));
+    let stdout = String::from_utf8_lossy(&dry_run.stdout);
+    for chunk in &manifest.chunks {
+        assert!(stdout.contains(&format!("Would delete: {}", chunk.file_path)), "{}", stdout);
+    }
+    assert!(stdout.contains(&format!("Selected {} file(s) from", manifest.chunks.len())), "{}", stdout);
+    assert!(decoys.iter().all(|d| !stdout.contains(&d.display().to_string())), "{}", stdout);
+    assert!(chunks_exist());
+
+    let output = aggregate(&[]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Skipped (unlisted):    0"));
+    assert!(!manifest.chunks.iter().any(|c| std::path::Path::new(&c.file_path).exists()));
+    assert!(decoys.iter().all(|d| d.exists()));
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_aggregate_include_file() {
+    let test_dir = create_test_structure();
+    let entries = scan_directory(test_dir.path(), ScanOptions::default()).unwrap();
+
+    let output_dir = TempDir::new().unwrap();
+    let config = RotatingWriterConfig {
+        base_output_path: output_dir.path().join("scan.parquet"),
+        rows_per_chunk: 5,
+        time_interval: std::time::Duration::from_secs(3600),
+    };
+    let mut writer = RotatingParquetWriter::new(config, "/test".to_string()).unwrap();
+    writer.write_batch(&entries).unwrap();
+    let manifest = writer.finalize().unwrap();
+    fs::remove_file(output_dir.path().join("scan_manifest.json")).unwrap();
+    let export = output_dir.path().join("export_2024_data.parquet");
+    fs::copy(&manifest.chunks[0].file_path, &export).unwrap();
+
+    let aggregate = |extra: &[&std::ffi::OsStr]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+            .args(["aggregate", "-i"])
+            .arg(output_dir.path())
+            .arg("-o")
+            .arg(output_dir.path().join("aggregated.parquet"))
+            .args(extra)
+            .output()
+            .unwrap()
+    };
+    let total_rows = |output: &std::process::Output| {
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+        let line = stdout.lines().find(|l| l.starts_with("Total rows:")).unwrap().to_string();
+        line.trim_start_matches("Total rows:").trim().replace(',', "").parse::<usize>().unwrap()
+    };
+
+    // The export is not a chunk, so only naming it adds its rows
+    assert_eq!(total_rows(&aggregate(&[])), entries.len());
+    let output = aggregate(&["--include-file".as_ref(), export.as_os_str()]);
+    assert_eq!(total_rows(&output), entries.len() + manifest.chunks[0].row_count as usize);
+    assert!(String::from_utf8_lossy(&output.stdout).contains(&format!("Selected 1 file(s) from {}", export.display())));
+
+    let missing = aggregate(&["--include-file".as_ref(), output_dir.path().join("missing.parquet").as_os_str()]);
+    assert!(!missing.status.success());
+    assert!(String::from_utf8_lossy(&missing.stderr).contains("is not a file"));
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_report_json_and_csv() {
+    let test_dir = create_test_structure();
+    let entries = scan_directory(test_dir.path(), ScanOptions::default()).unwrap();
+    let files: Vec<&FileEntry> = entries.iter().filter(|e| e.file_type != "directory").collect();
+    let txt_bytes: u64 = files.iter().filter(|e| e.file_type == "txt").map(|e| e.size).sum();
+
+    let output_dir = TempDir::new().unwrap();
+    let config = RotatingWriterConfig {
+        base_output_path: output_dir.path().join("scan.parquet"),
+        rows_per_chunk: 4,
+        time_interval: std::time::Duration::from_secs(3600),
+    };
+    let mut writer = RotatingParquetWriter::new(config, "/test".to_string()).unwrap();
+    for entry in &entries {
+        writer.write_batch(std::slice::from_ref(entry)).unwrap();
+    }
+    writer.finalize().unwrap();
+
+    let report = |format: &str| {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+            .args(["report", "--top", "2", "--format", format, "-i"])
+            .arg(output_dir.path().join("scan_manifest.json"))
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let json: serde_json::Value = serde_json::from_str(&report("json")).unwrap();
+    assert_eq!(json["files"], files.len() as u64);
+    assert_eq!(json["directories"], (entries.len() - files.len()) as u64);
+    assert_eq!(json["total_bytes"], files.iter().map(|e| e.size).sum::<u64>());
+    assert_eq!(json["by_file_type"]["rows"][0]["name"], "txt");
+    assert_eq!(json["by_file_type"]["rows"][0]["bytes"], txt_bytes);
+    assert_eq!(json["by_file_type"]["rows"].as_array().unwrap().len(), 2);
+    assert_eq!(json["by_modified_age"]["rows"][0]["count"], files.len() as u64);
+
+    let csv = report("csv");
+    assert!(csv.starts_with("section,name,count,bytes,percent\n"));
+    assert!(csv.contains(&format!("\nfile_type,txt,4,{},", txt_bytes)), "{}", csv);
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_top_json() {
+    let test_dir = create_test_structure();
+    let root = test_dir.path().to_string_lossy().to_string();
+    let output_dir = TempDir::new().unwrap();
+    let output = output_dir.path().join("scan.parquet");
+    let (tx, rx) = bounded(1);
+    tx.send(scan_directory(test_dir.path(), ScanOptions::default()).unwrap()).unwrap();
+    drop(tx);
+    write_to_parquet(&output, rx).unwrap();
+
+    let top = |extra: &[&str]| {
+        let result = std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+            .args(["top", "--json", "--files", "2", "--dirs", "2", "-i"])
+            .arg(&output)
+            .args(extra)
+            .output()
+            .unwrap();
+        assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+        serde_json::from_slice::<serde_json::Value>(&result.stdout).unwrap()
+    };
+
+    // file6.json (16 bytes) and file5.py (14 bytes) are the largest files
+    let report = top(&[]);
+    assert_eq!(report["files"][0]["path"], format!("{}/dir3/file6.json", root));
+    assert_eq!(report["files"][1]["path"], format!("{}/dir2/file5.py", root));
+    assert_eq!(report["directories"][0]["path"], root);
+    assert_eq!(report["directories"][0]["bytes"], 16);
+
+    // dir3 holds file6.json, file7.csv and file8.txt
+    let report = top(&["--cumulative", "--under", &format!("{}/dir3", root)]);
+    assert_eq!(report["directories"][0]["path"], format!("{}/dir3", root));
+    assert_eq!(report["directories"][0]["bytes"], 16 + 5 + 12);
+    assert_eq!(report["directories"][0]["files"], 3);
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_du_matches_tree() {
+    let test_dir = create_test_structure();
+    let root = test_dir.path().to_string_lossy().to_string();
+    let output_dir = TempDir::new().unwrap();
+    let output = output_dir.path().join("scan.parquet");
+    let (tx, rx) = bounded(1);
+    tx.send(scan_directory(test_dir.path(), ScanOptions::default()).unwrap()).unwrap();
+    drop(tx);
+    write_to_parquet(&output, rx).unwrap();
+
+    let result = std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+        .args(["du", "--depth", "1", "--sort-by", "size", "-i"])
+        .arg(&output)
+        .output()
+        .unwrap();
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+    let stdout = String::from_utf8(result.stdout).unwrap();
+    let lines: Vec<Vec<&str>> = stdout.lines().skip(1).map(|l| l.split_whitespace().collect()).collect();
+
+    // Size, unit, files, share of parent, path
+    assert_eq!(lines[0], ["79.00", "B", "8", &root]);
+    assert_eq!(lines[1], ["33.00", "B", "3", "41.8%", &format!("{}/dir3", root)]);
+    assert_eq!(lines[2][4], format!("{}/dir1", root));
+    assert_eq!(lines.len(), 4);
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_diff_reports_each_change() {
+    let test_dir = create_test_structure();
+    let root = test_dir.path().to_string_lossy().to_string();
+    let output_dir = TempDir::new().unwrap();
+    let scan = |name: &str| {
+        let output = output_dir.path().join(name);
+        let (tx, rx) = bounded(1);
+        tx.send(scan_directory(test_dir.path(), ScanOptions::default()).unwrap()).unwrap();
+        drop(tx);
+        write_to_parquet(&output, rx).unwrap();
+        output
+    };
+
+    let old = scan("old.parquet");
+    fs::remove_file(test_dir.path().join("dir1/file3.txt")).unwrap();
+    fs::write(test_dir.path().join("dir1/added.txt"), "twelve bytes").unwrap();
+    fs::write(test_dir.path().join("dir1/subdir1/file4.txt"), "content4 and more").unwrap();
+    let new = scan("new.parquet");
+
+    let diff = output_dir.path().join("diff.parquet");
+    let result = std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+        .arg("diff")
+        .arg("--old").arg(&old)
+        .arg("--new").arg(&new)
+        .arg("--under").arg(format!("{}/dir1", root))
+        .arg("-o").arg(&diff)
+        .output()
+        .unwrap();
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+    let stdout = String::from_utf8(result.stdout).unwrap();
+    assert!(stdout.contains("Added:                 1 (12.00 B)"), "{}", stdout);
+    assert!(stdout.contains("Removed:               1 (8.00 B)"), "{}", stdout);
+
+    let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(&diff).unwrap()).unwrap().build().unwrap();
+    let mut changes = Vec::new();
+    for batch in reader {
+        let batch = batch.unwrap();
+        let column = |name: &str| {
+            let column = batch.column_by_name(name).unwrap();
+            column.as_any().downcast_ref::<arrow::array::StringArray>().unwrap().clone()
+        };
+        let (types, paths) = (column("change_type"), column("path"));
+        for i in 0..batch.num_rows() {
+            changes.push((types.value(i).to_string(), paths.value(i).to_string()));
+        }
+    }
+    for (change_type, path) in [
+        ("added", "dir1/added.txt"),
+        ("removed", "dir1/file3.txt"),
+        ("modified", "dir1/subdir1/file4.txt"),
+    ] {
+        let expected = (change_type.to_string(), format!("{}/{}", root, path));
+        assert!(changes.contains(&expected), "{:?} missing from {:?}", expected, changes);
+    }
+    assert!(changes.iter().all(|(_, path)| path.starts_with(&format!("{}/dir1", root))));
+    assert!(!output_dir.path().join(".diff.parquet.diff").exists());
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_query_where() {
+    let test_dir = create_test_structure();
+    let output_dir = TempDir::new().unwrap();
+    let scan = output_dir.path().join("scan.parquet");
+    let (tx, rx) = bounded(1);
+    tx.send(scan_directory(test_dir.path(), ScanOptions::default()).unwrap()).unwrap();
+    drop(tx);
+    write_to_parquet(&scan, rx).unwrap();
+
+    let query = |filter: &str, output: &str| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+            .args(["query", "--where", filter, "-i"])
+            .arg(&scan)
+            .arg("-o")
+            .arg(output_dir.path().join(output))
+            .output()
+            .unwrap()
+    };
+
+    let result = query("file_type == 'txt' && size > 8 || path glob '*.py'", "result.csv");
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+    let csv = fs::read_to_string(output_dir.path().join("result.csv")).unwrap();
+    let mut names: Vec<&str> = csv.lines().skip(1)
+        .map(|line| line.split(',').next().unwrap().rsplit('/').next().unwrap())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["file5.py", "file8.txt"]);
+
+    let result = query("size > 10 && sise < 1KB", "result.parquet");
+    assert!(!result.status.success());
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("Unknown column 'sise'"), "{}", stderr);
+    assert!(stderr.contains("\n               ^^^^\n"), "{}", stderr);
+    assert!(!output_dir.path().join("result.parquet").exists());
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_duplicates_json() {
+    let test_dir = TempDir::new().unwrap();
+    let content = vec![7u8; 4096];
+    fs::create_dir_all(test_dir.path().join("copies")).unwrap();
+    fs::write(test_dir.path().join("original.bin"), &content).unwrap();
+    fs::write(test_dir.path().join("copies/copy.bin"), &content).unwrap();
+    fs::hard_link(test_dir.path().join("original.bin"), test_dir.path().join("copies/link.bin")).unwrap();
+    fs::write(test_dir.path().join("other.bin"), vec![8u8; 4096]).unwrap();
+
+    let output_dir = TempDir::new().unwrap();
+    let scan = output_dir.path().join("scan.parquet");
+    let (tx, rx) = bounded(1);
+    tx.send(scan_directory(test_dir.path(), ScanOptions::default()).unwrap()).unwrap();
+    drop(tx);
+    write_to_parquet(&scan, rx).unwrap();
+
+    let result = std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+        .args(["duplicates", "--hash", "--json", "-i"])
+        .arg(&scan)
+        .output()
+        .unwrap();
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+    let report: serde_json::Value = serde_json::from_slice(&result.stdout).unwrap();
+    assert_eq!(report["matched_by"], "live_hash");
+    assert_eq!(report["reclaimable_bytes"], 4096);
+    assert_eq!(report["hardlinks"], 1);
+    let groups = report["groups"].as_array().unwrap();
+    assert_eq!(groups.len(), 1);
+    assert_eq!(groups[0]["copies"].as_array().unwrap().len(), 2);
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_stale_as_of_is_reproducible() {
+    let output_dir = TempDir::new().unwrap();
+    let scan = output_dir.path().join("scan.parquet");
+    let day = 86_400;
+    let as_of = 1_700_000_000;
+    let entry = |path: &str, size: u64, accessed_days_ago: i64, uid: u32| FileEntry {
+        size,
+        modified_time: as_of - 1000 * day,
+        accessed_time: as_of - accessed_days_ago * day,
+        uid,
+        gid: 100,
+        ..FileEntry::fixture(path)
+    };
+    let (tx, rx) = bounded(1);
+    tx.send(vec![
+        entry("/project/lab/a.dat", 200_000_000, 400, 1000),
+        entry("/project/lab/b.dat", 300_000_000, 364, 1000),
+        entry("/project/lab/c.dat", 50_000_000, 400, 2000),
+        entry("/project/lab/d.dat", 150_000_000, 500, 2000),
+    ]).unwrap();
+    drop(tx);
+    write_to_parquet(&scan, rx).unwrap();
+
+    let stale = |as_of: &str| {
+        let result = std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+            .args(["stale", "--atime-older-than", "365d", "--min-size", "100MB", "--json", "--as-of", as_of, "-i"])
+            .arg(&scan)
+            .output()
+            .unwrap();
+        assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+        serde_json::from_slice::<serde_json::Value>(&result.stdout).unwrap()
+    };
+
+    let report = stale(&as_of.to_string());
+    assert_eq!(report["files"], 2);
+    assert_eq!(report["bytes"], 350_000_000);
+    assert_eq!(report["groups"][0]["group"], "1000");
+    assert_eq!(report["groups"][1]["group"], "2000");
+
+    // Two days later b.dat has crossed the threshold too
+    let later = stale(&(as_of + 2 * day).to_string());
+    assert_eq!(later["files"], 3);
+    assert_eq!(later["groups"][0]["bytes"], 500_000_000);
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_clean_candidates_tags_files_by_rule() {
+    let output_dir = TempDir::new().unwrap();
+    let scan = output_dir.path().join("scan.parquet");
+    let day = 86_400;
+    let as_of = 1_700_000_000;
+    let entry = |path: &str, size: u64, accessed_days_ago: i64, uid: u32| FileEntry {
+        size,
+        modified_time: as_of - 1000 * day,
+        accessed_time: as_of - accessed_days_ago * day,
+        uid,
+        gid: 100,
+        ..FileEntry::fixture(path)
+    };
+    let (tx, rx) = bounded(1);
+    tx.send(vec![
+        entry("/project/scratch/a.tmp", 10, 1, 1000),
+        entry("/project/scratch/b.dat", 200, 100, 1000),
+        entry("/project/scratch/c.dat", 300, 100, 0),
+        entry("/project/scratch/d.dat", 400, 10, 1000),
+    ]).unwrap();
+    drop(tx);
+    write_to_parquet(&scan, rx).unwrap();
+
+    let policy = output_dir.path().join("policy.toml");
+    fs::write(&policy, "[[rule]]\nname = \"temp\"\nprefix = \"/project\"\nextensions = [\"tmp\"]\n\n\
+                        [[rule]]\nname = \"idle\"\nprefix = \"/project/scratch\"\natime_older_than = \"90d\"\n\
+                        exclude_owners = [\"0\"]\n").unwrap();
+    let candidates = output_dir.path().join("candidates.jsonl");
+
+    let result = std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+        .args(["clean-candidates", "--json", "--as-of", &as_of.to_string(), "--policy"])
+        .arg(&policy)
+        .arg("-i").arg(&scan)
+        .arg("-o").arg(&candidates)
+        .output()
+        .unwrap();
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+
+    let report: serde_json::Value = serde_json::from_slice(&result.stdout).unwrap();
+    assert_eq!(report["files"], 2);
+    assert_eq!(report["bytes"], 210);
+    assert_eq!(report["rules"][0]["rule"], "temp");
+    assert_eq!(report["rules"][1]["bytes"], 200);
+
+    let rows: Vec<serde_json::Value> = fs::read_to_string(&candidates).unwrap().lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let tagged: Vec<(&str, &str)> = rows.iter()
+        .map(|row| (row["path"].as_str().unwrap(), row["rule"].as_str().unwrap()))
+        .collect();
+    assert_eq!(tagged, vec![("/project/scratch/a.tmp", "temp"), ("/project/scratch/b.dat", "idle")]);
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_user_report_with_mapping_file() {
+    let output_dir = TempDir::new().unwrap();
+    let scan = output_dir.path().join("scan.parquet");
+    let entry = |path: &str, size: u64, uid: u32, gid: u32| FileEntry { size, uid, gid, ..FileEntry::fixture(path) };
+    let (tx, rx) = bounded(1);
+    tx.send(vec![
+        entry("/project/lab/a.dat", 100, 51001, 52001),
+        entry("/project/lab/b.dat", 900, 51002, 52001),
+        entry("/project/lab/c.dat", 300, 51001, 52002),
+        entry("/project/other/d.dat", 5000, 51003, 52002),
+    ]).unwrap();
+    drop(tx);
+    write_to_parquet(&scan, rx).unwrap();
+
+    let passwd = output_dir.path().join("passwd");
+    fs::write(&passwd, "ada:x:51001:52001::/home/ada:/bin/sh\nbo:x:51002:52001::/home/bo:/bin/sh\n").unwrap();
+
+    let result = std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+        .args(["user-report", "--json", "--under", "/project/lab", "--map"])
+        .arg(&passwd)
+        .arg("-i")
+        .arg(&scan)
+        .output()
+        .unwrap();
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+    let report: serde_json::Value = serde_json::from_slice(&result.stdout).unwrap();
+    assert_eq!(report["bytes"], 1300);
+    assert_eq!(report["users"][0]["id"], 51002);
+    assert_eq!(report["users"][0]["name"], "bo");
+    assert_eq!(report["users"][1]["name"], "ada");
+    assert_eq!(report["users"][1]["entries"], 2);
+    assert_eq!(report["users"].as_array().unwrap().len(), 2);
+    assert_eq!(report["groups"][0]["id"], 52001);
+    assert_eq!(report["groups"][0]["bytes"], 1000);
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_export_gzip_csv() {
+    use arrow::array::{Array, StringArray, UInt64Array};
+    use arrow::datatypes::{DataType, Field, Schema};
+    use std::sync::Arc;
+
+    let test_dir = TempDir::new().unwrap();
+    fs::write(test_dir.path().join("plain.txt"), "12345").unwrap();
+    fs::write(test_dir.path().join("with, comma.txt"), "1234567890").unwrap();
+    fs::write(test_dir.path().join("empty.txt"), "").unwrap();
+
+    let output_dir = TempDir::new().unwrap();
+    let scan = output_dir.path().join("scan.parquet");
+    let (tx, rx) = bounded(1);
+    tx.send(scan_directory(test_dir.path(), ScanOptions::default()).unwrap()).unwrap();
+    drop(tx);
+    write_to_parquet(&scan, rx).unwrap();
+
+    let export = output_dir.path().join("out.csv.gz");
+    let result = std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+        .args(["export", "--columns", "path,size", "--where", "file_type == 'txt' && size > 0", "-i"])
+        .arg(&scan)
+        .arg("-o")
+        .arg(&export)
+        .output()
+        .unwrap();
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("path", DataType::Utf8, false),
+        Field::new("size", DataType::UInt64, false),
+    ]));
+    let gz = flate2::read::GzDecoder::new(File::open(&export).unwrap());
+    let reader = arrow::csv::ReaderBuilder::new(schema).has_header(true).build(gz).unwrap();
+    let mut rows = Vec::new();
+    for batch in reader {
+        let batch = batch.unwrap();
+        let paths = batch.column(0).as_any().downcast_ref::<StringArray>().unwrap();
+        let sizes = batch.column(1).as_any().downcast_ref::<UInt64Array>().unwrap();
+        for i in 0..batch.num_rows() {
+            let name = paths.value(i).rsplit('/').next().unwrap().to_string();
+            rows.push((name, sizes.value(i)));
+        }
+    }
+    rows.sort();
+    assert_eq!(rows, [("plain.txt".to_string(), 5), ("with, comma.txt".to_string(), 10)]);
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_validate_exit_status_and_json() {
+    let test_dir = create_test_structure();
+    let output_dir = TempDir::new().unwrap();
+    let scan = output_dir.path().join("scan.parquet");
+    let mut entries = scan_directory(test_dir.path(), ScanOptions::default()).unwrap();
+    let (tx, rx) = bounded(1);
+    tx.send(entries.clone()).unwrap();
+    drop(tx);
+    write_to_parquet(&scan, rx).unwrap();
+
+    let validate = |input: &std::path::Path| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+            .args(["validate", "--json", "-i"])
+            .arg(input)
+            .output()
+            .unwrap()
+    };
+
+    let result = validate(&scan);
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stdout));
+
+    // Deliberately corrupt one entry's depth
+    let corrupted = output_dir.path().join("corrupted.parquet");
+    let deepest = entries.iter_mut().max_by_key(|e| e.depth).unwrap();
+    deepest.depth += 1;
+    let (tx, rx) = bounded(1);
+    tx.send(entries).unwrap();
+    drop(tx);
+    write_to_parquet(&corrupted, rx).unwrap();
+
+    let result = validate(&corrupted);
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("Validation failed: depth"));
+    let report: serde_json::Value = serde_json::from_slice(&result.stdout).unwrap();
+    let depth = report["checks"].as_array().unwrap().iter().find(|c| c["name"] == "depth").unwrap();
+    assert_eq!(depth["status"], "failed");
+    assert_eq!(depth["failures"], 1);
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_sample_seed_is_reproducible() {
+    let test_dir = TempDir::new().unwrap();
+    for i in 0..50 {
+        fs::write(test_dir.path().join(format!("file{:02}.{}", i, if i % 5 == 0 { "log" } else { "dat" })), "x").unwrap();
+    }
+
+    let output_dir = TempDir::new().unwrap();
+    let scan = output_dir.path().join("scan.parquet");
+    let (tx, rx) = bounded(1);
+    tx.send(scan_directory(test_dir.path(), ScanOptions::default()).unwrap()).unwrap();
+    drop(tx);
+    write_to_parquet(&scan, rx).unwrap();
+
+    let sample = |args: &[&str]| {
+        let result = std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+            .args(["sample", "--format", "jsonl", "-i"])
+            .arg(&scan)
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+        String::from_utf8(result.stdout).unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["path"].as_str().unwrap().to_string())
+            .collect::<Vec<_>>()
+    };
+
+    let first = sample(&["-n", "8", "--seed", "42"]);
+    assert_eq!(first.len(), 8);
+    assert_eq!(sample(&["-n", "8", "--seed", "42"]), first);
+
+    // Filtering happens before sampling, so only .log files are picked
+    let logs = sample(&["-n", "20", "--seed", "42", "--where", "file_type == 'log'"]);
+    assert_eq!(logs.len(), 10);
+    assert!(logs.iter().all(|path| path.ends_with(".log")), "{:?}", logs);
+
+    assert_eq!(sample(&["--head", "3"]).len(), 3);
+}
+
+#[cfg(all(feature = "cli", feature = "watch"))]
+#[test]
+fn test_watch_records_changes_until_interrupted() {
+    use std::io::{BufRead, BufReader};
+    use storage_scanner::watch::{self, WatchManifest};
+
+    let test_dir = TempDir::new().unwrap();
+    let output_dir = TempDir::new().unwrap();
+    let mut child = std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+        .args(["watch", "-p"])
+        .arg(test_dir.path())
+        .arg("-o")
+        .arg(output_dir.path())
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    // This line is printed once the watch is in place; log lines may come first
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut line = String::new();
+    while !line.starts_with("Watching") {
+        line.clear();
+        assert!(stdout.read_line(&mut line).unwrap() > 0, "watch exited before starting");
+    }
+
+    fs::write(test_dir.path().join("new.txt"), "x").unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(500));
+    unsafe { libc::kill(child.id() as i32, libc::SIGINT) };
+    assert!(child.wait().unwrap().success());
+
+    let manifest = WatchManifest::load_from_file(watch::manifest_path(output_dir.path())).unwrap();
+    assert_eq!(manifest.files.len(), 1);
+    let changes = fs::read_to_string(output_dir.path().join(&manifest.files[0].file_name)).unwrap();
+    let first: serde_json::Value = serde_json::from_str(changes.lines().next().unwrap()).unwrap();
+    assert!(first["path"].as_str().unwrap().ends_with("new.txt"));
+    assert_eq!(first["event_type"], "create");
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_pipeline_command_publishes_and_reports() {
+    let test_dir = create_test_structure();
+    let work_dir = TempDir::new().unwrap();
+    let publish_dir = TempDir::new().unwrap();
+    let config = work_dir.path().join("pipeline.toml");
+    fs::write(&config, format!(
+        "work_dir = \"{}\"\n\n[scan]\npath = \"{}\"\nrows_per_chunk = 4\n\n[publish]\ndestination = \"{}\"\nmethod = \"hardlink\"\n",
+        work_dir.path().join("run").display(),
+        test_dir.path().display(),
+        publish_dir.path().display(),
+    )).unwrap();
+
+    let result = std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+        .args(["pipeline", "--config"])
+        .arg(&config)
+        .output()
+        .unwrap();
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+
+    let report: serde_json::Value = serde_json::from_slice(
+        &fs::read(work_dir.path().join("run/pipeline_report.json")).unwrap()
+    ).unwrap();
+    assert_eq!(report["succeeded"], true);
+    let stages: Vec<&str> = report["stages"].as_array().unwrap().iter()
+        .map(|s| s["stage"].as_str().unwrap())
+        .collect();
+    assert_eq!(stages, vec!["scan", "aggregate", "verify", "publish"]);
+    let scanned = report["stages"][0]["rows"].as_u64().unwrap();
+    assert_eq!(report["stages"][3]["rows"].as_u64().unwrap(), scanned);
+
+    let published = publish_dir.path().join("scan.parquet");
+    let file = File::open(&published).unwrap();
+    let rows: usize = ParquetRecordBatchReaderBuilder::try_new(file).unwrap().build().unwrap()
+        .map(|batch| batch.unwrap().num_rows())
+        .sum();
+    assert_eq!(rows as u64, scanned);
+    let artifact: serde_json::Value = serde_json::from_slice(
+        &fs::read(publish_dir.path().join("scan_published.json")).unwrap()
+    ).unwrap();
+    assert_eq!(artifact["rows"].as_u64().unwrap(), scanned);
+    assert_eq!(artifact["file_name"], "scan.parquet");
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_compare_manifests_exit_status() {
+    let output_dir = TempDir::new().unwrap();
+    let write = |name: &str, dirs: &[(&str, u64)]| {
+        let mut manifest = ScanManifest::new("/project".to_string());
+        for &(dir, rows) in dirs {
+            manifest.dir_stats.insert(dir.to_string(), storage_scanner::manifest::DirStats { rows, bytes: rows * 1000 });
+        }
+        manifest.total_rows = dirs.iter().map(|(_, rows)| rows).sum();
+        manifest.chunk_count = 4;
+        let path = output_dir.path().join(name);
+        manifest.save_to_file(&path).unwrap();
+        path
+    };
+    let old = write("old_manifest.json", &[("labA", 500), ("labB", 500)]);
+    let new = write("new_manifest.json", &[("labA", 510), ("labB", 100)]);
+
+    let compare = |threshold: &str| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+            .args(["compare-manifests", "--json", "--threshold", threshold, "--old"])
+            .arg(&old)
+            .arg("--new").arg(&new)
+            .output()
+            .unwrap()
+    };
+
+    let result = compare("10%");
+    assert!(!result.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&result.stdout).unwrap();
+    let exceeded: Vec<(&str, &str)> = report["metrics"].as_array().unwrap().iter()
+        .filter(|m| m["exceeded"] == true)
+        .map(|m| (m["scope"].as_str().unwrap(), m["metric"].as_str().unwrap()))
+        .collect();
+    assert_eq!(exceeded, vec![("(scan)", "rows"), ("(scan)", "bytes"), ("labB", "rows"), ("labB", "bytes")]);
+    assert_eq!(report["deviations"], 4);
+    assert!(String::from_utf8_lossy(&result.stderr).contains("4 metric(s)"));
+
+    // labB dropped by 80%
+    assert!(compare("80%").status.success());
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_growth_across_dated_scans() {
+    let scans_dir = TempDir::new().unwrap();
+    let entry = |path: &str, size: u64| FileEntry { size, gid: 100, ..FileEntry::fixture(path) };
+    for (date, sizes) in [("2024-06-01", vec![1000]), ("2024-06-03", vec![1000, 500])] {
+        let (tx, rx) = bounded(1);
+        tx.send(sizes.iter().enumerate().map(|(i, size)| entry(&format!("/project/lab/{}.dat", i), *size)).collect())
+            .unwrap();
+        drop(tx);
+        write_to_parquet(scans_dir.path().join(format!("nightly_{}.parquet", date)), rx).unwrap();
+    }
+
+    let result = std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+        .args(["growth", "--json", "--quota", "lab=3000", "-i"])
+        .arg(scans_dir.path())
+        .output()
+        .unwrap();
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+
+    let report: serde_json::Value = serde_json::from_slice(&result.stdout).unwrap();
+    assert_eq!(report["scans"][1]["date"], "2024-06-03");
+    let lab = &report["groups"][0];
+    assert_eq!(lab["group"], "lab");
+    assert_eq!(lab["bytes_change"], 500);
+    assert_eq!(lab["percent_change"], 50.0);
+    assert_eq!(lab["bytes_per_day"], 250.0);
+    // 1500 bytes left at 250 a day
+    assert_eq!(lab["days_until_quota"], 6.0);
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_quota_check_exit_codes() {
+    let temp_dir = TempDir::new().unwrap();
+    let scan = temp_dir.path().join("scan.parquet");
+    let (tx, rx) = bounded(1);
+    tx.send(vec![FileEntry { size: 850, gid: 100, ..FileEntry::fixture("/project/lab/data.dat") }]).unwrap();
+    drop(tx);
+    write_to_parquet(&scan, rx).unwrap();
+
+    // 850 bytes is 42.5%, 85%, and 94.4% of these limits
+    for (limit, code, status) in [("2000", 0, "ok"), ("1000", 1, "warn"), ("900", 2, "critical")] {
+        let quotas = temp_dir.path().join(format!("quotas_{}.toml", limit));
+        fs::write(&quotas, format!("[[quota]]\npath = \"/project/lab\"\nbytes = \"{}\"\n", limit)).unwrap();
+
+        let result = std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+            .args(["quota-check", "--json", "-i"])
+            .arg(&scan)
+            .arg("--quotas")
+            .arg(&quotas)
+            .output()
+            .unwrap();
+        assert_eq!(result.status.code(), Some(code), "{}", String::from_utf8_lossy(&result.stderr));
+
+        let report: serde_json::Value = serde_json::from_slice(&result.stdout).unwrap();
+        assert_eq!(report["status"], status);
+        assert_eq!(report["quotas"][0]["bytes_used"], 850);
+    }
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_inspect_json_on_scan_output() {
+    let test_dir = create_test_structure();
+    let output_dir = TempDir::new().unwrap();
+    let result = std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+        .args(["scan", "--incremental", "--rows-per-chunk", "5", "--batch-size", "2", "-p"])
+        .arg(test_dir.path())
+        .arg("-o")
+        .arg(output_dir.path().join("scan.parquet"))
+        .output()
+        .unwrap();
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+
+    let inspect = |input: &std::path::Path| {
+        let result = std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+            .args(["inspect", "--json", "-i"])
+            .arg(input)
+            .output()
+            .unwrap();
+        assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+        serde_json::from_slice::<serde_json::Value>(&result.stdout).unwrap()
+    };
+
+    let manifest = inspect(&output_dir.path().join("scan_manifest.json"));
+    assert_eq!(manifest["kind"], "manifest");
+    assert_eq!(manifest["completed"], true);
+    let chunks = manifest["chunks"].as_array().unwrap();
+    assert!(chunks.len() > 1);
+    let chunk_rows: u64 = chunks.iter().map(|c| c["row_count"].as_u64().unwrap()).sum();
+    assert_eq!(manifest["total_rows"].as_u64().unwrap(), chunk_rows);
+    assert!(manifest["completed_dirs"].is_array());
+
+    let chunk = inspect(std::path::Path::new(chunks[0]["file_path"].as_str().unwrap()));
+    assert_eq!(chunk["kind"], "parquet");
+    assert_eq!(chunk["rows"], chunks[0]["row_count"]);
+    assert!(chunk["metadata"]["scan_id"].is_string());
+    assert!(!chunk["row_groups"].as_array().unwrap().is_empty());
+    let size = chunk["columns"].as_array().unwrap().iter().find(|c| c["name"] == "size").unwrap();
+    assert_eq!(size["physical_type"], "INT64");
+    assert!(size["compressed_bytes"].as_u64().unwrap() > 0);
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_hash_backfills_matching_files() {
+    use arrow::array::{Array, StringArray};
+
+    let test_dir = create_test_structure();
+    let output_dir = TempDir::new().unwrap();
+    let scan = output_dir.path().join("scan.parquet");
+    let (tx, rx) = bounded(1);
+    tx.send(scan_directory(test_dir.path(), ScanOptions::default()).unwrap()).unwrap();
+    drop(tx);
+    write_to_parquet(&scan, rx).unwrap();
+    fs::remove_file(test_dir.path().join("dir1/file3.txt")).unwrap();
+
+    let hashed = output_dir.path().join("hashed.parquet");
+    let result = std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+        .args(["hash", "--where", "file_type == 'txt'", "--algo", "crc32", "-i"])
+        .arg(&scan)
+        .arg("-o")
+        .arg(&hashed)
+        .output()
+        .unwrap();
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+
+    let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(&hashed).unwrap()).unwrap().build().unwrap();
+    let mut rows = Vec::new();
+    for batch in reader {
+        let batch = batch.unwrap();
+        let column = |name: &str| batch.column_by_name(name).unwrap().as_any().downcast_ref::<StringArray>().unwrap().clone();
+        let (paths, hashes, errors) = (column("path"), column("content_hash"), column("error"));
+        for i in 0..batch.num_rows() {
+            rows.push((paths.value(i).to_string(), hashes.is_valid(i).then(|| hashes.value(i).to_string()),
+                       errors.is_valid(i).then(|| errors.value(i).to_string())));
+        }
+    }
+    let row = |name: &str| rows.iter().find(|r| r.0.ends_with(name)).unwrap().clone();
+
+    // file1.txt holds "content1"
+    let expected = storage_scanner::utils::file_checksum(&test_dir.path().join("file1.txt")).unwrap();
+    assert_eq!(row("file1.txt").1, Some(expected));
+    assert!(row("file4.txt").1.is_some());
+    let removed = row("file3.txt");
+    assert_eq!(removed.1, None);
+    assert!(removed.2.unwrap().starts_with("ENOENT"));
+    // Not matched: no hash and no error
+    assert_eq!(row("file2.log").1, None);
+    assert_eq!(row("file2.log").2, None);
+    // file1.txt, file4.txt, and deep/file8.txt
+    assert_eq!(rows.iter().filter(|r| r.1.is_some()).count(), 3);
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_empty_dirs_recursive() {
+    let test_dir = create_test_structure();
+    fs::create_dir_all(test_dir.path().join("dir2/skeleton/a/b")).unwrap();
+    fs::create_dir_all(test_dir.path().join("dir2/skeleton/c")).unwrap();
+    let output_dir = TempDir::new().unwrap();
+    let scan = output_dir.path().join("scan.parquet");
+    let (tx, rx) = bounded(1);
+    tx.send(scan_directory(test_dir.path(), ScanOptions::default()).unwrap()).unwrap();
+    drop(tx);
+    write_to_parquet(&scan, rx).unwrap();
+
+    let empty_dirs = |recursive: bool| {
+        let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"));
+        command.args(["empty-dirs", "--json", "-i"]).arg(&scan);
+        if recursive {
+            command.arg("--recursive");
+        }
+        let result = command.output().unwrap();
+        assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+        serde_json::from_slice::<serde_json::Value>(&result.stdout).unwrap()
+    };
+    let root = test_dir.path().to_string_lossy().to_string();
+
+    // skeleton/a/b and skeleton/c have nothing inside
+    let direct = empty_dirs(false);
+    assert_eq!(direct["total"], 2);
+
+    // skeleton holds only empty directories, so it is reported once for all four
+    let recursive = empty_dirs(true);
+    assert_eq!(recursive["total"], 4);
+    let dirs: Vec<(String, u64)> = recursive["dirs"].as_array().unwrap().iter()
+        .map(|d| (d["path"].as_str().unwrap().to_string(), d["dirs"].as_u64().unwrap()))
+        .collect();
+    assert_eq!(dirs, [(format!("{}/dir2/skeleton", root), 4)]);
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_convert_round_trip() {
+    let test_dir = create_test_structure();
+    let output_dir = TempDir::new().unwrap();
+    let scan = output_dir.path().join("scan.parquet");
+    let (tx, rx) = bounded(1);
+    tx.send(scan_directory(test_dir.path(), ScanOptions::default()).unwrap()).unwrap();
+    drop(tx);
+    write_to_parquet(&scan, rx).unwrap();
+
+    let convert = |input: &std::path::Path, output: &std::path::Path, extra: &[&str]| {
+        let result = std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+            .arg("convert")
+            .args(extra)
+            .arg("-i")
+            .arg(input)
+            .arg("-o")
+            .arg(output)
+            .output()
+            .unwrap();
+        assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+    };
+    let jsonl = output_dir.path().join("scan.jsonl.gz");
+    let back = output_dir.path().join("back.parquet");
+    convert(&scan, &jsonl, &[]);
+    convert(&jsonl, &back, &["--compression", "zstd", "--where", "file_type == 'txt'"]);
+
+    let read = |path: &std::path::Path| {
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap())
+            .unwrap().build().unwrap();
+        let batches: Vec<_> = reader.map(|b| b.unwrap()).collect();
+        arrow::compute::concat_batches(&batches[0].schema(), &batches).unwrap()
+    };
+    let original = read(&scan);
+    let converted = read(&back);
+    assert_eq!(converted.schema(), original.schema());
+
+    let file_types = original.column_by_name("file_type").unwrap();
+    let file_types = file_types.as_any().downcast_ref::<arrow::array::StringArray>().unwrap();
+    let txt = arrow::compute::eq_utf8_scalar(file_types, "txt").unwrap();
+    let expected = arrow::compute::filter_record_batch(&original, &txt).unwrap();
+    assert!(expected.num_rows() > 0);
+    assert_eq!(converted, expected);
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_symlinks_check_live() {
+    use std::os::unix::fs::symlink;
+
+    let test_dir = create_test_structure();
+    symlink("file3.txt", test_dir.path().join("dir1/relative")).unwrap();
+    symlink("/nonexistent/target", test_dir.path().join("dir2/dangling")).unwrap();
+    let output_dir = TempDir::new().unwrap();
+    let scan = output_dir.path().join("scan.parquet");
+    let (tx, rx) = bounded(1);
+    tx.send(scan_directory(test_dir.path(), ScanOptions::default()).unwrap()).unwrap();
+    drop(tx);
+    write_to_parquet(&scan, rx).unwrap();
+
+    let result = std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+        .args(["symlinks", "--check-live", "--json", "-i"])
+        .arg(&scan)
+        .output()
+        .unwrap();
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+    let report: serde_json::Value = serde_json::from_slice(&result.stdout).unwrap();
+    assert_eq!(report["links"], 2);
+    assert_eq!(report["relative"], 1);
+    assert_eq!(report["external"], 1);
+    assert_eq!(report["broken"], 1);
+    let dangling = &report["symlinks"][1];
+    assert!(dangling["path"].as_str().unwrap().ends_with("dir2/dangling"));
+    assert_eq!(dangling["broken"], true);
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_hardlinks_corrected_total() {
+    let test_dir = create_test_structure();
+    fs::write(test_dir.path().join("dir2/big.dat"), vec![b'x'; 4096]).unwrap();
+    fs::hard_link(test_dir.path().join("dir2/big.dat"), test_dir.path().join("dir3/big_link.dat")).unwrap();
+    let output_dir = TempDir::new().unwrap();
+    let scan = output_dir.path().join("scan.parquet");
+    let (tx, rx) = bounded(1);
+    tx.send(scan_directory(test_dir.path(), ScanOptions::default()).unwrap()).unwrap();
+    drop(tx);
+    write_to_parquet(&scan, rx).unwrap();
+
+    let result = std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+        .args(["hardlinks", "--json", "-i"])
+        .arg(&scan)
+        .output()
+        .unwrap();
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+    let report: serde_json::Value = serde_json::from_slice(&result.stdout).unwrap();
+    assert_eq!(report["groups"], 1);
+    assert_eq!(report["linked_paths"], 2);
+    let apparent = report["apparent_bytes"].as_u64().unwrap();
+    assert_eq!(report["corrected_bytes"].as_u64().unwrap(), apparent - 4096);
+    let paths = report["largest"][0]["paths"].as_array().unwrap();
+    assert!(paths[0].as_str().unwrap().ends_with("dir2/big.dat"));
+    assert!(paths[1].as_str().unwrap().ends_with("dir3/big_link.dat"));
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_permissions_audit_to_csv() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let test_dir = create_test_structure();
+    let setuid = test_dir.path().join("dir2/tool");
+    fs::write(&setuid, "#!/bin/sh\n").unwrap();
+    fs::set_permissions(&setuid, fs::Permissions::from_mode(0o4755)).unwrap();
+    fs::set_permissions(test_dir.path().join("dir1/file3.txt"), fs::Permissions::from_mode(0o666)).unwrap();
+    let output_dir = TempDir::new().unwrap();
+    let scan = output_dir.path().join("scan.parquet");
+    let (tx, rx) = bounded(1);
+    tx.send(scan_directory(test_dir.path(), ScanOptions::default()).unwrap()).unwrap();
+    drop(tx);
+    write_to_parquet(&scan, rx).unwrap();
+
+    let findings = output_dir.path().join("findings.csv");
+    let result = std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+        .args(["permissions", "--check", "world-writable,setuid", "--json", "-i"])
+        .arg(&scan)
+        .arg("-o")
+        .arg(&findings)
+        .output()
+        .unwrap();
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+    let report: serde_json::Value = serde_json::from_slice(&result.stdout).unwrap();
+    assert_eq!(report["checks"][0]["check"], "world-writable");
+    assert_eq!(report["checks"][0]["findings"], 1);
+    assert_eq!(report["checks"][1]["findings"], 1);
+
+    let csv = fs::read_to_string(&findings).unwrap();
+    assert!(csv.lines().any(|l| l.starts_with("setuid,") && l.contains("dir2/tool,dir2,no_extension,4755,")));
+    assert!(csv.lines().any(|l| l.starts_with("world-writable,") && l.contains("dir1/file3.txt,")));
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn test_hotspots_dense_directory_csv() {
+    let test_dir = create_test_structure();
+    for i in 0..120 {
+        fs::write(test_dir.path().join(format!("dir2/many{:03}.txt", i)), "").unwrap();
+    }
+    let output_dir = TempDir::new().unwrap();
+    let scan = output_dir.path().join("scan.parquet");
+    let (tx, rx) = bounded(1);
+    tx.send(scan_directory(test_dir.path(), ScanOptions::default()).unwrap()).unwrap();
+    drop(tx);
+    write_to_parquet(&scan, rx).unwrap();
+
+    let result = std::process::Command::new(env!("CARGO_BIN_EXE_storage-scanner"))
+        .args(["hotspots", "--top", "3", "--min-entries", "100", "--format", "csv", "-i"])
+        .arg(&scan)
+        .output()
+        .unwrap();
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+    let csv = String::from_utf8(result.stdout).unwrap();
+    let dense: Vec<&str> = csv.lines().filter(|l| l.starts_with("dense,")).collect();
+    assert_eq!(dense.len(), 1);
+    assert!(dense[0].ends_with("/dir2,121"), "{}", dense[0]);
+    assert_eq!(csv.lines().filter(|l| l.starts_with("deepest,")).count(), 3);
+}